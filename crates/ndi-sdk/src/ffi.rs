@@ -0,0 +1,404 @@
+use std::os::raw::{c_char, c_int};
+
+// Opaque handle types
+pub enum NDIlib_find_instance_type {}
+pub type NDIlib_find_instance_t = *mut NDIlib_find_instance_type;
+
+pub enum NDIlib_recv_instance_type {}
+pub type NDIlib_recv_instance_t = *mut NDIlib_recv_instance_type;
+
+pub enum NDIlib_send_instance_type {}
+pub type NDIlib_send_instance_t = *mut NDIlib_send_instance_type;
+
+pub enum NDIlib_routing_instance_type {}
+pub type NDIlib_routing_instance_t = *mut NDIlib_routing_instance_type;
+
+/// Timecode value asking the SDK to generate one from the send clock.
+pub const NDIlib_send_timecode_synthesize: i64 = i64::MAX;
+
+// Frame type returned by recv_capture
+pub type NDIlib_frame_type_e = i32;
+pub const NDIlib_frame_type_none: NDIlib_frame_type_e = 0;
+pub const NDIlib_frame_type_video: NDIlib_frame_type_e = 1;
+pub const NDIlib_frame_type_audio: NDIlib_frame_type_e = 2;
+pub const NDIlib_frame_type_metadata: NDIlib_frame_type_e = 3;
+pub const NDIlib_frame_type_error: NDIlib_frame_type_e = 4;
+pub const NDIlib_frame_type_status_change: NDIlib_frame_type_e = 100;
+
+// FourCC video types
+pub type NDIlib_FourCC_video_type_e = u32;
+
+macro_rules! fourcc {
+    ($a:expr, $b:expr, $c:expr, $d:expr) => {
+        ($a as u32) | (($b as u32) << 8) | (($c as u32) << 16) | (($d as u32) << 24)
+    };
+}
+
+pub const NDIlib_FourCC_video_type_UYVY: NDIlib_FourCC_video_type_e =
+    fourcc!(b'U', b'Y', b'V', b'Y');
+pub const NDIlib_FourCC_video_type_UYVA: NDIlib_FourCC_video_type_e =
+    fourcc!(b'U', b'Y', b'V', b'A');
+pub const NDIlib_FourCC_video_type_I420: NDIlib_FourCC_video_type_e =
+    fourcc!(b'I', b'4', b'2', b'0');
+pub const NDIlib_FourCC_video_type_NV12: NDIlib_FourCC_video_type_e =
+    fourcc!(b'N', b'V', b'1', b'2');
+pub const NDIlib_FourCC_video_type_YV12: NDIlib_FourCC_video_type_e =
+    fourcc!(b'Y', b'V', b'1', b'2');
+pub const NDIlib_FourCC_video_type_BGRA: NDIlib_FourCC_video_type_e =
+    fourcc!(b'B', b'G', b'R', b'A');
+pub const NDIlib_FourCC_video_type_BGRX: NDIlib_FourCC_video_type_e =
+    fourcc!(b'B', b'G', b'R', b'X');
+pub const NDIlib_FourCC_video_type_RGBA: NDIlib_FourCC_video_type_e =
+    fourcc!(b'R', b'G', b'B', b'A');
+pub const NDIlib_FourCC_video_type_RGBX: NDIlib_FourCC_video_type_e =
+    fourcc!(b'R', b'G', b'B', b'X');
+
+// FourCC audio types
+pub type NDIlib_FourCC_audio_type_e = u32;
+pub const NDIlib_FourCC_audio_type_FLTP: NDIlib_FourCC_audio_type_e =
+    fourcc!(b'F', b'L', b'T', b'p');
+
+// Frame format type
+pub type NDIlib_frame_format_type_e = i32;
+pub const NDIlib_frame_format_type_progressive: NDIlib_frame_format_type_e = 1;
+pub const NDIlib_frame_format_type_interleaved: NDIlib_frame_format_type_e = 0;
+pub const NDIlib_frame_format_type_field_0: NDIlib_frame_format_type_e = 2;
+pub const NDIlib_frame_format_type_field_1: NDIlib_frame_format_type_e = 3;
+
+// Recv bandwidth
+pub type NDIlib_recv_bandwidth_e = i32;
+pub const NDIlib_recv_bandwidth_metadata_only: NDIlib_recv_bandwidth_e = -10;
+pub const NDIlib_recv_bandwidth_audio_only: NDIlib_recv_bandwidth_e = 10;
+pub const NDIlib_recv_bandwidth_lowest: NDIlib_recv_bandwidth_e = 0;
+pub const NDIlib_recv_bandwidth_highest: NDIlib_recv_bandwidth_e = 100;
+
+// Recv color format
+pub type NDIlib_recv_color_format_e = i32;
+pub const NDIlib_recv_color_format_BGRX_BGRA: NDIlib_recv_color_format_e = 0;
+pub const NDIlib_recv_color_format_UYVY_BGRA: NDIlib_recv_color_format_e = 1;
+pub const NDIlib_recv_color_format_RGBX_RGBA: NDIlib_recv_color_format_e = 2;
+pub const NDIlib_recv_color_format_UYVY_RGBA: NDIlib_recv_color_format_e = 3;
+pub const NDIlib_recv_color_format_fastest: NDIlib_recv_color_format_e = 100;
+pub const NDIlib_recv_color_format_best: NDIlib_recv_color_format_e = 101;
+
+// Source descriptor
+#[repr(C)]
+pub struct NDIlib_source_t {
+    pub p_ndi_name: *const c_char,
+    pub p_url_address: *const c_char,
+}
+
+// Find creation settings
+#[repr(C)]
+pub struct NDIlib_find_create_t {
+    pub show_local_sources: bool,
+    pub p_groups: *const c_char,
+    pub p_extra_ips: *const c_char,
+}
+
+// Recv creation settings
+#[repr(C)]
+pub struct NDIlib_recv_create_v3_t {
+    pub source_to_connect_to: NDIlib_source_t,
+    pub color_format: NDIlib_recv_color_format_e,
+    pub bandwidth: NDIlib_recv_bandwidth_e,
+    pub allow_video_fields: bool,
+    pub p_ndi_recv_name: *const c_char,
+}
+
+// Send creation settings
+#[repr(C)]
+pub struct NDIlib_send_create_t {
+    pub p_ndi_name: *const c_char,
+    pub p_groups: *const c_char,
+    pub clock_video: bool,
+    pub clock_audio: bool,
+}
+
+// Routing creation settings
+#[repr(C)]
+pub struct NDIlib_routing_create_t {
+    pub p_ndi_name: *const c_char,
+    pub p_groups: *const c_char,
+}
+
+// Video frame
+#[repr(C)]
+pub struct NDIlib_video_frame_v2_t {
+    pub xres: c_int,
+    pub yres: c_int,
+    pub four_cc: NDIlib_FourCC_video_type_e,
+    pub frame_rate_n: c_int,
+    pub frame_rate_d: c_int,
+    pub picture_aspect_ratio: f32,
+    pub frame_format_type: NDIlib_frame_format_type_e,
+    pub timecode: i64,
+    pub p_data: *mut u8,
+    pub line_stride_in_bytes: c_int,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+// Audio frame v3
+#[repr(C)]
+pub struct NDIlib_audio_frame_v3_t {
+    pub sample_rate: c_int,
+    pub no_channels: c_int,
+    pub no_samples: c_int,
+    pub timecode: i64,
+    pub four_cc: NDIlib_FourCC_audio_type_e,
+    pub p_data: *mut u8,
+    pub channel_stride_in_bytes: c_int,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+// Metadata frame
+#[repr(C)]
+pub struct NDIlib_metadata_frame_t {
+    pub length: c_int,
+    pub timecode: i64,
+    pub p_data: *mut c_char,
+}
+
+// Tally
+#[repr(C)]
+pub struct NDIlib_tally_t {
+    pub on_program: bool,
+    pub on_preview: bool,
+}
+
+unsafe impl Send for NDIlib_video_frame_v2_t {}
+unsafe impl Send for NDIlib_audio_frame_v3_t {}
+unsafe impl Send for NDIlib_metadata_frame_t {}
+
+impl Default for NDIlib_video_frame_v2_t {
+    fn default() -> Self {
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+impl Default for NDIlib_audio_frame_v3_t {
+    fn default() -> Self {
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+impl Default for NDIlib_metadata_frame_t {
+    fn default() -> Self {
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+#[cfg(target_os = "windows")]
+const LIB_NAME: &str = "Processing.NDI.Lib.x64.dll";
+#[cfg(target_os = "macos")]
+const LIB_NAME: &str = "libndi.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIB_NAME: &str = "libndi.so.6";
+
+/// Where the NDI SDK and Tools installers put the runtime when it isn't on the
+/// loader's default search path.
+#[cfg(target_os = "windows")]
+const INSTALL_DIRS: &[&str] = &[];
+#[cfg(target_os = "macos")]
+const INSTALL_DIRS: &[&str] = &[
+    "/usr/local/lib",
+    "/opt/homebrew/lib",
+    "/Library/NDI SDK for Apple/lib/macOS",
+    "/Library/NDI 6 SDK/lib/macOS",
+];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const INSTALL_DIRS: &[&str] = &[
+    "/usr/lib",
+    "/usr/local/lib",
+    "/usr/lib/x86_64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/opt/ndi/lib",
+];
+
+pub struct NdiApi {
+    // Hold the library so it stays loaded for the lifetime of this struct.
+    _lib: libloading::Library,
+
+    pub initialize: unsafe extern "C" fn() -> bool,
+    pub destroy: unsafe extern "C" fn(),
+    pub version: unsafe extern "C" fn() -> *const c_char,
+
+    pub find_create_v2:
+        unsafe extern "C" fn(*const NDIlib_find_create_t) -> NDIlib_find_instance_t,
+    pub find_destroy: unsafe extern "C" fn(NDIlib_find_instance_t),
+    pub find_wait_for_sources: unsafe extern "C" fn(NDIlib_find_instance_t, u32) -> bool,
+    pub find_get_current_sources:
+        unsafe extern "C" fn(NDIlib_find_instance_t, *mut u32) -> *const NDIlib_source_t,
+
+    pub recv_create_v3:
+        unsafe extern "C" fn(*const NDIlib_recv_create_v3_t) -> NDIlib_recv_instance_t,
+    pub recv_destroy: unsafe extern "C" fn(NDIlib_recv_instance_t),
+    pub recv_connect: unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_source_t),
+    pub recv_capture_v3: unsafe extern "C" fn(
+        NDIlib_recv_instance_t,
+        *mut NDIlib_video_frame_v2_t,
+        *mut NDIlib_audio_frame_v3_t,
+        *mut NDIlib_metadata_frame_t,
+        u32,
+    ) -> NDIlib_frame_type_e,
+    pub recv_free_video_v2:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_video_frame_v2_t),
+    pub recv_free_audio_v3:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_audio_frame_v3_t),
+    pub recv_free_metadata:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_metadata_frame_t),
+    pub recv_free_string: unsafe extern "C" fn(NDIlib_recv_instance_t, *const c_char),
+    pub recv_send_metadata:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_metadata_frame_t) -> bool,
+    pub recv_add_connection_metadata:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_metadata_frame_t),
+    pub recv_clear_connection_metadata: unsafe extern "C" fn(NDIlib_recv_instance_t),
+    pub recv_get_no_connections: unsafe extern "C" fn(NDIlib_recv_instance_t) -> c_int,
+    pub recv_get_web_control: unsafe extern "C" fn(NDIlib_recv_instance_t) -> *const c_char,
+    pub recv_set_tally:
+        unsafe extern "C" fn(NDIlib_recv_instance_t, *const NDIlib_tally_t) -> bool,
+
+    /// Optional entry points, missing from old runtimes.
+    pub send: Option<SendApi>,
+    pub ptz: Option<PtzApi>,
+    pub routing: Option<RoutingApi>,
+    /// Whether the runtime has the frame synchronizer and `NDIlib_recv_create_v4`,
+    /// which nothing uses yet.
+    pub framesync: bool,
+    pub recv_create_v4: bool,
+}
+
+/// Sending, since NDI® 3.
+pub struct SendApi {
+    pub create: unsafe extern "C" fn(*const NDIlib_send_create_t) -> NDIlib_send_instance_t,
+    pub destroy: unsafe extern "C" fn(NDIlib_send_instance_t),
+    pub send_video_v2: unsafe extern "C" fn(NDIlib_send_instance_t, *const NDIlib_video_frame_v2_t),
+    pub send_audio_v3: unsafe extern "C" fn(NDIlib_send_instance_t, *const NDIlib_audio_frame_v3_t),
+    pub send_metadata: unsafe extern "C" fn(NDIlib_send_instance_t, *const NDIlib_metadata_frame_t),
+    pub get_no_connections: unsafe extern "C" fn(NDIlib_send_instance_t, u32) -> c_int,
+    pub get_source_name: unsafe extern "C" fn(NDIlib_send_instance_t) -> *const NDIlib_source_t,
+}
+
+/// PTZ control of receivers' senders, since NDI® 3.5.
+pub struct PtzApi {
+    pub is_supported: unsafe extern "C" fn(NDIlib_recv_instance_t) -> bool,
+    pub recall_preset: unsafe extern "C" fn(NDIlib_recv_instance_t, c_int, f32) -> bool,
+    pub store_preset: unsafe extern "C" fn(NDIlib_recv_instance_t, c_int) -> bool,
+    pub pan_tilt: unsafe extern "C" fn(NDIlib_recv_instance_t, f32, f32) -> bool,
+    pub pan_tilt_speed: unsafe extern "C" fn(NDIlib_recv_instance_t, f32, f32) -> bool,
+    pub zoom: unsafe extern "C" fn(NDIlib_recv_instance_t, f32) -> bool,
+    pub zoom_speed: unsafe extern "C" fn(NDIlib_recv_instance_t, f32) -> bool,
+}
+
+/// Routing, since NDI® 4.
+pub struct RoutingApi {
+    pub create: unsafe extern "C" fn(*const NDIlib_routing_create_t) -> NDIlib_routing_instance_t,
+    pub destroy: unsafe extern "C" fn(NDIlib_routing_instance_t),
+    pub change: unsafe extern "C" fn(NDIlib_routing_instance_t, *const NDIlib_source_t) -> bool,
+    pub clear: unsafe extern "C" fn(NDIlib_routing_instance_t) -> bool,
+}
+
+// Safety: the NDI SDK documentation states all functions are thread-safe.
+unsafe impl Send for NdiApi {}
+unsafe impl Sync for NdiApi {}
+
+impl NdiApi {
+    /// Try to load the NDI runtime library (`Processing.NDI.Lib.x64.dll`,
+    /// `libndi.dylib` or `libndi.so.6`).
+    ///
+    /// Search order:
+    /// 1. System default (exe dir, PATH, `LD_LIBRARY_PATH`, etc.)
+    /// 2. `$NDI_RUNTIME_DIR_V6`, then `$NDI_RUNTIME_DIR`
+    /// 3. The platform's standard install locations
+    ///
+    /// On failure, returns the error from the system default lookup.
+    pub fn load() -> Result<Self, libloading::Error> {
+        let lib = unsafe { libloading::Library::new(LIB_NAME) }.or_else(|first_err| {
+            let env_dirs = ["NDI_RUNTIME_DIR_V6", "NDI_RUNTIME_DIR"]
+                .into_iter()
+                .filter_map(std::env::var_os)
+                .map(std::path::PathBuf::from);
+            let install_dirs = INSTALL_DIRS.iter().map(std::path::PathBuf::from);
+            env_dirs
+                .chain(install_dirs)
+                .map(|dir| dir.join(LIB_NAME))
+                .filter(|path| path.is_file())
+                .find_map(|path| unsafe { libloading::Library::new(&path) }.ok())
+                .ok_or(first_err)
+        })?;
+
+        unsafe {
+            let has = |name: &[u8]| lib.get::<unsafe extern "C" fn()>(name).is_ok();
+            Ok(Self {
+                initialize: *lib.get(b"NDIlib_initialize\0")?,
+                destroy: *lib.get(b"NDIlib_destroy\0")?,
+                version: *lib.get(b"NDIlib_version\0")?,
+                find_create_v2: *lib.get(b"NDIlib_find_create_v2\0")?,
+                find_destroy: *lib.get(b"NDIlib_find_destroy\0")?,
+                find_wait_for_sources: *lib.get(b"NDIlib_find_wait_for_sources\0")?,
+                find_get_current_sources: *lib.get(b"NDIlib_find_get_current_sources\0")?,
+                recv_create_v3: *lib.get(b"NDIlib_recv_create_v3\0")?,
+                recv_destroy: *lib.get(b"NDIlib_recv_destroy\0")?,
+                recv_connect: *lib.get(b"NDIlib_recv_connect\0")?,
+                recv_capture_v3: *lib.get(b"NDIlib_recv_capture_v3\0")?,
+                recv_free_video_v2: *lib.get(b"NDIlib_recv_free_video_v2\0")?,
+                recv_free_audio_v3: *lib.get(b"NDIlib_recv_free_audio_v3\0")?,
+                recv_free_metadata: *lib.get(b"NDIlib_recv_free_metadata\0")?,
+                recv_free_string: *lib.get(b"NDIlib_recv_free_string\0")?,
+                recv_send_metadata: *lib.get(b"NDIlib_recv_send_metadata\0")?,
+                recv_add_connection_metadata: *lib.get(b"NDIlib_recv_add_connection_metadata\0")?,
+                recv_clear_connection_metadata: *lib.get(b"NDIlib_recv_clear_connection_metadata\0")?,
+                recv_get_no_connections: *lib.get(b"NDIlib_recv_get_no_connections\0")?,
+                recv_get_web_control: *lib.get(b"NDIlib_recv_get_web_control\0")?,
+                recv_set_tally: *lib.get(b"NDIlib_recv_set_tally\0")?,
+                send: SendApi::load(&lib).ok(),
+                ptz: PtzApi::load(&lib).ok(),
+                routing: RoutingApi::load(&lib).ok(),
+                framesync: has(b"NDIlib_framesync_create\0"),
+                recv_create_v4: has(b"NDIlib_recv_create_v4\0"),
+                _lib: lib,
+            })
+        }
+    }
+}
+
+impl SendApi {
+    unsafe fn load(lib: &libloading::Library) -> Result<Self, libloading::Error> {
+        Ok(Self {
+            create: *lib.get(b"NDIlib_send_create\0")?,
+            destroy: *lib.get(b"NDIlib_send_destroy\0")?,
+            send_video_v2: *lib.get(b"NDIlib_send_send_video_v2\0")?,
+            send_audio_v3: *lib.get(b"NDIlib_send_send_audio_v3\0")?,
+            send_metadata: *lib.get(b"NDIlib_send_send_metadata\0")?,
+            get_no_connections: *lib.get(b"NDIlib_send_get_no_connections\0")?,
+            get_source_name: *lib.get(b"NDIlib_send_get_source_name\0")?,
+        })
+    }
+}
+
+impl PtzApi {
+    unsafe fn load(lib: &libloading::Library) -> Result<Self, libloading::Error> {
+        Ok(Self {
+            is_supported: *lib.get(b"NDIlib_recv_ptz_is_supported\0")?,
+            recall_preset: *lib.get(b"NDIlib_recv_ptz_recall_preset\0")?,
+            store_preset: *lib.get(b"NDIlib_recv_ptz_store_preset\0")?,
+            pan_tilt: *lib.get(b"NDIlib_recv_ptz_pan_tilt\0")?,
+            pan_tilt_speed: *lib.get(b"NDIlib_recv_ptz_pan_tilt_speed\0")?,
+            zoom: *lib.get(b"NDIlib_recv_ptz_zoom\0")?,
+            zoom_speed: *lib.get(b"NDIlib_recv_ptz_zoom_speed\0")?,
+        })
+    }
+}
+
+impl RoutingApi {
+    unsafe fn load(lib: &libloading::Library) -> Result<Self, libloading::Error> {
+        Ok(Self {
+            create: *lib.get(b"NDIlib_routing_create\0")?,
+            destroy: *lib.get(b"NDIlib_routing_destroy\0")?,
+            change: *lib.get(b"NDIlib_routing_change\0")?,
+            clear: *lib.get(b"NDIlib_routing_clear\0")?,
+        })
+    }
+}
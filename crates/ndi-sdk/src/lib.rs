@@ -0,0 +1,417 @@
+//! Runtime-loaded bindings to the NDI® SDK with safe wrappers for finding,
+//! receiving, sending and routing sources. The NDI runtime is loaded with `libloading`
+//! on [`load`], so nothing links against the SDK at build time. Windows, Linux
+//! and macOS runtimes are supported.
+//!
+//! NDI is a registered trademark of the Vizrt Group.
+
+#[allow(non_camel_case_types, non_upper_case_globals, non_snake_case)]
+pub mod ffi;
+pub mod metadata;
+pub mod routing;
+pub mod send;
+pub mod types;
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::sync::Arc;
+
+pub use routing::Router;
+pub use send::{timecode_from, AudioFrame, MetadataFrame, SendInstance, SendSettings, VideoFrame};
+pub use types::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NdiError {
+    #[error("{0}")]
+    DllNotFound(String),
+    #[error("NDI initialization failed")]
+    InitFailed,
+    #[error("failed to create find instance")]
+    FindCreateFailed,
+    #[error("failed to create receive instance")]
+    RecvCreateFailed,
+    #[error("failed to create send instance")]
+    SendCreateFailed,
+    #[error("failed to create routing instance")]
+    RoutingCreateFailed,
+    #[error("this NDI runtime has no {0} support; update it from https://ndi.video/tools/")]
+    Unsupported(&'static str),
+    #[error("invalid frame: {0}")]
+    InvalidFrame(String),
+}
+
+/// Top-level NDI library handle. Calls `NDIlib_destroy` on drop.
+pub struct NdiInstance {
+    api: Arc<ffi::NdiApi>,
+}
+
+impl NdiInstance {
+    pub fn create_find_instance(&self) -> Result<FindInstance, NdiError> {
+        self.create_find_instance_with(&FindSettings::default())
+    }
+
+    pub fn create_find_instance_with(
+        &self,
+        settings: &FindSettings,
+    ) -> Result<FindInstance, NdiError> {
+        let to_c = |s: &Option<String>| match s {
+            Some(s) => CString::new(s.as_str()).map(Some).map_err(|_| NdiError::FindCreateFailed),
+            None => Ok(None),
+        };
+        let (groups_c, extra_ips_c) = (to_c(&settings.groups)?, to_c(&settings.extra_ips)?);
+        // The SDK copies the strings; they need only outlive the call.
+        let settings = ffi::NDIlib_find_create_t {
+            show_local_sources: settings.show_local_sources,
+            p_groups: groups_c.as_ref().map_or(ptr::null(), |g| g.as_ptr()),
+            p_extra_ips: extra_ips_c.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+        };
+        let handle = unsafe { (self.api.find_create_v2)(&settings) };
+        if handle.is_null() {
+            return Err(NdiError::FindCreateFailed);
+        }
+        Ok(FindInstance {
+            handle,
+            api: Arc::clone(&self.api),
+        })
+    }
+
+    pub fn create_receive_instance(&self, settings: &RecvSettings) -> Result<ReceiveInstance, NdiError> {
+        let name_c = settings.name.as_deref().map(CString::new).transpose();
+        let name_c = name_c.map_err(|_| NdiError::RecvCreateFailed)?;
+        // The SDK copies the name; it need only outlive the call.
+        let settings = ffi::NDIlib_recv_create_v3_t {
+            source_to_connect_to: ffi::NDIlib_source_t {
+                p_ndi_name: ptr::null(),
+                p_url_address: ptr::null(),
+            },
+            color_format: settings.color_format.to_raw(),
+            bandwidth: settings.bandwidth.to_raw(),
+            allow_video_fields: settings.allow_video_fields,
+            p_ndi_recv_name: name_c.as_ref().map_or(ptr::null(), |n| n.as_ptr()),
+        };
+        let handle = unsafe { (self.api.recv_create_v3)(&settings) };
+        if handle.is_null() {
+            return Err(NdiError::RecvCreateFailed);
+        }
+        Ok(ReceiveInstance {
+            handle,
+            api: Arc::clone(&self.api),
+        })
+    }
+
+    /// Create a sender that publishes a new NDI source on the network.
+    pub fn create_send_instance(&self, settings: &SendSettings) -> Result<SendInstance, NdiError> {
+        SendInstance::create(Arc::clone(&self.api), settings)
+    }
+
+    /// Publish a routed source called `name`, which forwards whichever source it
+    /// is pointed at with [`Router::change`] without re-encoding.
+    pub fn create_router(&self, name: &str, groups: Option<&str>) -> Result<Router, NdiError> {
+        Router::create(Arc::clone(&self.api), name, groups)
+    }
+
+    /// Which optional parts of the SDK the runtime has.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            send: self.api.send.is_some(),
+            ptz: self.api.ptz.is_some(),
+            routing: self.api.routing.is_some(),
+            framesync: self.api.framesync,
+            recv_v4: self.api.recv_create_v4,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        unsafe {
+            let ptr = (self.api.version)();
+            if ptr.is_null() {
+                "unknown"
+            } else {
+                CStr::from_ptr(ptr).to_str().unwrap_or("unknown")
+            }
+        }
+    }
+}
+
+impl Drop for NdiInstance {
+    fn drop(&mut self) {
+        unsafe { (self.api.destroy)() }
+    }
+}
+
+/// Initialize the NDI library. Returns an `NdiInstance` that must stay alive
+/// for the duration of NDI usage.
+pub fn load() -> Result<NdiInstance, NdiError> {
+    let api = ffi::NdiApi::load().map_err(|e| {
+        NdiError::DllNotFound(format!("failed to load NDI runtime library: {e}"))
+    })?;
+    let ok = unsafe { (api.initialize)() };
+    if !ok {
+        return Err(NdiError::InitFailed);
+    }
+    Ok(NdiInstance {
+        api: Arc::new(api),
+    })
+}
+
+/// Copy an SDK source descriptor into an owned [`Source`].
+fn source_from_raw(s: &ffi::NDIlib_source_t) -> Source {
+    let name = if s.p_ndi_name.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(s.p_ndi_name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    let url = if s.p_url_address.is_null() {
+        None
+    } else {
+        let u = unsafe { CStr::from_ptr(s.p_url_address) }
+            .to_string_lossy()
+            .into_owned();
+        if u.is_empty() { None } else { Some(u) }
+    };
+    Source { name, url }
+}
+
+/// NDI source finder. Discovers sources on the network.
+pub struct FindInstance {
+    handle: ffi::NDIlib_find_instance_t,
+    api: Arc<ffi::NdiApi>,
+}
+
+// The NDI SDK states that find instances can be used from any thread.
+unsafe impl Send for FindInstance {}
+unsafe impl Sync for FindInstance {}
+
+impl FindInstance {
+    /// Block up to `timeout_ms` waiting for the source list to change.
+    /// Returns `true` if sources changed.
+    pub fn wait_for_sources(&self, timeout_ms: u32) -> bool {
+        unsafe { (self.api.find_wait_for_sources)(self.handle, timeout_ms) }
+    }
+
+    /// Get the current snapshot of discovered sources.
+    pub fn get_current_sources(&self) -> Vec<Source> {
+        let mut count: u32 = 0;
+        let ptr = unsafe { (self.api.find_get_current_sources)(self.handle, &mut count) };
+        if ptr.is_null() || count == 0 {
+            return Vec::new();
+        }
+        let sources = unsafe { std::slice::from_raw_parts(ptr, count as usize) };
+        sources.iter().map(source_from_raw).collect()
+    }
+}
+
+impl Drop for FindInstance {
+    fn drop(&mut self) {
+        unsafe { (self.api.find_destroy)(self.handle) }
+    }
+}
+
+/// NDI receiver. Receives frames from a connected source.
+pub struct ReceiveInstance {
+    handle: ffi::NDIlib_recv_instance_t,
+    api: Arc<ffi::NdiApi>,
+}
+
+unsafe impl Send for ReceiveInstance {}
+unsafe impl Sync for ReceiveInstance {}
+
+impl ReceiveInstance {
+    /// Connect to a source. Pass the source's name and optional URL.
+    pub fn connect(&self, source: &Source) {
+        let name_c = CString::new(source.name.as_str()).unwrap();
+        let url_c = source.url.as_ref().map(|u| CString::new(u.as_str()).unwrap());
+        let ndi_src = ffi::NDIlib_source_t {
+            p_ndi_name: name_c.as_ptr(),
+            p_url_address: url_c.as_ref().map_or(ptr::null(), |c| c.as_ptr()),
+        };
+        unsafe { (self.api.recv_connect)(self.handle, &ndi_src) }
+    }
+
+    /// Disconnect from the current source.
+    pub fn disconnect(&self) {
+        unsafe { (self.api.recv_connect)(self.handle, ptr::null()) }
+    }
+
+    /// Attempt to capture a video frame. Returns the frame type and fills `video_frame`.
+    /// The caller must call `free_video` when done with the frame data.
+    pub fn capture_video(&self, video_frame: &mut ffi::NDIlib_video_frame_v2_t, timeout_ms: u32) -> FrameType {
+        self.capture(video_frame, None, None, timeout_ms)
+    }
+
+    /// Attempt to capture a video, audio or metadata frame. Audio and metadata are
+    /// only requested when a frame is given for them; otherwise the SDK discards
+    /// them. The caller must free whichever frame type is returned.
+    pub fn capture(
+        &self,
+        video_frame: &mut ffi::NDIlib_video_frame_v2_t,
+        audio_frame: Option<&mut ffi::NDIlib_audio_frame_v3_t>,
+        metadata_frame: Option<&mut ffi::NDIlib_metadata_frame_t>,
+        timeout_ms: u32,
+    ) -> FrameType {
+        let audio_ptr = audio_frame.map_or(ptr::null_mut(), |a| a as *mut _);
+        let metadata_ptr = metadata_frame.map_or(ptr::null_mut(), |m| m as *mut _);
+        let frame_type = unsafe {
+            (self.api.recv_capture_v3)(
+                self.handle,
+                video_frame,
+                audio_ptr,
+                metadata_ptr,
+                timeout_ms,
+            )
+        };
+        FrameType::from(frame_type)
+    }
+
+    /// Query the connection state. Call after `capture` returns
+    /// [`FrameType::StatusChange`] to find out what changed.
+    pub fn status(&self) -> StatusChange {
+        StatusChange {
+            connections: unsafe { (self.api.recv_get_no_connections)(self.handle) }.max(0) as u32,
+            web_control: self.web_control(),
+            ptz_supported: self.ptz(|ptz| unsafe { (ptz.is_supported)(self.handle) }),
+        }
+    }
+
+    /// URL of the connected sender's configuration web page, if it has one.
+    pub fn web_control(&self) -> Option<String> {
+        let ptr = unsafe { (self.api.recv_get_web_control)(self.handle) };
+        if ptr.is_null() {
+            return None;
+        }
+        let url = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned();
+        unsafe { (self.api.recv_free_string)(self.handle, ptr) };
+        if url.is_empty() { None } else { Some(url) }
+    }
+
+    /// `call` with the PTZ API, or `false` if the runtime has none.
+    fn ptz(&self, call: impl FnOnce(&ffi::PtzApi) -> bool) -> bool {
+        self.api.ptz.as_ref().is_some_and(call)
+    }
+
+    /// Move a PTZ camera to a stored preset (0-99). `speed` runs from 0.0 (slowest)
+    /// to 1.0 (fastest). Returns `false` if the sender rejected the command.
+    pub fn ptz_recall_preset(&self, preset: u32, speed: f32) -> bool {
+        let preset = preset.min(99) as std::os::raw::c_int;
+        self.ptz(|ptz| unsafe { (ptz.recall_preset)(self.handle, preset, speed.clamp(0.0, 1.0)) })
+    }
+
+    /// Store the camera's current position as a preset (0-99).
+    pub fn ptz_store_preset(&self, preset: u32) -> bool {
+        let preset = preset.min(99) as std::os::raw::c_int;
+        self.ptz(|ptz| unsafe { (ptz.store_preset)(self.handle, preset) })
+    }
+
+    /// Move to an absolute position. `pan` runs from -1.0 (left) to 1.0 (right),
+    /// `tilt` from -1.0 (down) to 1.0 (up).
+    pub fn ptz_pan_tilt(&self, pan: f32, tilt: f32) -> bool {
+        let (pan, tilt) = (pan.clamp(-1.0, 1.0), tilt.clamp(-1.0, 1.0));
+        self.ptz(|ptz| unsafe { (ptz.pan_tilt)(self.handle, pan, tilt) })
+    }
+
+    /// Keep panning and tilting at the given speeds, -1.0 to 1.0 each, until told
+    /// otherwise; 0.0 stops.
+    pub fn ptz_pan_tilt_speed(&self, pan: f32, tilt: f32) -> bool {
+        let (pan, tilt) = (pan.clamp(-1.0, 1.0), tilt.clamp(-1.0, 1.0));
+        self.ptz(|ptz| unsafe { (ptz.pan_tilt_speed)(self.handle, pan, tilt) })
+    }
+
+    /// Zoom to an absolute level, from 0.0 (zoomed in) to 1.0 (zoomed out).
+    pub fn ptz_zoom(&self, zoom: f32) -> bool {
+        self.ptz(|ptz| unsafe { (ptz.zoom)(self.handle, zoom.clamp(0.0, 1.0)) })
+    }
+
+    /// Keep zooming at `speed`, from -1.0 (out) to 1.0 (in); 0.0 stops.
+    pub fn ptz_zoom_speed(&self, speed: f32) -> bool {
+        self.ptz(|ptz| unsafe { (ptz.zoom_speed)(self.handle, speed.clamp(-1.0, 1.0)) })
+    }
+
+    /// Tell the sender whether this receiver shows it on program or preview, so
+    /// cameras can light their tally lamps. Returns `false` if not connected.
+    pub fn set_tally(&self, tally: Tally) -> bool {
+        let raw = ffi::NDIlib_tally_t {
+            on_program: tally.on_program,
+            on_preview: tally.on_preview,
+        };
+        unsafe { (self.api.recv_set_tally)(self.handle, &raw) }
+    }
+
+    /// Free a video frame previously captured.
+    pub fn free_video(&self, video_frame: &ffi::NDIlib_video_frame_v2_t) {
+        unsafe { (self.api.recv_free_video_v2)(self.handle, video_frame) }
+    }
+
+    /// Free an audio frame previously captured.
+    pub fn free_audio(&self, audio_frame: &ffi::NDIlib_audio_frame_v3_t) {
+        unsafe { (self.api.recv_free_audio_v3)(self.handle, audio_frame) }
+    }
+
+    /// Free a metadata frame previously captured.
+    pub fn free_metadata(&self, metadata_frame: &ffi::NDIlib_metadata_frame_t) {
+        unsafe { (self.api.recv_free_metadata)(self.handle, metadata_frame) }
+    }
+
+    /// The XML text of a captured metadata frame.
+    pub fn metadata_text<'a>(&self, frame: &'a ffi::NDIlib_metadata_frame_t) -> Option<&'a str> {
+        if frame.p_data.is_null() {
+            return None;
+        }
+        unsafe { CStr::from_ptr(frame.p_data) }.to_str().ok()
+    }
+
+    /// Send metadata to the connected sender once. Returns `false` if not connected.
+    pub fn send_metadata(&self, frame: &MetadataFrame) -> bool {
+        let raw = frame.as_raw();
+        unsafe { (self.api.recv_send_metadata)(self.handle, &raw) }
+    }
+
+    /// Register metadata that is sent to every sender this receiver connects to,
+    /// including on reconnects. Typically a [`metadata::ProductInfo`] announcement.
+    pub fn add_connection_metadata(&self, frame: &MetadataFrame) {
+        let raw = frame.as_raw();
+        unsafe { (self.api.recv_add_connection_metadata)(self.handle, &raw) }
+    }
+
+    pub fn clear_connection_metadata(&self) {
+        unsafe { (self.api.recv_clear_connection_metadata)(self.handle) }
+    }
+
+    /// Get one channel of a captured planar float (FLTP) audio frame.
+    /// Returns `None` for other sample formats or an out-of-range channel.
+    pub fn audio_channel<'a>(&self, frame: &'a ffi::NDIlib_audio_frame_v3_t, channel: usize) -> Option<&'a [f32]> {
+        if frame.p_data.is_null()
+            || frame.four_cc != ffi::NDIlib_FourCC_audio_type_FLTP
+            || channel >= frame.no_channels.max(0) as usize
+        {
+            return None;
+        }
+        let offset = channel * frame.channel_stride_in_bytes as usize;
+        let samples = frame.no_samples.max(0) as usize;
+        Some(unsafe { std::slice::from_raw_parts(frame.p_data.add(offset) as *const f32, samples) })
+    }
+
+    /// Get the raw video data as a byte slice from a captured frame.
+    /// Returns `None` if `p_data` is null.
+    pub fn video_data<'a>(&self, frame: &'a ffi::NDIlib_video_frame_v2_t) -> Option<&'a [u8]> {
+        if frame.p_data.is_null() {
+            return None;
+        }
+        let fourcc = FourCCVideoType::from(frame.four_cc);
+        let w = frame.xres as usize;
+        let h = frame.yres as usize;
+        let stride = match frame.line_stride_in_bytes {
+            0 => fourcc.default_stride(w),
+            s => s as usize,
+        };
+        let len = fourcc.frame_size(w, h, stride);
+        Some(unsafe { std::slice::from_raw_parts(frame.p_data, len) })
+    }
+}
+
+impl Drop for ReceiveInstance {
+    fn drop(&mut self) {
+        unsafe { (self.api.recv_destroy)(self.handle) }
+    }
+}
@@ -0,0 +1,123 @@
+//! Helpers for the XML metadata conventions NDI devices use to introduce
+//! themselves to each other on connect and to share state such as tally.
+
+use crate::types::Tally;
+use std::collections::BTreeMap;
+
+/// A receiver's `<ndi_product>` announcement, sent to senders on connect so they
+/// (and their operators) can tell what is watching them.
+#[derive(Debug, Clone, Default)]
+pub struct ProductInfo {
+    pub long_name: String,
+    pub short_name: String,
+    pub manufacturer: String,
+    pub version: String,
+    pub model_name: String,
+    pub session: String,
+    pub serial: String,
+}
+
+impl ProductInfo {
+    pub fn to_xml(&self) -> String {
+        format!(
+            r#"<ndi_product long_name="{}" short_name="{}" manufacturer="{}" version="{}" model_name="{}" session="{}" serial="{}"/>"#,
+            escape(&self.long_name),
+            escape(&self.short_name),
+            escape(&self.manufacturer),
+            escape(&self.version),
+            escape(&self.model_name),
+            escape(&self.session),
+            escape(&self.serial),
+        )
+    }
+}
+
+/// Attributes of the first `<element .../>` tag in `xml`, e.g. a sender's
+/// `<ndi_capabilities ntk_ptz="true" web_control="http://..."/>`. Returns `None`
+/// if the element is absent.
+pub fn element_attributes(xml: &str, element: &str) -> Option<BTreeMap<String, String>> {
+    let open = format!("<{element}");
+    let start = xml.match_indices(&open).map(|(i, _)| i + open.len()).find(|&i| {
+        xml[i..].starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>')
+    })?;
+    let mut rest = &xml[start..];
+    let mut attributes = BTreeMap::new();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() || rest.starts_with('/') || rest.starts_with('>') {
+            return Some(attributes);
+        }
+        let eq = rest.find('=')?;
+        let name = rest[..eq].trim();
+        rest = rest[eq + 1..].trim_start();
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let end = rest[1..].find(quote)? + 1;
+        attributes.insert(name.to_string(), unescape(&rest[1..end]));
+        rest = &rest[end + 1..];
+    }
+}
+
+/// The sender's combined tally from all its receivers, if `xml` is its
+/// `<ndi_tally_echo on_program="true" on_preview="false"/>` message.
+pub fn tally_echo(xml: &str) -> Option<Tally> {
+    let attributes = element_attributes(xml, "ndi_tally_echo")?;
+    let flag = |key: &str| attributes.get(key).is_some_and(|v| v == "true");
+    Some(Tally { on_program: flag("on_program"), on_preview: flag("on_preview") })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_capabilities() {
+        let xml = r#"<ndi_capabilities web_control="http://10.0.0.5/?a=1&amp;b=2" ntk_ptz='true' ntk_exposure_v2="true"/>"#;
+        let caps = element_attributes(xml, "ndi_capabilities").unwrap();
+        assert_eq!(caps["web_control"], "http://10.0.0.5/?a=1&b=2");
+        assert_eq!(caps["ntk_ptz"], "true");
+        assert_eq!(caps.len(), 3);
+    }
+
+    #[test]
+    fn ignores_other_elements_and_prefix_matches() {
+        let xml = r#"<ndi_capabilities_ext x="1"/><ndi_capabilities ntk_ptz="false"></ndi_capabilities>"#;
+        let caps = element_attributes(xml, "ndi_capabilities").unwrap();
+        assert_eq!(caps.into_iter().collect::<Vec<_>>(), [("ntk_ptz".into(), "false".into())]);
+        assert!(element_attributes(xml, "ndi_product").is_none());
+    }
+
+    #[test]
+    fn parses_tally_echo() {
+        let tally = tally_echo(r#"<ndi_tally_echo on_program="true" on_preview="false"/>"#);
+        assert_eq!(tally, Some(Tally { on_program: true, on_preview: false }));
+        assert_eq!(tally_echo(r#"<ndi_tally on_program="true"/>"#), None);
+    }
+
+    #[test]
+    fn product_info_round_trips() {
+        let info = ProductInfo {
+            long_name: "Bridge \"A\" & <B>".into(),
+            version: "1.2.3".into(),
+            ..Default::default()
+        };
+        let attrs = element_attributes(&info.to_xml(), "ndi_product").unwrap();
+        assert_eq!(attrs["long_name"], info.long_name);
+        assert_eq!(attrs["version"], "1.2.3");
+    }
+}
@@ -0,0 +1,72 @@
+//! Routing: publish a source that forwards another source's stream, like a
+//! switcher output, without receiving or re-encoding it.
+
+use crate::{ffi, NdiError, Source};
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Arc;
+
+/// A routed NDI source. Receivers connected to it follow each [`Router::change`]
+/// without reconnecting. Destroyed (and unpublished) on drop.
+pub struct Router {
+    handle: ffi::NDIlib_routing_instance_t,
+    api: Arc<ffi::NdiApi>,
+}
+
+// The NDI SDK states that routing instances can be used from any thread.
+unsafe impl Send for Router {}
+unsafe impl Sync for Router {}
+
+impl Router {
+    pub(crate) fn create(
+        api: Arc<ffi::NdiApi>,
+        name: &str,
+        groups: Option<&str>,
+    ) -> Result<Self, NdiError> {
+        let name_c = CString::new(name).map_err(|_| NdiError::RoutingCreateFailed)?;
+        let groups_c = match groups {
+            Some(g) => Some(CString::new(g).map_err(|_| NdiError::RoutingCreateFailed)?),
+            None => None,
+        };
+        let raw = ffi::NDIlib_routing_create_t {
+            p_ndi_name: name_c.as_ptr(),
+            p_groups: groups_c.as_ref().map_or(ptr::null(), |g| g.as_ptr()),
+        };
+        let routing = api.routing.as_ref().ok_or(NdiError::Unsupported("routing"))?;
+        let handle = unsafe { (routing.create)(&raw) };
+        if handle.is_null() {
+            return Err(NdiError::RoutingCreateFailed);
+        }
+        Ok(Self { handle, api })
+    }
+
+    /// Present: creating the instance checked.
+    fn api(&self) -> &ffi::RoutingApi {
+        self.api.routing.as_ref().expect("routing API checked on create")
+    }
+
+    /// Point the routed source at `source`. Returns `false` if the SDK refused.
+    pub fn change(&self, source: &Source) -> bool {
+        let Ok(name_c) = CString::new(source.name.as_str()) else {
+            return false;
+        };
+        let url_c = source.url.as_deref().and_then(|u| CString::new(u).ok());
+        let raw = ffi::NDIlib_source_t {
+            p_ndi_name: name_c.as_ptr(),
+            p_url_address: url_c.as_ref().map_or(ptr::null(), |c| c.as_ptr()),
+        };
+        unsafe { (self.api().change)(self.handle, &raw) }
+    }
+
+    /// Route nothing: receivers of the routed source see no video until the next
+    /// [`Router::change`].
+    pub fn clear(&self) -> bool {
+        unsafe { (self.api().clear)(self.handle) }
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        unsafe { (self.api().destroy)(self.handle) }
+    }
+}
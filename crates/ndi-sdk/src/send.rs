@@ -0,0 +1,379 @@
+//! Sending: publish an NDI source and push video, audio and metadata frames to it.
+
+use crate::{ffi, source_from_raw, FourCCVideoType, NdiError, Source};
+use std::ffi::CString;
+use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings for [`NdiInstance::create_send_instance`](crate::NdiInstance::create_send_instance).
+#[derive(Debug, Clone)]
+pub struct SendSettings {
+    /// Source name as shown on the network; the SDK prefixes it with the machine name.
+    pub name: String,
+    /// Comma-separated NDI groups to publish in; `None` for the default group.
+    pub groups: Option<String>,
+    /// Pace video to the frame rate: `send_video` blocks until the frame is due,
+    /// so a producer that runs ahead is slowed down instead of bursting frames.
+    pub clock_video: bool,
+    /// Pace audio to the sample rate. Only clock one of audio and video when
+    /// sending both from the same thread.
+    pub clock_audio: bool,
+}
+
+impl SendSettings {
+    /// Settings with the SDK defaults: both video and audio clocked.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            groups: None,
+            clock_video: true,
+            clock_audio: true,
+        }
+    }
+
+    pub fn groups(mut self, groups: impl Into<String>) -> Self {
+        self.groups = Some(groups.into());
+        self
+    }
+
+    pub fn clock_video(mut self, clocked: bool) -> Self {
+        self.clock_video = clocked;
+        self
+    }
+
+    pub fn clock_audio(mut self, clocked: bool) -> Self {
+        self.clock_audio = clocked;
+        self
+    }
+}
+
+/// Convert a media time (e.g. since the start of a stream) to an NDI timecode
+/// in 100 ns units, for use with the frames' `timecode` setters.
+pub fn timecode_from(time: Duration) -> i64 {
+    (time.as_nanos() / 100).min(i64::MAX as u128 - 1) as i64
+}
+
+/// An owned video frame ready to be sent. The buffer is allocated by
+/// [`VideoFrame::new`] and can be filled in place through [`VideoFrame::data_mut`].
+pub struct VideoFrame {
+    width: usize,
+    height: usize,
+    fourcc: FourCCVideoType,
+    stride: usize,
+    frame_rate: (i32, i32),
+    aspect_ratio: f32,
+    progressive: bool,
+    timecode: i64,
+    metadata: Option<CString>,
+    data: Vec<u8>,
+}
+
+impl VideoFrame {
+    /// A zeroed, tightly packed progressive frame at 30000/1001 fps.
+    pub fn new(width: usize, height: usize, fourcc: FourCCVideoType) -> Self {
+        let stride = fourcc.default_stride(width);
+        let data = vec![0; fourcc.frame_size(width, height, stride)];
+        Self::with_buffer(width, height, fourcc, stride, data)
+    }
+
+    /// Wrap an existing buffer with the given line stride. Fails if the buffer is
+    /// too small for the frame.
+    pub fn from_data(
+        width: usize,
+        height: usize,
+        fourcc: FourCCVideoType,
+        stride: usize,
+        data: Vec<u8>,
+    ) -> Result<Self, NdiError> {
+        let needed = fourcc.frame_size(width, height, stride);
+        if stride < fourcc.default_stride(width) || data.len() < needed {
+            return Err(NdiError::InvalidFrame(format!(
+                "{width}x{height} {fourcc:?} with stride {stride} needs {needed} bytes, got {}",
+                data.len()
+            )));
+        }
+        Ok(Self::with_buffer(width, height, fourcc, stride, data))
+    }
+
+    fn with_buffer(width: usize, height: usize, fourcc: FourCCVideoType, stride: usize, data: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            fourcc,
+            stride,
+            frame_rate: (30000, 1001),
+            aspect_ratio: 0.0,
+            progressive: true,
+            timecode: ffi::NDIlib_send_timecode_synthesize,
+            metadata: None,
+            data,
+        }
+    }
+
+    /// Nominal frame rate; also the pacing rate when the sender clocks video.
+    pub fn frame_rate(mut self, numerator: i32, denominator: i32) -> Self {
+        self.frame_rate = (numerator, denominator);
+        self
+    }
+
+    /// Picture aspect ratio; 0 means square pixels.
+    pub fn aspect_ratio(mut self, ratio: f32) -> Self {
+        self.aspect_ratio = ratio;
+        self
+    }
+
+    /// Mark the frame as interleaved fields instead of progressive.
+    pub fn interlaced(mut self) -> Self {
+        self.progressive = false;
+        self
+    }
+
+    /// Timecode in 100 ns units (see [`timecode_from`]). Defaults to letting the
+    /// SDK synthesize one from the send clock.
+    pub fn timecode(mut self, timecode: i64) -> Self {
+        self.timecode = timecode;
+        self
+    }
+
+    /// Attach per-frame XML metadata.
+    pub fn metadata(mut self, xml: &str) -> Result<Self, NdiError> {
+        self.metadata = Some(to_cstring(xml)?);
+        Ok(self)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// The SDK descriptor pointing into this frame. Only valid while `self` is borrowed.
+    fn as_raw(&self) -> ffi::NDIlib_video_frame_v2_t {
+        ffi::NDIlib_video_frame_v2_t {
+            xres: self.width as i32,
+            yres: self.height as i32,
+            four_cc: self.fourcc.to_raw(),
+            frame_rate_n: self.frame_rate.0,
+            frame_rate_d: self.frame_rate.1,
+            picture_aspect_ratio: self.aspect_ratio,
+            frame_format_type: if self.progressive {
+                ffi::NDIlib_frame_format_type_progressive
+            } else {
+                ffi::NDIlib_frame_format_type_interleaved
+            },
+            timecode: self.timecode,
+            // The SDK only reads from the buffer when sending.
+            p_data: self.data.as_ptr() as *mut u8,
+            line_stride_in_bytes: self.stride as i32,
+            p_metadata: self.metadata.as_ref().map_or(ptr::null(), |m| m.as_ptr()),
+            timestamp: 0,
+        }
+    }
+}
+
+/// An owned planar float (FLTP) audio frame ready to be sent.
+pub struct AudioFrame {
+    sample_rate: u32,
+    channels: usize,
+    samples: usize,
+    timecode: i64,
+    metadata: Option<CString>,
+    /// Channel-major: all samples of channel 0, then channel 1, ...
+    data: Vec<f32>,
+}
+
+impl AudioFrame {
+    /// A silent frame of `samples` samples per channel.
+    pub fn new(sample_rate: u32, channels: usize, samples: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            samples,
+            timecode: ffi::NDIlib_send_timecode_synthesize,
+            metadata: None,
+            data: vec![0.0; channels * samples],
+        }
+    }
+
+    /// Copy planar channel slices into a new frame. All channels must be the same length.
+    pub fn from_planar(sample_rate: u32, channels: &[&[f32]]) -> Result<Self, NdiError> {
+        let samples = channels.first().map_or(0, |c| c.len());
+        if channels.iter().any(|c| c.len() != samples) {
+            return Err(NdiError::InvalidFrame("audio channels differ in length".into()));
+        }
+        let mut frame = Self::new(sample_rate, channels.len(), samples);
+        for (i, channel) in channels.iter().enumerate() {
+            frame.channel_mut(i).copy_from_slice(channel);
+        }
+        Ok(frame)
+    }
+
+    /// Timecode in 100 ns units (see [`timecode_from`]). Defaults to letting the
+    /// SDK synthesize one from the send clock.
+    pub fn timecode(mut self, timecode: i64) -> Self {
+        self.timecode = timecode;
+        self
+    }
+
+    /// Attach per-frame XML metadata.
+    pub fn metadata(mut self, xml: &str) -> Result<Self, NdiError> {
+        self.metadata = Some(to_cstring(xml)?);
+        Ok(self)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn channel(&self, channel: usize) -> &[f32] {
+        &self.data[channel * self.samples..(channel + 1) * self.samples]
+    }
+
+    pub fn channel_mut(&mut self, channel: usize) -> &mut [f32] {
+        &mut self.data[channel * self.samples..(channel + 1) * self.samples]
+    }
+
+    fn as_raw(&self) -> ffi::NDIlib_audio_frame_v3_t {
+        ffi::NDIlib_audio_frame_v3_t {
+            sample_rate: self.sample_rate as i32,
+            no_channels: self.channels as i32,
+            no_samples: self.samples as i32,
+            timecode: self.timecode,
+            four_cc: ffi::NDIlib_FourCC_audio_type_FLTP,
+            p_data: self.data.as_ptr() as *mut u8,
+            channel_stride_in_bytes: (self.samples * size_of::<f32>()) as i32,
+            p_metadata: self.metadata.as_ref().map_or(ptr::null(), |m| m.as_ptr()),
+            timestamp: 0,
+        }
+    }
+}
+
+/// An owned XML metadata frame.
+pub struct MetadataFrame {
+    timecode: i64,
+    data: CString,
+}
+
+impl MetadataFrame {
+    pub fn new(xml: &str) -> Result<Self, NdiError> {
+        Ok(Self {
+            timecode: ffi::NDIlib_send_timecode_synthesize,
+            data: to_cstring(xml)?,
+        })
+    }
+
+    /// Timecode in 100 ns units (see [`timecode_from`]). Defaults to letting the
+    /// SDK synthesize one from the send clock.
+    pub fn timecode(mut self, timecode: i64) -> Self {
+        self.timecode = timecode;
+        self
+    }
+
+    pub(crate) fn as_raw(&self) -> ffi::NDIlib_metadata_frame_t {
+        ffi::NDIlib_metadata_frame_t {
+            length: self.data.as_bytes_with_nul().len() as i32,
+            timecode: self.timecode,
+            p_data: self.data.as_ptr() as *mut _,
+        }
+    }
+}
+
+fn to_cstring(s: &str) -> Result<CString, NdiError> {
+    CString::new(s).map_err(|_| NdiError::InvalidFrame("string contains a NUL byte".into()))
+}
+
+/// NDI sender. Publishes a source on the network for as long as it is alive.
+pub struct SendInstance {
+    handle: ffi::NDIlib_send_instance_t,
+    api: Arc<ffi::NdiApi>,
+}
+
+// The NDI SDK states that send instances can be used from any thread.
+unsafe impl Send for SendInstance {}
+unsafe impl Sync for SendInstance {}
+
+impl SendInstance {
+    pub(crate) fn create(api: Arc<ffi::NdiApi>, settings: &SendSettings) -> Result<Self, NdiError> {
+        let name_c = CString::new(settings.name.as_str()).map_err(|_| NdiError::SendCreateFailed)?;
+        let groups_c = match &settings.groups {
+            Some(g) => Some(CString::new(g.as_str()).map_err(|_| NdiError::SendCreateFailed)?),
+            None => None,
+        };
+        let raw = ffi::NDIlib_send_create_t {
+            p_ndi_name: name_c.as_ptr(),
+            p_groups: groups_c.as_ref().map_or(ptr::null(), |g| g.as_ptr()),
+            clock_video: settings.clock_video,
+            clock_audio: settings.clock_audio,
+        };
+        let send = api.send.as_ref().ok_or(NdiError::Unsupported("sending"))?;
+        let handle = unsafe { (send.create)(&raw) };
+        if handle.is_null() {
+            return Err(NdiError::SendCreateFailed);
+        }
+        Ok(Self { handle, api })
+    }
+
+    /// Present: creating the instance checked.
+    fn api(&self) -> &ffi::SendApi {
+        self.api.send.as_ref().expect("send API checked on create")
+    }
+
+    /// Send a video frame. The SDK copies the data before returning. With
+    /// [`SendSettings::clock_video`] this blocks until the frame is due.
+    pub fn send_video(&self, frame: &VideoFrame) {
+        let raw = frame.as_raw();
+        unsafe { (self.api().send_video_v2)(self.handle, &raw) }
+    }
+
+    /// Send an audio frame. The SDK copies the data before returning. With
+    /// [`SendSettings::clock_audio`] this blocks until the frame is due.
+    pub fn send_audio(&self, frame: &AudioFrame) {
+        let raw = frame.as_raw();
+        unsafe { (self.api().send_audio_v3)(self.handle, &raw) }
+    }
+
+    pub fn send_metadata(&self, frame: &MetadataFrame) {
+        let raw = frame.as_raw();
+        unsafe { (self.api().send_metadata)(self.handle, &raw) }
+    }
+
+    /// Number of receivers currently connected, waiting up to `timeout_ms` for
+    /// at least one to appear.
+    pub fn connections(&self, timeout_ms: u32) -> u32 {
+        unsafe { (self.api().get_no_connections)(self.handle, timeout_ms) }.max(0) as u32
+    }
+
+    /// The full network name of this source, as receivers will see it.
+    pub fn source(&self) -> Option<Source> {
+        let raw = unsafe { (self.api().get_source_name)(self.handle) };
+        if raw.is_null() {
+            return None;
+        }
+        Some(source_from_raw(unsafe { &*raw }))
+    }
+}
+
+impl Drop for SendInstance {
+    fn drop(&mut self) {
+        unsafe { (self.api().destroy)(self.handle) }
+    }
+}
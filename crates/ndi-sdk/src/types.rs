@@ -0,0 +1,259 @@
+use super::ffi;
+
+// Variant names follow the SDK's FourCC codes.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FourCCVideoType {
+    UYVY,
+    UYVA,
+    I420,
+    NV12,
+    YV12,
+    BGRA,
+    BGRX,
+    RGBA,
+    RGBX,
+    Unknown(u32),
+}
+
+impl From<ffi::NDIlib_FourCC_video_type_e> for FourCCVideoType {
+    fn from(v: ffi::NDIlib_FourCC_video_type_e) -> Self {
+        match v {
+            ffi::NDIlib_FourCC_video_type_UYVY => Self::UYVY,
+            ffi::NDIlib_FourCC_video_type_UYVA => Self::UYVA,
+            ffi::NDIlib_FourCC_video_type_I420 => Self::I420,
+            ffi::NDIlib_FourCC_video_type_NV12 => Self::NV12,
+            ffi::NDIlib_FourCC_video_type_YV12 => Self::YV12,
+            ffi::NDIlib_FourCC_video_type_BGRA => Self::BGRA,
+            ffi::NDIlib_FourCC_video_type_BGRX => Self::BGRX,
+            ffi::NDIlib_FourCC_video_type_RGBA => Self::RGBA,
+            ffi::NDIlib_FourCC_video_type_RGBX => Self::RGBX,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl FourCCVideoType {
+    pub fn to_raw(self) -> ffi::NDIlib_FourCC_video_type_e {
+        match self {
+            Self::UYVY => ffi::NDIlib_FourCC_video_type_UYVY,
+            Self::UYVA => ffi::NDIlib_FourCC_video_type_UYVA,
+            Self::I420 => ffi::NDIlib_FourCC_video_type_I420,
+            Self::NV12 => ffi::NDIlib_FourCC_video_type_NV12,
+            Self::YV12 => ffi::NDIlib_FourCC_video_type_YV12,
+            Self::BGRA => ffi::NDIlib_FourCC_video_type_BGRA,
+            Self::BGRX => ffi::NDIlib_FourCC_video_type_BGRX,
+            Self::RGBA => ffi::NDIlib_FourCC_video_type_RGBA,
+            Self::RGBX => ffi::NDIlib_FourCC_video_type_RGBX,
+            Self::Unknown(v) => v,
+        }
+    }
+
+    /// Line stride in bytes of a tightly packed frame (of the luma plane for planar formats).
+    pub fn default_stride(self, width: usize) -> usize {
+        match self {
+            Self::UYVY | Self::UYVA => width * 2,
+            Self::BGRA | Self::BGRX | Self::RGBA | Self::RGBX => width * 4,
+            Self::I420 | Self::NV12 | Self::YV12 => width,
+            Self::Unknown(_) => width * 2,
+        }
+    }
+
+    /// Total buffer size in bytes for a frame with the given line stride,
+    /// including the alpha plane of UYVA and the chroma planes of 4:2:0 formats.
+    pub fn frame_size(self, width: usize, height: usize, stride: usize) -> usize {
+        match self {
+            Self::UYVA => stride * height + width * height,
+            Self::I420 | Self::NV12 | Self::YV12 => stride * height * 3 / 2,
+            _ => stride * height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvBandwidth {
+    MetadataOnly,
+    AudioOnly,
+    Lowest,
+    Highest,
+}
+
+impl RecvBandwidth {
+    pub fn to_raw(self) -> ffi::NDIlib_recv_bandwidth_e {
+        match self {
+            Self::MetadataOnly => ffi::NDIlib_recv_bandwidth_metadata_only,
+            Self::AudioOnly => ffi::NDIlib_recv_bandwidth_audio_only,
+            Self::Lowest => ffi::NDIlib_recv_bandwidth_lowest,
+            Self::Highest => ffi::NDIlib_recv_bandwidth_highest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvColorFormat {
+    BgrxBgra,
+    UyvyBgra,
+    RgbxRgba,
+    UyvyRgba,
+    Fastest,
+    Best,
+}
+
+impl RecvColorFormat {
+    pub fn to_raw(self) -> ffi::NDIlib_recv_color_format_e {
+        match self {
+            Self::BgrxBgra => ffi::NDIlib_recv_color_format_BGRX_BGRA,
+            Self::UyvyBgra => ffi::NDIlib_recv_color_format_UYVY_BGRA,
+            Self::RgbxRgba => ffi::NDIlib_recv_color_format_RGBX_RGBA,
+            Self::UyvyRgba => ffi::NDIlib_recv_color_format_UYVY_RGBA,
+            Self::Fastest => ffi::NDIlib_recv_color_format_fastest,
+            Self::Best => ffi::NDIlib_recv_color_format_best,
+        }
+    }
+}
+
+/// Settings for [`NdiInstance::create_find_instance_with`](crate::NdiInstance::create_find_instance_with).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindSettings {
+    /// List sources sent from this machine too.
+    pub show_local_sources: bool,
+    /// Comma-separated NDI groups to look in; `None` for the default group.
+    pub groups: Option<String>,
+    /// Comma-separated IP addresses of machines to ask for their sources
+    /// directly, for senders mDNS doesn't reach, e.g. on other subnets.
+    pub extra_ips: Option<String>,
+}
+
+impl Default for FindSettings {
+    fn default() -> Self {
+        Self { show_local_sources: true, groups: None, extra_ips: None }
+    }
+}
+
+/// Settings for [`NdiInstance::create_receive_instance`](crate::NdiInstance::create_receive_instance).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecvSettings {
+    pub bandwidth: RecvBandwidth,
+    pub color_format: RecvColorFormat,
+    /// Deliver interlaced sources as separate fields instead of woven frames.
+    pub allow_video_fields: bool,
+    /// What senders and tools like NDI Studio Monitor show the receiver as;
+    /// `None` for an anonymous receiver.
+    pub name: Option<String>,
+}
+
+impl Default for RecvSettings {
+    fn default() -> Self {
+        Self {
+            bandwidth: RecvBandwidth::Highest,
+            color_format: RecvColorFormat::Fastest,
+            allow_video_fields: true,
+            name: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    None,
+    Video,
+    Audio,
+    Metadata,
+    Error,
+    StatusChange,
+    Unknown(i32),
+}
+
+impl From<ffi::NDIlib_frame_type_e> for FrameType {
+    fn from(v: ffi::NDIlib_frame_type_e) -> Self {
+        match v {
+            ffi::NDIlib_frame_type_none => Self::None,
+            ffi::NDIlib_frame_type_video => Self::Video,
+            ffi::NDIlib_frame_type_audio => Self::Audio,
+            ffi::NDIlib_frame_type_metadata => Self::Metadata,
+            ffi::NDIlib_frame_type_error => Self::Error,
+            ffi::NDIlib_frame_type_status_change => Self::StatusChange,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// How a video frame's lines are laid out. Interlaced senders send woven
+/// frames, or separate fields to receivers that allow them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    Progressive,
+    /// Both fields woven into one frame.
+    Interleaved,
+    /// The even lines only, at half height.
+    Field0,
+    /// The odd lines only, at half height.
+    Field1,
+    Unknown(i32),
+}
+
+impl From<ffi::NDIlib_frame_format_type_e> for FrameFormat {
+    fn from(v: ffi::NDIlib_frame_format_type_e) -> Self {
+        match v {
+            ffi::NDIlib_frame_format_type_progressive => Self::Progressive,
+            ffi::NDIlib_frame_format_type_interleaved => Self::Interleaved,
+            ffi::NDIlib_frame_format_type_field_0 => Self::Field0,
+            ffi::NDIlib_frame_format_type_field_1 => Self::Field1,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// What a receiver knows about its connection, queried after the SDK reports
+/// [`FrameType::StatusChange`]. Compare with the previous value to see what changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusChange {
+    /// Number of senders this receiver is connected to (0 or 1 in practice).
+    pub connections: u32,
+    /// URL of the sender's own configuration web page, if it offers one.
+    pub web_control: Option<String>,
+    /// Whether the sender accepts PTZ commands.
+    pub ptz_supported: bool,
+}
+
+/// Which optional parts of the SDK the loaded runtime has. Older runtimes lack
+/// some, and the features built on them report [`crate::NdiError::Unsupported`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Publishing sources, since NDI 3.
+    pub send: bool,
+    /// Controlling PTZ cameras, since NDI 3.5.
+    pub ptz: bool,
+    /// Routed sources, since NDI 4.
+    pub routing: bool,
+    /// Frame synchronizers, since NDI 4.
+    pub framesync: bool,
+    /// `NDIlib_recv_create_v4`, since NDI 5.
+    pub recv_v4: bool,
+}
+
+impl Capabilities {
+    /// Each capability by name, in the order of the fields.
+    pub fn list(&self) -> [(&'static str, bool); 5] {
+        [
+            ("send", self.send),
+            ("ptz", self.ptz),
+            ("routing", self.routing),
+            ("framesync", self.framesync),
+            ("recv_v4", self.recv_v4),
+        ]
+    }
+}
+
+/// Whether a source is on air (program) or cued up (preview).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub on_program: bool,
+    pub on_preview: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub url: Option<String>,
+}
@@ -0,0 +1,215 @@
+//! Streams of several sources at once, each over a WebSocket of its own that
+//! reconnects after server restarts and network trouble, with their events
+//! merged into one queue.
+
+use crate::protocol::{self, ClientMessage, Decoder, Event, Options};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// Events waiting for [`Client::next`] before sources stop reading their sockets.
+const QUEUE: usize = 64;
+
+/// The first wait before reconnecting, doubled after each failed attempt.
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+
+/// The longest wait before reconnecting.
+const MAX_RETRY: Duration = Duration::from_secs(10);
+
+/// A client of one server. Must be used within a Tokio runtime.
+pub struct Client {
+    base: String,
+    retry: (Duration, Duration),
+    events_tx: mpsc::Sender<(String, Event)>,
+    events: mpsc::Receiver<(String, Event)>,
+    sources: HashMap<String, Subscription>,
+}
+
+/// A source's connection task, and where to send it control messages.
+struct Subscription {
+    commands: mpsc::UnboundedSender<ClientMessage>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    /// A client of the server at `base`, e.g. `http://localhost:8080`. Only
+    /// plain `ws` connections are made; put TLS in front of the client's
+    /// network, not in it.
+    pub fn new(base: impl Into<String>) -> Self {
+        let (events_tx, events) = mpsc::channel(QUEUE);
+        Self {
+            base: base.into(),
+            retry: (FIRST_RETRY, MAX_RETRY),
+            events_tx,
+            events,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Wait `first` before reconnecting, doubling up to `max` while attempts fail.
+    pub fn retry_delays(mut self, first: Duration, max: Duration) -> Self {
+        self.retry = (first, max.max(first));
+        self
+    }
+
+    /// Start streaming `options.source`, replacing any stream of it there was.
+    pub fn subscribe(&mut self, options: Options) {
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let connection = Connection {
+            url: options.url(&self.base),
+            source: options.source.clone(),
+            retry: self.retry,
+            events: self.events_tx.clone(),
+        };
+        let task = tokio::spawn(connection.run(commands_rx));
+        self.sources.insert(options.source, Subscription { commands, task });
+    }
+
+    /// Stop streaming `source`. Returns whether it was streamed.
+    pub fn unsubscribe(&mut self, source: &str) -> bool {
+        self.sources.remove(source).is_some()
+    }
+
+    /// Move the split of comparison source `source`. Returns whether it is
+    /// streamed; while it reconnects, the message is dropped.
+    pub fn split(&self, source: &str, at: f32) -> bool {
+        self.send(source, ClientMessage::split(at))
+    }
+
+    /// Ask for the next frame of `source` as an [`Event::Still`].
+    pub fn grab(&self, source: &str) -> bool {
+        self.send(source, ClientMessage::grab())
+    }
+
+    fn send(&self, source: &str, message: ClientMessage) -> bool {
+        let subscription = self.sources.get(source);
+        subscription.is_some_and(|subscription| subscription.commands.send(message).is_ok())
+    }
+
+    /// The next event of any source, with the source's name. `None` once every
+    /// source has disconnected for good, or none is streamed.
+    pub async fn next(&mut self) -> Option<(String, Event)> {
+        if self.sources.is_empty() {
+            return self.events.try_recv().ok();
+        }
+        let (source, event) = self.events.recv().await?;
+        if matches!(event, Event::Disconnected { retrying: false, .. }) {
+            self.sources.remove(&source);
+        }
+        Some((source, event))
+    }
+}
+
+/// One source's stream, reconnected until the client goes or the server
+/// refuses it.
+struct Connection {
+    url: String,
+    source: String,
+    retry: (Duration, Duration),
+    events: mpsc::Sender<(String, Event)>,
+}
+
+impl Connection {
+    async fn run(self, mut commands: mpsc::UnboundedReceiver<ClientMessage>) {
+        let (first, max) = self.retry;
+        let mut delay = first;
+        loop {
+            // Control messages for a connection that's gone would arrive late.
+            while commands.try_recv().is_ok() {}
+            let (code, reason) = match self.connect().await {
+                Ok(socket) => match self.stream(socket, &mut commands, &mut delay).await {
+                    Some(closed) => closed,
+                    None => return,
+                },
+                Err(refused) => refused,
+            };
+            let retrying = protocol::retry(code);
+            let event = Event::Disconnected { code, reason, retrying };
+            if !self.send(event).await || !retrying {
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(max);
+        }
+    }
+
+    /// Open the socket. A refused handshake's status `s` reads as close code
+    /// `4000 + s`, like the refusals the server sends after it.
+    async fn connect(&self) -> Result<Socket, (Option<u16>, String)> {
+        let mut request = self
+            .url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| (Some(1002), e.to_string()))?;
+        let name = protocol::PROTOCOL.parse().expect("valid header value");
+        request.headers_mut().insert("sec-websocket-protocol", name);
+        match tokio_tungstenite::connect_async(request).await {
+            Ok((socket, _)) => Ok(socket),
+            Err(tungstenite::Error::Http(response)) => {
+                let status = response.status();
+                Err((Some(4000 + status.as_u16()), status.to_string()))
+            }
+            Err(e) => Err((None, e.to_string())),
+        }
+    }
+
+    /// Pass the socket's events on until it closes, with how it closed, or
+    /// `None` once the client is gone. A `hello` resets the reconnect `delay`.
+    async fn stream(
+        &self,
+        mut socket: Socket,
+        commands: &mut mpsc::UnboundedReceiver<ClientMessage>,
+        delay: &mut Duration,
+    ) -> Option<(Option<u16>, String)> {
+        let mut decoder = Decoder::new();
+        loop {
+            let event = tokio::select! {
+                message = socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => decoder.text(&text),
+                    Some(Ok(Message::Binary(data))) => Some(decoder.binary(data)),
+                    Some(Ok(Message::Close(frame))) => {
+                        let closed = frame.map(|f| (Some(u16::from(f.code)), f.reason.to_string()));
+                        return Some(closed.unwrap_or((None, "closed".to_string())));
+                    }
+                    Some(Ok(_)) => None,
+                    Some(Err(e)) => return Some((None, e.to_string())),
+                    None => return Some((None, "connection lost".to_string())),
+                },
+                Some(message) = commands.recv() => {
+                    if let Err(e) = socket.send(Message::Text(message.to_json().into())).await {
+                        return Some((None, e.to_string()));
+                    }
+                    None
+                }
+            };
+            let Some(event) = event else {
+                continue;
+            };
+            if matches!(event, Event::Connected { .. }) {
+                *delay = self.retry.0;
+            }
+            if !self.send(event).await {
+                return None;
+            }
+        }
+    }
+
+    /// Queue `event` for the client; false once it's gone.
+    async fn send(&self, event: Event) -> bool {
+        self.events.send((self.source.clone(), event)).await.is_ok()
+    }
+}
+
+type Socket = tokio_tungstenite::WebSocketStream<
+    tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+>;
@@ -0,0 +1,294 @@
+//! The client for JavaScript, on the browser's `WebSocket`. Build it with
+//! `wasm-pack build --target web -- --features js`, then:
+//!
+//! ```js
+//! import init, { Client } from "./pkg/streambridge_client.js";
+//! await init();
+//! const client = new Client("http://localhost:8080", (source, event) => {
+//!     if (event.type === "frame") show(source, event.data);
+//! });
+//! client.subscribe("CAM 1", { fps: 10, annotations: true });
+//! ```
+//!
+//! Events are objects with a `type`: `connected` (with `format`), `frame`
+//! (`data` as a `Uint8Array`, `seq` with annotations, and `timecode`,
+//! `timestamp`, `received_ms`, `width` and `height` with `framing: "meta"`),
+//! `still` (`format`, `data`), `metadata` (`xml`), `annotations`, `levels`
+//! (`channels`, each with `rms` and `peak`), `sender_reconnecting`
+//! (`attempt`), `sender_reconnected` and `disconnected` (`code`, `reason`,
+//! `retrying`). Browsers don't show why a handshake failed, so refusals before
+//! the connection opens read as code 1006 and are retried.
+
+use crate::protocol::{self, ClientMessage, Decoder, Event, Options};
+use js_sys::{Function, Object, Reflect, Uint8Array};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+/// The first wait before reconnecting, in milliseconds, doubled after each
+/// failed attempt.
+const FIRST_RETRY_MS: i32 = 250;
+
+/// The longest wait before reconnecting, in milliseconds.
+const MAX_RETRY_MS: i32 = 10_000;
+
+/// A client of one server, calling `on_event(source, event)` for the events of
+/// every source it streams.
+#[wasm_bindgen]
+pub struct Client {
+    base: String,
+    on_event: Function,
+    sources: HashMap<String, Rc<RefCell<Connection>>>,
+}
+
+#[wasm_bindgen]
+impl Client {
+    #[wasm_bindgen(constructor)]
+    pub fn new(base: String, on_event: Function) -> Client {
+        Client { base, on_event, sources: HashMap::new() }
+    }
+
+    /// Start streaming `source`, replacing any stream of it there was.
+    /// `options` takes the `/ws` query parameters, e.g. `{ fps: 10 }`.
+    pub fn subscribe(&mut self, source: String, options: JsValue) -> Result<(), JsError> {
+        let json = if options.is_undefined() || options.is_null() {
+            "{}".to_string()
+        } else {
+            let json = js_sys::JSON::stringify(&options);
+            json.map(String::from).map_err(|_| JsError::new("options aren't JSON"))?
+        };
+        let options: Options = serde_json::from_str(&json)?;
+        let options = Options { source: source.clone(), ..options };
+        let connection = Rc::new(RefCell::new(Connection {
+            url: options.url(&self.base),
+            source: source.clone(),
+            on_event: self.on_event.clone(),
+            socket: None,
+            decoder: Decoder::new(),
+            delay_ms: FIRST_RETRY_MS,
+            closed: false,
+            handlers: None,
+        }));
+        connect(&connection);
+        if let Some(old) = self.sources.insert(source, connection) {
+            old.borrow_mut().close();
+        }
+        Ok(())
+    }
+
+    /// Stop streaming `source`. Returns whether it was streamed.
+    pub fn unsubscribe(&mut self, source: &str) -> bool {
+        let connection = self.sources.remove(source);
+        connection.map(|connection| connection.borrow_mut().close()).is_some()
+    }
+
+    /// Move the split of comparison source `source`. Returns whether it is
+    /// streamed; while it reconnects, the message is dropped.
+    pub fn split(&self, source: &str, at: f32) -> bool {
+        self.send(source, ClientMessage::split(at))
+    }
+
+    /// Ask for the next frame of `source` as a `still` event.
+    pub fn grab(&self, source: &str) -> bool {
+        self.send(source, ClientMessage::grab())
+    }
+
+    fn send(&self, source: &str, message: ClientMessage) -> bool {
+        let Some(connection) = self.sources.get(source) else {
+            return false;
+        };
+        if let Some(socket) = &connection.borrow().socket {
+            let _ = socket.send_with_str(&message.to_json());
+        }
+        true
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        for connection in self.sources.values() {
+            connection.borrow_mut().close();
+        }
+    }
+}
+
+/// One source's stream, reconnected until unsubscribed or refused.
+struct Connection {
+    url: String,
+    source: String,
+    on_event: Function,
+    socket: Option<WebSocket>,
+    decoder: Decoder,
+    delay_ms: i32,
+    /// Unsubscribed: no more events or reconnects.
+    closed: bool,
+    /// The socket's handlers, kept alive while it may call them.
+    handlers: Option<Handlers>,
+}
+
+struct Handlers {
+    _message: Closure<dyn FnMut(MessageEvent)>,
+    _close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl Connection {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(socket) = self.socket.take() {
+            // Detached first: the handlers go with this connection.
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+        self.handlers = None;
+    }
+}
+
+/// Open `connection`'s socket, and have it report events and reconnect.
+fn connect(connection: &Rc<RefCell<Connection>>) {
+    let mut this = connection.borrow_mut();
+    if this.closed {
+        return;
+    }
+    let socket = match WebSocket::new_with_str(&this.url, protocol::PROTOCOL) {
+        Ok(socket) => socket,
+        Err(_) => {
+            let reason = format!("can't connect to {}", this.url);
+            let event = Event::Disconnected { code: None, reason, retrying: false };
+            let (on_event, source) = (this.on_event.clone(), this.source.clone());
+            drop(this);
+            emit(&on_event, &source, event);
+            return;
+        }
+    };
+    socket.set_binary_type(BinaryType::Arraybuffer);
+    this.decoder = Decoder::new();
+
+    let weak = Rc::downgrade(connection);
+    let message = Closure::<dyn FnMut(MessageEvent)>::new(move |message: MessageEvent| {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let mut this = connection.borrow_mut();
+        let data = message.data();
+        let event = match data.as_string() {
+            Some(text) => this.decoder.text(&text),
+            None => Some(this.decoder.binary(Uint8Array::new(&data).to_vec().into())),
+        };
+        let Some(event) = event else {
+            return;
+        };
+        if matches!(event, Event::Connected { .. }) {
+            this.delay_ms = FIRST_RETRY_MS;
+        }
+        // Released first: the callback may grab or split.
+        let (on_event, source) = (this.on_event.clone(), this.source.clone());
+        drop(this);
+        emit(&on_event, &source, event);
+    });
+    let weak = Rc::downgrade(connection);
+    let close = Closure::<dyn FnMut(CloseEvent)>::new(move |close: CloseEvent| {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let mut this = connection.borrow_mut();
+        this.socket = None;
+        let code = Some(close.code());
+        let retrying = protocol::retry(code);
+        let delay = this.delay_ms;
+        this.delay_ms = (delay * 2).min(MAX_RETRY_MS);
+        let (on_event, source) = (this.on_event.clone(), this.source.clone());
+        drop(this);
+        emit(&on_event, &source, Event::Disconnected { code, reason: close.reason(), retrying });
+        if retrying {
+            reconnect_after(weak.clone(), delay);
+        }
+    });
+    socket.set_onmessage(Some(message.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(close.as_ref().unchecked_ref()));
+    this.handlers = Some(Handlers { _message: message, _close: close });
+    this.socket = Some(socket);
+}
+
+fn reconnect_after(connection: Weak<RefCell<Connection>>, delay_ms: i32) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let retry = Closure::once_into_js(move || {
+        if let Some(connection) = connection.upgrade() {
+            connect(&connection);
+        }
+    });
+    let _ = window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay_ms);
+}
+
+/// Call `on_event` with `source` and `event` as a JavaScript object.
+fn emit(on_event: &Function, source: &str, event: Event) {
+    let object = Object::new();
+    let set = |key: &str, value: JsValue| {
+        let _ = Reflect::set(&object, &key.into(), &value);
+    };
+    let kind = match event {
+        Event::Connected { format } => {
+            set("format", format.into());
+            "connected"
+        }
+        Event::Frame { seq, meta, data } => {
+            if let Some(seq) = seq {
+                set("seq", (seq as f64).into());
+            }
+            if let Some(meta) = meta {
+                let time = |time: Option<i64>| time.map_or(JsValue::NULL, |t| (t as f64).into());
+                set("timecode", time(meta.timecode));
+                set("timestamp", time(meta.timestamp));
+                set("received_ms", (meta.received_ms as f64).into());
+                set("width", (meta.width as f64).into());
+                set("height", (meta.height as f64).into());
+            }
+            set("data", Uint8Array::from(&data[..]).into());
+            "frame"
+        }
+        Event::Still { format, data } => {
+            set("format", format.into());
+            set("data", Uint8Array::from(&data[..]).into());
+            "still"
+        }
+        Event::Metadata(xml) => {
+            set("xml", xml.into());
+            "metadata"
+        }
+        Event::Annotations(annotations) => {
+            let parsed = js_sys::JSON::parse(&annotations.to_string());
+            set("annotations", parsed.unwrap_or(JsValue::NULL));
+            "annotations"
+        }
+        Event::Levels(channels) => {
+            let list = js_sys::Array::new();
+            for channel in channels {
+                let level = Object::new();
+                let _ = Reflect::set(&level, &"rms".into(), &channel.rms.into());
+                let _ = Reflect::set(&level, &"peak".into(), &channel.peak.into());
+                list.push(&level);
+            }
+            set("channels", list.into());
+            "levels"
+        }
+        Event::SenderReconnecting { attempt } => {
+            set("attempt", attempt.into());
+            "sender_reconnecting"
+        }
+        Event::SenderReconnected => "sender_reconnected",
+        Event::Disconnected { code, reason, retrying } => {
+            set("code", code.map_or(JsValue::NULL, JsValue::from));
+            set("reason", reason.into());
+            set("retrying", retrying.into());
+            "disconnected"
+        }
+    };
+    set("type", kind.into());
+    let _ = on_event.call2(&JsValue::NULL, &source.into(), &object);
+}
@@ -0,0 +1,15 @@
+//! Clients for StreamBridge's `streambridge.v1` WebSocket protocol: a Tokio
+//! [`Client`] for Rust applications and, with the `js` feature, a JavaScript
+//! one built with wasm-bindgen. Both stream any number of sources, reconnect
+//! after server restarts and send the control messages; [`protocol`] holds
+//! what they share.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use client::Client;
+pub use protocol::{ChannelLevel, ClientMessage, Decoder, Event, FrameMeta, Options};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client;
+#[cfg(feature = "js")]
+pub mod js;
+pub mod protocol;
@@ -0,0 +1,377 @@
+//! The `streambridge.v1` WebSocket protocol, independent of any socket: the
+//! URL to connect to, the server's messages turned into [`Event`]s, the
+//! client's control messages, and which close codes are worth reconnecting
+//! after.
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The subprotocol to offer with `Sec-WebSocket-Protocol`.
+pub const PROTOCOL: &str = "streambridge.v1";
+
+/// One source's stream, as `/ws` query parameters. Unset options leave the
+/// server's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub source: String,
+    pub token: Option<String>,
+    /// Also receive the sender's metadata XML.
+    pub metadata: bool,
+    /// Also receive annotations, and each frame's `seq`.
+    pub annotations: bool,
+    /// Also receive the source's audio levels.
+    pub levels: bool,
+    /// `meta` for each frame's timing and size.
+    pub framing: Option<String>,
+    /// `diff` for the difference between frames instead of the frames.
+    pub mode: Option<String>,
+    pub quality: Option<i32>,
+    pub fps: Option<u32>,
+    pub width: Option<usize>,
+    /// `jpeg`, `webp` or `png`.
+    pub format: Option<String>,
+    /// `false` to keep the rate up when the link can't keep up.
+    pub adaptive: Option<bool>,
+    /// `lowest` for the sender's preview stream.
+    pub bandwidth: Option<String>,
+    /// The viewer's persistent id.
+    pub client_id: Option<String>,
+}
+
+impl Options {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self { source: source.into(), ..Self::default() }
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn annotations(mut self, annotations: bool) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    pub fn levels(mut self, levels: bool) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn framing(mut self, framing: impl Into<String>) -> Self {
+        self.framing = Some(framing.into());
+        self
+    }
+
+    pub fn mode(mut self, mode: impl Into<String>) -> Self {
+        self.mode = Some(mode.into());
+        self
+    }
+
+    pub fn quality(mut self, quality: i32) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn fps(mut self, fps: u32) -> Self {
+        self.fps = Some(fps);
+        self
+    }
+
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn bandwidth(mut self, bandwidth: impl Into<String>) -> Self {
+        self.bandwidth = Some(bandwidth.into());
+        self
+    }
+
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// The `/ws` URL of this stream on the server at `base`, e.g.
+    /// `http://localhost:8080`; `http` and `https` become `ws` and `wss`.
+    pub fn url(&self, base: &str) -> String {
+        let base = base.trim_end_matches('/');
+        let base = match base.split_once("://") {
+            Some(("http", rest)) => format!("ws://{rest}"),
+            Some(("https", rest)) => format!("wss://{rest}"),
+            Some(_) => base.to_string(),
+            None => format!("ws://{base}"),
+        };
+        let mut query = vec![("source", self.source.clone())];
+        let flags = [
+            ("metadata", self.metadata),
+            ("annotations", self.annotations),
+            ("levels", self.levels),
+        ];
+        query.extend(flags.iter().filter(|(_, on)| *on).map(|(name, _)| (*name, "true".into())));
+        let options = [
+            ("token", self.token.clone()),
+            ("framing", self.framing.clone()),
+            ("mode", self.mode.clone()),
+            ("quality", self.quality.map(|q| q.to_string())),
+            ("fps", self.fps.map(|fps| fps.to_string())),
+            ("width", self.width.map(|width| width.to_string())),
+            ("format", self.format.clone()),
+            ("adaptive", self.adaptive.map(|adaptive| adaptive.to_string())),
+            ("bandwidth", self.bandwidth.clone()),
+            ("client_id", self.client_id.clone()),
+        ];
+        query.extend(options.into_iter().filter_map(|(name, value)| Some((name, value?))));
+        let query: Vec<String> =
+            query.iter().map(|(name, value)| format!("{name}={}", escape(value))).collect();
+        format!("{base}/ws?{}", query.join("&"))
+    }
+}
+
+/// `value` percent-encoded for a query string.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                escaped.push(byte as char)
+            }
+            _ => escaped.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    escaped
+}
+
+/// What happened on a source's stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The server accepted the stream; frames follow in `format`, a content
+    /// type like `image/jpeg`. Sent again after each reconnect.
+    Connected { format: String },
+    /// A frame of the stream, numbered when annotations or `meta` framing were
+    /// asked for, and with its timing and size for the latter.
+    Frame { seq: Option<u64>, meta: Option<FrameMeta>, data: Bytes },
+    /// The still a [`grab`](ClientMessage::grab) asked for, in `format`.
+    Still { format: String, data: Bytes },
+    /// Metadata XML from the sender.
+    Metadata(String),
+    /// Annotations posted for the source, as `/api/annotations` takes them.
+    Annotations(Value),
+    /// Each audio channel's levels, about ten times a second.
+    Levels(Vec<ChannelLevel>),
+    /// The NDI® sender dropped; the server is retrying for the `attempt`th time.
+    SenderReconnecting { attempt: u32 },
+    SenderReconnected,
+    /// The connection to the server ended with close `code` (none if it just
+    /// broke). Unless `retrying`, no more events follow for the source.
+    Disconnected { code: Option<u16>, reason: String, retrying: bool },
+}
+
+/// When a frame was sent and received, and its size at the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct FrameMeta {
+    /// The NDI® sender's timecode, in 100 ns units.
+    pub timecode: Option<i64>,
+    /// When the NDI® sender sent it, in 100 ns units since the Unix epoch.
+    pub timestamp: Option<i64>,
+    /// When the server received it, in milliseconds since the Unix epoch.
+    pub received_ms: u64,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// One audio channel's RMS and peak level in dBFS; silence reads as -100.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ChannelLevel {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// Text messages from the server.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Hello { format: String },
+    Metadata { xml: String },
+    Reconnecting { attempt: u32 },
+    Reconnected,
+    Frame {
+        seq: u64,
+        #[serde(flatten)]
+        meta: Option<FrameMeta>,
+    },
+    Annotations,
+    Levels { channels: Vec<ChannelLevel> },
+    Still { format: String },
+    /// Messages from newer servers, ignored.
+    #[serde(other)]
+    Other,
+}
+
+/// Turns one connection's messages into [`Event`]s, pairing binary frames with
+/// the text message announcing them.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    seq: Option<u64>,
+    meta: Option<FrameMeta>,
+    still: Option<String>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The event for a text message, if it makes one on its own.
+    pub fn text(&mut self, text: &str) -> Option<Event> {
+        let message = serde_json::from_str(text).ok()?;
+        match message {
+            ServerMessage::Hello { format } => {
+                *self = Self::default();
+                Some(Event::Connected { format })
+            }
+            ServerMessage::Metadata { xml } => Some(Event::Metadata(xml)),
+            ServerMessage::Reconnecting { attempt } => Some(Event::SenderReconnecting { attempt }),
+            ServerMessage::Reconnected => Some(Event::SenderReconnected),
+            ServerMessage::Frame { seq, meta } => {
+                (self.seq, self.meta) = (Some(seq), meta);
+                None
+            }
+            ServerMessage::Annotations => {
+                let mut annotations: Value = serde_json::from_str(text).ok()?;
+                annotations.as_object_mut()?.remove("type");
+                Some(Event::Annotations(annotations))
+            }
+            ServerMessage::Levels { channels } => Some(Event::Levels(channels)),
+            ServerMessage::Still { format } => {
+                self.still = Some(format);
+                None
+            }
+            ServerMessage::Other => None,
+        }
+    }
+
+    /// The event for a binary message: a still if one was announced, a frame
+    /// otherwise.
+    pub fn binary(&mut self, data: Bytes) -> Event {
+        match self.still.take() {
+            Some(format) => Event::Still { format, data },
+            None => Event::Frame { seq: self.seq.take(), meta: self.meta.take(), data },
+        }
+    }
+}
+
+/// Text messages to the server.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ClientMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd: Option<&'static str>,
+}
+
+impl ClientMessage {
+    /// Move a comparison source's split to `at`, a fraction of the frame's
+    /// width. Needs the `control` right.
+    pub fn split(at: f32) -> Self {
+        Self { split: Some(at), ..Self::default() }
+    }
+
+    /// Send the next frame as an [`Event::Still`] at the source's own quality
+    /// and size. Needs the `snapshot` right.
+    pub fn grab() -> Self {
+        Self { cmd: Some("grab"), ..Self::default() }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Whether a connection that ended with close `code` (none if it broke) may
+/// succeed when tried again: after restarts, lost sources, full servers and
+/// network trouble, but not for bad requests, refused tokens or unknown sources.
+pub fn retry(code: Option<u16>) -> bool {
+    match code {
+        // Server restarting or shutting down, and abnormal closes.
+        Some(1001 | 1006 | 1011 | 1012 | 1013) | None => true,
+        // Source lost, too many clients, server draining.
+        Some(4410 | 4429 | 4503) => true,
+        Some(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_escape_sources_and_skip_unset_options() {
+        let options = Options::new("STUDIO (Program)").token("a b").fps(5).annotations(true);
+        assert_eq!(
+            options.url("http://localhost:8080/"),
+            "ws://localhost:8080/ws?source=STUDIO%20%28Program%29&annotations=true\
+             &token=a%20b&fps=5"
+        );
+        assert_eq!(Options::new("cam").url("https://h"), "wss://h/ws?source=cam");
+        assert_eq!(Options::new("cam").url("h:1"), "ws://h:1/ws?source=cam");
+    }
+
+    #[test]
+    fn decoder_pairs_frames_and_stills_with_their_announcements() {
+        let mut decoder = Decoder::new();
+        let hello = r#"{"type":"hello","protocol":"streambridge.v1","format":"image/jpeg"}"#;
+        assert_eq!(decoder.text(hello), Some(Event::Connected { format: "image/jpeg".into() }));
+        let data = Bytes::from_static(b"jpeg");
+        assert_eq!(decoder.text(r#"{"type":"frame","seq":7}"#), None);
+        let frame = |seq, meta| Event::Frame { seq, meta, data: data.clone() };
+        assert_eq!(decoder.binary(data.clone()), frame(Some(7), None));
+        assert_eq!(decoder.binary(data.clone()), frame(None, None));
+        let tag = r#"{"type":"frame","seq":8,"timecode":5,"received_ms":9,"width":16,"height":9}"#;
+        assert_eq!(decoder.text(tag), None);
+        let meta = FrameMeta {
+            timecode: Some(5),
+            timestamp: None,
+            received_ms: 9,
+            width: 16,
+            height: 9,
+        };
+        assert_eq!(decoder.binary(data.clone()), frame(Some(8), Some(meta)));
+        assert_eq!(decoder.text(r#"{"type":"still","format":"image/png"}"#), None);
+        let still = Event::Still { format: "image/png".into(), data: data.clone() };
+        assert_eq!(decoder.binary(data), still);
+
+        let annotations = decoder.text(r#"{"type":"annotations","seq":7,"boxes":[]}"#);
+        let expected = serde_json::json!({"seq": 7, "boxes": []});
+        assert_eq!(annotations, Some(Event::Annotations(expected)));
+        let levels = decoder.text(r#"{"type":"levels","channels":[{"rms":-18.2,"peak":-6.1}]}"#);
+        let expected = ChannelLevel { rms: -18.2, peak: -6.1 };
+        assert_eq!(levels, Some(Event::Levels(vec![expected])));
+        assert_eq!(decoder.text(r#"{"type":"from_the_future"}"#), None);
+        assert_eq!(decoder.text("not json"), None);
+    }
+
+    #[test]
+    fn control_messages_are_what_the_server_reads() {
+        assert_eq!(ClientMessage::split(0.25).to_json(), r#"{"split":0.25}"#);
+        assert_eq!(ClientMessage::grab().to_json(), r#"{"cmd":"grab"}"#);
+    }
+}
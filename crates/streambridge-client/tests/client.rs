@@ -0,0 +1,137 @@
+//! The client against a real bridge on a loopback port, fed by clock sources
+//! instead of the NDI® runtime.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use streambridge_core::clock::ClockConfig;
+use streambridge_core::receiver::VirtualSource;
+use streambridge_core::Bridge;
+use streambridge_client::{Client, Event, Options};
+use tokio::sync::oneshot;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Start a bridge at `addr` with a clock source for each of `sources`. It shuts
+/// down, draining for the time sent, when sent to.
+async fn start_server(
+    addr: SocketAddr,
+    sources: &[&str],
+) -> (SocketAddr, oneshot::Sender<Duration>) {
+    let mut bridge = Bridge::builder().bind(addr);
+    for name in sources {
+        let clock = ClockConfig { width: 320, height: 180, ..ClockConfig::default() };
+        bridge = bridge.virtual_source(*name, VirtualSource::Clock(clock));
+    }
+    let bridge = bridge.spawn().await.expect("spawn bridge");
+    let addr = bridge.local_addr();
+    let (shutdown, drain) = oneshot::channel();
+    tokio::spawn(async move {
+        let drain = drain.await.unwrap_or_default();
+        bridge.shutdown(drain).await.expect("server error");
+    });
+    (addr, shutdown)
+}
+
+async fn any_port(sources: &[&str]) -> (SocketAddr, oneshot::Sender<Duration>) {
+    start_server(SocketAddr::from(([127, 0, 0, 1], 0)), sources).await
+}
+
+/// The client's next event, failing the test if none comes in time.
+async fn next(client: &mut Client) -> (String, Event) {
+    let event = tokio::time::timeout(TIMEOUT, client.next()).await.expect("event in time");
+    event.expect("an event")
+}
+
+/// Events of `client` until one matches `wanted`, which is returned.
+async fn next_matching(
+    client: &mut Client,
+    mut wanted: impl FnMut(&str, &Event) -> bool,
+) -> (String, Event) {
+    loop {
+        let (source, event) = next(client).await;
+        if wanted(&source, &event) {
+            return (source, event);
+        }
+    }
+}
+
+#[tokio::test]
+async fn streams_several_sources_over_one_client() {
+    let (addr, _shutdown) = any_port(&["CAM 1", "CAM 2"]).await;
+    let mut client = Client::new(format!("http://{addr}"));
+    client.subscribe(Options::new("CAM 1"));
+    client.subscribe(Options::new("CAM 2").format("png"));
+
+    let mut formats = Vec::new();
+    let mut framed = Vec::new();
+    while formats.len() < 2 || framed.len() < 2 {
+        match next(&mut client).await {
+            (source, Event::Connected { format }) => formats.push((source, format)),
+            (source, Event::Frame { data, .. }) if !framed.contains(&source) => {
+                let magic: &[u8] = if source == "CAM 1" { &[0xFF, 0xD8] } else { b"\x89PNG" };
+                assert!(data.starts_with(magic), "{source} sent something else");
+                framed.push(source);
+            }
+            (_, Event::Frame { .. }) => {}
+            (source, event) => panic!("unexpected event for {source}: {event:?}"),
+        }
+    }
+    formats.sort();
+    let expected = [("CAM 1", "image/jpeg"), ("CAM 2", "image/png")];
+    assert_eq!(formats, expected.map(|(source, format)| (source.into(), format.into())));
+
+    assert!(client.unsubscribe("CAM 2"));
+    assert!(!client.grab("CAM 2"), "grabbed from a source no longer streamed");
+}
+
+#[tokio::test]
+async fn grabs_stills_and_numbers_frames_for_annotations() {
+    let (addr, _shutdown) = any_port(&["CAM"]).await;
+    let mut client = Client::new(addr.to_string());
+    client.subscribe(Options::new("CAM").annotations(true).quality(20));
+
+    let (_, frame) = next_matching(&mut client, |_, e| matches!(e, Event::Frame { .. })).await;
+    assert!(matches!(frame, Event::Frame { seq: Some(_), .. }), "{frame:?}");
+    assert!(client.grab("CAM"));
+    let (_, still) = next_matching(&mut client, |_, e| matches!(e, Event::Still { .. })).await;
+    let Event::Still { format, data } = still else { unreachable!() };
+    assert_eq!(format, "image/jpeg");
+    assert!(data.starts_with(&[0xFF, 0xD8]));
+}
+
+#[tokio::test]
+async fn reconnects_after_a_server_restart() {
+    let (addr, shutdown) = any_port(&["CAM"]).await;
+    let mut client = Client::new(format!("http://{addr}"))
+        .retry_delays(Duration::from_millis(50), Duration::from_millis(200));
+    client.subscribe(Options::new("CAM"));
+    next_matching(&mut client, |_, e| matches!(e, Event::Frame { .. })).await;
+
+    // A drain asks clients to come back (1012), e.g. to the restarted server.
+    shutdown.send(Duration::from_secs(1)).unwrap();
+    let disconnected = |_: &str, e: &Event| matches!(e, Event::Disconnected { .. });
+    let (_, gone) = next_matching(&mut client, disconnected).await;
+    let Event::Disconnected { code, retrying, .. } = gone else { unreachable!() };
+    assert_eq!((code, retrying), (Some(1012), true));
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (_restarted, _shutdown) = start_server(addr, &["CAM"]).await;
+    let (_, back) = next_matching(&mut client, |_, e| {
+        matches!(e, Event::Connected { .. } | Event::Frame { .. })
+    })
+    .await;
+    assert!(matches!(back, Event::Connected { .. }), "frame before hello: {back:?}");
+    next_matching(&mut client, |_, e| matches!(e, Event::Frame { .. })).await;
+}
+
+#[tokio::test]
+async fn gives_up_on_unknown_sources() {
+    let (addr, _shutdown) = any_port(&["CAM"]).await;
+    let mut client = Client::new(format!("http://{addr}"));
+    client.subscribe(Options::new("NOPE"));
+    let (source, event) = next(&mut client).await;
+    assert_eq!(source, "NOPE");
+    let Event::Disconnected { code, retrying, .. } = event else { panic!("{event:?}") };
+    assert_eq!((code, retrying), (Some(4404), false));
+    assert_eq!(client.next().await, None);
+}
@@ -0,0 +1,108 @@
+//! Baseline numbers for the per-frame hot path: colour conversion, JPEG encode
+//! and fan-out to WebSocket subscribers.
+//!
+//! Run with `cargo bench -p streambridge --bench pipeline`. To compare a change,
+//! record a baseline first with `-- --save-baseline main`, then rerun with
+//! `-- --baseline main`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use streambridge_core::encode::{encode_frame, uyvy_to_yuv420_planar, EncodeBuffers};
+use streambridge_core::ndi::FourCCVideoType;
+use streambridge_core::receiver::{FrameInfo, JpegFrame};
+use tokio::sync::broadcast;
+
+const RESOLUTIONS: [(&str, usize, usize); 2] = [("1080p", 1920, 1080), ("2160p", 3840, 2160)];
+
+/// Deterministic UYVY frame with enough detail that the encoder does real work.
+fn uyvy_frame(w: usize, h: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; w * 2 * h];
+    for y in 0..h {
+        for x in 0..w / 2 {
+            let i = (y * w / 2 + x) * 4;
+            let luma = ((x * 2 + y) % 220 + 16) as u8;
+            buf[i] = (x * 255 / (w / 2)) as u8;
+            buf[i + 1] = luma;
+            buf[i + 2] = (y * 255 / h) as u8;
+            buf[i + 3] = luma.wrapping_add(((x ^ y) & 15) as u8);
+        }
+    }
+    buf
+}
+
+fn bgra_frame(w: usize, h: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; w * 4 * h];
+    for y in 0..h {
+        for x in 0..w {
+            let i = (y * w + x) * 4;
+            buf[i] = (x * 255 / w) as u8;
+            buf[i + 1] = ((x + y) % 256) as u8;
+            buf[i + 2] = (y * 255 / h) as u8;
+            buf[i + 3] = 255;
+        }
+    }
+    buf
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("uyvy_to_yuv420_planar");
+    for (name, w, h) in RESOLUTIONS {
+        let src = uyvy_frame(w, h);
+        let mut y = vec![0u8; w * h];
+        let mut u = vec![0u8; w * h / 4];
+        let mut v = vec![0u8; w * h / 4];
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| uyvy_to_yuv420_planar(black_box(&src), w * 2, w, h, &mut y, &mut u, &mut v))
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_frame");
+    group.sample_size(20);
+    for (name, w, h) in RESOLUTIONS {
+        let mut buffers = EncodeBuffers::new();
+        group.throughput(Throughput::Elements(1));
+
+        let uyvy = uyvy_frame(w, h);
+        group.bench_function(BenchmarkId::new("uyvy", name), |b| {
+            b.iter(|| encode_frame(black_box(&uyvy), w, h, w * 2, FourCCVideoType::UYVY, 75, &mut buffers))
+        });
+
+        let bgra = bgra_frame(w, h);
+        group.bench_function(BenchmarkId::new("bgra", name), |b| {
+            b.iter(|| encode_frame(black_box(&bgra), w, h, w * 4, FourCCVideoType::BGRA, 75, &mut buffers))
+        });
+    }
+    group.finish();
+}
+
+/// Cost of handing one encoded frame to N subscribers, as the capture thread
+/// and WebSocket tasks do (same channel type and capacity as `ReceiverManager`).
+fn bench_fanout(c: &mut Criterion) {
+    let (name, w, h) = RESOLUTIONS[0];
+    let jpeg = encode_frame(&uyvy_frame(w, h), w, h, w * 2, FourCCVideoType::UYVY, 75, &mut EncodeBuffers::new())
+        .expect("encode");
+    let frame = JpegFrame { data: jpeg, info: FrameInfo::default() };
+
+    let mut group = c.benchmark_group(format!("broadcast_fanout/{name}"));
+    for subscribers in [1usize, 8, 64] {
+        let (tx, _) = broadcast::channel::<JpegFrame>(4);
+        let mut receivers: Vec<_> = (0..subscribers).map(|_| tx.subscribe()).collect();
+        group.throughput(Throughput::Elements(subscribers as u64));
+        group.bench_function(BenchmarkId::from_parameter(subscribers), |b| {
+            b.iter(|| {
+                let _ = tx.send(frame.clone());
+                for rx in &mut receivers {
+                    black_box(rx.try_recv().expect("recv").data.len());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_convert, bench_encode, bench_fanout);
+criterion_main!(benches);
@@ -0,0 +1,189 @@
+//! Per-client frame rates that follow the client's link: lowered when frames
+//! back up on the way to it, and raised again once they flow. The MJPEG take
+//! on adaptive bitrate, applied below any configured or requested fps cap.
+
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// The lowest rate a congested client is taken down to.
+const MIN_FPS: f64 = 1.0;
+/// How often the rate may be lowered, so one burst of slow sends counts once.
+const STEP_DOWN_GAP: Duration = Duration::from_millis(500);
+/// How long the link must stay clear before the rate is raised a step.
+const RECOVERY_PERIOD: Duration = Duration::from_secs(2);
+/// Round trips this much above the best one seen mean frames are queueing.
+const RTT_HEADROOM: Duration = Duration::from_millis(150);
+/// Sends are only slow if they take longer than a frame interval and this.
+const SLOW_SEND: Duration = Duration::from_millis(20);
+
+/// One client's adaptive frame rate. Feed it every frame that arrives for the
+/// client, how long each send took and the WebSocket ping round trips.
+pub struct AdaptiveRate {
+    source_name: String,
+    /// Smoothed interval between the source's frames.
+    source_interval: Option<Duration>,
+    last_arrival: Option<Instant>,
+    last_sent: Option<Instant>,
+    /// The rate the client gets, or `None` for every frame.
+    fps: Option<f64>,
+    best_rtt: Option<Duration>,
+    last_step_down: Option<Instant>,
+    clear_since: Instant,
+}
+
+impl AdaptiveRate {
+    pub fn new(source_name: &str, now: Instant) -> Self {
+        Self {
+            source_name: source_name.to_string(),
+            source_interval: None,
+            last_arrival: None,
+            last_sent: None,
+            fps: None,
+            best_rtt: None,
+            last_step_down: None,
+            clear_since: now,
+        }
+    }
+
+    /// The rate the client is held to, or `None` while it keeps up.
+    pub fn fps(&self) -> Option<f64> {
+        self.fps
+    }
+
+    /// Whether to send a frame that arrived at `now`.
+    pub fn admit(&mut self, now: Instant) -> bool {
+        if let Some(last) = self.last_arrival {
+            let gap = now - last;
+            self.source_interval = Some(match self.source_interval {
+                Some(average) => (average * 7 + gap) / 8,
+                None => gap,
+            });
+        }
+        self.last_arrival = Some(now);
+        let (Some(fps), Some(last_sent)) = (self.fps, self.last_sent) else {
+            return true;
+        };
+        // A little slack, so a rate near the source's doesn't skip every other frame.
+        now - last_sent >= Duration::from_secs_f64(0.9 / fps)
+    }
+
+    /// A frame admitted at `now` took `took` to hand to the socket. Sends only
+    /// block once the socket's buffers are full, i.e. the link is behind.
+    pub fn sent(&mut self, now: Instant, took: Duration) {
+        self.last_sent = Some(now);
+        let interval = self.source_interval.unwrap_or_default();
+        if took > interval.max(SLOW_SEND) {
+            self.congested(now, "slow sends");
+        } else {
+            self.clear(now);
+        }
+    }
+
+    /// A ping answered after `rtt`.
+    pub fn pong(&mut self, now: Instant, rtt: Duration) {
+        let best = self.best_rtt.map_or(rtt, |best| best.min(rtt));
+        self.best_rtt = Some(best);
+        if rtt > best + RTT_HEADROOM {
+            self.congested(now, "slow pongs");
+        } else {
+            self.clear(now);
+        }
+    }
+
+    /// Frames were lost to the client, or a ping went unanswered.
+    pub fn lagged(&mut self, now: Instant) {
+        self.congested(now, "lag");
+    }
+
+    fn source_fps(&self) -> Option<f64> {
+        self.source_interval
+            .filter(|interval| !interval.is_zero())
+            .map(|interval| 1.0 / interval.as_secs_f64())
+    }
+
+    fn congested(&mut self, now: Instant, why: &str) {
+        self.clear_since = now;
+        if self.last_step_down.is_some_and(|last| now - last < STEP_DOWN_GAP) {
+            return;
+        }
+        let Some(current) = self.fps.or_else(|| self.source_fps()) else {
+            return;
+        };
+        let lowered = (current * 0.7).max(MIN_FPS);
+        if self.fps != Some(lowered) {
+            debug!("[{}] client lowered to {:.1} fps ({})", self.source_name, lowered, why);
+        }
+        self.fps = Some(lowered);
+        self.last_step_down = Some(now);
+    }
+
+    fn clear(&mut self, now: Instant) {
+        let Some(fps) = self.fps else {
+            return;
+        };
+        if now - self.clear_since < RECOVERY_PERIOD {
+            return;
+        }
+        self.clear_since = now;
+        let raised = fps * 1.25 + 0.5;
+        self.fps = self.source_fps().filter(|&source| raised < source).map(|_| raised);
+        match self.fps {
+            Some(fps) => debug!("[{}] client raised to {:.1} fps", self.source_name, fps),
+            None => debug!("[{}] client back to the full rate", self.source_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(40);
+
+    /// Frames at 25 fps for `duration`, each sent in `took`; how many went out.
+    fn run(rate: &mut AdaptiveRate, now: &mut Instant, duration: Duration, took: Duration) -> u32 {
+        let mut sent = 0;
+        for _ in 0..duration.as_millis() / FRAME.as_millis() {
+            *now += FRAME;
+            if rate.admit(*now) {
+                rate.sent(*now, took);
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn slow_sends_lower_the_rate_until_they_recover() {
+        let mut now = Instant::now();
+        let mut rate = AdaptiveRate::new("cam", now);
+        let second = Duration::from_secs(1);
+        assert_eq!(run(&mut rate, &mut now, second, Duration::from_millis(1)), 25);
+        assert_eq!(rate.fps(), None);
+
+        let congested = run(&mut rate, &mut now, second * 3, Duration::from_millis(100));
+        let lowered = rate.fps().expect("rate lowered");
+        assert!(lowered < 10.0, "{lowered}");
+        assert!(congested < 50, "{congested}");
+
+        run(&mut rate, &mut now, second * 30, Duration::from_millis(1));
+        assert_eq!(rate.fps(), None);
+    }
+
+    #[test]
+    fn slow_pongs_and_lag_lower_the_rate() {
+        let mut now = Instant::now();
+        let mut rate = AdaptiveRate::new("cam", now);
+        run(&mut rate, &mut now, Duration::from_secs(1), Duration::ZERO);
+        rate.pong(now, Duration::from_millis(20));
+        assert_eq!(rate.fps(), None);
+        rate.pong(now, Duration::from_millis(400));
+        assert!(rate.fps().is_some_and(|fps| (17.0..18.0).contains(&fps)));
+
+        // Once per step-down gap, however many signals arrive.
+        rate.lagged(now);
+        now += STEP_DOWN_GAP;
+        rate.lagged(now);
+        assert!(rate.fps().is_some_and(|fps| (12.0..13.0).contains(&fps)));
+    }
+}
@@ -0,0 +1,146 @@
+//! Annotations from outside services, e.g. an ML model's detections, drawn by
+//! viewers over the live picture. A service reads frames along with their
+//! sequence numbers, posts what it found in one to
+//! `POST /api/annotations/<source>`, and the source's viewers that asked for
+//! annotations get them to draw as boxes and text.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Boxes plus texts one post may carry.
+const MAX_ITEMS: usize = 256;
+/// Characters in one label or text.
+const MAX_TEXT: usize = 200;
+
+/// A `POST /api/annotations/<source>` body. Positions and sizes are fractions
+/// of the frame's width and height, so they fit any size viewers get.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Annotations {
+    /// The frame they were made for, as numbered in the frames' `seq`.
+    pub seq: u64,
+    #[serde(default)]
+    pub boxes: Vec<AnnotationBox>,
+    #[serde(default)]
+    pub texts: Vec<AnnotationText>,
+    /// How long viewers show them unless newer ones come first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+}
+
+/// A rectangle, e.g. around a detected object.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The detector's confidence, 0 to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    /// A CSS colour.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+/// Text placed on the frame, e.g. a count or a caption.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationText {
+    pub x: f32,
+    pub y: f32,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+impl Annotations {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.boxes.len() + self.texts.len() > MAX_ITEMS {
+            return Err(format!("at most {MAX_ITEMS} boxes and texts"));
+        }
+        let fraction = |v: f32| (0.0..=1.0).contains(&v);
+        let short = |s: Option<&str>| s.is_none_or(|s| s.chars().count() <= MAX_TEXT);
+        for b in &self.boxes {
+            if ![b.x, b.y, b.width, b.height].into_iter().all(fraction)
+                || b.x + b.width > 1.0 + f32::EPSILON
+                || b.y + b.height > 1.0 + f32::EPSILON
+            {
+                return Err("boxes must lie within the frame, in fractions from 0 to 1".to_string());
+            }
+            if b.score.is_some_and(|score| !fraction(score)) {
+                return Err("scores must be 0 to 1".to_string());
+            }
+            if !short(b.label.as_deref()) || !short(b.color.as_deref()) {
+                return Err(format!("labels and colours must be at most {MAX_TEXT} characters"));
+            }
+        }
+        for t in &self.texts {
+            if !fraction(t.x) || !fraction(t.y) {
+                return Err("texts must lie within the frame, in fractions from 0 to 1".to_string());
+            }
+            if !short(Some(&t.text)) || !short(t.color.as_deref()) {
+                return Err(format!("texts and colours must be at most {MAX_TEXT} characters"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The annotation channels of sources with viewers that asked for them.
+#[derive(Default)]
+pub struct AnnotationHub {
+    channels: Mutex<HashMap<String, broadcast::Sender<Arc<Annotations>>>>,
+}
+
+impl AnnotationHub {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Annotations posted for `source` from now on.
+    pub fn subscribe(&self, source: &str) -> broadcast::Receiver<Arc<Annotations>> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, tx| tx.receiver_count() > 0);
+        channels.entry(source.to_string()).or_insert_with(|| broadcast::channel(16).0).subscribe()
+    }
+
+    /// Pass `annotations` on to `source`'s viewers; returns how many got them.
+    pub fn publish(&self, source: &str, annotations: Annotations) -> usize {
+        let channels = self.channels.lock().unwrap();
+        let tx = channels.get(source);
+        tx.and_then(|tx| tx.send(Arc::new(annotations)).ok()).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotations_reach_subscribers_of_their_source_only() {
+        let annotations: Annotations = serde_json::from_str(
+            r#"{"seq": 7, "boxes": [{"x": 0.1, "y": 0.2, "width": 0.3, "height": 0.4,
+                "label": "person", "score": 0.9}], "texts": [{"x": 0, "y": 0, "text": "1"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(annotations.validate(), Ok(()));
+        let mut outside = annotations.clone();
+        outside.boxes[0].x = 0.8;
+        assert!(outside.validate().is_err());
+
+        let hub = AnnotationHub::new();
+        assert_eq!(hub.publish("cam", annotations.clone()), 0);
+        let mut rx = hub.subscribe("cam");
+        let _other = hub.subscribe("other");
+        assert_eq!(hub.publish("cam", annotations.clone()), 1);
+        assert_eq!(*rx.try_recv().unwrap(), annotations);
+        drop(rx);
+        assert_eq!(hub.publish("cam", annotations), 0);
+    }
+}
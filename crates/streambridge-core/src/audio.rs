@@ -0,0 +1,205 @@
+//! Live audio for HTTP listeners: NDI® audio as 16-bit PCM, served as an endless
+//! WAV stream that browsers and media players can play directly. With the
+//! `ffmpeg` feature, the `ffmpeg` program also compresses it to MP3 or AAC for
+//! listeners on slow links.
+
+use bytes::Bytes;
+use futures_util::Stream;
+use std::io;
+
+/// Listeners get at most this many channels; further channels are dropped.
+pub const MAX_CHANNELS: usize = 2;
+
+/// Whether this build can serve compressed audio.
+pub const COMPRESSED: bool = cfg!(feature = "ffmpeg");
+
+/// The bitrate of compressed streams: plenty for speech and music in stereo,
+/// at a twelfth of 48 kHz PCM.
+const BITRATE: &str = "128k";
+
+/// The formats `/audio/<source>.<extension>` serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Wav,
+    Mp3,
+    Aac,
+}
+
+impl Format {
+    /// The source name and format of a `/audio/` file name like `CAM (1).mp3`.
+    pub fn split(file: &str) -> Option<(&str, Format)> {
+        let (source, extension) = file.rsplit_once('.')?;
+        let format = match extension {
+            "wav" => Format::Wav,
+            "mp3" => Format::Mp3,
+            "aac" => Format::Aac,
+            _ => return None,
+        };
+        Some((source, format))
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Wav => "audio/wav",
+            Format::Mp3 => "audio/mpeg",
+            Format::Aac => "audio/aac",
+        }
+    }
+
+    /// `ffmpeg`'s arguments to read a WAV stream on stdin and write this
+    /// format to stdout; `None` for WAV itself.
+    pub fn ffmpeg_args(self) -> Option<Vec<String>> {
+        let (codec, container) = match self {
+            Format::Wav => return None,
+            Format::Mp3 => ("libmp3lame", "mp3"),
+            Format::Aac => ("aac", "adts"),
+        };
+        let args = [
+            "-v", "error", "-nostdin", "-f", "wav", "-i", "-", "-c:a", codec, "-b:a", BITRATE,
+            "-f", container, "-flush_packets", "1", "-",
+        ];
+        Some(args.map(String::from).to_vec())
+    }
+}
+
+/// Compress the WAV stream `wav` to `format` with `ffmpeg`. The program is
+/// killed when the returned stream is dropped, and stops reading `wav` then.
+#[cfg(feature = "ffmpeg")]
+pub fn encode(
+    format: Format,
+    wav: impl Stream<Item = Bytes> + Send + 'static,
+) -> Result<impl Stream<Item = io::Result<Bytes>> + Send + 'static, String> {
+    use futures_util::StreamExt;
+    use std::process::Stdio;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::process::Command;
+
+    let args = format.ffmpeg_args().ok_or("WAV needs no encoding")?;
+    let mut child = Command::new("ffmpeg")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run ffmpeg: {e}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+    tokio::spawn(async move {
+        let mut wav = std::pin::pin!(wav);
+        while let Some(bytes) = wav.next().await {
+            if stdin.write_all(&bytes).await.is_err() {
+                break;
+            }
+        }
+    });
+    // The child goes along with its output, so dropping the stream kills it.
+    Ok(futures_util::stream::unfold((stdout, child), |(mut stdout, child)| async move {
+        let mut buf = vec![0; 8192];
+        match stdout.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), (stdout, child)))
+            }
+            Err(e) => Some((Err(e), (stdout, child))),
+        }
+    }))
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+pub fn encode(
+    _format: Format,
+    _wav: impl Stream<Item = Bytes> + Send + 'static,
+) -> Result<futures_util::stream::Empty<io::Result<Bytes>>, String> {
+    Err("MP3 and AAC need the ffmpeg feature".to_string())
+}
+
+/// A block of interleaved 16-bit little-endian PCM.
+#[derive(Clone)]
+pub struct AudioChunk {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub pcm: Bytes,
+}
+
+impl AudioChunk {
+    /// Convert planar float audio, keeping the first [`MAX_CHANNELS`] channels.
+    pub fn from_planar(sample_rate: u32, channels: &[&[f32]]) -> Self {
+        let channels = &channels[..channels.len().min(MAX_CHANNELS)];
+        let samples = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+        let mut pcm = Vec::with_capacity(samples * channels.len() * 2);
+        for i in 0..samples {
+            for channel in channels {
+                let sample = (channel[i].clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                pcm.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        Self {
+            sample_rate,
+            channels: channels.len() as u16,
+            pcm: Bytes::from(pcm),
+        }
+    }
+
+    /// Whether `other` can continue the same WAV stream.
+    pub fn same_format(&self, other: &AudioChunk) -> bool {
+        self.sample_rate == other.sample_rate && self.channels == other.channels
+    }
+}
+
+/// A WAV header for a stream of unknown length. The size fields are set to their
+/// maximum, which players treat as "until the connection closes".
+pub fn wav_header(sample_rate: u32, channels: u16) -> Bytes {
+    let block_align = channels * 2;
+    let byte_rate = sample_rate * block_align as u32;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&(u32::MAX - 36).to_le_bytes());
+    Bytes::from(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaves_and_clips_to_stereo() {
+        let (left, right, center) = ([0.5f32, -2.0], [0.0f32, 1.0], [0.25f32, 0.25]);
+        let chunk = AudioChunk::from_planar(48_000, &[&left, &right, &center]);
+        assert_eq!(chunk.channels, 2);
+        let samples: Vec<i16> = chunk
+            .pcm
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, [16383, 0, -32767, 32767]);
+
+        let header = wav_header(48_000, 2);
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(header[28..32].try_into().unwrap()), 192_000);
+    }
+
+    #[test]
+    fn file_names_pick_the_format() {
+        assert_eq!(Format::split("CAM (1).wav"), Some(("CAM (1)", Format::Wav)));
+        assert_eq!(Format::split("v1.2.mp3"), Some(("v1.2", Format::Mp3)));
+        assert_eq!(Format::split("CAM.ogg"), None);
+        assert_eq!(Format::Wav.ffmpeg_args(), None);
+        let args = Format::Aac.ffmpeg_args().unwrap();
+        assert!(args.windows(2).any(|w| w == ["-c:a", "aac"]));
+        assert!(args.windows(2).any(|w| w == ["-f", "adts"]));
+        assert_eq!(args.last().map(String::as_str), Some("-"));
+    }
+}
@@ -0,0 +1,241 @@
+/// Something a client can do with a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Live video over WebSocket or MJPEG, and the source's details.
+    View,
+    /// Single stills from `/snapshot`.
+    Snapshot,
+    /// Pan/tilt/zoom control.
+    Ptz,
+    /// Starting and stopping recordings.
+    Record,
+    /// Changing how a source is served: encode quality, program output routing.
+    Control,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] =
+        [Action::View, Action::Snapshot, Action::Ptz, Action::Record, Action::Control];
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "view" => Ok(Action::View),
+            "snapshot" => Ok(Action::Snapshot),
+            "ptz" => Ok(Action::Ptz),
+            "record" => Ok(Action::Record),
+            "control" => Ok(Action::Control),
+            _ => Err(format!(
+                "unknown action \"{s}\" (expected view, snapshot, ptz, record or control)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Action::View => "view",
+            Action::Snapshot => "snapshot",
+            Action::Ptz => "ptz",
+            Action::Record => "record",
+            Action::Control => "control",
+        })
+    }
+}
+
+/// What one token may do, and with which sources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub token: String,
+    /// Who holds the token, e.g. for watermarks. Only settable from the config file.
+    pub label: Option<String>,
+    pub actions: Vec<Action>,
+    /// Source names this grant covers; `None` for all sources.
+    pub sources: Option<Vec<String>>,
+}
+
+impl Grant {
+    /// Parse `TOKEN:ACTIONS:SOURCES`, where ACTIONS is a comma-separated list of
+    /// `view`, `snapshot`, `ptz`, `record` and `control`, SOURCES is a `|`-separated list of
+    /// source names, and either may be `*` for all.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(token), Some(actions), Some(sources)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("expected TOKEN:ACTIONS:SOURCES, got \"{s}\""));
+        };
+        if token.is_empty() {
+            return Err("empty token in grant".to_string());
+        }
+        let actions = match actions.trim() {
+            "*" => Action::ALL.to_vec(),
+            list => list
+                .split(',')
+                .map(|a| Action::parse(a.trim()))
+                .collect::<Result<_, _>>()?,
+        };
+        let sources = match sources.trim() {
+            "*" => None,
+            list => Some(list.split('|').map(|s| s.trim().to_string()).collect()),
+        };
+        Ok(Grant {
+            token: token.to_string(),
+            label: None,
+            actions,
+            sources,
+        })
+    }
+
+    /// Everything with every source, for `--api-token`.
+    pub fn full_access(token: &str, label: Option<String>) -> Self {
+        Self { token: token.to_string(), label, actions: Action::ALL.to_vec(), sources: None }
+    }
+
+    /// Full-access grants from an `--api-tokens-file`: one `TOKEN [LABEL]` per
+    /// line, skipping blank lines and `#` comments.
+    pub fn parse_token_file(contents: &str) -> Vec<Self> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once(char::is_whitespace) {
+                Some((token, label)) => Self::full_access(token, Some(label.trim().to_string())),
+                None => Self::full_access(line, None),
+            })
+            .collect()
+    }
+
+    fn allows(&self, source: &str, action: Action) -> bool {
+        self.actions.contains(&action)
+            && self
+                .sources
+                .as_ref()
+                .is_none_or(|sources| sources.iter().any(|s| s == source))
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// No token was presented, or it matches no grant.
+    Unauthenticated,
+    /// The token is valid but not for this source and action.
+    Forbidden,
+}
+
+/// The token → sources/actions matrix. With no grants every request is allowed,
+/// which keeps an unconfigured server open as before.
+pub struct AccessPolicy {
+    grants: Vec<Grant>,
+}
+
+impl AccessPolicy {
+    pub fn new(grants: Vec<Grant>) -> Self {
+        Self { grants }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    /// A name for the holder of `token`: its grant's label, else the grant's
+    /// number in the order the grants were given, which tells holders apart
+    /// without giving any of the token away. `None` without a known token, so
+    /// also whenever there are no grants.
+    pub fn viewer_label(&self, token: Option<&str>) -> Option<String> {
+        let token = token?;
+        let mut held = self
+            .grants
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| constant_time_eq(g.token.as_bytes(), token.as_bytes()))
+            .peekable();
+        let (first, _) = *held.peek()?;
+        let label = held.find_map(|(_, g)| g.label.clone());
+        Some(label.unwrap_or_else(|| format!("grant {}", first + 1)))
+    }
+
+    /// Whether `token` belongs to any grant, whatever it allows.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<(), Denial> {
+        if self.is_open() {
+            return Ok(());
+        }
+        let known = token.is_some_and(|t| {
+            self.grants.iter().any(|g| constant_time_eq(g.token.as_bytes(), t.as_bytes()))
+        });
+        if known {
+            Ok(())
+        } else {
+            Err(Denial::Unauthenticated)
+        }
+    }
+
+    /// The first grant's token `proves` holds for, for credentials made from a
+    /// token rather than carrying it, like an ONVIF password digest.
+    pub fn find_token(&self, proves: impl Fn(&str) -> bool) -> Option<&str> {
+        self.grants.iter().map(|g| g.token.as_str()).find(|token| proves(token))
+    }
+
+    /// Whether `token` may do `action` with every source, including any yet to
+    /// appear, e.g. to see what concerns no source in particular.
+    pub fn allows_all_sources(&self, token: Option<&str>, action: Action) -> bool {
+        self.is_open()
+            || token.is_some_and(|t| {
+                self.grants.iter().any(|g| {
+                    constant_time_eq(g.token.as_bytes(), t.as_bytes())
+                        && g.sources.is_none()
+                        && g.actions.contains(&action)
+                })
+            })
+    }
+
+    pub fn check(&self, token: Option<&str>, source: &str, action: Action) -> Result<(), Denial> {
+        if self.is_open() {
+            return Ok(());
+        }
+        let mut grants = self
+            .grants
+            .iter()
+            .filter(|g| token.is_some_and(|t| constant_time_eq(g.token.as_bytes(), t.as_bytes())))
+            .peekable();
+        if grants.peek().is_none() {
+            return Err(Denial::Unauthenticated);
+        }
+        if grants.any(|g| g.allows(source, action)) {
+            Ok(())
+        } else {
+            Err(Denial::Forbidden)
+        }
+    }
+}
+
+/// The first few characters of a token, safe to put in logs.
+pub fn token_hint(token: Option<&str>) -> String {
+    match token {
+        None => "(none)".to_string(),
+        Some(t) => format!("{}…", t.chars().take(4).collect::<String>()),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viewer_labels_never_show_the_token() {
+        let grants = ["a1b2c3d4e5:view:*", "f6g7h8i9j0:view:cam", "f6g7h8i9j0:snapshot:cam"];
+        let mut grants: Vec<Grant> = grants.iter().map(|g| Grant::parse(g).unwrap()).collect();
+        grants[2].label = Some("Agency".to_string());
+        let policy = AccessPolicy::new(grants);
+        assert_eq!(policy.viewer_label(Some("a1b2c3d4e5")).as_deref(), Some("grant 1"));
+        assert_eq!(policy.viewer_label(Some("f6g7h8i9j0")).as_deref(), Some("Agency"));
+        assert_eq!(policy.viewer_label(Some("unknown")), None);
+        assert_eq!(policy.viewer_label(None), None);
+        // Without grants nobody is told apart, and nothing is watermarked.
+        assert_eq!(AccessPolicy::new(Vec::new()).viewer_label(Some("a1b2c3d4e5")), None);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::discovery::SourceList;
+use crate::events::{Event, EventBus};
+use crate::receiver::{JpegFrame, ReceiverManager};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// How long a triggered capture waits for a frame.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A `[[capture_rules]]` entry: take a snapshot of the event's source whenever a
+/// matching event is published, and save and/or upload it, and/or save the
+/// source's pre-roll.
+///
+/// `path`, `upload` and `preroll` are templates: `{source}`, `{event}` and `{timestamp}`
+/// (UTC, `YYYYMMDD-HHMMSS`) are replaced per capture.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureRule {
+    /// Event kind, see [`Event::kind`].
+    pub on: String,
+    /// Only events from this source; any source when absent.
+    pub source: Option<String>,
+    /// For `on = "webhook"`: only this hook name.
+    pub webhook: Option<String>,
+    /// File to write the JPEG to.
+    pub path: Option<String>,
+    /// `http://` URL to PUT the JPEG to.
+    pub upload: Option<String>,
+    /// File to write the source's pre-roll to, as AVI; see `--preroll`.
+    pub preroll: Option<String>,
+}
+
+impl CaptureRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if !Event::KINDS.contains(&self.on.as_str()) {
+            return Err(format!(
+                "unknown event \"{}\" in capture rule (expected one of {})",
+                self.on,
+                Event::KINDS.join(", ")
+            ));
+        }
+        if self.path.is_none() && self.upload.is_none() && self.preroll.is_none() {
+            return Err(format!(
+                "capture rule on \"{}\" needs a path, an upload URL or a preroll path",
+                self.on
+            ));
+        }
+        if let Some(url) = &self.upload {
+            if !url.starts_with("http://") {
+                return Err(format!("upload URL must start with http://, got \"{url}\""));
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        if event.kind() != self.on || self.source.as_deref().is_some_and(|s| s != event.source()) {
+            return false;
+        }
+        match (event, &self.webhook) {
+            (Event::Webhook { name, .. }, Some(hook)) => name == hook,
+            _ => true,
+        }
+    }
+}
+
+/// Run `rules` against every event on the bus until the bus closes.
+pub fn start(
+    rules: Vec<CaptureRule>,
+    events: &EventBus,
+    sources: SourceList,
+    manager: Arc<ReceiverManager>,
+) {
+    if rules.is_empty() {
+        return;
+    }
+    info!("{} capture rule(s) active", rules.len());
+    let rules = Arc::new(rules);
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("capture rules missed {} events", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            for rule in rules.iter().filter(|r| r.matches(&event)) {
+                let (rule, event) = (rule.clone(), event.clone());
+                let (sources, manager) = (sources.clone(), manager.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = capture(&rule, &event, &sources, &manager).await {
+                        warn!("[{}] capture on {} failed: {}", event.source(), event.kind(), e);
+                    }
+                });
+            }
+        }
+    });
+}
+
+async fn capture(
+    rule: &CaptureRule,
+    event: &Event,
+    sources: &SourceList,
+    manager: &Arc<ReceiverManager>,
+) -> Result<(), String> {
+    let timestamp = manager.time_format(event.source()).file_timestamp(SystemTime::now());
+    let render = |template: &str| {
+        template
+            .replace("{source}", &sanitize(event.source()))
+            .replace("{event}", event.kind())
+            .replace("{timestamp}", &timestamp)
+    };
+
+    // Taken first: what led up to the event shouldn't wait for the next frame.
+    if let Some(path) = &rule.preroll {
+        let preroll = manager.preroll(event.source()).ok_or("no pre-roll for this source")?;
+        let avi = preroll
+            .avi()
+            .ok_or("no pre-roll frames buffered")?
+            .map_err(|e| format!("failed to write pre-roll: {e}"))?;
+        let path = PathBuf::from(render(path));
+        write(&path, &avi).await?;
+        info!("[{}] {} → saved pre-roll {}", event.source(), event.kind(), path.display());
+    }
+    if rule.path.is_none() && rule.upload.is_none() {
+        return Ok(());
+    }
+    let jpeg = next_frame(event.source(), sources, manager).await?;
+    if let Some(path) = &rule.path {
+        let path = PathBuf::from(render(path));
+        write(&path, &jpeg).await?;
+        info!("[{}] {} → saved {}", event.source(), event.kind(), path.display());
+    }
+    if let Some(url) = &rule.upload {
+        let url = render(url);
+        upload(&url, jpeg).await?;
+        info!("[{}] {} → uploaded {}", event.source(), event.kind(), url);
+    }
+    Ok(())
+}
+
+/// Write `data` to `path`, creating its directory.
+pub(crate) async fn write(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    }
+    tokio::fs::write(path, data)
+        .await
+        .map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+/// The next frame from `source_name`, starting its receiver if needed.
+pub(crate) async fn next_frame(
+    source_name: &str,
+    sources: &SourceList,
+    manager: &Arc<ReceiverManager>,
+) -> Result<Bytes, String> {
+    let source = {
+        let sources = sources.read().unwrap();
+        sources.iter().find(|s| s.name == source_name).cloned()
+    }
+    .ok_or("source not found")?;
+    let shared = manager.get_or_create(&source)?;
+    let mut rx = shared.subscribe();
+    let frame = tokio::time::timeout(CAPTURE_TIMEOUT, async {
+        match rx.recv().await {
+            Some(JpegFrame { data, .. }) => Ok(data),
+            None => Err("source lost".to_string()),
+        }
+    })
+    .await
+    .unwrap_or_else(|_| Err("no frame received".to_string()));
+    drop(rx);
+    shared.unsubscribe();
+    manager.maybe_remove(source_name);
+    frame
+}
+
+/// PUT `jpeg` to a plain-HTTP `url`.
+async fn upload(url: &str, jpeg: Bytes) -> Result<(), String> {
+    send(hyper::Method::PUT, url, "image/jpeg", jpeg).await
+}
+
+/// Send `body` to a plain `http://` `url` and wait for a success status.
+pub(crate) async fn send(
+    method: hyper::Method,
+    url: &str,
+    content_type: &str,
+    body: Bytes,
+) -> Result<(), String> {
+    use http_body_util::Full;
+    use hyper::{header, Request, Uri};
+
+    let uri: Uri = url.parse().map_err(|e| format!("invalid URL \"{url}\": {e}"))?;
+    let authority = uri.authority().ok_or("URL has no host")?.clone();
+    let port = authority.port_u16().unwrap_or(80);
+    let stream = tokio::net::TcpStream::connect((authority.host(), port))
+        .await
+        .map_err(|e| format!("failed to connect to {authority}: {e}"))?;
+    let io = hyper_util::rt::TokioIo::new(stream);
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
+        .await
+        .map_err(|e| format!("{method} handshake failed: {e}"))?;
+    tokio::spawn(conn);
+
+    let request = Request::builder()
+        .method(&method)
+        .uri(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(header::HOST, authority.as_str())
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Full::new(body))
+        .map_err(|e| format!("invalid {method} request: {e}"))?;
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| format!("{method} failed: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!("{method} rejected: {}", response.status()));
+    }
+    Ok(())
+}
+
+/// A source name made safe for use as a file name.
+pub(crate) fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() => c,
+            '-' | '_' | '.' | ' ' | '(' | ')' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC.
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_date(days as i64);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Year, month and day of the `days`th day since the Unix epoch.
+pub(crate) fn civil_date(days: i64) -> (i64, i64, i64) {
+    // Civil-from-days, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "19700101-000000");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_217_296);
+        assert_eq!(utc_timestamp(leap_day), "20240229-143456");
+    }
+
+    #[test]
+    fn sanitizes_source_names() {
+        assert_eq!(sanitize("HOST (Cam 1/2)"), "HOST (Cam 1_2)");
+    }
+}
@@ -0,0 +1,234 @@
+//! MJPEG in AVI: the bridge's JPEGs as they are, in a container every editor and
+//! player opens.
+//!
+//! The headers are written last, once the frame count, size and rate are known;
+//! until then the file starts with a zeroed placeholder. All integers are
+//! little-endian.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Players disagree beyond this without the OpenDML extensions, so recordings
+/// move on to a new file before it.
+pub const MAX_FILE_BYTES: u64 = 1 << 30;
+
+/// `RIFF` and `hdrl` up to the `movi` list's own header.
+const HEADER_BYTES: u64 = 212;
+/// Where the first frame goes: after the `movi` list's header.
+const MOVI_DATA: u64 = HEADER_BYTES + 12;
+/// `AVIF_HASINDEX`, and `AVIIF_KEYFRAME` for the index entries.
+const HAS_INDEX: u32 = 0x10;
+const KEYFRAME: u32 = 0x10;
+
+/// Writes JPEG frames to an AVI file, or anything else that seeks.
+pub struct AviWriter<W: Write + Seek = BufWriter<File>> {
+    out: Option<W>,
+    /// Offset and length of each frame, for the index.
+    index: Vec<(u32, u32)>,
+    /// Where the next chunk goes.
+    position: u64,
+    size: Option<(u16, u16)>,
+    largest: u32,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl AviWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&[0; MOVI_DATA as usize])?;
+        Ok(Self {
+            out: Some(out),
+            index: Vec::new(),
+            position: MOVI_DATA,
+            size: None,
+            largest: 0,
+            first: None,
+            last: None,
+        })
+    }
+
+    /// The file's size so far, not counting the index still to come.
+    pub fn bytes_written(&self) -> u64 {
+        self.position
+    }
+
+    /// Add one frame, received at `at`. The first frame sets the video's size;
+    /// the rate is the average over all of them.
+    pub fn write_frame(&mut self, jpeg: &[u8], at: Instant) -> io::Result<()> {
+        let out = self.out.as_mut().ok_or_else(|| io::Error::other("already finished"))?;
+        if self.size.is_none() {
+            let size = jpeg_size(jpeg).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "no frame size in JPEG")
+            })?;
+            self.size = Some(size);
+        }
+        let len = jpeg.len() as u32;
+        out.write_all(b"00dc")?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(jpeg)?;
+        if len % 2 == 1 {
+            out.write_all(&[0])?;
+        }
+        // Index offsets count from the `movi` fourcc.
+        self.index.push(((self.position - (MOVI_DATA - 4)) as u32, len));
+        self.position += 8 + u64::from(len + len % 2);
+        self.largest = self.largest.max(len);
+        self.first.get_or_insert(at);
+        self.last = Some(at);
+        Ok(())
+    }
+
+    /// Write the index and the headers; returns what they were written to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.complete()?.ok_or_else(|| io::Error::other("already finished"))
+    }
+
+    fn complete(&mut self) -> io::Result<Option<W>> {
+        let Some(mut out) = self.out.take() else {
+            return Ok(None);
+        };
+        out.write_all(b"idx1")?;
+        out.write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for &(offset, len) in &self.index {
+            out.write_all(b"00dc")?;
+            out.write_all(&KEYFRAME.to_le_bytes())?;
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&len.to_le_bytes())?;
+        }
+        let end = self.position + 8 + self.index.len() as u64 * 16;
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&self.header(end))?;
+        out.flush()?;
+        Ok(Some(out))
+    }
+
+    /// Frames per second, in thousandths.
+    fn rate(&self) -> u32 {
+        let frames = self.index.len() as f64;
+        match (self.first, self.last) {
+            (Some(first), Some(last)) if frames > 1.0 && last > first => {
+                let fps = (frames - 1.0) / (last - first).as_secs_f64();
+                (fps * 1000.0).round().clamp(1.0, 1_000_000.0) as u32
+            }
+            _ => 25_000,
+        }
+    }
+
+    fn header(&self, end: u64) -> Vec<u8> {
+        let (width, height) = self.size.map_or((0, 0), |(w, h)| (u32::from(w), u32::from(h)));
+        let frames = self.index.len() as u32;
+        let rate = self.rate();
+        let mut h = Vec::with_capacity(MOVI_DATA as usize);
+        let u32s = |h: &mut Vec<u8>, values: &[u32]| {
+            values.iter().for_each(|v| h.extend_from_slice(&v.to_le_bytes()));
+        };
+
+        h.extend_from_slice(b"RIFF");
+        u32s(&mut h, &[(end - 8) as u32]);
+        h.extend_from_slice(b"AVI LIST");
+        u32s(&mut h, &[HEADER_BYTES as u32 - 20]);
+        h.extend_from_slice(b"hdrlavih");
+        let usec_per_frame = (1_000_000_000 / u64::from(rate)) as u32;
+        u32s(&mut h, &[56, usec_per_frame, 0, 0, HAS_INDEX, frames, 0, 1, self.largest]);
+        u32s(&mut h, &[width, height, 0, 0, 0, 0]);
+
+        h.extend_from_slice(b"LIST");
+        u32s(&mut h, &[4 + 64 + 48]);
+        h.extend_from_slice(b"strlstrh");
+        u32s(&mut h, &[56]);
+        h.extend_from_slice(b"vidsMJPG");
+        // Flags, priority and language, initial frames, then the rate as
+        // rate / scale, start and length in frames.
+        u32s(&mut h, &[0, 0, 0, 1000, rate, 0, frames, self.largest, u32::MAX, 0]);
+        h.extend_from_slice(&[0, 0, 0, 0]);
+        h.extend_from_slice(&(width as u16).to_le_bytes());
+        h.extend_from_slice(&(height as u16).to_le_bytes());
+        h.extend_from_slice(b"strf");
+        u32s(&mut h, &[40, 40, width, height]);
+        // One plane of 24 bits.
+        h.extend_from_slice(&[1, 0, 24, 0]);
+        h.extend_from_slice(b"MJPG");
+        u32s(&mut h, &[width * height * 3, 0, 0, 0, 0]);
+
+        h.extend_from_slice(b"LIST");
+        u32s(&mut h, &[(self.position - MOVI_DATA + 4) as u32]);
+        h.extend_from_slice(b"movi");
+        h
+    }
+}
+
+impl<W: Write + Seek> Drop for AviWriter<W> {
+    /// A recording cut short, e.g. by shutdown, still gets its headers.
+    fn drop(&mut self) {
+        let _ = self.complete();
+    }
+}
+
+/// Width and height from a JPEG's start-of-frame segment.
+fn jpeg_size(jpeg: &[u8]) -> Option<(u16, u16)> {
+    let mut at = 2;
+    while at + 4 <= jpeg.len() {
+        if jpeg[at] != 0xFF {
+            return None;
+        }
+        let marker = jpeg[at + 1];
+        let len = usize::from(u16::from_be_bytes([jpeg[at + 2], jpeg[at + 3]]));
+        // SOF0 to SOF15, apart from DHT, JPG and DAC.
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let sof = jpeg.get(at + 5..at + 9)?;
+            let height = u16::from_be_bytes([sof[0], sof[1]]);
+            let width = u16::from_be_bytes([sof[2], sof[3]]);
+            return Some((width, height));
+        }
+        at += 2 + len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{encode_frame, EncodeBuffers};
+    use crate::ndi::FourCCVideoType;
+    use std::time::Duration;
+
+    #[test]
+    fn frames_are_indexed_behind_complete_headers() {
+        let mut buffers = EncodeBuffers::new();
+        let uyvy = [128; 32 * 16 * 2];
+        let jpeg = encode_frame(&uyvy, 32, 16, 64, FourCCVideoType::UYVY, 75, &mut buffers)
+            .expect("encode");
+        let path = std::env::temp_dir().join(format!("sb-avi-test-{}.avi", std::process::id()));
+        let mut avi = AviWriter::create(&path).unwrap();
+        let start = Instant::now();
+        for i in 0..10 {
+            avi.write_frame(&jpeg, start + Duration::from_millis(40 * i)).unwrap();
+        }
+        avi.finish().unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let u32_at = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, file.len() - 8);
+        assert_eq!(&file[8..12], b"AVI ");
+        assert_eq!(u32_at(32), 40_000, "microseconds per frame");
+        assert_eq!(u32_at(48), 10, "frames");
+        assert_eq!((u32_at(64), u32_at(68)), (32, 16));
+        assert_eq!(&file[HEADER_BYTES as usize + 8..MOVI_DATA as usize], b"movi");
+        assert_eq!(&file[MOVI_DATA as usize..MOVI_DATA as usize + 4], b"00dc");
+        let index = file.len() - 8 - 10 * 16;
+        assert_eq!(&file[index..index + 4], b"idx1");
+        // The first entry points at the first frame, counted from `movi`.
+        assert_eq!(u32_at(index + 16), 4);
+        assert_eq!(u32_at(index + 20) as usize, jpeg.len());
+    }
+}
@@ -0,0 +1,193 @@
+//! A "N VIEWERS" badge burned into a source's frames before they are encoded,
+//! so a presenter watching the return feed can see how many people watch.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use crate::watermark::glyph;
+
+const BLACK: u8 = 16;
+const WHITE: u8 = 235;
+
+/// Draws the badge into a copy of each frame, reusing the copy's buffer.
+#[derive(Default)]
+pub struct ViewerBadge {
+    frame: Vec<u8>,
+}
+
+impl ViewerBadge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `frame` with `viewers` in its top right corner. Frames in layouts the
+    /// badge can't be drawn on are passed on as they are.
+    pub fn apply<'a>(&'a mut self, frame: &VideoFrame<'a>, viewers: u64) -> VideoFrame<'a> {
+        if bytes_per_pixel(frame.fourcc).is_none() {
+            return VideoFrame { ..*frame };
+        }
+        self.frame.clear();
+        self.frame.extend_from_slice(frame.data);
+        let text = match viewers {
+            1 => "1 VIEWER".to_string(),
+            n => format!("{n} VIEWERS"),
+        };
+        if let Some(mut canvas) = Canvas::new(&mut self.frame, frame) {
+            draw_badge(&mut canvas, &text);
+        }
+        VideoFrame { data: &self.frame, ..*frame }
+    }
+}
+
+fn bytes_per_pixel(fourcc: FourCCVideoType) -> Option<usize> {
+    match fourcc {
+        FourCCVideoType::UYVY => Some(2),
+        FourCCVideoType::BGRA
+        | FourCCVideoType::BGRX
+        | FourCCVideoType::RGBA
+        | FourCCVideoType::RGBX => Some(4),
+        _ => None,
+    }
+}
+
+/// A frame to draw on.
+pub(crate) struct Canvas<'a> {
+    data: &'a mut [u8],
+    pub width: usize,
+    pub height: usize,
+    stride: usize,
+    bytes_per_pixel: usize,
+    /// Blue, green, red in the four byte layouts.
+    bgr: bool,
+}
+
+impl<'a> Canvas<'a> {
+    /// `data`, laid out like `frame`; `None` for layouts that can't be drawn on.
+    pub fn new(data: &'a mut [u8], frame: &VideoFrame) -> Option<Self> {
+        Some(Self {
+            data,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+            bytes_per_pixel: bytes_per_pixel(frame.fourcc)?,
+            bgr: matches!(frame.fourcc, FourCCVideoType::BGRA | FourCCVideoType::BGRX),
+        })
+    }
+
+    /// Set the pixel at `x`, `y` to gray `level`, clipped to the frame.
+    fn set(&mut self, x: usize, y: usize, level: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let at = y * self.stride + x * self.bytes_per_pixel;
+        if self.bytes_per_pixel == 2 {
+            // UYVY: chroma shared by pixel pairs, luma in the odd bytes.
+            let pair = y * self.stride + x / 2 * 4;
+            self.data[pair] = 128;
+            self.data[pair + 2] = 128;
+            self.data[at + 1] = level;
+        } else {
+            // The alpha or padding byte comes last in all four layouts.
+            self.data[at..at + 3].fill(level);
+        }
+    }
+
+    /// Blend `rgb` over the pixel at `x`, `y` by `alpha` (255 is opaque),
+    /// clipped to the frame.
+    pub fn blend(&mut self, x: usize, y: usize, rgb: [u8; 3], alpha: u8) {
+        if x >= self.width || y >= self.height || alpha == 0 {
+            return;
+        }
+        let at = y * self.stride + x * self.bytes_per_pixel;
+        if self.bytes_per_pixel == 2 {
+            // BT.709, studio range; a pair's chroma is taken from its left pixel.
+            let [r, g, b] = rgb.map(f32::from);
+            let luma = (16.0 + 0.183 * r + 0.614 * g + 0.062 * b) as u8;
+            self.data[at + 1] = mix(self.data[at + 1], luma, alpha);
+            if x.is_multiple_of(2) {
+                let cb = (128.0 - 0.101 * r - 0.339 * g + 0.439 * b) as u8;
+                let cr = (128.0 + 0.439 * r - 0.399 * g - 0.040 * b) as u8;
+                self.data[at] = mix(self.data[at], cb, alpha);
+                self.data[at + 2] = mix(self.data[at + 2], cr, alpha);
+            }
+        } else {
+            let [r, g, b] = rgb;
+            let ordered = if self.bgr { [b, g, r] } else { [r, g, b] };
+            for (under, over) in self.data[at..at + 3].iter_mut().zip(ordered) {
+                *under = mix(*under, over, alpha);
+            }
+        }
+    }
+}
+
+/// `over` blended onto `under` by `alpha`.
+fn mix(under: u8, over: u8, alpha: u8) -> u8 {
+    let a = u16::from(alpha);
+    ((u16::from(under) * (255 - a) + u16::from(over) * a + 127) / 255) as u8
+}
+
+/// The size of a [`draw_label`] box around `text` at `scale`.
+pub(crate) fn label_size(text: &str, scale: usize) -> (usize, usize) {
+    let pad = 2 * scale;
+    ((text.chars().count() * 6).saturating_sub(1) * scale + 2 * pad, 7 * scale + 2 * pad)
+}
+
+/// A black box with `text` in white, its top left corner at `x0`, `y0`.
+pub(crate) fn draw_label(canvas: &mut Canvas, text: &str, x0: usize, y0: usize, scale: usize) {
+    let (box_w, box_h) = label_size(text, scale);
+    let pad = 2 * scale;
+    for y in y0..y0 + box_h {
+        for x in x0..x0 + box_w {
+            canvas.set(x, y, BLACK);
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        for (gy, bits) in glyph(c).iter().enumerate() {
+            for gx in (0..5).filter(|gx| bits & (0x10 >> gx) != 0) {
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = x0 + pad + (i * 6 + gx) * scale + dx;
+                        canvas.set(x, y0 + pad + gy * scale + dy, WHITE);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A black box with `text` in white, inset from the top right corner by its
+/// own padding and sized to the frame's height.
+fn draw_badge(canvas: &mut Canvas, text: &str) {
+    let scale = (canvas.height / 240).max(1);
+    let pad = 2 * scale;
+    let (box_w, _) = label_size(text, scale);
+    draw_label(canvas, text, canvas.width.saturating_sub(box_w + pad), pad, scale);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: &[u8], fourcc: FourCCVideoType) -> VideoFrame<'_> {
+        let (width, height) = (320, 180);
+        let stride = data.len() / height;
+        VideoFrame { data, width, height, stride, fourcc }
+    }
+
+    #[test]
+    fn draws_in_the_top_right_corner_of_a_copy() {
+        let w = 320;
+        let uyvy = vec![128u8; w * 180 * 2];
+        let mut badge = ViewerBadge::new();
+        let badged = badge.apply(&frame(&uyvy, FourCCVideoType::UYVY), 12);
+        let luma = |x: usize, y: usize| badged.data[y * w * 2 + x * 2 + 1];
+        let corner: Vec<u8> = (0..20).flat_map(|y| (w - 70..w).map(move |x| luma(x, y))).collect();
+        assert!(corner.contains(&BLACK) && corner.contains(&WHITE));
+        assert!((0..w / 2).all(|x| luma(x, 5) == 128), "badge drawn outside the corner");
+        assert!(uyvy.iter().all(|&b| b == 128), "the producer's frame was drawn on");
+
+        let bgra = vec![200u8; w * 180 * 4];
+        let badged = badge.apply(&frame(&bgra, FourCCVideoType::BGRA), 1);
+        let alpha_kept = badged.data.chunks_exact(4).all(|px| px[3] == 200);
+        assert!(alpha_kept && badged.data.chunks_exact(4).any(|px| px[..3] == [WHITE; 3]));
+    }
+}
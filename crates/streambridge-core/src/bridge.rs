@@ -0,0 +1,634 @@
+//! The whole bridge behind one builder, for applications that embed it instead
+//! of running `streambridge serve`.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use streambridge_core::Bridge;
+//!
+//! # async fn run() -> Result<(), String> {
+//! let ndi = streambridge_core::ndi::load().map_err(|e| e.to_string())?;
+//! let bridge = Bridge::builder().ndi(ndi.into()).port(9550).spawn().await?;
+//! println!("open http://{}/", bridge.local_addr());
+//! tokio::signal::ctrl_c().await.ok();
+//! bridge.shutdown(Duration::ZERO).await.map_err(|e| e.to_string())
+//! # }
+//! ```
+
+use crate::annotations::AnnotationHub;
+use crate::auth::{AccessPolicy, Grant};
+use crate::automation::{self, CaptureRule};
+use crate::clients::ClientRegistry;
+use crate::commands::CommandRunner;
+use crate::config::{EncodeSettings, SourceSettings};
+use crate::discovery::{self, Finders, SourceList};
+use crate::events::EventBus;
+use crate::favorites::Favorites;
+use crate::idle::{self, Activity};
+use crate::limits::{ConnectionLimits, Limits};
+use crate::logs::LogBuffer;
+use crate::ndi::{NdiInstance, SendSettings, Source};
+use crate::onvif;
+use crate::quirks::{Quirk, Quirks};
+use crate::receiver::{self, ReceiverManager, VirtualSource};
+use crate::recording::Rotation;
+use crate::republish;
+use crate::rtsp;
+use crate::runtime::NdiRuntime;
+use crate::scripting::{self, Script};
+use crate::server::{self, AppState};
+use crate::snapshots::{self, Snapshots};
+use crate::srt::SrtOutputs;
+use crate::store::Store;
+use crate::threads::ThreadPolicy;
+use crate::whep::WhepSessions;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How long shutdown waits for capture threads to finish recordings and
+/// release their NDI® receivers.
+const CAPTURE_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for a [`Bridge`]; see [`Bridge::builder`].
+pub struct BridgeBuilder {
+    ndi: Option<Arc<NdiInstance>>,
+    addr: SocketAddr,
+    rtsp_port: Option<u16>,
+    onvif: bool,
+    settings: SourceSettings,
+    loudness_target: Option<f64>,
+    virtual_sources: Vec<(String, VirtualSource)>,
+    quirks: Vec<Quirk>,
+    grants: Vec<Grant>,
+    chaos: bool,
+    public_readonly: bool,
+    watermark: bool,
+    client_ids: bool,
+    capture_rules: Vec<CaptureRule>,
+    record_dir: Option<PathBuf>,
+    record_rotation: Rotation,
+    record_fps: Option<u32>,
+    record_shared: bool,
+    limits: ConnectionLimits,
+    program_output: Option<String>,
+    republish: Vec<(String, republish::Input)>,
+    script: Option<Script>,
+    log_interval: u64,
+    receiver_linger: Duration,
+    idle_after: Duration,
+    thread_policy: ThreadPolicy,
+    receiver_name: Option<String>,
+    logs: Option<LogBuffer>,
+    state_dir: Option<PathBuf>,
+    groups: Vec<String>,
+    extra_ips: Vec<IpAddr>,
+    static_sources: Vec<Source>,
+    aliases: BTreeMap<String, String>,
+}
+
+impl BridgeBuilder {
+    /// The NDI® runtime to discover and receive sources with. Without it only
+    /// virtual sources are served. Keep no clone of it: when it starts failing,
+    /// e.g. during an update of NDI Tools, it is reloaded once every user has
+    /// let go of it.
+    pub fn ndi(mut self, ndi: Arc<NdiInstance>) -> Self {
+        self.ndi = Some(ndi);
+        self
+    }
+
+    /// Listen on all interfaces on `port`; 0 picks a free one.
+    pub fn port(mut self, port: u16) -> Self {
+        self.addr.set_port(port);
+        self
+    }
+
+    /// Listen on `addr` instead.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Encode settings, with any per-source overrides.
+    /// Also serve sources over RTSP on `port`, on the HTTP server's interface;
+    /// 0 picks a free one.
+    pub fn rtsp_port(mut self, port: u16) -> Self {
+        self.rtsp_port = Some(port);
+        self
+    }
+
+    /// Answer ONVIF WS-Discovery probes with every source, so video management
+    /// systems find them as cameras.
+    pub fn onvif(mut self, onvif: bool) -> Self {
+        self.onvif = onvif;
+        self
+    }
+
+    pub fn settings(mut self, settings: SourceSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Measure loudness against this integrated target in LUFS.
+    pub fn loudness_target(mut self, lufs: f64) -> Self {
+        self.loudness_target = Some(lufs);
+        self
+    }
+
+    /// Serve a replay, media, composite or clock source under `name`.
+    pub fn virtual_source(mut self, name: impl Into<String>, source: VirtualSource) -> Self {
+        self.virtual_sources.push((name.into(), source));
+        self
+    }
+
+    /// Workarounds for particular senders, on top of the built-in ones.
+    pub fn quirks(mut self, quirks: Vec<Quirk>) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Require tokens; without grants every source is open to every client.
+    pub fn grant(mut self, grant: Grant) -> Self {
+        self.grants.push(grant);
+        self
+    }
+
+    /// Honour fault-injection query parameters on `/ws`.
+    pub fn chaos(mut self, chaos: bool) -> Self {
+        self.chaos = chaos;
+        self
+    }
+
+    /// No control or admin endpoints, and capped previews.
+    pub fn public_readonly(mut self, public_readonly: bool) -> Self {
+        self.public_readonly = public_readonly;
+        self
+    }
+
+    /// Mark each identified viewer's frames with their token label.
+    pub fn watermark(mut self, watermark: bool) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// Give viewers a persistent id in a cookie, so `/api/clients` can tell
+    /// their reconnections from new viewers.
+    pub fn client_ids(mut self, client_ids: bool) -> Self {
+        self.client_ids = client_ids;
+        self
+    }
+
+    /// Take snapshots automatically when events happen.
+    pub fn capture_rules(mut self, rules: Vec<CaptureRule>) -> Self {
+        self.capture_rules = rules;
+        self
+    }
+
+    /// Where recordings are written; `recordings` by default.
+    pub fn record_dir(mut self, dir: PathBuf) -> Self {
+        self.record_dir = Some(dir);
+        self
+    }
+
+    /// Move recordings on to a new file past a size or duration.
+    pub fn record_rotation(mut self, rotation: Rotation) -> Self {
+        self.record_rotation = rotation;
+        self
+    }
+
+    /// Write AVI recordings at exactly `fps` frames per second unless a
+    /// request asks for another rate.
+    pub fn record_fps(mut self, fps: Option<u32>) -> Self {
+        self.record_fps = fps;
+        self
+    }
+
+    /// Have AVI recordings take the JPEGs sent to viewers, at the fps cap,
+    /// instead of encoding every frame themselves.
+    pub fn record_shared(mut self, shared: bool) -> Self {
+        self.record_shared = shared;
+        self
+    }
+
+    /// Publish a routed NDI® source named `name` for the `switch_program` command.
+    pub fn program_output(mut self, name: impl Into<String>) -> Self {
+        self.program_output = Some(name.into());
+        self
+    }
+
+    /// Send `input`'s frames out as an NDI® source named `name`, e.g. so other
+    /// NDI tools can take a composite or mosaic.
+    pub fn republish(mut self, name: impl Into<String>, input: republish::Input) -> Self {
+        self.republish.push((name.into(), input));
+        self
+    }
+
+    /// Call `script`'s handlers when events happen.
+    pub fn script(mut self, script: Script) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Keep receivers running for `linger` after their last client leaves, so a
+    /// reloaded page reattaches without reconnecting to the sender.
+    pub fn receiver_linger(mut self, linger: Duration) -> Self {
+        self.receiver_linger = linger;
+        self
+    }
+
+    /// After `after` without requests or open streams, release unused receivers
+    /// and slow discovery down until the next request; zero (the default) for never.
+    pub fn idle_after(mut self, after: Duration) -> Self {
+        self.idle_after = after;
+        self
+    }
+
+    /// Run the capture threads, which also encode, at a priority and on CPUs
+    /// that leave room for other software on the machine.
+    pub fn thread_policy(mut self, policy: ThreadPolicy) -> Self {
+        self.thread_policy = policy;
+        self
+    }
+
+    /// What NDI® senders and tools like NDI Studio Monitor show the bridge's
+    /// receivers as; `streambridge@<hostname>` by default.
+    pub fn receiver_name(mut self, name: impl Into<String>) -> Self {
+        self.receiver_name = Some(name.into());
+        self
+    }
+
+    /// Serve `logs`, fed by the application's tracing subscriber, on `GET /admin/logs`.
+    pub fn logs(mut self, logs: LogBuffer) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
+    /// Cap streaming clients overall, per source and per address.
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only discover and serve NDI® sources in these groups instead of the
+    /// default one.
+    pub fn groups(mut self, groups: Vec<String>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Also ask the machines at `ips` for their NDI® sources, for senders that
+    /// discovery doesn't reach, e.g. on other subnets.
+    pub fn extra_ips(mut self, ips: Vec<IpAddr>) -> Self {
+        self.extra_ips = ips;
+        self
+    }
+
+    /// List the NDI® source `name`, sent from `url` (`ip:port`), whether
+    /// discovery finds it or not.
+    pub fn static_source(mut self, name: impl Into<String>, url: impl Into<String>) -> Self {
+        self.static_sources.push(Source { name: name.into(), url: Some(url.into()) });
+        self
+    }
+
+    /// Let `alias` stand in for the source `name` wherever a source is named,
+    /// so URLs survive the sending machine being renamed.
+    pub fn alias(mut self, alias: impl Into<String>, name: impl Into<String>) -> Self {
+        self.aliases.insert(alias.into(), name.into());
+        self
+    }
+
+    /// Keep state such as the source favorites in `dir` across restarts; by
+    /// default it lasts until the bridge stops.
+    pub fn state_dir(mut self, dir: PathBuf) -> Self {
+        self.state_dir = Some(dir);
+        self
+    }
+
+    /// Log each active source's stats every `secs` seconds; 0 (the default) for never.
+    pub fn log_interval(mut self, secs: u64) -> Self {
+        self.log_interval = secs;
+        self
+    }
+
+    /// Start discovery and the server on the current tokio runtime.
+    pub async fn spawn(self) -> Result<Bridge, String> {
+        let mut settings = self.settings;
+        if self.public_readonly {
+            settings = settings.public_readonly();
+        }
+        let srt_outputs = settings.srt_outputs();
+        let runtime = self.ndi.map(NdiRuntime::new);
+        // Everything that can fail comes before any thread or task is started.
+        let program = match &self.program_output {
+            Some(name) => {
+                let runtime = runtime.as_ref().ok_or("the program output needs the NDI® runtime")?;
+                let router = runtime
+                    .instance()?
+                    .create_router(name, None)
+                    .map_err(|e| format!("failed to create program output \"{name}\": {e}"))?;
+                info!("program output \"{}\" published", name);
+                Some(router)
+            }
+            None => None,
+        };
+        let mut senders = Vec::new();
+        for (name, input) in self.republish {
+            let runtime = runtime.as_ref().ok_or("re-publishing needs the NDI® runtime")?;
+            // Frames go out as they are encoded, at the source's own pace.
+            let settings = SendSettings::new(&name).clock_video(false).clock_audio(false);
+            let sender = runtime
+                .instance()?
+                .create_send_instance(&settings)
+                .map_err(|e| format!("failed to publish \"{name}\": {e}"))?;
+            info!("\"{}\" published", name);
+            senders.push((name, input, sender));
+        }
+        let store = self.state_dir.clone().map_or_else(Store::memory, Store::new);
+        let favorites = Favorites::load(store.clone())?;
+        let snapshot_dir = self.state_dir.map(|dir| dir.join("snapshots"));
+        let snapshots = Arc::new(Snapshots::load(store, snapshot_dir)?);
+        let listener = tokio::net::TcpListener::bind(self.addr)
+            .await
+            .map_err(|e| format!("failed to bind {}: {e}", self.addr))?;
+        let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let rtsp_listener = match self.rtsp_port {
+            Some(port) => {
+                let addr = SocketAddr::new(self.addr.ip(), port);
+                let listener = tokio::net::TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| format!("failed to bind {addr} for RTSP: {e}"))?;
+                Some(listener)
+            }
+            None => None,
+        };
+        let rtsp_addr = rtsp_listener.as_ref().map(|listener| listener.local_addr());
+        let rtsp_addr = rtsp_addr.transpose().map_err(|e| e.to_string())?;
+        // A finder per group, so `/sources` can tell which group a source is in.
+        let mut finders = None;
+        if let Some(runtime) = &runtime {
+            let mut groups = self.groups.clone();
+            if groups.is_empty() {
+                groups.push(discovery::DEFAULT_GROUP.to_string());
+            }
+            let extra_ips = &self.extra_ips;
+            let settings = groups
+                .into_iter()
+                .map(|group| {
+                    let settings = discovery::find_settings(slice::from_ref(&group), extra_ips);
+                    (group, settings)
+                })
+                .collect();
+            finders = Some(Finders::create(Arc::clone(runtime), settings)?);
+        }
+
+        let pinned = self
+            .virtual_sources
+            .iter()
+            .map(|(name, _)| Source { name: name.clone(), url: None })
+            .chain(self.static_sources)
+            .collect();
+        let events = EventBus::new();
+        let activity = Activity::new(self.idle_after);
+        let stop_discovery = Arc::new(AtomicBool::new(false));
+        let refresh = discovery::Refresh::new();
+        let (sources, source_groups, discovery) = discovery::start_discovery(
+            finders,
+            pinned,
+            events.clone(),
+            activity.clone(),
+            refresh.clone(),
+            stop_discovery.clone(),
+        );
+        let receiver_manager = ReceiverManager::new(
+            runtime,
+            settings,
+            self.loudness_target,
+            events.clone(),
+            self.virtual_sources.into_iter().collect(),
+            Quirks::new(self.quirks),
+        )
+        .linger(self.receiver_linger)
+        .thread_policy(self.thread_policy)
+        .receiver_name(self.receiver_name.unwrap_or_else(receiver::default_receiver_name));
+        let receiver_manager = Arc::new(receiver_manager);
+
+        let mut commands = CommandRunner::new(sources.clone(), receiver_manager.clone())
+            .record_rotation(self.record_rotation)
+            .record_fps(self.record_fps)
+            .record_shared(self.record_shared);
+        if let Some(dir) = self.record_dir {
+            commands = commands.record_dir(dir);
+        }
+        if let Some(router) = program {
+            commands = commands.program_output(router);
+        }
+        let commands = Arc::new(commands);
+
+        let srt = SrtOutputs::new(sources.clone(), receiver_manager.clone(), activity.clone());
+        for (source, config) in srt_outputs {
+            srt.start(&source, config);
+        }
+        for (name, input, sender) in senders {
+            let (sources, manager) = (sources.clone(), receiver_manager.clone());
+            tokio::spawn(republish::run(name, input, sender, sources, manager, activity.clone()));
+        }
+
+        let state = AppState {
+            sources: sources.clone(),
+            receiver_manager: receiver_manager.clone(),
+            chaos: self.chaos && !self.public_readonly,
+            access: Arc::new(AccessPolicy::new(self.grants)),
+            events: events.clone(),
+            public_readonly: self.public_readonly,
+            watermark: self.watermark,
+            commands: commands.clone(),
+            clients: ClientRegistry::new(),
+            client_ids: self.client_ids,
+            activity: activity.clone(),
+            logs: self.logs,
+            favorites: Arc::new(favorites),
+            source_groups,
+            discovery_refresh: refresh,
+            limits: Limits::new(self.limits),
+            annotations: AnnotationHub::new(),
+            snapshots: snapshots.clone(),
+            srt,
+            aliases: Arc::new(self.aliases),
+            rtsp_port: rtsp_addr.map(|addr| addr.port()),
+            whep: WhepSessions::new(),
+        };
+
+        if self.log_interval > 0 {
+            log_stats(receiver_manager.clone(), self.log_interval);
+        }
+        idle::start(activity, receiver_manager.clone());
+        automation::start(self.capture_rules, &events, sources.clone(), receiver_manager.clone());
+        if let Some(script) = self.script {
+            scripting::start(script, &events, commands);
+        }
+        snapshots::start(snapshots, sources.clone(), receiver_manager.clone());
+        log_events(&events);
+        if self.onvif {
+            let manager = receiver_manager.clone();
+            tokio::spawn(onvif::discover(sources.clone(), local_addr.port(), manager));
+        }
+
+        let (shutdown, signal) = oneshot::channel();
+        let signal = async move { signal.await.unwrap_or_default() };
+        if let Some(listener) = rtsp_listener {
+            tokio::spawn(rtsp::serve(listener, state.clone()));
+        }
+        let server = tokio::spawn(server::serve(listener, state, signal));
+
+        Ok(Bridge {
+            local_addr,
+            rtsp_addr,
+            sources,
+            events,
+            receiver_manager,
+            shutdown,
+            server,
+            discovery: discovery.map(|thread| (stop_discovery, thread)),
+        })
+    }
+}
+
+fn log_stats(manager: Arc<ReceiverManager>, interval: u64) {
+    let interval_secs = interval as f64;
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            tick.tick().await;
+            for (name, stats) in manager.active_stats() {
+                let snap = stats.snapshot_and_reset(interval_secs);
+                if snap.clients > 0 || snap.fps_out > 0.0 {
+                    info!("[{}] {}", name, snap);
+                }
+            }
+        }
+    });
+}
+
+fn log_events(events: &EventBus) {
+    let mut event_rx = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match event_rx.recv().await {
+                Ok(event) if event.is_alert() => warn!("{}", event),
+                Ok(event) => info!("{}", event),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// A running bridge: NDI® discovery, shared receivers and the HTTP server.
+/// Dropping it stops the server without draining; [`Bridge::shutdown`] also
+/// waits for everything to finish.
+pub struct Bridge {
+    local_addr: SocketAddr,
+    rtsp_addr: Option<SocketAddr>,
+    sources: SourceList,
+    events: Arc<EventBus>,
+    receiver_manager: Arc<ReceiverManager>,
+    shutdown: oneshot::Sender<Duration>,
+    server: JoinHandle<std::io::Result<()>>,
+    discovery: Option<(Arc<AtomicBool>, std::thread::JoinHandle<()>)>,
+}
+
+impl Bridge {
+    /// Everything off and unprotected, on port 9550 with quality 75 and no fps cap.
+    pub fn builder() -> BridgeBuilder {
+        BridgeBuilder {
+            ndi: None,
+            addr: SocketAddr::from(([0, 0, 0, 0], 9550)),
+            rtsp_port: None,
+            onvif: false,
+            settings: SourceSettings::from(EncodeSettings::new(75, 0)),
+            loudness_target: None,
+            virtual_sources: Vec::new(),
+            quirks: Vec::new(),
+            grants: Vec::new(),
+            chaos: false,
+            public_readonly: false,
+            watermark: false,
+            client_ids: false,
+            capture_rules: Vec::new(),
+            record_dir: None,
+            record_rotation: Rotation::default(),
+            record_fps: None,
+            record_shared: false,
+            limits: ConnectionLimits::default(),
+            program_output: None,
+            republish: Vec::new(),
+            script: None,
+            log_interval: 0,
+            receiver_linger: Duration::ZERO,
+            idle_after: Duration::ZERO,
+            thread_policy: ThreadPolicy::default(),
+            receiver_name: None,
+            logs: None,
+            state_dir: None,
+            groups: Vec::new(),
+            extra_ips: Vec::new(),
+            static_sources: Vec::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    /// The address the server listens on, with the port picked for port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The address RTSP is served on, if it is.
+    pub fn rtsp_addr(&self) -> Option<SocketAddr> {
+        self.rtsp_addr
+    }
+
+    /// The sources clients can currently pick from.
+    pub fn sources(&self) -> &SourceList {
+        &self.sources
+    }
+
+    /// Events such as access denials and loudness alerts.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    pub fn receiver_manager(&self) -> &Arc<ReceiverManager> {
+        &self.receiver_manager
+    }
+
+    /// Drain clients for up to `drain` (see [`server::serve`]), stop the server
+    /// and every receiver, and wait for the capture and discovery threads.
+    pub async fn shutdown(self, drain: Duration) -> std::io::Result<()> {
+        let _ = self.shutdown.send(drain);
+        let served = match self.server.await {
+            Ok(result) => result,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        let manager = self.receiver_manager;
+        let discovery = self.discovery;
+        // WebSocket tasks keep running while this waits, so their close frames go out.
+        let _ = tokio::task::spawn_blocking(move || {
+            let running = manager.join(CAPTURE_JOIN_TIMEOUT);
+            if running > 0 {
+                warn!("{} capture thread(s) didn't stop in time", running);
+            }
+            if let Some((stop, thread)) = discovery {
+                stop.store(true, Ordering::Relaxed);
+                let _ = thread.join();
+            }
+        })
+        .await;
+        served
+    }
+}
@@ -0,0 +1,74 @@
+//! Frame-rate conversion for outputs that need a constant frame rate, like AVI
+//! recordings: frames are repeated to fill gaps and dropped when several fall
+//! into one frame's time, and each gets the time of the slot it fills. Unlike
+//! the viewers' fps cap, which only skips frames, the output never runs slow.
+
+use bytes::Bytes;
+use std::time::{Duration, Instant};
+
+/// Converts frames arriving whenever to exactly `fps` frames per second.
+pub struct Cadence {
+    interval: Duration,
+    /// The slot the next output frame fills.
+    next: Option<Instant>,
+    /// The newest frame: what every slot until the next frame shows.
+    last: Option<Bytes>,
+}
+
+impl Cadence {
+    /// `fps` must be at least 1.
+    pub fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps.max(1),
+            next: None,
+            last: None,
+        }
+    }
+
+    /// Take a frame received at `at`. Returns the frames due before it, each
+    /// with its slot's time; the frame itself is only output once the next
+    /// one shows how long it lasted.
+    pub fn push(&mut self, frame: Bytes, at: Instant) -> Vec<(Bytes, Instant)> {
+        let due = self.flush(at);
+        self.next.get_or_insert(at);
+        self.last = Some(frame);
+        due
+    }
+
+    /// The frames due before `until`, e.g. at the end of a recording.
+    pub fn flush(&mut self, until: Instant) -> Vec<(Bytes, Instant)> {
+        let (Some(last), Some(next)) = (&self.last, &mut self.next) else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        while *next < until {
+            due.push((last.clone(), *next));
+            *next += self.interval;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_are_filled_and_bursts_dropped() {
+        let mut cadence = Cadence::new(25);
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let names = |frames: Vec<(Bytes, Instant)>| -> Vec<Bytes> {
+            frames.into_iter().map(|(frame, _)| frame).collect()
+        };
+
+        assert!(cadence.push(Bytes::from_static(b"a"), ms(0)).is_empty());
+        // Late: "a" fills three slots.
+        assert_eq!(names(cadence.push(Bytes::from_static(b"b"), ms(100))), ["a", "a", "a"]);
+        // Early: "c" replaces "b" before its slot at 120 ms.
+        assert!(cadence.push(Bytes::from_static(b"c"), ms(110)).is_empty());
+        let due = cadence.push(Bytes::from_static(b"d"), ms(130));
+        assert_eq!(due[0], (Bytes::from_static(b"c"), ms(120)));
+        assert_eq!(names(cadence.flush(ms(240))), ["d", "d"]);
+    }
+}
@@ -0,0 +1,137 @@
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fault-injection settings for one connection. Only honoured when the server
+/// runs with `--chaos`; meant for testing client reconnection and smoothing logic.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChaosParams {
+    /// Fixed delay added before every frame.
+    pub delay_ms: u64,
+    /// Random extra delay, uniformly distributed in `0..=jitter_ms`.
+    pub jitter_ms: u64,
+    /// Probability (0-1) that a frame is silently dropped.
+    pub drop: f64,
+    /// Probability (0-1) that a frame is held back and sent after the next one.
+    pub reorder: f64,
+}
+
+impl ChaosParams {
+    pub fn is_active(&self) -> bool {
+        self.delay_ms > 0 || self.jitter_ms > 0 || self.drop > 0.0 || self.reorder > 0.0
+    }
+}
+
+/// Minimal xorshift64* generator — good enough for fault injection, no extra dependency.
+struct XorShift(u64);
+
+impl XorShift {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::with_seed(nanos)
+    }
+
+    fn with_seed(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform float in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Applies [`ChaosParams`] to the outgoing frames of one connection.
+pub struct Chaos {
+    params: ChaosParams,
+    rng: XorShift,
+    held: Option<Bytes>,
+}
+
+impl Chaos {
+    pub fn new(params: ChaosParams) -> Self {
+        Self {
+            params,
+            rng: XorShift::seeded(),
+            held: None,
+        }
+    }
+
+    /// Delay, drop or reorder a frame. Returns the frames to send now, in order.
+    pub async fn apply(&mut self, frame: Bytes) -> Vec<Bytes> {
+        let delay = self.delay();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        if self.rng.next_f64() < self.params.drop {
+            return Vec::new();
+        }
+
+        if let Some(held) = self.held.take() {
+            return vec![frame, held];
+        }
+        if self.rng.next_f64() < self.params.reorder {
+            self.held = Some(frame);
+            return Vec::new();
+        }
+        vec![frame]
+    }
+
+    /// How long the next frame waits: the fixed delay plus a random jitter.
+    fn delay(&mut self) -> Duration {
+        let jitter = if self.params.jitter_ms > 0 {
+            self.rng.next_u64() % (self.params.jitter_ms + 1)
+        } else {
+            0
+        };
+        Duration::from_millis(self.params.delay_ms + jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded(params: ChaosParams) -> Chaos {
+        Chaos { rng: XorShift::with_seed(0x5EED), ..Chaos::new(params) }
+    }
+
+    #[test]
+    fn delays_stay_within_the_jitter() {
+        let params = ChaosParams { delay_ms: 20, jitter_ms: 30, ..Default::default() };
+        let mut chaos = seeded(params);
+        let delays: Vec<u128> = (0..1000).map(|_| chaos.delay().as_millis()).collect();
+        assert!(delays.iter().all(|ms| (20..=50).contains(ms)), "{delays:?}");
+        // The whole range is used, ends included.
+        assert!(delays.contains(&20) && delays.contains(&50));
+    }
+
+    #[tokio::test]
+    async fn reordering_swaps_neighbouring_frames() {
+        let mut chaos = seeded(ChaosParams { reorder: 0.3, ..Default::default() });
+        let mut sent = Vec::new();
+        for i in 0..200u8 {
+            sent.extend(chaos.apply(Bytes::from(vec![i])).await);
+        }
+        sent.extend(chaos.held.take());
+        let order: Vec<u8> = sent.iter().map(|frame| frame[0]).collect();
+
+        // Nothing is lost or duplicated...
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..200).collect::<Vec<u8>>());
+        // ...but some frames come right after the one that followed them.
+        let swaps: Vec<_> = order.windows(2).filter(|pair| pair[0] > pair[1]).collect();
+        assert!(swaps.len() > 20, "{order:?}");
+        assert!(swaps.iter().all(|pair| pair[0] == pair[1] + 1), "{order:?}");
+    }
+}
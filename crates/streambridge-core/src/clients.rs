@@ -0,0 +1,279 @@
+//! The clients connected right now and how well frames reach each of them, for
+//! `GET /api/clients`: when someone says the stream is slow or blurry for them,
+//! this shows what their link actually takes.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long throughput is measured over.
+const WINDOW: Duration = Duration::from_secs(2);
+/// How many persistent client ids are remembered before those not connected
+/// are forgotten.
+const MAX_REMEMBERED: usize = 10_000;
+
+/// Every connected streaming client, by id.
+#[derive(Default)]
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<ClientStats>>>,
+    /// Connections so far by persistent client id.
+    visits: Mutex<HashMap<String, u64>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Add a `kind` client (`websocket` or `mjpeg`) of `source`, connecting
+    /// from `peer` with `user_agent`, until the returned handle is dropped.
+    /// `client_id` is the viewer's persistent id, the same across reconnections.
+    pub fn register(
+        self: &Arc<Self>,
+        kind: &'static str,
+        source: &str,
+        label: Option<String>,
+        client_id: Option<String>,
+        peer: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> ClientHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(client_id) = &client_id {
+            self.visit(client_id);
+        }
+        let stats = Arc::new(ClientStats {
+            id,
+            kind,
+            source: source.to_string(),
+            label,
+            client_id,
+            peer,
+            user_agent,
+            connected: Instant::now(),
+            frames: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            lags: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            throughput: Mutex::new(Throughput::new(Instant::now())),
+            adaptive_fps: Mutex::new(None),
+        });
+        self.clients.lock().unwrap().insert(id, stats.clone());
+        ClientHandle { registry: self.clone(), stats }
+    }
+
+    /// The connected clients, oldest first.
+    pub fn list(&self) -> Vec<Arc<ClientStats>> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    /// How many times the viewer with this persistent id has connected.
+    pub fn visits(&self, client_id: &str) -> u64 {
+        self.visits.lock().unwrap().get(client_id).copied().unwrap_or(0)
+    }
+
+    fn visit(&self, client_id: &str) {
+        let mut visits = self.visits.lock().unwrap();
+        if visits.len() >= MAX_REMEMBERED && !visits.contains_key(client_id) {
+            let clients = self.clients.lock().unwrap();
+            visits.retain(|id, _| clients.values().any(|c| c.client_id.as_ref() == Some(id)));
+        }
+        *visits.entry(client_id.to_string()).or_default() += 1;
+    }
+}
+
+/// A new random persistent client id: 16 hex characters.
+pub fn new_client_id() -> String {
+    // Each RandomState is seeded from the OS's random source.
+    format!("{:016x}", RandomState::new().hash_one(0u8))
+}
+
+/// Whether `id` could have come from [`new_client_id`] or a client choosing
+/// its own: 1 to 64 letters, digits, `-` or `_`.
+pub fn valid_client_id(id: &str) -> bool {
+    (1..=64).contains(&id.len())
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// One client's counters.
+pub struct ClientStats {
+    pub id: u64,
+    pub kind: &'static str,
+    pub source: String,
+    /// The label of the client's token, if it sent one.
+    pub label: Option<String>,
+    /// The viewer's persistent id, with `--client-ids`.
+    pub client_id: Option<String>,
+    /// The address the connection came from; a proxy's, behind one.
+    pub peer: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub connected: Instant,
+    pub frames: AtomicU64,
+    pub bytes: AtomicU64,
+    /// How often the client fell so far behind that frames were skipped.
+    pub lags: AtomicU64,
+    /// The frames skipped.
+    pub dropped: AtomicU64,
+    throughput: Mutex<Throughput>,
+    adaptive_fps: Mutex<Option<f64>>,
+}
+
+impl ClientStats {
+    /// Throughput in kbit/s, and the share of the time spent waiting for writes
+    /// to complete, over the last couple of seconds. `None` until measured.
+    pub fn throughput(&self) -> (Option<f64>, Option<f64>) {
+        self.throughput.lock().unwrap().rates(Instant::now())
+    }
+
+    /// The rate an adaptive client is held to, if it is.
+    pub fn adaptive_fps(&self) -> Option<f64> {
+        *self.adaptive_fps.lock().unwrap()
+    }
+}
+
+/// A registered client; deregisters it when dropped.
+pub struct ClientHandle {
+    registry: Arc<ClientRegistry>,
+    stats: Arc<ClientStats>,
+}
+
+impl ClientHandle {
+    /// A frame of `bytes` whose write completed after `took`. Writes complete
+    /// as fast as the link drains the socket's buffers, so this paces the
+    /// estimate by what the client really takes.
+    pub fn sent(&self, bytes: usize, took: Duration) {
+        self.stats.frames.fetch_add(1, Ordering::Relaxed);
+        self.stats.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let mut throughput = self.stats.throughput.lock().unwrap();
+        throughput.record(Instant::now(), bytes as u64, took);
+    }
+
+    /// The client fell behind and `frames` were skipped.
+    pub fn lagged(&self, frames: u64) {
+        self.stats.lags.fetch_add(1, Ordering::Relaxed);
+        self.stats.dropped.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    pub fn set_adaptive_fps(&self, fps: Option<f64>) {
+        *self.stats.adaptive_fps.lock().unwrap() = fps;
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.registry.clients.lock().unwrap().remove(&self.stats.id);
+    }
+}
+
+/// Completed writes over consecutive windows.
+struct Throughput {
+    start: Instant,
+    bytes: u64,
+    busy: Duration,
+    /// kbit/s and busy share of the last full window.
+    last: Option<(f64, f64)>,
+}
+
+impl Throughput {
+    fn new(now: Instant) -> Self {
+        Self { start: now, bytes: 0, busy: Duration::ZERO, last: None }
+    }
+
+    fn record(&mut self, now: Instant, bytes: u64, took: Duration) {
+        if now - self.start >= WINDOW {
+            self.last = Some(self.window(now));
+            *self = Self { last: self.last, ..Self::new(now) };
+        }
+        self.bytes += bytes;
+        self.busy += took;
+    }
+
+    fn window(&self, now: Instant) -> (f64, f64) {
+        let secs = (now - self.start).as_secs_f64();
+        let busy = (self.busy.as_secs_f64() / secs).min(1.0);
+        (self.bytes as f64 * 8.0 / 1000.0 / secs, busy)
+    }
+
+    /// A window running over without any writes completing (a stalled link)
+    /// counts as it stands rather than showing the last full one.
+    fn rates(&self, now: Instant) -> (Option<f64>, Option<f64>) {
+        let current = (now - self.start >= WINDOW).then(|| self.window(now));
+        match current.or(self.last) {
+            Some((kbps, busy)) => (Some(kbps), Some(busy)),
+            None => (None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throughput_follows_completed_writes() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(start);
+        assert_eq!(throughput.rates(start), (None, None));
+
+        // 25 frames of 5 kB a second, each taking 10 ms to write.
+        for i in 0..50 {
+            let at = start + Duration::from_millis(40 * i);
+            throughput.record(at, 5_000, Duration::from_millis(10));
+        }
+        throughput.record(start + Duration::from_millis(2_000), 5_000, Duration::ZERO);
+        let (kbps, busy) = throughput.rates(start + Duration::from_millis(2_050));
+        assert!(kbps.is_some_and(|k| (995.0..1005.0).contains(&k)), "{kbps:?}");
+        assert!(busy.is_some_and(|b| (0.24..0.26).contains(&b)), "{busy:?}");
+
+        // Nothing completes for a while: the stall shows.
+        let (kbps, _) = throughput.rates(start + Duration::from_secs(10));
+        assert!(kbps.is_some_and(|k| k < 10.0), "{kbps:?}");
+    }
+
+    #[test]
+    fn handles_deregister_when_dropped() {
+        let registry = ClientRegistry::new();
+        let peer = Some(IpAddr::from([10, 0, 0, 7]));
+        let agent = Some("OBS".to_string());
+        let first = registry.register("websocket", "cam", None, None, peer, agent);
+        let label = Some("Agency".to_string());
+        let second = registry.register("mjpeg", "cam", label, None, None, None);
+        first.sent(1_000, Duration::ZERO);
+        first.lagged(3);
+        first.lagged(2);
+        let ids: Vec<u64> = registry.list().iter().map(|c| c.id).collect();
+        assert_eq!(ids, [1, 2]);
+        let listed = &registry.list()[0];
+        assert_eq!(listed.bytes.load(Ordering::Relaxed), 1_000);
+        assert_eq!((listed.peer, listed.user_agent.as_deref()), (peer, Some("OBS")));
+        let lags = (listed.lags.load(Ordering::Relaxed), listed.dropped.load(Ordering::Relaxed));
+        assert_eq!(lags, (2, 5));
+        drop(first);
+        assert_eq!(registry.list().len(), 1);
+        assert_eq!(registry.list()[0].label.as_deref(), Some("Agency"));
+        drop(second);
+        assert!(registry.list().is_empty());
+    }
+
+    #[test]
+    fn reconnections_count_as_visits_of_one_viewer() {
+        let registry = ClientRegistry::new();
+        let viewer = Some("a1b2".to_string());
+        drop(registry.register("websocket", "cam", None, viewer.clone(), None, None));
+        let again = registry.register("websocket", "cam", None, viewer, None, None);
+        let other = Some("c3d4".to_string());
+        let _other = registry.register("mjpeg", "cam", None, other, None, None);
+        assert_eq!(again.stats.client_id.as_deref(), Some("a1b2"));
+        assert_eq!(registry.visits("a1b2"), 2);
+        assert_eq!(registry.visits("c3d4"), 1);
+        assert_eq!(registry.visits("e5f6"), 0);
+
+        assert!(valid_client_id(&new_client_id()));
+        assert!(!valid_client_id(""));
+        assert!(!valid_client_id("a;b"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Clock sources: the server's wall clock, in UTC or the source's time zone, its
+//! NTP sync status and a fixed caption, generated at a chosen size and rate.
+//! Shown next to a camera pointed at a reference clock, the difference between
+//! the two is the end-to-end latency.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use crate::timestamps::TimeFormat;
+use crate::watermark::glyph;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A `[clock."NAME"]` entry.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    #[serde(default = "default_width")]
+    pub width: usize,
+    #[serde(default = "default_height")]
+    pub height: usize,
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+    /// Shown under the clock, uppercased.
+    #[serde(default)]
+    pub text: String,
+}
+
+fn default_width() -> usize {
+    1280
+}
+
+fn default_height() -> usize {
+    720
+}
+
+fn default_fps() -> u32 {
+    25
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            width: default_width(),
+            height: default_height(),
+            fps: default_fps(),
+            text: String::new(),
+        }
+    }
+}
+
+impl ClockConfig {
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if !(64..=7680).contains(&self.width) || !self.width.is_multiple_of(2) {
+            return Err(format!("clock \"{name}\": width must be even, 64 to 7680"));
+        }
+        if !(64..=4320).contains(&self.height) {
+            return Err(format!("clock \"{name}\": height must be 64 to 4320"));
+        }
+        if !(1..=120).contains(&self.fps) {
+            return Err(format!("clock \"{name}\": fps must be 1 to 120"));
+        }
+        Ok(())
+    }
+}
+
+/// Whether the kernel considers the system clock synchronized, e.g. by NTP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtpStatus {
+    Synced,
+    Unsynced,
+    Unknown,
+}
+
+impl NtpStatus {
+    #[cfg(target_os = "linux")]
+    pub fn query() -> Self {
+        // SAFETY: with `modes` zeroed, adjtimex only reads the clock state into `timex`.
+        let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+        match unsafe { libc::adjtimex(&mut timex) } {
+            -1 => Self::Unknown,
+            libc::TIME_ERROR => Self::Unsynced,
+            _ if timex.status & libc::STA_UNSYNC != 0 => Self::Unsynced,
+            _ => Self::Synced,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn query() -> Self {
+        Self::Unknown
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Synced => "NTP SYNCED",
+            Self::Unsynced => "NTP NOT SYNCED",
+            Self::Unknown => "NTP UNKNOWN",
+        }
+    }
+}
+
+/// Luma of the text and sweep marker; the background is black.
+const WHITE: u8 = 235;
+const BLACK: u8 = 16;
+
+/// Draws clock frames in UYVY into a reused buffer.
+pub struct ClockRenderer {
+    width: usize,
+    height: usize,
+    text: String,
+    times: TimeFormat,
+    frame: Vec<u8>,
+}
+
+impl ClockRenderer {
+    pub fn new(config: &ClockConfig) -> Self {
+        Self {
+            width: config.width,
+            height: config.height,
+            text: config.text.to_uppercase(),
+            times: TimeFormat::default(),
+            frame: vec![0; config.width * config.height * 2],
+        }
+    }
+
+    /// Show the time in this time zone, and the date in this format.
+    pub fn time_format(mut self, times: TimeFormat) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// The frame for `now`: time of day to the millisecond, date, NTP status and
+    /// caption, plus a marker sweeping along the bottom edge once a second.
+    pub fn render(&mut self, now: SystemTime, ntp: NtpStatus) -> VideoFrame<'_> {
+        let (w, h) = (self.width, self.height);
+        for pixel in self.frame.chunks_exact_mut(2) {
+            pixel.copy_from_slice(&[128, BLACK]);
+        }
+
+        let millis = now.duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis() as usize;
+        let zoned = self.times.zoned(now);
+        let time = format!(
+            "{:02}:{:02}:{:02}.{millis:03}",
+            zoned.hour(),
+            zoned.minute(),
+            zoned.second()
+        );
+        let date = self.times.format(now, "%Y-%m-%d %Z").to_uppercase();
+
+        // The time fills most of the width; the other lines are a third of its size.
+        let big = (w * 9 / 10 / (time.len() * 6)).min(h / 3 / 7).max(1);
+        let small = (big / 3).max(1);
+        let mut lines = vec![(time.as_str(), big), (date.as_str(), small), (ntp.label(), small)];
+        if !self.text.is_empty() {
+            lines.push((self.text.as_str(), small));
+        }
+        let total: usize = lines.iter().map(|(_, scale)| 10 * scale).sum();
+        let mut y = h.saturating_sub(total) / 2;
+        for (text, scale) in lines {
+            let text_w = (text.chars().count() * 6).saturating_sub(1) * scale;
+            draw_text(&mut self.frame, w, h, w.saturating_sub(text_w) / 2, y, scale, text);
+            y += 10 * scale;
+        }
+
+        let marker_w = (w / 20).max(1);
+        let marker_h = (h / 30).max(1);
+        let x0 = millis * (w - marker_w) / 1000;
+        for y in h - marker_h..h {
+            fill_row(&mut self.frame, w, h, y, x0, marker_w);
+        }
+
+        VideoFrame {
+            data: &self.frame,
+            width: w,
+            height: h,
+            stride: w * 2,
+            fourcc: FourCCVideoType::UYVY,
+        }
+    }
+}
+
+fn draw_text(frame: &mut [u8], w: usize, h: usize, x0: usize, y0: usize, scale: usize, text: &str) {
+    for (i, c) in text.chars().enumerate() {
+        for (gy, bits) in glyph(c).iter().enumerate() {
+            for gx in (0..5).filter(|gx| bits & (0x10 >> gx) != 0) {
+                for dy in 0..scale {
+                    let (x, y) = (x0 + (i * 6 + gx) * scale, y0 + gy * scale + dy);
+                    fill_row(frame, w, h, y, x, scale);
+                }
+            }
+        }
+    }
+}
+
+/// Set `len` pixels of row `y` from `x0` to white, clipped to the frame.
+fn fill_row(frame: &mut [u8], w: usize, h: usize, y: usize, x0: usize, len: usize) {
+    if y >= h {
+        return;
+    }
+    let row = &mut frame[y * w * 2..(y + 1) * w * 2];
+    for x in x0..(x0 + len).min(w) {
+        row[x * 2 + 1] = WHITE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn white_pixels(frame: &VideoFrame, rows: std::ops::Range<usize>) -> Vec<usize> {
+        let mut xs = Vec::new();
+        for y in rows {
+            for x in 0..frame.width {
+                if frame.data[y * frame.stride + x * 2 + 1] == WHITE {
+                    xs.push(x);
+                }
+            }
+        }
+        xs
+    }
+
+    #[test]
+    fn draws_time_and_sweeps_with_milliseconds() {
+        let config: ClockConfig = toml::from_str("width = 320\nheight = 180").unwrap();
+        config.validate("clock").unwrap();
+        let mut renderer = ClockRenderer::new(&config);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let frame = renderer.render(start, NtpStatus::Synced);
+        assert_eq!((frame.width, frame.height, frame.data.len()), (320, 180, 320 * 180 * 2));
+        assert!(white_pixels(&frame, 0..170).len() > 1000, "no text drawn");
+        let marker = white_pixels(&frame, 179..180);
+        assert_eq!(marker.first(), Some(&0));
+
+        let later = renderer.render(start + Duration::from_millis(500), NtpStatus::Synced);
+        assert_eq!(white_pixels(&later, 179..180).first(), Some(&152));
+    }
+
+    #[test]
+    fn rejects_odd_widths() {
+        let config: ClockConfig = toml::from_str("width = 321").unwrap();
+        assert!(config.validate("clock").is_err());
+    }
+}
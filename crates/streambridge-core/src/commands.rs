@@ -0,0 +1,493 @@
+use crate::auth::Action;
+use crate::config::Tuning;
+use crate::discovery::SourceList;
+use crate::ndi::{NdiError, Router, Source, Tally};
+use crate::receiver::{Control, PtzCommand, PtzRequest, ReceiverManager, SharedReceiver};
+use crate::recording::{self, Format, Recorder, RecordingFile, Rotation, MAX_RECORD_FPS};
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::info;
+
+/// How many idempotency keys are remembered before the oldest are forgotten.
+const RECENT_KEYS: usize = 1024;
+
+/// How long a PTZ command waits for the camera.
+const PTZ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A `POST /api/commands` body, e.g.
+/// `{"command": "set_quality", "source": "CAM (1)", "jpeg_quality": 50}`.
+#[derive(Debug, Deserialize)]
+pub struct CommandRequest {
+    /// Alternative to the `Idempotency-Key` header, for systems that can't set headers.
+    pub idempotency_key: Option<String>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Point the `--program-output` NDI source at `source`.
+    SwitchProgram { source: String },
+    /// Record `source` in `--record-dir`: to a raw file, replayable with
+    /// `--replay`, unless `format` says `avi`. AVI recordings can be written
+    /// at a fixed `fps` instead of `--record-fps`.
+    StartRecord { source: String, format: Option<Format>, fps: Option<u32> },
+    StopRecord { source: String },
+    /// Change `source`'s JPEG quality without reconnecting.
+    SetQuality { source: String, jpeg_quality: i32 },
+    /// Move `source`'s PTZ camera to a stored preset.
+    RecallPtzPreset {
+        source: String,
+        preset: u32,
+        /// 0.0 (slowest) to 1.0 (fastest).
+        #[serde(default = "full_speed")]
+        speed: f32,
+    },
+}
+
+fn full_speed() -> f32 {
+    1.0
+}
+
+impl Command {
+    pub fn source(&self) -> &str {
+        match self {
+            Command::SwitchProgram { source }
+            | Command::StartRecord { source, .. }
+            | Command::StopRecord { source }
+            | Command::SetQuality { source, .. }
+            | Command::RecallPtzPreset { source, .. } => source,
+        }
+    }
+
+    pub fn source_mut(&mut self) -> &mut String {
+        match self {
+            Command::SwitchProgram { source }
+            | Command::StartRecord { source, .. }
+            | Command::StopRecord { source }
+            | Command::SetQuality { source, .. }
+            | Command::RecallPtzPreset { source, .. } => source,
+        }
+    }
+
+    /// What a token needs to be allowed to do with the source to run this command.
+    pub fn action(&self) -> Action {
+        match self {
+            Command::SwitchProgram { .. } | Command::SetQuality { .. } => Action::Control,
+            Command::StartRecord { .. } | Command::StopRecord { .. } => Action::Record,
+            Command::RecallPtzPreset { .. } => Action::Ptz,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::SwitchProgram { .. } => "switch_program",
+            Command::StartRecord { .. } => "start_record",
+            Command::StopRecord { .. } => "stop_record",
+            Command::SetQuality { .. } => "set_quality",
+            Command::RecallPtzPreset { .. } => "recall_ptz_preset",
+        }
+    }
+}
+
+/// A command's HTTP status and JSON body, kept for replays of its idempotency key.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl Outcome {
+    fn ok(command: &Command, mut extra: Value) -> Self {
+        extra["ok"] = json!(true);
+        extra["command"] = json!(command.name());
+        Self { status: StatusCode::OK, body: extra.to_string() }
+    }
+
+    /// A plain success, for endpoints that report nothing else.
+    pub fn done() -> Self {
+        Self { status: StatusCode::OK, body: json!({ "ok": true }).to_string() }
+    }
+
+    pub fn error(status: StatusCode, message: &str) -> Self {
+        Self { status, body: json!({ "ok": false, "error": message }).to_string() }
+    }
+}
+
+/// What [`CommandRunner::begin`] found for an idempotency key.
+pub enum Attempt {
+    /// First use of the key: run the command, then call [`CommandRunner::finish`].
+    New,
+    /// The key's command is still running.
+    InProgress,
+    /// The key was used before; send this again instead of re-running.
+    Replay(Outcome),
+}
+
+/// Recently used idempotency keys and their outcomes; `None` while running.
+#[derive(Default)]
+struct Recent {
+    order: VecDeque<String>,
+    outcomes: HashMap<String, Option<Outcome>>,
+}
+
+/// Runs `POST /api/commands` on behalf of external automation.
+pub struct CommandRunner {
+    sources: SourceList,
+    manager: Arc<ReceiverManager>,
+    /// The `--program-output` routed NDI source.
+    program: Option<Router>,
+    record_dir: PathBuf,
+    rotation: Rotation,
+    /// The frame rate AVI recordings are written at, if fixed.
+    record_fps: Option<u32>,
+    /// Whether AVI recordings take the JPEGs sent to viewers.
+    record_shared: bool,
+    recent: Mutex<Recent>,
+}
+
+impl CommandRunner {
+    pub fn new(sources: SourceList, manager: Arc<ReceiverManager>) -> Self {
+        Self {
+            sources,
+            manager,
+            program: None,
+            record_dir: PathBuf::from("recordings"),
+            rotation: Rotation::default(),
+            record_fps: None,
+            record_shared: false,
+            recent: Mutex::new(Recent::default()),
+        }
+    }
+
+    pub fn program_output(mut self, router: Router) -> Self {
+        self.program = Some(router);
+        self
+    }
+
+    pub fn record_dir(mut self, dir: PathBuf) -> Self {
+        self.record_dir = dir;
+        self
+    }
+
+    /// When recordings move on to a new file.
+    pub fn record_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Write AVI recordings at exactly `fps` frames per second by default.
+    pub fn record_fps(mut self, fps: Option<u32>) -> Self {
+        self.record_fps = fps;
+        self
+    }
+
+    /// Record the JPEGs sent to viewers instead of encoding every frame.
+    pub fn record_shared(mut self, shared: bool) -> Self {
+        self.record_shared = shared;
+        self
+    }
+
+    /// Claim `key` for a new command, or find out what happened to it before.
+    pub fn begin(&self, key: &str) -> Attempt {
+        let mut recent = self.recent.lock().unwrap();
+        match recent.outcomes.get(key) {
+            Some(Some(outcome)) => return Attempt::Replay(outcome.clone()),
+            Some(None) => return Attempt::InProgress,
+            None => {}
+        }
+        if recent.order.len() >= RECENT_KEYS {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.outcomes.remove(&oldest);
+            }
+        }
+        recent.order.push_back(key.to_string());
+        recent.outcomes.insert(key.to_string(), None);
+        Attempt::New
+    }
+
+    /// Remember `outcome` for `key`. Server errors are forgotten instead, so the
+    /// caller can retry with the same key.
+    pub fn finish(&self, key: &str, outcome: &Outcome) {
+        let mut recent = self.recent.lock().unwrap();
+        if outcome.status.is_server_error() {
+            recent.outcomes.remove(key);
+            recent.order.retain(|k| k != key);
+        } else if let Some(slot) = recent.outcomes.get_mut(key) {
+            *slot = Some(outcome.clone());
+        }
+    }
+
+    pub async fn execute(&self, command: &Command) -> Outcome {
+        let Some(source) = self.find(command.source()) else {
+            return Outcome::error(StatusCode::NOT_FOUND, "source not found");
+        };
+        let result = match command {
+            Command::SwitchProgram { .. } => self.switch_program(&source),
+            Command::StartRecord { format, fps, .. } => {
+                self.start_record(&source, format.unwrap_or(Format::Raw), *fps)
+            }
+            Command::StopRecord { .. } => self.stop_record(&source).await,
+            Command::SetQuality { jpeg_quality, .. } => {
+                if !(1..=100).contains(jpeg_quality) {
+                    return Outcome::error(
+                        StatusCode::BAD_REQUEST,
+                        "jpeg_quality must be between 1 and 100",
+                    );
+                }
+                self.manager.set_quality(&source.name, *jpeg_quality);
+                Ok(json!({ "jpeg_quality": jpeg_quality }))
+            }
+            Command::RecallPtzPreset { preset, speed, .. } => {
+                if *preset > 99 {
+                    let message = "preset must be between 0 and 99";
+                    return Outcome::error(StatusCode::BAD_REQUEST, message);
+                }
+                let command = PtzCommand::RecallPreset { preset: *preset, speed: *speed };
+                self.send_ptz(&source, command).await.map(|()| json!({ "preset": preset }))
+            }
+        };
+        match result {
+            Ok(extra) => {
+                info!("[{}] command {} done", source.name, command.name());
+                Outcome::ok(command, extra)
+            }
+            Err((status, message)) => Outcome::error(status, &message),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<Source> {
+        self.sources.read().unwrap().iter().find(|s| s.name == name).cloned()
+    }
+
+    fn switch_program(&self, source: &Source) -> Result<Value, (StatusCode, String)> {
+        let Some(router) = &self.program else {
+            return Err((StatusCode::CONFLICT, "no --program-output configured".to_string()));
+        };
+        if !router.change(source) {
+            let message = "NDI routing change failed".to_string();
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, message));
+        }
+        Ok(json!({ "program": source.name }))
+    }
+
+    /// Start recording `source_name` as `format`, at `fps` if given, for
+    /// `POST /record/<source>/start`.
+    pub fn start_recording(
+        &self,
+        source_name: &str,
+        format: Format,
+        fps: Option<u32>,
+    ) -> Result<Value, (StatusCode, String)> {
+        let Some(source) = self.find(source_name) else {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        };
+        self.start_record(&source, format, fps)
+    }
+
+    /// Stop recording `source_name`, for `POST /record/<source>/stop`.
+    pub async fn stop_recording(&self, source_name: &str) -> Result<Value, (StatusCode, String)> {
+        let Some(source) = self.find(source_name) else {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        };
+        self.stop_record(&source).await
+    }
+
+    /// The recordings in `--record-dir`, by name.
+    pub fn recordings(&self) -> io::Result<Vec<RecordingFile>> {
+        recording::list(&self.record_dir)
+    }
+
+    /// The recording called `name`, if there is one.
+    pub fn recording(&self, name: &str) -> Option<(PathBuf, Format)> {
+        recording::find(&self.record_dir, name)
+    }
+
+    fn start_record(
+        &self,
+        source: &Source,
+        format: Format,
+        fps: Option<u32>,
+    ) -> Result<Value, (StatusCode, String)> {
+        if let Some(fps) = fps {
+            if format == Format::Raw {
+                let message = "fps only applies to avi recordings".to_string();
+                return Err((StatusCode::BAD_REQUEST, message));
+            }
+            if !(1..=MAX_RECORD_FPS).contains(&fps) {
+                let message = format!("fps must be between 1 and {MAX_RECORD_FPS}");
+                return Err((StatusCode::BAD_REQUEST, message));
+            }
+        }
+        let fps = fps.or(self.record_fps);
+        let unavailable = |e: String| (StatusCode::SERVICE_UNAVAILABLE, e);
+        let shared = self.manager.get_or_create(source).map_err(unavailable)?;
+        // Hold the receiver open until the recording flag keeps it running.
+        let hold = Hold::new(&self.manager, shared.clone());
+        if shared.is_recording() {
+            return Err((StatusCode::CONFLICT, "already recording".to_string()));
+        }
+        let cannot_record = |e: io::Error| {
+            let message = format!("cannot record in {}: {e}", self.record_dir.display());
+            (StatusCode::INTERNAL_SERVER_ERROR, message)
+        };
+        let times = self.manager.time_format(&source.name);
+        let dir = &self.record_dir;
+        let mut recorder = Recorder::create(dir, &source.name, format, self.rotation, times)
+            .map_err(cannot_record)?
+            .fps(fps)
+            .shared(self.record_shared);
+        // AVI recordings start with what led up to them.
+        let preroll = self.manager.preroll(&source.name).filter(|_| recorder.wants_jpeg());
+        if let Some(preroll) = preroll {
+            for (at, jpeg) in preroll.frames() {
+                recorder.jpeg(jpeg, at).map_err(cannot_record)?;
+            }
+        }
+        let path = recorder.path().to_path_buf();
+        shared
+            .start_recording(recorder)
+            .map_err(|e| (StatusCode::CONFLICT, e))?;
+        drop(hold);
+        info!("[{}] recording to {}", source.name, path.display());
+        let fps = fps.filter(|_| format == Format::Avi);
+        Ok(json!({ "path": path, "format": format, "fps": fps }))
+    }
+
+    async fn stop_record(&self, source: &Source) -> Result<Value, (StatusCode, String)> {
+        let Some(shared) = self.manager.get(&source.name) else {
+            return Err((StatusCode::CONFLICT, "not recording".to_string()));
+        };
+        let result = shared.stop_recording().await;
+        self.manager.maybe_remove(&source.name);
+        match result {
+            Ok(files) => Ok(json!({ "files": files })),
+            Err(e) if e == "not recording" => Err((StatusCode::CONFLICT, e)),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+        }
+    }
+
+    /// Send `command` to `source_name`'s PTZ camera and wait for it to be taken.
+    pub async fn ptz(
+        &self,
+        source_name: &str,
+        command: PtzCommand,
+    ) -> Result<(), (StatusCode, String)> {
+        let Some(source) = self.find(source_name) else {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        };
+        self.send_ptz(&source, command).await
+    }
+
+    /// Signal on-air state to `source_name`'s sender. See [`ReceiverManager::set_tally`].
+    pub fn tally(&self, source_name: &str, tally: Tally) -> Result<(), (StatusCode, String)> {
+        let Some(source) = self.find(source_name) else {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        };
+        if self.manager.is_virtual(source_name) {
+            return Err((StatusCode::CONFLICT, "not an NDI source".to_string()));
+        }
+        self.manager.set_tally(&source, tally).map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e))
+    }
+
+    /// Change a source's settings at runtime; see [`ReceiverManager::tune`].
+    pub fn tune(&self, source_name: &str, tuning: Tuning) -> Result<Value, (StatusCode, String)> {
+        if self.find(source_name).is_none() {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        }
+        tuning.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if tuning.low_bandwidth.is_some() && self.manager.is_virtual(source_name) {
+            return Err((StatusCode::CONFLICT, "not an NDI source".to_string()));
+        }
+        let settings = self.manager.tune(source_name, tuning);
+        info!("[{}] settings changed: {:?}", source_name, tuning);
+        Ok(json!({
+            "ok": true,
+            "source": source_name,
+            "active": self.manager.get(source_name).is_some(),
+            "jpeg_quality": settings.jpeg_quality,
+            "max_fps": settings.max_fps,
+            "low_bandwidth": settings.low_bandwidth,
+        }))
+    }
+
+    /// Reconnect `source_name`'s running receivers to its sender; see
+    /// [`ReceiverManager::restart`].
+    pub fn restart_receiver(&self, source_name: &str) -> Result<Value, (StatusCode, String)> {
+        if self.find(source_name).is_none() {
+            return Err((StatusCode::NOT_FOUND, "source not found".to_string()));
+        }
+        if self.manager.is_virtual(source_name) {
+            return Err((StatusCode::CONFLICT, "not an NDI source".to_string()));
+        }
+        match self.manager.restart(source_name) {
+            0 => Err((StatusCode::CONFLICT, "not running".to_string())),
+            receivers => Ok(json!({ "ok": true, "source": source_name, "receivers": receivers })),
+        }
+    }
+
+    /// Tear down `source_name`'s running receivers, dropping their clients; see
+    /// [`ReceiverManager::close`]. Works on sources discovery has lost too.
+    pub fn close_receiver(&self, source_name: &str) -> Result<Value, (StatusCode, String)> {
+        match self.manager.close(source_name) {
+            0 if self.find(source_name).is_none() => {
+                Err((StatusCode::NOT_FOUND, "source not found".to_string()))
+            }
+            0 => Err((StatusCode::CONFLICT, "not running".to_string())),
+            receivers => Ok(json!({ "ok": true, "source": source_name, "receivers": receivers })),
+        }
+    }
+
+    async fn send_ptz(
+        &self,
+        source: &Source,
+        command: PtzCommand,
+    ) -> Result<(), (StatusCode, String)> {
+        if self.manager.capabilities().is_some_and(|c| !c.ptz) {
+            let error = NdiError::Unsupported("PTZ").to_string();
+            return Err((StatusCode::NOT_IMPLEMENTED, error));
+        }
+        let unavailable = |e: String| (StatusCode::SERVICE_UNAVAILABLE, e);
+        let shared = self.manager.get_or_create(source).map_err(unavailable)?;
+        let _hold = Hold::new(&self.manager, shared.clone());
+        let (reply, done) = oneshot::channel();
+        if !shared.send(Control::Ptz(PtzRequest { command, reply })) {
+            return Err(unavailable("source lost".to_string()));
+        }
+        match tokio::time::timeout(PTZ_TIMEOUT, done).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(e))) => Err((StatusCode::CONFLICT, e)),
+            Ok(Err(_)) => Err(unavailable("source lost".to_string())),
+            Err(_) => Err((StatusCode::GATEWAY_TIMEOUT, "camera did not respond".to_string())),
+        }
+    }
+}
+
+/// Counts as a client of a receiver so it keeps running while a command uses it.
+struct Hold<'a> {
+    manager: &'a ReceiverManager,
+    shared: Arc<SharedReceiver>,
+    _rx: crate::latest::Receiver<crate::receiver::JpegFrame>,
+}
+
+impl<'a> Hold<'a> {
+    fn new(manager: &'a ReceiverManager, shared: Arc<SharedReceiver>) -> Self {
+        let rx = shared.subscribe();
+        Self { manager, shared, _rx: rx }
+    }
+}
+
+impl Drop for Hold<'_> {
+    fn drop(&mut self) {
+        self.shared.unsubscribe();
+        self.manager.maybe_remove(&self.shared.source_name);
+    }
+}
@@ -0,0 +1,675 @@
+//! Composite sources: frames built from other sources' streams. The inputs are
+//! decoded from the JPEGs their receivers already produce, combined, and handed
+//! to a regular [`Pipeline`](crate::pipeline::Pipeline) to be encoded once for
+//! every viewer of the composite.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use serde::{Deserialize, Serialize};
+
+/// A `[pip."NAME"]` entry: `inset` scaled into a corner of `main`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipConfig {
+    pub main: String,
+    pub inset: String,
+    #[serde(default)]
+    pub corner: Corner,
+    /// Inset width as a fraction of the main frame's width.
+    #[serde(default = "default_size")]
+    pub size: f32,
+    /// Distance from the frame edges, in pixels.
+    #[serde(default = "default_margin")]
+    pub margin: usize,
+    /// Border width around the inset, in pixels; 0 for none.
+    #[serde(default = "default_border")]
+    pub border: usize,
+    /// `#RRGGBB`.
+    #[serde(default = "default_border_color")]
+    pub border_color: String,
+}
+
+fn default_size() -> f32 {
+    0.25
+}
+
+fn default_margin() -> usize {
+    16
+}
+
+fn default_border() -> usize {
+    2
+}
+
+fn default_border_color() -> String {
+    "#FFFFFF".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl PipConfig {
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.main == name || self.inset == name {
+            return Err(format!("picture-in-picture \"{name}\" can't contain itself"));
+        }
+        if !(self.size > 0.0 && self.size <= 1.0) {
+            return Err(format!("pip size must be above 0 and at most 1, got {}", self.size));
+        }
+        parse_color(&self.border_color)?;
+        Ok(())
+    }
+}
+
+fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.strip_prefix('#').filter(|h| h.len() == 6 && h.is_ascii());
+    let channel = |i: usize| hex.and_then(|h| u8::from_str_radix(&h[i..i + 2], 16).ok());
+    match (channel(0), channel(2), channel(4)) {
+        (Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("expected a #RRGGBB color, got \"{s}\"")),
+    }
+}
+
+/// A `[compare."NAME"]` entry: a wipe between `left` and `right`, with `left`
+/// shown up to the split and `right` after it, for A/B checks of encoder
+/// settings or camera matching. Viewers can move the split at runtime.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompareConfig {
+    pub left: String,
+    pub right: String,
+    /// Where the split starts, as a fraction of the frame width.
+    #[serde(default = "default_split")]
+    pub split: f32,
+    /// Width of the line drawn at the split, in pixels; 0 for none.
+    #[serde(default = "default_border")]
+    pub divider: usize,
+    /// `#RRGGBB`.
+    #[serde(default = "default_border_color")]
+    pub divider_color: String,
+}
+
+fn default_split() -> f32 {
+    0.5
+}
+
+impl CompareConfig {
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.left == name || self.right == name {
+            return Err(format!("comparison \"{name}\" can't contain itself"));
+        }
+        if !(0.0..=1.0).contains(&self.split) {
+            return Err(format!("compare split must be between 0 and 1, got {}", self.split));
+        }
+        parse_color(&self.divider_color)?;
+        Ok(())
+    }
+}
+
+/// A `[crop."NAME"]` entry: the region of `source` starting at (`x`, `y`),
+/// `width` by `height`, all as fractions of its frame, e.g. one speaker out of
+/// a wide shot. Its own `[sources."NAME"]` settings then scale and overlay it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CropConfig {
+    pub source: String,
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    #[serde(default = "default_extent")]
+    pub width: f32,
+    #[serde(default = "default_extent")]
+    pub height: f32,
+}
+
+fn default_extent() -> f32 {
+    1.0
+}
+
+impl CropConfig {
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.source == name {
+            return Err(format!("crop \"{name}\" can't contain itself"));
+        }
+        let fits = |start: f32, extent: f32| {
+            start >= 0.0 && extent > 0.0 && start + extent <= 1.0 + f32::EPSILON
+        };
+        if !fits(self.x, self.width) || !fits(self.y, self.height) {
+            return Err(format!("crop \"{name}\" must lie within its source's frame"));
+        }
+        Ok(())
+    }
+
+    /// The region of a `w` x `h` frame it keeps, at least one pixel.
+    fn rect(&self, w: usize, h: usize) -> Rect {
+        let x = ((w as f32 * self.x) as usize).min(w.saturating_sub(1));
+        let y = ((h as f32 * self.y) as usize).min(h.saturating_sub(1));
+        let width = ((w as f32 * self.width).round() as usize).clamp(1, w - x);
+        let height = ((h as f32 * self.height).round() as usize).clamp(1, h - y);
+        Rect { x, y, width, height }
+    }
+}
+
+/// What a composite source draws.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Pip(PipConfig),
+    Compare(CompareConfig),
+}
+
+impl Layout {
+    /// The input whose frames set the composite's size and pace, and the other one.
+    pub fn inputs(&self) -> (&str, &str) {
+        match self {
+            Layout::Pip(pip) => (&pip.main, &pip.inset),
+            Layout::Compare(compare) => (&compare.left, &compare.right),
+        }
+    }
+
+    fn color(&self) -> &str {
+        match self {
+            Layout::Pip(pip) => &pip.border_color,
+            Layout::Compare(compare) => &compare.divider_color,
+        }
+    }
+}
+
+/// The size of a mosaic's frames.
+const MOSAIC_WIDTH: usize = 1920;
+const MOSAIC_HEIGHT: usize = 1080;
+
+/// Space between a mosaic's tiles, in pixels.
+const MOSAIC_GAP: usize = 4;
+
+/// The most tiles a mosaic may have.
+pub const MAX_TILES: usize = 16;
+
+/// A `GET /mosaic` grid: `sources` tiled left to right and top to bottom into
+/// `columns` x `rows` cells of one frame, for a multiviewer in a single stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MosaicConfig {
+    pub sources: Vec<String>,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl MosaicConfig {
+    /// `layout` is `COLUMNSxROWS`, e.g. `2x2`; without one, the squarest grid
+    /// that fits every source.
+    pub fn new(sources: Vec<String>, layout: Option<&str>) -> Result<Self, String> {
+        if sources.is_empty() || sources.iter().any(|s| s.is_empty()) {
+            return Err("a mosaic needs a list of sources".to_string());
+        }
+        let (columns, rows) = match layout {
+            Some(layout) => layout
+                .split_once('x')
+                .and_then(|(c, r)| Some((c.parse().ok()?, r.parse().ok()?)))
+                .filter(|&(c, r): &(usize, usize)| c > 0 && r > 0)
+                .ok_or_else(|| format!("expected a layout like 2x2, got \"{layout}\""))?,
+            None => {
+                let columns = (1..).find(|c| c * c >= sources.len()).unwrap_or(1);
+                (columns, sources.len().div_ceil(columns))
+            }
+        };
+        let tiles = columns.saturating_mul(rows);
+        if tiles > MAX_TILES {
+            return Err(format!("a mosaic has at most {MAX_TILES} tiles, got {columns}x{rows}"));
+        }
+        if sources.len() > tiles {
+            let count = sources.len();
+            return Err(format!("{count} sources don't fit a {columns}x{rows} mosaic"));
+        }
+        Ok(Self { sources, columns, rows })
+    }
+
+    /// The name its receiver goes by, e.g. `mosaic 2x1 ["CAM 1", "CAM 2"]`.
+    pub fn name(&self) -> String {
+        format!("mosaic {}x{} {:?}", self.columns, self.rows, self.sources)
+    }
+}
+
+/// Draws each of a mosaic's inputs into its tile of one frame.
+pub struct Mosaic {
+    columns: usize,
+    rows: usize,
+    decompressor: turbojpeg::Decompressor,
+    /// The composed RGBA frame.
+    frame: Vec<u8>,
+    /// The input frame last decoded.
+    tile: Vec<u8>,
+}
+
+impl Mosaic {
+    pub fn new(config: &MosaicConfig) -> Result<Self, String> {
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        let black = [0, 0, 0, 255];
+        Ok(Self {
+            columns: config.columns,
+            rows: config.rows,
+            decompressor,
+            frame: black.repeat(MOSAIC_WIDTH * MOSAIC_HEIGHT),
+            tile: Vec::new(),
+        })
+    }
+
+    /// Decode `jpeg` and scale it into tile `index`, keeping its aspect ratio.
+    pub fn draw(&mut self, index: usize, jpeg: &[u8]) -> Result<(), String> {
+        let (w, h) = decode_rgba(&mut self.decompressor, jpeg, &mut self.tile)?;
+        let cell = self.cell(index);
+        let width = cell.width.min(cell.height * w / h.max(1)).max(1);
+        let height = (width * h / w.max(1)).clamp(1, cell.height.max(1));
+        let rect = Rect {
+            x: cell.x + (cell.width - width) / 2,
+            y: cell.y + cell.height.saturating_sub(height) / 2,
+            width,
+            height,
+        };
+        fill(&mut self.frame, MOSAIC_WIDTH, cell, [0, 0, 0]);
+        let tile = Rgba { pixels: &self.tile, width: w, height: h };
+        draw_inset(&mut self.frame, MOSAIC_WIDTH, MOSAIC_HEIGHT, &tile, rect, 0, [0, 0, 0]);
+        Ok(())
+    }
+
+    /// Blank tile `index`, e.g. after its source went away.
+    pub fn clear(&mut self, index: usize) {
+        let cell = self.cell(index);
+        fill(&mut self.frame, MOSAIC_WIDTH, cell, [0, 0, 0]);
+    }
+
+    pub fn frame(&self) -> VideoFrame<'_> {
+        VideoFrame {
+            data: &self.frame,
+            width: MOSAIC_WIDTH,
+            height: MOSAIC_HEIGHT,
+            stride: MOSAIC_WIDTH * 4,
+            fourcc: FourCCVideoType::RGBA,
+        }
+    }
+
+    /// Where tile `index` goes, inside the gap around it.
+    fn cell(&self, index: usize) -> Rect {
+        let (column, row) = (index % self.columns, index / self.columns);
+        let x = column * MOSAIC_WIDTH / self.columns;
+        let y = row * MOSAIC_HEIGHT / self.rows;
+        let width = (column + 1) * MOSAIC_WIDTH / self.columns - x;
+        let height = (row + 1) * MOSAIC_HEIGHT / self.rows - y;
+        Rect {
+            x: x + MOSAIC_GAP / 2,
+            y: y + MOSAIC_GAP / 2,
+            width: width.saturating_sub(MOSAIC_GAP),
+            height: height.saturating_sub(MOSAIC_GAP),
+        }
+    }
+}
+
+/// Decodes a composite's inputs and draws the second over the first.
+pub struct Compositor {
+    layout: Layout,
+    color: [u8; 3],
+    decompressor: turbojpeg::Decompressor,
+    /// The composed RGBA frame.
+    frame: Vec<u8>,
+    /// The latest frame of the second input, decoded to RGBA, with its size.
+    second: Option<(Vec<u8>, usize, usize)>,
+    /// Where a comparison splits, as a fraction of the width.
+    split: f32,
+}
+
+impl Compositor {
+    pub fn new(layout: Layout) -> Result<Self, String> {
+        let color = parse_color(layout.color())?;
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        let split = match &layout {
+            Layout::Compare(compare) => compare.split,
+            Layout::Pip(_) => 0.0,
+        };
+        Ok(Self {
+            layout,
+            color,
+            decompressor,
+            frame: Vec::new(),
+            second: None,
+            split,
+        })
+    }
+
+    /// Use `jpeg` as the second input's frame until the next call.
+    pub fn set_second(&mut self, jpeg: &[u8]) -> Result<(), String> {
+        let mut pixels = self.second.take().map(|(p, _, _)| p).unwrap_or_default();
+        let (w, h) = decode_rgba(&mut self.decompressor, jpeg, &mut pixels)?;
+        self.second = Some((pixels, w, h));
+        Ok(())
+    }
+
+    /// Show the first input alone, e.g. after the second source went away.
+    pub fn clear_second(&mut self) {
+        self.second = None;
+    }
+
+    /// Move a comparison's split; `split` is a fraction of the width.
+    pub fn set_split(&mut self, split: f32) {
+        self.split = split.clamp(0.0, 1.0);
+    }
+
+    /// Decode the first input's `jpeg` and draw the second input over it.
+    pub fn compose(&mut self, jpeg: &[u8]) -> Result<VideoFrame<'_>, String> {
+        let (w, h) = decode_rgba(&mut self.decompressor, jpeg, &mut self.frame)?;
+        if let Some((pixels, sw, sh)) = &self.second {
+            let second = Rgba { pixels, width: *sw, height: *sh };
+            match &self.layout {
+                Layout::Pip(pip) => {
+                    let rect = inset_rect(w, h, *sw, *sh, pip);
+                    draw_inset(&mut self.frame, w, h, &second, rect, pip.border, self.color);
+                }
+                Layout::Compare(compare) => {
+                    let x = (w as f32 * self.split) as usize;
+                    draw_split(&mut self.frame, w, h, &second, x, compare.divider, self.color);
+                }
+            }
+        }
+        Ok(VideoFrame {
+            data: &self.frame,
+            width: w,
+            height: h,
+            stride: w * 4,
+            fourcc: FourCCVideoType::RGBA,
+        })
+    }
+}
+
+/// Decodes a crop's input and cuts its region out.
+pub struct Cropper {
+    config: CropConfig,
+    decompressor: turbojpeg::Decompressor,
+    /// The input frame last decoded.
+    input: Vec<u8>,
+    /// The cropped RGBA frame.
+    frame: Vec<u8>,
+}
+
+impl Cropper {
+    pub fn new(config: CropConfig) -> Result<Self, String> {
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        Ok(Self { config, decompressor, input: Vec::new(), frame: Vec::new() })
+    }
+
+    /// Decode the input's `jpeg` and keep the configured region of it.
+    pub fn crop(&mut self, jpeg: &[u8]) -> Result<VideoFrame<'_>, String> {
+        let (w, h) = decode_rgba(&mut self.decompressor, jpeg, &mut self.input)?;
+        if w == 0 || h == 0 {
+            return Err("empty frame".to_string());
+        }
+        let rect = self.config.rect(w, h);
+        self.frame.clear();
+        for row in self.input.chunks_exact(w * 4).skip(rect.y).take(rect.height) {
+            self.frame.extend_from_slice(&row[rect.x * 4..(rect.x + rect.width) * 4]);
+        }
+        Ok(VideoFrame {
+            data: &self.frame,
+            width: rect.width,
+            height: rect.height,
+            stride: rect.width * 4,
+            fourcc: FourCCVideoType::RGBA,
+        })
+    }
+}
+
+fn decode_rgba(
+    decompressor: &mut turbojpeg::Decompressor,
+    jpeg: &[u8],
+    pixels: &mut Vec<u8>,
+) -> Result<(usize, usize), String> {
+    let header = decompressor
+        .read_header(jpeg)
+        .map_err(|e| format!("turbojpeg header error: {e}"))?;
+    let (w, h) = (header.width, header.height);
+    pixels.resize(w * h * 4, 0);
+    let image = turbojpeg::Image {
+        pixels: &mut pixels[..],
+        width: w,
+        pitch: w * 4,
+        height: h,
+        format: turbojpeg::PixelFormat::RGBA,
+    };
+    decompressor
+        .decompress(jpeg, image)
+        .map_err(|e| format!("turbojpeg decompress error: {e}"))?;
+    Ok((w, h))
+}
+
+/// Where the inset goes: (x, y, width, height), keeping the inset's aspect ratio.
+fn inset_rect(w: usize, h: usize, iw: usize, ih: usize, spec: &PipConfig) -> Rect {
+    let width = ((w as f32 * spec.size) as usize).clamp(1, w);
+    let height = (width * ih / iw.max(1)).clamp(1, h);
+    let right = w.saturating_sub(width + spec.margin);
+    let bottom = h.saturating_sub(height + spec.margin);
+    let (x, y) = match spec.corner {
+        Corner::TopLeft => (spec.margin.min(right), spec.margin.min(bottom)),
+        Corner::TopRight => (right, spec.margin.min(bottom)),
+        Corner::BottomLeft => (spec.margin.min(right), bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    Rect { x, y, width, height }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+/// A decoded RGBA frame.
+struct Rgba<'a> {
+    pixels: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Rgba<'_> {
+    /// The RGB of the pixel at (`x`, `y`) when the frame is stretched to `w` x `h`.
+    fn sample(&self, x: usize, y: usize, w: usize, h: usize) -> &[u8] {
+        let (sx, sy) = (x * self.width / w, y * self.height / h);
+        let i = (sy * self.width + sx) * 4;
+        &self.pixels[i..i + 3]
+    }
+}
+
+/// Scale `inset` (nearest neighbour) into `rect` of `frame` and frame it with the border.
+fn draw_inset(
+    frame: &mut [u8],
+    w: usize,
+    h: usize,
+    inset: &Rgba,
+    rect: Rect,
+    border: usize,
+    color: [u8; 3],
+) {
+    let (x0, y0) = (rect.x.saturating_sub(border), rect.y.saturating_sub(border));
+    let x1 = (rect.x + rect.width + border).min(w);
+    let y1 = (rect.y + rect.height + border).min(h);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let dst = &mut frame[(y * w + x) * 4..(y * w + x) * 4 + 3];
+            let inside = (rect.x..rect.x + rect.width).contains(&x)
+                && (rect.y..rect.y + rect.height).contains(&y);
+            if inside {
+                dst.copy_from_slice(inset.sample(x - rect.x, y - rect.y, rect.width, rect.height));
+            } else {
+                dst.copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+/// Paint `rect` of `frame` in `color`.
+fn fill(frame: &mut [u8], w: usize, rect: Rect, color: [u8; 3]) {
+    for y in rect.y..rect.y + rect.height {
+        for x in rect.x..rect.x + rect.width {
+            frame[(y * w + x) * 4..(y * w + x) * 4 + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+/// Replace `frame` from column `split` on with `right`, stretched to the frame's
+/// size, and draw a `divider` pixels wide line at the split.
+fn draw_split(
+    frame: &mut [u8],
+    w: usize,
+    h: usize,
+    right: &Rgba,
+    split: usize,
+    divider: usize,
+    color: [u8; 3],
+) {
+    let line = split.saturating_sub(divider / 2)..(split + divider - divider / 2).min(w);
+    for y in 0..h {
+        for x in line.start.min(split)..w {
+            let dst = &mut frame[(y * w + x) * 4..(y * w + x) * 4 + 3];
+            if line.contains(&x) {
+                dst.copy_from_slice(&color);
+            } else if x >= split {
+                dst.copy_from_slice(right.sample(x, y, w, h));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_jpeg(w: usize, h: usize, rgb: [u8; 3]) -> Vec<u8> {
+        let pixels: Vec<u8> = (0..w * h).flat_map(|_| rgb).collect();
+        let image = turbojpeg::Image {
+            pixels: &pixels[..],
+            width: w,
+            pitch: w * 3,
+            height: h,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+        turbojpeg::compress(image, 95, turbojpeg::Subsamp::None).unwrap().to_vec()
+    }
+
+    #[test]
+    fn draws_framed_inset_in_corner() {
+        let spec: PipConfig = toml::from_str("main = \"a\"\ninset = \"b\"").unwrap();
+        let defaults = (spec.corner, spec.size, spec.margin, spec.border);
+        assert_eq!(defaults, (Corner::BottomRight, 0.25, 16, 2));
+        let mut compositor = Compositor::new(Layout::Pip(spec)).unwrap();
+        compositor.set_second(&solid_jpeg(160, 90, [255, 0, 0])).unwrap();
+        let frame = compositor.compose(&solid_jpeg(320, 180, [40, 40, 40])).unwrap();
+        assert_eq!((frame.width, frame.height, frame.fourcc), (320, 180, FourCCVideoType::RGBA));
+
+        // Inset is 80x45 at (224, 119); its border is one pixel further out.
+        let pixel = |x: usize, y: usize| &frame.data[(y * 320 + x) * 4..(y * 320 + x) * 4 + 3];
+        let near = |p: &[u8], rgb: [u8; 3]| p.iter().zip(rgb).all(|(&a, b)| a.abs_diff(b) <= 8);
+        assert!(near(pixel(10, 10), [40, 40, 40]));
+        assert!(near(pixel(264, 140), [255, 0, 0]), "{:?}", pixel(264, 140));
+        assert!(near(pixel(223, 140), [255, 255, 255]), "{:?}", pixel(223, 140));
+        assert!(near(pixel(319, 179), [40, 40, 40]));
+    }
+
+    #[test]
+    fn splits_at_the_moved_divider() {
+        let spec: CompareConfig = toml::from_str("left = \"a\"\nright = \"b\"").unwrap();
+        let mut compositor = Compositor::new(Layout::Compare(spec)).unwrap();
+        compositor.set_second(&solid_jpeg(160, 90, [0, 0, 255])).unwrap();
+        compositor.set_split(0.25);
+        let frame = compositor.compose(&solid_jpeg(320, 180, [40, 40, 40])).unwrap();
+
+        // Split at x = 80; the 2 pixel divider covers 79 and 80.
+        let pixel = |x: usize| &frame.data[(90 * 320 + x) * 4..(90 * 320 + x) * 4 + 3];
+        let near = |p: &[u8], rgb: [u8; 3]| p.iter().zip(rgb).all(|(&a, b)| a.abs_diff(b) <= 8);
+        assert!(near(pixel(70), [40, 40, 40]), "{:?}", pixel(70));
+        assert!(near(pixel(80), [255, 255, 255]), "{:?}", pixel(80));
+        assert!(near(pixel(90), [0, 0, 255]), "{:?}", pixel(90));
+        assert!(near(pixel(319), [0, 0, 255]), "{:?}", pixel(319));
+    }
+
+    #[test]
+    fn tiles_mosaic_inputs_in_reading_order() {
+        let names = |n: usize| (0..n).map(|i| format!("cam {i}")).collect::<Vec<_>>();
+        let grid = |n, layout| MosaicConfig::new(names(n), layout).map(|m| (m.columns, m.rows));
+        assert_eq!(grid(4, None), Ok((2, 2)));
+        assert_eq!(grid(5, None), Ok((3, 2)));
+        assert_eq!(grid(2, Some("1x2")), Ok((1, 2)));
+        assert!(grid(3, Some("1x2")).is_err());
+        assert!(grid(2, Some("5x4")).is_err());
+        assert!(grid(2, Some("2by2")).is_err());
+        assert!(grid(0, None).is_err());
+
+        let config = MosaicConfig::new(names(3), Some("2x2")).unwrap();
+        let mut mosaic = Mosaic::new(&config).unwrap();
+        mosaic.draw(1, &solid_jpeg(160, 90, [255, 0, 0])).unwrap();
+        // A 4:3 input is pillarboxed in its 16:9 tile.
+        mosaic.draw(2, &solid_jpeg(120, 90, [0, 0, 255])).unwrap();
+        let frame = mosaic.frame();
+        assert_eq!((frame.width, frame.height), (1920, 1080));
+        let pixel = |x: usize, y: usize| &frame.data[(y * 1920 + x) * 4..(y * 1920 + x) * 4 + 3];
+        let near = |p: &[u8], rgb: [u8; 3]| p.iter().zip(rgb).all(|(&a, b)| a.abs_diff(b) <= 8);
+        assert!(near(pixel(480, 270), [0, 0, 0]), "{:?}", pixel(480, 270));
+        assert!(near(pixel(1440, 270), [255, 0, 0]), "{:?}", pixel(1440, 270));
+        assert!(near(pixel(480, 810), [0, 0, 255]), "{:?}", pixel(480, 810));
+        assert!(near(pixel(20, 810), [0, 0, 0]), "{:?}", pixel(20, 810));
+        mosaic.clear(1);
+        assert_eq!(mosaic.frame().data[(270 * 1920 + 1440) * 4..][..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        let spec = |s: &str| toml::from_str::<PipConfig>(s).unwrap();
+        assert!(spec("main = \"pip\"\ninset = \"b\"").validate("pip").is_err());
+        assert!(spec("main = \"a\"\ninset = \"b\"\nsize = 1.5").validate("pip").is_err());
+        let named_color = spec("main = \"a\"\ninset = \"b\"\nborder_color = \"red\"");
+        assert!(named_color.validate("pip").is_err());
+        assert!(spec("main = \"a\"\ninset = \"b\"\ncorner = \"top_left\"").validate("pip").is_ok());
+        let compare = |s: &str| toml::from_str::<CompareConfig>(s).unwrap();
+        assert!(compare("left = \"a\"\nright = \"b\"\nsplit = 2.0").validate("ab").is_err());
+        assert!(compare("left = \"a\"\nright = \"ab\"").validate("ab").is_err());
+        let crop = |s: &str| toml::from_str::<CropConfig>(s).unwrap();
+        assert!(crop("source = \"a\"\nx = 0.5\nwidth = 0.5").validate("c").is_ok());
+        assert!(crop("source = \"a\"\nx = 0.5\nwidth = 0.6").validate("c").is_err());
+        assert!(crop("source = \"a\"\nheight = 0.0").validate("c").is_err());
+        assert!(crop("source = \"c\"").validate("c").is_err());
+    }
+
+    #[test]
+    fn crops_the_configured_region() {
+        let mut pixels = [40, 40, 40].repeat(320 * 180);
+        for y in 90..180 {
+            for x in 160..320 {
+                pixels[(y * 320 + x) * 3..][..3].copy_from_slice(&[0, 255, 0]);
+            }
+        }
+        let image = turbojpeg::Image {
+            pixels: &pixels[..],
+            width: 320,
+            pitch: 320 * 3,
+            height: 180,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+        let jpeg = turbojpeg::compress(image, 95, turbojpeg::Subsamp::None).unwrap();
+
+        let spec = "source = \"a\"\nx = 0.5\ny = 0.5\nwidth = 0.5\nheight = 0.5";
+        let mut cropper = Cropper::new(toml::from_str(spec).unwrap()).unwrap();
+        let frame = cropper.crop(&jpeg).unwrap();
+        assert_eq!((frame.width, frame.height, frame.data.len()), (160, 90, 160 * 90 * 4));
+        let pixel = |x: usize, y: usize| &frame.data[(y * 160 + x) * 4..][..3];
+        for (x, y) in [(8, 8), (80, 45), (151, 81)] {
+            assert!(pixel(x, y).iter().zip([0, 255, 0]).all(|(&a, b)| a.abs_diff(b) <= 8));
+        }
+    }
+}
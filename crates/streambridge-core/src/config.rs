@@ -0,0 +1,872 @@
+use crate::auth::{Action, Grant};
+use crate::automation::CaptureRule;
+use crate::clock::ClockConfig;
+use crate::composite::{CompareConfig, CropConfig, PipConfig};
+use crate::deinterlace::Deinterlace;
+use crate::discovery;
+use crate::encode::EncoderKind;
+use crate::media::MediaConfig;
+use crate::motion::{self, MotionSettings};
+use crate::overlay::{Logo, OverlayConfig};
+use crate::receiver::ColorFormat;
+use crate::recording::MAX_RECORD_FPS;
+use crate::republish::RepublishConfig;
+use crate::srt::{self, SrtConfig};
+use crate::threads::CpuList;
+use crate::timestamps::TimeFormat;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The `--config` file. Every field is optional; anything left out falls back to
+/// the command-line flag or its default.
+///
+/// ```toml
+/// port = 9550
+/// rtsp_port = 8554
+/// onvif = true
+/// max_fps = 25
+/// jpeg_quality = 75
+/// adaptive_quality = true
+/// max_height = 1080
+/// low_bandwidth = false
+/// deinterlace = "bob"
+/// color_format = "bgra"
+/// encoder = "turbojpeg"
+/// encode_threads = 1
+/// log_interval = 20
+/// log_buffer = 1000
+/// drain_timeout = 20
+/// receiver_linger = 30
+/// idle_after = 600
+/// thread_nice = 10
+/// thread_cpus = [2, 3]
+/// encode_workers = 2
+/// crash_dir = "/var/log/streambridge"
+/// state_dir = "/var/lib/streambridge"
+/// record_max_size = 2000
+/// record_max_duration = 3600
+/// record_fps = 25
+/// record_shared = true
+/// preroll = 10
+/// client_ids = true
+/// max_clients = 200
+/// max_clients_per_source = 50
+/// max_connections_per_ip = 30
+/// extra_ips = ["10.20.0.15", "10.30.0.21"]
+/// groups = ["studio-a", "studio-b"]
+/// receiver_name = "streambridge@studio-pc"
+/// time_zone = "Europe/Berlin"
+/// time_format = "%Y-%m-%d_%H%M%S%z"
+/// script = "hooks.lua"
+///
+/// [sources."STUDIO (Wide Shot)"]
+/// jpeg_quality = 50
+/// max_fps = 10
+/// max_width = 960
+/// low_bandwidth = true
+/// deinterlace = "weave"
+/// color_format = "uyvy"
+/// encode_threads = 3
+/// time_zone = "America/New_York"
+///
+/// [sources."STUDIO (Program)"]
+/// viewer_badge = true
+/// adaptive_quality = false
+///
+/// [sources."STUDIO (Program)".overlay]
+/// name = true
+/// time = "timecode"
+/// logo = "logos/station.png"
+/// logo_corner = "top_right"
+///
+/// [sources."STUDIO (Program)".srt]
+/// mode = "caller"
+/// address = "relay.example.com:9000"
+/// latency = 800
+///
+/// [sources."LOBBY (Cam)"]
+/// motion = true
+/// motion_threshold = 0.02
+/// motion_hold = 5
+///
+/// [static_sources]
+/// "REMOTE-PC (Cam 1)" = "10.20.0.15:5961"
+///
+/// [aliases]
+/// program = "STUDIO-PC (vMix - Output 1)"
+///
+/// [[grants]]
+/// token = "s3cret"
+/// label = "Agency review"
+/// actions = ["view", "snapshot"]
+/// sources = ["STUDIO (Wide Shot)"]
+///
+/// [[capture_rules]]
+/// on = "webhook"
+/// webhook = "goal"
+/// path = "captures/{source}/{timestamp}.jpg"
+///
+/// [pip."Program + Guest"]
+/// main = "STUDIO (Program)"
+/// inset = "REMOTE (Guest)"
+/// corner = "top_right"
+/// size = 0.3
+///
+/// [compare."Cam 1 vs Cam 2"]
+/// left = "STUDIO (Cam 1)"
+/// right = "STUDIO (Cam 2)"
+///
+/// [crop."Presenter"]
+/// source = "STUDIO (Wide Shot)"
+/// x = 0.5
+/// width = 0.5
+///
+/// [clock."Clock"]
+/// width = 1280
+/// height = 720
+/// fps = 25
+/// text = "Studio A"
+///
+/// [media."Rehearsal"]
+/// path = "clips/opener.avi"
+/// fps = 25
+///
+/// [republish."Program + Guest"]
+/// source = "Program + Guest"
+///
+/// [republish."Multiview"]
+/// mosaic = ["STUDIO (Cam 1)", "STUDIO (Cam 2)", "STUDIO (Cam 3)", "STUDIO (Cam 4)"]
+/// layout = "2x2"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub port: Option<u16>,
+    /// Also serve sources over RTSP on this port.
+    pub rtsp_port: Option<u16>,
+    /// Answer ONVIF discovery probes with every source.
+    pub onvif: Option<bool>,
+    pub max_fps: Option<u32>,
+    pub jpeg_quality: Option<i32>,
+    /// Lower the quality and frame rate while encoding or clients can't keep up.
+    pub adaptive_quality: Option<bool>,
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    /// Receive NDI® senders' low-bandwidth preview streams by default.
+    pub low_bandwidth: Option<bool>,
+    /// How interlaced NDI® sources are made progressive.
+    pub deinterlace: Option<Deinterlace>,
+    /// The pixel format NDI® receivers ask for.
+    pub color_format: Option<ColorFormat>,
+    pub encoder: Option<EncoderKind>,
+    /// Threads encoding each source's frames; 1 encodes on its capture thread.
+    pub encode_threads: Option<usize>,
+    pub log_interval: Option<u64>,
+    /// Recent log events kept for `/admin/logs`.
+    pub log_buffer: Option<usize>,
+    /// Seconds to drain clients for on SIGTERM.
+    pub drain_timeout: Option<u64>,
+    /// Seconds receivers keep running after their last client leaves.
+    pub receiver_linger: Option<u64>,
+    /// Seconds without use before the server idles.
+    pub idle_after: Option<u64>,
+    /// Niceness of the capture and encode threads, -20 to 19.
+    pub thread_nice: Option<i32>,
+    /// CPUs the capture and encode threads may run on.
+    pub thread_cpus: Option<CpuList>,
+    /// Threads for per-viewer re-encodes, like watermarks.
+    pub encode_workers: Option<u64>,
+    /// Where crash reports are written.
+    pub crash_dir: Option<PathBuf>,
+    /// Where state such as the source favorites is kept across restarts.
+    pub state_dir: Option<PathBuf>,
+    /// Megabytes before a recording moves on to a new file.
+    pub record_max_size: Option<u64>,
+    /// Seconds before a recording moves on to a new file.
+    pub record_max_duration: Option<u64>,
+    /// Frames per second AVI recordings are written at, if fixed.
+    pub record_fps: Option<u32>,
+    /// Record the JPEGs sent to viewers instead of encoding every frame.
+    pub record_shared: Option<bool>,
+    /// Seconds of every source kept in memory for `/preroll`.
+    pub preroll: Option<u64>,
+    /// Give viewers persistent ids in a cookie.
+    pub client_ids: Option<bool>,
+    /// Streaming clients allowed at once.
+    pub max_clients: Option<u64>,
+    /// Streaming clients allowed at once for one source.
+    pub max_clients_per_source: Option<u64>,
+    /// New streaming connections allowed from one IP address per minute.
+    pub max_connections_per_ip: Option<u64>,
+    /// Machines to ask for their NDI® sources directly.
+    pub extra_ips: Option<Vec<IpAddr>>,
+    /// NDI® groups to discover sources in, instead of the default one.
+    pub groups: Option<Vec<String>>,
+    /// What NDI® senders see the receivers as.
+    pub receiver_name: Option<String>,
+    /// Time zone of timestamps in file names and on clocks: an IANA name, `UTC`
+    /// or `local`.
+    pub time_zone: Option<String>,
+    /// strftime format of timestamps in file names and on clocks.
+    pub time_format: Option<String>,
+    /// Lua file whose handlers run when events happen.
+    pub script: Option<PathBuf>,
+    /// Per-source overrides, keyed by exact NDI source name.
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceOverride>,
+    /// Access grants, in addition to any `--grant` flags.
+    #[serde(default)]
+    pub grants: Vec<GrantConfig>,
+    /// Snapshots taken automatically when events happen.
+    #[serde(default)]
+    pub capture_rules: Vec<CaptureRule>,
+    /// Picture-in-picture sources, keyed by the name they are served under.
+    #[serde(default)]
+    pub pip: BTreeMap<String, PipConfig>,
+    /// A/B comparison sources, keyed by the name they are served under.
+    #[serde(default)]
+    pub compare: BTreeMap<String, CompareConfig>,
+    /// Regions of other sources, keyed by the name they are served under.
+    #[serde(default)]
+    pub crop: BTreeMap<String, CropConfig>,
+    /// Generated clock sources, keyed by the name they are served under.
+    #[serde(default)]
+    pub clock: BTreeMap<String, ClockConfig>,
+    /// Video files played in a loop, keyed by the name they are served under.
+    #[serde(default)]
+    pub media: BTreeMap<String, MediaConfig>,
+    /// Sources and mosaics sent out as NDI® sources, keyed by the name they are
+    /// published under.
+    #[serde(default)]
+    pub republish: BTreeMap<String, RepublishConfig>,
+    /// NDI® sources listed whether discovery finds them or not: their URLs
+    /// (`ip:port`), keyed by name.
+    #[serde(default)]
+    pub static_sources: BTreeMap<String, String>,
+    /// Short, stable names that stand in for sources' names in URLs: the
+    /// names, keyed by alias.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// A `[[grants]]` entry: the config-file form of `--grant`, plus a label.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrantConfig {
+    pub token: String,
+    pub label: Option<String>,
+    /// Action names, or `["*"]` for all.
+    pub actions: Vec<String>,
+    /// Source names, or `["*"]` (the default) for all.
+    #[serde(default = "all_sources")]
+    pub sources: Vec<String>,
+}
+
+fn all_sources() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+impl GrantConfig {
+    pub fn to_grant(&self) -> Result<Grant, String> {
+        if self.token.is_empty() {
+            return Err("empty token in grant".to_string());
+        }
+        let actions = if self.actions.iter().any(|a| a == "*") {
+            Action::ALL.to_vec()
+        } else {
+            self.actions.iter().map(|a| Action::parse(a)).collect::<Result<_, _>>()?
+        };
+        let sources = if self.sources.iter().any(|s| s == "*") {
+            None
+        } else {
+            Some(self.sources.clone())
+        };
+        Ok(Grant {
+            token: self.token.clone(),
+            label: self.label.clone(),
+            actions,
+            sources,
+        })
+    }
+}
+
+/// Settings that replace the global ones for a single source.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceOverride {
+    pub max_fps: Option<u32>,
+    pub jpeg_quality: Option<i32>,
+    pub adaptive_quality: Option<bool>,
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    pub low_bandwidth: Option<bool>,
+    pub deinterlace: Option<Deinterlace>,
+    pub color_format: Option<ColorFormat>,
+    pub encode_threads: Option<usize>,
+    /// Burn the number of viewers into the frames.
+    pub viewer_badge: Option<bool>,
+    /// Burn the source's name, the time or a logo into the frames.
+    pub overlay: Option<OverlayConfig>,
+    /// Push the source to a remote receiver over SRT.
+    pub srt: Option<SrtConfig>,
+    /// Look for motion, with `motion_threshold` and `motion_hold` if given.
+    pub motion: Option<bool>,
+    /// Share of the picture that must change, above 0 and at most 1.
+    pub motion_threshold: Option<f32>,
+    /// Seconds the picture must stay still for motion to end.
+    pub motion_hold: Option<u64>,
+    /// Seconds; 0 keeps none.
+    pub preroll: Option<u64>,
+    pub time_zone: Option<String>,
+    pub time_format: Option<String>,
+}
+
+/// Settings changed on a running server for a single source, e.g. with
+/// `PATCH /admin/receivers/<source>`. They win over the configured ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tuning {
+    pub jpeg_quality: Option<i32>,
+    /// 0 for uncapped.
+    pub max_fps: Option<u32>,
+    pub low_bandwidth: Option<bool>,
+}
+
+impl Tuning {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.jpeg_quality {
+            Some(quality) if !(1..=100).contains(&quality) => {
+                Err("jpeg_quality must be between 1 and 100".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `other`'s values where it has them, else these.
+    pub fn merge(self, other: Tuning) -> Tuning {
+        Tuning {
+            jpeg_quality: other.jpeg_quality.or(self.jpeg_quality),
+            max_fps: other.max_fps.or(self.max_fps),
+            low_bandwidth: other.low_bandwidth.or(self.low_bandwidth),
+        }
+    }
+
+    pub fn apply(&self, settings: EncodeSettings) -> EncodeSettings {
+        EncodeSettings {
+            jpeg_quality: self.jpeg_quality.unwrap_or(settings.jpeg_quality),
+            max_fps: self.max_fps.unwrap_or(settings.max_fps),
+            low_bandwidth: self.low_bandwidth.unwrap_or(settings.low_bandwidth),
+            ..settings
+        }
+    }
+}
+
+/// Environment variables named `STREAMBRIDGE_<KEY>` set the config key `<key>`.
+pub const ENV_PREFIX: &str = "STREAMBRIDGE_";
+/// The environment variable naming the config file, when `--config` isn't given.
+pub const CONFIG_ENV: &str = "STREAMBRIDGE_CONFIG";
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::load_with_env(Some(path), std::iter::empty())
+    }
+
+    /// The config file at `path`, if any, with `STREAMBRIDGE_*` variables from
+    /// `vars` replacing its top-level keys. Values are TOML (`8080`, `["view"]`,
+    /// `{ max_fps = 10 }`); anything that doesn't parse as TOML is a string.
+    pub fn load_with_env(
+        path: Option<&Path>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut table = match path {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?
+                .parse::<toml::Table>()
+                .map_err(|e| format!("invalid config {}: {e}", path.display()))?,
+            None => toml::Table::new(),
+        };
+        let mut from_env = Vec::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX).filter(|_| name != CONFIG_ENV) else {
+                continue;
+            };
+            table.insert(key.to_ascii_lowercase(), env_value(&value));
+            from_env.push(name);
+        }
+        Self::from_table(table).map_err(|e| match (path, from_env.is_empty()) {
+            (Some(path), true) => format!("invalid config {}: {e}", path.display()),
+            (Some(path), false) => {
+                format!("invalid config {} with {}: {e}", path.display(), from_env.join(", "))
+            }
+            (None, _) => format!("invalid settings in {}: {e}", from_env.join(", ")),
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::from_table(text.parse::<toml::Table>().map_err(|e| e.to_string())?)
+    }
+
+    fn from_table(table: toml::Table) -> Result<Self, String> {
+        let config: Config = toml::Value::Table(table).try_into().map_err(|e| e.to_string())?;
+        let qualities = config
+            .jpeg_quality
+            .iter()
+            .chain(config.sources.values().filter_map(|s| s.jpeg_quality.as_ref()));
+        for &quality in qualities {
+            if !(1..=100).contains(&quality) {
+                return Err(format!("jpeg_quality must be between 1 and 100, got {quality}"));
+            }
+        }
+        if let Some(nice) = config.thread_nice.filter(|nice| !(-20..=19).contains(nice)) {
+            return Err(format!("thread_nice must be between -20 and 19, got {nice}"));
+        }
+        let mut encode_threads = config
+            .encode_threads
+            .iter()
+            .chain(config.sources.values().filter_map(|s| s.encode_threads.as_ref()));
+        if encode_threads.any(|&threads| threads == 0) {
+            return Err("encode_threads must be at least 1".to_string());
+        }
+        if config.encode_workers == Some(0) {
+            return Err("encode_workers must be at least 1".to_string());
+        }
+        if config.record_max_size == Some(0) || config.record_max_duration == Some(0) {
+            return Err("record_max_size and record_max_duration must be at least 1".to_string());
+        }
+        let limits =
+            [config.max_clients, config.max_clients_per_source, config.max_connections_per_ip];
+        if limits.contains(&Some(0)) {
+            let names = "max_clients, max_clients_per_source and max_connections_per_ip";
+            return Err(format!("{names} must be at least 1"));
+        }
+        let times = TimeFormat::new(config.time_zone.as_deref(), config.time_format.as_deref())?;
+        for (name, source) in &config.sources {
+            if let Some(threshold) = source.motion_threshold {
+                let valid = threshold > 0.0 && threshold <= 1.0;
+                if !valid {
+                    let range = "above 0 and at most 1";
+                    return Err(format!("source \"{name}\": motion_threshold must be {range}"));
+                }
+            }
+            times
+                .with(source.time_zone.as_deref(), source.time_format.as_deref())
+                .map_err(|e| format!("source \"{name}\": {e}"))?;
+            if let Some(logo) = source.overlay.as_ref().and_then(|o| o.logo.as_deref()) {
+                Logo::load(logo).map_err(|e| format!("source \"{name}\": overlay logo {e}"))?;
+            }
+            if let Some(output) = &source.srt {
+                output.validate().map_err(|e| format!("source \"{name}\": {e}"))?;
+                if !srt::AVAILABLE {
+                    return Err(format!("source \"{name}\": SRT output needs the ffmpeg feature"));
+                }
+            }
+        }
+        if let Some(fps) = config.record_fps.filter(|fps| !(1..=MAX_RECORD_FPS).contains(fps)) {
+            return Err(format!("record_fps must be between 1 and {MAX_RECORD_FPS}, got {fps}"));
+        }
+        for grant in &config.grants {
+            grant.to_grant()?;
+        }
+        for rule in &config.capture_rules {
+            rule.validate()?;
+        }
+        let composite =
+            |name: &str| config.pip.contains_key(name) || config.compare.contains_key(name);
+        for (name, pip) in &config.pip {
+            pip.validate(name)?;
+            if composite(&pip.main) || composite(&pip.inset) {
+                return Err(format!("picture-in-picture \"{name}\" can't contain a composite"));
+            }
+        }
+        for (name, compare) in &config.compare {
+            compare.validate(name)?;
+            if composite(&compare.left) || composite(&compare.right) {
+                return Err(format!("comparison \"{name}\" can't contain a composite"));
+            }
+        }
+        for (name, crop) in &config.crop {
+            crop.validate(name)?;
+            if config.crop.contains_key(&crop.source) {
+                return Err(format!("crop \"{name}\" can't contain a crop"));
+            }
+        }
+        for (name, clock) in &config.clock {
+            clock.validate(name)?;
+        }
+        for (name, media) in &config.media {
+            media.validate(name)?;
+        }
+        for (name, republish) in &config.republish {
+            republish.input(name)?;
+        }
+        for (name, url) in &config.static_sources {
+            if !discovery::is_sender_address(url) {
+                return Err(format!("static source \"{name}\" needs an ip:port URL, got \"{url}\""));
+            }
+            let generated = config.clock.contains_key(name)
+                || config.media.contains_key(name)
+                || config.crop.contains_key(name);
+            if composite(name) || generated {
+                return Err(format!("static source \"{name}\" has the name of a generated source"));
+            }
+        }
+        for (alias, name) in &config.aliases {
+            if alias.trim().is_empty() || name.trim().is_empty() {
+                return Err(format!("alias \"{alias}\" = \"{name}\" needs both names"));
+            }
+            let generated = config.clock.contains_key(alias)
+                || config.media.contains_key(alias)
+                || config.crop.contains_key(alias);
+            if composite(alias) || generated || config.static_sources.contains_key(alias) {
+                return Err(format!("alias \"{alias}\" has the name of a configured source"));
+            }
+        }
+        for group in config.groups.iter().flatten() {
+            if group.trim().is_empty() || group.contains(',') {
+                return Err(format!("invalid NDI group \"{group}\""));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Frame rate for `--public-readonly`.
+pub const PUBLIC_MAX_FPS: u32 = 10;
+/// Frame width for `--public-readonly`.
+pub const PUBLIC_MAX_WIDTH: usize = 640;
+
+/// JPEG quality, fps and size caps for one source's pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeSettings {
+    pub jpeg_quality: i32,
+    /// Lower the quality, then the frame rate, under load; see [`crate::quality`].
+    pub adaptive_quality: bool,
+    /// 0 for uncapped.
+    pub max_fps: u32,
+    /// Wider or taller frames are scaled down by a power of two until they fit.
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    /// Ask NDI senders for their low-bandwidth proxy stream.
+    pub low_bandwidth: bool,
+    pub deinterlace: Deinterlace,
+    /// What NDI receivers ask senders' frames in.
+    pub color_format: ColorFormat,
+    pub encoder: EncoderKind,
+    /// Threads encoding frames; 1 encodes on the capture thread.
+    pub encode_threads: usize,
+    /// Draw the number of viewers into the frames; see [`crate::badge`].
+    pub viewer_badge: bool,
+    /// Look for motion; see [`crate::motion`].
+    pub motion: Option<MotionSettings>,
+    /// How much of the stream to keep in memory; see [`crate::preroll`].
+    pub preroll: Option<Duration>,
+}
+
+impl EncodeSettings {
+    pub fn new(jpeg_quality: i32, max_fps: u32) -> Self {
+        Self {
+            jpeg_quality,
+            adaptive_quality: false,
+            max_fps,
+            max_width: None,
+            max_height: None,
+            low_bandwidth: false,
+            deinterlace: Deinterlace::default(),
+            color_format: ColorFormat::default(),
+            encoder: EncoderKind::default(),
+            encode_threads: 1,
+            viewer_badge: false,
+            motion: None,
+            preroll: None,
+        }
+    }
+
+    /// These settings limited to the public read-only profile.
+    fn public(self) -> Self {
+        let max_fps = match self.max_fps {
+            0 => PUBLIC_MAX_FPS,
+            fps => fps.min(PUBLIC_MAX_FPS),
+        };
+        Self {
+            jpeg_quality: self.jpeg_quality,
+            adaptive_quality: self.adaptive_quality,
+            max_fps,
+            max_width: Some(self.max_width.map_or(PUBLIC_MAX_WIDTH, |w| w.min(PUBLIC_MAX_WIDTH))),
+            max_height: self.max_height,
+            low_bandwidth: true,
+            deinterlace: self.deinterlace,
+            color_format: self.color_format,
+            encoder: self.encoder,
+            encode_threads: self.encode_threads,
+            viewer_badge: self.viewer_badge,
+            motion: self.motion,
+            preroll: self.preroll,
+        }
+    }
+}
+
+/// Global encode settings plus per-source overrides.
+#[derive(Debug, Clone)]
+pub struct SourceSettings {
+    default: EncodeSettings,
+    overrides: BTreeMap<String, SourceOverride>,
+    public: bool,
+    times: TimeFormat,
+}
+
+impl SourceSettings {
+    pub fn new(default: EncodeSettings, overrides: BTreeMap<String, SourceOverride>) -> Self {
+        Self {
+            default,
+            overrides,
+            public: false,
+            times: TimeFormat::default(),
+        }
+    }
+
+    /// How times are written for sources without their own time zone or format.
+    pub fn time_format(mut self, times: TimeFormat) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// How times are written for `source`: its time zone and format where it
+    /// has them, else the global ones.
+    pub fn time_format_for(&self, source: &str) -> TimeFormat {
+        let Some(o) = self.overrides.get(source) else {
+            return self.times.clone();
+        };
+        // Checked when the config was read.
+        self.times
+            .with(o.time_zone.as_deref(), o.time_format.as_deref())
+            .unwrap_or_else(|_| self.times.clone())
+    }
+
+    /// What to burn into `source`'s frames, if anything.
+    pub fn overlay_for(&self, source: &str) -> Option<OverlayConfig> {
+        self.overrides.get(source).and_then(|o| o.overlay.clone())
+    }
+
+    /// The sources to push over SRT, and where.
+    pub fn srt_outputs(&self) -> Vec<(String, SrtConfig)> {
+        let outputs = self.overrides.iter();
+        outputs.filter_map(|(name, o)| Some((name.clone(), o.srt.clone()?))).collect()
+    }
+
+    /// Apply the public read-only caps on top of every source's settings.
+    pub fn public_readonly(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// The settings for `source`: its override where it has one, else the global value.
+    pub fn for_source(&self, source: &str) -> EncodeSettings {
+        let settings = match self.overrides.get(source) {
+            Some(o) => EncodeSettings {
+                jpeg_quality: o.jpeg_quality.unwrap_or(self.default.jpeg_quality),
+                adaptive_quality: o.adaptive_quality.unwrap_or(self.default.adaptive_quality),
+                max_fps: o.max_fps.unwrap_or(self.default.max_fps),
+                max_width: o.max_width.or(self.default.max_width),
+                max_height: o.max_height.or(self.default.max_height),
+                low_bandwidth: o.low_bandwidth.unwrap_or(self.default.low_bandwidth),
+                deinterlace: o.deinterlace.unwrap_or(self.default.deinterlace),
+                color_format: o.color_format.unwrap_or(self.default.color_format),
+                encoder: self.default.encoder,
+                encode_threads: o.encode_threads.unwrap_or(self.default.encode_threads),
+                viewer_badge: o.viewer_badge.unwrap_or(self.default.viewer_badge),
+                motion: match o.motion {
+                    Some(true) => Some(MotionSettings {
+                        threshold: o.motion_threshold.unwrap_or(motion::DEFAULT_THRESHOLD),
+                        hold: o.motion_hold.map_or(motion::DEFAULT_HOLD, Duration::from_secs),
+                    }),
+                    Some(false) => None,
+                    None => self.default.motion,
+                },
+                preroll: match o.preroll {
+                    Some(0) => None,
+                    Some(secs) => Some(Duration::from_secs(secs)),
+                    None => self.default.preroll,
+                },
+            },
+            None => self.default,
+        };
+        if self.public {
+            settings.public()
+        } else {
+            settings
+        }
+    }
+}
+
+/// An environment variable's value as TOML, or as a plain string.
+fn env_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+impl From<EncodeSettings> for SourceSettings {
+    fn from(default: EncodeSettings) -> Self {
+        Self::new(default, BTreeMap::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_sources_need_a_url_with_a_port() {
+        let config = Config::parse(
+            r#"
+            extra_ips = ["10.20.0.15"]
+            [static_sources]
+            "REMOTE (Cam 1)" = "10.20.0.15:5961"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.static_sources["REMOTE (Cam 1)"], "10.20.0.15:5961");
+        assert_eq!(config.extra_ips.unwrap()[0].to_string(), "10.20.0.15");
+        let error = Config::parse("[static_sources]\n\"CAM\" = \"10.20.0.15\"").unwrap_err();
+        assert!(error.contains("ip:port"), "{error}");
+        assert!(Config::parse("extra_ips = [\"remote-pc\"]").is_err());
+    }
+
+    #[test]
+    fn aliases_may_not_hide_configured_sources() {
+        let config = Config::parse("[aliases]\nprogram = \"STUDIO-PC (vMix - Output 1)\"").unwrap();
+        assert_eq!(config.aliases["program"], "STUDIO-PC (vMix - Output 1)");
+        let error = Config::parse("[aliases]\nwall = \"CAM (1)\"\n[clock.wall]").unwrap_err();
+        assert!(error.contains("configured source"), "{error}");
+        assert!(Config::parse("[aliases]\nprogram = \"\"").is_err());
+        let crop = "[crop.half]\nsource = \"CAM (1)\"\nwidth = 0.5\n";
+        assert!(Config::parse(&format!("{crop}[aliases]\nhalf = \"CAM (2)\"")).is_err());
+        let nested = format!("{crop}[crop.quarter]\nsource = \"half\"");
+        let error = Config::parse(&nested).unwrap_err();
+        assert!(error.contains("can't contain a crop"), "{error}");
+    }
+
+    #[test]
+    fn overrides_fall_back_to_globals() {
+        let config = Config::parse(
+            r#"
+            jpeg_quality = 80
+            time_format = "%H%M"
+            [sources."CAM (1)"]
+            max_fps = 5
+            color_format = "bgra"
+            time_zone = "Asia/Tokyo"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.jpeg_quality, Some(80));
+        assert_eq!(config.port, None);
+
+        let default = EncodeSettings::new(80, 25);
+        let times = TimeFormat::new(None, config.time_format.as_deref()).unwrap();
+        let settings = SourceSettings::new(default, config.sources).time_format(times);
+        let bgra = ColorFormat::Bgra;
+        let cam_1 = EncodeSettings { color_format: bgra, ..EncodeSettings::new(80, 5) };
+        assert_eq!(settings.for_source("CAM (1)"), cam_1);
+        assert_eq!(settings.for_source("CAM (2)"), default);
+        let noon = std::time::UNIX_EPOCH + Duration::from_secs(12 * 3600);
+        assert_eq!(settings.time_format_for("CAM (1)").file_timestamp(noon), "2100");
+        assert_eq!(settings.time_format_for("CAM (2)").file_timestamp(noon), "1200");
+
+        let unknown_zone = "[sources.\"CAM (1)\"]\ntime_zone = \"Mars/Olympus\"";
+        assert!(Config::parse(unknown_zone).unwrap_err().contains("CAM (1)"));
+    }
+
+    #[test]
+    fn motion_is_turned_on_per_source() {
+        let config = Config::parse(
+            r#"
+            [sources."LOBBY"]
+            motion = true
+            motion_hold = 10
+            "#,
+        )
+        .unwrap();
+        let settings = SourceSettings::new(EncodeSettings::new(75, 0), config.sources);
+        let motion = settings.for_source("LOBBY").motion.unwrap();
+        assert_eq!(motion.threshold, crate::motion::DEFAULT_THRESHOLD);
+        assert_eq!(motion.hold, Duration::from_secs(10));
+        assert_eq!(settings.for_source("STAGE").motion, None);
+
+        let too_much = "[sources.\"LOBBY\"]\nmotion = true\nmotion_threshold = 5.0";
+        assert!(Config::parse(too_much).unwrap_err().contains("motion_threshold"));
+    }
+
+    #[test]
+    fn public_profile_caps_every_source() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(
+            "fast".to_string(),
+            SourceOverride { max_fps: Some(60), max_width: Some(1920), ..Default::default() },
+        );
+        let settings = SourceSettings::new(EncodeSettings::new(75, 0), overrides).public_readonly();
+        for source in ["fast", "other"] {
+            let s = settings.for_source(source);
+            assert_eq!(s.max_fps, PUBLIC_MAX_FPS);
+            assert_eq!(s.max_width, Some(PUBLIC_MAX_WIDTH));
+            assert!(s.low_bandwidth);
+        }
+    }
+
+    #[test]
+    fn grants_convert_with_labels() {
+        let config = Config::parse(
+            r#"
+            [[grants]]
+            token = "abc"
+            label = "Agency"
+            actions = ["view"]
+            "#,
+        )
+        .unwrap();
+        let grant = config.grants[0].to_grant().unwrap();
+        assert_eq!(grant.label.as_deref(), Some("Agency"));
+        assert_eq!(grant.actions, [Action::View]);
+        assert_eq!(grant.sources, None);
+        assert!(Config::parse("[[grants]]\ntoken = \"a\"\nactions = [\"fly\"]").is_err());
+    }
+
+    #[test]
+    fn environment_overrides_top_level_keys() {
+        let vars = |pairs: &[(&str, &str)]| {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>()
+        };
+        let config = Config::load_with_env(
+            None,
+            vars(&[
+                ("STREAMBRIDGE_PORT", "8080"),
+                ("STREAMBRIDGE_ENCODER", "turbojpeg"),
+                ("STREAMBRIDGE_GRANTS", r#"[{ token = "s3cret", actions = ["view"] }]"#),
+                ("STREAMBRIDGE_SOURCES", r#"{ "CAM (1)" = { max_fps = 5 } }"#),
+                ("STREAMBRIDGE_CONFIG", "ignored.toml"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.encoder, Some(EncoderKind::Turbojpeg));
+        assert_eq!(config.grants[0].token, "s3cret");
+        assert_eq!(config.sources["CAM (1)"].max_fps, Some(5));
+
+        let error = Config::load_with_env(None, vars(&[("STREAMBRIDGE_PROT", "1")])).unwrap_err();
+        assert!(error.contains("STREAMBRIDGE_PROT"), "{error}");
+        let error = Config::load_with_env(None, vars(&[("STREAMBRIDGE_JPEG_QUALITY", "0")]));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_quality() {
+        assert!(Config::parse("prot = 1").is_err());
+        assert!(Config::parse("[sources.a]\njpeg_quality = 0").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Diagnostic bundles for bug reports from the field. On a panic or a fatal
+//! error the recent log, the configuration with secrets redacted, the sources'
+//! stats and the NDI® runtime version go into one text file, and its path is
+//! printed for the user to attach.
+
+use crate::config::{CONFIG_ENV, ENV_PREFIX};
+use crate::logs::LogBuffer;
+use crate::receiver::ReceiverManager;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How many log lines a report carries.
+const LOG_LINES: usize = 500;
+/// What redacted values are replaced with.
+const REDACTED: &str = "<redacted>";
+
+/// Writes crash reports into a directory.
+pub struct CrashReporter {
+    dir: PathBuf,
+    log: LogBuffer,
+    config: String,
+    started: Instant,
+    ndi_version: Mutex<Option<String>>,
+    manager: Mutex<Option<Arc<ReceiverManager>>>,
+}
+
+impl CrashReporter {
+    /// Reports into `dir`, with `log` and a [`config_snapshot`].
+    pub fn new(dir: PathBuf, log: LogBuffer, config: String) -> Arc<Self> {
+        Arc::new(Self {
+            dir,
+            log,
+            config,
+            started: Instant::now(),
+            ndi_version: Mutex::new(None),
+            manager: Mutex::new(None),
+        })
+    }
+
+    pub fn set_ndi_version(&self, version: &str) {
+        *self.ndi_version.lock().unwrap() = Some(version.to_string());
+    }
+
+    /// Include the stats of `manager`'s receivers.
+    pub fn watch(&self, manager: Arc<ReceiverManager>) {
+        *self.manager.lock().unwrap() = Some(manager);
+    }
+
+    /// Report every panic, on whichever thread, after the usual message.
+    pub fn install_panic_hook(self: &Arc<Self>) {
+        let reporter = Arc::clone(self);
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let thread = std::thread::current();
+            let name = thread.name().unwrap_or("unnamed");
+            reporter.report(&format!("panic in thread '{name}': {info}"));
+        }));
+    }
+
+    /// Write a report for `reason` and print where it went.
+    pub fn report(&self, reason: &str) -> Option<PathBuf> {
+        match self.write(reason) {
+            Ok(path) => {
+                let path_text = path.display();
+                eprintln!("crash report written to {path_text}; please attach it to bug reports");
+                Some(path)
+            }
+            Err(e) => {
+                eprintln!("failed to write a crash report to {}: {e}", self.dir.display());
+                None
+            }
+        }
+    }
+
+    fn write(&self, reason: &str) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let path = self.dir.join(format!("streambridge-crash-{secs}-{}.txt", std::process::id()));
+        std::fs::write(&path, self.contents(reason, secs))?;
+        Ok(path)
+    }
+
+    fn contents(&self, reason: &str, secs: u64) -> String {
+        let mut text = String::new();
+        let ndi = self.ndi_version.try_lock().ok().and_then(|v| v.clone());
+        let _ = writeln!(text, "streambridge {} crash report", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(text, "reason: {reason}");
+        let _ = writeln!(text, "time: {secs} (unix)");
+        let _ = writeln!(text, "uptime: {}s", self.started.elapsed().as_secs());
+        let _ = writeln!(text, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+        let _ = writeln!(text, "ndi runtime: {}", ndi.as_deref().unwrap_or("not loaded"));
+
+        let lines = self.log.lines(LOG_LINES);
+        let _ = writeln!(text, "\n== log (last {} lines)", lines.len());
+        for line in lines {
+            let _ = writeln!(text, "{line}");
+        }
+        let _ = writeln!(text, "\n== config\n{}", self.config);
+        let _ = writeln!(text, "== stats");
+        // A panicking thread may hold the receiver list; don't wait for it.
+        let manager = self.manager.try_lock().ok().and_then(|m| m.clone());
+        match manager.map(|m| m.try_active_stats()) {
+            None => text.push_str("no receivers started\n"),
+            Some(None) => text.push_str("unavailable: the receiver list is locked\n"),
+            Some(Some(stats)) if stats.is_empty() => text.push_str("no active receivers\n"),
+            Some(Some(stats)) => {
+                for (name, stats) in stats {
+                    let _ = writeln!(text, "[{name}] {}", stats.summary());
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Where reports go unless `--crash-dir` says otherwise.
+pub fn default_dir() -> PathBuf {
+    std::env::temp_dir().join("streambridge")
+}
+
+/// The configuration as the bridge got it, for a report: the config file, the
+/// `STREAMBRIDGE_*` variables and the command line, with tokens, secrets and
+/// passwords redacted.
+pub fn config_snapshot(
+    path: Option<&Path>,
+    vars: impl IntoIterator<Item = (String, String)>,
+    args: impl IntoIterator<Item = String>,
+) -> String {
+    let mut text = String::new();
+    let args: Vec<String> = redact_args(args).collect();
+    let _ = writeln!(text, "command line: {}", args.join(" "));
+    for (name, value) in vars {
+        if name.starts_with(ENV_PREFIX) && name != CONFIG_ENV {
+            let value = if is_secret(&name) || is_secret(&value) { REDACTED } else { &value };
+            let _ = writeln!(text, "{name}={value}");
+        }
+    }
+    let Some(path) = path else {
+        return text;
+    };
+    let _ = writeln!(text, "--- {}", path.display());
+    match std::fs::read_to_string(path).map(|file| file.parse::<toml::Table>()) {
+        Ok(Ok(mut table)) => {
+            redact_table(&mut table);
+            text.push_str(&table.to_string());
+        }
+        Ok(Err(e)) => {
+            let _ = writeln!(text, "not valid TOML: {e}");
+        }
+        Err(e) => {
+            let _ = writeln!(text, "unreadable: {e}");
+        }
+    }
+    text
+}
+
+fn is_secret(text: &str) -> bool {
+    let text = text.to_ascii_lowercase();
+    ["token", "secret", "password"].iter().any(|word| text.contains(word))
+}
+
+fn redact_table(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        if is_secret(key) {
+            *value = toml::Value::String(REDACTED.to_string());
+        } else {
+            redact_value(value);
+        }
+    }
+}
+
+fn redact_value(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => redact_table(table),
+        toml::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// The command line with the tokens in `--grant` and `--api-token` redacted.
+fn redact_args(args: impl IntoIterator<Item = String>) -> impl Iterator<Item = String> {
+    const SECRET_FLAGS: [&str; 2] = ["--grant", "--api-token"];
+    let mut redact_next = false;
+    args.into_iter().map(move |arg| {
+        if std::mem::take(&mut redact_next) {
+            return REDACTED.to_string();
+        }
+        if let Some((flag, _)) = arg.split_once('=').filter(|(f, _)| SECRET_FLAGS.contains(f)) {
+            return format!("{flag}={REDACTED}");
+        }
+        redact_next = SECRET_FLAGS.contains(&arg.as_str());
+        arg
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_redact_secrets() {
+        let dir = std::env::temp_dir().join(format!("sb-crash-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("streambridge.toml");
+        let config = "port = 9550\n\n[[grants]]\ntoken = \"s3cret\"\nactions = [\"view\"]\n";
+        std::fs::write(&path, config).unwrap();
+        let vars = [
+            ("STREAMBRIDGE_PORT".to_string(), "8080".to_string()),
+            ("STREAMBRIDGE_GRANTS".to_string(), "[{token = \"hush\"}]".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let args = ["streambridge", "--grant", "abc:*:*", "--api-token=xyz", "--port", "1"];
+        let snapshot = config_snapshot(Some(&path), vars, args.map(String::from));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for secret in ["s3cret", "hush", "abc", "xyz"] {
+            assert!(!snapshot.contains(secret), "{secret} in {snapshot}");
+        }
+        assert!(snapshot.contains("STREAMBRIDGE_PORT=8080"), "{snapshot}");
+        assert!(snapshot.contains("--api-token=<redacted>"), "{snapshot}");
+        assert!(snapshot.contains("port = 9550"), "{snapshot}");
+        assert!(snapshot.contains("actions = [\"view\"]"), "{snapshot}");
+        assert!(!snapshot.contains("HOME"), "{snapshot}");
+    }
+
+    #[test]
+    fn reports_carry_the_recent_log() {
+        let log = LogBuffer::new(LOG_LINES * 2);
+        for i in 0..LOG_LINES + 10 {
+            log.push(tracing::Level::INFO, "streambridge", format!("line {i}"));
+        }
+        let dir = std::env::temp_dir().join(format!("sb-crash-report-{}", std::process::id()));
+        let reporter = CrashReporter::new(dir.clone(), log, "port = 9550\n".to_string());
+        reporter.set_ndi_version("NDI SDK 6.0");
+        let path = reporter.report("panic in thread 'main': boom").expect("written");
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(report.contains("reason: panic in thread 'main': boom"), "{report}");
+        assert!(report.contains("ndi runtime: NDI SDK 6.0"), "{report}");
+        assert!(report.contains(&format!(": line {}\n", LOG_LINES + 9)), "{report}");
+        assert!(!report.contains(": line 9\n"), "oldest lines dropped: {report}");
+        assert!(report.contains("port = 9550"), "{report}");
+    }
+}
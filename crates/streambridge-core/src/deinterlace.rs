@@ -0,0 +1,197 @@
+//! Interlaced NDI® sources made progressive before encoding. Senders deliver
+//! interlaced video as woven frames, whose fields comb wherever there is
+//! motion, or as separate half-height fields to receivers that allow them,
+//! which StreamBridge's do.
+
+use crate::ndi::{FourCCVideoType, FrameFormat};
+use crate::pipeline::VideoFrame;
+use serde::Deserialize;
+
+/// How interlaced video is made progressive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deinterlace {
+    /// Every field a frame of its own, the missing lines interpolated: smooth
+    /// motion and no combing, at half the vertical detail.
+    #[default]
+    Bob,
+    /// Fields woven back into frames: full detail, combing on motion.
+    Weave,
+    /// Frames and fields passed on as they arrive.
+    Off,
+}
+
+impl Deinterlace {
+    /// Parse a `--deinterlace` value.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "bob" => Ok(Self::Bob),
+            "weave" => Ok(Self::Weave),
+            "off" => Ok(Self::Off),
+            other => Err(format!("unknown deinterlace mode \"{other}\" (bob, weave or off)")),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bob => "bob",
+            Self::Weave => "weave",
+            Self::Off => "off",
+        }
+    }
+}
+
+/// Makes one source's frames progressive, reusing its buffers.
+pub struct Deinterlacer {
+    mode: Deinterlace,
+    out: Vec<u8>,
+    /// The last even field's lines, width and height, waiting to be woven with
+    /// the odd one that follows.
+    field_0: Vec<u8>,
+    field_0_size: Option<(usize, usize)>,
+}
+
+impl Deinterlacer {
+    pub fn new(mode: Deinterlace) -> Self {
+        Self { mode, out: Vec::new(), field_0: Vec::new(), field_0_size: None }
+    }
+
+    /// `frame`, laid out as `format` says, made progressive; `None` while an even
+    /// field waits for its odd one. Planar frames are passed on as they are.
+    pub fn process<'a>(
+        &'a mut self,
+        frame: VideoFrame<'a>,
+        format: FrameFormat,
+    ) -> Option<VideoFrame<'a>> {
+        let Some(bytes_per_pixel) = bytes_per_pixel(frame.fourcc) else {
+            return Some(frame);
+        };
+        let row = frame.width * bytes_per_pixel;
+        let line = |y: usize| &frame.data[y * frame.stride..][..row];
+        let height = match (self.mode, format) {
+            (Deinterlace::Bob, FrameFormat::Interleaved) => {
+                bob(|i| line(2 * i), 0, frame.height, row, &mut self.out);
+                frame.height
+            }
+            (Deinterlace::Bob, FrameFormat::Field0 | FrameFormat::Field1) => {
+                let parity = usize::from(format == FrameFormat::Field1);
+                bob(line, parity, frame.height * 2, row, &mut self.out);
+                frame.height * 2
+            }
+            (Deinterlace::Weave, FrameFormat::Field0) => {
+                self.field_0.clear();
+                (0..frame.height).for_each(|y| self.field_0.extend_from_slice(line(y)));
+                self.field_0_size = Some((frame.width, frame.height));
+                return None;
+            }
+            (Deinterlace::Weave, FrameFormat::Field1) => {
+                if self.field_0_size.take() != Some((frame.width, frame.height)) {
+                    return None;
+                }
+                self.out.clear();
+                for (even, y) in self.field_0.chunks_exact(row).zip(0..frame.height) {
+                    self.out.extend_from_slice(even);
+                    self.out.extend_from_slice(line(y));
+                }
+                frame.height * 2
+            }
+            _ => return Some(frame),
+        };
+        Some(VideoFrame {
+            data: &self.out,
+            width: frame.width,
+            height,
+            stride: row,
+            // The alpha plane isn't carried over.
+            fourcc: match frame.fourcc {
+                FourCCVideoType::UYVA => FourCCVideoType::UYVY,
+                fourcc => fourcc,
+            },
+        })
+    }
+}
+
+/// Bytes per pixel of the packed formats, or of UYVA's colour plane.
+fn bytes_per_pixel(fourcc: FourCCVideoType) -> Option<usize> {
+    match fourcc {
+        FourCCVideoType::UYVY | FourCCVideoType::UYVA => Some(2),
+        FourCCVideoType::BGRA
+        | FourCCVideoType::BGRX
+        | FourCCVideoType::RGBA
+        | FourCCVideoType::RGBX => Some(4),
+        _ => None,
+    }
+}
+
+/// A `height`-line frame in `out`, with the field's `line`s on the lines of
+/// `parity` (0 for even) and the lines between them averaged from their
+/// neighbours.
+fn bob<'f>(
+    line: impl Fn(usize) -> &'f [u8],
+    parity: usize,
+    height: usize,
+    row: usize,
+    out: &mut Vec<u8>,
+) {
+    out.clear();
+    for y in 0..height {
+        if y % 2 == parity {
+            out.extend_from_slice(line(y / 2));
+            continue;
+        }
+        let above = y.checked_sub(1).map(|a| line(a / 2));
+        let below = Some(y + 1).filter(|&b| b < height).map(|b| line(b / 2));
+        match (above, below) {
+            (Some(a), Some(b)) => {
+                let average = |(&a, &b): (&u8, &u8)| {
+                    (u16::from(a) + u16::from(b)).div_ceil(2) as u8
+                };
+                out.extend(a.iter().zip(b).map(average));
+            }
+            (Some(only), None) | (None, Some(only)) => out.extend_from_slice(only),
+            (None, None) => out.resize(out.len() + row, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A UYVY frame two pixels wide whose lines are all `values`.
+    fn frame(values: &[u8]) -> Vec<u8> {
+        values.iter().flat_map(|&v| [v; 4]).collect()
+    }
+
+    fn view(data: &[u8], height: usize) -> VideoFrame<'_> {
+        VideoFrame { data, width: 2, height, stride: 4, fourcc: FourCCVideoType::UYVY }
+    }
+
+    fn lines(frame: &VideoFrame) -> Vec<u8> {
+        frame.data.chunks_exact(frame.stride).map(|line| line[0]).collect()
+    }
+
+    #[test]
+    fn fields_are_bobbed_or_woven_into_full_height_frames() {
+        let (even, odd, woven) = (frame(&[10, 30]), frame(&[20, 40]), frame(&[10, 90, 30, 90]));
+
+        let mut bob = Deinterlacer::new(Deinterlace::Bob);
+        let out = bob.process(view(&even, 2), FrameFormat::Field0).unwrap();
+        assert_eq!(lines(&out), [10, 20, 30, 30]);
+        let out = bob.process(view(&odd, 2), FrameFormat::Field1).unwrap();
+        assert_eq!(lines(&out), [20, 20, 30, 40]);
+        let out = bob.process(view(&woven, 4), FrameFormat::Interleaved).unwrap();
+        assert_eq!(lines(&out), [10, 20, 30, 30]);
+
+        let mut weave = Deinterlacer::new(Deinterlace::Weave);
+        assert!(weave.process(view(&odd, 2), FrameFormat::Field1).is_none());
+        assert!(weave.process(view(&even, 2), FrameFormat::Field0).is_none());
+        let out = weave.process(view(&odd, 2), FrameFormat::Field1).unwrap();
+        assert_eq!((lines(&out), out.height), (vec![10, 20, 30, 40], 4));
+        let out = weave.process(view(&woven, 4), FrameFormat::Interleaved).unwrap();
+        assert_eq!(lines(&out), [10, 90, 30, 90]);
+
+        let mut off = Deinterlacer::new(Deinterlace::Off);
+        assert_eq!(off.process(view(&even, 2), FrameFormat::Field0).unwrap().height, 2);
+    }
+}
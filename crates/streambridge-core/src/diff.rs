@@ -0,0 +1,151 @@
+//! Frame-difference diagnostics: the amplified absolute luma difference between
+//! consecutive frames, as a grayscale JPEG. Static content goes black, so
+//! flickering graphics, dropped fields and rolling-shutter skew stand out.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+
+/// How much luma differences are multiplied by before display.
+pub const DIFF_GAIN: u16 = 8;
+
+/// Turns a source's frames into difference images, one per frame after the first.
+pub struct FrameDiff {
+    /// Luma of the previous frame.
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    size: (usize, usize),
+    compressor: turbojpeg::Compressor,
+    quality: i32,
+}
+
+impl FrameDiff {
+    pub fn new() -> Result<Self, String> {
+        let mut compressor =
+            turbojpeg::Compressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        compressor
+            .set_subsamp(turbojpeg::Subsamp::Gray)
+            .map_err(|e| format!("turbojpeg error: {e}"))?;
+        Ok(Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            size: (0, 0),
+            compressor,
+            quality: -1,
+        })
+    }
+
+    /// Take `frame`, keeping every `factor`th pixel and row, and return the JPEG
+    /// of its difference to the previous frame. `None` for the first frame, or
+    /// after the size changed.
+    pub fn process(
+        &mut self,
+        frame: &VideoFrame,
+        factor: usize,
+        quality: i32,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let size = luma(frame, factor, &mut self.current)?;
+        std::mem::swap(&mut self.previous, &mut self.current);
+        if std::mem::replace(&mut self.size, size) != size {
+            return Ok(None);
+        }
+        for (diff, &now) in self.current.iter_mut().zip(&self.previous) {
+            *diff = (diff.abs_diff(now) as u16 * DIFF_GAIN).min(255) as u8;
+        }
+        if quality != self.quality {
+            self.compressor
+                .set_quality(quality)
+                .map_err(|e| format!("turbojpeg error: {e}"))?;
+            self.quality = quality;
+        }
+        let image = turbojpeg::Image {
+            pixels: &self.current[..],
+            width: size.0,
+            pitch: size.0,
+            height: size.1,
+            format: turbojpeg::PixelFormat::GRAY,
+        };
+        self.compressor
+            .compress_to_vec(image)
+            .map(Some)
+            .map_err(|e| format!("turbojpeg compress error: {e}"))
+    }
+}
+
+/// The luma plane of `frame`, keeping every `factor`th pixel and row. Returns its size.
+pub(crate) fn luma(
+    frame: &VideoFrame,
+    factor: usize,
+    out: &mut Vec<u8>,
+) -> Result<(usize, usize), String> {
+    let factor = factor.max(1);
+    let (w, h) = (frame.width / factor, frame.height / factor);
+    out.resize(w * h, 0);
+    // Byte offset of the first pixel's luma (or R, G, B) and the bytes per pixel.
+    let (offset, bytes, rgb): (usize, usize, Option<[usize; 3]>) = match frame.fourcc {
+        FourCCVideoType::UYVY | FourCCVideoType::UYVA => (1, 2, None),
+        FourCCVideoType::BGRA | FourCCVideoType::BGRX => (0, 4, Some([2, 1, 0])),
+        FourCCVideoType::RGBA | FourCCVideoType::RGBX => (0, 4, Some([0, 1, 2])),
+        other => return Err(format!("unsupported FourCC: {other:?}")),
+    };
+    for (y, row) in out.chunks_exact_mut(w.max(1)).enumerate().take(h) {
+        let src = &frame.data[y * factor * frame.stride..];
+        for (x, dst) in row.iter_mut().enumerate() {
+            let px = x * factor * bytes + offset;
+            *dst = match rgb {
+                None => src[px],
+                // BT.601 weights, in 1/256ths.
+                Some([r, g, b]) => {
+                    let (r, g, b) = (src[px + r] as u32, src[px + g] as u32, src[px + b] as u32);
+                    ((77 * r + 150 * g + 29 * b) >> 8) as u8
+                }
+            };
+        }
+    }
+    Ok((w, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uyvy(w: usize, h: usize, luma_at: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let mut data = vec![128u8; w * 2 * h];
+        for y in 0..h {
+            for x in 0..w {
+                data[y * w * 2 + x * 2 + 1] = luma_at(x, y);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn amplifies_changes_between_frames() {
+        let (w, h) = (32, 16);
+        let still = uyvy(w, h, |_, _| 100);
+        // One row flickers by 10 levels.
+        let flicker = uyvy(w, h, |_, y| if y == 8 { 110 } else { 100 });
+        let fourcc = FourCCVideoType::UYVY;
+        let frame = |data| VideoFrame { data, width: w, height: h, stride: w * 2, fourcc };
+
+        let mut diff = FrameDiff::new().unwrap();
+        assert_eq!(diff.process(&frame(&still), 1, 90).unwrap(), None);
+        assert!(diff.process(&frame(&flicker), 1, 90).unwrap().is_some());
+        assert_eq!(diff.current[8 * w], 80);
+        assert_eq!(diff.current[0], 0);
+
+        // Scaled frames have a different size, so they start over.
+        assert_eq!(diff.process(&frame(&still), 2, 90).unwrap(), None);
+    }
+
+    #[test]
+    fn luma_of_rgb_formats_matches() {
+        let bgra = [10u8, 200, 60, 255];
+        let rgba = [60u8, 200, 10, 255];
+        let mut out = Vec::new();
+        for (data, fourcc) in [(&bgra, FourCCVideoType::BGRA), (&rgba, FourCCVideoType::RGBA)] {
+            let frame = VideoFrame { data, width: 1, height: 1, stride: 4, fourcc };
+            luma(&frame, 1, &mut out).unwrap();
+            assert_eq!(out, [136]);
+        }
+    }
+}
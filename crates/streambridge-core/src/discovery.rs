@@ -0,0 +1,395 @@
+use crate::events::{Event, EventBus};
+use crate::idle::Activity;
+use crate::ndi::{FindInstance, FindSettings, Source};
+use crate::runtime::NdiRuntime;
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+pub type SourceList = Arc<RwLock<Vec<Source>>>;
+
+/// The NDI® groups each discovered source was found in, by source name.
+pub type SourceGroups = Arc<RwLock<HashMap<String, BTreeSet<String>>>>;
+
+/// The group NDI® senders and finders use when none is configured.
+pub const DEFAULT_GROUP: &str = "public";
+
+/// How often an idle server looks for source changes.
+const IDLE_INTERVAL: Duration = Duration::from_secs(15);
+/// How often discovery checks whether a quarantined NDI® runtime is back.
+const RUNTIME_POLL: Duration = Duration::from_millis(500);
+
+/// A source's id for URLs: its name in lowercase ASCII letters, digits and
+/// dashes, then a hash of the whole name so that names differing only in other
+/// characters differ too, e.g. `studio-wide-shot-b2dc67` for
+/// `STUDIO (Wide Shot)`. The same name always gets the same id.
+pub fn source_id(name: &str) -> String {
+    let mut id = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            id.push(c.to_ascii_lowercase());
+        } else if !id.is_empty() && !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+    if !id.is_empty() && !id.ends_with('-') {
+        id.push('-');
+    }
+    // FNV-1a: unlike `DefaultHasher`, the same in every build.
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    format!("{id}{:06x}", hash & 0x00ff_ffff)
+}
+
+/// The name of the source `reference` refers to, by exact name or by id.
+pub fn resolve(sources: &[Source], reference: &str) -> Option<String> {
+    let by_name = sources.iter().find(|s| s.name == reference);
+    let by_id = || sources.iter().find(|s| source_id(&s.name) == reference);
+    by_name.or_else(by_id).map(|s| s.name.clone())
+}
+
+/// Whether `url` is an NDI® sender's `ip:port` (or `host:port`) address.
+pub fn is_sender_address(url: &str) -> bool {
+    url.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+/// A source discovery hasn't seen, to connect to directly, e.g. across VLANs or
+/// through NDI® Bridge: `name` sent from `url`. Without a URL the NDI® runtime
+/// looks the sender up itself, which takes its full `MACHINE (Source)` name.
+pub fn direct_source(name: &str, url: Option<&str>) -> Result<Source, String> {
+    if name.is_empty() {
+        return Err("no source name".to_string());
+    }
+    if let Some(url) = url.filter(|url| !is_sender_address(url)) {
+        return Err(format!("\"{url}\" isn't an ip:port address"));
+    }
+    let full_name = name
+        .strip_suffix(')')
+        .and_then(|name| name.split_once(" ("))
+        .is_some_and(|(machine, source)| !machine.trim().is_empty() && !source.is_empty());
+    if url.is_none() && !full_name {
+        return Err(format!("\"{name}\" isn't a full MACHINE (Source) name"));
+    }
+    Ok(Source { name: name.to_string(), url: url.map(str::to_string) })
+}
+
+/// Finder settings that look in `groups`, or the default group when empty,
+/// and also ask the machines at `extra_ips` for their sources.
+pub fn find_settings(groups: &[String], extra_ips: &[IpAddr]) -> FindSettings {
+    let extra_ips = extra_ips.iter().map(IpAddr::to_string).collect::<Vec<_>>().join(",");
+    FindSettings {
+        groups: (!groups.is_empty()).then(|| groups.join(",")),
+        extra_ips: (!extra_ips.is_empty()).then_some(extra_ips),
+        ..FindSettings::default()
+    }
+}
+
+/// A finder per NDI® group, made afresh when the NDI® runtime is reloaded.
+pub struct Finders {
+    runtime: Arc<NdiRuntime>,
+    settings: Vec<(String, FindSettings)>,
+    /// Empty while the runtime is quarantined.
+    finders: Vec<(String, FindInstance)>,
+    /// The runtime generation the finders were made with.
+    generation: u64,
+}
+
+impl Finders {
+    /// A finder for each group and its settings.
+    pub fn create(
+        runtime: Arc<NdiRuntime>,
+        settings: Vec<(String, FindSettings)>,
+    ) -> Result<Self, String> {
+        let generation = runtime.generation();
+        let mut finders = Self { runtime, settings, finders: Vec::new(), generation };
+        finders.finders = finders.make()?;
+        Ok(finders)
+    }
+
+    fn make(&self) -> Result<Vec<(String, FindInstance)>, String> {
+        let ndi = self.runtime.instance()?;
+        let mut finders = Vec::new();
+        for (group, settings) in &self.settings {
+            let finder = ndi.create_find_instance_with(settings).map_err(|e| e.to_string())?;
+            finders.push((group.clone(), finder));
+        }
+        Ok(finders)
+    }
+
+    /// Whether there are finders to ask. Drops them while the runtime is
+    /// quarantined, so it can be reloaded, and makes them again afterwards.
+    fn refresh(&mut self) -> bool {
+        if self.runtime.is_quarantined() {
+            self.finders.clear();
+            return false;
+        }
+        if self.finders.is_empty() || self.generation != self.runtime.generation() {
+            self.finders.clear();
+            let generation = self.runtime.generation();
+            match self.make() {
+                Ok(finders) => {
+                    self.runtime.succeeded();
+                    info!("NDI discovery restarted with the reloaded runtime");
+                    (self.finders, self.generation) = (finders, generation);
+                }
+                Err(e) => {
+                    warn!("NDI discovery: {}", e);
+                    if !self.runtime.is_quarantined() {
+                        self.runtime.failed(&e);
+                    }
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Asks the discovery thread to look for sources right away, e.g. just after a
+/// camera was switched on, instead of at its next change.
+pub struct Refresh {
+    requested: AtomicU64,
+    /// The last request a pass was made for.
+    done: watch::Sender<u64>,
+    /// Whether there is a discovery thread to ask.
+    running: AtomicBool,
+}
+
+impl Refresh {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            requested: AtomicU64::new(0),
+            done: watch::Sender::new(0),
+            running: AtomicBool::new(false),
+        })
+    }
+
+    /// Ask for a pass and wait up to `timeout` for the source list to be
+    /// updated. False if it wasn't in time; without discovery there is nothing
+    /// to wait for.
+    pub async fn run(&self, timeout: Duration) -> bool {
+        let mut done = self.done.subscribe();
+        let ticket = self.requested.fetch_add(1, Ordering::SeqCst) + 1;
+        if !self.running.load(Ordering::SeqCst) {
+            return true;
+        }
+        let finished = done.wait_for(|done| *done >= ticket);
+        let finished = tokio::time::timeout(timeout, finished).await.is_ok();
+        finished
+    }
+
+    /// The latest request not yet made a pass for.
+    fn pending(&self) -> Option<u64> {
+        let requested = self.requested.load(Ordering::SeqCst);
+        (requested > *self.done.borrow()).then_some(requested)
+    }
+
+    fn finished(&self, ticket: u64) {
+        self.done.send_replace(ticket);
+    }
+}
+
+/// Spawn a background thread that continuously discovers NDI sources with
+/// `finders`. Returns a shared source list that is updated whenever
+/// sources change, and the groups each source was found in. `pinned` sources
+/// (e.g. replays and statically configured NDI® sources) are always listed
+/// first, instead of any found with the same name, and belong to no group.
+/// Without finders the list only ever holds the pinned sources. While the NDI®
+/// runtime is quarantined, the list stays as it was. Sources
+/// appearing and disappearing are published on `events`. While `activity` is
+/// idle, changes are only looked for every fifteen seconds. After each wait
+/// that `refresh` was asked during, the list is read whether it changed or
+/// not. The thread exits, dropping the finders, within two seconds of `stop`
+/// being set.
+pub fn start_discovery(
+    finders: Option<Finders>,
+    pinned: Vec<Source>,
+    events: Arc<EventBus>,
+    activity: Arc<Activity>,
+    refresh: Arc<Refresh>,
+    stop: Arc<AtomicBool>,
+) -> (SourceList, SourceGroups, Option<JoinHandle<()>>) {
+    let sources: SourceList = Arc::new(RwLock::new(pinned.clone()));
+    let groups = SourceGroups::default();
+    let Some(mut finders) = finders else {
+        return (sources, groups, None);
+    };
+    let (sources_clone, groups_clone) = (sources.clone(), groups.clone());
+    refresh.running.store(true, Ordering::SeqCst);
+
+    let thread = thread::Builder::new()
+        .name("ndi-discovery".into())
+        .spawn(move || {
+            info!("NDI discovery thread started");
+            while !stop.load(Ordering::Relaxed) {
+                if !finders.refresh() {
+                    thread::sleep(RUNTIME_POLL);
+                    continue;
+                }
+                let timeout = if activity.is_idle() {
+                    pause_while_idle(&activity, &stop);
+                    0
+                } else {
+                    2000
+                };
+                // The first finder waits; the others are only asked.
+                let mut changed = false;
+                for (i, (_, find)) in finders.finders.iter().enumerate() {
+                    changed |= find.wait_for_sources(if i == 0 { timeout } else { 0 });
+                }
+                let ticket = refresh.pending();
+                if changed || ticket.is_some() {
+                    let found = finders
+                        .finders
+                        .iter()
+                        .map(|(group, find)| (group.as_str(), find.get_current_sources()));
+                    let (current, groups) = merge(&pinned, found);
+                    debug!("discovered {} NDI source(s)", groups.len());
+                    let changes = {
+                        let mut list = sources_clone.write().unwrap();
+                        let changes = changes(&list, &current);
+                        *list = current;
+                        changes
+                    };
+                    *groups_clone.write().unwrap() = groups;
+                    for event in changes {
+                        events.publish(event);
+                    }
+                }
+                if let Some(ticket) = ticket {
+                    refresh.finished(ticket);
+                }
+            }
+            // Nobody is left to answer.
+            refresh.running.store(false, Ordering::SeqCst);
+            refresh.finished(refresh.requested.load(Ordering::SeqCst));
+        })
+        .expect("failed to spawn discovery thread");
+
+    (sources, groups, Some(thread))
+}
+
+/// The pinned sources, then those found in each group once each, with the
+/// groups each of those was found in.
+fn merge<'a>(
+    pinned: &[Source],
+    found: impl Iterator<Item = (&'a str, Vec<Source>)>,
+) -> (Vec<Source>, HashMap<String, BTreeSet<String>>) {
+    let mut sources = pinned.to_vec();
+    let mut groups: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for (group, found) in found {
+        for source in found.into_iter().filter(|s| !pinned.iter().any(|p| p.name == s.name)) {
+            groups.entry(source.name.clone()).or_default().insert(group.to_string());
+            if !sources.iter().any(|s| s.name == source.name) {
+                sources.push(source);
+            }
+        }
+    }
+    (sources, groups)
+}
+
+/// Sleep for up to [`IDLE_INTERVAL`], waking early when `activity` does.
+fn pause_while_idle(activity: &Activity, stop: &AtomicBool) {
+    let until = Instant::now() + IDLE_INTERVAL;
+    while activity.is_idle() && !stop.load(Ordering::Relaxed) && Instant::now() < until {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Events for the sources in `new` but not `old`, and the other way round.
+fn changes(old: &[Source], new: &[Source]) -> Vec<Event> {
+    let removed = old.iter().filter(|s| !new.iter().any(|n| n.name == s.name));
+    let added = new.iter().filter(|s| !old.iter().any(|o| o.name == s.name));
+    removed
+        .map(|s| Event::SourceRemoved { source: s.name.clone() })
+        .chain(added.map(|s| Event::SourceAdded { source: s.name.clone() }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(names: &[&str]) -> Vec<Source> {
+        names.iter().map(|name| Source { name: name.to_string(), url: None }).collect()
+    }
+
+    #[test]
+    fn reports_added_and_removed_sources() {
+        let old = sources(&["replay", "CAM (1)", "CAM (2)"]);
+        let new = sources(&["replay", "CAM (2)", "CAM (3)"]);
+        let kinds: Vec<_> =
+            changes(&old, &new).iter().map(|e| (e.kind(), e.source().to_string())).collect();
+        assert_eq!(
+            kinds,
+            [("source_removed", "CAM (1)".to_string()), ("source_added", "CAM (3)".to_string())]
+        );
+        assert!(changes(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn ids_are_url_safe_and_name_sources_as_well_as_their_names() {
+        let id = source_id("STUDIO (Wide Shot)");
+        assert!(id.starts_with("studio-wide-shot-") && id.len() == 23, "{id}");
+        assert_eq!(source_id("STUDIO (Wide Shot)"), id);
+        assert_ne!(source_id("STUDIO (Wide-Shot)"), id);
+        assert!(source_id("カメラ #1").starts_with("1-"));
+        assert_eq!(source_id("").len(), 6);
+
+        let list = sources(&["STUDIO (Wide Shot)", "replay"]);
+        assert_eq!(resolve(&list, &id).as_deref(), Some("STUDIO (Wide Shot)"));
+        assert_eq!(resolve(&list, "replay").as_deref(), Some("replay"));
+        assert_eq!(resolve(&list, "studio-wide-shot"), None);
+    }
+
+    #[test]
+    fn direct_sources_need_an_address_or_a_full_name() {
+        let source = direct_source("Remote", Some("10.20.0.15:5961")).unwrap();
+        assert_eq!(source.url.as_deref(), Some("10.20.0.15:5961"));
+        assert_eq!(direct_source("OB VAN (Cam 2)", None).unwrap().url, None);
+        assert!(direct_source("Remote", Some("10.20.0.15")).is_err());
+        assert!(direct_source("Remote", None).is_err());
+        assert!(direct_source(" (Cam 2)", None).is_err());
+        assert!(direct_source("", Some("10.20.0.15:5961")).is_err());
+    }
+
+    #[test]
+    fn sources_in_several_groups_are_listed_once() {
+        let found = [
+            ("studio", sources(&["CAM (1)", "replay"])),
+            ("news", sources(&["CAM (2)", "CAM (1)"])),
+        ];
+        let (list, groups) = merge(&sources(&["replay"]), found.into_iter());
+        let names: Vec<_> = list.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["replay", "CAM (1)", "CAM (2)"]);
+        assert_eq!(groups["CAM (1)"], BTreeSet::from(["news".into(), "studio".into()]));
+        assert!(!groups.contains_key("replay"));
+    }
+
+    #[tokio::test]
+    async fn refreshes_wait_for_a_pass_made_after_them() {
+        let refresh = Refresh::new();
+        assert!(refresh.run(Duration::ZERO).await, "nothing to wait for without discovery");
+
+        refresh.running.store(true, Ordering::SeqCst);
+        assert!(!refresh.run(Duration::from_millis(10)).await);
+        let answering = refresh.clone();
+        let thread = thread::spawn(move || loop {
+            if let Some(ticket) = answering.pending() {
+                answering.finished(ticket);
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        });
+        assert!(refresh.run(Duration::from_secs(5)).await);
+        thread.join().unwrap();
+        assert_eq!(refresh.pending(), None);
+    }
+}
@@ -0,0 +1,1066 @@
+#[cfg(feature = "ffmpeg")]
+use crate::media::MediaReader;
+use crate::ndi::FourCCVideoType;
+use crate::pool::BufferPool;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+#[cfg(feature = "ffmpeg")]
+use std::time::Duration;
+#[cfg(feature = "ffmpeg")]
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+/// An image encoding backend: the JPEG encoder chosen with `--encoder`, or the
+/// encoder of another [`OutputFormat`].
+pub trait Encoder: Send {
+    /// Encode a frame at `quality` (1-100), into a buffer that is reused once
+    /// every clone of it is dropped.
+    fn encode(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        quality: i32,
+    ) -> Result<Bytes, String>;
+}
+
+/// The encoding backends. The hardware ones are the `ffmpeg` program's, so they
+/// need the `ffmpeg` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderKind {
+    /// libjpeg-turbo on the CPU, and libx264 for H.264.
+    #[default]
+    Turbojpeg,
+    /// Intel Quick Sync: `mjpeg_qsv` and `h264_qsv`.
+    Qsv,
+    /// VA-API on Linux: `mjpeg_vaapi` and `h264_vaapi`.
+    Vaapi,
+    /// NVIDIA NVENC: `h264_nvenc`. NVENC has no JPEG encoder, so JPEGs are
+    /// TurboJPEG's.
+    Nvenc,
+}
+
+impl EncoderKind {
+    /// Parse an `--encoder` value.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let kind = match s {
+            "turbojpeg" => Self::Turbojpeg,
+            "qsv" => Self::Qsv,
+            "vaapi" => Self::Vaapi,
+            "nvenc" => Self::Nvenc,
+            other => {
+                let available = "turbojpeg, qsv, vaapi, nvenc";
+                return Err(format!("unknown encoder \"{other}\" (available: {available})"));
+            }
+        };
+        if kind != Self::Turbojpeg && !cfg!(feature = "ffmpeg") {
+            return Err(format!("the {s} encoder needs the ffmpeg feature"));
+        }
+        Ok(kind)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Turbojpeg => "turbojpeg",
+            Self::Qsv => "qsv",
+            Self::Vaapi => "vaapi",
+            Self::Nvenc => "nvenc",
+        }
+    }
+
+    pub fn create(self) -> Result<Box<dyn Encoder>, String> {
+        match self {
+            Self::Turbojpeg | Self::Nvenc => Ok(Box::new(EncodeBuffers::new())),
+            #[cfg(feature = "ffmpeg")]
+            Self::Qsv | Self::Vaapi => Ok(Box::new(FfmpegEncoder::new(self))),
+            #[cfg(not(feature = "ffmpeg"))]
+            Self::Qsv | Self::Vaapi => Err("hardware encoders need the ffmpeg feature".to_string()),
+        }
+    }
+
+    /// `ffmpeg`'s arguments to encode video it has decoded as H.264 for a
+    /// stream, with this kind's encoder. `baseline` keeps to the constrained
+    /// baseline profile every browser decodes.
+    pub fn h264_args(self, baseline: bool) -> Vec<String> {
+        // Even sizes, as 4:2:0 needs.
+        let even = "scale=trunc(iw/2)*2:trunc(ih/2)*2";
+        let mut args: Vec<String> = match self {
+            Self::Turbojpeg => [
+                "-vf", even, "-c:v", "libx264", "-preset", "veryfast", "-tune", "zerolatency",
+                "-pix_fmt", "yuv420p",
+            ]
+            .map(String::from)
+            .to_vec(),
+            Self::Qsv => {
+                let filter = format!("{even},format=nv12");
+                ["-vf", &filter, "-c:v", "h264_qsv", "-preset", "veryfast", "-async_depth", "1"]
+                    .map(String::from)
+                    .to_vec()
+            }
+            Self::Vaapi => {
+                let filter = format!("{even},format=nv12,hwupload");
+                ["-vaapi_device", VAAPI_DEVICE, "-vf", &filter, "-c:v", "h264_vaapi"]
+                    .map(String::from)
+                    .to_vec()
+            }
+            Self::Nvenc => [
+                "-vf", even, "-c:v", "h264_nvenc", "-preset", "p1", "-tune", "ull", "-pix_fmt",
+                "yuv420p",
+            ]
+            .map(String::from)
+            .to_vec(),
+        };
+        if baseline {
+            let profile = if self == Self::Vaapi { "constrained_baseline" } else { "baseline" };
+            args.extend(["-profile:v", profile, "-bf", "0"].map(String::from));
+        }
+        args
+    }
+}
+
+/// The render node VA-API encoders open.
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// `kind` if `ffmpeg` can encode H.264 with it here, otherwise TurboJPEG's
+/// counterpart, libx264: a missing device or driver is found out once per kind
+/// by encoding a blank frame, rather than by every stream failing.
+#[cfg(feature = "ffmpeg")]
+pub async fn h264_encoder(kind: EncoderKind) -> EncoderKind {
+    use std::collections::HashMap;
+    use std::process::Stdio;
+    use std::sync::Mutex;
+    use tokio::process::Command;
+
+    static PROBED: Mutex<Option<HashMap<EncoderKind, bool>>> = Mutex::new(None);
+    if kind == EncoderKind::Turbojpeg {
+        return kind;
+    }
+    if let Some(&works) = PROBED.lock().unwrap().get_or_insert_with(HashMap::new).get(&kind) {
+        return if works { kind } else { EncoderKind::Turbojpeg };
+    }
+    let mut args: Vec<String> = ["-v", "error", "-nostdin", "-f", "lavfi", "-i", "color=s=256x144"]
+        .map(String::from)
+        .to_vec();
+    args.extend(kind.h264_args(false));
+    args.extend(["-frames:v", "1", "-f", "null", "-"].map(String::from));
+    let status = Command::new("ffmpeg")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await;
+    let works = status.is_ok_and(|status| status.success());
+    if !works {
+        warn!("the {} H.264 encoder can't start here; using libx264", kind.name());
+    }
+    PROBED.lock().unwrap().get_or_insert_with(HashMap::new).insert(kind, works);
+    if works {
+        kind
+    } else {
+        EncoderKind::Turbojpeg
+    }
+}
+
+/// What frames are sent as, asked for with `format=` on streams and snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    /// Lossy WebP at the JPEG quality: smaller at about the same quality.
+    Webp,
+    /// Lossless PNG, e.g. for stills; ignores the quality.
+    Png,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Png => "image/png",
+        }
+    }
+
+    /// An encoder for this format; JPEGs are normally made by the `--encoder`.
+    pub fn encoder(self) -> Box<dyn Encoder> {
+        match self {
+            Self::Jpeg => Box::new(EncodeBuffers::new()),
+            Self::Webp => Box::new(WebpEncoder::default()),
+            Self::Png => Box::new(PngEncoder::default()),
+        }
+    }
+}
+
+/// libwebp, lossy.
+#[derive(Default)]
+pub struct WebpEncoder {
+    rgb: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Encoder for WebpEncoder {
+    fn encode(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        quality: i32,
+    ) -> Result<Bytes, String> {
+        to_rgb(data, w, h, stride, fourcc, &mut self.rgb)?;
+        webp::Encoder::from_rgb(&self.rgb, w as u32, h as u32)
+            .encode_simple(false, quality as f32)
+            .map(|webp| self.pool.copy(&webp))
+            .map_err(|e| format!("webp encode error: {e:?}"))
+    }
+}
+
+/// PNG, lossless; fast compression over small files.
+#[derive(Default)]
+pub struct PngEncoder {
+    rgb: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Encoder for PngEncoder {
+    fn encode(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        _quality: i32,
+    ) -> Result<Bytes, String> {
+        to_rgb(data, w, h, stride, fourcc, &mut self.rgb)?;
+        let mut png = self.pool.take(0);
+        let mut encoder = png::Encoder::new(&mut *png, w as u32, h as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Fast);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgb))
+            .map_err(|e| format!("png encode error: {e}"))?;
+        Ok(png.freeze())
+    }
+}
+
+/// Convert a frame to packed RGB. UYVY is converted as a JPEG decoder converts
+/// the bridge's JPEGs, so every format shows the same colours.
+fn to_rgb(
+    data: &[u8],
+    w: usize,
+    h: usize,
+    stride: usize,
+    fourcc: FourCCVideoType,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    out.resize(w * h * 3, 0);
+    let rows = out.chunks_exact_mut(w * 3).zip(data.chunks(stride));
+    match fourcc {
+        FourCCVideoType::UYVY => {
+            for (dst, src) in rows {
+                for (rgb, mp) in dst.chunks_exact_mut(6).zip(src.chunks_exact(4)) {
+                    let (cb, cr) = (i32::from(mp[0]) - 128, i32::from(mp[2]) - 128);
+                    for (px, y) in rgb.chunks_exact_mut(3).zip([mp[1], mp[3]]) {
+                        // Full-range BT.601 in 8.8 fixed point.
+                        let y = i32::from(y) << 8;
+                        let clamp = |v: i32| ((v + 128) >> 8).clamp(0, 255) as u8;
+                        px[0] = clamp(y + 359 * cr);
+                        px[1] = clamp(y - 88 * cb - 183 * cr);
+                        px[2] = clamp(y + 454 * cb);
+                    }
+                }
+            }
+        }
+        FourCCVideoType::BGRA | FourCCVideoType::BGRX => {
+            for (dst, src) in rows {
+                for (px, bgra) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                    px.copy_from_slice(&[bgra[2], bgra[1], bgra[0]]);
+                }
+            }
+        }
+        FourCCVideoType::RGBA | FourCCVideoType::RGBX => {
+            for (dst, src) in rows {
+                for (px, rgba) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                    px.copy_from_slice(&rgba[..3]);
+                }
+            }
+        }
+        other => return Err(format!("unsupported FourCC: {other:?}")),
+    }
+    Ok(())
+}
+
+/// Reusable encoding buffers to avoid per-frame allocation.
+pub struct EncodeBuffers {
+    /// Contiguous YUV buffer for turbojpeg: [Y][U][V]
+    pub yuv_buf: Vec<u8>,
+    /// Room for the largest JPEG a frame can make. Frames are copied out of it at
+    /// their own size, so the JPEGs clients hold don't pin this much memory each.
+    jpeg_buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+    compressor: turbojpeg::Compressor,
+    last_w: usize,
+    last_h: usize,
+    last_quality: i32,
+}
+
+impl EncodeBuffers {
+    pub fn new() -> Self {
+        Self {
+            yuv_buf: Vec::new(),
+            jpeg_buf: Vec::new(),
+            pool: BufferPool::new(),
+            compressor: turbojpeg::Compressor::new().expect("failed to create turbojpeg compressor"),
+            last_w: 0,
+            last_h: 0,
+            last_quality: -1,
+        }
+    }
+
+    /// Ensure buffers are sized for the given dimensions (4:2:0).
+    fn ensure_capacity(&mut self, w: usize, h: usize) {
+        if w != self.last_w || h != self.last_h {
+            self.yuv_buf.resize(w * h + (w / 2) * (h / 2) * 2, 0);
+            self.last_w = w;
+            self.last_h = h;
+        }
+    }
+
+    fn set_quality(&mut self, quality: i32) {
+        if quality != self.last_quality {
+            self.compressor.set_quality(quality).expect("failed to set quality");
+            self.last_quality = quality;
+        }
+    }
+}
+
+/// Compress a `w` x `h` image with `compress` into `jpeg_buf`, then copy the
+/// JPEG into a buffer from `pool`.
+fn compress_pooled(
+    compressor: &mut turbojpeg::Compressor,
+    jpeg_buf: &mut Vec<u8>,
+    pool: &Arc<BufferPool>,
+    (w, h): (usize, usize),
+    compress: impl FnOnce(&mut turbojpeg::Compressor, &mut [u8]) -> turbojpeg::Result<usize>,
+) -> Result<Bytes, String> {
+    let max_len = compressor.buf_len(w, h).map_err(|e| format!("turbojpeg error: {e}"))?;
+    if jpeg_buf.len() < max_len {
+        jpeg_buf.resize(max_len, 0);
+    }
+    let len = compress(compressor, jpeg_buf)
+        .map_err(|e| format!("turbojpeg compress error: {e}"))?;
+    Ok(pool.copy(&jpeg_buf[..len]))
+}
+
+impl Encoder for EncodeBuffers {
+    fn encode(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        quality: i32,
+    ) -> Result<Bytes, String> {
+        encode_frame(data, w, h, stride, fourcc, quality, self)
+    }
+}
+
+impl Default for EncodeBuffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How long a hardware encoder may take over a frame, and over its first,
+/// which includes starting `ffmpeg` and opening the device.
+#[cfg(feature = "ffmpeg")]
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+#[cfg(feature = "ffmpeg")]
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A hardware JPEG encoder that a long-running `ffmpeg` drives: frames go to
+/// it as raw video on stdin and come back as JPEGs on stdout. A change of size,
+/// pixel format or quality starts it over. Once it fails, e.g. without the
+/// device it needs, TurboJPEG takes over for good.
+#[cfg(feature = "ffmpeg")]
+pub struct FfmpegEncoder {
+    kind: EncoderKind,
+    process: Option<FfmpegProcess>,
+    /// Frames with padded rows, packed.
+    packed: Vec<u8>,
+    pool: Arc<BufferPool>,
+    fallback: Option<EncodeBuffers>,
+}
+
+/// A running `ffmpeg` and what it encodes.
+#[cfg(feature = "ffmpeg")]
+struct FfmpegProcess {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    /// The JPEGs its stdout is split into, by a thread of its own.
+    jpegs: std::sync::mpsc::Receiver<Vec<u8>>,
+    format: (usize, usize, FourCCVideoType, i32),
+    started: bool,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl FfmpegEncoder {
+    pub fn new(kind: EncoderKind) -> Self {
+        Self { kind, process: None, packed: Vec::new(), pool: BufferPool::new(), fallback: None }
+    }
+
+    /// `ffmpeg`'s arguments to read `w`x`h` frames of `pix_fmt` on stdin and
+    /// write them as JPEGs of `quality` to stdout.
+    fn ffmpeg_args(&self, w: usize, h: usize, pix_fmt: &str, quality: i32) -> Vec<String> {
+        let mut args: Vec<String> =
+            ["-v", "error", "-nostdin", "-f", "rawvideo", "-pix_fmt"].map(String::from).to_vec();
+        args.extend([pix_fmt.to_string(), "-video_size".to_string(), format!("{w}x{h}")]);
+        args.extend(["-i", "-", "-an", "-fps_mode", "passthrough"].map(String::from));
+        let codec = match self.kind {
+            EncoderKind::Qsv => ["-vf", "format=nv12", "-c:v", "mjpeg_qsv", "-async_depth", "1"],
+            _ => [
+                "-vaapi_device", VAAPI_DEVICE, "-vf", "format=nv12,hwupload", "-c:v", "mjpeg_vaapi",
+            ],
+        };
+        args.extend(codec.iter().map(|arg| arg.to_string()));
+        args.extend(["-global_quality".to_string(), quality.to_string()]);
+        args.extend(["-flush_packets", "1", "-f", "mjpeg", "-"].map(String::from));
+        args
+    }
+
+    fn spawn(&self, format: (usize, usize, FourCCVideoType, i32)) -> Result<FfmpegProcess, String> {
+        use std::io::BufReader;
+        use std::process::{Command, Stdio};
+
+        let (w, h, fourcc, quality) = format;
+        let pix_fmt = match fourcc {
+            FourCCVideoType::UYVY => "uyvy422",
+            FourCCVideoType::BGRA => "bgra",
+            FourCCVideoType::BGRX => "bgr0",
+            FourCCVideoType::RGBA => "rgba",
+            FourCCVideoType::RGBX => "rgb0",
+            other => return Err(format!("unsupported FourCC: {other:?}")),
+        };
+        let mut child = Command::new("ffmpeg")
+            .args(self.ffmpeg_args(w, h, pix_fmt, quality))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("failed to run ffmpeg: {e}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, jpegs) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name(format!("{}-jpegs", self.kind.name()))
+            .spawn(move || {
+                let mut reader = MediaReader::mjpeg(Box::new(BufReader::new(stdout)));
+                while let Ok(Some(jpeg)) = reader.next_frame() {
+                    if tx.send(jpeg).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(|e| format!("failed to spawn a thread: {e}"))?;
+        Ok(FfmpegProcess { child, stdin, jpegs, format, started: false })
+    }
+
+    fn encode_hardware(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        quality: i32,
+    ) -> Result<Bytes, String> {
+        use std::io::Write;
+
+        check_frame(data, w, h, stride, fourcc)?;
+        let format = (w, h, fourcc, quality);
+        if self.process.as_ref().is_none_or(|process| process.format != format) {
+            self.process = None;
+            self.process = Some(self.spawn(format)?);
+        }
+        let process = self.process.as_mut().expect("just started");
+        let row = fourcc.default_stride(w);
+        let frame = if stride == row {
+            &data[..row * h]
+        } else {
+            self.packed.clear();
+            data.chunks(stride).take(h).for_each(|line| self.packed.extend(&line[..row]));
+            &self.packed
+        };
+        process.stdin.write_all(frame).map_err(|_| "ffmpeg exited".to_string())?;
+        let timeout = if process.started { FRAME_TIMEOUT } else { START_TIMEOUT };
+        let jpeg = process.jpegs.recv_timeout(timeout).map_err(|e| match e {
+            std::sync::mpsc::RecvTimeoutError::Timeout => "ffmpeg timed out".to_string(),
+            std::sync::mpsc::RecvTimeoutError::Disconnected => "ffmpeg exited".to_string(),
+        })?;
+        process.started = true;
+        Ok(self.pool.copy(&jpeg))
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Encoder for FfmpegEncoder {
+    fn encode(
+        &mut self,
+        data: &[u8],
+        w: usize,
+        h: usize,
+        stride: usize,
+        fourcc: FourCCVideoType,
+        quality: i32,
+    ) -> Result<Bytes, String> {
+        if self.fallback.is_none() {
+            match self.encode_hardware(data, w, h, stride, fourcc, quality) {
+                Ok(jpeg) => return Ok(jpeg),
+                Err(e) => {
+                    error!("{} encoder failed, using TurboJPEG instead: {}", self.kind.name(), e);
+                    self.process = None;
+                }
+            }
+        }
+        let fallback = self.fallback.get_or_insert_with(EncodeBuffers::new);
+        encode_frame(data, w, h, stride, fourcc, quality, fallback)
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for FfmpegProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Convert UYVY packed 4:2:2 to planar YUV 4:2:0 (averaging chroma vertically).
+/// Processes two rows at a time to avoid per-pixel branching on row parity.
+pub fn uyvy_to_yuv420_planar(
+    uyvy: &[u8],
+    stride: usize,
+    w: usize,
+    h: usize,
+    y: &mut [u8],
+    u: &mut [u8],
+    v: &mut [u8],
+) {
+    let half_w = w / 2;
+    // Process pairs of rows (even + odd)
+    for row_pair in 0..h / 2 {
+        let even_row = row_pair * 2;
+        let odd_row = even_row + 1;
+        let src_even = &uyvy[even_row * stride..];
+        let src_odd = &uyvy[odd_row * stride..];
+        let y_off_even = even_row * w;
+        let y_off_odd = odd_row * w;
+        let uv_off = row_pair * half_w;
+
+        for col in 0..half_w {
+            let i = col * 4;
+            // Extract luma from both rows
+            y[y_off_even + col * 2] = src_even[i + 1];
+            y[y_off_even + col * 2 + 1] = src_even[i + 3];
+            y[y_off_odd + col * 2] = src_odd[i + 1];
+            y[y_off_odd + col * 2 + 1] = src_odd[i + 3];
+            // Average chroma from even and odd rows
+            u[uv_off + col] = ((src_even[i] as u16 + src_odd[i] as u16) / 2) as u8;
+            v[uv_off + col] = ((src_even[i + 2] as u16 + src_odd[i + 2] as u16) / 2) as u8;
+        }
+    }
+}
+
+/// The smallest power of two to shrink a `width` x `height` frame by so it fits
+/// within `max_width` and `max_height`. A limit of 0 is no limit.
+pub fn scale_factor(
+    width: usize,
+    height: usize,
+    max_width: Option<usize>,
+    max_height: Option<usize>,
+) -> usize {
+    let fit = |size: usize, max: Option<usize>| match max {
+        Some(max) if max > 0 => size.div_ceil(max).next_power_of_two(),
+        _ => 1,
+    };
+    fit(width, max_width).max(fit(height, max_height))
+}
+
+/// Check that `data` holds a whole `w`x`h` frame in `fourcc` with rows `stride`
+/// bytes apart, so a truncated frame, e.g. from a corrupt replay file, is an
+/// error rather than a panic.
+pub fn check_frame(
+    data: &[u8],
+    w: usize,
+    h: usize,
+    stride: usize,
+    fourcc: FourCCVideoType,
+) -> Result<(), String> {
+    if stride < fourcc.default_stride(w) {
+        return Err(format!("stride {stride} too small for a {w} pixel wide {fourcc:?} frame"));
+    }
+    let size = stride.checked_mul(h).map(|_| fourcc.frame_size(w, h, stride));
+    match size {
+        Some(size) if data.len() >= size => Ok(()),
+        _ => Err(format!("{} bytes is short of a {w}x{h} {fourcc:?} frame", data.len())),
+    }
+}
+
+/// Shrink a frame by an integer `factor` (a power of two) by keeping every
+/// `factor`-th pixel and row, writing the packed result to `out`. Returns the new
+/// width, height and stride. Cheap enough for preview-sized output; no filtering.
+pub fn decimate(
+    data: &[u8],
+    w: usize,
+    h: usize,
+    stride: usize,
+    fourcc: FourCCVideoType,
+    factor: usize,
+    out: &mut Vec<u8>,
+) -> Result<(usize, usize, usize), String> {
+    check_frame(data, w, h, stride, fourcc)?;
+    let out_h = h / factor;
+    match fourcc {
+        FourCCVideoType::UYVY => {
+            // Keep whole macropixels so each output pair shares the chroma of its first pixel.
+            let out_w = (w / factor) & !1;
+            let out_stride = out_w * 2;
+            out.resize(out_stride * out_h, 0);
+            for row in 0..out_h {
+                let src = &data[row * factor * stride..];
+                let dst = &mut out[row * out_stride..(row + 1) * out_stride];
+                for (pair, mp) in dst.chunks_exact_mut(4).enumerate() {
+                    let first = pair * factor * 4;
+                    let second = (pair * 2 + 1) * factor * 2;
+                    mp.copy_from_slice(&[src[first], src[first + 1], src[first + 2], src[second + 1]]);
+                }
+            }
+            Ok((out_w, out_h, out_stride))
+        }
+        FourCCVideoType::BGRA
+        | FourCCVideoType::BGRX
+        | FourCCVideoType::RGBA
+        | FourCCVideoType::RGBX => {
+            let out_w = w / factor;
+            let out_stride = out_w * 4;
+            out.resize(out_stride * out_h, 0);
+            for row in 0..out_h {
+                let src = &data[row * factor * stride..];
+                let dst = &mut out[row * out_stride..(row + 1) * out_stride];
+                for (x, px) in dst.chunks_exact_mut(4).enumerate() {
+                    px.copy_from_slice(&src[x * factor * 4..x * factor * 4 + 4]);
+                }
+            }
+            Ok((out_w, out_h, out_stride))
+        }
+        other => Err(format!("unsupported FourCC: {other:?}")),
+    }
+}
+
+/// Encode a video frame to JPEG. Returns the JPEG, in a buffer from `buffers`'
+/// pool, or an error message.
+pub fn encode_frame(
+    data: &[u8],
+    w: usize,
+    h: usize,
+    stride: usize,
+    fourcc: FourCCVideoType,
+    quality: i32,
+    buffers: &mut EncodeBuffers,
+) -> Result<Bytes, String> {
+    check_frame(data, w, h, stride, fourcc)?;
+    buffers.set_quality(quality);
+
+    match fourcc {
+        FourCCVideoType::UYVY => {
+            buffers.ensure_capacity(w, h);
+
+            // Convert straight into the contiguous [Y][U][V] buffer turbojpeg reads.
+            let y_size = w * h;
+            let uv_size = (w / 2) * (h / 2);
+            let (y, uv) = buffers.yuv_buf.split_at_mut(y_size);
+            let (u, v) = uv.split_at_mut(uv_size);
+            uyvy_to_yuv420_planar(data, stride, w, h, y, u, v);
+
+            let yuv_image = turbojpeg::YuvImage {
+                pixels: &buffers.yuv_buf[..y_size + uv_size * 2],
+                width: w,
+                align: 1,
+                height: h,
+                subsamp: turbojpeg::Subsamp::Sub2x2,
+            };
+            compress_pooled(
+                &mut buffers.compressor,
+                &mut buffers.jpeg_buf,
+                &buffers.pool,
+                (w, h),
+                |c, out| c.compress_yuv_to_slice(yuv_image, out),
+            )
+        }
+        FourCCVideoType::BGRA | FourCCVideoType::BGRX => {
+            let image = turbojpeg::Image {
+                pixels: data,
+                width: w,
+                pitch: stride,
+                height: h,
+                format: turbojpeg::PixelFormat::BGRA,
+            };
+            compress_pooled(
+                &mut buffers.compressor,
+                &mut buffers.jpeg_buf,
+                &buffers.pool,
+                (w, h),
+                |c, out| c.compress_to_slice(image, out),
+            )
+        }
+        FourCCVideoType::RGBA | FourCCVideoType::RGBX => {
+            let image = turbojpeg::Image {
+                pixels: data,
+                width: w,
+                pitch: stride,
+                height: h,
+                format: turbojpeg::PixelFormat::RGBA,
+            };
+            compress_pooled(
+                &mut buffers.compressor,
+                &mut buffers.jpeg_buf,
+                &buffers.pool,
+                (w, h),
+                |c, out| c.compress_to_slice(image, out),
+            )
+        }
+        other => Err(format!("unsupported FourCC: {other:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: usize = 320;
+    const H: usize = 180;
+
+    /// Deterministic test card: horizontal luma ramp with a soft diagonal
+    /// pattern, and chroma that sweeps across colour bars from top to bottom.
+    fn test_card(x: usize, y: usize) -> (u8, u8, u8) {
+        let ramp = 16 + (x * 219 / W) as i32;
+        let wave = (((x + y) as f32 / 12.0).sin() * 20.0) as i32;
+        let luma = (ramp + wave).clamp(16, 235) as u8;
+        let bar = y * 8 / H;
+        let cb = [128, 44, 156, 72, 184, 100, 212, 128][bar];
+        let cr = [128, 142, 44, 58, 198, 212, 114, 128][bar];
+        (luma, cb, cr)
+    }
+
+    fn synthetic_uyvy(stride: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; stride * H];
+        for y in 0..H {
+            for pair in 0..W / 2 {
+                let (y0, cb, cr) = test_card(pair * 2, y);
+                let (y1, _, _) = test_card(pair * 2 + 1, y);
+                let i = y * stride + pair * 4;
+                buf[i..i + 4].copy_from_slice(&[cb, y0, cr, y1]);
+            }
+        }
+        buf
+    }
+
+    /// Full-range BT.601 conversion, matching what JPEG decoders assume.
+    fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> (u8, u8, u8) {
+        let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+        let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+        (
+            clamp(y + 1.402 * cr),
+            clamp(y - 0.344136 * cb - 0.714136 * cr),
+            clamp(y + 1.772 * cb),
+        )
+    }
+
+    fn synthetic_bgra(stride: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; stride * H];
+        for y in 0..H {
+            for x in 0..W {
+                let (luma, cb, cr) = test_card(x, y);
+                let (r, g, b) = ycbcr_to_rgb(luma, cb, cr);
+                let i = y * stride + x * 4;
+                buf[i..i + 4].copy_from_slice(&[b, g, r, 255]);
+            }
+        }
+        buf
+    }
+
+    fn psnr(a: &[u8], b: &[u8]) -> f64 {
+        assert_eq!(a.len(), b.len());
+        let mse = a
+            .iter()
+            .zip(b)
+            .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+            .sum::<f64>()
+            / a.len() as f64;
+        if mse == 0.0 {
+            return f64::INFINITY;
+        }
+        10.0 * (255.0f64 * 255.0 / mse).log10()
+    }
+
+    /// Mean SSIM over non-overlapping 8x8 windows.
+    fn ssim(a: &[u8], b: &[u8], w: usize, h: usize) -> f64 {
+        let (c1, c2) = ((0.01f64 * 255.0).powi(2), (0.03f64 * 255.0).powi(2));
+        let mut total = 0.0;
+        let mut windows = 0;
+        for wy in (0..h - 7).step_by(8) {
+            for wx in (0..w - 7).step_by(8) {
+                let px = |p: &[u8], dx: usize, dy: usize| p[(wy + dy) * w + wx + dx] as f64;
+                let (mut ma, mut mb) = (0.0, 0.0);
+                for dy in 0..8 {
+                    for dx in 0..8 {
+                        ma += px(a, dx, dy);
+                        mb += px(b, dx, dy);
+                    }
+                }
+                ma /= 64.0;
+                mb /= 64.0;
+                let (mut va, mut vb, mut cov) = (0.0, 0.0, 0.0);
+                for dy in 0..8 {
+                    for dx in 0..8 {
+                        let (da, db) = (px(a, dx, dy) - ma, px(b, dx, dy) - mb);
+                        va += da * da;
+                        vb += db * db;
+                        cov += da * db;
+                    }
+                }
+                va /= 63.0;
+                vb /= 63.0;
+                cov /= 63.0;
+                total += ((2.0 * ma * mb + c1) * (2.0 * cov + c2))
+                    / ((ma * ma + mb * mb + c1) * (va + vb + c2));
+                windows += 1;
+            }
+        }
+        total / windows as f64
+    }
+
+    /// Decode a JPEG to its Y, U and V planes (no row padding). Chroma planes
+    /// are only split out for 4:2:0 images; otherwise they come back empty.
+    fn decode_planes(jpeg: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let image = turbojpeg::decompress_to_yuv(jpeg).expect("decode");
+        assert_eq!((image.width, image.height), (W, H));
+        let pad = |n: usize| n.div_ceil(image.align) * image.align;
+        let (cw, ch) = (W.div_ceil(2), H.div_ceil(2));
+        let (y_stride, c_stride) = (pad(W), pad(cw));
+        let rows = |data: &[u8], stride: usize, w: usize, h: usize| -> Vec<u8> {
+            (0..h).flat_map(|r| data[r * stride..r * stride + w].to_vec()).collect()
+        };
+        let y = rows(&image.pixels, y_stride, W, H);
+        if image.subsamp != turbojpeg::Subsamp::Sub2x2 {
+            return (y, Vec::new(), Vec::new());
+        }
+        let u_off = y_stride * H;
+        let u = rows(&image.pixels[u_off..], c_stride, cw, ch);
+        let v = rows(&image.pixels[u_off + c_stride * ch..], c_stride, cw, ch);
+        (y, u, v)
+    }
+
+    fn reference_planes() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let y = (0..H).flat_map(|r| (0..W).map(move |c| test_card(c, r).0)).collect();
+        // 4:2:0 reference chroma: vertical average of each 2x2 block's row pair.
+        let chroma = |pick: fn((u8, u8, u8)) -> u8| -> Vec<u8> {
+            (0..H / 2)
+                .flat_map(|r| {
+                    (0..W / 2).map(move |c| {
+                        let top = pick(test_card(c * 2, r * 2)) as u16;
+                        let bottom = pick(test_card(c * 2, r * 2 + 1)) as u16;
+                        ((top + bottom) / 2) as u8
+                    })
+                })
+                .collect()
+        };
+        (y, chroma(|p| p.1), chroma(|p| p.2))
+    }
+
+    #[test]
+    fn uyvy_to_yuv420_splits_luma_and_averages_chroma() {
+        // 4x2 frame: two macropixels per row.
+        let uyvy = [
+            10, 1, 20, 2, 30, 3, 40, 4, //
+            12, 5, 22, 6, 34, 7, 44, 8,
+        ];
+        let (mut y, mut u, mut v) = (vec![0; 8], vec![0; 2], vec![0; 2]);
+        uyvy_to_yuv420_planar(&uyvy, 8, 4, 2, &mut y, &mut u, &mut v);
+        assert_eq!(y, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(u, [11, 32]);
+        assert_eq!(v, [21, 42]);
+    }
+
+    #[test]
+    fn uyvy_golden_quality() {
+        let mut buffers = EncodeBuffers::new();
+        let jpeg = encode_frame(&synthetic_uyvy(W * 2), W, H, W * 2, FourCCVideoType::UYVY, 90, &mut buffers)
+            .expect("encode");
+        let (y, u, v) = decode_planes(&jpeg);
+        assert_eq!((u.len(), v.len()), (W * H / 4, W * H / 4), "expected 4:2:0 output");
+        let (ref_y, ref_u, ref_v) = reference_planes();
+
+        let luma_psnr = psnr(&y, &ref_y);
+        let luma_ssim = ssim(&y, &ref_y, W, H);
+        assert!(luma_psnr >= 48.0, "luma PSNR {luma_psnr:.2} dB");
+        assert!(luma_ssim >= 0.99, "luma SSIM {luma_ssim:.4}");
+        assert!(psnr(&u, &ref_u) >= 42.0, "Cb PSNR {:.2} dB", psnr(&u, &ref_u));
+        assert!(psnr(&v, &ref_v) >= 42.0, "Cr PSNR {:.2} dB", psnr(&v, &ref_v));
+    }
+
+    #[test]
+    fn uyvy_padded_stride_matches_packed() {
+        let stride = W * 2 + 64;
+        let mut buffers = EncodeBuffers::new();
+        let packed = encode_frame(&synthetic_uyvy(W * 2), W, H, W * 2, FourCCVideoType::UYVY, 75, &mut buffers)
+            .expect("encode packed");
+        let padded = encode_frame(&synthetic_uyvy(stride), W, H, stride, FourCCVideoType::UYVY, 75, &mut buffers)
+            .expect("encode padded");
+        assert_eq!(packed, padded);
+    }
+
+    #[test]
+    fn bgra_golden_quality() {
+        let stride = W * 4 + 32;
+        let mut buffers = EncodeBuffers::new();
+        let jpeg = encode_frame(&synthetic_bgra(stride), W, H, stride, FourCCVideoType::BGRA, 90, &mut buffers)
+            .expect("encode");
+        let decoded = turbojpeg::decompress(&jpeg, turbojpeg::PixelFormat::BGRA).expect("decode");
+        assert_eq!((decoded.width, decoded.height), (W, H));
+
+        let source = synthetic_bgra(stride);
+        let (mut got, mut want) = (Vec::new(), Vec::new());
+        for y in 0..H {
+            for x in 0..W {
+                let d = y * decoded.pitch + x * 4;
+                let s = y * stride + x * 4;
+                got.extend_from_slice(&decoded.pixels[d..d + 3]);
+                want.extend_from_slice(&source[s..s + 3]);
+            }
+        }
+        let rgb_psnr = psnr(&got, &want);
+        assert!(rgb_psnr >= 42.0, "BGRA PSNR {rgb_psnr:.2} dB");
+
+        // SSIM per colour channel, against the exact source pixels.
+        for (c, name) in ["B", "G", "R"].iter().enumerate() {
+            let plane = |rgb: &[u8]| -> Vec<u8> { rgb.iter().skip(c).step_by(3).copied().collect() };
+            let channel_ssim = ssim(&plane(&got), &plane(&want), W, H);
+            assert!(channel_ssim >= 0.98, "BGRA {name} SSIM {channel_ssim:.4}");
+        }
+    }
+
+    #[test]
+    fn rgba_and_bgra_agree_after_channel_swap() {
+        let bgra = synthetic_bgra(W * 4);
+        let rgba: Vec<u8> = bgra.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]).collect();
+        let mut buffers = EncodeBuffers::new();
+        let a = encode_frame(&bgra, W, H, W * 4, FourCCVideoType::BGRA, 80, &mut buffers).expect("bgra");
+        let b = encode_frame(&rgba, W, H, W * 4, FourCCVideoType::RGBA, 80, &mut buffers).expect("rgba");
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn encoder_kinds_parse() {
+        assert_eq!(EncoderKind::parse("turbojpeg"), Ok(EncoderKind::Turbojpeg));
+        let nvenc = EncoderKind::parse("nvenc");
+        if cfg!(feature = "ffmpeg") {
+            assert_eq!(nvenc, Ok(EncoderKind::Nvenc));
+        } else {
+            assert!(nvenc.unwrap_err().contains("needs the ffmpeg feature"));
+        }
+        assert!(EncoderKind::parse("png").unwrap_err().contains("unknown encoder"));
+        let args = EncoderKind::Nvenc.h264_args(true).join(" ");
+        assert!(args.contains("-c:v h264_nvenc") && args.ends_with("-profile:v baseline -bf 0"));
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    #[test]
+    fn hardware_encoders_fall_back_to_turbojpeg() {
+        // Without the device, or ffmpeg, TurboJPEG makes the JPEG instead.
+        let mut encoder = FfmpegEncoder::new(EncoderKind::Vaapi);
+        let uyvy = synthetic_uyvy(W * 2);
+        let jpeg = encoder.encode(&uyvy, W, H, W * 2, FourCCVideoType::UYVY, 80).unwrap();
+        assert!(jpeg.starts_with(&[0xFF, 0xD8]));
+    }
+
+    #[test]
+    fn scale_factor_fits_both_limits() {
+        assert_eq!(scale_factor(3840, 2160, None, None), 1);
+        assert_eq!(scale_factor(3840, 2160, Some(320), None), 16);
+        assert_eq!(scale_factor(3840, 2160, Some(1920), Some(720)), 4);
+        assert_eq!(scale_factor(1920, 1080, Some(1920), Some(0)), 1);
+    }
+
+    #[test]
+    fn decimate_uyvy_keeps_every_other_pixel_and_row() {
+        // Two rows of four pixels, Y values 0..8 and chroma 100+/200+ per macropixel.
+        let src = [
+            100, 0, 200, 1, 101, 2, 201, 3, //
+            110, 4, 210, 5, 111, 6, 211, 7,
+        ];
+        let mut out = Vec::new();
+        let (w, h, stride) = decimate(&src, 4, 2, 8, FourCCVideoType::UYVY, 2, &mut out).unwrap();
+        assert_eq!((w, h, stride), (2, 1, 4));
+        assert_eq!(out, [100, 0, 200, 2]);
+    }
+
+    #[test]
+    fn unsupported_fourcc_is_rejected() {
+        let mut buffers = EncodeBuffers::new();
+        let err = encode_frame(&[0; 64], 4, 4, 4, FourCCVideoType::NV12, 75, &mut buffers).unwrap_err();
+        assert!(err.contains("unsupported FourCC"), "{err}");
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let mut buffers = EncodeBuffers::new();
+        let uyvy = synthetic_uyvy(W * 2);
+        let short = &uyvy[..uyvy.len() - 1];
+        let err = encode_frame(short, W, H, W * 2, FourCCVideoType::UYVY, 75, &mut buffers);
+        assert!(err.unwrap_err().contains("short of"));
+        let err = encode_frame(&uyvy, W, H, W, FourCCVideoType::UYVY, 75, &mut buffers);
+        assert!(err.unwrap_err().contains("stride"));
+        let mut out = Vec::new();
+        assert!(decimate(short, W, H, W * 2, FourCCVideoType::UYVY, 2, &mut out).is_err());
+        // UYVA needs its alpha plane too.
+        assert!(check_frame(&uyvy, W, H, W * 2, FourCCVideoType::UYVA).is_err());
+    }
+
+    #[test]
+    fn png_keeps_the_jpeg_colours_losslessly_and_webp_is_smaller() {
+        let uyvy = synthetic_uyvy(W * 2);
+        let encode = |format: OutputFormat| {
+            format.encoder().encode(&uyvy, W, H, W * 2, FourCCVideoType::UYVY, 75)
+        };
+        let png = encode(OutputFormat::Png).expect("png");
+        let mut reader = png::Decoder::new(&png[..]).read_info().expect("png header");
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgb).expect("png data");
+        for (i, px) in rgb.chunks_exact(3).enumerate() {
+            let (x, y) = (i % W, i / W);
+            let (luma, _, _) = test_card(x, y);
+            let (_, cb, cr) = test_card(x & !1, y);
+            let (r, g, b) = ycbcr_to_rgb(luma, cb, cr);
+            for (got, want) in px.iter().zip([r, g, b]) {
+                assert!(got.abs_diff(want) <= 1, "pixel {x},{y}: {px:?} vs {r},{g},{b}");
+            }
+        }
+
+        let webp = encode(OutputFormat::Webp).expect("webp");
+        assert_eq!((&webp[..4], &webp[8..12]), (&b"RIFF"[..], &b"WEBP"[..]));
+        assert!(webp.len() < png.len());
+    }
+}
@@ -0,0 +1,179 @@
+//! Encoding spread over a few threads of a source's own. One thread takes about
+//! a frame's time at 60 fps to encode a 4K JPEG, so with `encode_threads` set the
+//! producer only copies each frame and hands it to the next free encode thread.
+//! The threads take turns delivering, so frames still go out in capture order.
+
+use crate::encode::EncoderKind;
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::{Encoders, Outputs, Plan, VideoFrame};
+use crate::pool::{BufferPool, PooledBuf};
+use crate::receiver::FrameInfo;
+use crate::stats::SourceStats;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Frames waiting or being encoded, per thread, before new ones are dropped.
+const QUEUED_PER_THREAD: usize = 2;
+
+/// One frame to encode, copied out of the producer's buffer.
+struct Job {
+    /// Its place in the delivery order.
+    turn: u64,
+    info: FrameInfo,
+    data: PooledBuf,
+    width: usize,
+    height: usize,
+    stride: usize,
+    fourcc: FourCCVideoType,
+    plan: Plan,
+}
+
+/// What the encode threads share.
+struct Shared {
+    /// The turn of the job to deliver next.
+    next: Mutex<u64>,
+    turned: Condvar,
+    in_flight: AtomicUsize,
+    outputs: Outputs,
+    stats: Arc<SourceStats>,
+    source_name: String,
+}
+
+/// A source's encode threads. Dropping it waits for the frames handed over to
+/// be delivered.
+pub(crate) struct EncodePool {
+    jobs: Option<mpsc::Sender<Job>>,
+    /// JPEGs for the recording, in order, for the pipeline to write.
+    recorded: mpsc::Receiver<Bytes>,
+    threads: Vec<JoinHandle<()>>,
+    shared: Arc<Shared>,
+    frames: Arc<BufferPool>,
+    next_turn: u64,
+}
+
+impl EncodePool {
+    pub fn new(
+        threads: usize,
+        kind: EncoderKind,
+        source_name: &str,
+        outputs: Outputs,
+        stats: Arc<SourceStats>,
+    ) -> Result<Self, String> {
+        let (jobs, queue) = mpsc::channel();
+        let (record_tx, recorded) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let shared = Arc::new(Shared {
+            next: Mutex::new(0),
+            turned: Condvar::new(),
+            in_flight: AtomicUsize::new(0),
+            outputs,
+            stats,
+            source_name: source_name.to_string(),
+        });
+        let mut pool = Self {
+            jobs: Some(jobs),
+            recorded,
+            threads: Vec::with_capacity(threads),
+            shared: Arc::clone(&shared),
+            frames: BufferPool::new(),
+            next_turn: 0,
+        };
+        for i in 0..threads {
+            let (queue, shared, record_tx) =
+                (Arc::clone(&queue), Arc::clone(&shared), record_tx.clone());
+            let encoders = Encoders::new(kind, source_name);
+            let thread = thread::Builder::new()
+                .name(format!("encode-{i}-{source_name}"))
+                .spawn(move || encode_jobs(&queue, &shared, encoders, &record_tx))
+                .map_err(|e| format!("failed to spawn an encode thread: {e}"))?;
+            pool.threads.push(thread);
+        }
+        Ok(pool)
+    }
+
+    /// Whether every thread has frames queued; new ones are dropped meanwhile.
+    pub fn is_busy(&self) -> bool {
+        self.shared.in_flight.load(Ordering::Relaxed) >= self.threads.len() * QUEUED_PER_THREAD
+    }
+
+    /// Encode the frame `info` describes as `plan` says, and deliver it after
+    /// those handed over before it.
+    pub fn submit(&mut self, frame: &VideoFrame, info: FrameInfo, plan: Plan) {
+        let mut data = self.frames.take(frame.data.len());
+        data.extend_from_slice(frame.data);
+        let job = Job {
+            turn: self.next_turn,
+            info,
+            data,
+            width: frame.width,
+            height: frame.height,
+            stride: frame.stride,
+            fourcc: frame.fourcc,
+            plan,
+        };
+        self.shared.in_flight.fetch_add(1, Ordering::Relaxed);
+        if self.jobs.as_ref().is_some_and(|jobs| jobs.send(job).is_ok()) {
+            self.next_turn += 1;
+        } else {
+            self.shared.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The JPEGs for the recording delivered since last asked.
+    pub fn recorded(&self) -> mpsc::TryIter<'_, Bytes> {
+        self.recorded.try_iter()
+    }
+}
+
+impl Drop for EncodePool {
+    fn drop(&mut self) {
+        // Without jobs coming, the threads finish the queue and stop.
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// An encode thread: encode frames from `queue` until the pool is dropped.
+fn encode_jobs(
+    queue: &Mutex<mpsc::Receiver<Job>>,
+    shared: &Shared,
+    mut encoders: Encoders,
+    record_tx: &mpsc::Sender<Bytes>,
+) {
+    loop {
+        let Ok(job) = queue.lock().unwrap().recv() else {
+            return;
+        };
+        let frame = VideoFrame {
+            data: &job.data,
+            width: job.width,
+            height: job.height,
+            stride: job.stride,
+            fourcc: job.fourcc,
+        };
+        let (stats, source_name) = (&shared.stats, &shared.source_name);
+        let images: Vec<_> = job
+            .plan
+            .images
+            .iter()
+            .map(|&key| encoders.encode(&frame, key, stats, source_name))
+            .collect();
+        // Back to the pool for the next frame.
+        drop(job.data);
+
+        let next = shared.next.lock().unwrap();
+        let mut next = shared.turned.wait_while(next, |next| *next != job.turn).unwrap();
+        if let Some(jpeg) = job.plan.deliver(&images, job.info, &shared.outputs) {
+            let _ = record_tx.send(jpeg);
+        }
+        *next += 1;
+        drop(next);
+        shared.in_flight.fetch_sub(1, Ordering::Relaxed);
+        shared.turned.notify_all();
+    }
+}
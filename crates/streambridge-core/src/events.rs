@@ -0,0 +1,158 @@
+use crate::auth::{Action, Denial};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Something noteworthy happened on a source. Published by capture threads and
+/// delivered to every subscriber of the [`EventBus`].
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Integrated loudness left the target window.
+    LoudnessOutOfSpec {
+        source: String,
+        integrated: f64,
+        target: f64,
+    },
+    /// Integrated loudness is back within the target window.
+    LoudnessInSpec { source: String, integrated: f64 },
+    /// A client was refused access to a source.
+    AccessDenied {
+        source: String,
+        action: Action,
+        reason: Denial,
+        /// Prefix of the presented token, see [`crate::auth::token_hint`].
+        token: String,
+    },
+    /// An external system called `POST /api/hooks/{name}` for a source.
+    Webhook { name: String, source: String },
+    /// Discovery found a new NDI® source.
+    SourceAdded { source: String },
+    /// An NDI® source is no longer announced.
+    SourceRemoved { source: String },
+    /// A receiver lost its sender and is trying to get it back, for the
+    /// `attempt`th time. Its clients stay connected meanwhile.
+    ReceiverReconnecting { source: String, attempt: u32 },
+    /// A receiver gets frames from its sender again.
+    ReceiverReconnected { source: String },
+    /// A source's picture started changing; `score` is the share of it that did.
+    MotionStarted { source: String, score: f32 },
+    /// A source's picture has been still for a while.
+    MotionStopped { source: String },
+}
+
+impl Event {
+    /// Every value [`Event::kind`] can return.
+    pub const KINDS: &'static [&'static str] = &[
+        "loudness_out_of_spec",
+        "loudness_in_spec",
+        "access_denied",
+        "webhook",
+        "source_added",
+        "source_removed",
+        "receiver_reconnecting",
+        "receiver_reconnected",
+        "motion_started",
+        "motion_stopped",
+    ];
+
+    /// Short snake_case name of the variant, as used in capture rules.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::LoudnessOutOfSpec { .. } => "loudness_out_of_spec",
+            Event::LoudnessInSpec { .. } => "loudness_in_spec",
+            Event::AccessDenied { .. } => "access_denied",
+            Event::Webhook { .. } => "webhook",
+            Event::SourceAdded { .. } => "source_added",
+            Event::SourceRemoved { .. } => "source_removed",
+            Event::ReceiverReconnecting { .. } => "receiver_reconnecting",
+            Event::ReceiverReconnected { .. } => "receiver_reconnected",
+            Event::MotionStarted { .. } => "motion_started",
+            Event::MotionStopped { .. } => "motion_stopped",
+        }
+    }
+
+    /// The source the event is about.
+    pub fn source(&self) -> &str {
+        match self {
+            Event::LoudnessOutOfSpec { source, .. }
+            | Event::LoudnessInSpec { source, .. }
+            | Event::AccessDenied { source, .. }
+            | Event::Webhook { source, .. }
+            | Event::SourceAdded { source }
+            | Event::SourceRemoved { source }
+            | Event::ReceiverReconnecting { source, .. }
+            | Event::ReceiverReconnected { source }
+            | Event::MotionStarted { source, .. }
+            | Event::MotionStopped { source } => source,
+        }
+    }
+
+    /// Whether the event signals a problem an operator should look at.
+    pub fn is_alert(&self) -> bool {
+        matches!(
+            self,
+            Event::LoudnessOutOfSpec { .. }
+                | Event::AccessDenied { .. }
+                | Event::ReceiverReconnecting { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::LoudnessOutOfSpec { source, integrated, target } => write!(
+                f,
+                "[{}] loudness out of spec: {:.1} LUFS integrated (target {:.1})",
+                source, integrated, target
+            ),
+            Event::LoudnessInSpec { source, integrated } => write!(
+                f,
+                "[{}] loudness back in spec: {:.1} LUFS integrated",
+                source, integrated
+            ),
+            Event::AccessDenied { source, action, reason, token } => write!(
+                f,
+                "[{}] access denied: {} with token {} ({})",
+                source,
+                action,
+                token,
+                match reason {
+                    Denial::Unauthenticated => "unknown token",
+                    Denial::Forbidden => "not permitted",
+                }
+            ),
+            Event::Webhook { name, source } => write!(f, "[{}] webhook \"{}\" called", source, name),
+            Event::SourceAdded { source } => write!(f, "[{}] source appeared", source),
+            Event::SourceRemoved { source } => write!(f, "[{}] source disappeared", source),
+            Event::ReceiverReconnecting { source, attempt } => {
+                write!(f, "[{}] connection lost, reconnecting (attempt {})", source, attempt)
+            }
+            Event::ReceiverReconnected { source } => write!(f, "[{}] reconnected", source),
+            Event::MotionStarted { source, score } => {
+                write!(f, "[{}] motion started ({:.0}% of the picture)", source, score * 100.0)
+            }
+            Event::MotionStopped { source } => write!(f, "[{}] motion stopped", source),
+        }
+    }
+}
+
+/// Fan-out channel for server events. Publishing never blocks; events are
+/// dropped when nobody is listening.
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Arc<Self> {
+        let (tx, _) = broadcast::channel(64);
+        Arc::new(Self { tx })
+    }
+
+    pub fn publish(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
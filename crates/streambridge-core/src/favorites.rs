@@ -0,0 +1,114 @@
+//! Starred sources and a curated order, shared by every operator: `/sources`
+//! lists starred sources first, then by the order, then as discovered.
+
+use crate::store::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// The document favorites are saved as in the [`Store`].
+const DOCUMENT: &str = "favorites";
+
+/// What `GET /api/favorites` returns and `PATCH /api/favorites` changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FavoritesState {
+    pub starred: BTreeSet<String>,
+    /// Source names in the order they are listed in; sources not in it follow.
+    pub order: Vec<String>,
+}
+
+/// The favorites, saved to the store on every change.
+pub struct Favorites {
+    store: Store,
+    state: Mutex<FavoritesState>,
+}
+
+impl Favorites {
+    /// The favorites last saved to `store`, if any.
+    pub fn load(store: Store) -> Result<Self, String> {
+        let state = store.load(DOCUMENT)?.unwrap_or_default();
+        Ok(Self { store, state: Mutex::new(state) })
+    }
+
+    pub fn state(&self) -> FavoritesState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn is_starred(&self, source: &str) -> bool {
+        self.state.lock().unwrap().starred.contains(source)
+    }
+
+    /// Star or unstar `source`.
+    pub fn set_starred(&self, source: &str, starred: bool) -> Result<FavoritesState, String> {
+        self.update(|state| {
+            if starred {
+                state.starred.insert(source.to_string());
+            } else {
+                state.starred.remove(source);
+            }
+        })
+    }
+
+    /// Replace the starred sources and/or the order.
+    pub fn replace(
+        &self,
+        starred: Option<BTreeSet<String>>,
+        order: Option<Vec<String>>,
+    ) -> Result<FavoritesState, String> {
+        self.update(|state| {
+            if let Some(starred) = starred {
+                state.starred = starred;
+            }
+            if let Some(mut order) = order {
+                // Only the first place counts.
+                let mut seen = BTreeSet::new();
+                order.retain(|name| seen.insert(name.clone()));
+                state.order = order;
+            }
+        })
+    }
+
+    /// Sort `names`, as discovered, into the order `/sources` lists them in.
+    pub fn sort<S: AsRef<str>>(&self, names: &mut [S]) {
+        let state = self.state.lock().unwrap();
+        names.sort_by_key(|name| {
+            let name = name.as_ref();
+            let position = state.order.iter().position(|n| n == name);
+            (!state.starred.contains(name), position.unwrap_or(usize::MAX))
+        });
+    }
+
+    /// Apply `change` and save the result; on failure nothing changes.
+    fn update(&self, change: impl FnOnce(&mut FavoritesState)) -> Result<FavoritesState, String> {
+        let mut state = self.state.lock().unwrap();
+        let mut changed = state.clone();
+        change(&mut changed);
+        if changed != *state {
+            self.store.save(DOCUMENT, &changed)?;
+            *state = changed.clone();
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starred_sources_come_first_then_the_order_then_the_rest() {
+        let dir = std::env::temp_dir().join(format!("sb-favorites-{}", std::process::id()));
+        let favorites = Favorites::load(Store::new(dir.clone())).unwrap();
+        favorites.set_starred("D", true).unwrap();
+        let order = ["C", "A", "C"].map(String::from).to_vec();
+        favorites.replace(None, Some(order)).unwrap();
+        let mut names = ["A", "B", "C", "D", "E"];
+        favorites.sort(&mut names);
+        assert_eq!(names, ["D", "C", "A", "B", "E"]);
+
+        let reloaded = Favorites::load(Store::new(dir.clone())).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reloaded.state().order, ["C", "A"]);
+        assert!(reloaded.is_starred("D"));
+    }
+}
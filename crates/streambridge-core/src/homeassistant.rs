@@ -0,0 +1,80 @@
+//! Home Assistant and go2rtc: each source as a camera, ready to paste into
+//! HA's MJPEG IP Camera setup (or Generic Camera, for stills) or into go2rtc's
+//! `streams:`. URLs point at the address the client used and carry its token,
+//! so pasted cameras need no credentials of their own.
+
+use crate::discovery::source_id;
+use serde::Serialize;
+
+/// A source as HA's camera setup asks for it.
+#[derive(Debug, Serialize)]
+pub struct Camera {
+    pub name: String,
+    /// The source's id, which stays the same across restarts; go2rtc's stream
+    /// name.
+    pub unique_id: String,
+    pub mjpeg_url: String,
+    pub still_image_url: String,
+}
+
+impl Camera {
+    /// Source `name` served from `base`, e.g. `http://10.0.0.5:9550`.
+    pub fn new(name: &str, base: &str, token: Option<&str>) -> Self {
+        let id = source_id(name);
+        let query = token.map_or(String::new(), |token| format!("?token={}", encode(token)));
+        Self {
+            name: name.to_string(),
+            mjpeg_url: format!("{base}/stream/{id}{query}"),
+            still_image_url: format!("{base}/snapshot/{id}{query}"),
+            unique_id: id,
+        }
+    }
+}
+
+/// go2rtc's `streams:` with every camera's MJPEG stream.
+pub fn go2rtc_streams(cameras: &[Camera]) -> String {
+    let mut yaml = String::from("streams:\n");
+    for camera in cameras {
+        // JSON strings are YAML's double-quoted scalars.
+        let url = serde_json::to_string(&camera.mjpeg_url).unwrap_or_default();
+        yaml.push_str(&format!("  {}: {url}\n", camera.unique_id));
+    }
+    yaml
+}
+
+/// `text` percent-encoded for a URL's path or query string.
+pub(crate) fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                char::from(b).into()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cameras_carry_the_token_and_list_as_go2rtc_streams() {
+        let cameras = [
+            Camera::new("STUDIO (Cam 1)", "http://10.0.0.5:9550", Some("ha&co")),
+            Camera::new("STUDIO (Cam 2)", "http://10.0.0.5:9550", None),
+        ];
+        let id = &cameras[0].unique_id;
+        assert!(id.starts_with("studio-cam-1-"), "{id}");
+        assert_eq!(cameras[0].mjpeg_url, format!("http://10.0.0.5:9550/stream/{id}?token=ha%26co"));
+        assert_eq!(
+            cameras[0].still_image_url,
+            format!("http://10.0.0.5:9550/snapshot/{id}?token=ha%26co")
+        );
+        let yaml = go2rtc_streams(&cameras);
+        assert!(yaml.starts_with("streams:\n"), "{yaml}");
+        let id = &cameras[1].unique_id;
+        let line = format!("  {id}: \"http://10.0.0.5:9550/stream/{id}\"\n");
+        assert!(yaml.ends_with(&line), "{yaml}");
+    }
+}
@@ -0,0 +1,119 @@
+//! Power saving for servers nobody is using, e.g. on a laptop or an always-on
+//! mini PC: after `--idle-after` without requests or open streams, receivers
+//! kept warm are released and discovery slows down, until the next request.
+
+use crate::receiver::ReceiverManager;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the server checks whether it has gone idle.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the server was last used, and whether it is idle now.
+pub struct Activity {
+    /// How long the server must go unused before it idles; zero for never.
+    after: Duration,
+    last_request: Mutex<Instant>,
+    streams: AtomicUsize,
+    idle: AtomicBool,
+}
+
+impl Activity {
+    pub fn new(after: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            after,
+            last_request: Mutex::new(Instant::now()),
+            streams: AtomicUsize::new(0),
+            idle: AtomicBool::new(false),
+        })
+    }
+
+    /// A request arrived; wakes an idle server.
+    pub fn touch(&self) {
+        *self.last_request.lock().unwrap() = Instant::now();
+        if self.idle.swap(false, Ordering::Relaxed) {
+            info!("request received: no longer idle");
+        }
+    }
+
+    /// A long-lived response, like a stream or WebSocket, that keeps the
+    /// server busy until the returned guard is dropped.
+    pub fn stream(self: &Arc<Self>) -> StreamGuard {
+        self.touch();
+        self.streams.fetch_add(1, Ordering::Relaxed);
+        StreamGuard(self.clone())
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    /// Go idle if the server has been unused for long enough. Returns whether
+    /// it just did.
+    fn check(&self) -> bool {
+        if self.after.is_zero() || self.streams.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        let last_request = *self.last_request.lock().unwrap();
+        last_request.elapsed() >= self.after && !self.idle.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// Counts an open stream; see [`Activity::stream`].
+pub struct StreamGuard(Arc<Activity>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        *self.0.last_request.lock().unwrap() = Instant::now();
+        self.0.streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Watch `activity`, releasing `manager`'s unused receivers while idle.
+pub fn start(activity: Arc<Activity>, manager: Arc<ReceiverManager>) {
+    if activity.after.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tick.tick().await;
+            if activity.check() {
+                info!(
+                    "unused for {}s: idle, releasing receivers and slowing discovery",
+                    activity.after.as_secs()
+                );
+            }
+            // Composites' inputs only become unused once the composite is gone.
+            if activity.is_idle() {
+                manager.release_unused();
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idles_only_without_requests_or_streams() {
+        let activity = Activity::new(Duration::from_millis(20));
+        let stream = activity.stream();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!activity.check(), "a stream is open");
+        drop(stream);
+        assert!(!activity.check(), "the stream just ended");
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(activity.check());
+        assert!(activity.is_idle());
+        assert!(!activity.check(), "already idle");
+        activity.touch();
+        assert!(!activity.is_idle());
+
+        let never = Activity::new(Duration::ZERO);
+        assert!(!never.check());
+    }
+}
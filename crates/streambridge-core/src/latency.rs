@@ -0,0 +1,216 @@
+//! End-to-end latency measurement through external NDI gear. A test pattern
+//! carries its send time as a bar code across the top of the frame; whatever
+//! comes back out of the chain is read and compared with the clock.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use std::fmt;
+
+/// Bits of send time in a stamp: milliseconds, wrapping every ~35 years.
+const TIME_BITS: usize = 40;
+const CHECK_BITS: usize = 8;
+/// A white and a black guard cell at each end, then time and check bits.
+const CELLS: usize = 2 + TIME_BITS + CHECK_BITS + 2;
+/// Delays beyond this are taken as misreads rather than latency.
+const MAX_LATENCY_MS: u64 = 60_000;
+
+/// The cells of a stamp for `millis`, `true` for white.
+fn cells(millis: u64) -> [bool; CELLS] {
+    let millis = millis & ((1 << TIME_BITS) - 1);
+    let check = checksum(millis);
+    let mut cells = [false; CELLS];
+    cells[0] = true;
+    cells[CELLS - 1] = true;
+    for bit in 0..TIME_BITS {
+        cells[2 + bit] = millis >> (TIME_BITS - 1 - bit) & 1 == 1;
+    }
+    for bit in 0..CHECK_BITS {
+        cells[2 + TIME_BITS + bit] = check >> (CHECK_BITS - 1 - bit) & 1 == 1;
+    }
+    cells
+}
+
+fn checksum(millis: u64) -> u8 {
+    millis.to_be_bytes()[3..].iter().fold(0xA5, |acc, b| acc.rotate_left(1) ^ b)
+}
+
+/// Draw the stamp for `millis` over the top sixth of a tightly packed UYVY frame.
+pub fn stamp(data: &mut [u8], width: usize, height: usize, millis: u64) {
+    let cells = cells(millis);
+    for y in 0..(height / 6).max(1) {
+        let row = &mut data[y * width * 2..(y + 1) * width * 2];
+        for x in 0..width {
+            let white = cells[x * CELLS / width];
+            row[x * 2] = 128;
+            row[x * 2 + 1] = if white { 235 } else { 16 };
+        }
+    }
+}
+
+/// The send time stamped into `frame`, in milliseconds modulo 2^40. `None` if
+/// there is no readable stamp, e.g. in a frame blended between two stamps.
+pub fn read_stamp(frame: &VideoFrame) -> Option<u64> {
+    let y = frame.height / 12;
+    let cell_w = frame.width / CELLS;
+    if cell_w < 2 {
+        return None;
+    }
+    // Average the middle half of each cell, skipping edges softened by scaling.
+    let mut levels = [0u32; CELLS];
+    for (i, level) in levels.iter_mut().enumerate() {
+        let x0 = i * frame.width / CELLS + cell_w / 4;
+        let samples = (cell_w / 2).max(1);
+        let sum: u32 = (x0..x0 + samples)
+            .map(|x| luma_at(frame, x, y).map(u32::from))
+            .sum::<Option<u32>>()?;
+        *level = sum / samples as u32;
+    }
+    let (white, black) = (levels[0], levels[1]);
+    if white < black + 64 || levels[CELLS - 1] < black + 64 || levels[CELLS - 2] > white - 64 {
+        return None;
+    }
+    let threshold = (white + black) / 2;
+    let bits = |range: std::ops::Range<usize>| {
+        levels[range].iter().fold(0u64, |acc, &l| acc << 1 | u64::from(l > threshold))
+    };
+    let millis = bits(2..2 + TIME_BITS);
+    let check = bits(2 + TIME_BITS..2 + TIME_BITS + CHECK_BITS) as u8;
+    (check == checksum(millis)).then_some(millis)
+}
+
+/// Milliseconds from a stamp's `sent` time to `now`, both as given to [`stamp`].
+/// `None` if the difference is implausible.
+pub fn delay_ms(sent: u64, now: u64) -> Option<u64> {
+    let delay = now.wrapping_sub(sent) & ((1 << TIME_BITS) - 1);
+    (delay <= MAX_LATENCY_MS).then_some(delay)
+}
+
+fn luma_at(frame: &VideoFrame, x: usize, y: usize) -> Option<u8> {
+    let row = frame.data.get(y * frame.stride..)?;
+    match frame.fourcc {
+        FourCCVideoType::UYVY | FourCCVideoType::UYVA => row.get(x * 2 + 1).copied(),
+        FourCCVideoType::I420 | FourCCVideoType::NV12 | FourCCVideoType::YV12 => {
+            row.get(x).copied()
+        }
+        FourCCVideoType::BGRA | FourCCVideoType::BGRX => {
+            let px = row.get(x * 4..x * 4 + 3)?;
+            Some(bt601(px[2], px[1], px[0]))
+        }
+        FourCCVideoType::RGBA | FourCCVideoType::RGBX => {
+            let px = row.get(x * 4..x * 4 + 3)?;
+            Some(bt601(px[0], px[1], px[2]))
+        }
+        FourCCVideoType::Unknown(_) => None,
+    }
+}
+
+fn bt601(r: u8, g: u8, b: u8) -> u8 {
+    ((77 * r as u32 + 150 * g as u32 + 29 * b as u32) >> 8) as u8
+}
+
+/// Measured delays, in milliseconds.
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    samples: Vec<u64>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, delay_ms: u64) {
+        self.samples.push(delay_ms);
+    }
+
+    /// `None` until a delay has been recorded.
+    pub fn summary(&self) -> Option<Summary> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let percentile = |q: f64| sorted[((n - 1) as f64 * q).round() as usize];
+        let mean = sorted.iter().sum::<u64>() as f64 / n.max(1) as f64;
+        let variance =
+            sorted.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / n.max(1) as f64;
+        Some(Summary {
+            count: n,
+            min: *sorted.first()?,
+            mean,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            max: sorted[n - 1],
+            jitter: variance.sqrt(),
+        })
+    }
+}
+
+/// Statistics over a run; `jitter` is the standard deviation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p95: u64,
+    pub max: u64,
+    pub jitter: f64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames: min {} ms, mean {:.1} ms, p50 {} ms, p95 {} ms, max {} ms, jitter {:.1} ms",
+            self.count, self.min, self.mean, self.p50, self.p95, self.max, self.jitter
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: &[u8], width: usize, height: usize, fourcc: FourCCVideoType) -> VideoFrame<'_> {
+        let stride = fourcc.default_stride(width);
+        VideoFrame { data, width, height, stride, fourcc }
+    }
+
+    #[test]
+    fn stamps_read_back_from_scaled_rgb() {
+        let (w, h) = (1280, 720);
+        let mut uyvy = vec![0u8; w * h * 2];
+        let sent = 1_760_000_000_123;
+        stamp(&mut uyvy, w, h, sent);
+        let read = read_stamp(&frame(&uyvy, w, h, FourCCVideoType::UYVY));
+        assert_eq!(read, Some(sent & ((1 << TIME_BITS) - 1)));
+
+        // Halved and converted to BGRA, as gear in the chain might.
+        let (sw, sh) = (w / 2, h / 2);
+        let mut bgra = vec![0u8; sw * sh * 4];
+        for y in 0..sh {
+            for x in 0..sw {
+                let l = uyvy[(y * 2 * w + x * 2) * 2 + 1];
+                bgra[(y * sw + x) * 4..][..4].copy_from_slice(&[l, l, l, 255]);
+            }
+        }
+        let read = read_stamp(&frame(&bgra, sw, sh, FourCCVideoType::BGRA)).unwrap();
+        assert_eq!(delay_ms(read, sent + 40), Some(40));
+
+        // Flipping the last time bit fails the check.
+        let cell = 2 + TIME_BITS - 1;
+        let flipped = if sent & 1 == 1 { 16 } else { 235 };
+        for x in cell * w / CELLS..(cell + 1) * w / CELLS {
+            uyvy[(h / 12 * w + x) * 2 + 1] = flipped;
+        }
+        assert_eq!(read_stamp(&frame(&uyvy, w, h, FourCCVideoType::UYVY)), None);
+    }
+
+    #[test]
+    fn summarizes_delays() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(stats.summary(), None);
+        for delay in [40, 60, 50, 80, 70] {
+            stats.record(delay);
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!((summary.min, summary.p50, summary.p95, summary.max), (40, 60, 80, 80));
+        assert_eq!(summary.mean, 60.0);
+        assert!((summary.jitter - 14.14).abs() < 0.01);
+    }
+}
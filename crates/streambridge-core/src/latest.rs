@@ -0,0 +1,156 @@
+//! Latest-value delivery for encoded frames: each subscriber holds only the
+//! newest frame, so a client that can't keep up skips straight to it instead
+//! of working through a queue of stale ones.
+
+use std::sync::{Arc, Weak};
+use tokio::sync::watch;
+
+/// The newest value, and how many were sent up to it.
+struct Slot<T> {
+    sent: u64,
+    value: Option<T>,
+}
+
+/// The sending side. Clones send to the same subscribers; they see the channel
+/// close once the last clone is dropped.
+pub struct Sender<T> {
+    tx: Arc<watch::Sender<Slot<T>>>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self { tx: Arc::clone(&self.tx) }
+    }
+}
+
+impl<T: Clone> Default for Sender<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    pub fn new() -> Self {
+        Self { tx: Arc::new(watch::Sender::new(Slot { sent: 0, value: None })) }
+    }
+
+    /// Replace the newest value; subscribers that haven't taken the one
+    /// before skip it.
+    pub fn send(&self, value: T) {
+        self.tx.send_modify(|slot| {
+            slot.sent += 1;
+            slot.value = Some(value);
+        });
+    }
+
+    /// A subscriber to the values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let rx = self.tx.subscribe();
+        let seen = rx.borrow().sent;
+        Receiver { rx, seen, skipped: 0 }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// A handle that doesn't keep the channel open.
+    pub fn downgrade(&self) -> WeakSender<T> {
+        WeakSender { tx: Arc::downgrade(&self.tx) }
+    }
+}
+
+/// A [`Sender`] that may be gone.
+pub struct WeakSender<T> {
+    tx: Weak<watch::Sender<Slot<T>>>,
+}
+
+impl<T> WeakSender<T> {
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        self.tx.upgrade().map(|tx| Sender { tx })
+    }
+}
+
+/// Every sender is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+/// One subscriber's view of the newest value.
+pub struct Receiver<T> {
+    rx: watch::Receiver<Slot<T>>,
+    seen: u64,
+    /// Values replaced before they were taken, since the last
+    /// [`Receiver::take_skipped`].
+    skipped: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// A receiver whose senders are already gone.
+    pub fn closed() -> Self {
+        Sender::new().subscribe()
+    }
+
+    /// Wait for a value newer than the last one taken, and take the newest.
+    /// `None` once every sender is gone and the last value was taken.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.rx.changed().await.ok()?;
+        self.take()
+    }
+
+    /// Like [`Receiver::recv`], without waiting: `None` if nothing new came.
+    pub fn try_recv(&mut self) -> Result<Option<T>, Closed> {
+        if self.rx.borrow().sent != self.seen {
+            return Ok(self.take());
+        }
+        self.rx.has_changed().map(|_| None).map_err(|_| Closed)
+    }
+
+    /// How many values were replaced before this receiver took them, since
+    /// the last call.
+    pub fn take_skipped(&mut self) -> u64 {
+        std::mem::take(&mut self.skipped)
+    }
+
+    fn take(&mut self) -> Option<T> {
+        let slot = self.rx.borrow_and_update();
+        self.skipped += slot.sent.saturating_sub(self.seen + 1);
+        self.seen = slot.sent;
+        slot.value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_receivers_skip_to_the_newest_value() {
+        let tx = Sender::new();
+        let mut rx = tx.subscribe();
+        assert_eq!(rx.try_recv(), Ok(None));
+        tx.send(1);
+        assert_eq!(rx.recv().await, Some(1));
+        for value in 2..=5 {
+            tx.send(value);
+        }
+        assert_eq!(rx.recv().await, Some(5));
+        assert_eq!(rx.take_skipped(), 3);
+        assert_eq!(rx.take_skipped(), 0);
+
+        // Subscribers start with what is sent after they subscribe.
+        let mut late = tx.subscribe();
+        assert_eq!(late.try_recv(), Ok(None));
+        assert_eq!(tx.receiver_count(), 2);
+
+        // The last value still arrives after the sender is gone.
+        let weak = tx.downgrade();
+        tx.send(6);
+        drop(tx);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(rx.recv().await, Some(6));
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(late.try_recv(), Ok(Some(6)));
+        assert_eq!(late.try_recv(), Err(Closed));
+        assert_eq!(Receiver::<u32>::closed().recv().await, None);
+    }
+}
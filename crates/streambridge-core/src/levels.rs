@@ -0,0 +1,103 @@
+//! Audio levels for VU meters: each channel's RMS and peak over a short window,
+//! in dBFS, so a page can show meters beside a preview without streaming audio.
+
+use serde::Serialize;
+
+/// Levels are reported once per this much audio.
+const WINDOW_SECS: f32 = 0.1;
+
+/// What silence reads as, in dBFS.
+const SILENCE_DBFS: f32 = -100.0;
+
+/// One channel's levels in dBFS, to a tenth of a dB.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ChannelLevel {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// Every channel's levels over the last window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Levels {
+    pub channels: Vec<ChannelLevel>,
+}
+
+/// Sums up planar float audio into [`Levels`] per window.
+#[derive(Default)]
+pub struct LevelMeter {
+    squares: Vec<f64>,
+    peaks: Vec<f32>,
+    samples: usize,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one frame of audio. Returns the levels once a window of it has
+    /// been measured; a change in the number of channels starts a new window.
+    pub fn process(&mut self, sample_rate: u32, channels: &[&[f32]]) -> Option<Levels> {
+        if channels.len() != self.squares.len() {
+            self.squares = vec![0.0; channels.len()];
+            self.peaks = vec![0.0; channels.len()];
+            self.samples = 0;
+        }
+        let sums = self.squares.iter_mut().zip(&mut self.peaks);
+        for (channel, (square, peak)) in channels.iter().zip(sums) {
+            for &sample in *channel {
+                *square += f64::from(sample) * f64::from(sample);
+                *peak = peak.max(sample.abs());
+            }
+        }
+        self.samples += channels.iter().map(|c| c.len()).min().unwrap_or(0);
+        if channels.is_empty() || (self.samples as f32) < sample_rate as f32 * WINDOW_SECS {
+            return None;
+        }
+        let samples = self.samples as f64;
+        let channels = self
+            .squares
+            .iter_mut()
+            .zip(&mut self.peaks)
+            .map(|(square, peak)| {
+                let rms = (*square / samples).sqrt() as f32;
+                let level = ChannelLevel { rms: dbfs(rms), peak: dbfs(*peak) };
+                (*square, *peak) = (0.0, 0.0);
+                level
+            })
+            .collect();
+        self.samples = 0;
+        Some(Levels { channels })
+    }
+}
+
+/// `amplitude`, where 1.0 is full scale, in dBFS to a tenth of a dB.
+fn dbfs(amplitude: f32) -> f32 {
+    let db = 20.0 * amplitude.log10();
+    if db > SILENCE_DBFS {
+        (db * 10.0).round() / 10.0
+    } else {
+        SILENCE_DBFS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_rms_and_peak_per_channel_once_a_window_is_full() {
+        let mut meter = LevelMeter::new();
+        let square: Vec<f32> = (0..2400).map(|n| if n % 2 == 0 { 0.5 } else { -0.5 }).collect();
+        let silence = vec![0.0f32; 2400];
+        assert_eq!(meter.process(48_000, &[&square, &silence]), None);
+        let levels = meter.process(48_000, &[&square, &silence]).expect("a full window");
+        let half = ChannelLevel { rms: -6.0, peak: -6.0 };
+        let quiet = ChannelLevel { rms: SILENCE_DBFS, peak: SILENCE_DBFS };
+        assert_eq!(levels.channels, [half, quiet]);
+        // The next window starts empty.
+        assert_eq!(meter.process(48_000, &[&silence, &silence]), None);
+        let levels = meter.process(48_000, &[&silence, &silence]).unwrap();
+        assert_eq!(levels.channels, [quiet, quiet]);
+    }
+}
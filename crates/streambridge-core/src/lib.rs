@@ -0,0 +1,70 @@
+//! StreamBridge: bridge NDI® sources to MJPEG over HTTP/WebSocket.
+//!
+//! The `streambridge` crate is a thin CLI over this library. Applications embed
+//! the bridge with [`Bridge::builder`]; benchmarks and integration tests also
+//! drive the pipeline and server modules directly.
+
+pub use bridge::{Bridge, BridgeBuilder};
+pub use ndi_sdk as ndi;
+
+pub mod adaptive;
+pub mod annotations;
+pub mod audio;
+pub mod auth;
+pub mod automation;
+pub mod avi;
+pub mod badge;
+pub mod bridge;
+pub mod cadence;
+pub mod chaos;
+pub mod clients;
+pub mod clock;
+pub mod commands;
+pub mod composite;
+pub mod config;
+pub mod crash;
+pub mod deinterlace;
+pub mod diff;
+pub mod discovery;
+pub mod encode;
+pub mod encode_pool;
+pub mod events;
+pub mod favorites;
+pub mod homeassistant;
+pub mod idle;
+pub mod latency;
+pub mod latest;
+pub mod levels;
+pub mod limits;
+pub mod logs;
+pub mod loudness;
+pub mod media;
+pub mod motion;
+pub mod onvif;
+pub mod openapi;
+pub mod overlay;
+pub mod pipeline;
+pub mod pool;
+pub mod preroll;
+pub mod publish;
+pub mod quality;
+pub mod quirks;
+pub mod rawfile;
+pub mod receiver;
+pub mod recording;
+pub mod republish;
+pub mod rtsp;
+pub mod runtime;
+pub mod scripting;
+pub mod server;
+pub mod service;
+pub mod setup;
+pub mod snapshots;
+pub mod srt;
+pub mod stats;
+pub mod store;
+pub mod threads;
+pub mod timestamps;
+pub mod watermark;
+pub mod whep;
+mod test_page;
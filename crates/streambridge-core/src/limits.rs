@@ -0,0 +1,128 @@
+//! Caps on streaming clients, so one misbehaving dashboard opening hundreds of
+//! sockets can't take every broadcast slot and the encoders' CPU from everyone
+//! else. Over a cap, streams get 429 and WebSockets close code 4429.
+
+use axum::http::StatusCode;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a connection counts against its address's rate.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The caps; `None` for no cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Streaming clients at once, over all sources.
+    pub max_clients: Option<usize>,
+    /// Streaming clients of one source at once.
+    pub max_clients_per_source: Option<usize>,
+    /// New streaming connections from one IP address per minute.
+    pub max_connections_per_ip: Option<usize>,
+}
+
+/// Admits streaming clients within [`ConnectionLimits`].
+#[derive(Default)]
+pub struct Limits {
+    limits: ConnectionLimits,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    total: usize,
+    by_source: HashMap<String, usize>,
+    /// When each address's connections within the window were made.
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl Limits {
+    pub fn new(limits: ConnectionLimits) -> Arc<Self> {
+        Arc::new(Self { limits, state: Mutex::default() })
+    }
+
+    /// Admit a client of `source` from `ip`, if known, until the returned slot
+    /// is dropped.
+    pub fn admit(
+        self: &Arc<Self>,
+        ip: Option<IpAddr>,
+        source: &str,
+    ) -> Result<Slot, (StatusCode, &'static str)> {
+        let too_many = |reason| Err((StatusCode::TOO_MANY_REQUESTS, reason));
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let (Some(max), Some(ip)) = (self.limits.max_connections_per_ip, ip) {
+            // Forget addresses that have been quiet for the window.
+            state.recent.retain(|_, times| {
+                while times.front().is_some_and(|&at| now - at >= RATE_WINDOW) {
+                    times.pop_front();
+                }
+                !times.is_empty()
+            });
+            if state.recent.get(&ip).is_some_and(|times| times.len() >= max) {
+                return too_many("too many connections from this address");
+            }
+        }
+        if self.limits.max_clients.is_some_and(|max| state.total >= max) {
+            return too_many("too many clients");
+        }
+        let of_source = state.by_source.get(source).copied().unwrap_or(0);
+        if self.limits.max_clients_per_source.is_some_and(|max| of_source >= max) {
+            return too_many("too many clients for this source");
+        }
+        if let (Some(_), Some(ip)) = (self.limits.max_connections_per_ip, ip) {
+            state.recent.entry(ip).or_default().push_back(now);
+        }
+        state.total += 1;
+        *state.by_source.entry(source.to_string()).or_default() += 1;
+        Ok(Slot { limits: Arc::clone(self), source: source.to_string() })
+    }
+}
+
+/// An admitted client; frees its place when dropped.
+pub struct Slot {
+    limits: Arc<Limits>,
+    source: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.limits.state.lock().unwrap();
+        state.total -= 1;
+        if let Some(count) = state.by_source.get_mut(&self.source) {
+            *count -= 1;
+            if *count == 0 {
+                state.by_source.remove(&self.source);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_past_a_cap_are_turned_away_until_others_leave() {
+        let limits = Limits::new(ConnectionLimits {
+            max_clients: Some(3),
+            max_clients_per_source: Some(2),
+            max_connections_per_ip: Some(4),
+        });
+        let ip = Some(IpAddr::from([10, 0, 0, 1]));
+        let a1 = limits.admit(ip, "a").unwrap();
+        let _a2 = limits.admit(ip, "a").unwrap();
+        assert_eq!(limits.admit(None, "a").err().unwrap().1, "too many clients for this source");
+        let _b1 = limits.admit(ip, "b").unwrap();
+        assert_eq!(limits.admit(None, "b").err().unwrap().1, "too many clients");
+        drop(a1);
+        let b2 = limits.admit(ip, "b").unwrap();
+        // Four connections from the address this minute, though only two stay.
+        drop(b2);
+        let (status, reason) = limits.admit(ip, "c").err().unwrap();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(reason, "too many connections from this address");
+        assert!(limits.admit(Some(IpAddr::from([10, 0, 0, 2])), "c").is_ok());
+    }
+}
@@ -0,0 +1,189 @@
+//! The last few thousand log events in memory, for `GET /admin/logs` and crash
+//! reports: troubleshooting a bridge on someone else's machine without a shell
+//! on it.
+
+use crate::automation::utc_timestamp;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How many events are kept unless `--log-buffer` says otherwise.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// One log event.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Counts up from 1, for asking only for newer events.
+    pub seq: u64,
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+    pub level: String,
+    /// The module that logged it.
+    pub target: String,
+    /// The source it is about: a `[name]` at the start of the message.
+    pub source: Option<String>,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// The entry as one line of plain text.
+    pub fn line(&self) -> String {
+        let time = UNIX_EPOCH + std::time::Duration::from_secs_f64(self.time);
+        let level = self.level.to_ascii_uppercase();
+        format!("{} {level:>5} {}: {}", utc_timestamp(time), self.target, self.message)
+    }
+}
+
+/// What a `GET /admin/logs` asks for; everything by default.
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    /// This level and more severe ones only.
+    pub level: Option<Level>,
+    pub source: Option<String>,
+    /// Only events after this `seq`.
+    pub after: Option<u64>,
+    /// The newest this many of the matching events.
+    pub limit: Option<usize>,
+}
+
+struct Ring {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+/// Recent log events, oldest first. Add it to the tracing subscriber as a
+/// layer; clones share the events.
+#[derive(Clone)]
+pub struct LogBuffer(Arc<Mutex<Ring>>);
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let ring = Ring { entries: VecDeque::new(), capacity, next_seq: 1 };
+        Self(Arc::new(Mutex::new(ring)))
+    }
+
+    /// Keep `capacity` events from now on, dropping the oldest beyond it.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut ring = self.ring();
+        ring.capacity = capacity;
+        let excess = ring.entries.len().saturating_sub(capacity);
+        ring.entries.drain(..excess);
+    }
+
+    /// The matching events, oldest first.
+    pub fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
+        let ring = self.ring();
+        let matching: Vec<&LogEntry> = ring
+            .entries
+            .iter()
+            .filter(|e| query.after.is_none_or(|after| e.seq > after))
+            .filter(|e| {
+                let level = e.level.parse::<Level>().unwrap_or(Level::TRACE);
+                query.level.is_none_or(|min| level <= min)
+            })
+            .filter(|e| query.source.is_none() || e.source == query.source)
+            .collect();
+        let skip = query.limit.map_or(0, |limit| matching.len().saturating_sub(limit));
+        matching[skip..].iter().map(|&e| e.clone()).collect()
+    }
+
+    /// The newest `count` events as lines of text.
+    pub fn lines(&self, count: usize) -> Vec<String> {
+        let ring = self.ring();
+        let skip = ring.entries.len().saturating_sub(count);
+        ring.entries.iter().skip(skip).map(LogEntry::line).collect()
+    }
+
+    pub(crate) fn push(&self, level: Level, target: &str, message: String) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let source = message
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .map(|(source, _)| source.to_string());
+        let mut ring = self.ring();
+        let entry = LogEntry {
+            seq: ring.next_seq,
+            time: time.as_secs_f64(),
+            level: level.as_str().to_ascii_lowercase(),
+            target: target.to_string(),
+            source,
+            message,
+        };
+        ring.next_seq += 1;
+        if ring.capacity == 0 {
+            return;
+        }
+        if ring.entries.len() >= ring.capacity {
+            ring.entries.pop_front();
+        }
+        ring.entries.push_back(entry);
+    }
+
+    fn ring(&self) -> std::sync::MutexGuard<'_, Ring> {
+        // A panic while logging shouldn't take the logs with it.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        self.push(*metadata.level(), metadata.target(), event_message(event));
+    }
+}
+
+/// An event's message, followed by its other fields as `name=value`.
+pub(crate) fn event_message(event: &Event<'_>) -> String {
+    let mut message = Message(String::new());
+    event.record(&mut message);
+    message.0
+}
+
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let fields = std::mem::take(&mut self.0);
+            let _ = write!(self.0, "{value:?}{fields}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_filter_by_level_source_and_seq() {
+        let logs = LogBuffer::new(3);
+        logs.push(Level::INFO, "streambridge_core::discovery", "found 2 sources".to_string());
+        logs.push(Level::WARN, "streambridge_core::receiver", "[CAM 1] no frames".to_string());
+        logs.push(Level::INFO, "streambridge_core::receiver", "[CAM 2] connected".to_string());
+        logs.push(Level::ERROR, "streambridge_core::pipeline", "[CAM 1] encode error".to_string());
+
+        let all = logs.query(&LogQuery::default());
+        let seqs: Vec<u64> = all.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [2, 3, 4], "the oldest is dropped");
+        assert_eq!(all[0].source.as_deref(), Some("CAM 1"));
+
+        let warnings = logs.query(&LogQuery { level: Some(Level::WARN), ..Default::default() });
+        assert_eq!(warnings.iter().map(|e| e.seq).collect::<Vec<_>>(), [2, 4]);
+        let source = Some("CAM 1".to_string());
+        let cam = logs.query(&LogQuery { source, limit: Some(1), ..Default::default() });
+        assert_eq!(cam[0].message, "[CAM 1] encode error");
+        assert_eq!(logs.query(&LogQuery { after: Some(3), ..Default::default() }).len(), 1);
+
+        assert!(logs.lines(1)[0].ends_with("ERROR streambridge_core::pipeline: [CAM 1] encode error"));
+        logs.set_capacity(1);
+        assert_eq!(logs.query(&LogQuery::default()).len(), 1);
+    }
+}
@@ -0,0 +1,280 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Absolute gate for integrated loudness (EBU R128 / ITU-R BS.1770).
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Relative gate, applied below the absolute-gated mean.
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Histogram resolution for gating blocks (LU per bin).
+const HIST_STEP: f64 = 0.1;
+/// Histogram covers -70 .. +30 LUFS.
+const HIST_BINS: usize = 1000;
+
+/// Loudness values in LUFS. `None` until enough audio has been measured.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LoudnessReading {
+    pub momentary: Option<f64>,
+    pub short_term: Option<f64>,
+    pub integrated: Option<f64>,
+}
+
+/// Second-order IIR section (transposed direct form II).
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z1;
+        self.z1 = self.b[1] * x - self.a[0] * y + self.z2;
+        self.z2 = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// K-weighting filter pair (high-shelf + high-pass) for the given sample rate.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z1: 0.0,
+        z2: 0.0,
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z1: 0.0,
+        z2: 0.0,
+    };
+
+    [shelf, highpass]
+}
+
+/// Channel weight per BS.1770: LFE is ignored, surrounds of a 5.1 layout get +1.5 dB.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    if channels == 6 {
+        match channel {
+            3 => 0.0,
+            4 | 5 => 1.41,
+            _ => 1.0,
+        }
+    } else {
+        1.0
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// EBU R128 loudness meter. Feed it planar float audio; read momentary (400 ms),
+/// short-term (3 s) and gated integrated loudness.
+pub struct LoudnessMeter {
+    sample_rate: u32,
+    filters: Vec<[Biquad; 2]>,
+    /// Sum of squared, filtered samples per channel for the current 100 ms block.
+    block_sums: Vec<f64>,
+    block_len: usize,
+    block_pos: usize,
+    /// Weighted mean-square energy of the most recent 100 ms blocks (up to 3 s).
+    blocks: VecDeque<f64>,
+    /// Gating-block (400 ms, 75% overlap) energies bucketed by loudness.
+    hist_count: Vec<u64>,
+    hist_energy: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 0,
+            filters: Vec::new(),
+            block_sums: Vec::new(),
+            block_len: 0,
+            block_pos: 0,
+            blocks: VecDeque::with_capacity(30),
+            hist_count: vec![0; HIST_BINS],
+            hist_energy: vec![0.0; HIST_BINS],
+        }
+    }
+
+    /// Reset filter state when the stream format changes. Integrated loudness is kept.
+    fn configure(&mut self, sample_rate: u32, channels: usize) {
+        if sample_rate == self.sample_rate && channels == self.filters.len() {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        self.filters = vec![k_weighting(sample_rate as f64); channels];
+        self.block_sums = vec![0.0; channels];
+        self.block_len = (sample_rate / 10).max(1) as usize;
+        self.block_pos = 0;
+        self.blocks.clear();
+    }
+
+    /// Process one frame of planar audio. All channels must hold the same number of samples.
+    pub fn process(&mut self, sample_rate: u32, channels: &[&[f32]]) {
+        if sample_rate == 0 || channels.is_empty() {
+            return;
+        }
+        self.configure(sample_rate, channels.len());
+        let samples = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+
+        for i in 0..samples {
+            for (ch, data) in channels.iter().enumerate() {
+                let [shelf, highpass] = &mut self.filters[ch];
+                let y = highpass.process(shelf.process(data[i] as f64));
+                self.block_sums[ch] += y * y;
+            }
+            self.block_pos += 1;
+            if self.block_pos == self.block_len {
+                self.finish_block();
+            }
+        }
+    }
+
+    fn finish_block(&mut self) {
+        let n = self.block_len as f64;
+        let count = self.block_sums.len();
+        let energy: f64 = self
+            .block_sums
+            .iter()
+            .enumerate()
+            .map(|(ch, sum)| channel_weight(ch, count) * sum / n)
+            .sum();
+        self.block_sums.iter_mut().for_each(|s| *s = 0.0);
+        self.block_pos = 0;
+
+        if self.blocks.len() == 30 {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(energy);
+
+        // Every 100 ms completes a new 400 ms gating block.
+        if let Some(gating) = self.mean_of_last(4) {
+            let lufs = energy_to_lufs(gating);
+            if lufs > ABSOLUTE_GATE_LUFS {
+                let bin = (((lufs - ABSOLUTE_GATE_LUFS) / HIST_STEP) as usize).min(HIST_BINS - 1);
+                self.hist_count[bin] += 1;
+                self.hist_energy[bin] += gating;
+            }
+        }
+    }
+
+    fn mean_of_last(&self, n: usize) -> Option<f64> {
+        if self.blocks.len() < n {
+            return None;
+        }
+        Some(self.blocks.iter().rev().take(n).sum::<f64>() / n as f64)
+    }
+
+    fn integrated(&self) -> Option<f64> {
+        let total: u64 = self.hist_count.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let ungated = self.hist_energy.iter().sum::<f64>() / total as f64;
+        let threshold = energy_to_lufs(ungated) + RELATIVE_GATE_LU;
+        let first_bin = ((threshold - ABSOLUTE_GATE_LUFS) / HIST_STEP).max(0.0) as usize;
+
+        let (count, energy) = (first_bin.min(HIST_BINS)..HIST_BINS)
+            .fold((0u64, 0.0), |(c, e), i| (c + self.hist_count[i], e + self.hist_energy[i]));
+        if count == 0 {
+            return None;
+        }
+        Some(energy_to_lufs(energy / count as f64))
+    }
+
+    pub fn reading(&self) -> LoudnessReading {
+        LoudnessReading {
+            momentary: self.mean_of_last(4).map(energy_to_lufs).filter(|v| v.is_finite()),
+            short_term: self.mean_of_last(30).map(energy_to_lufs).filter(|v| v.is_finite()),
+            integrated: self.integrated(),
+        }
+    }
+}
+
+impl Default for LoudnessMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// Feed `seconds` of a 997 Hz sine at `dbfs` peak to both channels of a
+    /// stereo meter, in 100 ms frames.
+    fn feed_sine(meter: &mut LoudnessMeter, dbfs: f64, seconds: usize) {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        for frame in 0..seconds * 10 {
+            let start = frame * RATE as usize / 10;
+            let sine: Vec<f32> = (start..start + RATE as usize / 10)
+                .map(|i| {
+                    let t = i as f64 / RATE as f64;
+                    (amplitude * (2.0 * std::f64::consts::PI * 997.0 * t).sin()) as f32
+                })
+                .collect();
+            meter.process(RATE, &[&sine, &sine]);
+        }
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_20_dbfs_reads_minus_20_lufs() {
+        let mut meter = LoudnessMeter::new();
+        feed_sine(&mut meter, -20.0, 5);
+        let reading = meter.reading();
+        for value in [reading.momentary, reading.short_term, reading.integrated] {
+            let lufs = value.expect("a reading after 5 s");
+            assert!((lufs + 20.0).abs() < 0.1, "{lufs} LUFS");
+        }
+    }
+
+    #[test]
+    fn audio_below_the_absolute_gate_has_no_integrated_loudness() {
+        let mut meter = LoudnessMeter::new();
+        let silence = vec![0.0f32; RATE as usize];
+        meter.process(RATE, &[&silence, &silence]);
+        assert_eq!(meter.integrated(), None);
+        assert_eq!(meter.reading().momentary, None);
+
+        feed_sine(&mut meter, -80.0, 2);
+        let momentary = meter.reading().momentary.unwrap();
+        assert!(momentary < ABSOLUTE_GATE_LUFS, "{momentary} LUFS");
+        assert_eq!(meter.integrated(), None);
+    }
+
+    #[test]
+    fn relative_gate_drops_quiet_blocks() {
+        let mut meter = LoudnessMeter::new();
+        feed_sine(&mut meter, -20.0, 10);
+        feed_sine(&mut meter, -40.0, 10);
+        // Averaged with the quiet half, the loudness would be about -23 LUFS;
+        // 20 LU down, the quiet blocks fall below the relative gate.
+        let integrated = meter.integrated().unwrap();
+        assert!((integrated + 20.0).abs() < 0.2, "{integrated} LUFS");
+        let short_term = meter.reading().short_term.unwrap();
+        assert!((short_term + 40.0).abs() < 0.2, "{short_term} LUFS");
+    }
+}
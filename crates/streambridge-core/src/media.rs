@@ -0,0 +1,301 @@
+//! Video files recorded elsewhere, served as looping sources: placeholder
+//! content or replays for rehearsals, through the same API as live sources.
+//!
+//! MJPEG in AVI, as recordings and `/preroll` are written, and bare MJPEG (JPEGs
+//! back to back) are read as they are. With the `ffmpeg` feature, anything else
+//! the `ffmpeg` program opens, like MP4, is converted to MJPEG in AVI on the fly.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::time::Duration;
+
+/// The rate of files that don't say, i.e. bare MJPEG.
+const DEFAULT_FPS: u32 = 25;
+/// Larger chunks are taken for a damaged file rather than read into memory.
+const MAX_FRAME_BYTES: u32 = 64 << 20;
+
+/// A `[media."NAME"]` entry.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MediaConfig {
+    pub path: PathBuf,
+    /// Overrides the file's own frame rate.
+    #[serde(default)]
+    pub fps: Option<u32>,
+}
+
+impl MediaConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, fps: None }
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.fps.is_some_and(|fps| !(1..=120).contains(&fps)) {
+            return Err(format!("media \"{name}\": fps must be 1 to 120"));
+        }
+        Ok(())
+    }
+}
+
+enum Format {
+    Avi,
+    Mjpeg,
+}
+
+/// Reads a media file's JPEGs from the start.
+pub struct MediaReader {
+    input: Box<dyn BufRead + Send>,
+    format: Format,
+    /// The time per frame an AVI header gives; known by its first frame.
+    interval: Option<Duration>,
+    /// Bare MJPEG read but not yet returned.
+    pending: Vec<u8>,
+    /// `ffmpeg`, when it converts the file.
+    converter: Option<Child>,
+}
+
+impl MediaReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let start = input.fill_buf()?;
+        let format = if start.len() >= 12 && &start[..4] == b"RIFF" && &start[8..12] == b"AVI " {
+            Format::Avi
+        } else if start.starts_with(&[0xFF, 0xD8]) {
+            Format::Mjpeg
+        } else {
+            return Self::convert(path);
+        };
+        Ok(Self::new(Box::new(input), format, None))
+    }
+
+    /// Read bare MJPEG from `input`, e.g. an encoder's output.
+    pub fn mjpeg(input: Box<dyn BufRead + Send>) -> Self {
+        Self::new(input, Format::Mjpeg, None)
+    }
+
+    fn new(input: Box<dyn BufRead + Send>, format: Format, converter: Option<Child>) -> Self {
+        Self { input, format, interval: None, pending: Vec::new(), converter }
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    fn convert(path: &Path) -> io::Result<Self> {
+        use std::process::{Command, Stdio};
+        let mut child = Command::new("ffmpeg")
+            .args(["-v", "error", "-nostdin", "-i"])
+            .arg(path)
+            .args(["-an", "-c:v", "mjpeg", "-q:v", "3", "-pix_fmt", "yuvj420p", "-f", "avi", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::other(format!("failed to run ffmpeg: {e}")))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Self::new(Box::new(BufReader::new(stdout)), Format::Avi, Some(child)))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    fn convert(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not MJPEG in AVI or bare MJPEG; other formats need the ffmpeg feature",
+        ))
+    }
+
+    /// The time per frame the file gives, if it does.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// The next JPEG, or `None` at the end of the file.
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.format {
+            Format::Avi => self.next_avi_frame(),
+            Format::Mjpeg => self.next_mjpeg_frame(),
+        }
+    }
+
+    /// Walks the chunks in file order, ignoring the lists' sizes, which is all
+    /// a file written to a pipe gets right.
+    fn next_avi_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let mut header = [0; 8];
+            if !read_or_end(&mut self.input, &mut header)? {
+                return Ok(None);
+            }
+            let id = &header[..4];
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+            if id == b"RIFF" || id == b"LIST" {
+                // Lists hold chunks: read on into them.
+                self.input.read_exact(&mut [0; 4])?;
+                continue;
+            }
+            let wanted = id == b"avih" || &id[2..] == b"dc";
+            if !wanted || len > MAX_FRAME_BYTES {
+                io::copy(&mut (&mut self.input).take(u64::from(len + len % 2)), &mut io::sink())?;
+                continue;
+            }
+            let mut data = vec![0; len as usize];
+            self.input.read_exact(&mut data)?;
+            if len % 2 == 1 {
+                // The last chunk's padding may be missing.
+                read_or_end(&mut self.input, &mut [0])?;
+            }
+            if id == b"avih" {
+                let usec = data.get(..4).map(|u| u32::from_le_bytes(u.try_into().unwrap()));
+                self.interval = usec.filter(|&u| u > 0).map(|u| Duration::from_micros(u.into()));
+            } else if data.starts_with(&[0xFF, 0xD8]) {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn next_mjpeg_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        // Where the search for the end of the frame picks up after a read.
+        let mut searched = 2;
+        loop {
+            if let Some(start) = find(&self.pending, &[0xFF, 0xD8]) {
+                if start > 0 {
+                    self.pending.drain(..start);
+                    searched = 2;
+                }
+                if let Some(end) = find(&self.pending[searched..], &[0xFF, 0xD9]) {
+                    let rest = self.pending.split_off(searched + end + 2);
+                    return Ok(Some(std::mem::replace(&mut self.pending, rest)));
+                }
+                searched = self.pending.len().saturating_sub(1).max(2);
+            }
+            let read = self.input.fill_buf()?;
+            if read.is_empty() {
+                return Ok(None);
+            }
+            let n = read.len();
+            self.pending.extend_from_slice(read);
+            self.input.consume(n);
+        }
+    }
+}
+
+impl Drop for MediaReader {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.converter.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// The frame rate to play `config`'s file at, given its reader.
+pub fn interval(config: &MediaConfig, reader: &MediaReader) -> Duration {
+    match (config.fps, reader.interval()) {
+        (Some(fps), _) => Duration::from_secs(1) / fps,
+        (None, Some(interval)) => interval,
+        (None, None) => Duration::from_secs(1) / DEFAULT_FPS,
+    }
+}
+
+/// Fill `buf`, or return false if the input ends before its first byte.
+fn read_or_end(input: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let first = input.read(buf)?;
+    if first == 0 {
+        return Ok(false);
+    }
+    input.read_exact(&mut buf[first..])?;
+    Ok(true)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Decodes JPEGs into a reused BGRA frame for the pipeline.
+pub struct JpegDecoder {
+    decompressor: turbojpeg::Decompressor,
+    data: Vec<u8>,
+}
+
+impl JpegDecoder {
+    pub fn new() -> Result<Self, String> {
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        Ok(Self { decompressor, data: Vec::new() })
+    }
+
+    pub fn decode(&mut self, jpeg: &[u8]) -> Result<VideoFrame<'_>, String> {
+        let header = self
+            .decompressor
+            .read_header(jpeg)
+            .map_err(|e| format!("turbojpeg header error: {e}"))?;
+        let (width, height) = (header.width, header.height);
+        self.data.resize(width * height * 4, 0);
+        let image = turbojpeg::Image {
+            pixels: self.data.as_mut_slice(),
+            width,
+            pitch: width * 4,
+            height,
+            format: turbojpeg::PixelFormat::BGRA,
+        };
+        self.decompressor
+            .decompress(jpeg, image)
+            .map_err(|e| format!("turbojpeg decompress error: {e}"))?;
+        Ok(VideoFrame {
+            data: &self.data,
+            width,
+            height,
+            stride: width * 4,
+            fourcc: FourCCVideoType::BGRA,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avi::AviWriter;
+    use crate::encode::{encode_frame, EncodeBuffers};
+    use std::time::Instant;
+
+    fn jpeg(width: usize, height: usize) -> Vec<u8> {
+        let uyvy = vec![128; width * height * 2];
+        let mut buffers = EncodeBuffers::new();
+        encode_frame(&uyvy, width, height, width * 2, FourCCVideoType::UYVY, 75, &mut buffers)
+            .expect("encode")
+            .to_vec()
+    }
+
+    #[test]
+    fn avi_and_bare_mjpeg_files_give_back_their_frames() {
+        let (small, large) = (jpeg(32, 16), jpeg(64, 32));
+        let dir = std::env::temp_dir();
+        let avi_path = dir.join(format!("sb-media-{}.avi", std::process::id()));
+        let mut avi = AviWriter::create(&avi_path).unwrap();
+        let start = Instant::now();
+        for (i, frame) in [&small, &large, &small].into_iter().enumerate() {
+            avi.write_frame(frame, start + Duration::from_millis(20 * i as u64)).unwrap();
+        }
+        avi.finish().unwrap();
+        let mjpeg_path = dir.join(format!("sb-media-{}.mjpeg", std::process::id()));
+        std::fs::write(&mjpeg_path, [large.as_slice(), &small].concat()).unwrap();
+
+        let mut reader = MediaReader::open(&avi_path).unwrap();
+        assert_eq!(reader.next_frame().unwrap().as_ref(), Some(&small));
+        assert_eq!(reader.interval(), Some(Duration::from_millis(20)));
+        assert_eq!(reader.next_frame().unwrap().as_ref(), Some(&large));
+        assert_eq!(reader.next_frame().unwrap().as_ref(), Some(&small));
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        let mut reader = MediaReader::open(&mjpeg_path).unwrap();
+        assert_eq!(reader.next_frame().unwrap().as_ref(), Some(&large));
+        assert_eq!(reader.next_frame().unwrap().as_ref(), Some(&small));
+        assert_eq!(reader.next_frame().unwrap(), None);
+        assert_eq!(interval(&MediaConfig::new(mjpeg_path.clone()), &reader).as_millis(), 40);
+        std::fs::remove_file(&avi_path).unwrap();
+        std::fs::remove_file(&mjpeg_path).unwrap();
+
+        let frame = JpegDecoder::new().unwrap().decode(&large).map(|f| (f.width, f.height));
+        assert_eq!(frame, Ok((64, 32)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Motion detection: the share of a source's picture that changed since the
+//! previous frame, compared on a small luma plane, so NVR-style consumers learn
+//! when a camera sees activity without decoding its stream.
+
+use crate::diff;
+use crate::pipeline::VideoFrame;
+use std::time::{Duration, Instant};
+
+/// Frames are compared at about this width, which keeps detection cheap and
+/// evens out sensor noise.
+const MOTION_WIDTH: usize = 160;
+
+/// Luma change below which a pixel counts as unchanged, to ignore noise.
+const PIXEL_THRESHOLD: u8 = 24;
+
+/// Share of the picture that must change, by default.
+pub const DEFAULT_THRESHOLD: f32 = 0.01;
+
+/// How long the picture must stay still for motion to end, by default.
+pub const DEFAULT_HOLD: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionSettings {
+    /// Share of the picture, above 0 and at most 1, that must change between
+    /// frames for motion to start.
+    pub threshold: f32,
+    /// How long changes must stay below the threshold for motion to end.
+    pub hold: Duration,
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self { threshold: DEFAULT_THRESHOLD, hold: DEFAULT_HOLD }
+    }
+}
+
+/// Motion starting or ending on a source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionChange {
+    /// `score` is the share of the picture that changed.
+    Started { score: f32 },
+    Stopped,
+}
+
+/// Compares each frame with the one before it.
+pub struct MotionDetector {
+    settings: MotionSettings,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    size: (usize, usize),
+    /// When the picture last changed enough, while in motion.
+    moving: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub fn new(settings: MotionSettings) -> Self {
+        Self { settings, previous: Vec::new(), current: Vec::new(), size: (0, 0), moving: None }
+    }
+
+    /// Take the frame that arrived at `now`. Returns the change it brought, if
+    /// any; frames of a format without luma, or of a new size, bring none.
+    pub fn process(&mut self, frame: &VideoFrame, now: Instant) -> Option<MotionChange> {
+        let factor = (frame.width / MOTION_WIDTH).max(1);
+        let size = diff::luma(frame, factor, &mut self.current).ok()?;
+        std::mem::swap(&mut self.previous, &mut self.current);
+        if std::mem::replace(&mut self.size, size) != size || self.previous.is_empty() {
+            return None;
+        }
+        let changed = self
+            .current
+            .iter()
+            .zip(&self.previous)
+            .filter(|(before, now)| before.abs_diff(**now) > PIXEL_THRESHOLD)
+            .count();
+        let score = changed as f32 / self.previous.len() as f32;
+        if score >= self.settings.threshold {
+            return self.moving.replace(now).is_none().then_some(MotionChange::Started { score });
+        }
+        let still_since = self.moving?;
+        (now - still_since >= self.settings.hold).then(|| {
+            self.moving = None;
+            MotionChange::Stopped
+        })
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.moving.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndi::FourCCVideoType;
+
+    #[test]
+    fn starts_on_change_and_stops_after_holding_still() {
+        let (w, h) = (64, 36);
+        let dark = vec![16u8; w * 2 * h];
+        // A bright box over a tenth of the picture.
+        let mut boxed = dark.clone();
+        for y in 0..h / 2 {
+            for x in 0..w / 5 {
+                boxed[y * w * 2 + x * 2 + 1] = 200;
+            }
+        }
+        let fourcc = FourCCVideoType::UYVY;
+        let frame = |data| VideoFrame { data, width: w, height: h, stride: w * 2, fourcc };
+        let settings = MotionSettings { threshold: 0.05, hold: Duration::from_secs(2) };
+        let mut motion = MotionDetector::new(settings);
+        let start = Instant::now();
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+
+        assert_eq!(motion.process(&frame(&dark), at(0.0)), None);
+        assert_eq!(motion.process(&frame(&dark), at(0.1)), None);
+        let Some(MotionChange::Started { score }) = motion.process(&frame(&boxed), at(0.2)) else {
+            panic!("no motion");
+        };
+        assert!((0.09..0.11).contains(&score), "{score}");
+        // Moving back counts as motion too; standing still then ends it.
+        assert_eq!(motion.process(&frame(&dark), at(0.3)), None);
+        assert!(motion.is_moving());
+        assert_eq!(motion.process(&frame(&dark), at(2.0)), None);
+        assert_eq!(motion.process(&frame(&dark), at(2.4)), Some(MotionChange::Stopped));
+        assert!(!motion.is_moving());
+    }
+}
@@ -0,0 +1,481 @@
+//! ONVIF facade: each source as a minimal Profile S camera, for video
+//! management systems that only add cameras over ONVIF. A source's device
+//! service is `/onvif/<source>/device_service` and its media service, with one
+//! JPEG profile whose stream URI is the RTSP endpoint (or the MJPEG one without
+//! RTSP), `/onvif/<source>/media_service`. With `--onvif`, WS-Discovery answers
+//! probes with every source as a camera of its own. Passwords are tokens,
+//! sent in a WS-Security user name token as text or digest.
+
+use crate::auth::AccessPolicy;
+use crate::clients::new_client_id;
+use crate::discovery::{source_id, SourceList};
+use crate::receiver::ReceiverManager;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use sha1::{Digest, Sha1};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+/// Where WS-Discovery probes are multicast.
+const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const DISCOVERY_PORT: u16 = 3702;
+/// Operations clients call before they have credentials, as ONVIF allows.
+const OPEN_OPERATIONS: &[&str] = &[
+    "GetSystemDateAndTime",
+    "GetCapabilities",
+    "GetServices",
+    "GetServiceCapabilities",
+    "GetHostname",
+    "GetWsdlUrl",
+];
+/// The one profile, video source and encoder configuration of every camera.
+const PROFILE: &str = "main";
+const VIDEO_SOURCE: &str = "video";
+const ENCODER: &str = "jpeg";
+
+const NAMESPACES: &str = concat!(
+    r#"xmlns:s="http://www.w3.org/2003/05/soap-envelope" "#,
+    r#"xmlns:tt="http://www.onvif.org/ver10/schema" "#,
+    r#"xmlns:tds="http://www.onvif.org/ver10/device/wsdl" "#,
+    r#"xmlns:trt="http://www.onvif.org/ver10/media/wsdl" "#,
+    r#"xmlns:ter="http://www.onvif.org/ver10/error""#,
+);
+const DEVICE_NAMESPACE: &str = "http://www.onvif.org/ver10/device/wsdl";
+const MEDIA_NAMESPACE: &str = "http://www.onvif.org/ver10/media/wsdl";
+
+/// A source as the camera a client sees.
+pub struct Camera {
+    pub name: String,
+    /// The camera's URL, which its services are under.
+    pub base: String,
+    pub stream_uri: String,
+    pub snapshot_uri: String,
+    pub width: usize,
+    pub height: usize,
+    pub fps: u32,
+    pub quality: i32,
+}
+
+/// A SOAP request to a camera's service.
+pub struct SoapRequest<'a> {
+    xml: &'a str,
+    /// The body's element, e.g. `GetStreamUri`.
+    pub operation: &'a str,
+}
+
+impl<'a> SoapRequest<'a> {
+    pub fn parse(xml: &'a str) -> Option<Self> {
+        let mut tags = Tags::new(xml).skip_while(|tag| tag.name != "Body" || tag.closing);
+        let operation = tags.nth(1).filter(|tag| !tag.closing)?.name;
+        Some(Self { xml, operation })
+    }
+
+    /// Whether the operation can be called without credentials.
+    pub fn is_open(&self) -> bool {
+        OPEN_OPERATIONS.contains(&self.operation)
+    }
+
+    /// The token the WS-Security user name token's password is or was made from.
+    pub fn token<'p>(&self, access: &'p AccessPolicy) -> Option<&'p str> {
+        let password_tag =
+            Tags::new(self.xml).find(|tag| tag.name == "Password" && !tag.closing)?;
+        let password = element(self.xml, "Password")?;
+        if !password_tag.text.contains("PasswordDigest") {
+            return access.find_token(|token| token == password);
+        }
+        let nonce = BASE64_STANDARD.decode(element(self.xml, "Nonce")?).ok()?;
+        let created = element(self.xml, "Created")?;
+        access.find_token(|token| password_digest(&nonce, created, token) == password)
+    }
+}
+
+/// WS-Security's digest of a password: `Base64(SHA-1(nonce + created + password))`.
+fn password_digest(nonce: &[u8], created: &str, password: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(nonce);
+    sha1.update(created.as_bytes());
+    sha1.update(password.as_bytes());
+    BASE64_STANDARD.encode(sha1.finalize())
+}
+
+/// One tag of an XML document.
+struct Tag<'a> {
+    /// Its local name, without the namespace prefix.
+    name: &'a str,
+    closing: bool,
+    /// Everything between `<` and `>`.
+    text: &'a str,
+    /// Where its content starts.
+    end: usize,
+}
+
+/// The tags of an XML document, enough to pick values out of SOAP messages.
+struct Tags<'a> {
+    xml: &'a str,
+    /// What is left to read.
+    rest: &'a str,
+}
+
+impl<'a> Tags<'a> {
+    fn new(xml: &'a str) -> Self {
+        Self { xml, rest: xml }
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Tag<'a>> {
+        loop {
+            let start = self.rest.find('<')? + 1;
+            let len = self.rest[start..].find('>')?;
+            let text = &self.rest[start..start + len];
+            let rest = &self.rest[start + len + 1..];
+            let end = self.xml.len() - rest.len();
+            self.rest = rest;
+            if text.starts_with('?') || text.starts_with('!') {
+                continue;
+            }
+            let closing = text.starts_with('/');
+            let mut words = text.trim_start_matches('/').split([' ', '\t', '\r', '\n', '/']);
+            let name = words.next()?.rsplit(':').next()?;
+            return Some(Tag { name, closing, text, end });
+        }
+    }
+}
+
+/// The text of the first element named `name`, in any namespace.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let tag = Tags::new(xml).find(|tag| tag.name == name && !tag.closing)?;
+    if tag.text.ends_with('/') {
+        return Some("");
+    }
+    let content = &xml[tag.end..];
+    Some(content[..content.find("</")?].trim())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A SOAP envelope around `body`.
+pub fn envelope(body: &str) -> String {
+    let declaration = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+    format!("{declaration}<s:Envelope {NAMESPACES}><s:Body>{body}</s:Body></s:Envelope>")
+}
+
+/// A SOAP fault body for a client error `subcode` like `ter:NotAuthorized`.
+pub fn fault(subcode: &str, reason: &str) -> String {
+    format!(
+        "<s:Fault><s:Code><s:Value>s:Sender</s:Value><s:Subcode><s:Value>{subcode}</s:Value>\
+         </s:Subcode></s:Code><s:Reason><s:Text xml:lang=\"en\">{}</s:Text></s:Reason>\
+         </s:Fault>",
+        escape(reason)
+    )
+}
+
+/// The device service's answer to `operation`, or `None` if it has none.
+pub fn device_service(operation: &str, camera: &Camera) -> Option<String> {
+    let base = &camera.base;
+    let body = match operation {
+        "GetDeviceInformation" => format!(
+            "<tds:Manufacturer>streambridge</tds:Manufacturer><tds:Model>{}</tds:Model>\
+             <tds:FirmwareVersion>{}</tds:FirmwareVersion>\
+             <tds:SerialNumber>{}</tds:SerialNumber><tds:HardwareId>NDI</tds:HardwareId>",
+            escape(&camera.name),
+            env!("CARGO_PKG_VERSION"),
+            source_id(&camera.name)
+        ),
+        "GetSystemDateAndTime" => {
+            let now = jiff::Timestamp::now().to_zoned(jiff::tz::TimeZone::UTC);
+            format!(
+                "<tds:SystemDateAndTime><tt:DateTimeType>Manual</tt:DateTimeType>\
+                 <tt:DaylightSavings>false</tt:DaylightSavings>\
+                 <tt:TimeZone><tt:TZ>UTC0</tt:TZ></tt:TimeZone><tt:UTCDateTime>\
+                 <tt:Time><tt:Hour>{}</tt:Hour><tt:Minute>{}</tt:Minute>\
+                 <tt:Second>{}</tt:Second></tt:Time><tt:Date><tt:Year>{}</tt:Year>\
+                 <tt:Month>{}</tt:Month><tt:Day>{}</tt:Day></tt:Date>\
+                 </tt:UTCDateTime></tds:SystemDateAndTime>",
+                now.hour(),
+                now.minute(),
+                now.second(),
+                now.year(),
+                now.month(),
+                now.day()
+            )
+        }
+        "GetCapabilities" => format!(
+            "<tds:Capabilities><tt:Device><tt:XAddr>{base}/device_service</tt:XAddr>\
+             </tt:Device><tt:Media><tt:XAddr>{base}/media_service</tt:XAddr>\
+             <tt:StreamingCapabilities><tt:RTPMulticast>false</tt:RTPMulticast>\
+             <tt:RTP_TCP>true</tt:RTP_TCP><tt:RTP_RTSP_TCP>true</tt:RTP_RTSP_TCP>\
+             </tt:StreamingCapabilities></tt:Media></tds:Capabilities>"
+        ),
+        "GetServices" => [(DEVICE_NAMESPACE, "device_service"), (MEDIA_NAMESPACE, "media_service")]
+            .iter()
+            .map(|(namespace, service)| {
+                format!(
+                    "<tds:Service><tds:Namespace>{namespace}</tds:Namespace>\
+                     <tds:XAddr>{base}/{service}</tds:XAddr><tds:Version>\
+                     <tt:Major>2</tt:Major><tt:Minor>0</tt:Minor></tds:Version></tds:Service>"
+                )
+            })
+            .collect(),
+        "GetScopes" => scopes(&camera.name)
+            .iter()
+            .map(|scope| {
+                format!(
+                    "<tds:Scopes><tt:ScopeDef>Fixed</tt:ScopeDef>\
+                     <tt:ScopeItem>{scope}</tt:ScopeItem></tds:Scopes>"
+                )
+            })
+            .collect(),
+        "GetHostname" => format!(
+            "<tds:HostnameInformation><tt:FromDHCP>false</tt:FromDHCP>\
+             <tt:Name>{}</tt:Name></tds:HostnameInformation>",
+            source_id(&camera.name)
+        ),
+        _ => return None,
+    };
+    Some(format!("<tds:{operation}Response>{body}</tds:{operation}Response>"))
+}
+
+/// The media service's answer to `operation`, or `None` if it has none.
+pub fn media_service(operation: &str, camera: &Camera) -> Option<String> {
+    let (width, height) = (camera.width, camera.height);
+    let media_uri = |uri: &str| {
+        format!(
+            "<trt:MediaUri><tt:Uri>{}</tt:Uri><tt:InvalidAfterConnect>false\
+             </tt:InvalidAfterConnect><tt:InvalidAfterReboot>false</tt:InvalidAfterReboot>\
+             <tt:Timeout>PT0S</tt:Timeout></trt:MediaUri>",
+            escape(uri)
+        )
+    };
+    let profile = || {
+        format!(
+            "<tt:Name>{PROFILE}</tt:Name>\
+             <tt:VideoSourceConfiguration token=\"{VIDEO_SOURCE}\">\
+             <tt:Name>{VIDEO_SOURCE}</tt:Name><tt:UseCount>1</tt:UseCount>\
+             <tt:SourceToken>{VIDEO_SOURCE}</tt:SourceToken>\
+             <tt:Bounds x=\"0\" y=\"0\" width=\"{width}\" height=\"{height}\"/>\
+             </tt:VideoSourceConfiguration>\
+             <tt:VideoEncoderConfiguration token=\"{ENCODER}\">\
+             <tt:Name>{ENCODER}</tt:Name><tt:UseCount>1</tt:UseCount>\
+             <tt:Encoding>JPEG</tt:Encoding><tt:Resolution><tt:Width>{width}</tt:Width>\
+             <tt:Height>{height}</tt:Height></tt:Resolution>\
+             <tt:Quality>{}</tt:Quality><tt:RateControl>\
+             <tt:FrameRateLimit>{}</tt:FrameRateLimit><tt:EncodingInterval>1\
+             </tt:EncodingInterval><tt:BitrateLimit>0</tt:BitrateLimit></tt:RateControl>\
+             <tt:SessionTimeout>PT60S</tt:SessionTimeout></tt:VideoEncoderConfiguration>",
+            camera.quality, camera.fps
+        )
+    };
+    let body = match operation {
+        "GetProfiles" => {
+            format!("<trt:Profiles token=\"{PROFILE}\" fixed=\"true\">{}</trt:Profiles>", profile())
+        }
+        "GetProfile" => {
+            format!("<trt:Profile token=\"{PROFILE}\" fixed=\"true\">{}</trt:Profile>", profile())
+        }
+        "GetVideoSources" => format!(
+            "<trt:VideoSources token=\"{VIDEO_SOURCE}\"><tt:Framerate>{}</tt:Framerate>\
+             <tt:Resolution><tt:Width>{width}</tt:Width><tt:Height>{height}</tt:Height>\
+             </tt:Resolution></trt:VideoSources>",
+            camera.fps
+        ),
+        "GetStreamUri" => media_uri(&camera.stream_uri),
+        "GetSnapshotUri" => media_uri(&camera.snapshot_uri),
+        _ => return None,
+    };
+    Some(format!("<trt:{operation}Response>{body}</trt:{operation}Response>"))
+}
+
+/// The ONVIF scopes a camera is found by.
+fn scopes(name: &str) -> Vec<String> {
+    let name: String = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => char::from(b).into(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    [
+        "type/video_encoder".to_string(),
+        "Profile/Streaming".to_string(),
+        "hardware/NDI".to_string(),
+        format!("name/{name}"),
+    ]
+    .iter()
+    .map(|scope| format!("onvif://www.onvif.org/{scope}"))
+    .collect()
+}
+
+/// A camera's WS-Discovery address: a UUID made from the source's name, so it
+/// stays the same across restarts.
+fn endpoint(name: &str) -> String {
+    let hash = Sha1::digest(name.as_bytes());
+    uuid(&hash.iter().map(|b| format!("{b:02x}")).collect::<String>())
+}
+
+/// A UUID URN of the first 32 of `hex`'s digits.
+fn uuid(hex: &str) -> String {
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Answer WS-Discovery probes for cameras with every source, each camera's
+/// services on `http_port`, until the server starts draining.
+pub async fn discover(sources: SourceList, http_port: u16, manager: Arc<ReceiverManager>) {
+    let socket = match bind_discovery() {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("ONVIF: discovery unavailable: {}", e);
+            return;
+        }
+    };
+    info!("ONVIF: answering discovery probes on port {}", DISCOVERY_PORT);
+    let mut buf = vec![0; 65536];
+    loop {
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            () = manager.drained() => return,
+        };
+        let Ok((len, peer)) = received else {
+            continue;
+        };
+        let Ok(probe) = std::str::from_utf8(&buf[..len]) else {
+            continue;
+        };
+        let Some(message_id) = probe_id(probe) else {
+            continue;
+        };
+        let Some(host) = local_ip_for(peer) else {
+            continue;
+        };
+        let names: Vec<String> = sources.read().unwrap().iter().map(|s| s.name.clone()).collect();
+        debug!("ONVIF: probe from {}, answering with {} camera(s)", peer, names.len());
+        for name in names {
+            let id = source_id(&name);
+            let xaddr = format!("http://{host}:{http_port}/onvif/{id}/device_service");
+            let matches = probe_match(message_id, &name, &xaddr);
+            let _ = socket.send_to(matches.as_bytes(), peer).await;
+        }
+    }
+}
+
+fn bind_discovery() -> std::io::Result<UdpSocket> {
+    let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))?;
+    socket.join_multicast_v4(&DISCOVERY_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+/// The message id of a probe for cameras; `None` for other messages.
+fn probe_id(xml: &str) -> Option<&str> {
+    let request = SoapRequest::parse(xml).filter(|request| request.operation == "Probe")?;
+    // No types, or a type cameras have.
+    let types = element(xml, "Types").unwrap_or_default();
+    let mut wanted = types.split_whitespace().map(|t| t.rsplit(':').next().unwrap_or(t));
+    if !types.is_empty() && !wanted.any(|t| t == "NetworkVideoTransmitter" || t == "Device") {
+        return None;
+    }
+    element(request.xml, "MessageID")
+}
+
+/// The address of this host that `peer` reaches it on.
+fn local_ip_for(peer: SocketAddr) -> Option<std::net::IpAddr> {
+    let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// A WS-Discovery answer to the probe `relates_to` for the camera `name`.
+fn probe_match(relates_to: &str, name: &str, xaddr: &str) -> String {
+    let message_id = uuid(&format!("{}{}", new_client_id(), new_client_id()));
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" "#,
+            r#"xmlns:a="http://schemas.xmlsoap.org/ws/2004/08/addressing" "#,
+            r#"xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" "#,
+            r#"xmlns:dn="http://www.onvif.org/ver10/network/wsdl" "#,
+            r#"xmlns:tds="http://www.onvif.org/ver10/device/wsdl">"#,
+            "<s:Header><a:MessageID>{message_id}</a:MessageID>",
+            "<a:RelatesTo>{relates_to}</a:RelatesTo>",
+            "<a:To>http://schemas.xmlsoap.org/ws/2004/08/addressing/role/anonymous</a:To>",
+            "<a:Action>http://schemas.xmlsoap.org/ws/2005/04/discovery/ProbeMatches</a:Action>",
+            "</s:Header><s:Body><d:ProbeMatches><d:ProbeMatch>",
+            "<a:EndpointReference><a:Address>{endpoint}</a:Address></a:EndpointReference>",
+            "<d:Types>dn:NetworkVideoTransmitter tds:Device</d:Types>",
+            "<d:Scopes>{scopes}</d:Scopes><d:XAddrs>{xaddr}</d:XAddrs>",
+            "<d:MetadataVersion>1</d:MetadataVersion>",
+            "</d:ProbeMatch></d:ProbeMatches></s:Body></s:Envelope>",
+        ),
+        message_id = message_id,
+        relates_to = escape(relates_to),
+        endpoint = endpoint(name),
+        scopes = scopes(name).join(" "),
+        xaddr = escape(xaddr),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Grant;
+
+    #[test]
+    fn soap_requests_are_read_and_digests_checked() {
+        let request = concat!(
+            r#"<?xml version="1.0"?>
+            <s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope">
+            <s:Header><Security><UsernameToken><Username>vms</Username>
+            <Password Type="http://docs.oasis-open.org/wss/2004/01/"#,
+            r#"oasis-200401-wss-username-token-profile-1.0#PasswordDigest">"#,
+            r#"n7Fyfj9TtpuhWBk6eiqhYYNW2N4=</Password>
+            <Nonce>MDEyMzQ1Njc4OWFiY2RlZg==</Nonce><Created>2026-10-18T12:00:00Z</Created>
+            </UsernameToken></Security></s:Header>
+            <s:Body><trt:GetStreamUri xmlns:trt="http://www.onvif.org/ver10/media/wsdl">
+            <trt:ProfileToken>main</trt:ProfileToken></trt:GetStreamUri></s:Body></s:Envelope>"#
+        );
+        let request = SoapRequest::parse(request).expect("a SOAP request");
+        assert_eq!(request.operation, "GetStreamUri");
+        assert!(!request.is_open());
+        let grants = ["other:view:*", "s3cret:view:*"].map(|g| Grant::parse(g).unwrap());
+        assert_eq!(request.token(&AccessPolicy::new(grants.to_vec())), Some("s3cret"));
+        let other = AccessPolicy::new(grants[..1].to_vec());
+        assert_eq!(request.token(&other), None);
+
+        let camera = Camera {
+            name: "STUDIO (Cam 1)".to_string(),
+            base: "http://10.0.0.5:9550/onvif/studio-cam-1-a1b2c3".to_string(),
+            stream_uri: "rtsp://10.0.0.5:8554/studio-cam-1-a1b2c3".to_string(),
+            snapshot_uri: "http://10.0.0.5:9550/snapshot/studio-cam-1-a1b2c3".to_string(),
+            width: 1280,
+            height: 720,
+            fps: 25,
+            quality: 75,
+        };
+        let response = media_service(request.operation, &camera).unwrap();
+        assert_eq!(element(&response, "Uri"), Some("rtsp://10.0.0.5:8554/studio-cam-1-a1b2c3"));
+        assert!(media_service("GetProfiles", &camera).unwrap().contains("<tt:Width>1280<"));
+        assert!(device_service("GetStreamUri", &camera).is_none());
+
+        let probe = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"
+            xmlns:a="http://schemas.xmlsoap.org/ws/2004/08/addressing"><s:Header>
+            <a:MessageID>uuid:1234</a:MessageID></s:Header><s:Body><d:Probe>
+            <d:Types>dn:NetworkVideoTransmitter</d:Types></d:Probe></s:Body></s:Envelope>"#;
+        assert_eq!(probe_id(probe), Some("uuid:1234"));
+        assert_eq!(probe_id(&probe.replace("NetworkVideoTransmitter", "Printer")), None);
+        let matched = probe_match("uuid:1234", "STUDIO (Cam 1)", "http://10.0.0.5:9550/x");
+        assert!(matched.contains("onvif://www.onvif.org/name/STUDIO%20%28Cam%201%29"));
+        assert_eq!(element(&matched, "Address"), Some(endpoint("STUDIO (Cam 1)").as_str()));
+    }
+}
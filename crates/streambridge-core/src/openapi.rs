@@ -0,0 +1,60 @@
+//! The HTTP API described as an OpenAPI 3.1 document, served at `/openapi.json`
+//! for integrators to generate clients from, and browsed with Swagger UI at
+//! `/docs`. The paths come from the `#[utoipa::path]` attributes of the server's
+//! handlers as they are routed, so the document lists exactly the routes the
+//! server answers; this module adds what applies to all of them.
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// The document without its paths.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "StreamBridge",
+        description = "NDI sources as MJPEG, WebSocket and HTTP streams. With grants, send a \
+                       token as a bearer token or as ?token=.",
+        license(name = "MIT")
+    ),
+    modifiers(&TokenSchemes),
+    // Open unless the server has grants.
+    security((), ("bearer" = []), ("token" = []))
+)]
+pub struct ApiDoc;
+
+/// The two ways to send a token.
+struct TokenSchemes;
+
+impl Modify for TokenSchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let bearer = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build();
+        components.add_security_scheme("bearer", SecurityScheme::Http(bearer));
+        let query = ApiKey::Query(ApiKeyValue::new("token"));
+        components.add_security_scheme("token", SecurityScheme::ApiKey(query));
+    }
+}
+
+/// `spec` at `/openapi.json`, and Swagger UI for it at `/docs`, served by the
+/// bridge itself so it works without internet access.
+pub fn docs(spec: utoipa::openapi::OpenApi) -> SwaggerUi {
+    SwaggerUi::new("/docs").url("/openapi.json", spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_go_in_a_header_or_the_query() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert_eq!(spec["openapi"], "3.1.0");
+        assert_eq!(spec["info"]["title"], "StreamBridge");
+        let schemes = &spec["components"]["securitySchemes"];
+        assert_eq!(schemes["bearer"]["scheme"], "bearer");
+        assert_eq!(schemes["token"]["in"], "query");
+        // Anonymous access stays an option for servers without grants.
+        assert_eq!(spec["security"][0], serde_json::json!({}));
+    }
+}
@@ -0,0 +1,228 @@
+//! Burned-in overlays: the source's name, the time and a PNG logo drawn into a
+//! source's frames before they are encoded, so recordings and feeds passed on
+//! elsewhere still say what they show and when.
+
+use crate::badge::{draw_label, label_size, Canvas};
+use crate::composite::Corner;
+use crate::pipeline::VideoFrame;
+use crate::timestamps::TimeFormat;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// NDI® timecodes count in these per second.
+const TIMECODE_UNITS: i64 = 10_000_000;
+
+/// A source's `overlay` table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayConfig {
+    /// Draw the source's name.
+    #[serde(default)]
+    pub name: bool,
+    pub time: Option<OverlayTime>,
+    /// Where the name and time go.
+    #[serde(default = "default_corner")]
+    pub corner: Corner,
+    /// A PNG drawn at its own size, blended by its alpha.
+    pub logo: Option<PathBuf>,
+    #[serde(default = "default_logo_corner")]
+    pub logo_corner: Corner,
+}
+
+fn default_corner() -> Corner {
+    Corner::BottomLeft
+}
+
+fn default_logo_corner() -> Corner {
+    Corner::TopLeft
+}
+
+/// Which time an overlay shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayTime {
+    /// The server's wall clock when the frame arrived, in the source's time zone.
+    Clock,
+    /// The NDI® sender's timecode; other sources have none.
+    Timecode,
+}
+
+/// A decoded RGBA logo.
+pub struct Logo {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl Logo {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut decoder = png::Decoder::new(file);
+        // Palettes, gray and 16 bits become 8 bit RGB(A).
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| format!("{}: {e}", path.display()))?;
+        let (width, height) = (info.width as usize, info.height as usize);
+        let samples = &buf[..info.buffer_size()];
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => samples.to_vec(),
+            png::ColorType::Rgb => {
+                samples.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect()
+            }
+            png::ColorType::GrayscaleAlpha => {
+                samples.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect()
+            }
+            png::ColorType::Grayscale => samples.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(format!("{}: palette not expanded", path.display()));
+            }
+        };
+        Ok(Self { pixels, width, height })
+    }
+}
+
+/// Draws a source's overlay into a copy of each frame, reusing the copy's buffer.
+pub struct Overlay {
+    config: OverlayConfig,
+    /// The name as drawn: the glyphs are capitals.
+    name: String,
+    times: TimeFormat,
+    logo: Option<Logo>,
+    frame: Vec<u8>,
+}
+
+impl Overlay {
+    /// The overlay `config` asks for on `source`, with times in `times`' zone.
+    /// A logo that can't be read is left out.
+    pub fn new(config: OverlayConfig, source: &str, times: TimeFormat) -> Self {
+        let logo = config.logo.as_deref().and_then(|path| {
+            Logo::load(path).map_err(|e| warn!("[{}] overlay logo: {}", source, e)).ok()
+        });
+        Self { name: source.to_uppercase(), config, times, logo, frame: Vec::new() }
+    }
+
+    /// `frame`, which arrived at `received` with the sender's `timecode`, with
+    /// the overlay drawn on. Frames in layouts it can't be drawn on are passed
+    /// on as they are.
+    pub fn apply<'a>(
+        &'a mut self,
+        frame: &VideoFrame<'a>,
+        received: SystemTime,
+        timecode: Option<i64>,
+    ) -> VideoFrame<'a> {
+        self.frame.clear();
+        self.frame.extend_from_slice(frame.data);
+        let Some(mut canvas) = Canvas::new(&mut self.frame, frame) else {
+            return VideoFrame { ..*frame };
+        };
+        let scale = (frame.height / 240).max(1);
+        let margin = 2 * scale;
+        if let Some(logo) = &self.logo {
+            let (x0, y0) = place(&canvas, self.config.logo_corner, logo.width, logo.height, margin);
+            for (y, row) in logo.pixels.chunks_exact(logo.width * 4).enumerate() {
+                for (x, px) in row.chunks_exact(4).enumerate() {
+                    canvas.blend(x0 + x, y0 + y, [px[0], px[1], px[2]], px[3]);
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        if self.config.name {
+            lines.push(self.name.clone());
+        }
+        match self.config.time {
+            Some(OverlayTime::Clock) => lines.push(clock_text(&self.times, received)),
+            Some(OverlayTime::Timecode) => lines.push(timecode_text(timecode)),
+            None => {}
+        }
+        let sizes: Vec<_> = lines.iter().map(|line| label_size(line, scale)).collect();
+        let width = sizes.iter().map(|&(w, _)| w).max().unwrap_or(0);
+        let height: usize = sizes.iter().map(|&(_, h)| h).sum();
+        if !lines.is_empty() {
+            let (x0, mut y) = place(&canvas, self.config.corner, width, height, margin);
+            let right = matches!(self.config.corner, Corner::TopRight | Corner::BottomRight);
+            for (line, (w, h)) in lines.iter().zip(sizes) {
+                let x = if right { x0 + width - w } else { x0 };
+                draw_label(&mut canvas, line, x, y, scale);
+                y += h;
+            }
+        }
+        VideoFrame { data: &self.frame, ..*frame }
+    }
+}
+
+/// Where something `w` x `h` goes in `corner`, `margin` in from the edges.
+fn place(canvas: &Canvas, corner: Corner, w: usize, h: usize, margin: usize) -> (usize, usize) {
+    let right = canvas.width.saturating_sub(w + margin);
+    let bottom = canvas.height.saturating_sub(h + margin);
+    match corner {
+        Corner::TopLeft => (margin, margin),
+        Corner::TopRight => (right, margin),
+        Corner::BottomLeft => (margin, bottom),
+        Corner::BottomRight => (right, bottom),
+    }
+}
+
+/// The date and time of day to the millisecond, in `times`' zone.
+fn clock_text(times: &TimeFormat, time: SystemTime) -> String {
+    let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis();
+    format!("{}.{millis:03}", times.zoned(time).strftime("%Y-%m-%d %H:%M:%S"))
+}
+
+/// An NDI® timecode as the time of day it counts to, to the millisecond.
+fn timecode_text(timecode: Option<i64>) -> String {
+    let Some(timecode) = timecode else {
+        return "TC --:--:--.---".to_string();
+    };
+    let millis = timecode.rem_euclid(86_400 * TIMECODE_UNITS) / (TIMECODE_UNITS / 1000);
+    let secs = millis / 1000;
+    format!("TC {:02}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, millis % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndi::FourCCVideoType;
+
+    #[test]
+    fn draws_name_time_and_logo_in_their_corners() {
+        let (w, h) = (320, 180);
+        let logo = std::env::temp_dir().join(format!("overlay-logo-{}.png", std::process::id()));
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 8, 8);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255, 0, 0, 255].repeat(64)).unwrap();
+        writer.finish().unwrap();
+        std::fs::write(&logo, &png).unwrap();
+
+        let config = OverlayConfig {
+            name: true,
+            time: Some(OverlayTime::Timecode),
+            corner: Corner::BottomLeft,
+            logo: Some(logo.clone()),
+            logo_corner: Corner::TopRight,
+        };
+        let mut overlay = Overlay::new(config, "Cam (1)", TimeFormat::default());
+        let _ = std::fs::remove_file(&logo);
+        let rgba = vec![128u8; w * h * 4];
+        let fourcc = FourCCVideoType::RGBA;
+        let frame = VideoFrame { data: &rgba, width: w, height: h, stride: w * 4, fourcc };
+        // 01:02:03.456 past midnight, a day later.
+        let timecode = (86_400 + 3_723) * TIMECODE_UNITS + 4_560_000;
+        let drawn = overlay.apply(&frame, UNIX_EPOCH, Some(timecode));
+        let pixel = |x: usize, y: usize| &drawn.data[(y * w + x) * 4..(y * w + x) * 4 + 4];
+        assert_eq!(pixel(w - 6, 5), [255, 0, 0, 128], "logo");
+        assert_eq!(pixel(w / 2, 5), [128; 4], "drawn outside the corners");
+        // Two lines of labels, 11 pixels each, 2 in from the bottom left.
+        assert_eq!(pixel(3, h - 3), [16, 16, 16, 128]);
+        assert_eq!(pixel(3, h - 24), [16, 16, 16, 128]);
+        assert_eq!(pixel(3, h - 25), [128; 4]);
+        assert!(rgba.iter().all(|&b| b == 128), "the producer's frame was drawn on");
+        assert_eq!(timecode_text(Some(timecode)), "TC 01:02:03.456");
+        assert_eq!(clock_text(&TimeFormat::default(), UNIX_EPOCH), "1970-01-01 00:00:00.000");
+    }
+}
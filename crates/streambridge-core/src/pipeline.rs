@@ -0,0 +1,919 @@
+use bytes::Bytes;
+use crate::audio::AudioChunk;
+use crate::badge::ViewerBadge;
+use crate::diff::FrameDiff;
+use crate::encode::{self, EncodeBuffers, Encoder, EncoderKind, OutputFormat};
+use crate::encode_pool::EncodePool;
+use crate::events::{Event, EventBus};
+use crate::latest;
+use crate::levels::{LevelMeter, Levels};
+use crate::loudness::LoudnessMeter;
+use crate::motion::{MotionChange, MotionDetector, MotionSettings};
+use crate::ndi::FourCCVideoType;
+use crate::overlay::Overlay;
+use crate::preroll::Preroll;
+use crate::quality::AdaptiveQuality;
+use crate::receiver::{Control, FrameInfo, JpegFrame, Link};
+use crate::recording::Recorder;
+use crate::stats::SourceStats;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+use tracing::{error, info};
+
+/// Allowed deviation from the loudness target before an alert is raised (EBU R128 live tolerance).
+const LOUDNESS_TOLERANCE_LU: f64 = 1.0;
+
+/// An uncompressed video frame, borrowed from whichever producer captured it.
+pub struct VideoFrame<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub fourcc: FourCCVideoType,
+}
+
+/// Per-source processing shared by all frame producers (NDI receiver, replay file, crop):
+/// fps cap, JPEG encode, broadcast, loudness metering, audio and metadata fan-out,
+/// frame-difference diagnostics and recording.
+pub struct Pipeline {
+    source_name: String,
+    stats: Arc<SourceStats>,
+    outputs: Outputs,
+    events: Arc<EventBus>,
+    encoder_kind: EncoderKind,
+    encoders: Encoders,
+    /// Threads encoding frames instead of the producer's, started with the first frame.
+    encode_threads: usize,
+    encode_pool: Option<EncodePool>,
+    /// Burns the source's name, the time and a logo into frames before they are encoded.
+    overlay: Option<Overlay>,
+    /// Draws the viewer count into frames before they are encoded.
+    viewer_badge: Option<ViewerBadge>,
+    /// Looks for motion in every frame.
+    motion: Option<MotionDetector>,
+    quality: i32,
+    min_frame_interval_ms: u64,
+    /// Lowers the quality and frame rate viewers get while encoding or they
+    /// can't keep up.
+    adaptive: Option<AdaptiveQuality>,
+    max_width: Option<usize>,
+    max_height: Option<usize>,
+    last_send: Instant,
+    meter: Option<LoudnessMeter>,
+    loudness_target: Option<f64>,
+    loudness_in_spec: bool,
+    last_loudness_report: Instant,
+    /// Recording in progress.
+    recorder: Option<Recorder>,
+    /// Set while a recording is wanted, so the producer keeps running without viewers.
+    recording: Arc<AtomicBool>,
+    /// Set while the source's tally is on, for the same reason.
+    on_air: Arc<AtomicBool>,
+    /// PCM for audio listeners.
+    audio_tx: Option<broadcast::Sender<AudioChunk>>,
+    /// Audio levels, for meters.
+    levels_tx: Option<broadcast::Sender<Levels>>,
+    level_meter: LevelMeter,
+    /// The sender's metadata XML, for metadata listeners.
+    metadata_tx: Option<broadcast::Sender<Arc<str>>>,
+    /// Frame-difference images, for diagnostic viewers.
+    diff_tx: Option<latest::Sender<JpegFrame>>,
+    diff: Option<FrameDiff>,
+    last_diff_send: Instant,
+    /// The latest frame's size and format, as last published to the stats.
+    input_format: Option<(usize, usize, FourCCVideoType)>,
+    /// The latest frame's number, timing and size.
+    info: FrameInfo,
+    /// When the latest frame arrived, and the average time between frames.
+    last_frame: Option<Instant>,
+    frame_interval: Option<f64>,
+    /// Whether frames are flowing from the sender, for clients to show.
+    link_tx: Option<watch::Sender<Link>>,
+    /// How long the producer keeps running after the last subscriber left.
+    linger: Duration,
+    /// When the pipeline last had a subscriber.
+    last_subscribed: Cell<Instant>,
+}
+
+impl Drop for Pipeline {
+    /// Motion can't be seen any more, so it ends for listeners too.
+    fn drop(&mut self) {
+        if self.motion.as_ref().is_some_and(MotionDetector::is_moving) {
+            let source = std::mem::take(&mut self.source_name);
+            self.events.publish(Event::MotionStopped { source });
+        }
+    }
+}
+
+/// Encode limits a client asked for, each of which can only lower the source's
+/// own, and the format it wants frames in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub quality: Option<i32>,
+    pub max_fps: Option<u32>,
+    pub max_width: Option<usize>,
+    pub format: OutputFormat,
+}
+
+/// The variant streams of one source, shared by its receiver and pipeline.
+pub type Variants = Mutex<HashMap<Variant, VariantOutput>>;
+
+/// One variant's frames; dropped by the pipeline once nobody subscribes.
+pub struct VariantOutput {
+    tx: latest::Sender<JpegFrame>,
+    last_send: Instant,
+}
+
+impl Default for VariantOutput {
+    fn default() -> Self {
+        Self {
+            tx: latest::Sender::new(),
+            last_send: Instant::now(),
+        }
+    }
+}
+
+impl VariantOutput {
+    pub fn subscribe(&self) -> latest::Receiver<JpegFrame> {
+        self.tx.subscribe()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+impl Pipeline {
+    pub fn new(
+        source_name: String,
+        stats: Arc<SourceStats>,
+        tx: latest::Sender<JpegFrame>,
+        events: Arc<EventBus>,
+        quality: i32,
+        max_fps: u32,
+        loudness_target: Option<f64>,
+    ) -> Self {
+        Self {
+            source_name,
+            stats,
+            outputs: Outputs { tx, preroll: None, variants: Arc::new(Variants::default()) },
+            events,
+            encoder_kind: EncoderKind::default(),
+            encoders: Encoders::default(),
+            encode_threads: 1,
+            encode_pool: None,
+            overlay: None,
+            viewer_badge: None,
+            motion: None,
+            quality,
+            min_frame_interval_ms: if max_fps > 0 { 1000 / max_fps as u64 } else { 0 },
+            adaptive: None,
+            max_width: None,
+            max_height: None,
+            last_send: Instant::now(),
+            meter: loudness_target.map(|_| LoudnessMeter::new()),
+            loudness_target,
+            loudness_in_spec: true,
+            last_loudness_report: Instant::now(),
+            recorder: None,
+            recording: Arc::new(AtomicBool::new(false)),
+            on_air: Arc::new(AtomicBool::new(false)),
+            audio_tx: None,
+            levels_tx: None,
+            level_meter: LevelMeter::new(),
+            metadata_tx: None,
+            diff_tx: None,
+            diff: None,
+            last_diff_send: Instant::now(),
+            input_format: None,
+            info: FrameInfo::default(),
+            last_frame: None,
+            frame_interval: None,
+            link_tx: None,
+            linger: Duration::ZERO,
+            last_subscribed: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Keep the JPEGs viewers get in `preroll`, encoding them even while nobody
+    /// watches.
+    pub fn preroll(mut self, preroll: Arc<Preroll>) -> Self {
+        self.outputs.preroll = Some(preroll);
+        self
+    }
+
+    /// Keep running for `linger` after the last subscriber leaves, so one that
+    /// comes back, e.g. by reloading its page, finds the source warm.
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// Scale frames wider than `max_width` down (by halving) before encoding.
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// Likewise for frames taller than `max_height`.
+    pub fn max_height(mut self, max_height: Option<usize>) -> Self {
+        self.max_height = max_height;
+        self
+    }
+
+    /// Encode JPEGs with `kind` instead of TurboJPEG. TurboJPEG takes over if
+    /// the backend fails, e.g. without the hardware it needs.
+    pub fn encoder(mut self, kind: EncoderKind) -> Self {
+        self.encoder_kind = kind;
+        self.encoders = Encoders::new(kind, &self.source_name);
+        self
+    }
+
+    /// Encode on `threads` threads of the pipeline's own, so the producer only
+    /// copies each frame: for sources too large for one thread to keep up with.
+    /// 1 encodes on the producer's thread.
+    pub fn encode_threads(mut self, threads: usize) -> Self {
+        self.encode_threads = threads.max(1);
+        self
+    }
+
+    /// Burn `overlay` into every frame encoded; diagnostics and raw
+    /// recordings still see the frames as they came.
+    pub fn overlay(mut self, overlay: Option<Overlay>) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// Burn the number of viewers into the top right corner of every frame
+    /// encoded, e.g. for a presenter watching the return feed.
+    pub fn viewer_badge(mut self, enabled: bool) -> Self {
+        self.viewer_badge = enabled.then(ViewerBadge::new);
+        self
+    }
+
+    /// Adapt the quality and frame rate viewers get to the load; see
+    /// [`crate::quality`]. Recordings keep the configured quality.
+    pub fn adaptive_quality(mut self, enabled: bool) -> Self {
+        self.adaptive = enabled.then(|| AdaptiveQuality::new(Instant::now()));
+        self.publish_adapted();
+        self
+    }
+
+    /// Look for motion in every frame, publishing events as it starts and stops.
+    pub fn motion(mut self, settings: Option<MotionSettings>) -> Self {
+        self.motion = settings.map(MotionDetector::new);
+        self
+    }
+
+    /// Serve variant streams from `variants`. The receiver holds it weakly, so
+    /// the variants' channels close along with the pipeline.
+    pub fn variant_outputs(mut self, variants: Arc<Variants>) -> Self {
+        self.outputs.variants = variants;
+        self
+    }
+
+    /// Share the flag that marks this source as on air, so the producer keeps
+    /// the camera's tally lit without viewers.
+    pub fn on_air_flag(mut self, on_air: Arc<AtomicBool>) -> Self {
+        self.on_air = on_air;
+        self
+    }
+
+    /// Share the flag that marks this source as recording.
+    pub fn recording_flag(mut self, recording: Arc<AtomicBool>) -> Self {
+        self.recording = recording;
+        self
+    }
+
+    /// Broadcast the source's audio as PCM whenever someone listens.
+    pub fn audio_output(mut self, audio_tx: broadcast::Sender<AudioChunk>) -> Self {
+        self.audio_tx = Some(audio_tx);
+        self
+    }
+
+    /// Broadcast the source's audio levels whenever someone watches them.
+    pub fn levels_output(mut self, levels_tx: broadcast::Sender<Levels>) -> Self {
+        self.levels_tx = Some(levels_tx);
+        self
+    }
+
+    /// Broadcast the sender's metadata frames whenever someone listens.
+    pub fn metadata_output(mut self, metadata_tx: broadcast::Sender<Arc<str>>) -> Self {
+        self.metadata_tx = Some(metadata_tx);
+        self
+    }
+
+    /// Report the sender connection's state here.
+    pub fn link_output(mut self, link_tx: watch::Sender<Link>) -> Self {
+        self.link_tx = Some(link_tx);
+        self
+    }
+
+    /// Tell clients and event listeners whether frames are flowing from the sender.
+    pub fn link(&self, link: Link) {
+        if let Some(tx) = &self.link_tx {
+            tx.send_replace(link);
+        }
+        let source = self.source_name.clone();
+        self.events.publish(match link {
+            Link::Up => Event::ReceiverReconnected { source },
+            Link::Reconnecting { attempt } => Event::ReceiverReconnecting { source, attempt },
+        });
+    }
+
+    /// Broadcast frame-difference images whenever someone watches them.
+    pub fn diff_output(mut self, diff_tx: latest::Sender<JpegFrame>) -> Self {
+        self.diff_tx = Some(diff_tx);
+        self
+    }
+
+    pub fn stats(&self) -> &SourceStats {
+        &self.stats
+    }
+
+    /// Whether the producer should capture audio for this pipeline.
+    pub fn wants_audio(&self) -> bool {
+        // AVI recordings are video only.
+        let recording_audio = self.recorder.as_ref().is_some_and(|r| !r.wants_jpeg());
+        self.meter.is_some() || recording_audio || self.audio_listeners()
+    }
+
+    fn audio_listeners(&self) -> bool {
+        self.audio_tx.as_ref().is_some_and(|tx| tx.receiver_count() > 0)
+            || self.levels_tx.as_ref().is_some_and(|tx| tx.receiver_count() > 0)
+    }
+
+    /// Whether anyone is still listening or recording, or was within the linger
+    /// time. Producers stop when this turns false.
+    pub fn has_subscribers(&self) -> bool {
+        let subscribed = self.outputs.tx.receiver_count() > 0
+            || self.stats.clients.load(Ordering::Relaxed) > 0
+            || self.recording.load(Ordering::Relaxed)
+            || self.on_air.load(Ordering::Relaxed);
+        if subscribed {
+            self.last_subscribed.set(Instant::now());
+        }
+        subscribed || self.last_subscribed.get().elapsed() < self.linger
+    }
+
+    /// Apply a control message. Those meant for the producer itself, like PTZ
+    /// requests for the NDI receiver it owns, are handed back.
+    pub fn control(&mut self, control: Control) -> Option<Control> {
+        match control {
+            Control::SetQuality(quality) => {
+                info!("[{}] JPEG quality set to {}", self.source_name, quality);
+                self.quality = quality;
+                self.publish_adapted();
+            }
+            Control::SetMaxFps(max_fps) => {
+                info!("[{}] fps cap set to {}", self.source_name, max_fps);
+                self.min_frame_interval_ms = if max_fps > 0 { 1000 / max_fps as u64 } else { 0 };
+            }
+            Control::StartRecording(recorder) => {
+                self.recorder = Some(*recorder);
+            }
+            Control::StopRecording(reply) => {
+                self.write_recorded();
+                let result = match self.recorder.take() {
+                    Some(recorder) => {
+                        recorder.finish().map_err(|e| format!("write failed: {e}"))
+                    }
+                    None => Err("not recording".to_string()),
+                };
+                self.recording.store(false, Ordering::Relaxed);
+                let _ = reply.send(result);
+            }
+            Control::Ptz(_)
+            | Control::SetSplit(_)
+            | Control::SetTally(_)
+            | Control::SetLowBandwidth(_)
+            | Control::Reconnect => return Some(control),
+        }
+        None
+    }
+
+    pub fn video(&mut self, frame: &VideoFrame) {
+        self.video_stamped(frame, None, None);
+    }
+
+    /// Like [`Pipeline::video`], for a frame its NDI® sender stamped with
+    /// `timecode` and `timestamp`, which clients can ask to get with it.
+    pub fn video_stamped(
+        &mut self,
+        frame: &VideoFrame,
+        timecode: Option<i64>,
+        timestamp: Option<i64>,
+    ) {
+        self.stats.frames_in.fetch_add(1, Ordering::Relaxed);
+        // Every stage below slices the frame by its geometry.
+        let (data, w, h) = (frame.data, frame.width, frame.height);
+        if let Err(e) = encode::check_frame(data, w, h, frame.stride, frame.fourcc) {
+            error!("bad frame from \"{}\": {}", self.source_name, e);
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let received = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.info = FrameInfo {
+            seq: self.info.seq + 1,
+            timecode,
+            timestamp,
+            received_ms: received.as_millis() as u64,
+            width: frame.width,
+            height: frame.height,
+        };
+        let format = Some((frame.width, frame.height, frame.fourcc));
+        if format != self.input_format {
+            self.input_format = format;
+            *self.stats.input_format.lock().unwrap() = format;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last_frame.replace(now) {
+            // A moving average over roughly the last ten frames.
+            let interval = (now - last).as_secs_f64();
+            let average = self.frame_interval.map_or(interval, |a| a * 0.9 + interval * 0.1);
+            self.frame_interval = Some(average);
+            if average > 0.0 {
+                *self.stats.input_fps.lock().unwrap() = Some(1.0 / average);
+            }
+        }
+        self.adapt(now);
+        if let Some(recorder) = self.recorder.as_mut().filter(|r| !r.wants_jpeg()) {
+            let write_start = Instant::now();
+            let result = recorder.video(frame);
+            self.stats.stages.record.record(write_start);
+            if let Err(e) = result {
+                self.abort_recording(e);
+            }
+        }
+        let factor =
+            encode::scale_factor(frame.width, frame.height, self.max_width, self.max_height);
+        self.diff(frame, factor);
+        self.detect_motion(frame, now);
+
+        self.write_recorded();
+        let Some(plan) = self.plan(frame, factor) else {
+            return;
+        };
+        let overlaid;
+        let frame = match self.overlay.as_mut() {
+            Some(overlay) => {
+                let started = Instant::now();
+                overlaid = overlay.apply(frame, UNIX_EPOCH + received, timecode);
+                self.stats.stages.burn_in.record(started);
+                &overlaid
+            }
+            None => frame,
+        };
+        let badged;
+        let frame = match self.viewer_badge.as_mut() {
+            Some(badge) => {
+                let started = Instant::now();
+                badged = badge.apply(frame, self.outputs.viewers());
+                self.stats.stages.badge.record(started);
+                &badged
+            }
+            None => frame,
+        };
+        if self.encode_threads > 1 && self.encode_pool.is_none() {
+            let (threads, kind) = (self.encode_threads, self.encoder_kind);
+            let (outputs, stats) = (self.outputs.clone(), Arc::clone(&self.stats));
+            match EncodePool::new(threads, kind, &self.source_name, outputs, stats) {
+                Ok(pool) => self.encode_pool = Some(pool),
+                Err(e) => {
+                    error!("encode threads for \"{}\": {}", self.source_name, e);
+                    self.encode_threads = 1;
+                }
+            }
+        }
+        let recorded = match self.encode_pool.as_mut() {
+            Some(pool) => {
+                pool.submit(frame, self.info, plan);
+                None
+            }
+            None => {
+                let images: Vec<_> = plan
+                    .images
+                    .iter()
+                    .map(|&key| self.encoders.encode(frame, key, &self.stats, &self.source_name))
+                    .collect();
+                plan.deliver(&images, self.info, &self.outputs)
+            }
+        };
+        if let Some(jpeg) = recorded {
+            self.record_jpeg(jpeg);
+        }
+    }
+
+    /// What to encode from a frame shrunk by `factor`, and where each image
+    /// goes; `None` if nobody needs one now.
+    fn plan(&mut self, frame: &VideoFrame, factor: usize) -> Option<Plan> {
+        let variants = Arc::clone(&self.outputs.variants);
+        let mut variants = variants.lock().unwrap();
+        variants.retain(|_, output| output.tx.receiver_count() > 0);
+        let record_jpeg = self.recorder.as_ref().is_some_and(Recorder::wants_jpeg);
+        let record_shared = self.recorder.as_ref().is_some_and(Recorder::is_shared);
+        let main = self.outputs.tx.receiver_count() > 0
+            || self.outputs.preroll.is_some()
+            || record_shared;
+        // Audio listeners and raw recordings keep the producer running; nobody needs JPEGs.
+        if !main && variants.is_empty() && !record_jpeg {
+            return None;
+        }
+        if self.encode_pool.as_ref().is_some_and(EncodePool::is_busy) {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let mut plan = Plan::default();
+
+        // Recordings get every frame, at the quality viewers get by default,
+        // unless they share the viewers' JPEGs.
+        if record_jpeg && !record_shared {
+            plan.record = Some(plan.image((factor, self.quality, OutputFormat::Jpeg)));
+        }
+
+        let (quality, min_interval_ms) = (self.viewer_quality(), self.viewer_interval_ms());
+        if main {
+            // FPS cap: skip if too soon
+            let elapsed = self.last_send.elapsed().as_millis() as u64;
+            if elapsed < min_interval_ms {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.last_send = Instant::now();
+                let image = plan.image((factor, quality, OutputFormat::Jpeg));
+                plan.main = Some(image);
+                if record_shared {
+                    plan.record = Some(image);
+                }
+            }
+        }
+        for (variant, output) in variants.iter_mut() {
+            let interval = match variant.max_fps {
+                Some(fps) if fps > 0 => min_interval_ms.max(1000 / fps as u64),
+                _ => min_interval_ms,
+            };
+            if (output.last_send.elapsed().as_millis() as u64) < interval {
+                continue;
+            }
+            output.last_send = Instant::now();
+            let (w, h) = (frame.width, frame.height);
+            let factor = factor.max(encode::scale_factor(w, h, variant.max_width, None));
+            let quality = variant.quality.map_or(quality, |q| q.clamp(1, quality));
+            let image = plan.image((factor, quality, variant.format));
+            plan.variants.push((*variant, image));
+        }
+        (!plan.images.is_empty()).then_some(plan)
+    }
+
+    /// The quality viewers get, below the configured one while adapting.
+    fn viewer_quality(&self) -> i32 {
+        self.adaptive.as_ref().map_or(self.quality, |a| a.quality(self.quality))
+    }
+
+    /// The least time between viewers' frames, longer while adapting.
+    fn viewer_interval_ms(&self) -> u64 {
+        let adapted = self.adaptive.as_ref().and_then(AdaptiveQuality::max_fps);
+        let adapted = adapted.map_or(0, |fps| (1000.0 / fps) as u64);
+        self.min_frame_interval_ms.max(adapted)
+    }
+
+    /// Step the adaptive quality on the encode time and skipped frames so far.
+    fn adapt(&mut self, now: Instant) {
+        let Some(adaptive) = self.adaptive.as_mut() else {
+            return;
+        };
+        // The rate viewers would get without adapting.
+        let source_fps = self.frame_interval.filter(|&i| i > 0.0).map(|i| 1.0 / i);
+        let interval = self.min_frame_interval_ms;
+        let capped = (interval > 0).then(|| 1000.0 / interval as f64);
+        let fps = match (source_fps, capped) {
+            (Some(fps), Some(cap)) => Some(fps.min(cap)),
+            (fps, cap) => fps.or(cap),
+        };
+        let encode = self.stats.stages.encode.total();
+        let skipped = self.stats.skipped.load(Ordering::Relaxed);
+        if adaptive.update(now, self.quality, encode, skipped, self.encode_threads, fps) {
+            let fps = match adaptive.max_fps() {
+                Some(fps) => format!("capped at {fps:.1} fps"),
+                None => "fps as configured".to_string(),
+            };
+            let quality = adaptive.quality(self.quality);
+            info!("[{}] adaptive quality: JPEG quality {}, {}", self.source_name, quality, fps);
+            self.publish_adapted();
+        }
+    }
+
+    fn publish_adapted(&self) {
+        let adapted = self.adaptive.as_ref().map(|a| (a.quality(self.quality), a.max_fps()));
+        *self.stats.adapted.lock().unwrap() = adapted;
+    }
+
+    /// Write the JPEGs the encode threads finished for the recording.
+    fn write_recorded(&mut self) {
+        let Some(pool) = &self.encode_pool else {
+            return;
+        };
+        let recorded: Vec<Bytes> = pool.recorded().collect();
+        for jpeg in recorded {
+            if self.recorder.is_some() {
+                self.record_jpeg(jpeg);
+            }
+        }
+    }
+
+    /// Write `jpeg` to the recording.
+    fn record_jpeg(&mut self, jpeg: Bytes) {
+        let write_start = Instant::now();
+        let result = self.recorder.as_mut().map_or(Ok(()), |r| r.jpeg(jpeg, write_start));
+        self.stats.stages.record.record(write_start);
+        if let Err(e) = result {
+            self.abort_recording(e);
+        }
+    }
+
+    /// Compare `frame` with the previous one for diagnostic viewers. Every frame is
+    /// compared, so single-frame glitches show; the images sent are fps capped.
+    fn diff(&mut self, frame: &VideoFrame, factor: usize) {
+        let Some(diff_tx) = self.diff_tx.as_ref().filter(|tx| tx.receiver_count() > 0) else {
+            // Nobody watching: start over from the next frame someone sees.
+            self.diff = None;
+            return;
+        };
+        let diff = match self.diff.as_mut() {
+            Some(diff) => diff,
+            None => match FrameDiff::new() {
+                Ok(diff) => self.diff.insert(diff),
+                Err(e) => {
+                    error!("frame diff for \"{}\": {}", self.source_name, e);
+                    return;
+                }
+            },
+        };
+        let diff_start = Instant::now();
+        let result = diff.process(frame, factor, self.quality);
+        self.stats.stages.diff.record(diff_start);
+        match result {
+            Ok(Some(jpeg)) => {
+                if self.last_diff_send.elapsed().as_millis() as u64 >= self.min_frame_interval_ms {
+                    self.last_diff_send = Instant::now();
+                    let frame = JpegFrame { data: Bytes::from(jpeg), info: self.info };
+                    diff_tx.send(frame);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("frame diff for \"{}\": {}", self.source_name, e),
+        }
+    }
+
+    /// Look for motion in `frame`, which arrived at `now`, counting and
+    /// announcing when it starts and stops.
+    fn detect_motion(&mut self, frame: &VideoFrame, now: Instant) {
+        let Some(motion) = self.motion.as_mut() else {
+            return;
+        };
+        let started = Instant::now();
+        let change = motion.process(frame, now);
+        self.stats.stages.motion.record(started);
+        let source = self.source_name.clone();
+        let event = match change {
+            Some(MotionChange::Started { score }) => {
+                self.stats.motion_events.fetch_add(1, Ordering::Relaxed);
+                Event::MotionStarted { source, score }
+            }
+            Some(MotionChange::Stopped) => Event::MotionStopped { source },
+            None => return,
+        };
+        self.stats.motion.store(motion.is_moving(), Ordering::Relaxed);
+        self.events.publish(event);
+    }
+
+    /// Feed one frame of planar float audio.
+    pub fn audio(&mut self, sample_rate: u32, channels: &[&[f32]]) {
+        if let Some(meter) = self.meter.as_mut() {
+            meter.process(sample_rate, channels);
+        }
+        if let Some(audio_tx) = self.audio_tx.as_ref().filter(|tx| tx.receiver_count() > 0) {
+            let _ = audio_tx.send(AudioChunk::from_planar(sample_rate, channels));
+        }
+        if let Some(levels_tx) = self.levels_tx.as_ref().filter(|tx| tx.receiver_count() > 0) {
+            if let Some(levels) = self.level_meter.process(sample_rate, channels) {
+                let _ = levels_tx.send(levels);
+            }
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.audio(sample_rate, channels) {
+                self.abort_recording(e);
+            }
+        }
+    }
+
+    /// Pass on one metadata frame's XML, e.g. tally, captions or custom data.
+    pub fn metadata(&self, xml: &str) {
+        if let Some(metadata_tx) = self.metadata_tx.as_ref().filter(|tx| tx.receiver_count() > 0) {
+            let _ = metadata_tx.send(Arc::from(xml));
+        }
+    }
+
+    fn abort_recording(&mut self, e: std::io::Error) {
+        error!("recording \"{}\" stopped: write failed: {}", self.source_name, e);
+        self.recorder = None;
+        self.recording.store(false, Ordering::Relaxed);
+    }
+
+    /// Periodic housekeeping; call once per producer loop iteration.
+    pub fn tick(&mut self) {
+        self.write_recorded();
+        if let (Some(_), Some(target)) = (self.meter.as_ref(), self.loudness_target) {
+            if self.last_loudness_report.elapsed() >= Duration::from_secs(1) {
+                self.report_loudness(target);
+                self.last_loudness_report = Instant::now();
+            }
+        }
+    }
+
+    /// Publish the latest loudness reading and raise an event whenever integrated
+    /// loudness crosses the target window.
+    fn report_loudness(&mut self, target: f64) {
+        let Some(meter) = self.meter.as_ref() else {
+            return;
+        };
+        let reading = meter.reading();
+        *self.stats.loudness.lock().unwrap() = Some(reading);
+
+        let Some(integrated) = reading.integrated else {
+            return;
+        };
+        let in_spec = (integrated - target).abs() <= LOUDNESS_TOLERANCE_LU;
+        if in_spec != self.loudness_in_spec {
+            let source = self.source_name.clone();
+            self.events.publish(if in_spec {
+                Event::LoudnessInSpec { source, integrated }
+            } else {
+                Event::LoudnessOutOfSpec { source, integrated, target }
+            });
+            self.loudness_in_spec = in_spec;
+        }
+    }
+}
+
+/// Where encoded frames go; shared with the encode threads.
+#[derive(Clone)]
+pub(crate) struct Outputs {
+    tx: latest::Sender<JpegFrame>,
+    /// The last seconds of what viewers got, kept even without viewers.
+    preroll: Option<Arc<Preroll>>,
+    /// Streams encoded to clients' own quality, fps and width limits.
+    variants: Arc<Variants>,
+}
+
+impl Outputs {
+    /// Subscribers to the source's frames and to its variants.
+    fn viewers(&self) -> u64 {
+        let variants = self.variants.lock().unwrap();
+        let variants: usize = variants.values().map(VariantOutput::receiver_count).sum();
+        (self.tx.receiver_count() + variants) as u64
+    }
+}
+
+/// The shrink factor, quality and format of one image encoded from a frame.
+pub(crate) type ImageKey = (usize, i32, OutputFormat);
+
+/// The images to encode from one frame and where each goes, as indexes into
+/// `images`.
+#[derive(Default)]
+pub(crate) struct Plan {
+    /// No two alike: outputs asking for the same share one encode.
+    pub images: Vec<ImageKey>,
+    record: Option<usize>,
+    main: Option<usize>,
+    variants: Vec<(Variant, usize)>,
+}
+
+impl Plan {
+    /// The index of the image made with `key`, added if new.
+    fn image(&mut self, key: ImageKey) -> usize {
+        self.images.iter().position(|&k| k == key).unwrap_or_else(|| {
+            self.images.push(key);
+            self.images.len() - 1
+        })
+    }
+
+    /// Send the `images` of the frame `info` describes, in the order planned, on
+    /// to viewers and the preroll; returns the one to record. Failed encodes are
+    /// `None`.
+    pub fn deliver(
+        &self,
+        images: &[Option<Bytes>],
+        info: FrameInfo,
+        outputs: &Outputs,
+    ) -> Option<Bytes> {
+        let image = |i: Option<usize>| i.and_then(|i| images[i].clone());
+        if let Some(jpeg) = image(self.main) {
+            if let Some(preroll) = &outputs.preroll {
+                preroll.push(jpeg.clone());
+            }
+            outputs.tx.send(JpegFrame { data: jpeg, info });
+        }
+        if !self.variants.is_empty() {
+            let variants = outputs.variants.lock().unwrap();
+            for (variant, i) in &self.variants {
+                if let (Some(output), Some(data)) = (variants.get(variant), image(Some(*i))) {
+                    output.tx.send(JpegFrame { data, info });
+                }
+            }
+        }
+        image(self.record)
+    }
+}
+
+/// One thread's encoders: the JPEG one chosen with `--encoder`, and those of
+/// the other formats clients asked for, made on first use.
+pub(crate) struct Encoders {
+    jpeg: Box<dyn Encoder>,
+    formats: HashMap<OutputFormat, Box<dyn Encoder>>,
+    /// Scratch space for frames scaled down.
+    scaled: Vec<u8>,
+}
+
+impl Default for Encoders {
+    fn default() -> Self {
+        Self { jpeg: Box::new(EncodeBuffers::new()), formats: HashMap::new(), scaled: Vec::new() }
+    }
+}
+
+impl Encoders {
+    /// JPEGs made with `kind`, or with TurboJPEG if the backend can't start,
+    /// e.g. without the hardware it needs.
+    pub fn new(kind: EncoderKind, source_name: &str) -> Self {
+        match kind.create() {
+            Ok(jpeg) => Self { jpeg, ..Self::default() },
+            Err(e) => {
+                error!("{} encoder for \"{}\": {}", kind.name(), source_name, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// `frame` as `key` says, counted in `stats`; `None` if encoding failed.
+    pub fn encode(
+        &mut self,
+        frame: &VideoFrame,
+        (factor, quality, format): ImageKey,
+        stats: &SourceStats,
+        source_name: &str,
+    ) -> Option<Bytes> {
+        let encoder = match format {
+            OutputFormat::Jpeg => &mut self.jpeg,
+            other => self.formats.entry(other).or_insert_with(|| other.encoder()),
+        };
+        let encode_start = Instant::now();
+        let result = if factor > 1 {
+            encode::decimate(
+                frame.data,
+                frame.width,
+                frame.height,
+                frame.stride,
+                frame.fourcc,
+                factor,
+                &mut self.scaled,
+            )
+            .and_then(|(w, h, stride)| {
+                stats.stages.scale.record(encode_start);
+                let scaled = &self.scaled;
+                let compress_start = Instant::now();
+                let image = encoder.encode(scaled, w, h, stride, frame.fourcc, quality);
+                stats.stages.encode.record(compress_start);
+                image
+            })
+        } else {
+            let image = encoder.encode(
+                frame.data,
+                frame.width,
+                frame.height,
+                frame.stride,
+                frame.fourcc,
+                quality,
+            );
+            stats.stages.encode.record(encode_start);
+            image
+        };
+        match result {
+            Ok(image) => {
+                let encode_us = encode_start.elapsed().as_micros() as u64;
+                stats.encode_time_us.fetch_add(encode_us, Ordering::Relaxed);
+                stats.encode_count.fetch_add(1, Ordering::Relaxed);
+                stats.bytes_out.fetch_add(image.len() as u64, Ordering::Relaxed);
+                stats.frames_out.fetch_add(1, Ordering::Relaxed);
+                Some(image)
+            }
+            Err(e) => {
+                error!("encode error for \"{}\": {}", source_name, e);
+                stats.dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
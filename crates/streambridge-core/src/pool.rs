@@ -0,0 +1,125 @@
+//! Reusable frame buffers. Encoded frames are shared by every client, the
+//! preroll and recordings as [`Bytes`], and each buffer comes back to its pool
+//! once the last of them lets go, so a steady stream allocates nothing per frame.
+
+use bytes::Bytes;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Buffers kept for reuse; any more are freed. Enough for a few frames in each
+/// client's queue.
+const MAX_FREE: usize = 16;
+
+/// Buffers waiting to be reused.
+#[derive(Default)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes: a free one if
+    /// there is one, preferably one already large enough.
+    pub fn take(self: &Arc<Self>, capacity: usize) -> PooledBuf {
+        let buf = {
+            let mut free = self.free.lock().unwrap();
+            let fits = free.iter().position(|buf| buf.capacity() >= capacity);
+            match fits.or(free.len().checked_sub(1)) {
+                Some(i) => free.swap_remove(i),
+                None => Vec::new(),
+            }
+        };
+        let mut buf = PooledBuf { buf, pool: Arc::downgrade(self) };
+        buf.reserve(capacity);
+        buf
+    }
+
+    /// `data`, copied into a pooled buffer.
+    pub fn copy(self: &Arc<Self>, data: &[u8]) -> Bytes {
+        let mut buf = self.take(data.len());
+        buf.extend_from_slice(data);
+        buf.freeze()
+    }
+
+    /// How many buffers wait to be reused.
+    pub fn free(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+/// A buffer from a [`BufferPool`], returned to it when dropped.
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    /// Held weakly, so buffers still out don't keep a dropped pool alive.
+    pool: Weak<BufferPool>,
+}
+
+impl PooledBuf {
+    /// Share the buffer; it goes back to the pool once every clone is dropped.
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        let Some(pool) = self.pool.upgrade() else {
+            return;
+        };
+        let mut free = pool.free.lock().unwrap();
+        if free.len() < MAX_FREE {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_come_back_once_every_clone_is_dropped() {
+        let pool = BufferPool::new();
+        let frame = pool.copy(b"jpeg");
+        let shared = frame.clone();
+        drop(frame);
+        assert_eq!((pool.free(), &shared[..]), (0, &b"jpeg"[..]));
+        let address = shared.as_ptr();
+        drop(shared);
+        assert_eq!(pool.free(), 1);
+
+        // The same allocation, reused.
+        let again = pool.take(4);
+        assert_eq!((again.len(), again.as_ptr()), (0, address));
+        drop(again);
+
+        let outlived = pool.take(1);
+        drop(pool);
+        drop(outlived);
+    }
+}
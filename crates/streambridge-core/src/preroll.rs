@@ -0,0 +1,74 @@
+//! The last few seconds of a source's JPEGs, kept in memory so a recording or
+//! capture started by an event can show what led up to it.
+
+use crate::avi::AviWriter;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::io::{self, Cursor};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The frames a source's receiver sent within the last `window`. Outlives the
+/// receiver, so the moments before a source was lost stay available.
+pub struct Preroll {
+    window: Duration,
+    frames: Mutex<VecDeque<(Instant, Bytes)>>,
+}
+
+impl Preroll {
+    pub fn new(window: Duration) -> Self {
+        Self { window, frames: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Add a frame, dropping those older than the window.
+    pub fn push(&self, jpeg: Bytes) {
+        let now = Instant::now();
+        let mut frames = self.frames.lock().unwrap();
+        while frames.front().is_some_and(|(at, _)| now - *at > self.window) {
+            frames.pop_front();
+        }
+        frames.push_back((now, jpeg));
+    }
+
+    /// The buffered frames with when they were sent, oldest first.
+    pub fn frames(&self) -> Vec<(Instant, Bytes)> {
+        self.frames.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// The buffered frames as an AVI file; `None` while there are none.
+    pub fn avi(&self) -> Option<io::Result<Vec<u8>>> {
+        let frames = self.frames();
+        if frames.is_empty() {
+            return None;
+        }
+        let write = || {
+            let mut avi = AviWriter::new(Cursor::new(Vec::new()))?;
+            for (at, jpeg) in &frames {
+                avi.write_frame(jpeg, *at)?;
+            }
+            Ok(avi.finish()?.into_inner())
+        };
+        Some(write())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_older_than_the_window_are_dropped() {
+        let preroll = Preroll::new(Duration::from_millis(50));
+        assert!(preroll.avi().is_none());
+        preroll.push(Bytes::from_static(b"old"));
+        std::thread::sleep(Duration::from_millis(80));
+        preroll.push(Bytes::from_static(b"new"));
+        let frames = preroll.frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].1, "new");
+    }
+}
@@ -0,0 +1,319 @@
+//! `streambridge publish`: the reverse direction. Frames from an MJPEG stream,
+//! a raw capture file or a generated clock are sent out as an NDI® source.
+
+use crate::clock::{ClockConfig, ClockRenderer, NtpStatus};
+use crate::ndi::{self, AudioFrame, FourCCVideoType, SendInstance};
+use crate::rawfile::{RawReader, Record};
+use bytes::{Buf, Bytes, BytesMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::warn;
+
+/// What `publish --input` reads from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// A `multipart/x-mixed-replace` MJPEG stream over plain HTTP, such as another
+    /// bridge's `/stream/<source>` or an IP camera.
+    Mjpeg(String),
+    /// A raw capture file, played in a loop at its recorded pace.
+    File(PathBuf),
+    /// A generated clock.
+    Clock,
+}
+
+impl Input {
+    /// `http://...` for MJPEG, `clock`, or else a file path.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.starts_with("http://") {
+            Ok(Self::Mjpeg(s.to_string()))
+        } else if s.starts_with("https://") {
+            Err("only plain http:// MJPEG streams are supported".to_string())
+        } else if s == "clock" {
+            Ok(Self::Clock)
+        } else if s.is_empty() {
+            Err("empty input".to_string())
+        } else {
+            Ok(Self::File(PathBuf::from(s)))
+        }
+    }
+}
+
+/// Send a clock at `config`'s size and rate until `stop`, letting `edit` draw on
+/// each UYVY frame before it goes out.
+pub fn clock(
+    sender: &SendInstance,
+    config: &ClockConfig,
+    stop: &AtomicBool,
+    mut edit: impl FnMut(&mut [u8], SystemTime),
+) {
+    let mut clock = ClockRenderer::new(config);
+    let mut frame = ndi::VideoFrame::new(config.width, config.height, FourCCVideoType::UYVY)
+        .frame_rate(config.fps as i32, 1);
+    let interval = Duration::from_secs(1) / config.fps;
+    let mut ntp = NtpStatus::query();
+    let mut ntp_checked = Instant::now();
+    let mut due = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        if ntp_checked.elapsed() >= Duration::from_secs(10) {
+            ntp = NtpStatus::query();
+            ntp_checked = Instant::now();
+        }
+        let now = SystemTime::now();
+        frame.data_mut().copy_from_slice(clock.render(now, ntp).data);
+        edit(frame.data_mut(), now);
+        sender.send_video(&frame);
+
+        due += interval;
+        match due.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            None => due = Instant::now(),
+        }
+    }
+}
+
+/// Send a raw capture file's video and audio in a loop until `stop`, paced by
+/// the times they were recorded at. The sender must not clock video or audio.
+pub fn file(sender: &SendInstance, path: &Path, stop: &AtomicBool) -> Result<(), String> {
+    while !stop.load(Ordering::Relaxed) {
+        let mut reader = RawReader::open(path)
+            .map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+        let start = Instant::now();
+        let mut records = 0u64;
+        while let Some(timed) = reader
+            .next_record()
+            .map_err(|e| format!("read error in {}: {e}", path.display()))?
+        {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            records += 1;
+            let due = start + Duration::from_micros(timed.time_us);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            match timed.record {
+                Record::Video(video) => {
+                    let fourcc = FourCCVideoType::from(video.fourcc);
+                    let frame = ndi::VideoFrame::from_data(
+                        video.width,
+                        video.height,
+                        fourcc,
+                        video.stride,
+                        video.data,
+                    )
+                    .map_err(|e| format!("bad frame in {}: {e}", path.display()))?;
+                    sender.send_video(&frame);
+                }
+                Record::Audio(audio) => {
+                    let channels: Vec<&[f32]> = audio.channels.iter().map(Vec::as_slice).collect();
+                    if let Ok(frame) = AudioFrame::from_planar(audio.sample_rate, &channels) {
+                        sender.send_audio(&frame);
+                    }
+                }
+            }
+        }
+        if records == 0 {
+            return Err(format!("{} holds no frames", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Send the JPEGs of an MJPEG stream at `url` as they arrive, until the stream
+/// ends or `stop`. `fps` is announced to receivers as the nominal rate.
+pub async fn mjpeg(
+    sender: &SendInstance,
+    url: &str,
+    fps: u32,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    use http_body_util::BodyExt;
+    use hyper::{header, Request, Uri};
+
+    let uri: Uri = url.parse().map_err(|e| format!("invalid URL \"{url}\": {e}"))?;
+    let authority = uri.authority().ok_or("URL has no host")?.clone();
+    let port = authority.port_u16().unwrap_or(80);
+    let stream = tokio::net::TcpStream::connect((authority.host(), port))
+        .await
+        .map_err(|e| format!("failed to connect to {authority}: {e}"))?;
+    let io = hyper_util::rt::TokioIo::new(stream);
+    let (mut client, conn) = hyper::client::conn::http1::handshake(io)
+        .await
+        .map_err(|e| format!("handshake failed: {e}"))?;
+    tokio::spawn(conn);
+
+    let request = Request::get(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(header::HOST, authority.as_str())
+        .body(http_body_util::Empty::<Bytes>::new())
+        .map_err(|e| format!("invalid request: {e}"))?;
+    let response = client
+        .send_request(request)
+        .await
+        .map_err(|e| format!("request failed: {e}"))?;
+    if !response.status().is_success() {
+        return Err(format!("stream refused: {}", response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let boundary = boundary(content_type)
+        .ok_or_else(|| format!("not an MJPEG stream (Content-Type: {content_type})"))?;
+
+    let mut parts = MultipartReader::new(&boundary);
+    let mut decoder = JpegDecoder::new(fps)?;
+    let mut body = response.into_body();
+    while !stop.load(Ordering::Relaxed) {
+        let Some(chunk) = body.frame().await else {
+            return Err("stream ended".to_string());
+        };
+        let chunk = chunk.map_err(|e| format!("read error: {e}"))?;
+        let Ok(data) = chunk.into_data() else {
+            continue;
+        };
+        parts.push(&data);
+        while let Some(jpeg) = parts.next_part() {
+            match decoder.decode(&jpeg) {
+                Ok(frame) => sender.send_video(frame),
+                Err(e) => warn!("publish: skipping frame: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The `boundary` parameter of a `multipart/x-mixed-replace` content type.
+fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/x-mixed-replace") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        let value = value.trim().trim_matches('"');
+        (key.trim().eq_ignore_ascii_case("boundary") && !value.is_empty())
+            .then(|| value.trim_start_matches("--").to_string())
+    })
+}
+
+/// Splits a multipart body into its parts' bodies as bytes arrive. Parts with a
+/// `Content-Length` are cut at that length; others run to the next boundary.
+struct MultipartReader {
+    delimiter: Vec<u8>,
+    buf: BytesMut,
+}
+
+impl MultipartReader {
+    fn new(boundary: &str) -> Self {
+        Self { delimiter: format!("--{boundary}").into_bytes(), buf: BytesMut::new() }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The next complete part's body, if one has fully arrived.
+    fn next_part(&mut self) -> Option<Bytes> {
+        loop {
+            let start = find(&self.buf, &self.delimiter)?;
+            let headers_end = find(&self.buf[start..], b"\r\n\r\n")? + start + 4;
+            let headers = String::from_utf8_lossy(&self.buf[start..headers_end]);
+            let length = headers.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                let is_length = name.trim().eq_ignore_ascii_case("content-length");
+                is_length.then(|| value.trim().parse::<usize>().ok())?
+            });
+            let end = match length {
+                Some(length) if self.buf.len() >= headers_end + length => headers_end + length,
+                Some(_) => return None,
+                None => {
+                    let next = find(&self.buf[headers_end..], &self.delimiter)? + headers_end;
+                    // The CRLF before a delimiter belongs to it, not to the part.
+                    next - usize::from(self.buf[..next].ends_with(b"\r\n")) * 2
+                }
+            };
+            self.buf.advance(headers_end);
+            let body = self.buf.split_to(end - headers_end).freeze();
+            if !body.is_empty() {
+                return Some(body);
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Decodes JPEGs into a reused BGRA frame for sending.
+pub(crate) struct JpegDecoder {
+    decompressor: turbojpeg::Decompressor,
+    fps: u32,
+    frame: Option<ndi::VideoFrame>,
+}
+
+impl JpegDecoder {
+    pub(crate) fn new(fps: u32) -> Result<Self, String> {
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        Ok(Self { decompressor, fps, frame: None })
+    }
+
+    pub(crate) fn decode(&mut self, jpeg: &[u8]) -> Result<&ndi::VideoFrame, String> {
+        let header = self
+            .decompressor
+            .read_header(jpeg)
+            .map_err(|e| format!("turbojpeg header error: {e}"))?;
+        let (w, h) = (header.width, header.height);
+        let fits = self.frame.as_ref().is_some_and(|f| (f.width(), f.height()) == (w, h));
+        if !fits {
+            let frame = ndi::VideoFrame::new(w, h, FourCCVideoType::BGRA);
+            self.frame = Some(frame.frame_rate(self.fps as i32, 1));
+        }
+        let frame = self.frame.as_mut().expect("frame allocated above");
+        let image = turbojpeg::Image {
+            pixels: frame.data_mut(),
+            width: w,
+            pitch: w * 4,
+            height: h,
+            format: turbojpeg::PixelFormat::BGRA,
+        };
+        self.decompressor
+            .decompress(jpeg, image)
+            .map_err(|e| format!("turbojpeg decompress error: {e}"))?;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inputs_and_boundaries() {
+        assert_eq!(Input::parse("clock"), Ok(Input::Clock));
+        assert!(matches!(Input::parse("http://cam/stream/a"), Ok(Input::Mjpeg(_))));
+        assert!(Input::parse("https://cam/").is_err());
+        assert_eq!(Input::parse("a.sbraw"), Ok(Input::File("a.sbraw".into())));
+
+        let mime = "multipart/x-mixed-replace; boundary=\"--frame\"";
+        assert_eq!(boundary(mime).as_deref(), Some("frame"));
+        assert_eq!(boundary("image/jpeg"), None);
+    }
+
+    #[test]
+    fn splits_parts_across_chunks() {
+        let stream = b"--frame\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\nAB\r\n\r\n\
+            --frame\r\nContent-Type: image/jpeg\r\n\r\nXYZ\r\n--frame\r\n";
+        let mut parts = MultipartReader::new("frame");
+        let mut got = Vec::new();
+        for chunk in stream.chunks(5) {
+            parts.push(chunk);
+            got.extend(std::iter::from_fn(|| parts.next_part()));
+        }
+        assert_eq!(got, [Bytes::from_static(b"AB\r\n"), Bytes::from_static(b"XYZ")]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Adaptive quality: a feedback loop that lowers a source's JPEG quality, then
+//! its frame rate, while encoding can't keep up with its frames or clients keep
+//! falling behind, and raises them back once there is headroom again.
+
+use std::time::{Duration, Instant};
+
+/// How often the load is measured and the settings may change.
+const WINDOW: Duration = Duration::from_secs(1);
+/// Encoding busier than this share of the time (per thread) is overload: the
+/// average encode time is near the frame interval.
+const HIGH_LOAD: f64 = 0.8;
+/// Encoding less busy than this has room for more.
+const LOW_LOAD: f64 = 0.5;
+/// Windows in a row in which clients skipped frames before that counts as lag.
+const LAG_WINDOWS: u32 = 2;
+/// How long things must stay calm before a step back up.
+const RECOVERY_PERIOD: Duration = Duration::from_secs(5);
+/// Quality is lowered this far before the frame rate is capped.
+pub const MIN_QUALITY: i32 = 30;
+const QUALITY_STEP_DOWN: i32 = 10;
+const QUALITY_STEP_UP: i32 = 5;
+/// The lowest frame rate a source is capped to.
+const MIN_FPS: f64 = 5.0;
+/// Assumed for sources whose frame rate isn't known yet.
+const DEFAULT_FPS: f64 = 25.0;
+
+/// One source's adaptive quality. Feed it the encode time and skipped frames
+/// counted so far as frames arrive.
+#[derive(Debug)]
+pub struct AdaptiveQuality {
+    /// Quality points taken off the configured quality.
+    reduction: i32,
+    /// The frame rate the source is capped to, or `None` for its configured one.
+    fps: Option<f64>,
+    window_start: Instant,
+    encode_at_start: Duration,
+    skipped_at_start: u64,
+    /// Windows in a row in which clients skipped frames.
+    lagging: u32,
+    calm_since: Instant,
+}
+
+impl AdaptiveQuality {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            reduction: 0,
+            fps: None,
+            window_start: now,
+            encode_at_start: Duration::ZERO,
+            skipped_at_start: 0,
+            lagging: 0,
+            calm_since: now,
+        }
+    }
+
+    /// The quality to encode at, given the configured `ceiling`.
+    pub fn quality(&self, ceiling: i32) -> i32 {
+        (ceiling - self.reduction).max(MIN_QUALITY.min(ceiling))
+    }
+
+    /// The frame rate the source is capped to, if lowered.
+    pub fn max_fps(&self) -> Option<f64> {
+        self.fps
+    }
+
+    /// Take the source's totals at `now`: time spent encoding on `threads`
+    /// threads and frames its clients skipped. Once a window has passed,
+    /// steps the settings and returns whether they changed.
+    pub fn update(
+        &mut self,
+        now: Instant,
+        ceiling: i32,
+        encode: Duration,
+        skipped: u64,
+        threads: usize,
+        source_fps: Option<f64>,
+    ) -> bool {
+        let elapsed = now - self.window_start;
+        if elapsed < WINDOW {
+            return false;
+        }
+        let busy = encode.saturating_sub(self.encode_at_start).as_secs_f64();
+        let load = busy / elapsed.as_secs_f64() / threads.max(1) as f64;
+        let skips = skipped.saturating_sub(self.skipped_at_start);
+        self.window_start = now;
+        self.encode_at_start = encode;
+        self.skipped_at_start = skipped;
+        self.lagging = if skips > 0 { self.lagging + 1 } else { 0 };
+
+        if load > HIGH_LOAD || self.lagging >= LAG_WINDOWS {
+            self.lagging = 0;
+            self.calm_since = now;
+            return self.step_down(ceiling, source_fps);
+        }
+        if load >= LOW_LOAD || skips > 0 {
+            self.calm_since = now;
+            return false;
+        }
+        if now - self.calm_since < RECOVERY_PERIOD {
+            return false;
+        }
+        self.calm_since = now;
+        self.step_up(source_fps)
+    }
+
+    /// Lower the quality, or once it is as low as it goes, the frame rate.
+    fn step_down(&mut self, ceiling: i32, source_fps: Option<f64>) -> bool {
+        if self.quality(ceiling) > MIN_QUALITY {
+            self.reduction = (self.reduction + QUALITY_STEP_DOWN).min(ceiling - MIN_QUALITY);
+            return true;
+        }
+        let fps = self.fps.or(source_fps).unwrap_or(DEFAULT_FPS);
+        let lowered = (fps * 0.75).max(MIN_FPS);
+        let changed = self.fps != Some(lowered);
+        self.fps = Some(lowered);
+        changed
+    }
+
+    /// Undo a step down: the frame rate first, then the quality.
+    fn step_up(&mut self, source_fps: Option<f64>) -> bool {
+        if let Some(fps) = self.fps {
+            let raised = fps / 0.75;
+            self.fps = (raised < source_fps.unwrap_or(DEFAULT_FPS)).then_some(raised);
+            return true;
+        }
+        if self.reduction > 0 {
+            self.reduction = (self.reduction - QUALITY_STEP_UP).max(0);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_down_under_load_and_back_up_with_headroom() {
+        let start = Instant::now();
+        let mut adaptive = AdaptiveQuality::new(start);
+        let (mut now, mut encode, mut skipped) = (start, Duration::ZERO, 0);
+        let mut window = |adaptive: &mut AdaptiveQuality, busy: f64, skips: u64| {
+            now += WINDOW;
+            encode += WINDOW.mul_f64(busy);
+            skipped += skips;
+            adaptive.update(now, 75, encode, skipped, 1, Some(30.0))
+        };
+        assert!(!adaptive.update(start, 75, Duration::ZERO, 0, 1, Some(30.0)), "mid-window");
+
+        // Encoding takes nearly the whole frame interval.
+        assert!(window(&mut adaptive, 0.9, 0));
+        assert_eq!(adaptive.quality(75), 65);
+        for _ in 0..4 {
+            window(&mut adaptive, 0.9, 0);
+        }
+        assert_eq!(adaptive.quality(75), MIN_QUALITY);
+        assert_eq!(adaptive.max_fps(), None);
+        assert!(window(&mut adaptive, 0.9, 0));
+        assert_eq!(adaptive.max_fps(), Some(22.5));
+
+        // A single skip is noise; skips in window after window are lag.
+        assert!(!window(&mut adaptive, 0.6, 3));
+        assert!(window(&mut adaptive, 0.6, 1));
+        assert!(adaptive.max_fps().unwrap() < 22.5);
+
+        // Calm windows bring the frame rate back first, then the quality.
+        let mut steps = Vec::new();
+        for _ in 0..60 {
+            if window(&mut adaptive, 0.1, 0) {
+                steps.push((adaptive.quality(75), adaptive.max_fps()));
+            }
+        }
+        assert_eq!(steps[0].0, MIN_QUALITY);
+        assert_eq!(steps[2], (MIN_QUALITY + QUALITY_STEP_UP, None));
+        assert_eq!(adaptive.quality(75), 75);
+        // A configured quality below the floor is left alone.
+        assert_eq!(AdaptiveQuality::new(start).quality(20), 20);
+    }
+}
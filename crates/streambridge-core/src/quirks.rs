@@ -0,0 +1,103 @@
+use crate::ndi::{RecvColorFormat, RecvSettings};
+
+/// Built-in workarounds, in `--quirk` syntax. Add entries here as field reports
+/// come in; user-supplied quirks are checked first and override these.
+const BUILTIN: &[&str] = &[];
+
+/// Workarounds for one kind of sender, matched by product string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quirk {
+    /// Case-insensitive substring of the sender's product string (or, before the
+    /// sender has announced itself, its source name).
+    pub product: String,
+    /// Ask for UYVY instead of letting the SDK pick the fastest format.
+    pub force_uyvy: bool,
+    /// Ask for woven frames instead of separate fields.
+    pub disable_fields: bool,
+    /// Capture timeout in milliseconds, for senders that stall the receive loop.
+    pub capture_timeout_ms: Option<u32>,
+}
+
+impl Quirk {
+    /// Parse `PRODUCT=workaround[,workaround...]`, where a workaround is
+    /// `force-uyvy`, `no-fields` or `timeout=<ms>`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (product, workarounds) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected PRODUCT=WORKAROUNDS, got \"{s}\""))?;
+        if product.trim().is_empty() {
+            return Err(format!("empty product in quirk \"{s}\""));
+        }
+        let mut quirk = Quirk {
+            product: product.trim().to_string(),
+            force_uyvy: false,
+            disable_fields: false,
+            capture_timeout_ms: None,
+        };
+        for workaround in workarounds.split(',').map(str::trim) {
+            match workaround {
+                "force-uyvy" => quirk.force_uyvy = true,
+                "no-fields" => quirk.disable_fields = true,
+                _ => match workaround.strip_prefix("timeout=") {
+                    Some(ms) => {
+                        let ms = ms.parse().map_err(|_| format!("invalid timeout in quirk \"{s}\""))?;
+                        quirk.capture_timeout_ms = Some(ms);
+                    }
+                    None => return Err(format!("unknown workaround \"{workaround}\" in quirk \"{s}\"")),
+                },
+            }
+        }
+        Ok(quirk)
+    }
+
+    /// Receiver settings with this quirk's workarounds applied.
+    pub fn apply(&self, mut settings: RecvSettings) -> RecvSettings {
+        if self.force_uyvy {
+            settings.color_format = RecvColorFormat::UyvyBgra;
+        }
+        if self.disable_fields {
+            settings.allow_video_fields = false;
+        }
+        settings
+    }
+}
+
+impl std::fmt::Display for Quirk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut workarounds = Vec::new();
+        if self.force_uyvy {
+            workarounds.push("force-uyvy".to_string());
+        }
+        if self.disable_fields {
+            workarounds.push("no-fields".to_string());
+        }
+        if let Some(ms) = self.capture_timeout_ms {
+            workarounds.push(format!("timeout={ms}"));
+        }
+        write!(f, "{}={}", self.product, workarounds.join(","))
+    }
+}
+
+/// The quirks table: user entries first, then the built-in ones.
+pub struct Quirks {
+    table: Vec<Quirk>,
+}
+
+impl Quirks {
+    pub fn new(user: Vec<Quirk>) -> Self {
+        let builtin = BUILTIN
+            .iter()
+            .map(|s| Quirk::parse(s).expect("invalid built-in quirk"));
+        Self {
+            table: user.into_iter().chain(builtin).collect(),
+        }
+    }
+
+    /// The first quirk whose product matches `product`.
+    pub fn lookup(&self, product: &str) -> Option<&Quirk> {
+        let product = product.to_lowercase();
+        self.table
+            .iter()
+            .find(|q| product.contains(&q.product.to_lowercase()))
+    }
+}
@@ -0,0 +1,252 @@
+//! Raw NDI® session files, written by `streambridge capture-raw` and served back
+//! as replay sources.
+//!
+//! A file is a gzip stream holding a magic header followed by records. Every record
+//! starts with a kind byte and the capture time in microseconds since the first
+//! frame; all integers are little-endian.
+
+use crate::ndi::FourCCVideoType;
+use crate::pipeline::VideoFrame;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SBRAW\0\0\x01";
+const KIND_VIDEO: u8 = 1;
+const KIND_AUDIO: u8 = 2;
+/// Limits on what a record's header may claim, so a corrupt file can't make
+/// the reader allocate gigabytes: an 8K BGRA frame with room to spare, and a
+/// second of 192 kHz audio in up to 64 channels.
+const MAX_VIDEO_BYTES: usize = 256 << 20;
+const MAX_CHANNELS: usize = 64;
+const MAX_SAMPLES: usize = 192_000;
+
+/// A video frame read back from a raw file.
+pub struct RawVideo {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub fourcc: u32,
+    pub data: Vec<u8>,
+}
+
+impl RawVideo {
+    pub fn as_frame(&self) -> VideoFrame<'_> {
+        VideoFrame {
+            data: &self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            fourcc: FourCCVideoType::from(self.fourcc),
+        }
+    }
+}
+
+/// Planar float audio read back from a raw file.
+pub struct RawAudio {
+    pub sample_rate: u32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+pub enum Record {
+    Video(RawVideo),
+    Audio(RawAudio),
+}
+
+/// A record together with its capture time in microseconds since the first record.
+pub struct TimedRecord {
+    pub time_us: u64,
+    pub record: Record,
+}
+
+pub struct RawWriter {
+    out: GzEncoder<BufWriter<File>>,
+}
+
+impl RawWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let mut out = GzEncoder::new(BufWriter::new(file), Compression::fast());
+        out.write_all(MAGIC)?;
+        Ok(Self { out })
+    }
+
+    pub fn write_video(&mut self, time_us: u64, frame: &VideoFrame) -> io::Result<()> {
+        // Whole frames only, with UYVA's alpha and 4:2:0 formats' chroma planes.
+        let len = frame.fourcc.frame_size(frame.width, frame.height, frame.stride);
+        let data = &frame.data[..len.min(frame.data.len())];
+        self.out.write_all(&[KIND_VIDEO])?;
+        self.out.write_all(&time_us.to_le_bytes())?;
+        self.out.write_all(&(frame.width as u32).to_le_bytes())?;
+        self.out.write_all(&(frame.height as u32).to_le_bytes())?;
+        self.out.write_all(&(frame.stride as u32).to_le_bytes())?;
+        self.out.write_all(&frame.fourcc.to_raw().to_le_bytes())?;
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(data)
+    }
+
+    pub fn write_audio(&mut self, time_us: u64, sample_rate: u32, channels: &[&[f32]]) -> io::Result<()> {
+        let samples = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+        self.out.write_all(&[KIND_AUDIO])?;
+        self.out.write_all(&time_us.to_le_bytes())?;
+        self.out.write_all(&sample_rate.to_le_bytes())?;
+        self.out.write_all(&(channels.len() as u32).to_le_bytes())?;
+        self.out.write_all(&(samples as u32).to_le_bytes())?;
+        for channel in channels {
+            for sample in &channel[..samples] {
+                self.out.write_all(&sample.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.out.finish()?.flush()
+    }
+}
+
+pub struct RawReader {
+    input: GzDecoder<BufReader<File>>,
+}
+
+impl RawReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut input = GzDecoder::new(BufReader::new(file));
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a streambridge raw capture"));
+        }
+        Ok(Self { input })
+    }
+
+    /// Read the next record. Returns `Ok(None)` at end of file.
+    pub fn next_record(&mut self) -> io::Result<Option<TimedRecord>> {
+        let mut kind = [0u8; 1];
+        match self.input.read_exact(&mut kind) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let time_us = self.read_u64()?;
+
+        let record = match kind[0] {
+            KIND_VIDEO => {
+                let width = self.read_u32()? as usize;
+                let height = self.read_u32()? as usize;
+                let stride = self.read_u32()? as usize;
+                let fourcc = self.read_u32()?;
+                let len = self.read_u32()? as usize;
+                let size = FourCCVideoType::from(fourcc).frame_size(width, height, stride);
+                if len > size || len > MAX_VIDEO_BYTES {
+                    return Err(invalid(format!("{len} byte {width}x{height} video record")));
+                }
+                let mut data = vec![0u8; len];
+                self.input.read_exact(&mut data)?;
+                Record::Video(RawVideo { width, height, stride, fourcc, data })
+            }
+            KIND_AUDIO => {
+                let sample_rate = self.read_u32()?;
+                let channel_count = self.read_u32()? as usize;
+                let samples = self.read_u32()? as usize;
+                if channel_count > MAX_CHANNELS || samples > MAX_SAMPLES {
+                    let claimed = format!("{channel_count} channels of {samples} samples");
+                    return Err(invalid(format!("audio record of {claimed}")));
+                }
+                let mut channels = Vec::with_capacity(channel_count);
+                let mut buf = vec![0u8; samples * 4];
+                for _ in 0..channel_count {
+                    self.input.read_exact(&mut buf)?;
+                    channels.push(
+                        buf.chunks_exact(4)
+                            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                            .collect(),
+                    );
+                }
+                Record::Audio(RawAudio { sample_rate, channels })
+            }
+            other => return Err(invalid(format!("unknown record kind {other}"))),
+        };
+        Ok(Some(TimedRecord { time_us, record }))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut b = [0u8; 4];
+        self.input.read_exact(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut b = [0u8; 8];
+        self.input.read_exact(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_read_back_as_written() {
+        let path = std::env::temp_dir().join(format!("sb-raw-test-{}.sbraw", std::process::id()));
+        let (w, h) = (8, 4);
+        // UYVY lines, then the alpha plane.
+        let uyva: Vec<u8> = (0..w * h * 3).map(|i| i as u8).collect();
+        let frame = VideoFrame {
+            data: &uyva,
+            width: w,
+            height: h,
+            stride: w * 2,
+            fourcc: FourCCVideoType::UYVA,
+        };
+        let (left, right) = ([0.5f32, -0.25, 1.0], [0.0f32, 0.125, -1.0]);
+        let mut writer = RawWriter::create(&path).unwrap();
+        writer.write_video(0, &frame).unwrap();
+        writer.write_audio(20_000, 48_000, &[&left, &right]).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = RawReader::open(&path).unwrap();
+        let video = reader.next_record().unwrap().unwrap();
+        let audio = reader.next_record().unwrap().unwrap();
+        assert!(reader.next_record().unwrap().is_none());
+        std::fs::remove_file(&path).unwrap();
+        let Record::Video(video) = video.record else { panic!("expected video") };
+        assert_eq!((video.width, video.height, video.stride), (w, h, w * 2));
+        assert_eq!(video.as_frame().fourcc, FourCCVideoType::UYVA);
+        assert_eq!(video.data, uyva, "alpha plane kept");
+        assert_eq!(audio.time_us, 20_000);
+        let Record::Audio(audio) = audio.record else { panic!("expected audio") };
+        assert_eq!(audio.sample_rate, 48_000);
+        assert_eq!(audio.channels, [left.to_vec(), right.to_vec()]);
+    }
+
+    #[test]
+    fn oversized_records_are_rejected() {
+        let path = std::env::temp_dir().join(format!("sb-raw-big-{}.sbraw", std::process::id()));
+        let mut out = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        out.write_all(MAGIC).unwrap();
+        out.write_all(&[KIND_VIDEO]).unwrap();
+        // A 4x4 UYVY frame claiming 4 GB of data.
+        for field in [0u64.to_le_bytes().as_slice(), &4u32.to_le_bytes(), &4u32.to_le_bytes()] {
+            out.write_all(field).unwrap();
+        }
+        out.write_all(&8u32.to_le_bytes()).unwrap();
+        out.write_all(&FourCCVideoType::UYVY.to_raw().to_le_bytes()).unwrap();
+        out.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        out.finish().unwrap();
+
+        let mut reader = RawReader::open(&path).unwrap();
+        let err = reader.next_record().err().expect("rejected");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,1783 @@
+use bytes::Bytes;
+use crate::audio::AudioChunk;
+use crate::clock::{ClockConfig, ClockRenderer, NtpStatus};
+use crate::composite::{Compositor, CropConfig, Cropper, Layout, Mosaic, MosaicConfig};
+use crate::config::{EncodeSettings, SourceSettings, Tuning};
+use crate::deinterlace::Deinterlacer;
+use crate::events::EventBus;
+use crate::latest;
+use crate::levels::Levels;
+use crate::media::{self, JpegDecoder, MediaConfig, MediaReader};
+use crate::overlay::{Overlay, OverlayConfig};
+use crate::pipeline::{Pipeline, Variant, Variants, VideoFrame};
+use crate::preroll::Preroll;
+use crate::quirks::{Quirk, Quirks};
+use crate::rawfile::{RawReader, Record};
+use crate::recording::Recorder;
+use crate::runtime::NdiRuntime;
+use crate::stats::SourceStats;
+use crate::threads::ThreadPolicy;
+use crate::timestamps::TimeFormat;
+use crate::ndi::metadata::{element_attributes, tally_echo, ProductInfo};
+use crate::ndi::{
+    ffi, Capabilities, FourCCVideoType, FrameFormat, FrameType, MetadataFrame, NdiInstance,
+    ReceiveInstance, RecvBandwidth, RecvColorFormat, RecvSettings, Source, Tally,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, oneshot, watch};
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+
+/// An encoded frame ready to send: a JPEG, or the format its variant asked for.
+#[derive(Clone)]
+pub struct JpegFrame {
+    pub data: Bytes,
+    pub info: FrameInfo,
+}
+
+/// The source frame an image was encoded from; the same in every format and
+/// variant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Counted from when the source's receiver started.
+    pub seq: u64,
+    /// The NDI® sender's timecode, in 100 ns units.
+    pub timecode: Option<i64>,
+    /// When the NDI® sender sent it, in 100 ns units since the Unix epoch.
+    pub timestamp: Option<i64>,
+    /// When the bridge received it, in milliseconds since the Unix epoch.
+    pub received_ms: u64,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A change to a running receiver, applied by its capture thread.
+pub enum Control {
+    SetQuality(i32),
+    /// 0 for uncapped.
+    SetMaxFps(u32),
+    /// Reconnect to an NDI® sender for its proxy stream, or back to full quality.
+    SetLowBandwidth(bool),
+    /// Drop the connection to an NDI® sender and make a new one; subscribers stay.
+    Reconnect,
+    StartRecording(Box<Recorder>),
+    /// Replies with every file the recording wrote.
+    StopRecording(oneshot::Sender<Result<Vec<PathBuf>, String>>),
+    Ptz(PtzRequest),
+    /// Move a comparison's split, as a fraction of the frame width.
+    SetSplit(f32),
+    /// Signal on-air state to the sender.
+    SetTally(Tally),
+}
+
+impl Control {
+    /// Turn down a control the producer can't apply, answering it if it waits for a reply.
+    fn refuse(self, reason: &str) {
+        if let Control::Ptz(request) = self {
+            let _ = request.reply.send(Err(reason.to_string()));
+        }
+    }
+}
+
+/// A command for the source's PTZ camera. Ranges are those of the NDI® SDK;
+/// out-of-range values are clamped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PtzCommand {
+    /// Absolute position, -1.0 to 1.0 on each axis.
+    PanTilt { pan: f32, tilt: f32 },
+    /// Continuous movement, -1.0 to 1.0 on each axis; 0.0 stops.
+    PanTiltSpeed { pan: f32, tilt: f32 },
+    /// Absolute zoom, 0.0 (in) to 1.0 (out).
+    Zoom(f32),
+    /// Continuous zoom, -1.0 (out) to 1.0 (in); 0.0 stops.
+    ZoomSpeed(f32),
+    /// Move to preset 0-99, at 0.0 (slowest) to 1.0 (fastest).
+    RecallPreset { preset: u32, speed: f32 },
+    StorePreset(u32),
+}
+
+impl PtzCommand {
+    fn apply(self, recv: &ReceiveInstance) -> bool {
+        match self {
+            PtzCommand::PanTilt { pan, tilt } => recv.ptz_pan_tilt(pan, tilt),
+            PtzCommand::PanTiltSpeed { pan, tilt } => recv.ptz_pan_tilt_speed(pan, tilt),
+            PtzCommand::Zoom(zoom) => recv.ptz_zoom(zoom),
+            PtzCommand::ZoomSpeed(speed) => recv.ptz_zoom_speed(speed),
+            PtzCommand::RecallPreset { preset, speed } => recv.ptz_recall_preset(preset, speed),
+            PtzCommand::StorePreset(preset) => recv.ptz_store_preset(preset),
+        }
+    }
+}
+
+/// A [`PtzCommand`] and where to report whether the camera took it.
+pub struct PtzRequest {
+    pub command: PtzCommand,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+/// How long a PTZ request waits for the sender to report PTZ support, e.g. while
+/// a receiver started for the request is still connecting.
+const PTZ_WAIT: Duration = Duration::from_secs(3);
+
+/// Which of an NDI® sender's streams a client asks for with `?bandwidth=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Bandwidth {
+    /// The full program stream.
+    Highest,
+    /// The sender's low-bandwidth preview stream, far cheaper for thumbnail
+    /// walls and remote links.
+    Lowest,
+}
+
+/// The pixel format NDI® receivers ask senders for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFormat {
+    /// Whatever the SDK delivers fastest, UYVY on most machines.
+    #[default]
+    Fastest,
+    /// The SDK's best quality, which can be 16-bit formats the bridge doesn't
+    /// encode.
+    Best,
+    /// BGRA, or BGRX for opaque sources, which is encoded without converting
+    /// the chroma first.
+    Bgra,
+    /// UYVY, or BGRA for sources with alpha.
+    Uyvy,
+}
+
+impl ColorFormat {
+    /// Parse a `--color-format` value.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "fastest" => Ok(Self::Fastest),
+            "best" => Ok(Self::Best),
+            "bgra" => Ok(Self::Bgra),
+            "uyvy" => Ok(Self::Uyvy),
+            other => Err(format!("unknown color format \"{other}\" (fastest, best, bgra or uyvy)")),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fastest => "fastest",
+            Self::Best => "best",
+            Self::Bgra => "bgra",
+            Self::Uyvy => "uyvy",
+        }
+    }
+
+    fn recv(self) -> RecvColorFormat {
+        match self {
+            Self::Fastest => RecvColorFormat::Fastest,
+            Self::Best => RecvColorFormat::Best,
+            Self::Bgra => RecvColorFormat::BgrxBgra,
+            Self::Uyvy => RecvColorFormat::UyvyBgra,
+        }
+    }
+}
+
+/// Whether a receiver is getting frames from its sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Up,
+    /// The connection failed; retrying for the `attempt`th time.
+    Reconnecting { attempt: u32 },
+}
+
+/// A shared receiver for a single NDI source. Broadcasts JPEG frames to subscribers.
+pub struct SharedReceiver {
+    pub source_name: String,
+    /// What the manager keeps it under: the source name, or e.g. `CAM (1)
+    /// [lowest]` for a receiver at another bandwidth than the source's own.
+    pub key: String,
+    pub stats: Arc<SourceStats>,
+    pub started: Instant,
+    /// Weak so the channel closes (and clients see "source lost") as soon as the
+    /// capture thread exits, even while subscribers still hold this receiver.
+    tx: latest::WeakSender<JpegFrame>,
+    audio_tx: broadcast::WeakSender<AudioChunk>,
+    levels_tx: broadcast::WeakSender<Levels>,
+    metadata_tx: broadcast::WeakSender<Arc<str>>,
+    diff_tx: latest::WeakSender<JpegFrame>,
+    /// Weak like `tx`: the pipeline owns the variant streams.
+    variants: Weak<Variants>,
+    /// Signals the capture thread to stop.
+    stop: Arc<AtomicBool>,
+    control: mpsc::Sender<Control>,
+    /// Set from the start of a recording until its file is finished.
+    recording: Arc<AtomicBool>,
+    /// Set while the source's tally is on program or preview.
+    on_air: Arc<AtomicBool>,
+    link: watch::Receiver<Link>,
+}
+
+impl SharedReceiver {
+    pub fn subscribe(&self) -> latest::Receiver<JpegFrame> {
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.tx.upgrade() {
+            Some(tx) => tx.subscribe(),
+            // Capture thread already gone: hand out a receiver that reports Closed.
+            None => latest::Receiver::closed(),
+        }
+    }
+
+    /// Like [`SharedReceiver::subscribe`], for the source's audio.
+    pub fn subscribe_audio(&self) -> broadcast::Receiver<AudioChunk> {
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.audio_tx.upgrade() {
+            Some(tx) => tx.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// Like [`SharedReceiver::subscribe`], for the levels of the source's audio.
+    pub fn subscribe_levels(&self) -> broadcast::Receiver<Levels> {
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.levels_tx.upgrade() {
+            Some(tx) => tx.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// Like [`SharedReceiver::subscribe`], for the XML of the sender's metadata frames.
+    pub fn subscribe_metadata(&self) -> broadcast::Receiver<Arc<str>> {
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.metadata_tx.upgrade() {
+            Some(tx) => tx.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// Changes of the connection to the sender. Only NDI® receivers ever
+    /// reconnect; for other sources the link stays up.
+    pub fn watch_link(&self) -> watch::Receiver<Link> {
+        self.link.clone()
+    }
+
+    /// Like [`SharedReceiver::subscribe`], for frame-difference images.
+    pub fn subscribe_diff(&self) -> latest::Receiver<JpegFrame> {
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.diff_tx.upgrade() {
+            Some(tx) => tx.subscribe(),
+            None => latest::Receiver::closed(),
+        }
+    }
+
+    /// Like [`SharedReceiver::subscribe`], encoded within a client's own limits.
+    pub fn subscribe_variant(&self, variant: Variant) -> latest::Receiver<JpegFrame> {
+        if variant == Variant::default() {
+            return self.subscribe();
+        }
+        self.stats.clients.fetch_add(1, Ordering::Relaxed);
+        match self.variants.upgrade() {
+            Some(variants) => variants.lock().unwrap().entry(variant).or_default().subscribe(),
+            None => latest::Receiver::closed(),
+        }
+    }
+
+    /// How many subscribers each of the receiver's outputs has right now.
+    pub fn outputs(&self) -> Outputs {
+        let variants = self.variants.upgrade().map(|variants| {
+            let variants = variants.lock().unwrap();
+            variants.iter().map(|(variant, output)| (*variant, output.receiver_count())).collect()
+        });
+        Outputs {
+            jpeg: self.tx.upgrade().map_or(0, |tx| tx.receiver_count()),
+            variants: variants.unwrap_or_default(),
+            diff: self.diff_tx.upgrade().map_or(0, |tx| tx.receiver_count()),
+            audio: self.audio_tx.upgrade().map_or(0, |tx| tx.receiver_count()),
+            metadata: self.metadata_tx.upgrade().map_or(0, |tx| tx.receiver_count()),
+            recording: self.is_recording(),
+        }
+    }
+
+    pub fn unsubscribe(&self) {
+        self.stats.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn client_count(&self) -> u64 {
+        self.stats.clients.load(Ordering::Relaxed)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    pub fn is_on_air(&self) -> bool {
+        self.on_air.load(Ordering::Relaxed)
+    }
+
+    /// Pass `control` to the capture thread. `false` if the thread has exited.
+    pub fn send(&self, control: Control) -> bool {
+        self.control.send(control).is_ok()
+    }
+
+    /// Record every frame with `recorder` until [`SharedReceiver::stop_recording`].
+    /// The receiver keeps running while recording, even without viewers.
+    pub fn start_recording(&self, recorder: Recorder) -> Result<(), String> {
+        if self.recording.swap(true, Ordering::Relaxed) {
+            return Err("already recording".to_string());
+        }
+        if !self.send(Control::StartRecording(Box::new(recorder))) {
+            self.recording.store(false, Ordering::Relaxed);
+            return Err("source lost".to_string());
+        }
+        Ok(())
+    }
+
+    /// Finish the recording's file, returning all it wrote.
+    pub async fn stop_recording(&self) -> Result<Vec<PathBuf>, String> {
+        if !self.is_recording() {
+            return Err("not recording".to_string());
+        }
+        let (reply, done) = oneshot::channel();
+        if !self.send(Control::StopRecording(reply)) {
+            return Err("source lost".to_string());
+        }
+        done.await.unwrap_or_else(|_| Err("source lost".to_string()))
+    }
+}
+
+/// Subscriber counts of a receiver's outputs. Variants whose last subscriber
+/// left may linger with 0 until the next frame.
+#[derive(Debug, Clone, Default)]
+pub struct Outputs {
+    pub jpeg: usize,
+    pub variants: Vec<(Variant, usize)>,
+    pub diff: usize,
+    pub audio: usize,
+    pub metadata: usize,
+    pub recording: bool,
+}
+
+impl Drop for SharedReceiver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        debug!("SharedReceiver dropped for {}", self.source_name);
+    }
+}
+
+/// A source the bridge produces itself instead of receiving it over NDI.
+#[derive(Debug, Clone)]
+pub enum VirtualSource {
+    /// A raw capture file played in a loop.
+    Replay(PathBuf),
+    /// A video file recorded elsewhere, played in a loop.
+    Media(MediaConfig),
+    /// A picture-in-picture or comparison of two other sources.
+    Composite(Layout),
+    /// A region of another source.
+    Crop(CropConfig),
+    /// A generated wall clock.
+    Clock(ClockConfig),
+}
+
+/// Where a shared receiver gets its frames from.
+enum Producer {
+    Ndi(NdiSession),
+    Replay(PathBuf),
+    Media(MediaConfig),
+    Composite(Layout),
+    Crop(CropConfig),
+    Mosaic(MosaicConfig),
+    Clock(ClockConfig),
+}
+
+/// Default time `capture` waits for a frame before the loop checks for shutdown.
+const CAPTURE_TIMEOUT_MS: u32 = 1000;
+
+/// The wait before the first attempt to reconnect to a sender, doubled for each
+/// further attempt up to [`RECONNECT_MAX_DELAY`].
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+/// How long a sender may stay away before its clients are told it is lost.
+const RECONNECT_GIVE_UP: Duration = Duration::from_secs(60);
+
+/// How often a receiver checks whether a quarantined NDI® runtime is back.
+const RUNTIME_POLL: Duration = Duration::from_millis(500);
+
+/// A connected NDI receiver plus what is needed to reopen it when a quirk
+/// changes its settings.
+struct NdiSession {
+    runtime: Arc<NdiRuntime>,
+    /// Kept until the receiver is gone, so the runtime isn't destroyed under it.
+    ndi: Arc<NdiInstance>,
+    source: Source,
+    recv: ReceiveInstance,
+    /// Receiver settings before the quirk's workarounds are applied.
+    base: RecvSettings,
+    quirk: Option<Quirk>,
+    /// Tally last signalled to the sender, restored on reconnects.
+    tally: Tally,
+}
+
+impl NdiSession {
+    fn open(
+        runtime: Arc<NdiRuntime>,
+        source: Source,
+        base: RecvSettings,
+        quirk: Option<Quirk>,
+        tally: Tally,
+    ) -> Result<Self, String> {
+        let settings = match &quirk {
+            Some(q) => q.apply(base.clone()),
+            None => base.clone(),
+        };
+        let ndi = runtime.instance()?;
+        let recv = match ndi.create_receive_instance(&settings) {
+            Ok(recv) => recv,
+            Err(e) => {
+                let error = format!("failed to create receiver: {e}");
+                runtime.failed(&error);
+                return Err(error);
+            }
+        };
+        runtime.succeeded();
+        if let Ok(announcement) = MetadataFrame::new(&product_info().to_xml()) {
+            recv.add_connection_metadata(&announcement);
+        }
+        recv.connect(&source);
+        recv.set_tally(tally);
+        Ok(Self { runtime, ndi, source, recv, base, quirk, tally })
+    }
+
+    /// A new connection to the same sender with other settings.
+    fn reopen(&self, base: RecvSettings, quirk: Option<Quirk>) -> Result<Self, String> {
+        Self::open(self.runtime.clone(), self.source.clone(), base, quirk, self.tally)
+    }
+
+    fn capture_timeout_ms(&self) -> u32 {
+        self.quirk
+            .as_ref()
+            .and_then(|q| q.capture_timeout_ms)
+            .unwrap_or(CAPTURE_TIMEOUT_MS)
+    }
+}
+
+/// Manages shared NDI receivers. Creates on first subscriber, destroys on last unsubscribe.
+pub struct ReceiverManager {
+    receivers: Mutex<HashMap<String, Arc<SharedReceiver>>>,
+    /// `None` when the NDI runtime is unavailable and only replay sources are served.
+    ndi: Option<Arc<NdiRuntime>>,
+    settings: SourceSettings,
+    /// Target integrated loudness in LUFS; `None` disables audio capture and metering.
+    loudness_target: Option<f64>,
+    events: Arc<EventBus>,
+    /// Replay, media, composite and clock sources, by name.
+    virtual_sources: HashMap<String, VirtualSource>,
+    /// Mosaics asked for by clients, by name, while their receivers run.
+    mosaics: Mutex<HashMap<String, MosaicConfig>>,
+    quirks: Arc<Quirks>,
+    /// Settings changed at runtime, by source; win over `settings`.
+    tuning: Mutex<HashMap<String, Tuning>>,
+    /// Tally signalled by production software, by source.
+    tally: Mutex<HashMap<String, Tally>>,
+    /// The last seconds of each source with a pre-roll, by source.
+    prerolls: Mutex<HashMap<String, Arc<Preroll>>>,
+    /// Capture threads that may still be running.
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    /// Set by [`ReceiverManager::drain`] and [`ReceiverManager::shutdown`]; no
+    /// new clients are taken.
+    draining: watch::Sender<bool>,
+    /// Set by [`ReceiverManager::shutdown`].
+    shutting_down: AtomicBool,
+    /// How long receivers outlive their last client.
+    linger: Duration,
+    /// Priority and CPUs for the capture threads, which also encode.
+    thread_policy: ThreadPolicy,
+    /// What NDI® senders see the receivers as; `None` for anonymous ones.
+    receiver_name: Option<String>,
+}
+
+impl ReceiverManager {
+    pub fn new(
+        ndi: Option<Arc<NdiRuntime>>,
+        settings: SourceSettings,
+        loudness_target: Option<f64>,
+        events: Arc<EventBus>,
+        virtual_sources: HashMap<String, VirtualSource>,
+        quirks: Quirks,
+    ) -> Self {
+        Self {
+            receivers: Mutex::new(HashMap::new()),
+            ndi,
+            settings,
+            loudness_target,
+            events,
+            virtual_sources,
+            mosaics: Mutex::new(HashMap::new()),
+            quirks: Arc::new(quirks),
+            tuning: Mutex::new(HashMap::new()),
+            tally: Mutex::new(HashMap::new()),
+            prerolls: Mutex::new(HashMap::new()),
+            threads: Mutex::new(Vec::new()),
+            draining: watch::Sender::new(false),
+            shutting_down: AtomicBool::new(false),
+            linger: Duration::ZERO,
+            thread_policy: ThreadPolicy::default(),
+            receiver_name: None,
+        }
+    }
+
+    /// Keep receivers running for `linger` after their last client leaves.
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// Run capture threads with `policy`.
+    pub fn thread_policy(mut self, policy: ThreadPolicy) -> Self {
+        self.thread_policy = policy;
+        self
+    }
+
+    /// Name the NDI® receivers `name`, e.g. `streambridge@studio-pc`, for
+    /// senders and monitoring tools to show.
+    pub fn receiver_name(mut self, name: String) -> Self {
+        self.receiver_name = Some(name);
+        self
+    }
+
+    /// Get or create a shared receiver for the given source.
+    /// Returns the SharedReceiver or an error if the source can't be connected.
+    pub fn get_or_create(
+        self: &Arc<Self>,
+        source: &Source,
+    ) -> Result<Arc<SharedReceiver>, String> {
+        self.get_or_create_at(source, None)
+    }
+
+    /// Like [`ReceiverManager::get_or_create`], but receiving `bandwidth` from an
+    /// NDI® sender. Asking for another bandwidth than the source is configured
+    /// for opens a second receiver, shared by the clients that asked for it.
+    /// Other sources have a single stream and ignore `bandwidth`.
+    pub fn get_or_create_at(
+        self: &Arc<Self>,
+        source: &Source,
+        bandwidth: Option<Bandwidth>,
+    ) -> Result<Arc<SharedReceiver>, String> {
+        if self.is_draining() {
+            return Err("server is draining".to_string());
+        }
+        let mut encode = self.encode_settings(&source.name);
+        let mut key = source.name.clone();
+        if let Some(bandwidth) = bandwidth.filter(|_| !self.is_virtual(&source.name)) {
+            let low = bandwidth == Bandwidth::Lowest;
+            if low != encode.low_bandwidth {
+                encode.low_bandwidth = low;
+                key = format!("{} [{}]", source.name, if low { "lowest" } else { "highest" });
+            }
+        }
+        let mut receivers = self.receivers.lock().unwrap();
+
+        if let Some(existing) = receivers.get(&key) {
+            return Ok(existing.clone());
+        }
+
+        let tally = self.tally(&source.name);
+        let producer = match self.virtual_sources.get(&source.name) {
+            Some(VirtualSource::Replay(path)) => Producer::Replay(path.clone()),
+            Some(VirtualSource::Media(config)) => Producer::Media(config.clone()),
+            Some(VirtualSource::Composite(layout)) => Producer::Composite(layout.clone()),
+            Some(VirtualSource::Crop(config)) => Producer::Crop(config.clone()),
+            Some(VirtualSource::Clock(config)) => Producer::Clock(config.clone()),
+            None => {
+                let runtime = self.ndi.clone().ok_or("NDI runtime not available")?;
+                // Until the sender announces its product, its name is the best guess.
+                let quirk = self.quirks.lookup(&source.name).cloned();
+                if let Some(q) = &quirk {
+                    info!("[{}] applying quirk {}", source.name, q);
+                }
+                let mut base = RecvSettings {
+                    color_format: encode.color_format.recv(),
+                    name: self.receiver_name.clone(),
+                    ..Default::default()
+                };
+                if encode.low_bandwidth {
+                    base.bandwidth = RecvBandwidth::Lowest;
+                }
+                Producer::Ndi(NdiSession::open(runtime, source.clone(), base, quirk, tally)?)
+            }
+        };
+        self.start(&mut receivers, key, &source.name, encode, tally, producer)
+    }
+
+    /// Get or create the receiver that tiles `mosaic`'s sources into one frame.
+    pub fn get_or_create_mosaic(
+        self: &Arc<Self>,
+        mosaic: &MosaicConfig,
+    ) -> Result<Arc<SharedReceiver>, String> {
+        if self.is_draining() {
+            return Err("server is draining".to_string());
+        }
+        let name = mosaic.name();
+        let mut receivers = self.receivers.lock().unwrap();
+        if let Some(existing) = receivers.get(&name) {
+            return Ok(existing.clone());
+        }
+        self.mosaics.lock().unwrap().insert(name.clone(), mosaic.clone());
+        let encode = self.encode_settings(&name);
+        let producer = Producer::Mosaic(mosaic.clone());
+        self.start(&mut receivers, name.clone(), &name, encode, Tally::default(), producer)
+    }
+
+    /// Start a capture thread feeding `producer`'s frames to a new receiver,
+    /// listed in `receivers` under `key`.
+    fn start(
+        self: &Arc<Self>,
+        receivers: &mut HashMap<String, Arc<SharedReceiver>>,
+        key: String,
+        source_name: &str,
+        encode: EncodeSettings,
+        tally: Tally,
+        producer: Producer,
+    ) -> Result<Arc<SharedReceiver>, String> {
+        let tx = latest::Sender::<JpegFrame>::new();
+        let (audio_tx, _) = broadcast::channel::<AudioChunk>(16);
+        let (levels_tx, _) = broadcast::channel::<Levels>(4);
+        let (metadata_tx, _) = broadcast::channel::<Arc<str>>(32);
+        let diff_tx = latest::Sender::<JpegFrame>::new();
+        let variants = Arc::new(Variants::default());
+        let (control, controls) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let recording = Arc::new(AtomicBool::new(false));
+        let on_air = Arc::new(AtomicBool::new(tally.on_program || tally.on_preview));
+        let (link_tx, link) = watch::channel(Link::Up);
+        let stats = SourceStats::new();
+
+        let shared = Arc::new(SharedReceiver {
+            source_name: source_name.to_string(),
+            key: key.clone(),
+            stats: stats.clone(),
+            started: Instant::now(),
+            tx: tx.downgrade(),
+            audio_tx: audio_tx.downgrade(),
+            levels_tx: levels_tx.downgrade(),
+            metadata_tx: metadata_tx.downgrade(),
+            diff_tx: diff_tx.downgrade(),
+            variants: Arc::downgrade(&variants),
+            stop: stop.clone(),
+            control,
+            recording: recording.clone(),
+            on_air: on_air.clone(),
+            link,
+        });
+
+        let source_name = source_name.to_string();
+        let overlay = self.overlay(&source_name).map(|config| {
+            Overlay::new(config, &source_name, self.time_format(&source_name))
+        });
+        let mut pipeline = Pipeline::new(
+            source_name.clone(),
+            stats,
+            tx,
+            self.events.clone(),
+            encode.jpeg_quality,
+            encode.max_fps,
+            self.loudness_target,
+        )
+        .max_width(encode.max_width)
+        .max_height(encode.max_height)
+        .encoder(encode.encoder)
+        .encode_threads(encode.encode_threads)
+        .adaptive_quality(encode.adaptive_quality)
+        .overlay(overlay)
+        .viewer_badge(encode.viewer_badge)
+        .motion(encode.motion)
+        .recording_flag(recording)
+        .on_air_flag(on_air)
+        .audio_output(audio_tx)
+        .levels_output(levels_tx)
+        .metadata_output(metadata_tx)
+        .diff_output(diff_tx)
+        .variant_outputs(variants)
+        .link_output(link_tx)
+        .linger(self.linger);
+        // Receivers at another bandwidth would interleave their frames with the main one's.
+        if let Some(window) = encode.preroll.filter(|_| key == source_name) {
+            let mut prerolls = self.prerolls.lock().unwrap();
+            let preroll = prerolls
+                .entry(source_name.clone())
+                .and_modify(|p| {
+                    if p.window() != window {
+                        *p = Arc::new(Preroll::new(window));
+                    }
+                })
+                .or_insert_with(|| Arc::new(Preroll::new(window)));
+            pipeline = pipeline.preroll(Arc::clone(preroll));
+        }
+        let manager = Arc::clone(self);
+        let quirks = Arc::clone(&self.quirks);
+        let source_name_thread = source_name.clone();
+        let key_thread = key.clone();
+        let thread_policy = self.thread_policy.clone();
+        let deinterlacer = Deinterlacer::new(encode.deinterlace);
+
+        let thread = std::thread::Builder::new()
+            .name(format!("ndi-recv-{}", &key))
+            .spawn(move || {
+                info!("capture thread started for \"{}\"", source_name_thread);
+                if let Err(e) = thread_policy.apply() {
+                    warn!("[{}] capture thread: {}", source_name_thread, e);
+                }
+                match producer {
+                    Producer::Ndi(session) => capture_ndi(
+                        session,
+                        deinterlacer,
+                        &quirks,
+                        &mut pipeline,
+                        &controls,
+                        &stop,
+                        &source_name_thread,
+                    ),
+                    Producer::Replay(path) => {
+                        capture_replay(&path, &mut pipeline, &controls, &stop, &source_name_thread)
+                    }
+                    Producer::Media(config) => {
+                        capture_media(&config, &mut pipeline, &controls, &stop, &source_name_thread)
+                    }
+                    Producer::Composite(layout) => capture_composite(
+                        layout,
+                        &manager,
+                        &mut pipeline,
+                        &controls,
+                        &stop,
+                        &source_name_thread,
+                    ),
+                    Producer::Crop(config) => capture_crop(
+                        config,
+                        &manager,
+                        &mut pipeline,
+                        &controls,
+                        &stop,
+                        &source_name_thread,
+                    ),
+                    Producer::Mosaic(config) => capture_mosaic(
+                        config,
+                        &manager,
+                        &mut pipeline,
+                        &controls,
+                        &stop,
+                        &source_name_thread,
+                    ),
+                    Producer::Clock(config) => {
+                        let times = manager.time_format(&source_name_thread);
+                        capture_clock(&config, times, &mut pipeline, &controls, &stop)
+                    }
+                }
+                info!("capture thread stopped for \"{}\"", source_name_thread);
+                // Clean up from manager, unless it was released and replaced.
+                let mut receivers = manager.receivers.lock().unwrap();
+                if receivers.get(&key_thread).is_some_and(|r| Arc::ptr_eq(&r.stop, &stop)) {
+                    receivers.remove(&key_thread);
+                    manager.mosaics.lock().unwrap().remove(&key_thread);
+                }
+            })
+            .map_err(|e| format!("failed to spawn capture thread: {e}"))?;
+        let mut threads = self.threads.lock().unwrap();
+        threads.retain(|t| !t.is_finished());
+        threads.push(thread);
+
+        receivers.insert(key, shared.clone());
+        Ok(shared)
+    }
+
+    /// The encode settings a receiver for `source` uses.
+    pub fn encode_settings(&self, source: &str) -> EncodeSettings {
+        let settings = self.settings.for_source(source);
+        match self.tuning.lock().unwrap().get(source) {
+            Some(tuning) => tuning.apply(settings),
+            None => settings,
+        }
+    }
+
+    /// How times are written for `source`, e.g. in its recordings' file names.
+    pub fn time_format(&self, source: &str) -> TimeFormat {
+        self.settings.time_format_for(source)
+    }
+
+    /// What a receiver for `source` burns into its frames, if anything.
+    pub fn overlay(&self, source: &str) -> Option<OverlayConfig> {
+        self.settings.overlay_for(source)
+    }
+
+    /// Change `source`'s JPEG quality, on its running receiver and for any later one.
+    pub fn set_quality(&self, source: &str, quality: i32) {
+        self.tune(source, Tuning { jpeg_quality: Some(quality), ..Default::default() });
+    }
+
+    /// Change the settings `tuning` gives for `source`, on its running receiver
+    /// (without dropping its subscribers) and for any later one. Returns the
+    /// settings now in effect.
+    pub fn tune(&self, source: &str, tuning: Tuning) -> EncodeSettings {
+        let before = self.encode_settings(source);
+        {
+            let mut all = self.tuning.lock().unwrap();
+            let merged = all.get(source).copied().unwrap_or_default().merge(tuning);
+            all.insert(source.to_string(), merged);
+        }
+        let after = self.encode_settings(source);
+        if let Some(active) = self.get(source) {
+            if tuning.jpeg_quality.is_some() {
+                active.send(Control::SetQuality(after.jpeg_quality));
+            }
+            if tuning.max_fps.is_some() {
+                active.send(Control::SetMaxFps(after.max_fps));
+            }
+            if after.low_bandwidth != before.low_bandwidth {
+                active.send(Control::SetLowBandwidth(after.low_bandwidth));
+            }
+        }
+        after
+    }
+
+    /// Which optional parts of the SDK the NDI® runtime has, if there is one.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.ndi.as_ref().map(|runtime| runtime.capabilities())
+    }
+
+    /// Whether `source` is a replay, media, composite, crop, mosaic or clock rather
+    /// than an NDI source.
+    pub fn is_virtual(&self, source: &str) -> bool {
+        self.virtual_sources.contains_key(source)
+            || self.mosaics.lock().unwrap().contains_key(source)
+    }
+
+    /// What produces `source`'s frames: `ndi`, `replay`, `media`, `pip`, `compare`,
+    /// `crop`, `mosaic` or `clock`.
+    pub fn producer(&self, source: &str) -> &'static str {
+        match self.virtual_sources.get(source) {
+            None if self.mosaics.lock().unwrap().contains_key(source) => "mosaic",
+            None => "ndi",
+            Some(VirtualSource::Replay(_)) => "replay",
+            Some(VirtualSource::Media(_)) => "media",
+            Some(VirtualSource::Composite(Layout::Pip(_))) => "pip",
+            Some(VirtualSource::Composite(Layout::Compare(_))) => "compare",
+            Some(VirtualSource::Crop(_)) => "crop",
+            Some(VirtualSource::Clock(_)) => "clock",
+        }
+    }
+
+    /// The tally last set for `source`; off unless production software set it.
+    pub fn tally(&self, source: &str) -> Tally {
+        self.tally.lock().unwrap().get(source).copied().unwrap_or_default()
+    }
+
+    /// Signal `tally` to `source`'s sender, now and on every later connection.
+    /// While the tally is on, the bridge stays connected so the lamp stays lit.
+    pub fn set_tally(self: &Arc<Self>, source: &Source, tally: Tally) -> Result<(), String> {
+        if self.is_virtual(&source.name) {
+            return Err("not an NDI source".to_string());
+        }
+        self.tally.lock().unwrap().insert(source.name.clone(), tally);
+        let on_air = tally.on_program || tally.on_preview;
+        let active = if on_air {
+            Some(self.get_or_create(source)?)
+        } else {
+            self.get(&source.name)
+        };
+        if let Some(active) = active {
+            active.on_air.store(on_air, Ordering::Relaxed);
+            active.send(Control::SetTally(tally));
+            self.maybe_remove(&source.name);
+        }
+        Ok(())
+    }
+
+    /// The running receiver for `source_name`, if there is one.
+    pub fn get(&self, source_name: &str) -> Option<Arc<SharedReceiver>> {
+        self.receivers.lock().unwrap().get(source_name).cloned()
+    }
+
+    /// All active receivers, by key.
+    pub fn active(&self) -> Vec<(String, Arc<SharedReceiver>)> {
+        let receivers = self.receivers.lock().unwrap();
+        receivers.iter().map(|(name, r)| (name.clone(), r.clone())).collect()
+    }
+
+    /// Returns (key, stats) for all active receivers.
+    pub fn active_stats(&self) -> Vec<(String, Arc<crate::stats::SourceStats>)> {
+        let receivers = self.receivers.lock().unwrap();
+        receivers
+            .iter()
+            .map(|(name, r)| (name.clone(), r.stats.clone()))
+            .collect()
+    }
+
+    /// Like [`Self::active_stats`], but `None` rather than waiting while another
+    /// thread holds the list, e.g. one that panicked with it.
+    pub fn try_active_stats(&self) -> Option<Vec<(String, Arc<SourceStats>)>> {
+        let receivers = self.receivers.try_lock().ok()?;
+        Some(receivers.iter().map(|(name, r)| (name.clone(), r.stats.clone())).collect())
+    }
+
+    /// Stats of the active receiver for `source_name`, if there is one.
+    pub fn stats(&self, source_name: &str) -> Option<Arc<SourceStats>> {
+        let receivers = self.receivers.lock().unwrap();
+        receivers.get(source_name).map(|r| r.stats.clone())
+    }
+
+    /// `source`'s pre-roll, once it has been received with one.
+    pub fn preroll(&self, source: &str) -> Option<Arc<Preroll>> {
+        self.prerolls.lock().unwrap().get(source).cloned()
+    }
+
+    /// Remove a receiver, by its [key](SharedReceiver::key), if it has no more
+    /// clients, isn't recording and isn't on air. Receivers that linger remove
+    /// themselves once the linger time is up.
+    pub fn maybe_remove(&self, key: &str) {
+        if !self.linger.is_zero() {
+            return;
+        }
+        let mut receivers = self.receivers.lock().unwrap();
+        if let Some(recv) = receivers.get(key) {
+            if recv.client_count() == 0 && !recv.is_recording() && !recv.is_on_air() {
+                receivers.remove(key);
+                // The SharedReceiver drop will signal the thread to stop
+            }
+        }
+    }
+
+    /// Have every receiver of `source`, whatever its bandwidth, reconnect to
+    /// its NDI® sender. Returns how many there were.
+    pub fn restart(&self, source: &str) -> usize {
+        let receivers = self.receivers.lock().unwrap();
+        let of_source = receivers.values().filter(|r| r.source_name == source);
+        of_source.filter(|r| r.send(Control::Reconnect)).count()
+    }
+
+    /// Stop every receiver of `source` now, whoever holds it. Its clients see
+    /// the source as lost, recordings of it end, and those that come back get
+    /// a new receiver. Returns how many there were.
+    pub fn close(&self, source: &str) -> usize {
+        let mut receivers = self.receivers.lock().unwrap();
+        let before = receivers.len();
+        receivers.retain(|key, recv| {
+            if recv.source_name != source {
+                return true;
+            }
+            info!("[{}] closed", key);
+            recv.stop.store(true, Ordering::Relaxed);
+            self.mosaics.lock().unwrap().remove(key);
+            false
+        });
+        before - receivers.len()
+    }
+
+    /// Release every receiver without clients that isn't recording or on air,
+    /// lingering or not. Returns how many were released.
+    pub fn release_unused(&self) -> usize {
+        let mut receivers = self.receivers.lock().unwrap();
+        let before = receivers.len();
+        receivers.retain(|key, recv| {
+            let used = recv.client_count() > 0 || recv.is_recording() || recv.is_on_air();
+            if !used {
+                info!("[{}] released while idle", key);
+            }
+            used
+        });
+        before - receivers.len()
+    }
+
+    /// Total clients over all active receivers.
+    pub fn client_count(&self) -> u64 {
+        let receivers = self.receivers.lock().unwrap();
+        receivers.values().map(|r| r.client_count()).sum()
+    }
+
+    /// Refuse new clients and tell existing ones to leave; see [`Self::drained`].
+    pub fn drain(&self) {
+        self.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Resolves once draining has started.
+    pub async fn drained(&self) {
+        let _ = self.draining.subscribe().wait_for(|draining| *draining).await;
+    }
+
+    /// Stop every receiver, whoever still holds it, and refuse new clients.
+    /// Subscribers see their channels close as the capture threads exit.
+    pub fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        self.drain();
+        for recv in self.receivers.lock().unwrap().values() {
+            recv.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Wait up to `timeout` for the capture threads to exit, finishing recordings
+    /// and releasing their NDI® receivers. Returns how many are still running.
+    pub fn join(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        while threads.iter().any(|t| !t.is_finished()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let (finished, running): (Vec<_>, Vec<_>) =
+            threads.into_iter().partition(|t| t.is_finished());
+        for thread in finished {
+            if thread.join().is_err() {
+                warn!("a capture thread panicked");
+            }
+        }
+        running.len()
+    }
+}
+
+/// What the bridge's NDI® receivers are named unless told otherwise:
+/// `streambridge@<hostname>`.
+pub fn default_receiver_name() -> String {
+    format!("streambridge@{}", hostname().unwrap_or_else(|| "localhost".to_string()))
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most `buf.len()` bytes into `buf`.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok().filter(|name| !name.is_empty())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok().filter(|name| !name.is_empty())
+}
+
+/// How the bridge introduces itself to the senders it connects to.
+fn product_info() -> ProductInfo {
+    ProductInfo {
+        long_name: "StreamBridge NDI to MJPEG bridge".into(),
+        short_name: "StreamBridge".into(),
+        manufacturer: "Hassler Labs".into(),
+        version: env!("CARGO_PKG_VERSION").into(),
+        model_name: "streambridge".into(),
+        session: "default".into(),
+        serial: String::new(),
+    }
+}
+
+/// Returns true when a producer should exit: stop was requested, or there are
+/// still no subscribers after a short grace period.
+fn should_stop(pipeline: &Pipeline, stop: &AtomicBool) -> bool {
+    if stop.load(Ordering::Relaxed) {
+        return true;
+    }
+    if !pipeline.has_subscribers() {
+        std::thread::sleep(Duration::from_millis(100));
+        // Check again and exit if still no clients
+        return !pipeline.has_subscribers();
+    }
+    false
+}
+
+/// View a captured NDI video frame as a [`VideoFrame`]. Returns `None` if the frame has no data.
+pub fn ndi_video_frame<'a>(
+    recv: &ReceiveInstance,
+    frame: &'a ffi::NDIlib_video_frame_v2_t,
+) -> Option<VideoFrame<'a>> {
+    let width = frame.xres as usize;
+    let height = frame.yres as usize;
+    let fourcc = FourCCVideoType::from(frame.four_cc);
+    let stride = if frame.line_stride_in_bytes > 0 {
+        frame.line_stride_in_bytes as usize
+    } else {
+        match fourcc {
+            FourCCVideoType::UYVY | FourCCVideoType::UYVA => width * 2,
+            _ => width * 4,
+        }
+    };
+    let data = recv.video_data(frame)?;
+    Some(VideoFrame { data, width, height, stride, fourcc })
+}
+
+/// A captured NDI® video frame's timecode and timestamp, unless the sender
+/// left them out.
+fn ndi_frame_times(frame: &ffi::NDIlib_video_frame_v2_t) -> (Option<i64>, Option<i64>) {
+    let set = |time: i64| (time != ffi::NDIlib_send_timecode_synthesize).then_some(time);
+    (set(frame.timecode), set(frame.timestamp).filter(|&time| time != 0))
+}
+
+/// All channels of a captured NDI audio frame, as planar float slices.
+pub fn ndi_audio_channels<'a>(
+    recv: &ReceiveInstance,
+    frame: &'a ffi::NDIlib_audio_frame_v3_t,
+) -> Vec<&'a [f32]> {
+    (0..frame.no_channels.max(0) as usize)
+        .filter_map(|ch| recv.audio_channel(frame, ch))
+        .collect()
+}
+
+fn capture_ndi(
+    mut session: NdiSession,
+    mut deinterlacer: Deinterlacer,
+    quirks: &Quirks,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    let mut video_frame = ffi::NDIlib_video_frame_v2_t::default();
+    let mut audio_frame = ffi::NDIlib_audio_frame_v3_t::default();
+    let mut metadata_frame = ffi::NDIlib_metadata_frame_t::default();
+    let mut ptz_pending: Vec<(PtzRequest, Instant)> = Vec::new();
+    // When the connection failed, and how often reconnecting was tried since.
+    let mut outage: Option<(Instant, u32)> = None;
+
+    while !should_stop(pipeline, stop) {
+        if session.runtime.is_quarantined() {
+            // Not the sender's fault: wait for the runtime however long it takes.
+            match wait_for_runtime(session, pipeline, stop, source_name) {
+                Some(reopened) => session = reopened,
+                None => break,
+            }
+            outage = Some((Instant::now(), 1));
+            continue;
+        }
+        if outage.is_some_and(|(since, _)| since.elapsed() >= RECONNECT_GIVE_UP) {
+            warn!("[{}] sender still unreachable, giving up", source_name);
+            break;
+        }
+        let recv = &session.recv;
+        let mut base = None;
+        let mut reconnect = false;
+        for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+            match control {
+                Control::Ptz(request) => ptz_pending.push((request, Instant::now() + PTZ_WAIT)),
+                Control::SetLowBandwidth(low) => {
+                    let bandwidth =
+                        if low { RecvBandwidth::Lowest } else { RecvBandwidth::Highest };
+                    base = Some(RecvSettings { bandwidth, ..session.base.clone() });
+                }
+                Control::Reconnect => reconnect = true,
+                Control::SetTally(tally) => {
+                    info!("[{}] tally: {:?}", source_name, tally);
+                    session.tally = tally;
+                    recv.set_tally(tally);
+                }
+                other => other.refuse("not a composite source"),
+            }
+        }
+        if !ptz_pending.is_empty() {
+            ptz_pending = send_ptz(recv, pipeline.stats(), ptz_pending, source_name);
+        }
+
+        let audio = pipeline.wants_audio().then_some(&mut audio_frame);
+        let timeout_ms = session.capture_timeout_ms();
+        let frame_type = recv.capture(&mut video_frame, audio, Some(&mut metadata_frame), timeout_ms);
+        let mut product_quirk = None;
+
+        match frame_type {
+            FrameType::Video => {
+                if let Some(frame) = ndi_video_frame(recv, &video_frame) {
+                    let format = FrameFormat::from(video_frame.frame_format_type);
+                    if let Some(frame) = deinterlacer.process(frame, format) {
+                        let (timecode, timestamp) = ndi_frame_times(&video_frame);
+                        pipeline.video_stamped(&frame, timecode, timestamp);
+                    }
+                }
+                recv.free_video(&video_frame);
+                if outage.take().is_some() {
+                    pipeline.link(Link::Up);
+                }
+            }
+            FrameType::Audio => {
+                let channels = ndi_audio_channels(recv, &audio_frame);
+                pipeline.audio(audio_frame.sample_rate.max(0) as u32, &channels);
+                recv.free_audio(&audio_frame);
+            }
+            FrameType::Error => {
+                // Keep the subscribers and try to get the sender back.
+                let (_, attempt) = outage.get_or_insert((Instant::now(), 0));
+                *attempt += 1;
+                let delay = reconnect_delay(*attempt);
+                warn!(
+                    "NDI connection error for \"{}\", reconnecting in {:?} (attempt {})",
+                    source_name, delay, attempt
+                );
+                pipeline.link(Link::Reconnecting { attempt: *attempt });
+                if !wait(delay, pipeline, stop) {
+                    break;
+                }
+                match session.reopen(session.base.clone(), session.quirk.clone()) {
+                    Ok(reopened) => session = reopened,
+                    Err(e) => warn!("[{}] {}", source_name, e),
+                }
+                continue;
+            }
+            FrameType::Metadata => {
+                if let Some(xml) = recv.metadata_text(&metadata_frame) {
+                    pipeline.metadata(xml);
+                    record_capabilities(xml, pipeline.stats(), source_name);
+                    record_tally_echo(xml, pipeline.stats(), source_name);
+                    product_quirk = announced_product(xml).and_then(|p| quirks.lookup(&p)).cloned();
+                }
+                recv.free_metadata(&metadata_frame);
+            }
+            FrameType::StatusChange => update_status(recv, pipeline.stats(), source_name),
+            FrameType::None => {
+                // Timeout, no data — loop
+            }
+            _ => {
+                // Unknown frame type — ignore
+            }
+        }
+
+        // The sender's product announcement matched a different quirk, the
+        // bandwidth was changed or an operator asked: reconnect with the new
+        // settings. Subscribers stay.
+        let quirk = product_quirk.filter(|q| session.quirk.as_ref() != Some(q));
+        let base = base.filter(|b| b.bandwidth != session.base.bandwidth);
+        if quirk.is_some() || base.is_some() || reconnect {
+            if let Some(quirk) = &quirk {
+                info!("[{}] applying quirk {}, reconnecting", source_name, quirk);
+            }
+            if let Some(base) = &base {
+                info!("[{}] bandwidth set to {:?}, reconnecting", source_name, base.bandwidth);
+            }
+            if reconnect {
+                info!("[{}] reconnecting on request", source_name);
+            }
+            let quirk = quirk.or_else(|| session.quirk.clone());
+            match session.reopen(base.unwrap_or_else(|| session.base.clone()), quirk) {
+                Ok(reopened) => session = reopened,
+                // The old settings are back with the reloaded runtime.
+                Err(e) if session.runtime.is_quarantined() => warn!("[{}] {}", source_name, e),
+                Err(e) => {
+                    warn!("[{}] {}", source_name, e);
+                    break;
+                }
+            }
+        }
+
+        pipeline.tick();
+    }
+}
+
+/// Drop `session` while the NDI® runtime is quarantined, so the runtime can be
+/// reloaded, and connect to the sender again once it has been. Clients are told
+/// the source is reconnecting meanwhile. Returns `None` if the receiver stops.
+fn wait_for_runtime(
+    session: NdiSession,
+    pipeline: &Pipeline,
+    stop: &AtomicBool,
+    source_name: &str,
+) -> Option<NdiSession> {
+    warn!("[{}] NDI runtime quarantined, releasing the receiver until it is reloaded", source_name);
+    let NdiSession { runtime, ndi, source, recv, base, quirk, tally } = session;
+    drop(recv);
+    drop(ndi);
+    pipeline.link(Link::Reconnecting { attempt: 1 });
+    loop {
+        if !wait(RUNTIME_POLL, pipeline, stop) {
+            return None;
+        }
+        if runtime.is_quarantined() {
+            continue;
+        }
+        match NdiSession::open(runtime.clone(), source.clone(), base.clone(), quirk.clone(), tally)
+        {
+            Ok(session) => {
+                info!("[{}] reconnecting with the reloaded NDI runtime", source_name);
+                return Some(session);
+            }
+            Err(e) => warn!("[{}] {}", source_name, e),
+        }
+    }
+}
+
+/// The wait before the `attempt`th reconnection: doubling from [`RECONNECT_DELAY`].
+fn reconnect_delay(attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(1).min(16);
+    RECONNECT_DELAY.saturating_mul(1 << doublings).min(RECONNECT_MAX_DELAY)
+}
+
+/// Sleep for `delay`, or less if the receiver is stopped or loses its
+/// subscribers meanwhile. Returns whether it should carry on.
+fn wait(delay: Duration, pipeline: &Pipeline, stop: &AtomicBool) -> bool {
+    let until = Instant::now() + delay;
+    while let Some(left) = until.checked_duration_since(Instant::now()) {
+        if should_stop(pipeline, stop) {
+            return false;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+    true
+}
+
+/// Send the PTZ requests the sender can take now, fail those that waited too long,
+/// and return the rest.
+fn send_ptz(
+    recv: &ReceiveInstance,
+    stats: &SourceStats,
+    pending: Vec<(PtzRequest, Instant)>,
+    source_name: &str,
+) -> Vec<(PtzRequest, Instant)> {
+    let supported = stats.ndi_status.lock().unwrap().as_ref().is_some_and(|s| s.ptz_supported);
+    let now = Instant::now();
+    let mut waiting = Vec::new();
+    for (request, deadline) in pending {
+        let result = if supported {
+            info!("[{}] PTZ {:?}", source_name, request.command);
+            request
+                .command
+                .apply(recv)
+                .then_some(())
+                .ok_or_else(|| "camera rejected the command".to_string())
+        } else if now >= deadline {
+            Err("source does not support PTZ".to_string())
+        } else {
+            waiting.push((request, deadline));
+            continue;
+        };
+        let _ = request.reply.send(result);
+    }
+    waiting
+}
+
+/// The product string from a sender's `<ndi_product>` announcement, if `xml` is one.
+fn announced_product(xml: &str) -> Option<String> {
+    let product = element_attributes(xml, "ndi_product")?;
+    let parts: Vec<&str> = ["manufacturer", "long_name", "short_name", "model_name"]
+        .iter()
+        .filter_map(|key| product.get(*key).map(String::as_str))
+        .filter(|v| !v.is_empty())
+        .collect();
+    Some(parts.join(" "))
+}
+
+/// Record the receiver's connection status after a status change, logging what changed.
+fn update_status(recv: &ReceiveInstance, stats: &SourceStats, source_name: &str) {
+    let status = recv.status();
+    let mut current = stats.ndi_status.lock().unwrap();
+    let previous = current.take().unwrap_or_default();
+    if status.connections != previous.connections {
+        info!("[{}] NDI connections: {}", source_name, status.connections);
+    }
+    if status.web_control != previous.web_control {
+        if let Some(url) = &status.web_control {
+            info!("[{}] web control: {}", source_name, url);
+        }
+    }
+    if status.ptz_supported && !previous.ptz_supported {
+        info!("[{}] PTZ control available", source_name);
+    }
+    *current = Some(status);
+}
+
+/// Keep the sender's capability announcement, if `xml` is one.
+fn record_capabilities(xml: &str, stats: &SourceStats, source_name: &str) {
+    let Some(capabilities) = element_attributes(xml, "ndi_capabilities") else {
+        return;
+    };
+    debug!("[{}] sender capabilities: {:?}", source_name, capabilities);
+    *stats.ndi_capabilities.lock().unwrap() = Some(capabilities);
+}
+
+/// Keep the sender's tally echo, if `xml` is one.
+fn record_tally_echo(xml: &str, stats: &SourceStats, source_name: &str) {
+    let Some(tally) = tally_echo(xml) else {
+        return;
+    };
+    debug!("[{}] tally echo: {:?}", source_name, tally);
+    *stats.tally_echo.lock().unwrap() = Some(tally);
+}
+
+/// Play a raw capture file in a loop, paced by the recorded timestamps.
+fn capture_replay(
+    path: &Path,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    'file: loop {
+        let mut reader = match RawReader::open(path) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("replay \"{}\": cannot open {}: {}", source_name, path.display(), e);
+                return;
+            }
+        };
+        let start = Instant::now();
+        let mut records = 0u64;
+
+        loop {
+            if should_stop(pipeline, stop) {
+                return;
+            }
+            for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+                control.refuse("replay sources have no PTZ");
+            }
+            let timed = match reader.next_record() {
+                Ok(Some(r)) => r,
+                Ok(None) if records > 0 => continue 'file,
+                Ok(None) => {
+                    warn!("replay \"{}\": {} holds no frames", source_name, path.display());
+                    return;
+                }
+                Err(e) => {
+                    error!("replay \"{}\": read error in {}: {}", source_name, path.display(), e);
+                    return;
+                }
+            };
+            records += 1;
+
+            let due = start + Duration::from_micros(timed.time_us);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+
+            match &timed.record {
+                Record::Video(video) => pipeline.video(&video.as_frame()),
+                Record::Audio(audio) => {
+                    let channels: Vec<&[f32]> = audio.channels.iter().map(|c| c.as_slice()).collect();
+                    pipeline.audio(audio.sample_rate, &channels);
+                }
+            }
+            pipeline.tick();
+        }
+    }
+}
+
+/// Play a media file in a loop at its frame rate, or the configured one.
+fn capture_media(
+    config: &MediaConfig,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    let path = &config.path;
+    let mut decoder = match JpegDecoder::new() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("media \"{}\": {}", source_name, e);
+            return;
+        }
+    };
+    let mut due = Instant::now();
+
+    'file: loop {
+        let mut reader = match MediaReader::open(path) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("media \"{}\": cannot open {}: {}", source_name, path.display(), e);
+                return;
+            }
+        };
+        let mut frames = 0u64;
+
+        loop {
+            if should_stop(pipeline, stop) {
+                return;
+            }
+            for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+                control.refuse("media sources have no PTZ");
+            }
+            let jpeg = match reader.next_frame() {
+                Ok(Some(jpeg)) => jpeg,
+                Ok(None) if frames > 0 => continue 'file,
+                Ok(None) => {
+                    warn!("media \"{}\": {} holds no frames", source_name, path.display());
+                    return;
+                }
+                Err(e) => {
+                    error!("media \"{}\": read error in {}: {}", source_name, path.display(), e);
+                    return;
+                }
+            };
+            frames += 1;
+
+            match decoder.decode(&jpeg) {
+                Ok(frame) => pipeline.video(&frame),
+                Err(e) => debug!("media \"{}\": skipping a frame: {}", source_name, e),
+            }
+            pipeline.tick();
+
+            due += media::interval(config, &reader);
+            match due.checked_duration_since(Instant::now()) {
+                Some(wait) => std::thread::sleep(wait),
+                None => due = Instant::now(),
+            }
+        }
+    }
+}
+
+/// How often a clock source rechecks the system clock's sync status.
+const NTP_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Render a clock source at its configured frame rate.
+fn capture_clock(
+    config: &ClockConfig,
+    times: TimeFormat,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+) {
+    let mut renderer = ClockRenderer::new(config).time_format(times);
+    let interval = Duration::from_secs(1) / config.fps;
+    let mut ntp = NtpStatus::query();
+    let mut ntp_checked = Instant::now();
+    let mut due = Instant::now();
+
+    while !should_stop(pipeline, stop) {
+        for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+            control.refuse("clock sources have no PTZ");
+        }
+        if ntp_checked.elapsed() >= NTP_CHECK_INTERVAL {
+            ntp = NtpStatus::query();
+            ntp_checked = Instant::now();
+        }
+        pipeline.video(&renderer.render(SystemTime::now(), ntp));
+        pipeline.tick();
+
+        due += interval;
+        match due.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            // Fell behind; don't try to catch up with a burst of frames.
+            None => due = Instant::now(),
+        }
+    }
+}
+
+/// A composite's subscription to one of its inputs; counts as a client of the
+/// input's receiver until dropped.
+struct Input {
+    manager: Arc<ReceiverManager>,
+    shared: Arc<SharedReceiver>,
+    rx: latest::Receiver<JpegFrame>,
+}
+
+impl Input {
+    fn open(manager: &Arc<ReceiverManager>, name: &str) -> Result<Self, String> {
+        let source = Source { name: name.to_string(), url: None };
+        let shared = manager.get_or_create(&source)?;
+        let rx = shared.subscribe();
+        Ok(Self { manager: manager.clone(), shared, rx })
+    }
+
+    /// The newest frame received since the last call, skipping older ones.
+    fn latest(&mut self) -> Result<Option<JpegFrame>, ()> {
+        self.rx.try_recv().map_err(|_| ())
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        self.shared.unsubscribe();
+        self.manager.maybe_remove(&self.shared.source_name);
+    }
+}
+
+/// Build a composite from the JPEGs of its inputs' receivers. Runs until the
+/// first input goes away; without the second, the first is passed through.
+fn capture_composite(
+    layout: Layout,
+    manager: &Arc<ReceiverManager>,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    let (first_name, second_name) = layout.inputs();
+    let (first_name, second_name) = (first_name.to_string(), second_name.to_string());
+    let mut compositor = match Compositor::new(layout) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("composite \"{}\": {}", source_name, e);
+            return;
+        }
+    };
+    let mut first = match Input::open(manager, &first_name) {
+        Ok(input) => input,
+        Err(e) => {
+            error!("composite \"{}\": source \"{}\": {}", source_name, first_name, e);
+            return;
+        }
+    };
+    let mut second = Input::open(manager, &second_name)
+        .map_err(|e| warn!("composite \"{}\": source \"{}\": {}", source_name, second_name, e))
+        .ok();
+
+    while !should_stop(pipeline, stop) {
+        for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+            match control {
+                Control::SetSplit(split) => compositor.set_split(split),
+                other => other.refuse("composite sources have no PTZ"),
+            }
+        }
+        if let Some(input) = second.as_mut() {
+            match input.latest() {
+                Ok(Some(frame)) => {
+                    if let Err(e) = compositor.set_second(&frame.data) {
+                        warn!("composite \"{}\": {}: {}", source_name, second_name, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(()) => {
+                    warn!("composite \"{}\": source \"{}\" lost", source_name, second_name);
+                    second = None;
+                    compositor.clear_second();
+                }
+            }
+        }
+        match first.latest() {
+            Ok(Some(frame)) => match compositor.compose(&frame.data) {
+                Ok(composed) => pipeline.video(&composed),
+                Err(e) => warn!("composite \"{}\": {}: {}", source_name, first_name, e),
+            },
+            Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+            Err(()) => {
+                warn!("composite \"{}\": source \"{}\" lost", source_name, first_name);
+                return;
+            }
+        }
+        pipeline.tick();
+    }
+}
+
+/// Cut a crop's region out of each of its input's frames. Runs until the input
+/// goes away.
+fn capture_crop(
+    config: CropConfig,
+    manager: &Arc<ReceiverManager>,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    let input_name = config.source.clone();
+    let mut cropper = match Cropper::new(config) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("crop \"{}\": {}", source_name, e);
+            return;
+        }
+    };
+    let mut input = match Input::open(manager, &input_name) {
+        Ok(input) => input,
+        Err(e) => {
+            error!("crop \"{}\": source \"{}\": {}", source_name, input_name, e);
+            return;
+        }
+    };
+
+    while !should_stop(pipeline, stop) {
+        for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+            control.refuse("crops have no PTZ");
+        }
+        match input.latest() {
+            Ok(Some(frame)) => match cropper.crop(&frame.data) {
+                Ok(cropped) => pipeline.video(&cropped),
+                Err(e) => warn!("crop \"{}\": {}: {}", source_name, input_name, e),
+            },
+            Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+            Err(()) => {
+                warn!("crop \"{}\": source \"{}\" lost", source_name, input_name);
+                return;
+            }
+        }
+        pipeline.tick();
+    }
+}
+
+/// Tile a mosaic's inputs into one frame, drawn again whenever one of them has
+/// a new frame. Runs while any input is left; tiles of lost inputs go blank.
+fn capture_mosaic(
+    config: MosaicConfig,
+    manager: &Arc<ReceiverManager>,
+    pipeline: &mut Pipeline,
+    controls: &mpsc::Receiver<Control>,
+    stop: &AtomicBool,
+    source_name: &str,
+) {
+    let mut mosaic = match Mosaic::new(&config) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("mosaic \"{}\": {}", source_name, e);
+            return;
+        }
+    };
+    let mut inputs: Vec<Option<Input>> = config
+        .sources
+        .iter()
+        .map(|name| {
+            Input::open(manager, name)
+                .map_err(|e| warn!("mosaic \"{}\": source \"{}\": {}", source_name, name, e))
+                .ok()
+        })
+        .collect();
+
+    while !should_stop(pipeline, stop) {
+        for control in controls.try_iter().filter_map(|c| pipeline.control(c)) {
+            control.refuse("mosaics have no PTZ");
+        }
+        if inputs.iter().all(Option::is_none) {
+            warn!("mosaic \"{}\": no sources left", source_name);
+            return;
+        }
+        let mut drawn = false;
+        for (index, (slot, name)) in inputs.iter_mut().zip(&config.sources).enumerate() {
+            let Some(input) = slot else {
+                continue;
+            };
+            match input.latest() {
+                Ok(Some(frame)) => match mosaic.draw(index, &frame.data) {
+                    Ok(()) => drawn = true,
+                    Err(e) => warn!("mosaic \"{}\": {}: {}", source_name, name, e),
+                },
+                Ok(None) => {}
+                Err(()) => {
+                    warn!("mosaic \"{}\": source \"{}\" lost", source_name, name);
+                    *slot = None;
+                    mosaic.clear(index);
+                    drawn = true;
+                }
+            }
+        }
+        if drawn {
+            pipeline.video(&mosaic.frame());
+        } else {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        pipeline.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_a_cap() {
+        let delays: Vec<u64> = (1..=7).map(|n| reconnect_delay(n).as_millis() as u64).collect();
+        assert_eq!(delays, [500, 1_000, 2_000, 4_000, 8_000, 10_000, 10_000]);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+    }
+
+    #[test]
+    fn receivers_are_named_after_the_host() {
+        let name = default_receiver_name();
+        let host = name.strip_prefix("streambridge@").expect("streambridge@<hostname>");
+        assert!(!host.is_empty() && !host.contains('\0'), "{name}");
+    }
+}
@@ -0,0 +1,351 @@
+//! Recordings in `--record-dir`: MJPEG-in-AVI files of the JPEGs viewers get,
+//! or raw files replayable with `--replay`, moving on to a new file past a size
+//! or duration.
+
+use crate::automation::sanitize;
+use crate::avi::{self, AviWriter};
+use crate::cadence::Cadence;
+use crate::pipeline::VideoFrame;
+use crate::rawfile::RawWriter;
+use crate::timestamps::TimeFormat;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// What a recording is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// The source's JPEGs, at its quality and size, in an AVI file. Video only.
+    Avi,
+    /// Uncompressed frames and audio, for `--replay`.
+    Raw,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Avi => "avi",
+            Format::Raw => "sbraw",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        [Format::Avi, Format::Raw].into_iter().find(|f| f.extension() == extension)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Avi => "video/x-msvideo",
+            Format::Raw => "application/octet-stream",
+        }
+    }
+}
+
+/// The highest fixed frame rate a recording can be written at.
+pub const MAX_RECORD_FPS: u32 = 120;
+
+/// When a recording moves on to a new file; never by default, though AVI files
+/// always do at [`avi::MAX_FILE_BYTES`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_duration: Option<Duration>,
+}
+
+enum Writer {
+    Avi(AviWriter),
+    Raw(RawWriter),
+}
+
+/// One source's recording, as files in a directory.
+pub struct Recorder {
+    dir: PathBuf,
+    /// The source's name as used in file names.
+    stem: String,
+    format: Format,
+    rotation: Rotation,
+    /// How the time in file names is written.
+    times: TimeFormat,
+    writer: Writer,
+    /// The file being written, after those finished before it.
+    files: Vec<PathBuf>,
+    /// When the current file was started.
+    started: Instant,
+    /// Converts AVI recordings to a fixed frame rate.
+    cadence: Option<Cadence>,
+    /// Whether an AVI recording takes only the JPEGs sent to viewers.
+    shared: bool,
+}
+
+impl Recorder {
+    /// Start recording `source_name` to a new file in `dir`, named after it and
+    /// the time as `times` writes it.
+    pub fn create(
+        dir: &Path,
+        source_name: &str,
+        format: Format,
+        rotation: Rotation,
+        times: TimeFormat,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let stem = sanitize(source_name);
+        let (writer, path) = open(dir, &stem, format, &times)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            stem,
+            format,
+            rotation,
+            times,
+            writer,
+            files: vec![path],
+            started: Instant::now(),
+            cadence: None,
+            shared: false,
+        })
+    }
+
+    /// Write AVI recordings at exactly `fps` frames per second, repeating and
+    /// dropping frames as needed, instead of at the source's own rate.
+    pub fn fps(mut self, fps: Option<u32>) -> Self {
+        self.cadence = fps.filter(|_| self.format == Format::Avi).map(Cadence::new);
+        self
+    }
+
+    /// Record the JPEGs sent to viewers, at their fps cap, instead of encoding
+    /// every frame, so an AVI recording costs no encoding of its own.
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.shared = shared && self.format == Format::Avi;
+        self
+    }
+
+    /// Whether the recording takes the viewers' JPEGs; see [`Recorder::shared`].
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// The file being written.
+    pub fn path(&self) -> &Path {
+        self.files.last().expect("a recording has a file")
+    }
+
+    /// Whether the recording takes JPEGs rather than raw frames.
+    pub fn wants_jpeg(&self) -> bool {
+        self.format == Format::Avi
+    }
+
+    /// Record a raw frame; a no-op for AVI recordings.
+    pub fn video(&mut self, frame: &VideoFrame) -> io::Result<()> {
+        self.rotate_if_due()?;
+        match &mut self.writer {
+            Writer::Raw(raw) => raw.write_video(self.started.elapsed().as_micros() as u64, frame),
+            Writer::Avi(_) => Ok(()),
+        }
+    }
+
+    /// Record an encoded frame, sent at `at`; a no-op for raw recordings.
+    pub fn jpeg(&mut self, jpeg: Bytes, at: Instant) -> io::Result<()> {
+        let Some(cadence) = self.cadence.as_mut() else {
+            return self.write_jpeg(&jpeg, at);
+        };
+        let due = cadence.push(jpeg, at);
+        due.into_iter().try_for_each(|(jpeg, at)| self.write_jpeg(&jpeg, at))
+    }
+
+    fn write_jpeg(&mut self, jpeg: &[u8], at: Instant) -> io::Result<()> {
+        self.rotate_if_due()?;
+        match &mut self.writer {
+            Writer::Avi(avi) => avi.write_frame(jpeg, at),
+            Writer::Raw(_) => Ok(()),
+        }
+    }
+
+    /// Record planar float audio; only raw recordings carry it.
+    pub fn audio(&mut self, sample_rate: u32, channels: &[&[f32]]) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Raw(raw) => {
+                let time_us = self.started.elapsed().as_micros() as u64;
+                raw.write_audio(time_us, sample_rate, channels)
+            }
+            Writer::Avi(_) => Ok(()),
+        }
+    }
+
+    /// Finish the current file; returns every file the recording wrote.
+    pub fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        // A fixed-rate recording shows its last frame until it stops.
+        if let Some(cadence) = self.cadence.as_mut() {
+            for (jpeg, at) in cadence.flush(Instant::now()) {
+                self.write_jpeg(&jpeg, at)?;
+            }
+        }
+        finish(self.writer)?;
+        Ok(self.files)
+    }
+
+    fn size(&self) -> u64 {
+        match &self.writer {
+            Writer::Avi(avi) => avi.bytes_written(),
+            // Compressed on the way out, so only the file knows.
+            Writer::Raw(_) => std::fs::metadata(self.path()).map_or(0, |m| m.len()),
+        }
+    }
+
+    fn rotate_if_due(&mut self) -> io::Result<()> {
+        let mut max_bytes = self.rotation.max_bytes;
+        if self.format == Format::Avi {
+            max_bytes = Some(max_bytes.map_or(avi::MAX_FILE_BYTES, |m| m.min(avi::MAX_FILE_BYTES)));
+        }
+        let full = max_bytes.is_some_and(|max| self.size() >= max);
+        let due = self.rotation.max_duration.is_some_and(|max| self.started.elapsed() >= max);
+        if !full && !due {
+            return Ok(());
+        }
+        let (writer, path) = open(&self.dir, &self.stem, self.format, &self.times)?;
+        finish(std::mem::replace(&mut self.writer, writer))?;
+        self.files.push(path);
+        self.started = Instant::now();
+        Ok(())
+    }
+}
+
+/// A new file for `stem`, named after it and the time.
+fn open(
+    dir: &Path,
+    stem: &str,
+    format: Format,
+    times: &TimeFormat,
+) -> io::Result<(Writer, PathBuf)> {
+    let (timestamp, extension) = (times.file_timestamp(SystemTime::now()), format.extension());
+    let mut path = dir.join(format!("{stem}-{timestamp}.{extension}"));
+    // A file rotated within the second gets a number.
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem}-{timestamp}_{n}.{extension}"));
+        n += 1;
+    }
+    let writer = match format {
+        Format::Avi => Writer::Avi(AviWriter::create(&path)?),
+        Format::Raw => Writer::Raw(RawWriter::create(&path)?),
+    };
+    Ok((writer, path))
+}
+
+fn finish(writer: Writer) -> io::Result<()> {
+    match writer {
+        Writer::Avi(avi) => avi.finish().map(drop),
+        Writer::Raw(raw) => raw.finish(),
+    }
+}
+
+/// A finished or ongoing recording, for `GET /recordings`.
+#[derive(Debug, Serialize)]
+pub struct RecordingFile {
+    pub name: String,
+    pub bytes: u64,
+    /// Last written, in seconds since the Unix epoch.
+    pub modified: u64,
+}
+
+impl RecordingFile {
+    /// The recorded source's name as used in file names; see [`sanitize`].
+    pub fn stem(&self) -> Option<&str> {
+        let (rest, _) = self.name.rsplit_once('.')?;
+        let rest = match rest.rsplit_once('_') {
+            Some((rest, n)) if n.bytes().all(|b| b.is_ascii_digit()) => rest,
+            _ => rest,
+        };
+        // `-YYYYMMDD-HHMMSS`
+        let split = rest.len().checked_sub(16)?;
+        let (stem, timestamp) = (rest.get(..split)?, &rest.as_bytes()[split..]);
+        let digits = |range: std::ops::Range<usize>| {
+            timestamp[range].iter().all(u8::is_ascii_digit)
+        };
+        let shaped = timestamp[0] == b'-' && timestamp[9] == b'-';
+        (shaped && digits(1..9) && digits(10..16)).then_some(stem)
+    }
+}
+
+/// The recordings in `dir`, by name; none if it doesn't exist yet.
+pub fn list(dir: &Path) -> io::Result<Vec<RecordingFile>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || find(dir, &name).is_none() {
+            continue;
+        }
+        let modified = metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        files.push(RecordingFile {
+            name,
+            bytes: metadata.len(),
+            modified: modified.map_or(0, |d| d.as_secs()),
+        });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// The path and format of the recording `name` in `dir`. Anything but a plain
+/// file name with a recording's extension is refused.
+pub fn find(dir: &Path, name: &str) -> Option<(PathBuf, Format)> {
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return None;
+    }
+    let format = Format::from_extension(name.rsplit_once('.')?.1)?;
+    let path = dir.join(name);
+    path.is_file().then_some((path, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_lead_back_to_their_source() {
+        let file = |name: &str| RecordingFile { name: name.to_string(), bytes: 0, modified: 0 };
+        assert_eq!(file("CAM (1)-20261018-120000.avi").stem(), Some("CAM (1)"));
+        assert_eq!(file("cam_2-20261018-120000_3.sbraw").stem(), Some("cam_2"));
+        assert_eq!(file("notes.txt").stem(), None);
+        assert_eq!(file("cam-2026-1018-120000.avi").stem(), None);
+        assert!(find(Path::new("."), "../secret.avi").is_none());
+        assert!(find(Path::new("."), "Cargo.toml").is_none());
+    }
+
+    #[test]
+    fn recordings_rotate_by_duration() {
+        let dir = std::env::temp_dir().join(format!("sb-rotation-{}", std::process::id()));
+        let rotation = Rotation { max_duration: Some(Duration::from_millis(50)), max_bytes: None };
+        let times = TimeFormat::default();
+        let mut recorder = Recorder::create(&dir, "cam", Format::Raw, rotation, times).unwrap();
+        let data = [0u8; 16 * 8 * 2];
+        let frame = VideoFrame {
+            data: &data,
+            width: 16,
+            height: 8,
+            stride: 32,
+            fourcc: crate::ndi::FourCCVideoType::UYVY,
+        };
+        for _ in 0..3 {
+            recorder.video(&frame).unwrap();
+            std::thread::sleep(Duration::from_millis(60));
+        }
+        let files = recorder.finish().unwrap();
+        let listed = list(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 3, "{files:?}");
+        assert_eq!(listed.len(), 3);
+        assert!(listed.iter().all(|f| f.stem() == Some("cam") && f.bytes > 0));
+    }
+}
@@ -0,0 +1,149 @@
+//! Sources the bridge builds, like composites and mosaics, sent back out as NDI®
+//! sources of their own, so other NDI tools can take them, not only browsers.
+//! Their JPEGs are decoded back into BGRA for sending: what goes out is what
+//! viewers see, at the source's quality and frame rate.
+
+use crate::composite::MosaicConfig;
+use crate::discovery::SourceList;
+use crate::idle::Activity;
+use crate::ndi::SendInstance;
+use crate::publish::JpegDecoder;
+use crate::receiver::{ReceiverManager, SharedReceiver};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long an output waits before starting over after its source was lost.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The frame rate announced for sources without a `max_fps`.
+const UNCAPPED_FPS: u32 = 30;
+
+/// A `[republish."NAME"]` entry: a source, or a mosaic of several, sent out as
+/// the NDI® source `NAME`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepublishConfig {
+    pub source: Option<String>,
+    /// Sources to tile, in reading order, instead of `source`.
+    pub mosaic: Option<Vec<String>>,
+    /// The mosaic's `COLUMNSxROWS`; by default the squarest grid that fits.
+    pub layout: Option<String>,
+}
+
+impl RepublishConfig {
+    /// What the output named `name` sends.
+    pub fn input(&self, name: &str) -> Result<Input, String> {
+        let input = match (&self.source, &self.mosaic) {
+            (Some(_), Some(_)) | (None, None) => Err("needs either source or mosaic".to_string()),
+            (Some(_), None) if self.layout.is_some() => Err("layout is for mosaics".to_string()),
+            (Some(source), None) => Ok(Input::Source(source.clone())),
+            (None, Some(sources)) => {
+                MosaicConfig::new(sources.clone(), self.layout.as_deref()).map(Input::Mosaic)
+            }
+        };
+        if name.trim().is_empty() {
+            return Err("republish needs a name".to_string());
+        }
+        input.map_err(|e| format!("republish \"{name}\": {e}"))
+    }
+}
+
+/// What an output sends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Source(String),
+    Mosaic(MosaicConfig),
+}
+
+/// Send `input` through `sender` until the runtime stops, starting over each
+/// time the source goes away. Keeps the source's receiver running.
+pub async fn run(
+    name: String,
+    input: Input,
+    sender: SendInstance,
+    sources: SourceList,
+    manager: Arc<ReceiverManager>,
+    activity: Arc<Activity>,
+) {
+    let _stream = activity.stream();
+    loop {
+        let result = send(&input, &sender, &sources, &manager).await;
+        let error = result.err().unwrap_or_else(|| "source lost".to_string());
+        warn!("NDI® output \"{}\": {}", name, error);
+        tokio::time::sleep(RETRY_DELAY).await;
+        info!("NDI® output \"{}\" starting over", name);
+    }
+}
+
+/// Send `input`'s frames until its receiver goes away.
+async fn send(
+    input: &Input,
+    sender: &SendInstance,
+    sources: &SourceList,
+    manager: &Arc<ReceiverManager>,
+) -> Result<(), String> {
+    let shared = match input {
+        Input::Source(source) => {
+            let found = sources.read().unwrap().iter().find(|s| s.name == *source).cloned();
+            manager.get_or_create(&found.ok_or("source not found")?)?
+        }
+        Input::Mosaic(config) => manager.get_or_create_mosaic(config)?,
+    };
+    let fps = match manager.encode_settings(&shared.source_name).max_fps {
+        0 => UNCAPPED_FPS,
+        fps => fps,
+    };
+    let mut rx = shared.subscribe();
+    let _subscription = Subscription { shared, manager: Arc::clone(manager) };
+    let mut decoder = JpegDecoder::new(fps)?;
+    while let Some(frame) = rx.recv().await {
+        match decoder.decode(&frame.data) {
+            Ok(video) => sender.send_video(video),
+            Err(e) => warn!("NDI® output: skipping frame: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// An output's subscription to its source; unsubscribes when dropped.
+struct Subscription {
+    shared: Arc<SharedReceiver>,
+    manager: Arc<ReceiverManager>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.shared.unsubscribe();
+        self.manager.maybe_remove(&self.shared.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_send_a_source_or_a_mosaic() {
+        let parse = |toml: &str| toml::from_str::<RepublishConfig>(toml).unwrap().input("Out");
+        let source = parse("source = \"Program + Guest\"").unwrap();
+        assert_eq!(source, Input::Source("Program + Guest".to_string()));
+        let Input::Mosaic(mosaic) = parse("mosaic = [\"A\", \"B\", \"C\"]").unwrap() else {
+            panic!("expected a mosaic");
+        };
+        assert_eq!((mosaic.columns, mosaic.rows), (2, 2));
+
+        let errors = [
+            "",
+            "source = \"A\"\nmosaic = [\"B\"]",
+            "source = \"A\"\nlayout = \"2x2\"",
+            "mosaic = [\"A\", \"B\", \"C\"]\nlayout = \"1x2\"",
+        ];
+        for toml in errors {
+            let error = parse(toml).unwrap_err();
+            assert!(error.starts_with("republish \"Out\": "), "{toml}: {error}");
+        }
+        assert!(RepublishConfig::default().input(" ").is_err());
+    }
+}
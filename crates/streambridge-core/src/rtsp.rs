@@ -0,0 +1,823 @@
+//! RTSP output: `rtsp://host:<rtsp-port>/<source>` for NVRs like Frigate, Blue
+//! Iris and Milestone, which ingest IP cameras rather than MJPEG over HTTP.
+//! Sources are named as in the HTTP URLs (alias, name or id), and tokens go in
+//! `?token=` or as the password of Basic auth. Frames go out as encoded, as
+//! RTP/JPEG (RFC 2435), interleaved on the RTSP connection or over UDP. A
+//! session lasts as long as its RTSP connection.
+
+use crate::auth::Action;
+use crate::clients::new_client_id;
+use crate::pipeline::Variant;
+use crate::server::{self, AppState, Rejection, ViewerFeed};
+use axum::http::StatusCode;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// RTP payload type of JPEG, which has a 90 kHz clock.
+const PAYLOAD_TYPE: u8 = 26;
+const CLOCK_RATE: u128 = 90_000;
+/// The largest RTP packet sent, to stay under common MTUs.
+const MAX_PACKET: usize = 1400;
+/// RTP/JPEG gives the width and height in 8-pixel blocks, a byte each.
+const MAX_SIZE: usize = 2040;
+/// The control URL of the one track, under the source's.
+const TRACK: &str = "video";
+const MAX_LINE: u64 = 8192;
+const MAX_HEADERS: usize = 64;
+/// Announced to clients; sessions actually end with their connection.
+const SESSION_TIMEOUT: u32 = 60;
+const METHODS: &str = "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER, SET_PARAMETER";
+/// How long to wait after failing to accept a connection, e.g. out of files.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serve RTSP on `listener` until the server starts draining.
+pub async fn serve(listener: TcpListener, state: AppState) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = state.receiver_manager.drained() => return,
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                tokio::spawn(connection(stream, peer, state.clone()));
+            }
+            Err(e) => {
+                warn!("RTSP: failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Answer `peer`'s requests until it disconnects, its stream ends or the
+/// server drains.
+async fn connection(stream: TcpStream, peer: SocketAddr, state: AppState) {
+    let Ok(local) = stream.local_addr() else {
+        return;
+    };
+    let (read, write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let writer = Arc::new(Mutex::new(write));
+    let mut session = Session::new(state.clone(), peer, local.ip(), writer.clone());
+    loop {
+        let request = tokio::select! {
+            request = read_request(&mut reader) => request,
+            () = session.ended() => break,
+            () = state.receiver_manager.drained() => break,
+        };
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                debug!("RTSP: {}: {}", peer, e);
+                break;
+            }
+        };
+        state.activity.touch();
+        let response = session.handle(&request).await;
+        let response = response.to_bytes(request.header("CSeq"));
+        if writer.lock().await.write_all(&response).await.is_err() {
+            break;
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        let mut headers = self.headers.iter();
+        headers.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The next request, skipping the RTCP reports clients interleave. `None`
+/// once the client has closed the connection.
+async fn read_request(reader: &mut BufReader<OwnedReadHalf>) -> io::Result<Option<Request>> {
+    let request_line = loop {
+        let buffered = reader.fill_buf().await?;
+        if buffered.is_empty() {
+            return Ok(None);
+        }
+        if buffered[0] == b'$' {
+            let mut header = [0; 4];
+            reader.read_exact(&mut header).await?;
+            let len = u16::from_be_bytes([header[2], header[3]]);
+            tokio::io::copy(&mut (&mut *reader).take(len.into()), &mut tokio::io::sink()).await?;
+            continue;
+        }
+        match read_line(reader).await? {
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+            None => return Ok(None),
+        }
+    };
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(uri), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("malformed request line"));
+    };
+    if !version.starts_with("RTSP/1.") {
+        return Err(invalid("not an RTSP/1.x request"));
+    }
+    let mut headers = Vec::new();
+    loop {
+        let Some(line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let request = Request { method: method.to_string(), uri: uri.to_string(), headers };
+    // Parameters to get or set, which there are none of.
+    let body = request.header("Content-Length").and_then(|len| len.parse().ok()).unwrap_or(0);
+    tokio::io::copy(&mut (&mut *reader).take(body), &mut tokio::io::sink()).await?;
+    Ok(Some(request))
+}
+
+async fn read_line(reader: &mut BufReader<OwnedReadHalf>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if (&mut *reader).take(MAX_LINE).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid("line too long"));
+    }
+    Ok(Some(line.trim_end().to_string()))
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16, reason: &'static str) -> Self {
+        Self { status, reason, headers: Vec::new(), body: String::new() }
+    }
+
+    fn ok() -> Self {
+        Self::new(200, "OK")
+    }
+
+    /// A request turned away like its HTTP counterpart would be.
+    fn rejected((status, reason): Rejection) -> Self {
+        debug!("RTSP: {} {}", status.as_u16(), reason);
+        let response = Self::new(status.as_u16(), status.canonical_reason().unwrap_or("Error"));
+        if status == StatusCode::UNAUTHORIZED {
+            // NVRs ask for a user name and password; the password is the token.
+            return response.header("WWW-Authenticate", "Basic realm=\"streambridge\"");
+        }
+        response
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn to_bytes(&self, cseq: Option<&str>) -> Vec<u8> {
+        let mut text = format!("RTSP/1.0 {} {}\r\n", self.status, self.reason);
+        if let Some(cseq) = cseq {
+            text += &format!("CSeq: {cseq}\r\n");
+        }
+        text += "Server: streambridge\r\n";
+        for (name, value) in &self.headers {
+            text += &format!("{name}: {value}\r\n");
+        }
+        if !self.body.is_empty() {
+            text += &format!("Content-Length: {}\r\n", self.body.len());
+        }
+        text += "\r\n";
+        text += &self.body;
+        text.into_bytes()
+    }
+}
+
+/// The source a request URL names, and the URLs it is played by.
+struct Target {
+    /// The source's name.
+    source: String,
+    token: Option<String>,
+    /// The source's URL, without the query.
+    base: String,
+    /// The track's URL, with the query, so its token comes along.
+    control: String,
+}
+
+/// What a `SETUP` request set up.
+struct Setup {
+    source: String,
+    token: Option<String>,
+    control: String,
+    output: Output,
+}
+
+/// One RTSP connection and the session set up on it.
+struct Session {
+    state: AppState,
+    id: String,
+    peer: SocketAddr,
+    local: IpAddr,
+    user_agent: Option<String>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    setup: Option<Setup>,
+    packetizer: Packetizer,
+    /// The RTP timestamp streams start at.
+    rtp_base: u32,
+    playing: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    fn new(
+        state: AppState,
+        peer: SocketAddr,
+        local: IpAddr,
+        writer: Arc<Mutex<OwnedWriteHalf>>,
+    ) -> Self {
+        // 16 random hex digits, which also make the random RTP identifiers.
+        let id = new_client_id();
+        let ssrc = u32::from_str_radix(&id[..8], 16).unwrap_or_default();
+        let rtp_base = u32::from_str_radix(&id[8..], 16).unwrap_or_default();
+        Self {
+            state,
+            peer,
+            local,
+            user_agent: None,
+            writer,
+            setup: None,
+            packetizer: Packetizer { ssrc, seq: rtp_base as u16 },
+            rtp_base,
+            playing: None,
+            id,
+        }
+    }
+
+    /// Resolves once the session's stream has ended; never when not playing.
+    async fn ended(&mut self) {
+        match &mut self.playing {
+            Some(playing) => {
+                let _ = playing.await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn handle(&mut self, request: &Request) -> Response {
+        if self.user_agent.is_none() {
+            self.user_agent = request.header("User-Agent").map(str::to_string);
+        }
+        let response = match request.method.as_str() {
+            "OPTIONS" => Response::ok().header("Public", METHODS),
+            "DESCRIBE" => self.describe(request),
+            "SETUP" => self.setup(request).await,
+            "PLAY" => self.play(request),
+            "TEARDOWN" => {
+                self.stop();
+                self.setup = None;
+                return Response::ok();
+            }
+            "GET_PARAMETER" | "SET_PARAMETER" => Response::ok(),
+            _ => return Response::new(501, "Not Implemented").header("Public", METHODS),
+        };
+        if self.setup.is_none() {
+            return response;
+        }
+        response.header("Session", format!("{};timeout={SESSION_TIMEOUT}", self.id))
+    }
+
+    /// The source `request` is for, once its token may view it.
+    fn target(&self, request: &Request) -> Result<Target, Rejection> {
+        let (url, query) = match request.uri.split_once('?') {
+            Some((url, query)) => (url, Some(query)),
+            None => (request.uri.as_str(), None),
+        };
+        let base = url.trim_end_matches('/');
+        let base = base.strip_suffix(TRACK).and_then(|b| b.strip_suffix('/')).unwrap_or(base);
+        let path = match base.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |slash| &rest[slash..]),
+            None => base,
+        };
+        let reference = percent_decode(path.trim_start_matches('/'));
+        if reference.is_empty() {
+            return Err((StatusCode::NOT_FOUND, "expected rtsp://host:port/<source>"));
+        }
+        let source = self.state.source_name(reference);
+        let token = request_token(request, query);
+        server::authorize(&self.state, token.as_deref(), &source, Action::View)?;
+        if !self.state.sources.read().unwrap().iter().any(|s| s.name == source) {
+            return Err((StatusCode::NOT_FOUND, "source not found"));
+        }
+        let query = query.map(|query| format!("?{query}")).unwrap_or_default();
+        let control = format!("{base}/{TRACK}{query}");
+        Ok(Target { source, token, base: base.to_string(), control })
+    }
+
+    fn describe(&self, request: &Request) -> Response {
+        let target = match self.target(request) {
+            Ok(target) => target,
+            Err(rejection) => return Response::rejected(rejection),
+        };
+        let (family, any) = match self.local {
+            IpAddr::V4(_) => ("IP4", "0.0.0.0"),
+            IpAddr::V6(_) => ("IP6", "::"),
+        };
+        let sdp = [
+            "v=0".to_string(),
+            format!("o=- {} 1 IN {family} {}", self.packetizer.ssrc, self.local),
+            format!("s={}", target.source),
+            format!("c=IN {family} {any}"),
+            "t=0 0".to_string(),
+            "a=range:npt=0-".to_string(),
+            format!("m=video 0 RTP/AVP {PAYLOAD_TYPE}"),
+            format!("a=rtpmap:{PAYLOAD_TYPE} JPEG/{CLOCK_RATE}"),
+            format!("a=control:{}", target.control),
+            String::new(),
+        ];
+        let mut response = Response::ok()
+            .header("Content-Base", format!("{}/", target.base))
+            .header("Content-Type", "application/sdp");
+        response.body = sdp.join("\r\n");
+        response
+    }
+
+    async fn setup(&mut self, request: &Request) -> Response {
+        if self.playing.is_some() {
+            return Response::new(455, "Method Not Valid in This State");
+        }
+        let target = match self.target(request) {
+            Ok(target) => target,
+            Err(rejection) => return Response::rejected(rejection),
+        };
+        let Some(transport) = request.header("Transport").and_then(parse_transport) else {
+            return Response::new(461, "Unsupported Transport");
+        };
+        let (output, reply) = match transport {
+            Transport::Interleaved(channel) => {
+                let channels = format!("{channel}-{}", channel.wrapping_add(1));
+                let output = Output::Interleaved(self.writer.clone(), channel);
+                (output, format!("RTP/AVP/TCP;unicast;interleaved={channels}"))
+            }
+            Transport::Udp(client_port) => {
+                let socket = match UdpSocket::bind((self.local, 0)).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        warn!("RTSP: failed to bind a UDP socket: {}", e);
+                        return Response::new(500, "Internal Server Error");
+                    }
+                };
+                let server_port = socket.local_addr().map_or(0, |addr| addr.port());
+                let ports = |port: u16| format!("{port}-{}", port.wrapping_add(1));
+                let reply = format!(
+                    "RTP/AVP;unicast;client_port={};server_port={};ssrc={:08X}",
+                    ports(client_port),
+                    ports(server_port),
+                    self.packetizer.ssrc
+                );
+                let destination = SocketAddr::new(self.peer.ip(), client_port);
+                (Output::Udp(Arc::new(socket), destination), reply)
+            }
+        };
+        let Target { source, token, control, .. } = target;
+        self.setup = Some(Setup { source, token, control, output });
+        Response::ok().header("Transport", reply)
+    }
+
+    fn play(&mut self, request: &Request) -> Response {
+        let Some(setup) = &self.setup else {
+            return Response::new(455, "Method Not Valid in This State");
+        };
+        let session = request.header("Session").and_then(|s| s.split(';').next());
+        if session.is_some_and(|id| id.trim() != self.id) {
+            return Response::new(454, "Session Not Found");
+        }
+        let info =
+            format!("url={};seq={};rtptime={}", setup.control, self.packetizer.seq, self.rtp_base);
+        let response = Response::ok().header("Range", "npt=0.000-").header("RTP-Info", info);
+        if self.playing.is_some() {
+            return response;
+        }
+        let query = request.uri.split_once('?').map(|(_, query)| query);
+        let token = request_token(request, query).or_else(|| setup.token.clone());
+        let viewer = Viewer {
+            state: self.state.clone(),
+            token,
+            source: setup.source.clone(),
+            peer: self.peer.ip(),
+            user_agent: self.user_agent.clone(),
+        };
+        let feed = match viewer.open(Variant::default()) {
+            Ok(feed) => feed,
+            Err(rejection) => return Response::rejected(rejection),
+        };
+        info!("RTSP: client connected for \"{}\"", setup.source);
+        let stream = Stream {
+            viewer,
+            output: setup.output.clone(),
+            packetizer: self.packetizer.clone(),
+            rtp_base: self.rtp_base,
+        };
+        self.playing = Some(tokio::spawn(stream.run(feed)));
+        response
+    }
+
+    fn stop(&mut self) {
+        if let Some(playing) = self.playing.take() {
+            playing.abort();
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Who a stream is for, to open its feed with.
+struct Viewer {
+    state: AppState,
+    token: Option<String>,
+    source: String,
+    peer: IpAddr,
+    user_agent: Option<String>,
+}
+
+impl Viewer {
+    fn open(&self, variant: Variant) -> Result<ViewerFeed, Rejection> {
+        let (token, user_agent) = (self.token.as_deref(), self.user_agent.clone());
+        let peer = Some(self.peer);
+        ViewerFeed::open(&self.state, "rtsp", token, &self.source, variant, peer, user_agent)
+    }
+}
+
+/// A playing session's frames on their way out.
+struct Stream {
+    viewer: Viewer,
+    output: Output,
+    packetizer: Packetizer,
+    rtp_base: u32,
+}
+
+impl Stream {
+    async fn run(mut self, mut feed: ViewerFeed) {
+        let source = &self.viewer.source;
+        let start = Instant::now();
+        let mut scaled = false;
+        let mut warned = false;
+        while let Some(image) = feed.next().await {
+            let jpeg = match Jpeg::parse(&image) {
+                Ok(jpeg) => jpeg,
+                Err(e) => {
+                    if !warned {
+                        warn!("RTSP: \"{}\" can't be sent as RTP/JPEG: {}", source, e);
+                        warned = true;
+                    }
+                    continue;
+                }
+            };
+            if jpeg.width > MAX_SIZE || jpeg.height > MAX_SIZE {
+                // Too large for RTP/JPEG: ask for frames scaled down to fit.
+                if scaled {
+                    continue;
+                }
+                scaled = true;
+                let max_width = jpeg.width * MAX_SIZE / jpeg.width.max(jpeg.height);
+                let variant = Variant { max_width: Some(max_width), ..Default::default() };
+                feed = match self.viewer.open(variant) {
+                    Ok(feed) => feed,
+                    Err((_, reason)) => {
+                        warn!("RTSP: \"{}\" can't be scaled down: {}", source, reason);
+                        break;
+                    }
+                };
+                continue;
+            }
+            let elapsed = (start.elapsed().as_micros() * CLOCK_RATE / 1_000_000) as u32;
+            let packets = self.packetizer.packets(&jpeg, self.rtp_base.wrapping_add(elapsed));
+            let sending = Instant::now();
+            match self.output.send(&packets).await {
+                Ok(bytes) => feed.sent(bytes, sending.elapsed()),
+                Err(e) => {
+                    debug!("RTSP: \"{}\": {}", source, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A transport a client asked for in `SETUP`.
+#[derive(Debug, PartialEq)]
+enum Transport {
+    /// On the RTSP connection, on this channel.
+    Interleaved(u8),
+    /// Over UDP to this port of the client.
+    Udp(u16),
+}
+
+/// The first unicast transport in a `Transport` header this server offers.
+fn parse_transport(header: &str) -> Option<Transport> {
+    fn first<T: FromStr>(range: &str) -> Option<T> {
+        range.split('-').next()?.parse().ok()
+    }
+    header.split(',').find_map(|spec| {
+        let mut params = spec.split(';').map(str::trim);
+        let protocol = params.next()?;
+        let params: Vec<&str> = params.collect();
+        if params.contains(&"multicast") {
+            return None;
+        }
+        let value = |key| params.iter().find_map(|p| p.strip_prefix(key)?.strip_prefix('='));
+        match protocol {
+            "RTP/AVP/TCP" => {
+                let channel = value("interleaved").map_or(Some(0), first)?;
+                Some(Transport::Interleaved(channel))
+            }
+            "RTP/AVP" | "RTP/AVP/UDP" => value("client_port").and_then(first).map(Transport::Udp),
+            _ => None,
+        }
+    })
+}
+
+/// Where a session's RTP packets go.
+#[derive(Clone)]
+enum Output {
+    /// On the RTSP connection, on this channel.
+    Interleaved(Arc<Mutex<OwnedWriteHalf>>, u8),
+    Udp(Arc<UdpSocket>, SocketAddr),
+}
+
+impl Output {
+    /// Send a frame's packets; returns the bytes sent.
+    async fn send(&self, packets: &[Vec<u8>]) -> io::Result<usize> {
+        match self {
+            Output::Interleaved(writer, channel) => {
+                let mut framed = Vec::with_capacity(packets.iter().map(|p| p.len() + 4).sum());
+                for packet in packets {
+                    framed.extend_from_slice(&[b'$', *channel]);
+                    framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                    framed.extend_from_slice(packet);
+                }
+                writer.lock().await.write_all(&framed).await?;
+                Ok(framed.len())
+            }
+            Output::Udp(socket, destination) => {
+                for packet in packets {
+                    socket.send_to(packet, destination).await?;
+                }
+                Ok(packets.iter().map(Vec::len).sum())
+            }
+        }
+    }
+}
+
+/// What RTP/JPEG carries of a baseline JPEG; receivers rebuild the rest, with
+/// the standard Huffman tables, which is what the encoder uses.
+#[derive(Debug)]
+struct Jpeg<'a> {
+    /// 0 for 4:2:2 chroma, 1 for 4:2:0.
+    kind: u8,
+    width: usize,
+    height: usize,
+    /// The luma and chroma quantization tables, in zigzag order.
+    tables: Vec<u8>,
+    restart_interval: u16,
+    /// The entropy-coded scan.
+    scan: &'a [u8],
+}
+
+impl<'a> Jpeg<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return Err("not a JPEG");
+        }
+        let mut tables: [Option<&[u8]>; 4] = [None; 4];
+        let mut frame = None;
+        let mut restart_interval = 0;
+        let mut pos = 2;
+        let scan_start = loop {
+            while data.get(pos..pos + 2) == Some(&[0xFF, 0xFF]) {
+                pos += 1;
+            }
+            let (Some(0xFF), Some(&marker)) = (data.get(pos), data.get(pos + 1)) else {
+                return Err("truncated JPEG");
+            };
+            let len = data.get(pos + 2..pos + 4).ok_or("truncated JPEG")?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let segment = data.get(pos + 4..pos + 2 + len).ok_or("truncated JPEG")?;
+            match marker {
+                0xDB => {
+                    let mut rest = segment;
+                    while let Some((&id, after)) = rest.split_first() {
+                        if id >> 4 != 0 {
+                            return Err("16-bit quantization tables");
+                        }
+                        let table = after.get(..64).ok_or("truncated JPEG")?;
+                        tables[usize::from(id & 3)] = Some(table);
+                        rest = &after[64..];
+                    }
+                }
+                0xC0 => frame = Some(segment),
+                0xC1..=0xCF if ![0xC4, 0xC8, 0xCC].contains(&marker) => {
+                    return Err("not a baseline JPEG");
+                }
+                0xDD => {
+                    let interval = segment.get(..2).ok_or("truncated JPEG")?;
+                    restart_interval = u16::from_be_bytes([interval[0], interval[1]]);
+                }
+                0xDA => break pos + 2 + len,
+                _ => {}
+            }
+            pos += 2 + len;
+        };
+        // Precision, height, width, then id, sampling and table of 3 components.
+        let frame = frame.ok_or("no frame header")?;
+        let components = frame.get(6..15).filter(|_| frame[5] == 3).ok_or("not YCbCr")?;
+        let kind = match components[1] {
+            0x21 => 0,
+            0x22 => 1,
+            _ => return Err("unsupported chroma subsampling"),
+        };
+        if components[4] != 0x11 || components[7] != 0x11 || components[5] != components[8] {
+            return Err("unsupported chroma subsampling");
+        }
+        let table = |id: u8| tables[usize::from(id & 3)].ok_or("missing quantization table");
+        let tables = [table(components[2])?, table(components[5])?].concat();
+        let end = if data.ends_with(&[0xFF, 0xD9]) { data.len() - 2 } else { data.len() };
+        Ok(Self {
+            kind,
+            height: usize::from(u16::from_be_bytes([frame[1], frame[2]])),
+            width: usize::from(u16::from_be_bytes([frame[3], frame[4]])),
+            tables,
+            restart_interval,
+            scan: data.get(scan_start..end).ok_or("truncated JPEG")?,
+        })
+    }
+}
+
+/// Splits one stream's JPEGs into RTP/JPEG packets.
+#[derive(Clone)]
+struct Packetizer {
+    ssrc: u32,
+    /// The next packet's sequence number.
+    seq: u16,
+}
+
+impl Packetizer {
+    /// `jpeg`'s packets, all stamped `timestamp`, the last one marked. The
+    /// first carries the quantization tables (Q 255).
+    fn packets(&mut self, jpeg: &Jpeg, timestamp: u32) -> Vec<Vec<u8>> {
+        let restart = jpeg.restart_interval > 0;
+        let kind = jpeg.kind + if restart { 64 } else { 0 };
+        let blocks = |pixels: usize| pixels.div_ceil(8) as u8;
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut packet = Vec::with_capacity(MAX_PACKET);
+            packet.extend_from_slice(&[0x80, PAYLOAD_TYPE]);
+            packet.extend_from_slice(&self.seq.to_be_bytes());
+            packet.extend_from_slice(&timestamp.to_be_bytes());
+            packet.extend_from_slice(&self.ssrc.to_be_bytes());
+            packet.push(0);
+            packet.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+            packet.extend_from_slice(&[kind, 255, blocks(jpeg.width), blocks(jpeg.height)]);
+            if restart {
+                // Not aligned to restart intervals: F and L set, count all ones.
+                packet.extend_from_slice(&jpeg.restart_interval.to_be_bytes());
+                packet.extend_from_slice(&[0xFF, 0xFF]);
+            }
+            if offset == 0 {
+                packet.extend_from_slice(&[0, 0]);
+                packet.extend_from_slice(&(jpeg.tables.len() as u16).to_be_bytes());
+                packet.extend_from_slice(&jpeg.tables);
+            }
+            let end = (offset + MAX_PACKET - packet.len()).min(jpeg.scan.len());
+            packet.extend_from_slice(&jpeg.scan[offset..end]);
+            self.seq = self.seq.wrapping_add(1);
+            offset = end;
+            if offset == jpeg.scan.len() {
+                packet[1] |= 0x80;
+                packets.push(packet);
+                return packets;
+            }
+            packets.push(packet);
+        }
+    }
+}
+
+/// The request's token: `?token=`, a bearer token, or the password of Basic
+/// auth, which is what NVRs send.
+fn request_token(request: &Request, query: Option<&str>) -> Option<String> {
+    let from_query = query.into_iter().flat_map(|query| query.split('&'));
+    let from_query = from_query.filter_map(|pair| pair.strip_prefix("token=")).next();
+    if let Some(token) = from_query {
+        return Some(percent_decode(token));
+    }
+    let authorization = request.header("Authorization")?;
+    if let Some(token) = authorization.strip_prefix("Bearer ") {
+        return Some(token.trim().to_string());
+    }
+    let credentials = authorization.strip_prefix("Basic ")?.trim();
+    let credentials = BASE64_STANDARD.decode(credentials).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+    Some(password.to_string())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpegs_split_into_rtp_jpeg_packets() {
+        let (width, height) = (320, 240);
+        let pixels: Vec<u8> = (0..width * height * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let image = turbojpeg::Image {
+            pixels: pixels.as_slice(),
+            width,
+            pitch: width * 3,
+            height,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+        let data = turbojpeg::compress(image, 90, turbojpeg::Subsamp::Sub2x2).unwrap();
+        let jpeg = Jpeg::parse(&data).unwrap();
+        assert_eq!((jpeg.kind, jpeg.width, jpeg.height), (1, 320, 240));
+        assert_eq!(jpeg.tables.len(), 128);
+        assert!(jpeg.scan.len() > MAX_PACKET, "spans several packets");
+
+        let mut packetizer = Packetizer { ssrc: 7, seq: u16::MAX };
+        let packets = packetizer.packets(&jpeg, 9000);
+        let mut scan = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            assert!(packet.len() <= MAX_PACKET);
+            let last = i == packets.len() - 1;
+            assert_eq!(packet[1], PAYLOAD_TYPE | if last { 0x80 } else { 0 });
+            assert_eq!(&packet[4..12], &[0, 0, 0x23, 0x28, 0, 0, 0, 7]);
+            let offset = u32::from_be_bytes([0, packet[13], packet[14], packet[15]]);
+            assert_eq!(offset as usize, scan.len());
+            assert_eq!(&packet[16..20], &[1, 255, 40, 30]);
+            let payload = if i == 0 { &packet[20 + 4 + 128..] } else { &packet[20..] };
+            scan.extend_from_slice(payload);
+        }
+        assert_eq!(scan, jpeg.scan);
+        assert_eq!(&packets[0][20..24], &[0, 0, 0, 128]);
+        // Sequence numbers wrap.
+        assert_eq!(&packets[1][2..4], &[0, 0]);
+        assert_eq!(packetizer.seq, packets.len() as u16 - 1);
+
+        assert_eq!(
+            parse_transport("RTP/AVP/TCP;unicast;interleaved=2-3"),
+            Some(Transport::Interleaved(2))
+        );
+        let udp = "RTP/AVP;multicast,RTP/AVP;unicast;client_port=5000-5001";
+        assert_eq!(parse_transport(udp), Some(Transport::Udp(5000)));
+        assert_eq!(percent_decode("STUDIO%20(Cam%201)"), "STUDIO (Cam 1)");
+    }
+}
@@ -0,0 +1,169 @@
+//! The NDI® runtime, reloaded when it stops working. Updating NDI Tools in place
+//! on Windows swaps the DLL under a running server, after which creating
+//! receivers and finders fails. Rather than every capture thread failing on its
+//! own, a few failures in a row quarantine the runtime: receivers and finders
+//! drop what they made with it, clients are told their sources are
+//! reconnecting, and the runtime is loaded afresh once nothing uses the old one.
+
+use crate::ndi::{self, Capabilities, NdiInstance};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Failures in a row, with no success between them, that quarantine the runtime.
+const QUARANTINE_AFTER: u32 = 3;
+/// How often the reload checks whether the old runtime is still in use.
+const RELEASE_POLL: Duration = Duration::from_millis(100);
+/// How long between warnings that the old runtime is still in use.
+const RELEASE_WARN: Duration = Duration::from_secs(10);
+/// The longest wait between attempts to load the runtime again.
+const RELOAD_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The loaded NDI® runtime, shared by receivers and discovery.
+pub struct NdiRuntime {
+    /// `None` while quarantined.
+    current: RwLock<Option<Arc<NdiInstance>>>,
+    quarantined: AtomicBool,
+    failures: Mutex<Failures>,
+    /// How often the runtime has been reloaded.
+    generation: AtomicU64,
+    /// The current runtime's, or while quarantined the last one's.
+    capabilities: Mutex<Capabilities>,
+}
+
+impl NdiRuntime {
+    /// Users must hold no other clone of `instance`, or a reload waits forever.
+    pub fn new(instance: Arc<NdiInstance>) -> Arc<Self> {
+        Arc::new(Self {
+            capabilities: Mutex::new(instance.capabilities()),
+            current: RwLock::new(Some(instance)),
+            quarantined: AtomicBool::new(false),
+            failures: Mutex::default(),
+            generation: AtomicU64::new(0),
+        })
+    }
+
+    /// The runtime to create instances with, unless it is quarantined.
+    pub fn instance(&self) -> Result<Arc<NdiInstance>, String> {
+        let current = self.current.read().unwrap();
+        current.clone().ok_or_else(|| "NDI runtime is being reloaded".to_string())
+    }
+
+    /// Whether the runtime is quarantined: whatever was made with it should be
+    /// dropped, so it can be reloaded.
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    /// How often the runtime has been reloaded; users made with an older one
+    /// make them afresh.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Which optional parts of the SDK the runtime has.
+    pub fn capabilities(&self) -> Capabilities {
+        *self.capabilities.lock().unwrap()
+    }
+
+    /// Creating something with the runtime worked.
+    pub fn succeeded(&self) {
+        self.failures.lock().unwrap().succeeded();
+    }
+
+    /// Creating something with the runtime failed with `error`; quarantines the
+    /// runtime and starts reloading it after too many failures in a row.
+    pub fn failed(self: &Arc<Self>, error: &str) {
+        if !self.failures.lock().unwrap().failed() || self.quarantined.swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+        error!("NDI runtime keeps failing ({}); quarantining and reloading it", error);
+        let old = self.current.write().unwrap().take();
+        let old = old.as_ref().map_or_else(Weak::new, Arc::downgrade);
+        let runtime = Arc::clone(self);
+        let spawned = thread::Builder::new()
+            .name("ndi-reload".into())
+            .spawn(move || runtime.reload(old));
+        if let Err(e) = spawned {
+            error!("failed to spawn the NDI runtime reload thread: {}", e);
+        }
+    }
+
+    /// Wait for the receivers and finders to let go of `old`, then load the
+    /// runtime until that works.
+    fn reload(&self, old: Weak<NdiInstance>) {
+        let mut warned = Instant::now();
+        while old.strong_count() > 0 {
+            if warned.elapsed() >= RELEASE_WARN {
+                warn!("NDI runtime reload: the old runtime is still in use");
+                warned = Instant::now();
+            }
+            thread::sleep(RELEASE_POLL);
+        }
+        for attempt in 1u32.. {
+            match ndi::load() {
+                Ok(instance) => {
+                    info!("NDI runtime reloaded, version {}", instance.version());
+                    *self.capabilities.lock().unwrap() = instance.capabilities();
+                    *self.current.write().unwrap() = Some(Arc::new(instance));
+                    self.failures.lock().unwrap().succeeded();
+                    self.generation.fetch_add(1, Ordering::SeqCst);
+                    self.quarantined.store(false, Ordering::SeqCst);
+                    return;
+                }
+                Err(e) => {
+                    let delay = reload_delay(attempt);
+                    warn!("reloading the NDI runtime failed ({}), retrying in {:?}", e, delay);
+                    thread::sleep(delay);
+                }
+            }
+        }
+    }
+}
+
+/// The wait after the `attempt`th failed reload: doubling from a second.
+fn reload_delay(attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(1).min(16);
+    Duration::from_secs(1).saturating_mul(1 << doublings).min(RELOAD_MAX_DELAY)
+}
+
+/// Counts failures in a row.
+#[derive(Default)]
+struct Failures {
+    in_a_row: u32,
+}
+
+impl Failures {
+    fn succeeded(&mut self) {
+        self.in_a_row = 0;
+    }
+
+    /// Whether this failure is the one that quarantines the runtime.
+    fn failed(&mut self) -> bool {
+        self.in_a_row += 1;
+        self.in_a_row == QUARANTINE_AFTER
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_failures_in_a_row_quarantine() {
+        let mut failures = Failures::default();
+        assert!(!failures.failed());
+        assert!(!failures.failed());
+        failures.succeeded();
+        assert!(!failures.failed());
+        assert!(!failures.failed());
+        assert!(failures.failed());
+        // Only once: the reload resets the count.
+        assert!(!failures.failed());
+        assert_eq!(reload_delay(1), Duration::from_secs(1));
+        assert_eq!(reload_delay(9), RELOAD_MAX_DELAY);
+    }
+}
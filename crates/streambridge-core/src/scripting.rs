@@ -0,0 +1,223 @@
+//! Lua hooks: a `--script` file whose handlers run when events are published
+//! and act on them through the same commands as `POST /api/commands`, or tell
+//! other systems with a webhook.
+//!
+//! ```lua
+//! streambridge.on("motion_started", function(event)
+//!   streambridge.command({ command = "start_record", source = event.source, format = "avi" })
+//! end)
+//! ```
+
+use crate::auth::Denial;
+use crate::automation;
+use crate::commands::{Command, CommandRunner};
+use crate::events::{Event, EventBus};
+use bytes::Bytes;
+use hyper::Method;
+use mlua::{Function, Lua, LuaSerdeExt, Table, Value};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// The registry table of handlers registered with `streambridge.on`, by event kind.
+const HANDLERS: &str = "streambridge.handlers";
+
+/// A script, run once to register its handlers.
+pub struct Script {
+    lua: Lua,
+    name: String,
+}
+
+/// What handlers reach the bridge through.
+struct Hooks {
+    commands: Arc<CommandRunner>,
+    runtime: Handle,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let code = std::fs::read_to_string(path)
+            .map_err(|e| format!("can't read script {}: {e}", path.display()))?;
+        Self::new(&code, &path.display().to_string())
+    }
+
+    /// Run `code`, called `name` in messages, to register its handlers.
+    pub fn new(code: &str, name: &str) -> Result<Self, String> {
+        let lua = Lua::new();
+        register(&lua).map_err(|e| format!("script {name}: {e}"))?;
+        lua.load(code).set_name(name).exec().map_err(|e| format!("script {name}: {e}"))?;
+        Ok(Self { lua, name: name.to_string() })
+    }
+
+    /// Call the handlers for `event`'s kind, in the order they were registered.
+    fn handle(&self, event: &Event) -> mlua::Result<()> {
+        let handlers: Table = self.lua.named_registry_value(HANDLERS)?;
+        let Some(list) = handlers.get::<_, Option<Table>>(event.kind())? else {
+            return Ok(());
+        };
+        let table = event_table(&self.lua, event)?;
+        for handler in list.sequence_values::<Function>() {
+            if let Err(e) = handler?.call::<_, ()>(table.clone()) {
+                warn!("script {}: {} handler failed: {}", self.name, event.kind(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Call `script`'s handlers for every event on the bus until it closes. They
+/// run one at a time on a thread of their own, so a slow handler holds up the
+/// next events but never the streams.
+pub fn start(script: Script, events: &EventBus, commands: Arc<CommandRunner>) {
+    script.lua.set_app_data(Hooks { commands, runtime: Handle::current() });
+    let mut rx = events.subscribe();
+    info!("script {} loaded", script.name);
+    let thread = std::thread::Builder::new().name("script".to_string()).spawn(move || loop {
+        match rx.blocking_recv() {
+            Ok(event) => {
+                if let Err(e) = script.handle(&event) {
+                    warn!("script {}: {}", script.name, e);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("script {} missed {} events", script.name, n);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    });
+    if let Err(e) = thread {
+        error!("failed to start the script thread: {}", e);
+    }
+}
+
+/// Add the `streambridge` table scripts call into.
+fn register(lua: &Lua) -> mlua::Result<()> {
+    lua.set_named_registry_value(HANDLERS, lua.create_table()?)?;
+    let api = lua.create_table()?;
+
+    // streambridge.on(kind, handler): call handler(event) for each event of that kind.
+    let on = lua.create_function(|lua, (kind, handler): (String, Function)| {
+        if !Event::KINDS.contains(&kind.as_str()) {
+            let kinds = Event::KINDS.join(", ");
+            let message = format!("unknown event \"{kind}\" (expected one of {kinds})");
+            return Err(mlua::Error::RuntimeError(message));
+        }
+        let handlers: Table = lua.named_registry_value(HANDLERS)?;
+        let list = match handlers.get::<_, Option<Table>>(kind.as_str())? {
+            Some(list) => list,
+            None => {
+                let list = lua.create_table()?;
+                handlers.set(kind.as_str(), list.clone())?;
+                list
+            }
+        };
+        list.push(handler)
+    })?;
+    api.set("on", on)?;
+
+    // streambridge.command(command): run a `POST /api/commands` body and return
+    // the response's, e.g. { ok = false, error = "source not found" }.
+    let command = lua.create_function(|lua, command: Value| {
+        let command: Command = lua.from_value(command)?;
+        let hooks = hooks(lua)?;
+        let outcome = hooks.runtime.block_on(hooks.commands.execute(&command));
+        let body: serde_json::Value =
+            serde_json::from_str(&outcome.body).map_err(mlua::Error::external)?;
+        lua.to_value(&body)
+    })?;
+    api.set("command", command)?;
+
+    // streambridge.webhook(url, body): POST body as JSON to a plain http:// URL;
+    // returns true, or false and what went wrong.
+    let webhook = lua.create_function(|lua, (url, body): (String, Value)| {
+        if !url.starts_with("http://") {
+            let message = format!("webhook URL must start with http://, got \"{url}\"");
+            return Ok((false, Some(message)));
+        }
+        let body: serde_json::Value = lua.from_value(body)?;
+        let hooks = hooks(lua)?;
+        let body = Bytes::from(body.to_string());
+        let sent = automation::send(Method::POST, &url, "application/json", body);
+        match hooks.runtime.block_on(sent) {
+            Ok(()) => Ok((true, None)),
+            Err(e) => Ok((false, Some(e))),
+        }
+    })?;
+    api.set("webhook", webhook)?;
+
+    let log = lua.create_function(|_, message: String| {
+        info!("script: {}", message);
+        Ok(())
+    })?;
+    api.set("log", log)?;
+
+    lua.globals().set("streambridge", api)
+}
+
+fn hooks(lua: &Lua) -> mlua::Result<mlua::AppDataRef<'_, Hooks>> {
+    lua.app_data_ref::<Hooks>().ok_or_else(|| {
+        mlua::Error::RuntimeError("the bridge can only be called from handlers".to_string())
+    })
+}
+
+/// `event` as handlers get it: its `kind`, `source` and log `message`, and the
+/// fields of its kind, like a motion event's `score`.
+fn event_table<'lua>(lua: &'lua Lua, event: &Event) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("kind", event.kind())?;
+    table.set("source", event.source())?;
+    table.set("message", event.to_string())?;
+    match event {
+        Event::LoudnessOutOfSpec { integrated, target, .. } => {
+            table.set("integrated", *integrated)?;
+            table.set("target", *target)?;
+        }
+        Event::LoudnessInSpec { integrated, .. } => table.set("integrated", *integrated)?,
+        Event::AccessDenied { action, reason, .. } => {
+            table.set("action", action.to_string())?;
+            let reason = match reason {
+                Denial::Unauthenticated => "unauthenticated",
+                Denial::Forbidden => "forbidden",
+            };
+            table.set("reason", reason)?;
+        }
+        Event::Webhook { name, .. } => table.set("name", name.as_str())?,
+        Event::ReceiverReconnecting { attempt, .. } => table.set("attempt", *attempt)?,
+        Event::MotionStarted { score, .. } => table.set("score", *score)?,
+        Event::SourceAdded { .. }
+        | Event::SourceRemoved { .. }
+        | Event::ReceiverReconnected { .. }
+        | Event::MotionStopped { .. } => {}
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handlers_get_events_of_their_kind() {
+        let code = r#"
+            seen = {}
+            streambridge.on("motion_started", function(event)
+                table.insert(seen, event.source .. " " .. event.kind .. " " .. event.score)
+            end)
+            streambridge.on("webhook", function(event) table.insert(seen, event.name) end)
+        "#;
+        let script = Script::new(code, "hooks.lua").unwrap();
+        script.handle(&Event::MotionStarted { source: "CAM".into(), score: 0.5 }).unwrap();
+        script.handle(&Event::MotionStopped { source: "CAM".into() }).unwrap();
+        script.handle(&Event::Webhook { name: "goal".into(), source: "CAM".into() }).unwrap();
+        let seen: Vec<String> = script.lua.globals().get("seen").unwrap();
+        assert_eq!(seen, ["CAM motion_started 0.5", "goal"]);
+
+        let error = Script::new("streambridge.on('motion', print)", "hooks.lua").err().unwrap();
+        assert!(error.contains("unknown event \"motion\""), "{error}");
+        let command = "streambridge.command({ command = 'stop_record', source = 'CAM' })";
+        let error = Script::new(command, "hooks.lua").err().unwrap();
+        assert!(error.contains("only be called from handlers"), "{error}");
+    }
+}
@@ -0,0 +1,3704 @@
+use crate::adaptive::AdaptiveRate;
+use crate::annotations::{AnnotationHub, Annotations};
+use crate::audio::{self, wav_header, AudioChunk};
+use crate::auth::{token_hint, AccessPolicy, Action, Denial};
+use crate::automation::sanitize;
+use crate::chaos::{Chaos, ChaosParams};
+use crate::clients::{new_client_id, valid_client_id, ClientHandle, ClientRegistry, ClientStats};
+use crate::commands::{Attempt, CommandRequest, CommandRunner, Outcome};
+use crate::composite::MosaicConfig;
+use crate::config::Tuning;
+use crate::discovery::{self, SourceGroups, SourceList};
+use crate::events::{Event, EventBus};
+use crate::favorites::{Favorites, FavoritesState};
+use crate::homeassistant;
+use crate::idle::{Activity, StreamGuard};
+use crate::latest;
+use crate::levels::Levels;
+use crate::limits::{Limits, Slot};
+use crate::logs::{LogBuffer, LogQuery};
+use crate::loudness::LoudnessReading;
+use crate::onvif::{self, Camera, SoapRequest};
+use crate::openapi;
+use crate::ndi::Tally;
+use crate::encode::{self, OutputFormat};
+use crate::pipeline::Variant;
+use crate::recording::{Format, RecordingFile};
+use crate::snapshots::{self, Schedule, ScheduleRequest, Snapshots};
+use crate::srt::{self, SrtConfig, SrtOutputs};
+use crate::receiver::{
+    Bandwidth, Control, FrameInfo, JpegFrame, Link, PtzCommand, ReceiverManager, SharedReceiver,
+};
+use crate::stats::{SourceStats, StageTiming};
+use crate::test_page::TEST_PAGE_HTML;
+use crate::watermark::Watermarker;
+use crate::whep::{self, WhepSessions};
+use axum::body::Body;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::extract::{ConnectInfo, Path, Query, Request, State, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Router};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, watch, Notify};
+use tower_http::cors::{Any, CorsLayer};
+use tracing::{debug, info, warn};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub sources: SourceList,
+    pub receiver_manager: Arc<ReceiverManager>,
+    /// Honour fault-injection query parameters on `/ws`.
+    pub chaos: bool,
+    /// Which tokens may do what with which sources.
+    pub access: Arc<AccessPolicy>,
+    /// Where access denials are reported.
+    pub events: Arc<EventBus>,
+    /// `--public-readonly`: no control or admin endpoints and no source URLs.
+    pub public_readonly: bool,
+    /// Mark each identified viewer's frames with their token label.
+    pub watermark: bool,
+    /// Runs `POST /api/commands`.
+    pub commands: Arc<CommandRunner>,
+    /// Connected streaming clients, for `GET /api/clients`.
+    pub clients: Arc<ClientRegistry>,
+    /// Give viewers persistent ids in a cookie, so their reconnections group.
+    pub client_ids: bool,
+    /// Requests and open streams, for `--idle-after`.
+    pub activity: Arc<Activity>,
+    /// Recent log events, for `GET /admin/logs`, if the embedder keeps them.
+    pub logs: Option<LogBuffer>,
+    /// Starred sources and their order, for `/sources`.
+    pub favorites: Arc<Favorites>,
+    /// The NDI® groups discovered sources are in, for `/sources?groups=`.
+    pub source_groups: SourceGroups,
+    /// Asks discovery for a pass, for `POST /api/discovery/refresh`.
+    pub discovery_refresh: Arc<discovery::Refresh>,
+    /// Caps on streaming clients.
+    pub limits: Arc<Limits>,
+    /// Annotations posted for viewers to draw.
+    pub annotations: Arc<AnnotationHub>,
+    /// Snapshots taken on a schedule.
+    pub snapshots: Arc<Snapshots>,
+    /// Sources pushed to remote receivers over SRT.
+    pub srt: Arc<SrtOutputs>,
+    /// Source names by the aliases that stand in for them.
+    pub aliases: Arc<BTreeMap<String, String>>,
+    /// The port RTSP is served on, if it is, for ONVIF stream URIs.
+    pub rtsp_port: Option<u16>,
+    /// Sources played over WebRTC, for `/webrtc`.
+    pub whep: Arc<WhepSessions>,
+}
+
+impl AppState {
+    /// The name of the source `reference` refers to by alias, name or id;
+    /// `reference` itself when there is none, so it is reported as not found.
+    pub(crate) fn source_name(&self, reference: String) -> String {
+        if let Some(name) = self.aliases.get(&reference) {
+            return name.clone();
+        }
+        discovery::resolve(&self.sources.read().unwrap(), &reference).unwrap_or(reference)
+    }
+
+    /// The first alias of the source `name`, if it has one.
+    fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.iter().find(|(_, target)| *target == name).map(|(alias, _)| alias.as_str())
+    }
+}
+
+/// A connection's watermarker, shared with the blocking pool while it works.
+type Marker = Option<Arc<Mutex<Watermarker>>>;
+
+/// The watermarker for a viewer of `source`; `None` when watermarking is off or
+/// the viewer has no token. Marks are drawn into JPEGs, so marked viewers can't
+/// have frames in another `format`.
+fn viewer_marker(
+    state: &AppState,
+    token: Option<&str>,
+    source: &str,
+    format: OutputFormat,
+) -> Result<Marker, (StatusCode, &'static str)> {
+    if !state.watermark {
+        return Ok(None);
+    }
+    let Some(label) = state.access.viewer_label(token) else {
+        return Ok(None);
+    };
+    if format != OutputFormat::Jpeg {
+        return Err((StatusCode::BAD_REQUEST, "watermarked frames are JPEG only"));
+    }
+    let quality = state.receiver_manager.encode_settings(source).jpeg_quality;
+    match Watermarker::new(&label, quality) {
+        Ok(marker) => Ok(Some(Arc::new(Mutex::new(marker)))),
+        Err(e) => {
+            warn!("watermark: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "watermarking unavailable"))
+        }
+    }
+}
+
+/// Watermark a frame off the async runtime, timed in `stats`. `None` if marking
+/// failed, in which case the frame must be withheld rather than sent unmarked.
+async fn mark(marker: &Marker, jpeg: Bytes, stats: &Arc<SourceStats>) -> Option<Bytes> {
+    let Some(marker) = marker.clone() else {
+        return Some(jpeg);
+    };
+    let stats = Arc::clone(stats);
+    let marked = tokio::task::spawn_blocking(move || {
+        let started = Instant::now();
+        let marked = marker.lock().unwrap().apply(&jpeg);
+        stats.stages.overlay.record(started);
+        marked
+    });
+    match marked.await {
+        Ok(Ok(marked)) => Some(marked),
+        Ok(Err(e)) => {
+            warn!("watermark: {}", e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// The optional `?token=` parameter, for clients that can't set headers
+/// (`<img>` tags, browser WebSockets).
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+/// What a WebSocket sends along with each frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// The frames alone.
+    #[default]
+    None,
+    /// A `frame` text message before each, with its number, timing and size.
+    Meta,
+}
+
+/// Which frames a video stream carries.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamMode {
+    /// The source's frames.
+    #[default]
+    Normal,
+    /// The amplified difference between consecutive frames, for diagnostics.
+    Diff,
+}
+
+impl StreamMode {
+    /// The format of this mode's frames: diff images are always JPEGs.
+    fn format(self, variant: Variant) -> OutputFormat {
+        match self {
+            StreamMode::Normal => variant.format,
+            StreamMode::Diff => OutputFormat::Jpeg,
+        }
+    }
+
+    /// Subscribe to this mode's frames. Diff images ignore `variant`.
+    fn subscribe(
+        self,
+        shared: &SharedReceiver,
+        variant: Variant,
+    ) -> latest::Receiver<JpegFrame> {
+        match self {
+            StreamMode::Normal => shared.subscribe_variant(variant),
+            StreamMode::Diff => shared.subscribe_diff(),
+        }
+    }
+}
+
+/// A client's `quality`, `fps` and `width` parameters, where 0 means no limit,
+/// and its `format`.
+fn variant(
+    quality: Option<i32>,
+    fps: Option<u32>,
+    width: Option<usize>,
+    format: OutputFormat,
+) -> Variant {
+    Variant {
+        quality: quality.filter(|&q| q > 0),
+        max_fps: fps.filter(|&f| f > 0),
+        max_width: width.filter(|&w| w > 0),
+        format,
+    }
+}
+
+/// A client's `bandwidth` parameter. Public read-only servers only serve the
+/// senders' low-bandwidth streams.
+fn bandwidth(state: &AppState, requested: Option<Bandwidth>) -> Option<Bandwidth> {
+    requested.filter(|_| !state.public_readonly)
+}
+
+/// The cookie persistent client ids are kept in.
+const CLIENT_COOKIE: &str = "streambridge_client";
+
+/// The viewer's persistent id with `--client-ids`: `?client_id=`, else the
+/// cookie, else a new one along with the `Set-Cookie` header that keeps it.
+fn client_id(
+    state: &AppState,
+    headers: &HeaderMap,
+    requested: &Option<String>,
+) -> (Option<String>, Option<HeaderValue>) {
+    if !state.client_ids {
+        return (None, None);
+    }
+    let cookie = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find_map(|(name, value)| (name == CLIENT_COOKIE).then_some(value));
+    if let Some(id) = requested.as_deref().or(cookie).filter(|id| valid_client_id(id)) {
+        return (Some(id.to_string()), None);
+    }
+    let id = new_client_id();
+    let cookie = format!("{CLIENT_COOKIE}={id}; Path=/; Max-Age=31536000; SameSite=Lax; HttpOnly");
+    (Some(id), HeaderValue::from_str(&cookie).ok())
+}
+
+/// `response`, setting a new client id's cookie if there is one.
+fn with_cookie(mut response: Response, cookie: Option<HeaderValue>) -> Response {
+    if let Some(cookie) = cookie {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    #[param(ignore)]
+    token: Option<String>,
+    /// What frames the stream carries.
+    #[serde(default)]
+    #[param(inline)]
+    mode: StreamMode,
+    /// Lower JPEG quality for this client, 1-100.
+    quality: Option<i32>,
+    /// Lower frame rate for this client.
+    fps: Option<u32>,
+    /// Lower width for this client.
+    width: Option<usize>,
+    #[serde(default)]
+    #[param(inline)]
+    format: OutputFormat,
+    #[param(inline)]
+    bandwidth: Option<Bandwidth>,
+    /// The viewer's persistent id, for clients that can't keep cookies.
+    client_id: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MosaicQuery {
+    /// The sources to tile, comma separated, in reading order.
+    sources: String,
+    /// `COLUMNSxROWS`; by default the squarest grid that fits the sources.
+    layout: Option<String>,
+}
+
+/// The mosaic of `sources`, comma separated names, aliases or ids, in `layout`,
+/// once `token` may view each of them.
+fn mosaic_config(
+    state: &AppState,
+    token: Option<&str>,
+    sources: &str,
+    layout: Option<&str>,
+) -> Result<MosaicConfig, (StatusCode, &'static str)> {
+    let names = sources.split(',').map(|name| state.source_name(name.trim().to_string()));
+    let mosaic = MosaicConfig::new(names.collect(), layout).map_err(|e| {
+        warn!("mosaic: {}", e);
+        (StatusCode::BAD_REQUEST, "bad mosaic")
+    })?;
+    for name in &mosaic.sources {
+        authorize(state, token, name, Action::View)?;
+        if !state.sources.read().unwrap().iter().any(|s| &s.name == name) {
+            warn!("mosaic: source not found: \"{}\"", name);
+            return Err((StatusCode::NOT_FOUND, "source not found"));
+        }
+    }
+    Ok(mosaic)
+}
+
+/// The request's access token: an `Authorization: Bearer` header, else `?token=`.
+fn request_token<'a>(headers: &'a HeaderMap, query: &'a Option<String>) -> Option<&'a str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or(query.as_deref())
+}
+
+/// The client's IP address, when the server was started by [`serve`]; behind
+/// a reverse proxy, the proxy's.
+fn peer_ip(peer: Option<Extension<ConnectInfo<SocketAddr>>>) -> Option<IpAddr> {
+    peer.map(|Extension(ConnectInfo(addr))| addr.ip())
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers.get(header::USER_AGENT)?.to_str().ok().map(str::to_string)
+}
+
+/// Check `action` on `source` against the access policy, reporting denials on the
+/// event bus.
+pub(crate) fn authorize(
+    state: &AppState,
+    token: Option<&str>,
+    source: &str,
+    action: Action,
+) -> Result<(), (StatusCode, &'static str)> {
+    let controls = matches!(action, Action::Ptz | Action::Record | Action::Control);
+    if state.public_readonly && controls {
+        return Err((StatusCode::FORBIDDEN, "read-only server"));
+    }
+    state.access.check(token, source, action).map_err(|reason| {
+        state.events.publish(Event::AccessDenied {
+            source: source.to_string(),
+            action,
+            reason,
+            token: token_hint(token),
+        });
+        match reason {
+            Denial::Unauthenticated => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            Denial::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+        }
+    })
+}
+
+pub fn create_router(state: AppState) -> Router {
+    let cors = CorsLayer::new().allow_origin(Any);
+
+    // The OpenAPI document lists the routes as they are added, from the
+    // handlers' `#[utoipa::path]` attributes.
+    let mut router = OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .routes(routes!(get_sources))
+        .routes(routes!(get_source_detail))
+        .routes(routes!(mjpeg_stream))
+        .routes(routes!(mosaic_stream))
+        .routes(routes!(snapshot))
+        .routes(routes!(audio_stream))
+        .routes(routes!(post_webrtc))
+        .routes(routes!(delete_webrtc))
+        .routes(routes!(get_levels))
+        .routes(routes!(metadata_stream))
+        .routes(routes!(source_events))
+        .routes(routes!(get_homeassistant));
+    if !state.public_readonly {
+        router = router
+            .routes(routes!(get_stats))
+            .routes(routes!(get_clients))
+            .routes(routes!(get_pipeline))
+            .routes(routes!(post_discovery_refresh))
+            .routes(routes!(get_favorites, patch_favorites))
+            .routes(routes!(put_favorite, delete_favorite))
+            .routes(routes!(post_hook))
+            .routes(routes!(post_command))
+            .routes(routes!(post_annotations))
+            .routes(routes!(get_schedules, post_schedule))
+            .routes(routes!(get_schedule, delete_schedule))
+            .routes(routes!(get_snapshots))
+            .routes(routes!(get_snapshot_image))
+            .routes(routes!(post_ptz))
+            .routes(routes!(post_tally))
+            .routes(routes!(post_record))
+            .routes(routes!(get_recordings))
+            .routes(routes!(get_recording))
+            .routes(routes!(get_preroll))
+            .routes(routes!(get_receivers))
+            .routes(routes!(patch_receiver, delete_receiver))
+            .routes(routes!(post_restart_receiver))
+            .routes(routes!(get_receiver_clients))
+            .routes(routes!(get_srt_outputs))
+            .routes(routes!(put_srt_output, delete_srt_output))
+            .routes(routes!(get_logs));
+    }
+    // Everything above needs a known token once there are grants. The page asks
+    // for one, health checks carry none, browsers only see WebSocket denials
+    // as close codes, and ONVIF clients send credentials in the SOAP request,
+    // so those routes check for themselves.
+    // Health checks don't count as use, or an idle server would never idle.
+    let (router, spec) = router
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .routes(routes!(ws_handler))
+        .routes(routes!(onvif_service))
+        .route("/", get(test_page))
+        .route_layer(middleware::from_fn_with_state(state.clone(), wake))
+        .routes(routes!(healthz))
+        .split_for_parts();
+    let docs = Router::from(openapi::docs(spec))
+        .route_layer(middleware::from_fn_with_state(state.clone(), wake));
+    router.merge(docs).layer(cors).with_state(state)
+}
+
+/// Counts the request as use, waking an idle server.
+async fn wake(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    state.activity.touch();
+    next.run(request).await
+}
+
+/// Turns away requests whose token matches no grant. Handlers still check what
+/// the token may do with each source.
+async fn require_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    if state.access.authenticate(token).is_err() {
+        let challenge = [(header::WWW_AUTHENTICATE, "Bearer")];
+        return (StatusCode::UNAUTHORIZED, challenge, "unauthorized").into_response();
+    }
+    next.run(request).await
+}
+
+/// How long open connections get to finish once shutdown has started.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Serve `state` on `listener` until `signal` resolves to a drain time.
+///
+/// For a non-zero drain time the server first drains: `/healthz` turns 503, new
+/// clients are refused, streams end and WebSocket clients get a 1012 ("server
+/// restarting") close frame, and it waits for clients to leave or the drain time
+/// to pass. Then every receiver is stopped, so any remaining streams end and
+/// WebSocket clients get a 1001 close frame, and open connections get
+/// [`SHUTDOWN_GRACE`] to finish.
+pub async fn serve(
+    listener: TcpListener,
+    state: AppState,
+    signal: impl Future<Output = Duration> + Send + 'static,
+) -> std::io::Result<()> {
+    let manager = state.receiver_manager.clone();
+    let stopping = Arc::new(Notify::new());
+    let notify = stopping.clone();
+    let app = create_router(state).into_make_service_with_connect_info::<SocketAddr>();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        let drain = signal.await;
+        if !drain.is_zero() {
+            info!("draining: refusing new clients for up to {}s", drain.as_secs_f32());
+            manager.drain();
+            let deadline = Instant::now() + drain;
+            while manager.client_count() > 0 && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+        info!("shutting down: closing streams and stopping receivers");
+        manager.shutdown();
+        notify.notify_one();
+    });
+    tokio::select! {
+        result = server => result,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(SHUTDOWN_GRACE).await;
+        } => {
+            warn!("shutdown: gave up waiting for open connections");
+            Ok(())
+        }
+    }
+}
+
+/// For load balancers and container probes: 200 while taking clients, 503 once
+/// draining.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses((status = 200, description = "Taking clients", content_type = "text/plain"))
+)]
+async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    if state.receiver_manager.is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else {
+        (StatusCode::OK, "ok")
+    }
+}
+
+/// Sources visible to the request's token.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SourcesQuery {
+    #[param(ignore)]
+    token: Option<String>,
+    /// List bare names instead of objects, as clients written before the ids
+    /// expect.
+    #[serde(default)]
+    names: bool,
+    /// Comma-separated NDI® groups; only sources found in one are listed.
+    groups: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SourceListJson<'a> {
+    /// Stands in for the name wherever a source is referenced.
+    id: String,
+    name: &'a str,
+    /// Stands in for the name too, when one is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<&'a str>,
+    /// `None` for generated sources and on a `--public-readonly` server.
+    url: Option<&'a str>,
+    /// The latest frame's size and the frame rate, while the source is received.
+    width: Option<usize>,
+    height: Option<usize>,
+    fps: Option<f64>,
+    active_clients: u64,
+    /// `None` until a receiver has connected to the source.
+    ptz_supported: Option<bool>,
+    starred: bool,
+}
+
+/// The sources the token may view, starred ones first in the favorites order.
+#[utoipa::path(
+    get,
+    path = "/sources",
+    params(SourcesQuery),
+    responses(
+        (status = 200, description = "An object per source with its id, name, URL, size, frame \
+        rate and clients; bare names with `names=true`", content_type = "application/json")
+    )
+)]
+async fn get_sources(
+    headers: HeaderMap,
+    Query(query): Query<SourcesQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let sources = state.sources.read().unwrap();
+    let source_groups = state.source_groups.read().unwrap();
+    let wanted: Option<Vec<&str>> =
+        query.groups.as_deref().map(|groups| groups.split(',').map(str::trim).collect());
+    let in_wanted_group = |name: &str| match &wanted {
+        Some(wanted) => source_groups
+            .get(name)
+            .is_some_and(|groups| wanted.iter().any(|group| groups.contains(*group))),
+        None => true,
+    };
+    let mut names: Vec<&str> = sources
+        .iter()
+        .map(|s| s.name.as_str())
+        .filter(|name| in_wanted_group(name))
+        .filter(|name| state.access.check(token, name, Action::View).is_ok())
+        .collect();
+    state.favorites.sort(&mut names);
+    let json = if query.names {
+        serde_json::to_string(&names)
+    } else {
+        let listed: Vec<SourceListJson> = names
+            .into_iter()
+            .map(|name| {
+                let stats = state.receiver_manager.stats(name);
+                let stats = stats.as_deref();
+                let format = stats.and_then(|s| *s.input_format.lock().unwrap());
+                let url = sources.iter().find(|s| s.name == name).and_then(|s| s.url.as_deref());
+                SourceListJson {
+                    id: discovery::source_id(name),
+                    name,
+                    alias: state.alias(name),
+                    url: url.filter(|_| !state.public_readonly),
+                    width: format.map(|(width, _, _)| width),
+                    height: format.map(|(_, height, _)| height),
+                    fps: stats
+                        .and_then(|s| *s.input_fps.lock().unwrap())
+                        .map(|fps| (fps * 10.0).round() / 10.0),
+                    active_clients: stats.map_or(0, |s| s.clients.load(Ordering::Relaxed)),
+                    ptz_supported: stats.and_then(|s| {
+                        s.ndi_status.lock().unwrap().as_ref().map(|status| status.ptz_supported)
+                    }),
+                    starred: state.favorites.is_starred(name),
+                }
+            })
+            .collect();
+        serde_json::to_string(&listed)
+    };
+    let json = json.unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+/// How long `POST /api/discovery/refresh` waits for discovery.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Look for sources right away and list them as `GET /sources` does, once
+/// discovery has looked or 5 seconds have passed.
+#[utoipa::path(
+    post,
+    path = "/api/discovery/refresh",
+    params(SourcesQuery),
+    responses(
+        (status = 200, description = "An object per source with its id, name, URL, size, frame \
+        rate and clients; bare names with `names=true`", content_type = "application/json")
+    )
+)]
+async fn post_discovery_refresh(
+    headers: HeaderMap,
+    query: Query<SourcesQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !state.discovery_refresh.run(REFRESH_TIMEOUT).await {
+        warn!("discovery refresh: no pass within {}s", REFRESH_TIMEOUT.as_secs());
+    }
+    get_sources(headers, query, State(state)).await.into_response()
+}
+
+#[derive(Serialize)]
+struct SourceStatsJson {
+    clients: u64,
+    loudness: Option<LoudnessReading>,
+    /// Whether there's motion now, and how often it started, for sources
+    /// that look for it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motion: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    motion_events: Option<u64>,
+}
+
+/// Frame, encode and client statistics of each active source the token may view.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = 200, description = "Statistics by source", content_type = "application/json")
+    )
+)]
+async fn get_stats(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let stats: BTreeMap<String, SourceStatsJson> = state
+        .receiver_manager
+        .active()
+        .into_iter()
+        .filter(|(_, r)| state.access.check(token, &r.source_name, Action::View).is_ok())
+        .map(|(name, r)| {
+            let s = &r.stats;
+            let detecting = state.receiver_manager.encode_settings(&r.source_name).motion.is_some();
+            let entry = SourceStatsJson {
+                clients: s.clients.load(Ordering::Relaxed),
+                loudness: *s.loudness.lock().unwrap(),
+                motion: detecting.then(|| s.motion.load(Ordering::Relaxed)),
+                motion_events: detecting.then(|| s.motion_events.load(Ordering::Relaxed)),
+            };
+            (name, entry)
+        })
+        .collect();
+    let json = serde_json::to_string(&stats).unwrap_or_else(|_| "{}".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+#[derive(Serialize)]
+struct ClientJson<'a> {
+    id: u64,
+    kind: &'static str,
+    source: &'a str,
+    label: Option<&'a str>,
+    /// The viewer's persistent id, the same across their reconnections.
+    client_id: Option<&'a str>,
+    /// How many times that viewer has connected.
+    visits: Option<u64>,
+    connected_secs: f64,
+    frames: u64,
+    bytes: u64,
+    /// Throughput over the last couple of seconds, by completed writes.
+    kbps: Option<f64>,
+    /// Share of that time spent waiting for writes; near 1 means the client's
+    /// link is full and `kbps` is about what it can take.
+    send_busy: Option<f64>,
+    adaptive_fps: Option<f64>,
+    /// How often the client fell so far behind that frames were skipped, and
+    /// how many were.
+    lags: u64,
+    dropped: u64,
+}
+
+impl<'a> ClientJson<'a> {
+    fn new(state: &AppState, client: &'a ClientStats) -> Self {
+        let (kbps, send_busy) = client.throughput();
+        ClientJson {
+            id: client.id,
+            kind: client.kind,
+            source: &client.source,
+            label: client.label.as_deref(),
+            client_id: client.client_id.as_deref(),
+            visits: client.client_id.as_deref().map(|id| state.clients.visits(id)),
+            connected_secs: client.connected.elapsed().as_secs_f64(),
+            frames: client.frames.load(Ordering::Relaxed),
+            bytes: client.bytes.load(Ordering::Relaxed),
+            kbps,
+            send_busy,
+            adaptive_fps: client.adaptive_fps(),
+            lags: client.lags.load(Ordering::Relaxed),
+            dropped: client.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The connected WebSocket and MJPEG clients of sources the token may view,
+/// with what their links take.
+#[utoipa::path(
+    get,
+    path = "/api/clients",
+    responses((status = 200, description = "The clients", content_type = "application/json"))
+)]
+async fn get_clients(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let clients = state.clients.list();
+    let listed: Vec<ClientJson> = clients
+        .iter()
+        .filter(|c| state.access.check(token, &c.source, Action::View).is_ok())
+        .map(|c| ClientJson::new(&state, c))
+        .collect();
+    let json = serde_json::to_string(&listed).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+#[derive(Serialize)]
+struct PipelineJson {
+    stages: Vec<StageJson>,
+}
+
+/// One stage of a source's pipeline, with the settings in effect for it.
+#[derive(Serialize)]
+struct StageJson {
+    stage: &'static str,
+    /// Frames through the stage since the receiver started; `null` if untimed.
+    frames: Option<u64>,
+    avg_ms: Option<f64>,
+    #[serde(flatten)]
+    settings: serde_json::Value,
+}
+
+impl StageJson {
+    fn untimed(stage: &'static str, settings: serde_json::Value) -> Self {
+        Self { stage, frames: None, avg_ms: None, settings }
+    }
+
+    fn timed(stage: &'static str, timing: &StageTiming, settings: serde_json::Value) -> Self {
+        Self { stage, frames: Some(timing.frames()), avg_ms: timing.avg_ms(), settings }
+    }
+}
+
+/// The stages each active stream's frames go through, in order, so users can
+/// see what their config produced and where the time goes.
+#[utoipa::path(
+    get,
+    path = "/api/pipeline",
+    responses((status = 200, description = "Stages by source", content_type = "application/json"))
+)]
+async fn get_pipeline(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let pipelines: BTreeMap<String, PipelineJson> = state
+        .receiver_manager
+        .active()
+        .into_iter()
+        .filter(|(_, r)| state.access.check(token, &r.source_name, Action::View).is_ok())
+        .map(|(name, shared)| {
+            let stages = pipeline_stages(&state, &shared);
+            (name, PipelineJson { stages })
+        })
+        .collect();
+    let json = serde_json::to_string(&pipelines).unwrap_or_else(|_| "{}".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+fn pipeline_stages(state: &AppState, shared: &SharedReceiver) -> Vec<StageJson> {
+    use serde_json::json;
+
+    let settings = state.receiver_manager.encode_settings(&shared.source_name);
+    let stages = &shared.stats.stages;
+    let outputs = shared.outputs();
+    let input = *shared.stats.input_format.lock().unwrap();
+    let adapted = *shared.stats.adapted.lock().unwrap();
+    let producer = state.receiver_manager.producer(&shared.source_name);
+    let mut pipeline = vec![StageJson::untimed(
+        "capture",
+        json!({
+            "producer": producer,
+            "deinterlace": (producer == "ndi").then(|| settings.deinterlace.name()),
+            "color_format": (producer == "ndi").then(|| settings.color_format.name()),
+            "width": input.map(|(w, _, _)| w),
+            "height": input.map(|(_, h, _)| h),
+            "format": input.map(|(_, _, fourcc)| format!("{fourcc:?}")),
+        }),
+    )];
+    if outputs.recording {
+        pipeline.push(StageJson::timed("record", &stages.record, json!({})));
+    }
+    let factor = input.map_or(1, |(w, h, _)| {
+        encode::scale_factor(w, h, settings.max_width, settings.max_height)
+    });
+    let variant_widths = outputs.variants.iter().any(|(v, _)| v.max_width.is_some());
+    if factor > 1 || variant_widths {
+        let scaled = input.map(|(w, h, _)| (w / factor, h / factor));
+        pipeline.push(StageJson::timed(
+            "scale",
+            &stages.scale,
+            json!({
+                "max_width": settings.max_width,
+                "max_height": settings.max_height,
+                "factor": factor,
+                "width": scaled.map(|(w, _)| w),
+                "height": scaled.map(|(_, h)| h),
+            }),
+        ));
+    }
+    if outputs.diff > 0 {
+        pipeline.push(StageJson::timed("diff", &stages.diff, json!({})));
+    }
+    if let Some(overlay) = state.receiver_manager.overlay(&shared.source_name) {
+        let config = json!({
+            "name": overlay.name,
+            "time": overlay.time,
+            "corner": overlay.corner,
+            "logo": overlay.logo.is_some(),
+            "logo_corner": overlay.logo_corner,
+        });
+        pipeline.push(StageJson::timed("burn_in", &stages.burn_in, config));
+    }
+    if settings.viewer_badge {
+        pipeline.push(StageJson::timed("badge", &stages.badge, json!({ "viewers": true })));
+    }
+    if let Some(motion) = settings.motion {
+        let config = json!({ "threshold": motion.threshold, "hold_secs": motion.hold.as_secs() });
+        pipeline.push(StageJson::timed("motion", &stages.motion, config));
+    }
+    pipeline.push(StageJson::timed(
+        "encode",
+        &stages.encode,
+        json!({
+            "encoder": settings.encoder.name(),
+            "threads": settings.encode_threads,
+            "quality": settings.jpeg_quality,
+            "max_fps": (settings.max_fps > 0).then_some(settings.max_fps),
+            "adaptive": adapted.map(|(quality, fps)| json!({ "quality": quality, "max_fps": fps })),
+        }),
+    ));
+    if state.watermark {
+        pipeline.push(StageJson::timed("overlay", &stages.overlay, json!({ "watermark": true })));
+    }
+    let variants: Vec<_> = outputs
+        .variants
+        .iter()
+        .map(|(v, clients)| {
+            json!({
+                "quality": v.quality,
+                "fps": v.max_fps,
+                "width": v.max_width,
+                "format": v.format,
+                "clients": clients,
+            })
+        })
+        .collect();
+    pipeline.push(StageJson::untimed(
+        "outputs",
+        json!({
+            "video": outputs.jpeg,
+            "variants": variants,
+            "diff": outputs.diff,
+            "audio": outputs.audio,
+            "metadata": outputs.metadata,
+            "recording": outputs.recording,
+        }),
+    ));
+    pipeline
+}
+
+#[derive(Serialize)]
+struct SourceDetailJson<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<&'a str>,
+    url: Option<&'a str>,
+    /// Whether a receiver is currently running for this source.
+    active: bool,
+    clients: u64,
+    /// NDI connection status; `null` until the receiver reports one.
+    ndi: Option<NdiStatusJson>,
+    /// The sender's `<ndi_capabilities>` attributes, if it announced any.
+    capabilities: Option<BTreeMap<String, String>>,
+    /// Tally as the sender last echoed it, combined over all its receivers.
+    tally: Option<TallyJson>,
+}
+
+#[derive(Serialize)]
+struct TallyJson {
+    program: bool,
+    preview: bool,
+}
+
+#[derive(Serialize)]
+struct NdiStatusJson {
+    connections: u32,
+    web_control: Option<String>,
+    ptz_supported: bool,
+}
+
+/// A source's details, as far as known: its address, NDI® connection and tally.
+#[utoipa::path(
+    get,
+    path = "/api/sources/{name}",
+    params(("name" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "The source's details", content_type = "application/json")
+    )
+)]
+async fn get_source_detail(
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let name = state.source_name(name);
+    let token = request_token(&headers, &query.token);
+    if let Err((status, error)) = authorize(&state, token, &name, Action::View) {
+        let json = serde_json::json!({ "error": error }).to_string();
+        return (status, [(header::CONTENT_TYPE, "application/json")], json).into_response();
+    }
+    let source = {
+        let sources = state.sources.read().unwrap();
+        sources.iter().find(|s| s.name == name).cloned()
+    };
+    let Some(source) = source else {
+        return (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "application/json")],
+            r#"{"error":"source not found"}"#,
+        )
+            .into_response();
+    };
+
+    let stats = state.receiver_manager.stats(&name);
+    // Source and camera URLs point into the production network; keep them private.
+    let private = !state.public_readonly;
+    let ndi = stats
+        .as_ref()
+        .and_then(|s| s.ndi_status.lock().unwrap().clone())
+        .map(|status| NdiStatusJson {
+            connections: status.connections,
+            web_control: status.web_control.filter(|_| private),
+            ptz_supported: status.ptz_supported,
+        });
+    let detail = SourceDetailJson {
+        name: &source.name,
+        alias: state.alias(&source.name),
+        url: source.url.as_deref().filter(|_| private),
+        active: stats.is_some(),
+        clients: stats.as_ref().map_or(0, |s| s.clients.load(Ordering::Relaxed)),
+        ndi,
+        capabilities: stats
+            .as_ref()
+            .filter(|_| private)
+            .and_then(|s| s.ndi_capabilities.lock().unwrap().clone()),
+        tally: stats.as_ref().and_then(|s| *s.tally_echo.lock().unwrap()).map(|t| TallyJson {
+            program: t.on_program,
+            preview: t.on_preview,
+        }),
+    };
+    let json = serde_json::to_string(&detail).unwrap_or_else(|_| "{}".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WsQuery {
+    /// The source to stream; `name` is accepted too.
+    #[serde(alias = "name", default)]
+    source: String,
+    /// Sources to tile into one stream instead of `source`, comma separated.
+    mosaic: Option<String>,
+    /// The mosaic's `COLUMNSxROWS`.
+    layout: Option<String>,
+    /// The NDI® sender's `ip:port`, for a source discovery hasn't seen.
+    url: Option<String>,
+    /// With `--chaos`: delay every frame.
+    delay_ms: Option<u64>,
+    /// With `--chaos`: delay frames randomly up to this.
+    jitter_ms: Option<u64>,
+    /// With `--chaos`: drop frames with this probability.
+    drop: Option<f64>,
+    /// With `--chaos`: reorder frames with this probability.
+    reorder: Option<f64>,
+    #[param(ignore)]
+    token: Option<String>,
+    /// Also send the sender's metadata XML, as text messages.
+    #[serde(default)]
+    metadata: bool,
+    /// Also send posted annotations, and each frame's `seq` before it, to
+    /// `streambridge.v1` clients.
+    #[serde(default)]
+    annotations: bool,
+    /// Also send the source's audio levels to `streambridge.v1` clients.
+    #[serde(default)]
+    levels: bool,
+    /// `meta` to precede each frame with its timing and size.
+    #[serde(default)]
+    #[param(inline)]
+    framing: Framing,
+    /// What frames the stream carries.
+    #[serde(default)]
+    #[param(inline)]
+    mode: StreamMode,
+    /// Lower JPEG quality for this client than the source's own, 1-100.
+    quality: Option<i32>,
+    /// Lower frame rate for this client.
+    fps: Option<u32>,
+    /// Lower width for this client.
+    width: Option<usize>,
+    /// The format of this client's frames.
+    #[serde(default)]
+    #[param(inline)]
+    format: OutputFormat,
+    /// Lower the rate while the client's link can't keep up; on by default.
+    adaptive: Option<bool>,
+    /// Which of an NDI® sender's streams to receive.
+    #[param(inline)]
+    bandwidth: Option<Bandwidth>,
+    /// The viewer's persistent id, for clients that can't keep cookies.
+    client_id: Option<String>,
+}
+
+impl WsQuery {
+    fn chaos_params(&self) -> ChaosParams {
+        ChaosParams {
+            delay_ms: self.delay_ms.unwrap_or(0),
+            jitter_ms: self.jitter_ms.unwrap_or(0),
+            drop: self.drop.unwrap_or(0.0).clamp(0.0, 1.0),
+            reorder: self.reorder.unwrap_or(0.0).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Stream a source over a WebSocket, one image per binary message. Clients
+/// offering the `streambridge.v1` subprotocol also get JSON text messages.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    params(WsQuery),
+    responses((status = 101, description = "Switching to the WebSocket"))
+)]
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(mut query): Query<WsQuery>,
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    if query.source.is_empty() && query.mosaic.is_none() {
+        return (StatusCode::BAD_REQUEST, "missing source or mosaic").into_response();
+    }
+    // Browsers can't read a refused handshake's status, so report denials as
+    // close codes like the other failures.
+    let token = request_token(&headers, &query.token);
+    let mosaic = query.mosaic.as_deref().map(|sources| {
+        mosaic_config(&state, token, sources, query.layout.as_deref())
+    });
+    query.source = match &mosaic {
+        Some(Ok(mosaic)) => mosaic.name(),
+        _ => state.source_name(query.source),
+    };
+    let variant = variant(query.quality, query.fps, query.width, query.format);
+    let format = query.mode.format(variant);
+    let viewable = match mosaic {
+        Some(mosaic) => mosaic.map(Some),
+        None => authorize(&state, token, &query.source, Action::View).map(|()| None),
+    };
+    let peer = peer_ip(peer);
+    let admitted = viewable.and_then(|mosaic| {
+        let marker = viewer_marker(&state, token, &query.source, format)?;
+        Ok((mosaic, marker, state.limits.admit(peer, &query.source)?))
+    });
+    let (mosaic, marker, slot) = match admitted {
+        Ok(admitted) => admitted,
+        Err((status, reason)) => {
+            warn!("WS: {} for \"{}\"", reason, query.source);
+            let code = 4000 + status.as_u16();
+            return ws.on_upgrade(move |mut socket| async move {
+                send_close(&mut socket, code, reason).await;
+            });
+        }
+    };
+    let (client_id, cookie) = client_id(&state, &headers, &query.client_id);
+    let params = query.chaos_params();
+    let chaos = if state.chaos && params.is_active() {
+        info!("WS: chaos enabled for \"{}\": {:?}", query.source, params);
+        Some(Chaos::new(params))
+    } else {
+        None
+    };
+    let options = WsOptions {
+        chaos,
+        marker,
+        metadata: query.metadata,
+        annotations: query.annotations,
+        levels: query.levels,
+        framing: query.framing,
+        mode: query.mode,
+        variant,
+        token: token.map(str::to_string),
+        // Chaos delays would read as congestion.
+        adaptive: query.adaptive.unwrap_or(true) && !state.chaos,
+        bandwidth: bandwidth(&state, query.bandwidth),
+        client_id,
+        peer,
+        user_agent: user_agent(&headers),
+        url: query.url,
+        mosaic,
+        slot,
+    };
+    // Clients that offer subprotocols must get one of them; those that offer
+    // none get the original bare format.
+    let offered = headers.contains_key(header::SEC_WEBSOCKET_PROTOCOL);
+    let ws = ws.protocols(WsProtocol::NAMES);
+    let Some(protocol) = WsProtocol::selected(offered, ws.selected_protocol()) else {
+        warn!("WS: no supported protocol offered for \"{}\"", query.source);
+        let supported = WsProtocol::NAMES.join(", ");
+        let reason = format!("unsupported WebSocket protocol; this server speaks {supported}");
+        return (StatusCode::BAD_REQUEST, reason).into_response();
+    };
+    let source_name = query.source;
+    let response =
+        ws.on_upgrade(move |socket| handle_ws(socket, source_name, state, options, protocol));
+    with_cookie(response, cookie)
+}
+
+/// The message format a WebSocket client negotiated with `Sec-WebSocket-Protocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WsProtocol {
+    /// No subprotocol: binary JPEG frames, and metadata XML as bare text.
+    Bare,
+    /// `streambridge.v1`: binary JPEG frames, and JSON text messages with a
+    /// `type`, starting with a `hello` naming the protocol and source.
+    V1,
+}
+
+impl WsProtocol {
+    /// The subprotocols the server speaks, preferred first.
+    const NAMES: [&'static str; 1] = ["streambridge.v1"];
+
+    /// The protocol picked from a client's offer, or `None` if it offered only
+    /// ones the server doesn't speak.
+    fn selected(offered: bool, selected: Option<&header::HeaderValue>) -> Option<Self> {
+        match selected.map(|name| name.as_bytes()) {
+            Some(b"streambridge.v1") => Some(Self::V1),
+            Some(_) => None,
+            None if offered => None,
+            None => Some(Self::Bare),
+        }
+    }
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Bare => None,
+            Self::V1 => Some("streambridge.v1"),
+        }
+    }
+
+    /// A text message in this protocol's format.
+    fn text(self, message: V1Message) -> Message {
+        match (self, message) {
+            (Self::Bare, V1Message::Metadata { xml }) => Message::Text(xml.into()),
+            (_, message) => {
+                Message::Text(serde_json::to_string(&message).unwrap_or_default().into())
+            }
+        }
+    }
+}
+
+/// Text messages to `streambridge.v1` clients.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum V1Message<'a> {
+    /// `format` is the content type of the binary frames to follow.
+    Hello { protocol: &'static str, source: &'a str, format: &'static str },
+    Metadata { xml: &'a str },
+    /// The source's sender dropped; frames resume after a `reconnected`.
+    Reconnecting { attempt: u32 },
+    Reconnected,
+    /// The number of the binary frame that follows, for matching annotations,
+    /// and with `framing=meta` its timing and size.
+    Frame {
+        seq: u64,
+        #[serde(flatten)]
+        meta: Option<FrameMeta>,
+    },
+    Annotations(&'a Annotations),
+    /// The source's audio levels, about ten times a second.
+    Levels(&'a Levels),
+    /// The next binary frame is the still a `grab` asked for, in `format`, not
+    /// a frame of the stream.
+    Still { format: &'static str },
+}
+
+/// When a frame was sent and received, for measuring latency, and its size.
+#[derive(Serialize)]
+struct FrameMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timecode: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<i64>,
+    received_ms: u64,
+    width: usize,
+    height: usize,
+}
+
+impl From<FrameInfo> for FrameMeta {
+    fn from(info: FrameInfo) -> Self {
+        let FrameInfo { timecode, timestamp, received_ms, width, height, .. } = info;
+        Self { timecode, timestamp, received_ms, width, height }
+    }
+}
+
+async fn send_close(socket: &mut WebSocket, code: u16, reason: &str) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.to_string().into(),
+        })))
+        .await;
+}
+
+/// What a WebSocket client asked for, and may do.
+struct WsOptions {
+    chaos: Option<Chaos>,
+    marker: Marker,
+    /// Also send the sender's metadata XML.
+    metadata: bool,
+    /// Also send annotations and frame numbers.
+    annotations: bool,
+    /// Also send audio levels.
+    levels: bool,
+    framing: Framing,
+    mode: StreamMode,
+    variant: Variant,
+    token: Option<String>,
+    adaptive: bool,
+    bandwidth: Option<Bandwidth>,
+    client_id: Option<String>,
+    peer: Option<IpAddr>,
+    user_agent: Option<String>,
+    /// Where to connect if discovery hasn't seen the source.
+    url: Option<String>,
+    /// The mosaic to stream instead of a source.
+    mosaic: Option<MosaicConfig>,
+    /// The client's place within the connection limits.
+    slot: Slot,
+}
+
+/// How often adaptive clients are pinged to measure their round trip.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+async fn handle_ws(
+    mut socket: WebSocket,
+    source_name: String,
+    state: AppState,
+    options: WsOptions,
+    protocol: WsProtocol,
+) {
+    let WsOptions {
+        mut chaos,
+        marker,
+        metadata,
+        annotations,
+        levels,
+        framing,
+        mode,
+        variant,
+        token,
+        adaptive,
+        bandwidth,
+        client_id,
+        peer,
+        user_agent,
+        url,
+        mosaic,
+        slot: _slot,
+    } = options;
+    let _stream = state.activity.stream();
+    let shared = match mosaic {
+        Some(mosaic) => state.receiver_manager.get_or_create_mosaic(&mosaic),
+        None => {
+            // Find the source in our discovery list
+            let source = {
+                let sources = state.sources.read().unwrap();
+                sources.iter().find(|s| s.name == source_name).cloned()
+            };
+
+            let source = match source {
+                Some(s) => s,
+                // Public servers only serve what they list.
+                None if state.public_readonly => {
+                    warn!("WS: source not found: \"{}\"", source_name);
+                    send_close(&mut socket, 4404, "source not found").await;
+                    return;
+                }
+                None => match discovery::direct_source(&source_name, url.as_deref()) {
+                    Ok(s) => {
+                        let at = s.url.as_deref().unwrap_or("its name");
+                        info!("WS: connecting to unlisted source \"{}\" at {}", source_name, at);
+                        s
+                    }
+                    Err(e) => {
+                        warn!("WS: source not found: \"{}\" ({})", source_name, e);
+                        let (code, reason) = match url {
+                            Some(_) => (4400, "bad source address"),
+                            None => (4404, "source not found"),
+                        };
+                        send_close(&mut socket, code, reason).await;
+                        return;
+                    }
+                },
+            };
+            state.receiver_manager.get_or_create_at(&source, bandwidth)
+        }
+    };
+    let shared = match shared {
+        Ok(s) => s,
+        Err(_) if state.receiver_manager.is_draining() => {
+            send_away(&mut socket, &state).await;
+            return;
+        }
+        Err(e) => {
+            warn!("WS: failed to create receiver for \"{}\": {}", source_name, e);
+            send_close(&mut socket, 4404, "source not found").await;
+            return;
+        }
+    };
+
+    info!("WS: client connected for \"{}\"", source_name);
+    if let Some(name) = protocol.name() {
+        let format = mode.format(variant).content_type();
+        let hello = V1Message::Hello { protocol: name, source: &source_name, format };
+        if socket.send(protocol.text(hello)).await.is_err() {
+            state.receiver_manager.maybe_remove(&shared.key);
+            return;
+        }
+    }
+    let mut rx = mode.subscribe(&shared, variant);
+    let mut metadata_rx = metadata.then(|| {
+        let rx = shared.subscribe_metadata();
+        // The frame subscription already counts this client.
+        shared.unsubscribe();
+        rx
+    });
+    let mut adaptive = adaptive.then(|| AdaptiveRate::new(&source_name, Instant::now()));
+    let mut ping = tokio::time::interval_at(
+        tokio::time::Instant::now() + PING_INTERVAL,
+        PING_INTERVAL,
+    );
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut pinged: Option<Instant> = None;
+    let label = state.access.viewer_label(token.as_deref());
+    let client =
+        state.clients.register("websocket", &source_name, label, client_id, peer, user_agent);
+    // Bare clients only expect frames.
+    let mut link_rx = protocol.name().map(|_| shared.watch_link());
+    // Stills come at the source's own quality and size, in the client's format.
+    let still_format = mode.format(variant);
+    let mut still_rx = None;
+    let mut levels_rx = (levels && protocol.name().is_some()).then(|| {
+        let rx = shared.subscribe_levels();
+        shared.unsubscribe();
+        rx
+    });
+    let wants_annotations = annotations && protocol.name().is_some();
+    let mut annotations_rx = wants_annotations.then(|| state.annotations.subscribe(&source_name));
+
+    loop {
+        let received = tokio::select! {
+            received = rx.recv() => received,
+            xml = next_metadata(&mut metadata_rx) => {
+                if socket.send(protocol.text(V1Message::Metadata { xml: &xml })).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            () = state.receiver_manager.drained() => {
+                send_away(&mut socket, &state).await;
+                break;
+            }
+            still = next_still(&mut still_rx) => {
+                still_rx = None;
+                let Some(still) = mark(&marker, still, &shared.stats).await else {
+                    continue;
+                };
+                let tag = V1Message::Still { format: still_format.content_type() };
+                if protocol.name().is_some() && socket.send(protocol.text(tag)).await.is_err() {
+                    break;
+                }
+                let (bytes, sending) = (still.len(), Instant::now());
+                if socket.send(Message::Binary(still)).await.is_err() {
+                    break;
+                }
+                client.sent(bytes, sending.elapsed());
+                continue;
+            }
+            levels = next_levels(&mut levels_rx) => {
+                if socket.send(protocol.text(V1Message::Levels(&levels))).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            annotations = next_annotations(&mut annotations_rx) => {
+                let message = V1Message::Annotations(&annotations);
+                if socket.send(protocol.text(message)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            link = next_link(&mut link_rx) => {
+                let message = match link {
+                    Link::Up => V1Message::Reconnected,
+                    Link::Reconnecting { attempt } => V1Message::Reconnecting { attempt },
+                };
+                if socket.send(protocol.text(message)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ = ping.tick(), if adaptive.is_some() => {
+                let now = Instant::now();
+                if let Some(rate) = adaptive.as_mut() {
+                    if pinged.is_some_and(|sent| now - sent >= PING_INTERVAL) {
+                        rate.lagged(now);
+                    }
+                    client.set_adaptive_fps(rate.fps());
+                }
+                if socket.send(Message::Ping(Bytes::new())).await.is_err() {
+                    break;
+                }
+                pinged.get_or_insert(now);
+                continue;
+            }
+            incoming = socket.recv() => {
+                match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        let grab = client_message(&state, token.as_deref(), &shared, &text);
+                        if grab && still_rx.is_none() {
+                            let still = Variant { format: still_format, ..Variant::default() };
+                            let rx = shared.subscribe_variant(still);
+                            // The frame subscription already counts this client.
+                            shared.unsubscribe();
+                            still_rx = Some(rx);
+                        }
+                    }
+                    Some(Ok(Message::Pong(_))) => {
+                        if let (Some(rate), Some(sent)) = (adaptive.as_mut(), pinged.take()) {
+                            rate.pong(Instant::now(), sent.elapsed());
+                        }
+                    }
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+                continue;
+            }
+        };
+        match received {
+            Some(JpegFrame { data, info }) => {
+                let arrived = Instant::now();
+                let skipped = rx.take_skipped();
+                if skipped > 0 {
+                    client.lagged(skipped);
+                    shared.stats.skipped.fetch_add(skipped, Ordering::Relaxed);
+                    if let Some(rate) = adaptive.as_mut() {
+                        rate.lagged(arrived);
+                    }
+                }
+                if adaptive.as_mut().is_some_and(|rate| !rate.admit(arrived)) {
+                    continue;
+                }
+                let Some(data) = mark(&marker, data, &shared.stats).await else {
+                    continue;
+                };
+                let frames = match chaos.as_mut() {
+                    Some(chaos) => chaos.apply(data).await,
+                    None => vec![data],
+                };
+                let mut send_failed = false;
+                let tagged = annotations_rx.is_some() || framing == Framing::Meta;
+                for data in frames {
+                    let meta = (framing == Framing::Meta).then(|| FrameMeta::from(info));
+                    let tag = V1Message::Frame { seq: info.seq, meta };
+                    if tagged && socket.send(protocol.text(tag)).await.is_err() {
+                        send_failed = true;
+                        break;
+                    }
+                    let (bytes, sending) = (data.len(), Instant::now());
+                    if socket.send(Message::Binary(data)).await.is_err() {
+                        send_failed = true;
+                        break;
+                    }
+                    client.sent(bytes, sending.elapsed());
+                }
+                if send_failed {
+                    break;
+                }
+                if let Some(rate) = adaptive.as_mut() {
+                    rate.sent(arrived, arrived.elapsed());
+                }
+            }
+            None if state.receiver_manager.is_draining() => {
+                send_away(&mut socket, &state).await;
+                break;
+            }
+            None => {
+                warn!("WS: source lost for \"{}\"", source_name);
+                send_close(&mut socket, 4410, "source lost").await;
+                break;
+            }
+        }
+    }
+
+    shared.unsubscribe();
+    state.receiver_manager.maybe_remove(&shared.key);
+    info!("WS: client disconnected from \"{}\"", source_name);
+}
+
+/// Close a client's socket for a drain (1012, inviting it to reconnect, e.g. to
+/// another instance) or a shutdown (1001), and wait for it to answer, so that a
+/// drain counts the client until it has really gone.
+async fn send_away(socket: &mut WebSocket, state: &AppState) {
+    let (code, reason) = if state.receiver_manager.is_shutting_down() {
+        (1001, "server shutting down")
+    } else {
+        (1012, "server restarting")
+    };
+    send_close(socket, code, reason).await;
+    while let Some(Ok(message)) = socket.recv().await {
+        if let Message::Close(_) = message {
+            break;
+        }
+    }
+}
+
+/// A text message from a WebSocket client, e.g. `{"split": 0.3}` or
+/// `{"cmd": "grab"}`.
+#[derive(Deserialize)]
+struct ClientMessage {
+    /// Move a comparison source's split, as a fraction of the frame width.
+    split: Option<f32>,
+    /// `grab`: send the next frame as a still, at the source's own quality and
+    /// size and whatever the client's fps.
+    cmd: Option<String>,
+}
+
+/// Apply a WebSocket client's message, returning whether it asked to grab a
+/// still. Changes to the source need `control`, stills `snapshot`.
+fn client_message(
+    state: &AppState,
+    token: Option<&str>,
+    shared: &SharedReceiver,
+    text: &str,
+) -> bool {
+    let message: ClientMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("WS: ignoring message for \"{}\": {}", shared.source_name, e);
+            return false;
+        }
+    };
+    if let Some(split) = message.split {
+        if authorize(state, token, &shared.source_name, Action::Control).is_ok() {
+            shared.send(Control::SetSplit(split));
+        }
+    }
+    match message.cmd.as_deref() {
+        Some("grab") => authorize(state, token, &shared.source_name, Action::Snapshot).is_ok(),
+        Some(other) => {
+            debug!("WS: ignoring command \"{}\" for \"{}\"", other, shared.source_name);
+            false
+        }
+        None => false,
+    }
+}
+
+/// The still a WebSocket client grabbed. Never resolves while none is wanted,
+/// nor once the source is gone: the frame side reports that.
+async fn next_still(rx: &mut Option<latest::Receiver<JpegFrame>>) -> Bytes {
+    if let Some(frame) = rx.as_mut().map(latest::Receiver::recv) {
+        if let Some(frame) = frame.await {
+            return frame.data;
+        }
+    }
+    std::future::pending().await
+}
+
+/// The next annotations for a WebSocket that asked for them. Never resolves
+/// otherwise.
+async fn next_annotations(
+    rx: &mut Option<broadcast::Receiver<Arc<Annotations>>>,
+) -> Arc<Annotations> {
+    if let Some(rx) = rx {
+        loop {
+            match rx.recv().await {
+                Ok(annotations) => return annotations,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+/// The sender connection's next change, for a WebSocket told about them. Never
+/// resolves otherwise, nor once the receiver is gone.
+async fn next_link(rx: &mut Option<watch::Receiver<Link>>) -> Link {
+    if let Some(rx) = rx {
+        if rx.changed().await.is_ok() {
+            return *rx.borrow_and_update();
+        }
+    }
+    std::future::pending().await
+}
+
+/// The next levels for a WebSocket that asked for them. Never resolves
+/// otherwise, nor once the source is gone: the frame side reports that.
+async fn next_levels(rx: &mut Option<broadcast::Receiver<Levels>>) -> Levels {
+    if let Some(rx) = rx {
+        loop {
+            match rx.recv().await {
+                Ok(levels) => return levels,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+/// The next metadata XML for a WebSocket that asked for it. Never resolves
+/// otherwise, nor once the source is gone: the frame side reports that.
+async fn next_metadata(rx: &mut Option<broadcast::Receiver<Arc<str>>>) -> Arc<str> {
+    if let Some(rx) = rx {
+        loop {
+            match rx.recv().await {
+                Ok(xml) => return xml,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+const MJPEG_BOUNDARY: &str = "frame";
+
+/// How long a snapshot request waits for the next frame.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long an audio listener waits for the first audio.
+const AUDIO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An HTTP client's subscription to a shared receiver's frames (or audio).
+/// Unsubscribes when dropped, e.g. when a streaming client disconnects and its
+/// response body is dropped.
+struct Subscription<R = latest::Receiver<JpegFrame>> {
+    shared: Arc<SharedReceiver>,
+    manager: Arc<ReceiverManager>,
+    rx: R,
+    _stream: StreamGuard,
+}
+
+impl Subscription {
+    /// The newest frame since the last one. `None` once the source is gone or
+    /// the server is draining.
+    async fn next(&mut self) -> Option<JpegFrame> {
+        tokio::select! {
+            frame = self.rx.recv() => frame,
+            () = self.manager.drained() => None,
+        }
+    }
+
+    /// Like [`Subscription::next`], for the frame's image.
+    async fn next_frame(&mut self) -> Option<Bytes> {
+        self.next().await.map(|frame| frame.data)
+    }
+}
+
+impl Subscription<broadcast::Receiver<AudioChunk>> {
+    fn open_audio(
+        state: &AppState,
+        source_name: &str,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        Self::open_with(state, source_name, "audio", None, SharedReceiver::subscribe_audio)
+    }
+}
+
+impl Subscription<broadcast::Receiver<Levels>> {
+    fn open_levels(
+        state: &AppState,
+        source_name: &str,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        Self::open_with(state, source_name, "levels", None, SharedReceiver::subscribe_levels)
+    }
+}
+
+impl Subscription<broadcast::Receiver<Arc<str>>> {
+    fn open_metadata(
+        state: &AppState,
+        source_name: &str,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        let subscribe = SharedReceiver::subscribe_metadata;
+        Self::open_with(state, source_name, "metadata", None, subscribe)
+    }
+}
+
+impl<R> Subscription<R> {
+    /// Look up `source_name` and subscribe to its receiver, creating it if needed.
+    fn open_with(
+        state: &AppState,
+        source_name: &str,
+        kind: &str,
+        bandwidth: Option<Bandwidth>,
+        subscribe: impl FnOnce(&SharedReceiver) -> R,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        let source = {
+            let sources = state.sources.read().unwrap();
+            sources.iter().find(|s| s.name == source_name).cloned()
+        };
+        let Some(source) = source else {
+            warn!("{}: source not found: \"{}\"", kind, source_name);
+            return Err((StatusCode::NOT_FOUND, "source not found"));
+        };
+        let create = |manager: &Arc<ReceiverManager>| manager.get_or_create_at(&source, bandwidth);
+        Self::open_receiver(state, source_name, kind, subscribe, create)
+    }
+
+    /// Subscribe to the receiver `create` gets or starts for `name`.
+    fn open_receiver(
+        state: &AppState,
+        name: &str,
+        kind: &str,
+        subscribe: impl FnOnce(&SharedReceiver) -> R,
+        create: impl FnOnce(&Arc<ReceiverManager>) -> Result<Arc<SharedReceiver>, String>,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        if state.receiver_manager.is_draining() {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "server restarting"));
+        }
+        let shared = create(&state.receiver_manager).map_err(|e| {
+            warn!("{}: failed to create receiver for \"{}\": {}", kind, name, e);
+            (StatusCode::SERVICE_UNAVAILABLE, "source unavailable")
+        })?;
+        let rx = subscribe(&shared);
+        Ok(Self {
+            shared,
+            manager: state.receiver_manager.clone(),
+            rx,
+            _stream: state.activity.stream(),
+        })
+    }
+}
+
+impl<T: Clone> Subscription<broadcast::Receiver<T>> {
+    /// The next item, skipping over any we lagged behind on. `None` once the
+    /// source is gone or the server is draining.
+    async fn next(&mut self) -> Option<T> {
+        loop {
+            let received = tokio::select! {
+                received = self.rx.recv() => received,
+                () = self.manager.drained() => return None,
+            };
+            match received {
+                Ok(item) => return Some(item),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl<R> Drop for Subscription<R> {
+    fn drop(&mut self) {
+        self.shared.unsubscribe();
+        self.manager.maybe_remove(&self.shared.key);
+    }
+}
+
+/// A viewer's frames outside HTTP, e.g. over RTSP: authorized, admitted and
+/// listed under `/api/clients` like an MJPEG stream's.
+pub(crate) struct ViewerFeed {
+    subscription: Subscription,
+    marker: Marker,
+    client: ClientHandle,
+    _slot: Slot,
+}
+
+impl ViewerFeed {
+    /// Subscribe a `kind` viewer with `token` to `source`'s frames in `variant`.
+    pub(crate) fn open(
+        state: &AppState,
+        kind: &'static str,
+        token: Option<&str>,
+        source: &str,
+        variant: Variant,
+        peer: Option<IpAddr>,
+        user_agent: Option<String>,
+    ) -> Result<Self, Rejection> {
+        authorize(state, token, source, Action::View)?;
+        let marker = viewer_marker(state, token, source, variant.format)?;
+        let slot = state.limits.admit(peer, source)?;
+        let subscribe = |shared: &SharedReceiver| shared.subscribe_variant(variant);
+        let subscription = Subscription::open_with(state, source, kind, None, subscribe)?;
+        let label = state.access.viewer_label(token);
+        let client = state.clients.register(kind, source, label, None, peer, user_agent);
+        Ok(Self { subscription, marker, client, _slot: slot })
+    }
+
+    /// The newest frame, watermarked for the viewer. `None` once the source is
+    /// gone or the server is draining.
+    pub(crate) async fn next(&mut self) -> Option<Bytes> {
+        loop {
+            let frame = self.subscription.next_frame().await?;
+            let skipped = self.subscription.rx.take_skipped();
+            let stats = &self.subscription.shared.stats;
+            if skipped > 0 {
+                self.client.lagged(skipped);
+                stats.skipped.fetch_add(skipped, Ordering::Relaxed);
+            }
+            if let Some(frame) = mark(&self.marker, frame, stats).await {
+                return Some(frame);
+            }
+        }
+    }
+
+    /// Count a frame of `bytes` that took `took` to send.
+    pub(crate) fn sent(&self, bytes: usize, took: Duration) {
+        self.client.sent(bytes, took);
+    }
+}
+
+/// One part of a `multipart/x-mixed-replace` stream.
+fn mjpeg_part(image: &[u8], format: OutputFormat) -> Bytes {
+    let header = format!(
+        "--{MJPEG_BOUNDARY}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        format.content_type(),
+        image.len()
+    );
+    let mut part = Vec::with_capacity(header.len() + image.len() + 2);
+    part.extend_from_slice(header.as_bytes());
+    part.extend_from_slice(image);
+    part.extend_from_slice(b"\r\n");
+    Bytes::from(part)
+}
+
+/// Classic MJPEG over HTTP for `<img>` tags, VLC and IP-camera consumers.
+/// Shares the source's receiver (and its fps cap) with WebSocket clients.
+#[utoipa::path(
+    get,
+    path = "/stream/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias"), StreamQuery),
+    responses(
+        (status = 200, description = "JPEG frames as they come",
+        content_type = "multipart/x-mixed-replace")
+    )
+)]
+async fn mjpeg_stream(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::View) {
+        return rejection.into_response();
+    }
+    let bandwidth = bandwidth(&state, query.bandwidth);
+    serve_mjpeg(state, source_name, headers, query, peer, |state, name, subscribe| {
+        Subscription::open_with(state, name, "MJPEG", bandwidth, subscribe)
+    })
+}
+
+/// Several sources tiled into one MJPEG stream, for a multiviewer that would
+/// otherwise open a stream of each.
+#[utoipa::path(
+    get,
+    path = "/mosaic",
+    params(MosaicQuery, StreamQuery),
+    responses(
+        (status = 200, description = "JPEG frames as they come",
+        content_type = "multipart/x-mixed-replace")
+    )
+)]
+async fn mosaic_stream(
+    Query(mosaic): Query<MosaicQuery>,
+    headers: HeaderMap,
+    Query(query): Query<StreamQuery>,
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    let layout = mosaic.layout.as_deref();
+    let mosaic = match mosaic_config(&state, token, &mosaic.sources, layout) {
+        Ok(mosaic) => mosaic,
+        Err(rejection) => return rejection.into_response(),
+    };
+    serve_mjpeg(state, mosaic.name(), headers, query, peer, |state, name, subscribe| {
+        let create = |manager: &Arc<ReceiverManager>| manager.get_or_create_mosaic(&mosaic);
+        Subscription::open_receiver(state, name, "MJPEG", subscribe, create)
+    })
+}
+
+/// A frame subscription for a stream's mode and variant.
+type Subscribe<'a> = &'a dyn Fn(&SharedReceiver) -> latest::Receiver<JpegFrame>;
+
+/// Why a request was turned away.
+pub(crate) type Rejection = (StatusCode, &'static str);
+
+/// Stream `source_name`, which the client may view, over the subscription
+/// `open` makes.
+fn serve_mjpeg(
+    state: AppState,
+    source_name: String,
+    headers: HeaderMap,
+    query: StreamQuery,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    open: impl FnOnce(&AppState, &str, Subscribe) -> Result<Subscription, Rejection>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    let mode = query.mode;
+    let variant = variant(query.quality, query.fps, query.width, query.format);
+    let format = mode.format(variant);
+    let subscribe = |shared: &SharedReceiver| mode.subscribe(shared, variant);
+    let peer = peer_ip(peer);
+    let opened = viewer_marker(&state, token, &source_name, format).and_then(|marker| {
+        let slot = state.limits.admit(peer, &source_name)?;
+        let subscription = open(&state, &source_name, &subscribe)?;
+        Ok((subscription, marker, slot))
+    });
+    let (subscription, marker, slot) = match opened {
+        Ok(opened) => opened,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    info!("MJPEG: client connected for \"{}\"", source_name);
+    let label = state.access.viewer_label(token);
+    let (client_id, cookie) = client_id(&state, &headers, &query.client_id);
+    let user_agent = user_agent(&headers);
+    let client = state.clients.register("mjpeg", &source_name, label, client_id, peer, user_agent);
+    // The body is polled for the next part once the last one has been written,
+    // which times the writes like a WebSocket send.
+    let start = (subscription, marker, (client, slot), None);
+    let frames = stream::unfold(start, move |(mut subscription, marker, client, written)| {
+        async move {
+            if let Some((bytes, yielded)) = written {
+                client.0.sent(bytes, Instant::now() - yielded);
+            }
+            loop {
+                let frame = subscription.next_frame().await?;
+                let skipped = subscription.rx.take_skipped();
+                let stats = &subscription.shared.stats;
+                if skipped > 0 {
+                    client.0.lagged(skipped);
+                    stats.skipped.fetch_add(skipped, Ordering::Relaxed);
+                }
+                if let Some(frame) = mark(&marker, frame, stats).await {
+                    let part = mjpeg_part(&frame, format);
+                    let written = Some((part.len(), Instant::now()));
+                    let state = (subscription, marker, client, written);
+                    return Some((Ok::<_, Infallible>(part), state));
+                }
+            }
+        }
+    });
+
+    let response = (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={MJPEG_BOUNDARY}"),
+            ),
+            (header::CACHE_CONTROL, "no-cache, no-store".to_string()),
+        ],
+        Body::from_stream(frames),
+    )
+        .into_response();
+    with_cookie(response, cookie)
+}
+
+/// The source's audio as an endless stream, for browsers, VLC and Icecast-style
+/// players: `/audio/<source>.wav` as 16-bit PCM WAV, and with the `ffmpeg`
+/// feature `.mp3` or `.aac` at 128 kbit/s.
+#[utoipa::path(
+    get,
+    path = "/audio/{file}",
+    params(
+        ("file" = String, Path,
+        description = "`<source>.wav`, `.mp3` or `.aac`, by the source's name, id or alias")
+    ),
+    responses(
+        (status = 200, description = "A WAV header, then PCM as it comes; or MP3 or AAC frames",
+        content(("audio/wav"), ("audio/mpeg"), ("audio/aac"))),
+        (status = 501, description = "MP3 or AAC asked of a build without the ffmpeg feature")
+    )
+)]
+async fn audio_stream(
+    Path(file): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some((source_name, format)) = audio::Format::split(&file) else {
+        let message = "expected /audio/<source>.wav, .mp3 or .aac";
+        return (StatusCode::NOT_FOUND, message).into_response();
+    };
+    if format != audio::Format::Wav && !audio::COMPRESSED {
+        let message = "MP3 and AAC need the ffmpeg feature";
+        return (StatusCode::NOT_IMPLEMENTED, message).into_response();
+    }
+    let source_name = state.source_name(source_name.to_string());
+    let token = request_token(&headers, &query.token);
+    let opened = authorize(&state, token, &source_name, Action::View)
+        .and_then(|()| Subscription::open_audio(&state, &source_name));
+    let mut subscription = match opened {
+        Ok(subscription) => subscription,
+        Err(rejection) => return rejection.into_response(),
+    };
+    // The header needs the format, so wait for the first audio.
+    let first = match tokio::time::timeout(AUDIO_TIMEOUT, subscription.next()).await {
+        Ok(Some(chunk)) => chunk,
+        Ok(None) => return (StatusCode::SERVICE_UNAVAILABLE, "source lost").into_response(),
+        Err(_) => return (StatusCode::GATEWAY_TIMEOUT, "source has no audio").into_response(),
+    };
+
+    info!("audio: client connected for \"{}\"", source_name);
+    let header_part = wav_header(first.sample_rate, first.channels);
+    let head = stream::iter([header_part, first.pcm.clone()]);
+    // A format change can't be signalled mid-stream; end it and let the player reconnect.
+    let rest = stream::unfold((subscription, first), |(mut subscription, first)| async move {
+        let chunk = subscription.next().await.filter(|c| c.same_format(&first))?;
+        Some((chunk.pcm.clone(), (subscription, first)))
+    });
+    let wav = head.chain(rest);
+    let body = match format {
+        audio::Format::Wav => Body::from_stream(wav.map(Ok::<_, Infallible>)),
+        format => match audio::encode(format, wav) {
+            Ok(encoded) => Body::from_stream(encoded),
+            Err(e) => {
+                warn!("audio: \"{}\": {}", source_name, e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "audio encoder failed").into_response();
+            }
+        },
+    };
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CACHE_CONTROL, "no-cache, no-store".to_string()),
+            (header::HeaderName::from_static("icy-name"), source_name.to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Play a source over WebRTC, as WHEP has it: the body is the browser's SDP
+/// offer and the response its answer, with the session to `DELETE` when done
+/// in `Location`.
+#[utoipa::path(
+    post,
+    path = "/webrtc/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    request_body(content = String, content_type = "application/sdp"),
+    responses(
+        (status = 201, description = "The SDP answer", content_type = "application/sdp"),
+        (status = 400, description = "The offer can't be answered"),
+        (status = 501, description = "Built without the ffmpeg feature")
+    )
+)]
+async fn post_webrtc(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    offer: String,
+) -> Response {
+    if !whep::AVAILABLE {
+        return (StatusCode::NOT_IMPLEMENTED, "WebRTC needs the ffmpeg feature").into_response();
+    }
+    let source = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    let (peer, user_agent, variant) = (peer_ip(peer), user_agent(&headers), Variant::default());
+    let opened = ViewerFeed::open(&state, "webrtc", token, &source, variant, peer, user_agent);
+    let feed = match opened {
+        Ok(feed) => feed,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let encoder = state.receiver_manager.encode_settings(&source).encoder;
+    let (id, answer) = match state.whep.start(&source, feed, encoder, offer).await {
+        Ok(session) => session,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    info!("WebRTC: session {} offered for \"{}\"", id, source);
+    let location = format!("/webrtc/{}/{}", homeassistant::encode(&source), id);
+    let content_type = (header::CONTENT_TYPE, "application/sdp".to_string());
+    (StatusCode::CREATED, [content_type, (header::LOCATION, location)], answer).into_response()
+}
+
+/// End a WebRTC session.
+#[utoipa::path(
+    delete,
+    path = "/webrtc/{source}/{session}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        ("session" = String, Path, description = "The session, from `Location`")
+    ),
+    responses((status = 204, description = "Ended"))
+)]
+async fn delete_webrtc(
+    Path((source_name, session)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source, Action::View) {
+        return rejection.into_response();
+    }
+    if !state.whep.stop(&source, &session) {
+        return (StatusCode::NOT_FOUND, "no such WebRTC session").into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The levels of the source's audio over the last tenth of a second, for VU
+/// meters: `{"channels": [{"rms": -18.2, "peak": -6.1}, ...]}` in dBFS.
+#[utoipa::path(
+    get,
+    path = "/levels/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "Each channel's levels", content_type = "application/json")
+    )
+)]
+async fn get_levels(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    let opened = authorize(&state, token, &source_name, Action::View)
+        .and_then(|()| Subscription::open_levels(&state, &source_name));
+    let mut subscription = match opened {
+        Ok(subscription) => subscription,
+        Err(rejection) => return rejection.into_response(),
+    };
+    match tokio::time::timeout(AUDIO_TIMEOUT, subscription.next()).await {
+        Ok(Some(levels)) => {
+            let json = serde_json::to_string(&levels).unwrap_or_default();
+            ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+        }
+        Ok(None) => (StatusCode::SERVICE_UNAVAILABLE, "source lost").into_response(),
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, "source has no audio").into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct SourceEventJson<'a> {
+    source: &'a str,
+    /// The share of the picture that changed, for `motion_started`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f32>,
+}
+
+/// Sources appearing and disappearing, and motion on them starting and
+/// stopping, as server-sent events named `source_added`, `source_removed`,
+/// `motion_started` and `motion_stopped` with `{"source": "<name>"}` as data.
+/// Only sources the token may view are reported.
+#[utoipa::path(
+    get,
+    path = "/events",
+    responses(
+        (status = 200, description = "Events as they happen", content_type = "text/event-stream")
+    )
+)]
+async fn source_events(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = request_token(&headers, &query.token).map(str::to_string);
+    let rx = state.events.subscribe();
+    let events = stream::unfold((rx, state, token), |(mut rx, state, token)| async move {
+        loop {
+            let received = tokio::select! {
+                received = rx.recv() => received,
+                () = state.receiver_manager.drained() => return None,
+            };
+            let event = match received {
+                Ok(
+                    event @ (Event::SourceAdded { .. }
+                    | Event::SourceRemoved { .. }
+                    | Event::MotionStarted { .. }
+                    | Event::MotionStopped { .. }),
+                ) => event,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            if state.access.check(token.as_deref(), event.source(), Action::View).is_err() {
+                continue;
+            }
+            let score = match event {
+                Event::MotionStarted { score, .. } => Some(score),
+                _ => None,
+            };
+            let data = serde_json::to_string(&SourceEventJson { source: event.source(), score })
+                .unwrap_or_default();
+            let sse = sse::Event::default().event(event.kind()).data(data);
+            return Some((Ok::<_, Infallible>(sse), (rx, state, token)));
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// The sender's metadata frames (tally, captions, custom XML) as server-sent
+/// events named `metadata`, one per frame.
+#[utoipa::path(
+    get,
+    path = "/metadata/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "A `metadata` event per frame",
+        content_type = "text/event-stream")
+    )
+)]
+async fn metadata_stream(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    let opened = authorize(&state, token, &source_name, Action::View)
+        .and_then(|()| Subscription::open_metadata(&state, &source_name));
+    let subscription = match opened {
+        Ok(subscription) => subscription,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    info!("metadata: client connected for \"{}\"", source_name);
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let xml = subscription.next().await?;
+        let event = sse::Event::default().event("metadata").data(xml.as_ref());
+        Some((Ok::<_, Infallible>(event), subscription))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// A single JPEG of the source's next frame, for thumbnailing dashboards and
+/// camera integrations that poll for stills.
+#[utoipa::path(
+    get,
+    path = "/snapshot/{source}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        SnapshotQuery
+    ),
+    responses(
+        (status = 200, description = "The frame, in the format asked for",
+        content_type = "image/jpeg")
+    )
+)]
+async fn snapshot(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<SnapshotQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    let format = query.format;
+    let variant = variant(None, None, None, format);
+    let subscribe = |shared: &SharedReceiver| shared.subscribe_variant(variant);
+    let opened = authorize(&state, token, &source_name, Action::Snapshot)
+        .and_then(|()| viewer_marker(&state, token, &source_name, format))
+        .and_then(|marker| {
+            let subscription =
+                Subscription::open_with(&state, &source_name, "snapshot", None, subscribe)?;
+            Ok((subscription, marker))
+        });
+    let (mut subscription, marker) = match opened {
+        Ok(opened) => opened,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let frame = match tokio::time::timeout(SNAPSHOT_TIMEOUT, subscription.next()).await {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return (StatusCode::SERVICE_UNAVAILABLE, "source lost").into_response(),
+        Err(_) => {
+            warn!("snapshot: no frame from \"{}\" within {:?}", source_name, SNAPSHOT_TIMEOUT);
+            return (StatusCode::GATEWAY_TIMEOUT, "no frame received").into_response();
+        }
+    };
+    let Some(jpeg) = mark(&marker, frame.data, &subscription.shared.stats).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "watermarking failed").into_response();
+    };
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CACHE_CONTROL, "no-cache, no-store".to_string()),
+            // For matching annotations to the frame.
+            (header::HeaderName::from_static("x-frame-seq"), frame.info.seq.to_string()),
+        ],
+        jpeg,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SnapshotQuery {
+    #[param(ignore)]
+    token: Option<String>,
+    /// `png` for a lossless still, or `webp`.
+    #[serde(default)]
+    #[param(inline)]
+    format: OutputFormat,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HookQuery {
+    /// The source the event is about.
+    source: String,
+    #[param(ignore)]
+    token: Option<String>,
+}
+
+/// Publish a webhook event for a source, e.g. to trigger capture rules.
+#[utoipa::path(
+    post,
+    path = "/api/hooks/{name}",
+    params(("name" = String, Path, description = "The event's name"), HookQuery),
+    responses((status = 202, description = "Published"))
+)]
+async fn post_hook(
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Query(mut query): Query<HookQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    query.source = state.source_name(query.source);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &query.source, Action::Snapshot) {
+        return rejection.into_response();
+    }
+    let known = state.sources.read().unwrap().iter().any(|s| s.name == query.source);
+    if !known {
+        return (StatusCode::NOT_FOUND, "source not found").into_response();
+    }
+    state.events.publish(Event::Webhook {
+        name,
+        source: query.source,
+    });
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Pass annotations, e.g. an ML service's detections in one frame, on to the
+/// source's viewers that asked for them.
+#[utoipa::path(
+    post,
+    path = "/api/annotations/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "How many viewers they went to",
+        content_type = "application/json")
+    )
+)]
+async fn post_annotations(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::Control) {
+        return rejection.into_response();
+    }
+    let known = state.sources.read().unwrap().iter().any(|s| s.name == source_name);
+    if !known {
+        return (StatusCode::NOT_FOUND, "source not found").into_response();
+    }
+    let annotations = serde_json::from_slice::<Annotations>(&body)
+        .map_err(|e| format!("invalid annotations: {e}"))
+        .and_then(|annotations| annotations.validate().map(|()| annotations));
+    match annotations {
+        Ok(annotations) => {
+            let viewers = state.annotations.publish(&source_name, annotations);
+            let json = serde_json::json!({ "ok": true, "viewers": viewers }).to_string();
+            ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// Whether `token` may take snapshots of every source in `sources`, or of
+/// every source there is when it's `None`.
+fn may_snapshot(state: &AppState, token: Option<&str>, sources: Option<&[String]>) -> bool {
+    match sources {
+        Some(sources) => {
+            sources.iter().all(|s| state.access.check(token, s, Action::Snapshot).is_ok())
+        }
+        None => state.access.allows_all_sources(token, Action::Snapshot),
+    }
+}
+
+/// A schedule as `/api/snapshots/schedule` shows it.
+fn schedule_json(schedule: &Schedule) -> serde_json::Value {
+    serde_json::json!({
+        "id": schedule.id,
+        "sources": schedule.sources,
+        "every": schedule.every,
+        "align": schedule.align,
+        "keep": schedule.keep,
+        "max_age": schedule.max_age,
+        "next_at": schedule.next_at,
+        "snapshots": schedule.snapshot_count(),
+    })
+}
+
+/// The snapshot schedules of sources the caller may take snapshots of.
+#[utoipa::path(
+    get,
+    path = "/api/snapshots/schedule",
+    responses((status = 200, description = "The schedules", content_type = "application/json"))
+)]
+async fn get_schedules(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    let schedules: Vec<_> = state
+        .snapshots
+        .schedules()
+        .iter()
+        .filter(|s| may_snapshot(&state, token, s.sources.as_deref()))
+        .map(schedule_json)
+        .collect();
+    let json = serde_json::Value::from(schedules).to_string();
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// Take snapshots once or on a schedule, kept by the rules given.
+#[utoipa::path(
+    post,
+    path = "/api/snapshots/schedule",
+    request_body(content = Object, content_type = "application/json"),
+    responses((status = 201, description = "The new schedule", content_type = "application/json"))
+)]
+async fn post_schedule(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let mut request: ScheduleRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid schedule: {e}")).into_response();
+        }
+    };
+    if let Some(sources) = request.sources.take() {
+        request.sources = Some(sources.into_iter().map(|s| state.source_name(s)).collect());
+    }
+    let token = request_token(&headers, &query.token);
+    if !may_snapshot(&state, token, request.sources.as_deref()) {
+        return (StatusCode::FORBIDDEN, "forbidden").into_response();
+    }
+    match state.snapshots.add(request, snapshots::unix_now()) {
+        Ok(schedule) => {
+            let json = schedule_json(&schedule).to_string();
+            let content_type = [(header::CONTENT_TYPE, "application/json")];
+            (StatusCode::CREATED, content_type, json).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// The schedule `id`, if the caller may take its snapshots.
+fn visible_schedule(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &TokenQuery,
+    id: u64,
+) -> Option<Schedule> {
+    let token = request_token(headers, &query.token);
+    let schedule = state.snapshots.schedule(id);
+    schedule.filter(|s| may_snapshot(state, token, s.sources.as_deref()))
+}
+
+/// A snapshot schedule, if the caller may take snapshots of its sources.
+#[utoipa::path(
+    get,
+    path = "/api/snapshots/schedule/{id}",
+    params(("id" = u64, Path, description = "The schedule's id")),
+    responses((status = 200, description = "The schedule", content_type = "application/json"))
+)]
+async fn get_schedule(
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    match visible_schedule(&state, &headers, &query, id) {
+        Some(schedule) => {
+            let json = schedule_json(&schedule).to_string();
+            ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "schedule not found").into_response(),
+    }
+}
+
+/// Stop a schedule and delete the snapshots it took.
+#[utoipa::path(
+    delete,
+    path = "/api/snapshots/schedule/{id}",
+    params(("id" = u64, Path, description = "The schedule's id")),
+    responses((status = 204, description = "Stopped"))
+)]
+async fn delete_schedule(
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if visible_schedule(&state, &headers, &query, id).is_none() {
+        return (StatusCode::NOT_FOUND, "schedule not found").into_response();
+    }
+    state.snapshots.remove(id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// A source's scheduled snapshots, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/snapshots/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses((status = 200, description = "The snapshots", content_type = "application/json"))
+)]
+async fn get_snapshots(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::Snapshot) {
+        return rejection.into_response();
+    }
+    let shots: Vec<_> = state
+        .snapshots
+        .list(&source_name)
+        .into_iter()
+        .map(|(schedule, shot)| {
+            serde_json::json!({
+                "schedule": schedule,
+                "taken": shot.taken,
+                "file": shot.file,
+            })
+        })
+        .collect();
+    let json = serde_json::Value::from(shots).to_string();
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// One of a source's scheduled snapshots, or with `latest` the newest: its
+/// thumbnail.
+#[utoipa::path(
+    get,
+    path = "/api/snapshots/{source}/{file}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        ("file" = String, Path, description = "A snapshot's file name, or `latest`")
+    ),
+    responses((status = 200, description = "The snapshot", content_type = "image/jpeg"))
+)]
+async fn get_snapshot_image(
+    Path((source_name, file)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::Snapshot) {
+        return rejection.into_response();
+    }
+    let file = Some(file).filter(|file| file != "latest");
+    match state.snapshots.read(&source_name, file.as_deref()).await {
+        Some(jpeg) => ([(header::CONTENT_TYPE, "image/jpeg")], jpeg).into_response(),
+        None => (StatusCode::NOT_FOUND, "snapshot not found").into_response(),
+    }
+}
+
+/// Run a command from an external automation system. With an idempotency key
+/// (`Idempotency-Key` header or `idempotency_key` field), a repeated request gets
+/// the first one's response instead of running the command again.
+#[utoipa::path(
+    post,
+    path = "/api/commands",
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The command's outcome", content_type = "application/json")
+    )
+)]
+async fn post_command(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let mut request: CommandRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            let message = format!("invalid command: {e}");
+            return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+        }
+    };
+    let source = request.command.source_mut();
+    *source = state.source_name(std::mem::take(source));
+    let token = request_token(&headers, &query.token);
+    let command = &request.command;
+    if let Err((status, message)) = authorize(&state, token, command.source(), command.action()) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let key = headers
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .or(request.idempotency_key.as_deref())
+        // Keys are per token, so one client can't see another's results.
+        .map(|key| format!("{}\n{}", token.unwrap_or_default(), key));
+    let Some(key) = key else {
+        return outcome_response(state.commands.execute(command).await, false);
+    };
+    match state.commands.begin(&key) {
+        Attempt::Replay(outcome) => outcome_response(outcome, true),
+        Attempt::InProgress => {
+            let message = "a command with this idempotency key is still running";
+            outcome_response(Outcome::error(StatusCode::CONFLICT, message), false)
+        }
+        Attempt::New => {
+            // Run to completion even if the client hangs up, so the key is never
+            // left claimed by a command that stopped halfway.
+            let (commands, command) = (state.commands.clone(), command.clone());
+            let run = tokio::spawn(async move {
+                let outcome = commands.execute(&command).await;
+                commands.finish(&key, &outcome);
+                outcome
+            });
+            let outcome = run.await.unwrap_or_else(|_| {
+                Outcome::error(StatusCode::INTERNAL_SERVER_ERROR, "command failed")
+            });
+            outcome_response(outcome, false)
+        }
+    }
+}
+
+/// A `POST /ptz/<source>/<control>` body; which fields apply depends on the control.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PtzBody {
+    pan: Option<f32>,
+    tilt: Option<f32>,
+    pan_speed: Option<f32>,
+    tilt_speed: Option<f32>,
+    zoom: Option<f32>,
+    speed: Option<f32>,
+    recall: Option<u32>,
+    store: Option<u32>,
+}
+
+fn ptz_command(control: &str, body: PtzBody) -> Result<PtzCommand, (StatusCode, &'static str)> {
+    let bad = |message| Err((StatusCode::BAD_REQUEST, message));
+    match control {
+        "pan_tilt" => match body {
+            PtzBody { pan: Some(pan), tilt: Some(tilt), pan_speed: None, tilt_speed: None, .. } => {
+                Ok(PtzCommand::PanTilt { pan, tilt })
+            }
+            PtzBody { pan: None, tilt: None, pan_speed, tilt_speed, .. }
+                if pan_speed.is_some() || tilt_speed.is_some() =>
+            {
+                let (pan, tilt) = (pan_speed.unwrap_or(0.0), tilt_speed.unwrap_or(0.0));
+                Ok(PtzCommand::PanTiltSpeed { pan, tilt })
+            }
+            _ => bad("expected pan and tilt, or pan_speed and/or tilt_speed"),
+        },
+        "zoom" => match (body.zoom, body.speed) {
+            (Some(zoom), None) => Ok(PtzCommand::Zoom(zoom)),
+            (None, Some(speed)) => Ok(PtzCommand::ZoomSpeed(speed)),
+            _ => bad("expected zoom or speed"),
+        },
+        "preset" => match (body.recall, body.store) {
+            (Some(preset), None) if preset <= 99 => {
+                Ok(PtzCommand::RecallPreset { preset, speed: body.speed.unwrap_or(1.0) })
+            }
+            (None, Some(preset)) if preset <= 99 => Ok(PtzCommand::StorePreset(preset)),
+            _ => bad("expected recall or store, with a preset from 0 to 99"),
+        },
+        _ => Err((StatusCode::NOT_FOUND, "expected pan_tilt, zoom or preset")),
+    }
+}
+
+/// Drive a PTZ camera: `pan_tilt`, `zoom` or `preset`. Answers once the camera
+/// took the command.
+#[utoipa::path(
+    post,
+    path = "/ptz/{source}/{control}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        ("control" = String, Path, description = "`pan_tilt`, `zoom` or `preset`")
+    ),
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The camera took the command",
+        content_type = "application/json")
+    )
+)]
+async fn post_ptz(
+    Path((source_name, control)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let body: PtzBody = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            let message = format!("invalid PTZ request: {e}");
+            return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    let command = authorize(&state, token, &source_name, Action::Ptz)
+        .and_then(|()| ptz_command(&control, body));
+    let command = match command {
+        Ok(command) => command,
+        Err((status, message)) => return outcome_response(Outcome::error(status, message), false),
+    };
+    let outcome = match state.commands.ptz(&source_name, command).await {
+        Ok(()) => Outcome::done(),
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+/// A `POST /tally/<source>` body.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TallyBody {
+    program: bool,
+    preview: bool,
+}
+
+/// Signal on-air state to a source's sender, e.g. to light a camera's tally lamp.
+#[utoipa::path(
+    post,
+    path = "/tally/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    request_body(content = Object, content_type = "application/json"),
+    responses((status = 200, description = "The tally sent", content_type = "application/json"))
+)]
+async fn post_tally(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let body: TallyBody = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            let message = format!("invalid tally request: {e}");
+            return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source_name, Action::Control) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let tally = Tally { on_program: body.program, on_preview: body.preview };
+    let outcome = match state.commands.tally(&source_name, tally) {
+        Ok(()) => Outcome::done(),
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+/// `state` as JSON, without sources the token may not view.
+fn favorites_response(
+    state: &AppState,
+    token: Option<&str>,
+    mut favorites: FavoritesState,
+) -> Response {
+    let visible = |name: &String| state.access.check(token, name, Action::View).is_ok();
+    favorites.starred.retain(visible);
+    favorites.order.retain(visible);
+    let json = serde_json::to_string(&favorites).unwrap_or_else(|_| "{}".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// The starred sources and the source order.
+#[utoipa::path(
+    get,
+    path = "/api/favorites",
+    responses(
+        (status = 200, description = "The starred sources and the order",
+        content_type = "application/json")
+    )
+)]
+async fn get_favorites(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    favorites_response(&state, token, state.favorites.state())
+}
+
+/// A `PATCH /api/favorites` body: what to replace.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FavoritesBody {
+    starred: Option<BTreeSet<String>>,
+    order: Option<Vec<String>>,
+}
+
+/// Replace the starred sources and/or the order, e.g. `{"order": ["CAM 2",
+/// "CAM 1"]}`. Every operator sees the change, so it needs `control` for all
+/// sources.
+#[utoipa::path(
+    patch,
+    path = "/api/favorites",
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The starred sources and the order",
+        content_type = "application/json")
+    )
+)]
+async fn patch_favorites(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let body: FavoritesBody = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            let message = format!("invalid favorites: {e}");
+            return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    if !state.access.allows_all_sources(token, Action::Control) {
+        return outcome_response(Outcome::error(StatusCode::FORBIDDEN, "forbidden"), false);
+    }
+    let resolve = |names: Vec<String>| names.into_iter().map(|n| state.source_name(n));
+    let starred = body.starred.map(|starred| resolve(starred.into_iter().collect()).collect());
+    let order = body.order.map(|order| resolve(order).collect());
+    match state.favorites.replace(starred, order) {
+        Ok(favorites) => favorites_response(&state, token, favorites),
+        Err(e) => outcome_response(Outcome::error(StatusCode::INTERNAL_SERVER_ERROR, &e), false),
+    }
+}
+
+/// Star a source.
+#[utoipa::path(
+    put,
+    path = "/api/favorites/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "The starred sources and the order",
+        content_type = "application/json")
+    )
+)]
+async fn put_favorite(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    set_favorite(&state, &headers, query, &source_name, true)
+}
+
+/// Unstar a source.
+#[utoipa::path(
+    delete,
+    path = "/api/favorites/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "The starred sources and the order",
+        content_type = "application/json")
+    )
+)]
+async fn delete_favorite(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    set_favorite(&state, &headers, query, &source_name, false)
+}
+
+fn set_favorite(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: TokenQuery,
+    source_name: &str,
+    starred: bool,
+) -> Response {
+    let token = request_token(headers, &query.token);
+    if let Err((status, message)) = authorize(state, token, source_name, Action::Control) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    match state.favorites.set_starred(source_name, starred) {
+        Ok(favorites) => favorites_response(state, token, favorites),
+        Err(e) => outcome_response(Outcome::error(StatusCode::INTERNAL_SERVER_ERROR, &e), false),
+    }
+}
+
+/// A `POST /record/<source>/start` body; may be left out.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordBody {
+    format: Option<Format>,
+    /// A fixed frame rate for an AVI recording, instead of `--record-fps`.
+    fps: Option<u32>,
+}
+
+/// Start (`start`) or stop (`stop`) recording a source in `--record-dir`, as
+/// MJPEG-in-AVI unless the body asks for `{"format": "raw"}`.
+#[utoipa::path(
+    post,
+    path = "/record/{source}/{action}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        ("action" = String, Path, description = "`start` or `stop`")
+    ),
+    request_body(content = Object, content_type = "application/json"),
+    responses((status = 200, description = "The recording", content_type = "application/json"))
+)]
+async fn post_record(
+    Path((source_name, action)): Path<(String, String)>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let body = if body.is_empty() {
+        RecordBody { format: None, fps: None }
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                let message = format!("invalid record request: {e}");
+                return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+            }
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source_name, Action::Record) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let result = match action.as_str() {
+        "start" => {
+            let format = body.format.unwrap_or(Format::Avi);
+            state.commands.start_recording(&source_name, format, body.fps)
+        }
+        "stop" => state.commands.stop_recording(&source_name).await,
+        _ => Err((StatusCode::NOT_FOUND, "expected start or stop".to_string())),
+    };
+    let outcome = match result {
+        Ok(mut extra) => {
+            extra["ok"] = serde_json::json!(true);
+            Outcome { status: StatusCode::OK, body: extra.to_string() }
+        }
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+#[derive(Serialize)]
+struct RecordingJson<'a> {
+    #[serde(flatten)]
+    file: &'a RecordingFile,
+    source: String,
+}
+
+/// The source a recording is of, for access checks: the one whose name gives
+/// the file's name, else that part of the file name itself.
+fn recorded_source(state: &AppState, file: &RecordingFile) -> String {
+    let sources = state.sources.read().unwrap();
+    let Some(stem) = file.stem() else {
+        // Timestamps in a custom format: the longest name the file's starts with.
+        let named = sources
+            .iter()
+            .filter(|s| file.name.starts_with(&format!("{}-", sanitize(&s.name))))
+            .max_by_key(|s| s.name.len());
+        return named.map_or_else(|| file.name.clone(), |source| source.name.clone());
+    };
+    match sources.iter().find(|s| sanitize(&s.name) == stem) {
+        Some(source) => source.name.clone(),
+        None => stem.to_string(),
+    }
+}
+
+/// The recordings in `--record-dir` of sources the token may record, by name.
+#[utoipa::path(
+    get,
+    path = "/recordings",
+    responses((status = 200, description = "The recordings", content_type = "application/json"))
+)]
+async fn get_recordings(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    let files = match state.commands.recordings() {
+        Ok(files) => files,
+        Err(e) => {
+            let message = format!("can't list recordings: {e}");
+            let outcome = Outcome::error(StatusCode::INTERNAL_SERVER_ERROR, &message);
+            return outcome_response(outcome, false);
+        }
+    };
+    let listed: Vec<RecordingJson> = files
+        .iter()
+        .map(|file| RecordingJson { file, source: recorded_source(&state, file) })
+        .filter(|r| state.access.check(token, &r.source, Action::Record).is_ok())
+        .collect();
+    let json = serde_json::to_string(&listed).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// Download a recording. An AVI file still being written won't play yet.
+#[utoipa::path(
+    get,
+    path = "/recordings/{file}",
+    params(("file" = String, Path, description = "The recording's file name, as listed")),
+    responses((status = 200, description = "The file", content_type = "application/octet-stream"))
+)]
+async fn get_recording(
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some((path, format)) = state.commands.recording(&name) else {
+        return (StatusCode::NOT_FOUND, "recording not found").into_response();
+    };
+    let file = RecordingFile { name, bytes: 0, modified: 0 };
+    let token = request_token(&headers, &query.token);
+    let source = recorded_source(&state, &file);
+    if let Err(rejection) = authorize(&state, token, &source, Action::Record) {
+        return rejection.into_response();
+    }
+    let opened = match tokio::fs::File::open(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            let message = format!("can't read {}: {e}", file.name);
+            return (StatusCode::INTERNAL_SERVER_ERROR, message).into_response();
+        }
+    };
+    let len = opened.metadata().await.map_or(0, |m| m.len());
+    // A recording still being written grows; send what there was.
+    let opened = tokio::io::AsyncReadExt::take(opened, len);
+    let chunks = stream::unfold(Some(opened), |opened| async move {
+        use tokio::io::AsyncReadExt;
+        let mut opened = opened?;
+        let mut buf = vec![0; 64 * 1024];
+        match opened.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some(opened)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    let mut response = Response::new(Body::from_stream(chunks));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    let disposition = format!("attachment; filename=\"{}\"", file.name);
+    let disposition = HeaderValue::from_str(&disposition)
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"));
+    headers.insert(header::CONTENT_DISPOSITION, disposition);
+    response
+}
+
+/// The last seconds of a source as MJPEG-in-AVI, from its `--preroll` buffer.
+#[utoipa::path(
+    get,
+    path = "/preroll/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses((status = 200, description = "The AVI file", content_type = "video/x-msvideo"))
+)]
+async fn get_preroll(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::Record) {
+        return rejection.into_response();
+    }
+    let Some(preroll) = state.receiver_manager.preroll(&source_name) else {
+        return (StatusCode::NOT_FOUND, "no pre-roll for this source").into_response();
+    };
+    let avi = match preroll.avi() {
+        Some(Ok(avi)) => avi,
+        Some(Err(e)) => {
+            let message = format!("can't write pre-roll: {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, message).into_response();
+        }
+        None => return (StatusCode::NOT_FOUND, "no frames buffered").into_response(),
+    };
+    let times = state.receiver_manager.time_format(&source_name);
+    let timestamp = times.file_timestamp(SystemTime::now());
+    let name = format!("{}-preroll-{timestamp}.avi", sanitize(&source_name));
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{name}\""))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"));
+    let content_type = HeaderValue::from_static(Format::Avi.content_type());
+    let headers = [
+        (header::CONTENT_TYPE, content_type),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    (headers, avi).into_response()
+}
+
+/// Change a source's quality, fps cap or bandwidth while it runs, e.g.
+/// `{"max_fps": 10, "low_bandwidth": true}`. Viewers stay connected.
+#[utoipa::path(
+    patch,
+    path = "/admin/receivers/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The receivers changed", content_type = "application/json")
+    )
+)]
+async fn patch_receiver(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let tuning: Tuning = match serde_json::from_slice(&body) {
+        Ok(tuning) => tuning,
+        Err(e) => {
+            let message = format!("invalid settings: {e}");
+            return outcome_response(Outcome::error(StatusCode::BAD_REQUEST, &message), false);
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source_name, Action::Control) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let outcome = match state.commands.tune(&source_name, tuning) {
+        Ok(settings) => Outcome { status: StatusCode::OK, body: settings.to_string() },
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+#[derive(Serialize)]
+struct ReceiverJson<'a> {
+    key: &'a str,
+    source: &'a str,
+    producer: &'static str,
+    clients: u64,
+    uptime_secs: f64,
+    recording: bool,
+    on_air: bool,
+    /// Set while an NDI® receiver tries to get its sender back.
+    reconnecting: bool,
+}
+
+/// The running receivers of sources the token may control, by key.
+#[utoipa::path(
+    get,
+    path = "/admin/receivers",
+    responses((status = 200, description = "Receivers by key", content_type = "application/json"))
+)]
+async fn get_receivers(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let mut active = state.receiver_manager.active();
+    active.retain(|(_, r)| state.access.check(token, &r.source_name, Action::Control).is_ok());
+    active.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let listed: Vec<ReceiverJson> = active
+        .iter()
+        .map(|(key, r)| ReceiverJson {
+            key,
+            source: &r.source_name,
+            producer: state.receiver_manager.producer(&r.source_name),
+            clients: r.client_count(),
+            uptime_secs: r.started.elapsed().as_secs_f64(),
+            recording: r.is_recording(),
+            on_air: r.is_on_air(),
+            reconnecting: matches!(*r.watch_link().borrow(), Link::Reconnecting { .. }),
+        })
+        .collect();
+    let json = serde_json::to_string(&listed).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+#[derive(Serialize)]
+struct ReceiverClientJson<'a> {
+    #[serde(flatten)]
+    client: ClientJson<'a>,
+    peer: Option<IpAddr>,
+    user_agent: Option<&'a str>,
+}
+
+/// A source's connected clients, with where they connect from, so the one
+/// falling behind can be found.
+#[utoipa::path(
+    get,
+    path = "/admin/receivers/{source}/clients",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses((status = 200, description = "The clients", content_type = "application/json"))
+)]
+async fn get_receiver_clients(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err(rejection) = authorize(&state, token, &source_name, Action::Control) {
+        return rejection.into_response();
+    }
+    let clients = state.clients.list();
+    let listed: Vec<ReceiverClientJson> = clients
+        .iter()
+        .filter(|c| c.source == source_name)
+        .map(|c| ReceiverClientJson {
+            client: ClientJson::new(&state, c),
+            peer: c.peer,
+            user_agent: c.user_agent.as_deref(),
+        })
+        .collect();
+    let json = serde_json::to_string(&listed).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// Have a source's receivers reconnect to its NDI® sender, e.g. one that
+/// stopped sending without dropping the connection. Viewers stay connected.
+#[utoipa::path(
+    post,
+    path = "/admin/receivers/{source}/restart",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "The receivers restarted", content_type = "application/json")
+    )
+)]
+async fn post_restart_receiver(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source_name, Action::Control) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let outcome = match state.commands.restart_receiver(&source_name) {
+        Ok(body) => Outcome { status: StatusCode::OK, body: body.to_string() },
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+/// Tear down a source's receivers, disconnecting everyone streaming it.
+#[utoipa::path(
+    delete,
+    path = "/admin/receivers/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses(
+        (status = 200, description = "The receivers torn down", content_type = "application/json")
+    )
+)]
+async fn delete_receiver(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source_name = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source_name, Action::Control) {
+        return outcome_response(Outcome::error(status, message), false);
+    }
+    let outcome = match state.commands.close_receiver(&source_name) {
+        Ok(body) => Outcome { status: StatusCode::OK, body: body.to_string() },
+        Err((status, message)) => Outcome::error(status, &message),
+    };
+    outcome_response(outcome, false)
+}
+
+#[derive(Serialize)]
+struct SrtOutputJson {
+    source: String,
+    #[serde(flatten)]
+    config: SrtConfig,
+    #[serde(flatten)]
+    status: srt::SrtStatus,
+}
+
+/// The sources pushed over SRT, with how each is doing.
+#[utoipa::path(
+    get,
+    path = "/admin/srt",
+    responses((status = 200, description = "The outputs", content_type = "application/json"))
+)]
+async fn get_srt_outputs(
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let token = request_token(&headers, &query.token);
+    let listed: Vec<SrtOutputJson> = state
+        .srt
+        .list()
+        .into_iter()
+        .filter(|(source, _, _)| state.access.check(token, source, Action::Control).is_ok())
+        .map(|(source, config, status)| SrtOutputJson { source, config, status })
+        .collect();
+    let json = serde_json::to_string(&listed).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+/// Push a source to a remote receiver over SRT, replacing the output it had.
+#[utoipa::path(
+    put,
+    path = "/admin/srt/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    request_body(content = Object, content_type = "application/json"),
+    responses((status = 201, description = "The output", content_type = "application/json"))
+)]
+async fn put_srt_output(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Response {
+    let source = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source, Action::Control) {
+        return (status, message).into_response();
+    }
+    let config: SrtConfig = match serde_json::from_slice(&body) {
+        Ok(config) => config,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid SRT output: {e}")).into_response();
+        }
+    };
+    if let Err(e) = config.validate() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    if !srt::AVAILABLE {
+        let message = "SRT output needs the ffmpeg feature";
+        return (StatusCode::NOT_IMPLEMENTED, message).into_response();
+    }
+    let replaced = state.srt.start(&source, config.clone());
+    let status = if replaced { StatusCode::OK } else { StatusCode::CREATED };
+    let output = SrtOutputJson { source, config, status: srt::SrtStatus::default() };
+    let json = serde_json::to_string(&output).unwrap_or_default();
+    (status, [(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+/// Stop pushing a source over SRT.
+#[utoipa::path(
+    delete,
+    path = "/admin/srt/{source}",
+    params(("source" = String, Path, description = "The source's name, id or alias")),
+    responses((status = 204, description = "Stopped"))
+)]
+async fn delete_srt_output(
+    Path(source_name): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TokenQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let source = state.source_name(source_name);
+    let token = request_token(&headers, &query.token);
+    if let Err((status, message)) = authorize(&state, token, &source, Action::Control) {
+        return (status, message).into_response();
+    }
+    if !state.srt.stop(&source) {
+        return (StatusCode::NOT_FOUND, "no SRT output for this source").into_response();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The host (and port) the client addressed, for URLs back to this server.
+fn request_host(headers: &HeaderMap) -> &str {
+    headers.get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or("localhost")
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HomeAssistantQuery {
+    #[param(ignore)]
+    token: Option<String>,
+    /// `go2rtc` for go2rtc's `streams:` as YAML instead of JSON.
+    format: Option<String>,
+}
+
+/// Every source the token may view as a Home Assistant camera, starred ones
+/// first, or as go2rtc streams.
+#[utoipa::path(
+    get,
+    path = "/integrations/homeassistant",
+    params(HomeAssistantQuery),
+    responses(
+        (status = 200, description = "The cameras, or go2rtc streams as YAML",
+        content_type = "application/json")
+    )
+)]
+async fn get_homeassistant(
+    headers: HeaderMap,
+    Query(query): Query<HomeAssistantQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let token = request_token(&headers, &query.token);
+    let base = format!("http://{}", request_host(&headers));
+    let sources = state.sources.read().unwrap();
+    let mut names: Vec<&str> = sources
+        .iter()
+        .map(|s| s.name.as_str())
+        .filter(|name| state.access.check(token, name, Action::View).is_ok())
+        .collect();
+    state.favorites.sort(&mut names);
+    let cameras: Vec<homeassistant::Camera> =
+        names.into_iter().map(|name| homeassistant::Camera::new(name, &base, token)).collect();
+    let (content_type, body) = match query.format.as_deref() {
+        None | Some("json") => {
+            let json = serde_json::to_string(&cameras).unwrap_or_else(|_| "[]".to_string());
+            ("application/json", json)
+        }
+        Some("go2rtc") => ("application/yaml", homeassistant::go2rtc_streams(&cameras)),
+        Some(_) => return (StatusCode::BAD_REQUEST, "format is json or go2rtc").into_response(),
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+/// A source's ONVIF device or media service, answering SOAP requests as the
+/// camera the source is to ONVIF clients. Their credentials come in the SOAP
+/// header, so this checks its own.
+#[utoipa::path(
+    post,
+    path = "/onvif/{source}/{service}",
+    params(
+        ("source" = String, Path, description = "The source's name, id or alias"),
+        ("service" = String, Path, description = "`device_service` or `media_service`")
+    ),
+    request_body(content = String, content_type = "application/soap+xml"),
+    responses(
+        (status = 200, description = "The SOAP response", content_type = "application/soap+xml")
+    )
+)]
+async fn onvif_service(
+    Path((source, service)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    body: String,
+) -> Response {
+    let soap = |status: StatusCode, body: String| {
+        let content_type = [(header::CONTENT_TYPE, "application/soap+xml; charset=utf-8")];
+        (status, content_type, onvif::envelope(&body)).into_response()
+    };
+    let answer: fn(&str, &Camera) -> Option<String> = match service.as_str() {
+        "device_service" => onvif::device_service,
+        "media_service" => onvif::media_service,
+        _ => return (StatusCode::NOT_FOUND, "no such ONVIF service").into_response(),
+    };
+    let Some(request) = SoapRequest::parse(&body) else {
+        let fault = onvif::fault("ter:InvalidArgVal", "expected a SOAP request");
+        return soap(StatusCode::BAD_REQUEST, fault);
+    };
+    let source_name = state.source_name(source);
+    if !request.is_open() {
+        let query = None;
+        let token = request.token(&state.access).or(request_token(&headers, &query));
+        if let Err((status, reason)) = authorize(&state, token, &source_name, Action::View) {
+            return soap(status, onvif::fault("ter:NotAuthorized", reason));
+        }
+    }
+    if !state.sources.read().unwrap().iter().any(|s| s.name == source_name) {
+        return (StatusCode::NOT_FOUND, "source not found").into_response();
+    }
+    match answer(request.operation, &onvif_camera(&state, &headers, &source_name)) {
+        Some(response) => soap(StatusCode::OK, response),
+        None => {
+            let fault = onvif::fault("ter:ActionNotSupported", "not supported by this camera");
+            soap(StatusCode::BAD_REQUEST, fault)
+        }
+    }
+}
+
+/// The source `name` as an ONVIF camera, at the address the client used.
+fn onvif_camera(state: &AppState, headers: &HeaderMap, name: &str) -> Camera {
+    let host = request_host(headers);
+    let id = discovery::source_id(name);
+    let stream_uri = match state.rtsp_port {
+        Some(port) => {
+            let with_port = host.rsplit_once(':').filter(|(_, port)| !port.contains(']'));
+            format!("rtsp://{}:{port}/{id}", with_port.map_or(host, |(hostname, _)| hostname))
+        }
+        None => format!("http://{host}/stream/{id}"),
+    };
+    let settings = state.receiver_manager.encode_settings(name);
+    let stats = state.receiver_manager.get(name).map(|shared| shared.stats.clone());
+    let input = stats.as_ref().and_then(|stats| *stats.input_format.lock().unwrap());
+    let input_fps = stats.as_ref().and_then(|stats| *stats.input_fps.lock().unwrap());
+    // Until the source has been received, a guess at what it is.
+    let (width, height) = input.map_or((1920, 1080), |(width, height, _)| (width, height));
+    let fps = match settings.max_fps {
+        0 => input_fps.map_or(25, |fps| fps.round() as u32),
+        max_fps => max_fps,
+    };
+    Camera {
+        name: name.to_string(),
+        base: format!("http://{host}/onvif/{id}"),
+        stream_uri,
+        snapshot_uri: format!("http://{host}/snapshot/{id}"),
+        width,
+        height,
+        fps,
+        quality: settings.jpeg_quality,
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LogsQuery {
+    #[param(ignore)]
+    token: Option<String>,
+    /// This level and more severe ones only.
+    level: Option<String>,
+    /// Only events about this source.
+    source: Option<String>,
+    /// Only events after this `seq`.
+    after: Option<u64>,
+    /// The newest this many events.
+    limit: Option<usize>,
+}
+
+/// Recent log events, oldest first: `?level=warn` for warnings and errors only,
+/// `?source=` for one source's, `?after=<seq>` for those since the last poll
+/// and `?limit=` for only the newest. Events about a source need the `control`
+/// action for it, the rest `control` for all sources.
+#[utoipa::path(
+    get,
+    path = "/admin/logs",
+    params(LogsQuery),
+    responses((status = 200, description = "The events", content_type = "application/json"))
+)]
+async fn get_logs(
+    headers: HeaderMap,
+    Query(query): Query<LogsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(logs) = &state.logs else {
+        return (StatusCode::NOT_FOUND, "logs aren't kept").into_response();
+    };
+    let level = match query.level.as_deref().map(str::parse) {
+        None => None,
+        Some(Ok(level)) => Some(level),
+        Some(Err(_)) => {
+            let message = "level must be error, warn, info, debug or trace";
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
+    };
+    let token = request_token(&headers, &query.token);
+    let everything = state.access.allows_all_sources(token, Action::Control);
+    let mut entries = logs.query(&LogQuery {
+        level,
+        source: query.source,
+        after: query.after,
+        limit: None,
+    });
+    entries.retain(|e| match &e.source {
+        Some(source) => state.access.check(token, source, Action::Control).is_ok(),
+        None => everything,
+    });
+    let skip = query.limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    let json = serde_json::to_string(&entries[skip..]).unwrap_or_else(|_| "[]".to_string());
+    ([(header::CONTENT_TYPE, "application/json")], json).into_response()
+}
+
+fn outcome_response(outcome: Outcome, replayed: bool) -> Response {
+    let mut response = (
+        outcome.status,
+        [(header::CONTENT_TYPE, "application/json")],
+        outcome.body,
+    )
+        .into_response();
+    if replayed {
+        let value = header::HeaderValue::from_static("true");
+        response.headers_mut().insert("idempotent-replayed", value);
+    }
+    response
+}
+
+/// Also hands out the page's client id cookie, so its streams share one id.
+async fn test_page(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let (_, cookie) = client_id(&state, &headers, &None);
+    with_cookie(Html(TEST_PAGE_HTML).into_response(), cookie)
+}
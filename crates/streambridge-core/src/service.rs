@@ -0,0 +1,341 @@
+//! Running unattended at boot: in the background with a pid file on Unix
+//! (`--daemon`), as a Windows service elsewhere (`install-service`). Without a
+//! console, log events go to the system's log instead: syslog, which journald
+//! collects, or the Windows event log.
+
+use crate::logs;
+use std::path::{Path, PathBuf};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// The Windows service's name, and the name log events are filed under.
+pub const SERVICE_NAME: &str = "StreamBridge";
+
+/// Log events sent to the system's log. Add it to the tracing subscriber as a
+/// layer.
+pub struct SystemLog {
+    /// The event source, as the address of its handle.
+    #[cfg(windows)]
+    source: usize,
+}
+
+#[cfg(unix)]
+impl SystemLog {
+    pub fn new() -> Self {
+        // openlog keeps the identity's address rather than copying it.
+        // SAFETY: a static, NUL-terminated identity.
+        unsafe { libc::openlog(c"streambridge".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+        Self {}
+    }
+
+    fn write(&self, level: Level, message: &str) {
+        let priority = match level {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            _ => libc::LOG_DEBUG,
+        };
+        let Ok(message) = std::ffi::CString::new(message.replace('\0', " ")) else {
+            return;
+        };
+        // SAFETY: a plain %s format, with a NUL-terminated string for it.
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+    }
+}
+
+#[cfg(windows)]
+impl SystemLog {
+    pub fn new() -> Self {
+        use windows_sys::Win32::System::EventLog::RegisterEventSourceW;
+        let name = wide(SERVICE_NAME);
+        // SAFETY: a NUL-terminated name; a null server is the local machine.
+        let source = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+        Self { source: source as usize }
+    }
+
+    fn write(&self, level: Level, message: &str) {
+        use windows_sys::Win32::System::EventLog::{
+            ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+        };
+        if self.source == 0 {
+            return;
+        }
+        let kind = match level {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message = wide(message);
+        let strings = [message.as_ptr()];
+        // SAFETY: one NUL-terminated string and no raw data, for a source
+        // registered in `new`.
+        unsafe {
+            ReportEventW(
+                self.source as _,
+                kind,
+                0,
+                0,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+impl SystemLog {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn write(&self, _: Level, _: &str) {}
+}
+
+impl Default for SystemLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SystemLog {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        let message = logs::event_message(event);
+        self.write(*metadata.level(), &format!("{}: {message}", metadata.target()));
+    }
+}
+
+/// `s` as a NUL-terminated UTF-16 string.
+#[cfg(windows)]
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Detach from the terminal: carry on in a background process of a new
+/// session, with standard input and output on `/dev/null`, while the one that
+/// was started exits. Threads don't survive it, so call it before starting any.
+/// The working directory stays as it was, so relative paths keep working.
+#[cfg(unix)]
+pub fn daemonize() -> Result<(), String> {
+    // SAFETY: with no other threads, the child is a whole copy; the parent
+    // leaves at once, without running anything else.
+    let fork = || match unsafe { libc::fork() } {
+        -1 => Err(format!("fork failed: {}", std::io::Error::last_os_error())),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    };
+    fork()?;
+    // Leave the terminal's session, then fork again so that, not being the
+    // session leader, the daemon can't pick up a terminal by opening one.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(format!("setsid failed: {}", std::io::Error::last_os_error()));
+    }
+    fork()?;
+    let null = unsafe { libc::open(c"/dev/null".as_ptr(), libc::O_RDWR) };
+    if null == -1 {
+        return Err(format!("can't open /dev/null: {}", std::io::Error::last_os_error()));
+    }
+    for fd in 0..3 {
+        unsafe { libc::dup2(null, fd) };
+    }
+    if null > 2 {
+        unsafe { libc::close(null) };
+    }
+    Ok(())
+}
+
+/// A file holding the process's id, for init scripts and the like to find it
+/// by. Removed when dropped.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Write this process's id to `path`, unless it holds the id of another
+    /// process that is still running.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let running = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<u32>().ok())
+            .filter(|&pid| pid != std::process::id() && is_running(pid));
+        if let Some(pid) = running {
+            return Err(format!("{} says streambridge runs as pid {pid}", path.display()));
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("can't write {}: {e}", path.display()))?;
+        Ok(Self(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists; EPERM means it does, as someone else's.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_: u32) -> bool {
+    // Without a cheap check, assume a leftover file is stale.
+    false
+}
+
+#[cfg(windows)]
+pub use self::windows::{install, run, uninstall};
+
+#[cfg(windows)]
+mod windows {
+    use super::SERVICE_NAME;
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tracing::error;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    /// Runs the server until told how long to drain for.
+    type Serve = Box<dyn FnOnce(oneshot::Receiver<Duration>) + Send>;
+
+    /// What [`run`] was given, for the service's main function to take.
+    static SERVE: Mutex<Option<(Duration, Serve)>> = Mutex::new(None);
+
+    /// Register the service to start at boot as this executable with `arguments`,
+    /// running as LocalSystem.
+    pub fn install(arguments: Vec<OsString>) -> Result<(), String> {
+        let executable_path =
+            std::env::current_exe().map_err(|e| format!("can't find the executable: {e}"))?;
+        let access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
+        let manager = ServiceManager::local_computer(None::<&str>, access)
+            .map_err(|e| format!("can't reach the service manager: {e}"))?;
+        let info = ServiceInfo {
+            name: SERVICE_NAME.into(),
+            display_name: SERVICE_NAME.into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path,
+            launch_arguments: arguments,
+            dependencies: Vec::new(),
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager
+            .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+            .map_err(|e| format!("can't create the service: {e}"))?;
+        service
+            .set_description("Bridges NDI\u{00ae} sources to MJPEG over HTTP")
+            .map_err(|e| format!("can't describe the service: {e}"))
+    }
+
+    /// Stop the service if it runs, and remove it.
+    pub fn uninstall() -> Result<(), String> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .map_err(|e| format!("can't reach the service manager: {e}"))?;
+        let access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE;
+        let service = manager
+            .open_service(SERVICE_NAME, access)
+            .map_err(|e| format!("can't open the service: {e}"))?;
+        service.delete().map_err(|e| format!("can't remove the service: {e}"))?;
+        let state = service.query_status().map(|status| status.current_state);
+        if state.is_ok_and(|state| state != ServiceState::Stopped) {
+            let _ = service.stop();
+        }
+        Ok(())
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Run as the service, calling `serve` with where the service manager's stop
+    /// request arrives: with `drain` to drain clients for, or none when the
+    /// machine shuts down. Returns once the service has stopped, or at once
+    /// when not started by the service manager.
+    pub fn run(
+        drain: Duration,
+        serve: impl FnOnce(oneshot::Receiver<Duration>) + Send + 'static,
+    ) -> Result<(), String> {
+        *SERVE.lock().unwrap() = Some((drain, Box::new(serve)));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .map_err(|e| format!("not started by the service manager: {e}"))
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = serve_as_service() {
+            error!("service: {}", e);
+        }
+    }
+
+    fn serve_as_service() -> windows_service::Result<()> {
+        let Some((drain, serve)) = SERVE.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let mut stop_tx = Some(stop_tx);
+        let handler = move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let stopped = matches!(control, ServiceControl::Stop);
+                let drain = if stopped { drain } else { Duration::ZERO };
+                if let Some(stop) = stop_tx.take() {
+                    let _ = stop.send(drain);
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let status = service_control_handler::register(SERVICE_NAME, handler)?;
+        let report = |current_state, controls_accepted| ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            // Drains plus the server's grace period for open connections.
+            wait_hint: drain + Duration::from_secs(10),
+            process_id: None,
+        };
+        let accepted = ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN;
+        status.set_service_status(report(ServiceState::Running, accepted))?;
+        serve(stop_rx);
+        status.set_service_status(report(ServiceState::Stopped, ServiceControlAccept::empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn pid_files_refuse_a_running_process_and_go_away() {
+        let path = std::env::temp_dir().join(format!("streambridge-{}.pid", std::process::id()));
+        let pid_file = PidFile::create(&path).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.trim(), std::process::id().to_string());
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // PID 1 always runs; a file naming it belongs to someone else.
+        std::fs::write(&path, "1\n").unwrap();
+        let refused = PidFile::create(&path).err().unwrap_or_default();
+        assert!(refused.contains("pid 1"), "{refused}");
+        std::fs::write(&path, "not a pid").unwrap();
+        drop(PidFile::create(&path).unwrap());
+        assert!(!path.exists());
+    }
+}
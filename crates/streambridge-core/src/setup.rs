@@ -0,0 +1,258 @@
+//! `streambridge init`: a few questions on the console, answered with Enter for
+//! the defaults, turned into a commented config file.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, Write};
+
+/// Quality presets offered by the wizard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Many viewers or remote links: small, light streams.
+    Low,
+    Standard,
+    /// Confidence monitoring on the local network.
+    High,
+}
+
+impl Preset {
+    const ALL: [Preset; 3] = [Preset::Low, Preset::Standard, Preset::High];
+
+    fn label(self) -> &'static str {
+        match self {
+            Preset::Low => "low (quality 50, 10 fps, 640 wide)",
+            Preset::Standard => "standard (quality 75, 25 fps, 1280 wide)",
+            Preset::High => "high (quality 90, 50 fps, full size)",
+        }
+    }
+
+    /// JPEG quality, fps cap and width cap.
+    fn settings(self) -> (i32, u32, Option<usize>) {
+        match self {
+            Preset::Low => (50, 10, Some(640)),
+            Preset::Standard => (75, 25, Some(1280)),
+            Preset::High => (90, 50, None),
+        }
+    }
+}
+
+/// What the operator chose.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answers {
+    pub port: u16,
+    pub preset: Preset,
+    /// Tokens for viewers and operators; `None` for open access.
+    pub tokens: Option<(String, String)>,
+    /// Sources viewers may watch; empty for all.
+    pub sources: Vec<String>,
+}
+
+/// Ask the setup questions on `output`, reading answers from `input`. `discovered`
+/// are the NDI® sources found on the network, offered for the viewers' grant.
+pub fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    discovered: &[String],
+) -> io::Result<Answers> {
+    let mut prompt = Prompt { input, output };
+
+    let port = loop {
+        let answer = prompt.line("HTTP port", "9550")?;
+        match answer.parse::<u16>() {
+            Ok(port) if port > 0 => break port,
+            _ => writeln!(prompt.output, "  Please enter a port number from 1 to 65535.")?,
+        }
+    };
+
+    writeln!(prompt.output, "Quality presets:")?;
+    for (i, preset) in Preset::ALL.iter().enumerate() {
+        writeln!(prompt.output, "  {}. {}", i + 1, preset.label())?;
+    }
+    let preset = loop {
+        let answer = prompt.line("Preset", "2")?;
+        match answer.parse::<usize>() {
+            Ok(n @ 1..=3) => break Preset::ALL[n - 1],
+            _ => writeln!(prompt.output, "  Please enter 1, 2 or 3.")?,
+        }
+    };
+
+    let question = "Require access tokens (advised unless the network is private)";
+    let auth = prompt.yes_no(question, true)?;
+    let mut sources = Vec::new();
+    if auth && !discovered.is_empty() {
+        writeln!(prompt.output, "Sources found on the network:")?;
+        for (i, name) in discovered.iter().enumerate() {
+            writeln!(prompt.output, "  {}. {}", i + 1, name)?;
+        }
+        sources = loop {
+            let answer = prompt.line("Sources viewers may watch (e.g. 1,3, or * for all)", "*")?;
+            match pick(&answer, discovered) {
+                Some(picked) => break picked,
+                None => writeln!(prompt.output, "  Please enter numbers from the list, or *.")?,
+            }
+        };
+    }
+    let tokens = auth.then(|| (token(), token()));
+    Ok(Answers { port, preset, tokens, sources })
+}
+
+/// The numbered entries of `list` named in a comma-separated `answer`; empty for `*`.
+fn pick(answer: &str, list: &[String]) -> Option<Vec<String>> {
+    if answer == "*" {
+        return Some(Vec::new());
+    }
+    let mut picked = Vec::new();
+    for part in answer.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let n: usize = part.parse().ok()?;
+        let name = list.get(n.checked_sub(1)?)?;
+        if !picked.contains(name) {
+            picked.push(name.clone());
+        }
+    }
+    (!picked.is_empty()).then_some(picked)
+}
+
+/// A random 32-character hex token.
+fn token() -> String {
+    // Each RandomState is seeded from the OS's random source.
+    (0..2).map(|_| format!("{:016x}", RandomState::new().hash_one(0u8))).collect()
+}
+
+struct Prompt<'a, R, W> {
+    input: &'a mut R,
+    output: &'a mut W,
+}
+
+impl<R: BufRead, W: Write> Prompt<'_, R, W> {
+    /// One answer, or `default` for an empty line. Fails at end of input.
+    fn line(&mut self, question: &str, default: &str) -> io::Result<String> {
+        write!(self.output, "{question} [{default}]: ")?;
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "setup cancelled"));
+        }
+        let answer = answer.trim();
+        Ok(if answer.is_empty() { default } else { answer }.to_string())
+    }
+
+    fn yes_no(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        loop {
+            let answer = self.line(question, if default { "Y/n" } else { "y/N" })?;
+            match answer.to_ascii_lowercase().as_str() {
+                "y/n" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => writeln!(self.output, "  Please answer y or n.")?,
+            }
+        }
+    }
+}
+
+/// The config file for `answers`, with a comment on every setting.
+pub fn render(answers: &Answers, path: &str) -> String {
+    let (quality, max_fps, max_width) = answers.preset.settings();
+    let mut config = format!(
+        "# StreamBridge configuration, written by `streambridge init`.\n\
+         # Start the server with: streambridge serve --config {path}\n\
+         # Flags given on the command line override the values here.\n\
+         \n\
+         # HTTP and WebSocket port; the test page is at http://<this machine>:{port}/\n\
+         port = {port}\n\
+         \n\
+         # Preset: {label}\n\
+         # JPEG quality from 1 (smallest) to 100 (best)\n\
+         jpeg_quality = {quality}\n\
+         # Frames per second sent to each viewer, at most\n\
+         max_fps = {max_fps}\n",
+        port = answers.port,
+        label = answers.preset.label(),
+    );
+    match max_width {
+        Some(width) => config.push_str(&format!(
+            "# Wider sources are scaled down by halving until they fit\n\
+             max_width = {width}\n"
+        )),
+        None => config.push_str("# Uncomment to scale wider sources down\n# max_width = 1920\n"),
+    }
+    match &answers.tokens {
+        None => config.push_str(
+            "\n# Access is open to anyone who can reach the port. To require tokens,\n\
+             # add [[grants]] sections; see the README.\n",
+        ),
+        Some((viewer, operator)) => {
+            let sources = if answers.sources.is_empty() {
+                "[\"*\"]".to_string()
+            } else {
+                let quoted: Vec<String> = answers
+                    .sources
+                    .iter()
+                    .map(|s| toml::Value::String(s.clone()).to_string())
+                    .collect();
+                format!("[{}]", quoted.join(", "))
+            };
+            config.push_str(&format!(
+                "\n# Viewers open http://<this machine>:{port}/?token={viewer}\n\
+                 [[grants]]\n\
+                 token = \"{viewer}\"\n\
+                 label = \"Viewers\"\n\
+                 actions = [\"view\", \"snapshot\"]\n\
+                 sources = {sources}\n\
+                 \n\
+                 # Operators can also record, move PTZ cameras and change settings.\n\
+                 [[grants]]\n\
+                 token = \"{operator}\"\n\
+                 label = \"Operators\"\n\
+                 actions = [\"*\"]\n",
+                port = answers.port,
+            ));
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn run(script: &str, discovered: &[&str]) -> (Answers, String) {
+        let discovered: Vec<String> = discovered.iter().map(|s| s.to_string()).collect();
+        let mut output = Vec::new();
+        let answers = ask(&mut script.as_bytes(), &mut output, &discovered).unwrap();
+        (answers, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn defaults_write_a_valid_config() {
+        let (answers, _) = run("\n\n\n\n", &["CAM (1)", "CAM (2)"]);
+        assert_eq!((answers.port, answers.preset), (9550, Preset::Standard));
+        assert!(answers.sources.is_empty());
+        let config = Config::parse(&render(&answers, "streambridge.toml")).unwrap();
+        assert_eq!((config.port, config.jpeg_quality), (Some(9550), Some(75)));
+        assert_eq!(config.max_width, Some(1280));
+        assert_eq!(config.grants.len(), 2);
+        assert_eq!(config.grants[0].sources, ["*"]);
+    }
+
+    #[test]
+    fn retries_bad_answers_and_limits_sources() {
+        let (answers, output) = run("99999\n8080\n4\n3\ny\n0\n2,\"\n2, 1\n", &["A \"1\"", "B"]);
+        assert!(output.contains("Please enter a port number"));
+        assert!(output.contains("Please enter 1, 2 or 3"));
+        assert!(output.contains("numbers from the list"));
+        assert_eq!((answers.port, answers.preset), (8080, Preset::High));
+        assert_eq!(answers.sources, ["B", "A \"1\""]);
+        let config = Config::parse(&render(&answers, "x.toml")).unwrap();
+        assert_eq!(config.max_width, None);
+        assert_eq!(config.grants[0].sources, ["B", "A \"1\""]);
+    }
+
+    #[test]
+    fn open_access_writes_no_grants() {
+        let (answers, _) = run("\n1\nn\n", &["A"]);
+        assert_eq!(answers.tokens, None);
+        let config = Config::parse(&render(&answers, "x.toml")).unwrap();
+        assert!(config.grants.is_empty());
+    }
+}
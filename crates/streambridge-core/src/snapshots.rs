@@ -0,0 +1,431 @@
+//! Snapshots taken on a schedule, e.g. of every source at the top of every hour,
+//! registered with `POST /api/snapshots/schedule` instead of an outside cron
+//! job. Schedules and their snapshots' index are kept in the [`Store`]; the
+//! images go to `<state dir>/snapshots/<source>/`, or without a state dir stay
+//! in memory. Each source's latest snapshot doubles as its thumbnail.
+
+use crate::automation::{self, sanitize};
+use crate::discovery::SourceList;
+use crate::receiver::ReceiverManager;
+use crate::store::Store;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// The document schedules are saved as in the [`Store`].
+const DOCUMENT: &str = "snapshot_schedules";
+/// Schedules one server keeps.
+const MAX_SCHEDULES: usize = 64;
+/// Snapshots a schedule keeps per source without a `keep` or `max_age`.
+pub const DEFAULT_KEEP: usize = 100;
+/// How often the scheduler looks for schedules that are due.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A `POST /api/snapshots/schedule` body. Times are Unix seconds.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRequest {
+    /// The sources to snapshot; every listed source when absent.
+    pub sources: Option<Vec<String>>,
+    /// When to take the first snapshots; now when absent.
+    pub at: Option<u64>,
+    /// Seconds between snapshots; just once when absent.
+    pub every: Option<u64>,
+    /// Take them at whole multiples of `every` since midnight UTC, e.g. at the
+    /// top of every hour for 3600.
+    #[serde(default)]
+    pub align: bool,
+    /// Snapshots kept per source, the oldest deleted first.
+    pub keep: Option<usize>,
+    /// Seconds before a snapshot is deleted.
+    pub max_age: Option<u64>,
+}
+
+impl ScheduleRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sources.as_ref().is_some_and(Vec::is_empty) {
+            return Err("sources must not be empty; leave it out for every source".to_string());
+        }
+        if self.every == Some(0) || self.keep == Some(0) || self.max_age == Some(0) {
+            return Err("every, keep and max_age must be at least 1".to_string());
+        }
+        if self.align && self.every.is_none() {
+            return Err("align needs every".to_string());
+        }
+        if self.align && self.at.is_some() {
+            return Err("at and align can't be combined".to_string());
+        }
+        Ok(())
+    }
+
+    /// When the first snapshots are due, for a schedule made at `now`.
+    fn first_run(&self, now: u64) -> u64 {
+        match (self.at, self.every) {
+            (Some(at), _) => at,
+            (None, Some(every)) if self.align => now.div_ceil(every) * every,
+            _ => now,
+        }
+    }
+}
+
+/// A registered schedule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u64,
+    pub sources: Option<Vec<String>>,
+    pub every: Option<u64>,
+    pub align: bool,
+    pub keep: Option<usize>,
+    pub max_age: Option<u64>,
+    /// When the next snapshots are due; `None` once a one-off is done.
+    pub next_at: Option<u64>,
+    /// Each source's snapshots, oldest first.
+    #[serde(default)]
+    shots: BTreeMap<String, Vec<Shot>>,
+}
+
+impl Schedule {
+    /// Whether it covers `source`.
+    pub fn covers(&self, source: &str) -> bool {
+        self.sources.as_ref().is_none_or(|sources| sources.iter().any(|s| s == source))
+    }
+
+    pub fn snapshot_count(&self) -> usize {
+        self.shots.values().map(Vec::len).sum()
+    }
+
+    /// After taking the snapshots due, when the next are due: the first time
+    /// after `now`, so a server that was down skips the ones it missed.
+    fn advance(&mut self, now: u64) {
+        self.next_at = match (self.next_at, self.every) {
+            (Some(next), Some(every)) => {
+                let missed = now.saturating_sub(next) / every;
+                Some(next + (missed + 1) * every)
+            }
+            _ => None,
+        };
+    }
+
+    /// Drop the snapshots past the retention rules as of `now`; returns the
+    /// sources and files to delete.
+    fn prune(&mut self, now: u64) -> Vec<(String, String)> {
+        let keep = match (self.keep, self.max_age) {
+            (None, None) => DEFAULT_KEEP,
+            (keep, _) => keep.unwrap_or(usize::MAX),
+        };
+        let oldest = self.max_age.map_or(0, |age| now.saturating_sub(age));
+        let mut expired = Vec::new();
+        for (source, shots) in &mut self.shots {
+            let over = shots.len().saturating_sub(keep);
+            let old = shots.iter().skip(over).take_while(|shot| shot.taken < oldest).count();
+            expired.extend(shots.drain(..over + old).map(|shot| (source.clone(), shot.file)));
+        }
+        self.shots.retain(|_, shots| !shots.is_empty());
+        expired
+    }
+}
+
+/// One snapshot taken by a schedule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shot {
+    /// Unix seconds.
+    pub taken: u64,
+    pub file: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SnapshotState {
+    next_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+/// Where the images are kept.
+enum Images {
+    /// `<dir>/<source>/<file>`, the source name made safe for a file name.
+    Dir(PathBuf),
+    /// By source and file name.
+    Memory(Mutex<HashMap<(String, String), Bytes>>),
+}
+
+impl Images {
+    fn path(dir: &Path, source: &str, file: &str) -> PathBuf {
+        dir.join(sanitize(source)).join(file)
+    }
+
+    async fn write(&self, source: &str, file: &str, jpeg: Bytes) -> Result<(), String> {
+        match self {
+            Self::Dir(dir) => automation::write(&Self::path(dir, source, file), &jpeg).await,
+            Self::Memory(images) => {
+                images.lock().unwrap().insert((source.to_string(), file.to_string()), jpeg);
+                Ok(())
+            }
+        }
+    }
+
+    async fn read(&self, source: &str, file: &str) -> Option<Bytes> {
+        match self {
+            Self::Dir(dir) => {
+                let image = tokio::fs::read(Self::path(dir, source, file)).await;
+                image.ok().map(Bytes::from)
+            }
+            Self::Memory(images) => {
+                images.lock().unwrap().get(&(source.to_string(), file.to_string())).cloned()
+            }
+        }
+    }
+
+    fn remove(&self, source: &str, file: &str) {
+        match self {
+            Self::Dir(dir) => {
+                let path = Self::path(dir, source, file);
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("failed to delete snapshot {}: {}", path.display(), e);
+                }
+            }
+            Self::Memory(images) => {
+                images.lock().unwrap().remove(&(source.to_string(), file.to_string()));
+            }
+        }
+    }
+}
+
+/// The snapshot schedules and what they took, saved to the store on every change.
+pub struct Snapshots {
+    store: Store,
+    images: Images,
+    state: Mutex<SnapshotState>,
+    /// Wakes the scheduler when a schedule is added.
+    added: Notify,
+}
+
+impl Snapshots {
+    /// The schedules last saved to `store`, keeping images in `dir`, or in
+    /// memory without one.
+    pub fn load(store: Store, dir: Option<PathBuf>) -> Result<Self, String> {
+        let state = store.load(DOCUMENT)?.unwrap_or_default();
+        let images = dir.map_or_else(|| Images::Memory(Mutex::default()), Images::Dir);
+        Ok(Self { store, images, state: Mutex::new(state), added: Notify::new() })
+    }
+
+    /// Register a schedule made at `now`. Fails only for an invalid request;
+    /// one that can't be saved lasts until the server stops.
+    pub fn add(&self, request: ScheduleRequest, now: u64) -> Result<Schedule, String> {
+        request.validate()?;
+        let mut state = self.state.lock().unwrap();
+        if state.schedules.len() >= MAX_SCHEDULES {
+            return Err(format!("at most {MAX_SCHEDULES} snapshot schedules"));
+        }
+        state.next_id += 1;
+        let schedule = Schedule {
+            id: state.next_id,
+            next_at: Some(request.first_run(now)),
+            sources: request.sources,
+            every: request.every,
+            align: request.align,
+            keep: request.keep,
+            max_age: request.max_age,
+            shots: BTreeMap::new(),
+        };
+        state.schedules.push(schedule.clone());
+        self.save(&state);
+        drop(state);
+        self.added.notify_one();
+        Ok(schedule)
+    }
+
+    /// Remove schedule `id` and delete its snapshots; `None` if there's none.
+    pub fn remove(&self, id: u64) -> Option<Schedule> {
+        let mut state = self.state.lock().unwrap();
+        let i = state.schedules.iter().position(|s| s.id == id)?;
+        let schedule = state.schedules.remove(i);
+        self.save(&state);
+        drop(state);
+        for (source, shots) in &schedule.shots {
+            shots.iter().for_each(|shot| self.images.remove(source, &shot.file));
+        }
+        Some(schedule)
+    }
+
+    pub fn schedule(&self, id: u64) -> Option<Schedule> {
+        self.state.lock().unwrap().schedules.iter().find(|s| s.id == id).cloned()
+    }
+
+    pub fn schedules(&self) -> Vec<Schedule> {
+        self.state.lock().unwrap().schedules.clone()
+    }
+
+    /// `source`'s snapshots from every schedule, with the schedule's id,
+    /// newest first.
+    pub fn list(&self, source: &str) -> Vec<(u64, Shot)> {
+        let state = self.state.lock().unwrap();
+        let mut shots: Vec<(u64, Shot)> = state
+            .schedules
+            .iter()
+            .flat_map(|s| {
+                let shots = s.shots.get(source).into_iter().flatten();
+                shots.map(|shot| (s.id, shot.clone()))
+            })
+            .collect();
+        shots.sort_by(|(_, a), (_, b)| (b.taken, &b.file).cmp(&(a.taken, &a.file)));
+        shots
+    }
+
+    /// The image of `source`'s snapshot `file`, or of its latest.
+    pub async fn read(&self, source: &str, file: Option<&str>) -> Option<Bytes> {
+        let mut shots = self.list(source).into_iter().map(|(_, shot)| shot);
+        let shot = match file {
+            Some(file) => shots.find(|shot| shot.file == file)?,
+            None => shots.next()?,
+        };
+        self.images.read(source, &shot.file).await
+    }
+
+    /// The schedules due at `now`, moved on to their next time.
+    fn due(&self, now: u64) -> Vec<Schedule> {
+        let mut state = self.state.lock().unwrap();
+        let mut due = Vec::new();
+        for schedule in &mut state.schedules {
+            if schedule.next_at.is_some_and(|next| next <= now) {
+                due.push(schedule.clone());
+                schedule.advance(now);
+            }
+        }
+        if !due.is_empty() {
+            self.save(&state);
+        }
+        due
+    }
+
+    /// Keep `jpeg`, taken for schedule `id` of `source` at `taken`.
+    async fn keep(&self, id: u64, source: &str, taken: u64, file: String, jpeg: Bytes) {
+        if let Err(e) = self.images.write(source, &file, jpeg).await {
+            warn!("[{}] scheduled snapshot: {}", source, e);
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let Some(schedule) = state.schedules.iter_mut().find(|s| s.id == id) else {
+            // Removed meanwhile.
+            drop(state);
+            self.images.remove(source, &file);
+            return;
+        };
+        schedule.shots.entry(source.to_string()).or_default().push(Shot { taken, file });
+        let expired = schedule.prune(taken);
+        self.save(&state);
+        drop(state);
+        expired.iter().for_each(|(source, file)| self.images.remove(source, file));
+    }
+
+    fn save(&self, state: &SnapshotState) {
+        if let Err(e) = self.store.save(DOCUMENT, state) {
+            warn!("snapshot schedules: {}", e);
+        }
+    }
+
+    /// Delete the snapshots past their `max_age` as of `now`.
+    fn expire(&self, now: u64) {
+        let mut state = self.state.lock().unwrap();
+        let aging = state.schedules.iter_mut().filter(|s| s.max_age.is_some());
+        let expired: Vec<_> = aging.flat_map(|s| s.prune(now)).collect();
+        if expired.is_empty() {
+            return;
+        }
+        self.save(&state);
+        drop(state);
+        expired.iter().for_each(|(source, file)| self.images.remove(source, file));
+    }
+}
+
+/// Unix seconds now.
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Take the schedules' snapshots as they come due, until the runtime stops.
+pub fn start(snapshots: Arc<Snapshots>, sources: SourceList, manager: Arc<ReceiverManager>) {
+    tokio::spawn(async move {
+        loop {
+            let now = unix_now();
+            snapshots.expire(now);
+            for schedule in snapshots.due(now) {
+                let names: Vec<String> = {
+                    let sources = sources.read().unwrap();
+                    let names = sources.iter().map(|s| s.name.clone());
+                    names.filter(|name| schedule.covers(name)).collect()
+                };
+                for name in names {
+                    let (snapshots, sources, manager) =
+                        (Arc::clone(&snapshots), sources.clone(), Arc::clone(&manager));
+                    tokio::spawn(async move {
+                        take(&snapshots, schedule.id, &name, &sources, &manager).await;
+                    });
+                }
+            }
+            tokio::select! {
+                () = tokio::time::sleep(TICK) => {}
+                () = snapshots.added.notified() => {}
+            }
+        }
+    });
+}
+
+/// Take schedule `id`'s snapshot of `source`.
+async fn take(
+    snapshots: &Snapshots,
+    id: u64,
+    source: &str,
+    sources: &SourceList,
+    manager: &Arc<ReceiverManager>,
+) {
+    let time = SystemTime::now();
+    match automation::next_frame(source, sources, manager).await {
+        Ok(jpeg) => {
+            let timestamp = manager.time_format(source).file_timestamp(time);
+            let file = format!("{id}-{timestamp}.jpg");
+            let taken = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            info!("[{}] scheduled snapshot {}", source, file);
+            snapshots.keep(id, source, taken, file, jpeg).await;
+        }
+        Err(e) => warn!("[{}] scheduled snapshot failed: {}", source, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shot(taken: u64) -> Shot {
+        Shot { taken, file: format!("{taken}.jpg") }
+    }
+
+    #[test]
+    fn schedules_run_on_time_and_keep_what_their_rules_allow() {
+        let hourly = ScheduleRequest { every: Some(3600), align: true, ..Default::default() };
+        assert_eq!(hourly.first_run(7_200), 7_200);
+        assert_eq!(hourly.first_run(7_201), 10_800);
+        assert!(ScheduleRequest { align: true, ..Default::default() }.validate().is_err());
+
+        let snapshots = Snapshots::load(Store::memory(), None).unwrap();
+        let mut schedule = snapshots.add(hourly, 7_201).unwrap();
+        assert!(snapshots.due(10_799).is_empty());
+        assert_eq!(snapshots.due(10_800).len(), 1);
+        // Down for two hours and a bit: the missed runs are skipped.
+        assert_eq!(snapshots.due(18_100).len(), 1);
+        assert_eq!(snapshots.schedule(schedule.id).unwrap().next_at, Some(21_600));
+
+        schedule.keep = Some(2);
+        schedule.max_age = Some(100);
+        schedule.shots.insert("cam".into(), vec![shot(10), shot(20), shot(150), shot(160)]);
+        let files = |expired: Vec<(String, String)>| -> Vec<String> {
+            expired.into_iter().map(|(_, file)| file).collect()
+        };
+        assert_eq!(files(schedule.prune(160)), ["10.jpg", "20.jpg"]);
+        assert_eq!(files(schedule.prune(255)), ["150.jpg"]);
+        assert_eq!(schedule.snapshot_count(), 1);
+    }
+}
@@ -0,0 +1,376 @@
+//! SRT output: a source's frames pushed to a remote receiver as H.264 in
+//! MPEG-TS over SRT, which retransmits what a lossy WAN link drops, where the
+//! HTTP and WebSocket endpoints only suit the LAN. Outputs are configured per
+//! source in the config file or with `PUT /admin/srt/<source>`, and reconnect
+//! until removed. The `ffmpeg` program (built with libsrt) encodes and sends
+//! the stream, so this needs the `ffmpeg` feature. The H.264 encoder is the
+//! source's `--encoder`'s hardware one where `ffmpeg` can open it, otherwise
+//! libx264.
+
+use crate::discovery::SourceList;
+#[cfg(feature = "ffmpeg")]
+use crate::encode;
+use crate::encode::EncoderKind;
+use crate::idle::Activity;
+use crate::receiver::ReceiverManager;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+
+/// Whether this build can send SRT.
+pub const AVAILABLE: bool = cfg!(feature = "ffmpeg");
+/// How long an output waits before reconnecting after a failure.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// The longest `latency` allowed, in milliseconds.
+const MAX_LATENCY_MS: u32 = 60_000;
+
+/// Which side opens the SRT connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SrtMode {
+    /// Connect out to a listening receiver.
+    #[default]
+    Caller,
+    /// Wait for the receiver to connect in.
+    Listener,
+}
+
+/// A source's `srt` table, or a `PUT /admin/srt/<source>` body.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SrtConfig {
+    #[serde(default)]
+    pub mode: SrtMode,
+    /// `host:port` to call, or the local `ip:port` to listen on.
+    pub address: String,
+    /// Milliseconds the receiver buffers to give retransmissions time; SRT's
+    /// own 120 when absent.
+    pub latency: Option<u32>,
+    /// Encrypts the stream; 10 to 79 characters.
+    #[serde(skip_serializing)]
+    pub passphrase: Option<String>,
+    /// Tells a receiver taking several streams which this is.
+    pub stream_id: Option<String>,
+    /// Kilobits per second of H.264; the encoder's choice when absent.
+    pub bitrate: Option<u32>,
+}
+
+impl SrtConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        // An IP address, or a host name: nothing that would end up in the URL's
+        // path or query, like `?` or `&`.
+        let host_name = |host: &str| {
+            !host.is_empty()
+                && host.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+        };
+        let valid = self.address.parse::<SocketAddr>().map(|addr| addr.port() > 0);
+        let valid = valid.unwrap_or_else(|_| {
+            self.address.rsplit_once(':').is_some_and(|(host, port)| {
+                host_name(host) && port.parse::<u16>().is_ok_and(|port| port > 0)
+            })
+        });
+        if !valid {
+            return Err(format!("SRT address needs a host and port, got \"{}\"", self.address));
+        }
+        if self.latency.is_some_and(|ms| ms > MAX_LATENCY_MS) {
+            return Err(format!("SRT latency must be at most {MAX_LATENCY_MS} ms"));
+        }
+        if self.passphrase.as_ref().is_some_and(|p| !(10..=79).contains(&p.chars().count())) {
+            return Err("SRT passphrase must be 10 to 79 characters".to_string());
+        }
+        if self.bitrate == Some(0) {
+            return Err("SRT bitrate must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// The `srt://` URL `ffmpeg` sends to.
+    fn url(&self) -> String {
+        let mode = match self.mode {
+            SrtMode::Caller => "caller",
+            SrtMode::Listener => "listener",
+        };
+        let mut url = format!("srt://{}?mode={mode}&transtype=live", self.address);
+        if let Some(ms) = self.latency {
+            // In microseconds.
+            url += &format!("&latency={}", u64::from(ms) * 1000);
+        }
+        if let Some(passphrase) = &self.passphrase {
+            url += &format!("&passphrase={}", query_value(passphrase));
+        }
+        if let Some(id) = &self.stream_id {
+            url += &format!("&streamid={}", query_value(id));
+        }
+        url
+    }
+
+    /// `ffmpeg`'s arguments to read JPEGs on stdin and send them as H.264
+    /// over SRT, made by `encoder`.
+    pub fn ffmpeg_args(&self, encoder: EncoderKind) -> Vec<String> {
+        let mut args: Vec<String> = [
+            "-v", "error", "-nostdin", "-fflags", "nobuffer", "-use_wallclock_as_timestamps",
+            "1", "-f", "mjpeg", "-i", "-", "-an",
+        ]
+        .map(String::from)
+        .to_vec();
+        args.extend(encoder.h264_args(false));
+        if let Some(kbps) = self.bitrate {
+            args.extend(["-b:v".to_string(), format!("{kbps}k")]);
+        }
+        args.extend(["-f".to_string(), "mpegts".to_string(), self.url()]);
+        args
+    }
+}
+
+/// `value` escaped for a URL's query.
+fn query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// How an output is doing.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SrtStatus {
+    /// Whether `ffmpeg` is running; otherwise the output waits to retry.
+    pub running: bool,
+    /// Frames handed to `ffmpeg` since the output was started.
+    pub frames: u64,
+    /// Why it last stopped.
+    pub error: Option<String>,
+}
+
+struct SrtOutput {
+    config: SrtConfig,
+    status: Arc<Mutex<SrtStatus>>,
+    task: AbortHandle,
+}
+
+/// Every source's SRT output.
+pub struct SrtOutputs {
+    sources: SourceList,
+    manager: Arc<ReceiverManager>,
+    activity: Arc<Activity>,
+    outputs: Mutex<BTreeMap<String, SrtOutput>>,
+}
+
+impl SrtOutputs {
+    pub fn new(
+        sources: SourceList,
+        manager: Arc<ReceiverManager>,
+        activity: Arc<Activity>,
+    ) -> Arc<Self> {
+        Arc::new(Self { sources, manager, activity, outputs: Mutex::default() })
+    }
+
+    /// Send `source` with `config`, which must be valid, replacing any output
+    /// it had. Returns whether it had one.
+    pub fn start(&self, source: &str, config: SrtConfig) -> bool {
+        let status = Arc::new(Mutex::new(SrtStatus::default()));
+        let task = tokio::spawn(run(
+            source.to_string(),
+            config.clone(),
+            Arc::clone(&status),
+            self.sources.clone(),
+            Arc::clone(&self.manager),
+            Arc::clone(&self.activity),
+        ));
+        let output = SrtOutput { config, status, task: task.abort_handle() };
+        let replaced = self.outputs.lock().unwrap().insert(source.to_string(), output);
+        replaced.inspect(|old| old.task.abort()).is_some()
+    }
+
+    /// Stop `source`'s output. Returns whether it had one.
+    pub fn stop(&self, source: &str) -> bool {
+        let output = self.outputs.lock().unwrap().remove(source);
+        output.inspect(|output| output.task.abort()).is_some()
+    }
+
+    /// Each output's source, settings and status, by source.
+    pub fn list(&self) -> Vec<(String, SrtConfig, SrtStatus)> {
+        let outputs = self.outputs.lock().unwrap();
+        outputs
+            .iter()
+            .map(|(source, o)| (source.clone(), o.config.clone(), o.status.lock().unwrap().clone()))
+            .collect()
+    }
+}
+
+/// Send `source` until aborted, starting over after each failure.
+async fn run(
+    source: String,
+    config: SrtConfig,
+    status: Arc<Mutex<SrtStatus>>,
+    sources: SourceList,
+    manager: Arc<ReceiverManager>,
+    activity: Arc<Activity>,
+) {
+    let _stream = activity.stream();
+    loop {
+        info!("[{}] SRT output to {} starting", source, config.address);
+        let result = send(&source, &config, &status, &sources, &manager).await;
+        let error = result.err().unwrap_or_else(|| "stream ended".to_string());
+        warn!("[{}] SRT output to {}: {}", source, config.address, error);
+        {
+            let mut status = status.lock().unwrap();
+            status.running = false;
+            status.error = Some(error);
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Feed `source`'s frames to `ffmpeg` until either is gone.
+#[cfg(feature = "ffmpeg")]
+async fn send(
+    source: &str,
+    config: &SrtConfig,
+    status: &Mutex<SrtStatus>,
+    sources: &SourceList,
+    manager: &Arc<ReceiverManager>,
+) -> Result<(), String> {
+    use std::process::Stdio;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::process::Command;
+
+    let found = sources.read().unwrap().iter().find(|s| s.name == source).cloned();
+    let shared = manager.get_or_create(&found.ok_or("source not found")?)?;
+    let mut rx = shared.subscribe();
+    let feed = Feed { shared, manager: Arc::clone(manager) };
+    let encoder = encode::h264_encoder(manager.encode_settings(source).encoder).await;
+    let mut child = Command::new("ffmpeg")
+        .args(config.ffmpeg_args(encoder))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run ffmpeg: {e}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    status.lock().unwrap().running = true;
+    let ended = loop {
+        tokio::select! {
+            frame = rx.recv() => {
+                let Some(frame) = frame else {
+                    break "source lost".to_string();
+                };
+                if stdin.write_all(&frame.data).await.is_err() {
+                    break "ffmpeg stopped reading".to_string();
+                }
+                status.lock().unwrap().frames += 1;
+            }
+            exit = child.wait() => {
+                break exit.map_or_else(|e| e.to_string(), |code| format!("ffmpeg {code}"));
+            }
+        }
+    };
+    drop((stdin, rx, feed));
+    let _ = child.kill().await;
+    let mut stderr = String::new();
+    if let Some(mut err) = child.stderr.take() {
+        let _ = err.read_to_string(&mut stderr).await;
+    }
+    match stderr.lines().rfind(|line| !line.trim().is_empty()) {
+        Some(line) => Err(format!("{ended}: {}", line.trim())),
+        None => Err(ended),
+    }
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+async fn send(
+    _source: &str,
+    _config: &SrtConfig,
+    _status: &Mutex<SrtStatus>,
+    _sources: &SourceList,
+    _manager: &Arc<ReceiverManager>,
+) -> Result<(), String> {
+    Err("SRT output needs the ffmpeg feature".to_string())
+}
+
+/// An output's subscription to its source; unsubscribes when dropped.
+#[cfg(feature = "ffmpeg")]
+struct Feed {
+    shared: Arc<crate::receiver::SharedReceiver>,
+    manager: Arc<ReceiverManager>,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for Feed {
+    fn drop(&mut self) {
+        self.shared.unsubscribe();
+        self.manager.maybe_remove(&self.shared.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_become_ffmpeg_srt_urls() {
+        let config: SrtConfig = toml::from_str(
+            r##"
+            address = "relay.example.com:9000"
+            latency = 800
+            passphrase = "correct horse&battery"
+            stream_id = "#!::r=live/cam1"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            config.url(),
+            "srt://relay.example.com:9000?mode=caller&transtype=live&latency=800000\
+             &passphrase=correct%20horse%26battery&streamid=%23%21%3A%3Ar%3Dlive%2Fcam1"
+        );
+        let args = config.ffmpeg_args(EncoderKind::Turbojpeg);
+        assert!(args.windows(2).any(|w| w == ["-c:v", "libx264"]));
+        assert_eq!(args[args.len() - 2..], ["mpegts".to_string(), config.url()]);
+
+        let listener = SrtConfig {
+            mode: SrtMode::Listener,
+            address: "0.0.0.0:9000".to_string(),
+            latency: None,
+            passphrase: None,
+            stream_id: None,
+            bitrate: Some(4000),
+        };
+        assert_eq!(listener.url(), "srt://0.0.0.0:9000?mode=listener&transtype=live");
+        assert!(listener.ffmpeg_args(EncoderKind::Qsv).windows(2).any(|w| w == ["-b:v", "4000k"]));
+        // The passphrase is never listed.
+        let json = serde_json::to_value(&config).unwrap();
+        assert!(json.get("passphrase").is_none());
+
+        let ipv6 = SrtConfig { address: "[::1]:9000".to_string(), ..listener.clone() };
+        assert_eq!(ipv6.validate(), Ok(()));
+        let invalid = [
+            "relay.example.com",
+            ":9000",
+            "relay:0",
+            "relay:port",
+            // Would add to or cut off the URL's query.
+            "relay:9000?mode=listener",
+            "relay:9000&passphrase=x",
+            "relay/x:9000",
+            "relay#x:9000",
+            "relay .example.com:9000",
+            "relay:9000 ",
+        ];
+        for address in invalid {
+            let config = SrtConfig { address: address.to_string(), ..listener.clone() };
+            assert!(config.validate().is_err(), "{address}");
+        }
+        let short = SrtConfig { passphrase: Some("short".to_string()), ..listener.clone() };
+        assert!(short.validate().unwrap_err().contains("passphrase"));
+    }
+}
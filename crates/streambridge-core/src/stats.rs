@@ -0,0 +1,178 @@
+use crate::loudness::LoudnessReading;
+use crate::ndi::{FourCCVideoType, StatusChange, Tally};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Per-source statistics counters.
+pub struct SourceStats {
+    pub frames_in: AtomicU64,
+    pub frames_out: AtomicU64,
+    pub encode_time_us: AtomicU64,
+    pub encode_count: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub dropped: AtomicU64,
+    pub clients: AtomicU64,
+    /// Latest loudness reading, if loudness measurement is enabled.
+    pub loudness: Mutex<Option<LoudnessReading>>,
+    /// Latest NDI connection status; `None` until the receiver reports one
+    /// (and always for replay sources).
+    pub ndi_status: Mutex<Option<StatusChange>>,
+    /// Attributes of the sender's `<ndi_capabilities>` announcement, if it sent one.
+    pub ndi_capabilities: Mutex<Option<BTreeMap<String, String>>>,
+    /// The sender's tally as it last echoed it, combined over all its receivers.
+    pub tally_echo: Mutex<Option<Tally>>,
+    /// Size and pixel format of the latest captured frame.
+    pub input_format: Mutex<Option<(usize, usize, FourCCVideoType)>>,
+    /// Frames per second arriving from the source, averaged over the last few.
+    pub input_fps: Mutex<Option<f64>>,
+    /// Whether motion detection sees motion now.
+    pub motion: AtomicBool,
+    /// Times motion started since the receiver did. Never reset.
+    pub motion_events: AtomicU64,
+    /// Frames clients skipped for falling behind. Never reset.
+    pub skipped: AtomicU64,
+    /// The quality and fps cap adaptive quality holds the source to, if on.
+    pub adapted: Mutex<Option<(i32, Option<f64>)>>,
+    pub stages: StageTimings,
+}
+
+impl SourceStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            frames_in: AtomicU64::new(0),
+            frames_out: AtomicU64::new(0),
+            encode_time_us: AtomicU64::new(0),
+            encode_count: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            clients: AtomicU64::new(0),
+            loudness: Mutex::new(None),
+            ndi_status: Mutex::new(None),
+            ndi_capabilities: Mutex::new(None),
+            tally_echo: Mutex::new(None),
+            input_format: Mutex::new(None),
+            input_fps: Mutex::new(None),
+            motion: AtomicBool::new(false),
+            motion_events: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            adapted: Mutex::new(None),
+            stages: StageTimings::default(),
+        })
+    }
+
+    /// Snapshot and reset counters. Returns (frames_in, frames_out, avg_encode_ms, kb_per_sec, dropped, clients).
+    pub fn snapshot_and_reset(&self, interval_secs: f64) -> StatsSnapshot {
+        let fi = self.frames_in.swap(0, Ordering::Relaxed);
+        let fo = self.frames_out.swap(0, Ordering::Relaxed);
+        let et = self.encode_time_us.swap(0, Ordering::Relaxed);
+        let ec = self.encode_count.swap(0, Ordering::Relaxed);
+        let bo = self.bytes_out.swap(0, Ordering::Relaxed);
+        let dr = self.dropped.swap(0, Ordering::Relaxed);
+        let cl = self.clients.load(Ordering::Relaxed);
+
+        let fps_in = fi as f64 / interval_secs;
+        let fps_out = fo as f64 / interval_secs;
+        let avg_encode_ms = if ec > 0 {
+            (et as f64 / ec as f64) / 1000.0
+        } else {
+            0.0
+        };
+        let kb_per_sec = (bo as f64 / 1024.0) / interval_secs;
+
+        StatsSnapshot {
+            clients: cl,
+            fps_in,
+            fps_out,
+            avg_encode_ms,
+            kb_per_sec,
+            dropped: dr,
+        }
+    }
+
+    /// A line about the source without resetting anything, for crash reports.
+    /// Skips whatever is locked rather than waiting.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} clients", self.clients.load(Ordering::Relaxed));
+        if let Ok(Some((width, height, fourcc))) = self.input_format.try_lock().as_deref() {
+            summary += &format!(", {width}x{height} {fourcc:?}");
+        }
+        let encode = &self.stages.encode;
+        if let Some(ms) = encode.avg_ms() {
+            summary += &format!(", {} frames encoded at {ms:.1} ms avg", encode.frames());
+        }
+        if let Ok(Some(status)) = self.ndi_status.try_lock().as_deref() {
+            summary += &format!(", NDI status {status:?}");
+        }
+        summary
+    }
+}
+
+/// Time spent in each stage of a source's pipeline, for `/api/pipeline`.
+#[derive(Default)]
+pub struct StageTimings {
+    pub record: StageTiming,
+    pub scale: StageTiming,
+    pub encode: StageTiming,
+    pub diff: StageTiming,
+    /// Drawing the burned-in overlay.
+    pub burn_in: StageTiming,
+    /// Drawing the viewer badge.
+    pub badge: StageTiming,
+    /// Looking for motion.
+    pub motion: StageTiming,
+    /// Watermarking, timed per viewer.
+    pub overlay: StageTiming,
+}
+
+/// Frames through one stage and the time they took. Unlike the counters above,
+/// these are never reset, so they average over the receiver's lifetime.
+#[derive(Default)]
+pub struct StageTiming {
+    frames: AtomicU64,
+    total_us: AtomicU64,
+}
+
+impl StageTiming {
+    /// Count one frame that entered the stage at `started`.
+    pub fn record(&self, started: Instant) {
+        let us = started.elapsed().as_micros() as u64;
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Time spent in the stage so far.
+    pub fn total(&self) -> Duration {
+        Duration::from_micros(self.total_us.load(Ordering::Relaxed))
+    }
+
+    /// Mean time per frame; `None` before the first frame.
+    pub fn avg_ms(&self) -> Option<f64> {
+        let frames = self.frames();
+        (frames > 0).then(|| self.total_us.load(Ordering::Relaxed) as f64 / frames as f64 / 1000.0)
+    }
+}
+
+pub struct StatsSnapshot {
+    pub clients: u64,
+    pub fps_in: f64,
+    pub fps_out: f64,
+    pub avg_encode_ms: f64,
+    pub kb_per_sec: f64,
+    pub dropped: u64,
+}
+
+impl std::fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} clients, {:.1} fps out, {:.1} fps in, {:.1} ms encode avg, {:.0} KB/s, {} dropped",
+            self.clients, self.fps_out, self.fps_in, self.avg_encode_ms, self.kb_per_sec, self.dropped,
+        )
+    }
+}
@@ -0,0 +1,54 @@
+//! State the server keeps across restarts, like the source favorites, as JSON
+//! files in `--state-dir`. Without one it lasts until the server stops.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+
+/// A directory of JSON documents, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Store {
+    dir: Option<PathBuf>,
+}
+
+impl Store {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir: Some(dir) }
+    }
+
+    /// A store that keeps nothing; every load finds nothing.
+    pub fn memory() -> Self {
+        Self::default()
+    }
+
+    /// The document `name`, or `None` if it was never saved.
+    pub fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, String> {
+        let Some(dir) = &self.dir else {
+            return Ok(None);
+        };
+        let path = dir.join(format!("{name}.json"));
+        let json = match std::fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+        };
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| format!("invalid {}: {e}", path.display()))
+    }
+
+    /// Replace the document `name`. A crash while saving leaves the old one.
+    pub fn save<T: Serialize>(&self, name: &str, value: &T) -> Result<(), String> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let path = dir.join(format!("{name}.json"));
+        let partial = dir.join(format!(".{name}.json.partial"));
+        let json = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(dir)
+            .and_then(|()| std::fs::write(&partial, json))
+            .and_then(|()| std::fs::rename(&partial, &path))
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,387 @@
+pub const TEST_PAGE_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>streambridge test</title>
+<style>
+  * { margin: 0; padding: 0; box-sizing: border-box; }
+  body { font-family: system-ui, sans-serif; background: #1a1a2e; color: #e0e0e0; padding: 20px; }
+  h1 { margin-bottom: 16px; font-size: 1.4em; color: #fff; }
+  .toolbar { margin-bottom: 16px; display: flex; gap: 8px; }
+  button {
+    padding: 6px 14px; border: 1px solid #444; border-radius: 4px;
+    background: #2a2a4a; color: #e0e0e0; cursor: pointer; font-size: 0.9em;
+  }
+  button:hover { background: #3a3a5a; }
+  #source-list { margin-bottom: 20px; }
+  .source-btn {
+    display: inline-block; margin: 4px; padding: 8px 16px;
+    border: 1px solid #555; border-radius: 4px; background: #2a2a4a;
+    color: #e0e0e0; cursor: pointer; font-size: 0.9em;
+  }
+  .source-btn:hover { background: #3a3a5a; }
+  .source-btn.active { border-color: #6c6cff; background: #3a3a6a; }
+  .source-btn .star { margin-right: 6px; color: #e0b050; }
+  #previews { display: flex; flex-wrap: wrap; gap: 16px; }
+  .preview {
+    background: #222244; border-radius: 6px; overflow: hidden;
+    border: 1px solid #333;
+  }
+  .preview-header {
+    padding: 8px 12px; font-size: 0.85em; display: flex;
+    justify-content: space-between; align-items: center; background: #1a1a3e;
+  }
+  .preview-close { cursor: pointer; color: #aaa; font-size: 1.1em; }
+  .preview-header a { color: #9a9aff; margin-left: 12px; font-size: 0.9em; }
+  .link-status { color: #e0b050; margin-left: 12px; font-size: 0.9em; }
+  .preview-close:hover { color: #fff; }
+  .preview img { display: block; max-width: 640px; height: auto; }
+  .frame { position: relative; }
+  .overlay { position: absolute; inset: 0; pointer-events: none; font-size: 0.75em; }
+  .overlay div { position: absolute; color: #0f0; border-color: #0f0; }
+  .overlay .box { border: 2px solid; }
+  .overlay .box span { position: absolute; bottom: 100%; left: -2px; background: rgba(0,0,0,.6); padding: 0 3px; }
+  .info { margin-top: 40px; max-width: 800px; }
+  .info h2 { font-size: 1.15em; color: #fff; margin: 24px 0 8px; border-bottom: 1px solid #333; padding-bottom: 4px; }
+  .info h2:first-child { margin-top: 0; }
+  .info p, .info ul { margin: 6px 0; line-height: 1.5; font-size: 0.9em; color: #ccc; }
+  .info ul { padding-left: 20px; }
+  .info code { background: #2a2a4a; padding: 1px 5px; border-radius: 3px; font-size: 0.85em; }
+  .info pre {
+    background: #16162e; border: 1px solid #333; border-radius: 4px;
+    padding: 12px; overflow-x: auto; font-size: 0.82em; line-height: 1.45; margin: 8px 0;
+  }
+  .info pre code { background: none; padding: 0; }
+  #logs { display: none; margin-bottom: 20px; }
+  #logs.open { display: block; }
+  #logs select, #logs input {
+    padding: 4px 8px; border: 1px solid #444; border-radius: 4px;
+    background: #2a2a4a; color: #e0e0e0; font-size: 0.85em; margin-right: 6px;
+  }
+  #log-lines {
+    margin-top: 8px; max-height: 300px; overflow-y: auto; background: #16162e;
+    border: 1px solid #333; border-radius: 4px; padding: 8px; font-size: 0.78em;
+  }
+  .log-warn { color: #e0b050; }
+  .log-error { color: #ff7070; }
+  .ndi-attr { margin-top: 32px; padding-top: 12px; border-top: 1px solid #333; font-size: 0.78em; color: #888; }
+</style>
+</head>
+<body>
+<h1>streambridge test</h1>
+<div class="toolbar">
+  <button onclick="refreshSources()">Refresh Sources</button>
+  <button onclick="clearAll()">Clear All</button>
+  <button onclick="toggleLogs()">Logs</button>
+</div>
+<div id="logs">
+  <select id="log-level" onchange="reloadLogs()">
+    <option value="">all levels</option>
+    <option value="info">info and up</option>
+    <option value="warn">warnings and errors</option>
+    <option value="error">errors</option>
+  </select>
+  <input id="log-source" placeholder="source" onchange="reloadLogs()">
+  <pre id="log-lines"></pre>
+</div>
+<div id="source-list"></div>
+<div id="previews"></div>
+<script>
+const wsProto = location.protocol === 'https:' ? 'wss:' : 'ws:';
+const baseUrl = location.origin;
+const wsBase = wsProto + '//' + location.host;
+let connections = {};
+// Pass the page's ?token= (or the one asked for) on to the API when the
+// server requires one.
+let token = new URLSearchParams(location.search).get('token')
+  || sessionStorage.getItem('streambridge_token');
+function withToken(url) {
+  if (!token) return url;
+  return url + (url.includes('?') ? '&' : '?') + 'token=' + encodeURIComponent(token);
+}
+
+async function refreshSources() {
+  try {
+    const res = await fetch(withToken(baseUrl + '/sources'));
+    if (res.status === 401) {
+      const entered = prompt(token ? 'Token rejected. Access token:' : 'Access token:');
+      if (!entered) return;
+      token = entered;
+      sessionStorage.setItem('streambridge_token', token);
+      return refreshSources();
+    }
+    const sources = await res.json();
+    const el = document.getElementById('source-list');
+    el.innerHTML = '';
+    // Starred sources come first, then the shared order; the star toggles it.
+    sources.forEach(({ name, starred }) => {
+      const btn = document.createElement('button');
+      btn.className = 'source-btn' + (connections[name] ? ' active' : '');
+      btn.dataset.source = name;
+      const star = document.createElement('span');
+      star.className = 'star';
+      star.textContent = starred ? '\u2605' : '\u2606';
+      star.title = starred ? 'Unstar' : 'Star';
+      star.onclick = (e) => { e.stopPropagation(); setStarred(name, !starred); };
+      btn.appendChild(star);
+      btn.appendChild(document.createTextNode(name));
+      btn.onclick = () => togglePreview(name);
+      el.appendChild(btn);
+    });
+  } catch (e) {
+    console.error('Failed to fetch sources:', e);
+  }
+}
+
+async function setStarred(name, starred) {
+  const url = withToken(baseUrl + '/api/favorites/' + encodeURIComponent(name));
+  const res = await fetch(url, { method: starred ? 'PUT' : 'DELETE' });
+  if (!res.ok) {
+    alert('Could not change the favorites: ' + res.status);
+    return;
+  }
+  refreshSources();
+}
+
+function togglePreview(name) {
+  if (connections[name]) {
+    closePreview(name);
+  } else {
+    openPreview(name);
+  }
+}
+
+function openPreview(name) {
+  const previews = document.getElementById('previews');
+  const div = document.createElement('div');
+  div.className = 'preview';
+  div.id = 'preview-' + CSS.escape(name);
+
+  const header = document.createElement('div');
+  header.className = 'preview-header';
+  header.innerHTML = '<span>' + name + '<a class="web-control" target="_blank" rel="noopener" hidden>web UI</a><span class="link-status" hidden></span></span><span class="preview-close" onclick="closePreview(\'' + name.replace(/'/g, "\\'") + '\')">&times;</span>';
+
+  const frame = document.createElement('div');
+  frame.className = 'frame';
+  const img = document.createElement('img');
+  const overlay = document.createElement('div');
+  overlay.className = 'overlay';
+  frame.appendChild(img);
+  frame.appendChild(overlay);
+  div.appendChild(header);
+  div.appendChild(frame);
+  previews.appendChild(div);
+
+  const url = withToken(wsBase + '/ws?annotations=true&source=' + encodeURIComponent(name));
+  const ws = new WebSocket(url, 'streambridge.v1');
+  ws.binaryType = 'arraybuffer';
+  const status = header.querySelector('.link-status');
+  ws.onmessage = (e) => {
+    if (typeof e.data === 'string') {
+      const message = JSON.parse(e.data);
+      if (message.type === 'reconnecting') {
+        status.textContent = 'reconnecting (attempt ' + message.attempt + ')';
+        status.hidden = false;
+      } else if (message.type === 'reconnected') {
+        status.hidden = true;
+      } else if (message.type === 'annotations') {
+        drawAnnotations(overlay, message);
+      }
+      return;
+    }
+    const blob = new Blob([e.data], { type: 'image/jpeg' });
+    const url = URL.createObjectURL(blob);
+    const oldUrl = img.src;
+    img.src = url;
+    if (oldUrl && oldUrl.startsWith('blob:')) URL.revokeObjectURL(oldUrl);
+  };
+  ws.onclose = (e) => {
+    console.log('WS closed for', name, e.code, e.reason);
+    delete connections[name];
+    updateButtons();
+  };
+  ws.onerror = () => { ws.close(); };
+
+  connections[name] = { ws, div };
+  updateButtons();
+  setTimeout(() => updateDetail(name), 1500);
+}
+
+// Draw posted annotations over a preview until newer ones come or their time is up.
+function drawAnnotations(overlay, annotations) {
+  overlay.replaceChildren();
+  const pct = (v) => (v * 100) + '%';
+  (annotations.boxes || []).forEach(b => {
+    const box = document.createElement('div');
+    box.className = 'box';
+    Object.assign(box.style, { left: pct(b.x), top: pct(b.y), width: pct(b.width), height: pct(b.height) });
+    if (b.color) box.style.color = box.style.borderColor = b.color;
+    if (b.label || b.score != null) {
+      const label = document.createElement('span');
+      label.textContent = [b.label, b.score != null ? b.score.toFixed(2) : null].filter(v => v != null).join(' ');
+      box.appendChild(label);
+    }
+    overlay.appendChild(box);
+  });
+  (annotations.texts || []).forEach(t => {
+    const text = document.createElement('div');
+    Object.assign(text.style, { left: pct(t.x), top: pct(t.y) });
+    if (t.color) text.style.color = t.color;
+    text.textContent = t.text;
+    overlay.appendChild(text);
+  });
+  clearTimeout(overlay.timer);
+  overlay.timer = setTimeout(() => overlay.replaceChildren(), annotations.ttl_ms || 1000);
+}
+
+// Show a link to the camera's own configuration page once the sender announces one.
+async function updateDetail(name) {
+  const conn = connections[name];
+  if (!conn) return;
+  try {
+    const res = await fetch(withToken(baseUrl + '/api/sources/' + encodeURIComponent(name)));
+    if (!res.ok) return;
+    const detail = await res.json();
+    const link = conn.div.querySelector('.web-control');
+    const url = detail.ndi && detail.ndi.web_control;
+    link.hidden = !url;
+    if (url) link.href = url;
+  } catch (e) {
+    console.error('Failed to fetch source detail:', e);
+  }
+}
+
+function closePreview(name) {
+  const conn = connections[name];
+  if (conn) {
+    conn.ws.close();
+    conn.div.remove();
+    delete connections[name];
+    updateButtons();
+  }
+}
+
+function clearAll() {
+  Object.keys(connections).forEach(closePreview);
+}
+
+function updateButtons() {
+  document.querySelectorAll('.source-btn').forEach(btn => {
+    btn.className = 'source-btn' + (connections[btn.dataset.source] ? ' active' : '');
+  });
+}
+
+// Recent server log events, polled for newer ones while the panel is open.
+let lastLog = 0;
+let logTimer = null;
+function toggleLogs() {
+  const open = document.getElementById('logs').classList.toggle('open');
+  clearInterval(logTimer);
+  if (open) {
+    reloadLogs();
+    logTimer = setInterval(fetchLogs, 2000);
+  }
+}
+
+function reloadLogs() {
+  lastLog = 0;
+  document.getElementById('log-lines').textContent = '';
+  fetchLogs();
+}
+
+async function fetchLogs() {
+  const params = new URLSearchParams({ after: lastLog, limit: 500 });
+  const level = document.getElementById('log-level').value;
+  const source = document.getElementById('log-source').value;
+  if (level) params.set('level', level);
+  if (source) params.set('source', source);
+  try {
+    const res = await fetch(withToken(baseUrl + '/admin/logs?' + params));
+    if (!res.ok) return;
+    const lines = document.getElementById('log-lines');
+    const atBottom = lines.scrollTop + lines.clientHeight >= lines.scrollHeight - 4;
+    for (const entry of await res.json()) {
+      const line = document.createElement('div');
+      line.className = 'log-' + entry.level;
+      const time = new Date(entry.time * 1000).toLocaleTimeString();
+      line.textContent = time + ' ' + entry.level.toUpperCase() + ' ' + entry.message;
+      lines.appendChild(line);
+      lastLog = entry.seq;
+    }
+    if (atBottom) lines.scrollTop = lines.scrollHeight;
+  } catch (e) {
+    console.error('Failed to fetch logs:', e);
+  }
+}
+
+refreshSources();
+// Redraw the list as sources appear and disappear instead of polling.
+const sourceEvents = new EventSource(withToken(baseUrl + '/events'));
+sourceEvents.addEventListener('source_added', refreshSources);
+sourceEvents.addEventListener('source_removed', refreshSources);
+setInterval(() => Object.keys(connections).forEach(updateDetail), 10000);
+</script>
+
+<div class="info">
+  <h2>API Reference</h2>
+  <ul>
+    <li><code>GET /sources</code> &mdash; returns a JSON array of the NDI<sup>&reg;</sup> sources currently visible on the network. Starred sources come first, then those in the shared order, then the rest as discovered. Each is an object with <code>id</code>, <code>name</code>, <code>url</code>, <code>width</code>, <code>height</code> and <code>fps</code> (while the source is received), <code>active_clients</code>, <code>ptz_supported</code> (<code>null</code> until the source has been connected), <code>starred</code> and, for sources given one in <code>[aliases]</code>, <code>alias</code>. The <code>id</code> is URL-safe and stable, and like the alias can be used instead of the name wherever a source is referenced. <code>?names=true</code> returns bare names instead, for clients written before the objects. <code>?groups=studio-a,studio-b</code> lists only sources found in those NDI<sup>&reg;</sup> groups (see <code>--groups</code>).</li>
+    <li><code>POST /api/discovery/refresh</code> &mdash; looks for sources right away, e.g. just after a camera was switched on, and returns the list as <code>GET /sources</code> does (same parameters), waiting up to 5 seconds for discovery.</li>
+    <li><code>GET /api/favorites</code> &mdash; the <code>starred</code> sources and the source <code>order</code>, shared by everyone using the server. <code>PUT</code> or <code>DELETE /api/favorites/&lt;name&gt;</code> stars or unstars a source (needs the <code>control</code> action for it); <code>PATCH /api/favorites</code> with <code>{"starred": [...], "order": [...]}</code>, each optional, replaces them (needs <code>control</code> for all sources). Kept across restarts with <code>--state-dir</code>.</li>
+    <li><code>GET /events</code> &mdash; server-sent events named <code>source_added</code> and <code>source_removed</code> as NDI<sup>&reg;</sup> sources appear and disappear, each with data like <code>{"source": "CAM (1)"}</code>, and <code>motion_started</code> (with a <code>score</code>) and <code>motion_stopped</code> for sources with <code>motion = true</code>. Only sources the token may view are reported. Use it with <code>EventSource</code> instead of polling <code>/sources</code>.</li>
+    <li><code>GET /healthz</code> &mdash; 200 <code>ok</code> while the server takes clients, 503 <code>draining</code> once a SIGTERM with <code>--drain-timeout</code> has it sending clients away.</li>
+    <li><code>GET /api/clients</code> &mdash; the connected WebSocket and MJPEG clients: <code>id</code>, <code>kind</code>, <code>source</code>, token <code>label</code>, <code>connected_secs</code>, <code>frames</code> and <code>bytes</code> sent, <code>kbps</code> delivered over the last two seconds, <code>send_busy</code> (the share of that time spent waiting for writes; near 1 means the client's link is full), <code>adaptive_fps</code> when the adaptive rate is holding the client back, <code>lags</code> and <code>dropped</code> (how often it fell behind so frames were skipped, and how many), and with <code>--client-ids</code> the viewer's persistent <code>client_id</code> (a cookie, or <code>?client_id=</code> on <code>/ws</code> and <code>/stream</code>) with its number of <code>visits</code>.</li>
+    <li><code>GET /stats</code> &mdash; returns a JSON object keyed by active source name with client count and, when started with <code>--loudness</code>, EBU R128 momentary/short-term/integrated loudness in LUFS.</li>
+    <li><code>POST /api/snapshots/schedule</code> &mdash; takes snapshots once or on a schedule, e.g. <code>{"every": 3600, "align": true, "keep": 24}</code> for every source at the top of every hour, keeping 24 each; also <code>sources</code>, <code>at</code> (Unix seconds) and <code>max_age</code> (seconds). Returns 201 with the schedule's <code>id</code> and <code>next_at</code>. <code>GET /api/snapshots/schedule</code> lists schedules, <code>DELETE /api/snapshots/schedule/&lt;id&gt;</code> stops one and deletes its snapshots. <code>GET /api/snapshots/&lt;name&gt;</code> lists a source's snapshots, newest first, and <code>GET /api/snapshots/&lt;name&gt;/&lt;file&gt;</code> (or <code>latest</code>) returns one. Needs the <code>snapshot</code> action when grants are in use; kept across restarts with <code>--state-dir</code>.</li>
+    <li><code>GET /openapi.json</code> &mdash; the HTTP API as an OpenAPI 3.1 document; <a href="/docs">/docs</a> browses it with Swagger UI.</li>
+    <li><code>GET /api/pipeline</code> &mdash; for each active source, the stages its frames go through (capture, record, scale, diff, burn_in, encode, overlay, outputs), each with the settings in effect, frames processed and average time per frame in milliseconds.</li>
+    <li><code>GET /api/sources/&lt;name&gt;</code> &mdash; returns details for one source: its NDI<sup>&reg;</sup> URL, whether a receiver is active, client count, and, once connected, the sender's connection count, web control URL (the camera's own configuration page), PTZ support, any capabilities the sender announced and the sender's tally echo (<code>tally</code>, with <code>program</code> and <code>preview</code>). Returns 404 for unknown sources.</li>
+    <li><code>GET /stream/&lt;name&gt;</code> &mdash; classic MJPEG stream (<code>multipart/x-mixed-replace</code>) for the given source. Works directly in an <code>&lt;img src="..."&gt;</code> tag, VLC or anything that reads IP-camera MJPEG; no JavaScript needed. Add <code>?mode=diff</code> (here or on <code>/ws</code>) for a diagnostic view of the amplified difference between consecutive frames: still content goes black, while flickering graphics, dropped fields and rolling-shutter artifacts light up.</li>
+    <li><code>GET /mosaic?sources=a,b,c,d&amp;layout=2x2</code> &mdash; several sources tiled into one 1920&times;1080 MJPEG stream, for a multiviewer that would otherwise open a stream of each. Sources (names, aliases or ids) fill the grid's tiles left to right, top to bottom, each scaled to fit its tile; <code>layout</code> is <code>COLUMNSxROWS</code> with at most 16 tiles and defaults to the squarest grid that fits. Takes the same <code>quality</code>, <code>fps</code>, <code>width</code> and <code>format</code> parameters as <code>/stream</code>, needs the <code>view</code> action for every source, and returns 400 for a bad layout and 404 if a source isn't listed. <code>/ws?mosaic=a,b,c,d&amp;layout=2x2</code> streams the same over a WebSocket. A source that goes away leaves its tile black.</li>
+    <li><code>rtsp://&lt;host&gt;:&lt;port&gt;/&lt;name&gt;</code> &mdash; with <code>--rtsp-port</code>, the source over RTSP as RTP/JPEG, for NVRs like Frigate, Blue Iris and Milestone. Interleaved on the connection or over UDP; the token goes in <code>?token=</code> or as the password.</li>
+    <li><code>POST /onvif/&lt;name&gt;/device_service</code>, <code>.../media_service</code> &mdash; the source as an ONVIF camera for NVRs that add cameras that way; <code>--onvif</code> also answers WS-Discovery probes. The token is the WS-Security password.</li>
+    <li><code>GET /integrations/homeassistant</code> &mdash; every source the token may view as a Home Assistant camera, with its <code>mjpeg_url</code> and <code>still_image_url</code> ready to paste; <code>?format=go2rtc</code> gives go2rtc's <code>streams:</code> as YAML instead.</li>
+    <li><code>GET /snapshot/&lt;name&gt;</code> &mdash; a single <code>image/jpeg</code> still of the source's next frame, for dashboards and camera integrations that poll; <code>?format=png</code> or <code>?format=webp</code> for a PNG or WebP one. Returns 504 if no frame arrives within 5 seconds.</li>
+    <li><code>GET /audio/&lt;name&gt;.wav</code> &mdash; the source's audio as an endless 16-bit PCM WAV stream (up to two channels) for <code>&lt;audio&gt;</code> tags, VLC and Icecast-style players. Returns 504 if the source sends no audio within 5 seconds. <code>.mp3</code> and <code>.aac</code> stream it compressed at 128 kbit/s, encoded by <code>ffmpeg</code>; builds without the <code>ffmpeg</code> feature return 501.</li>
+    <li><code>POST /webrtc/&lt;name&gt;</code> &mdash; plays the source over WebRTC as H.264, as WHEP has it: the body is the browser's SDP offer (<code>application/sdp</code>), and the 201 response the answer, with the session in <code>Location</code>; <code>DELETE</code> it to end the session. Encoded by <code>ffmpeg</code> with the source's <code>--encoder</code>; builds without the <code>ffmpeg</code> feature return 501.</li>
+    <li><code>GET /levels/&lt;name&gt;</code> &mdash; the source's audio levels over the last tenth of a second, for VU meters without streaming the audio: <code>{"channels": [{"rms": -18.2, "peak": -6.1}, ...]}</code> in dBFS, with silence at -100. Returns 504 if the source sends no audio within 5 seconds. <code>streambridge.v1</code> WebSocket clients can get the same as <code>{"type": "levels", "channels": [...]}</code> about ten times a second with <code>levels=true</code>.</li>
+    <li><code>GET /metadata/&lt;name&gt;</code> &mdash; the sender's NDI<sup>&reg;</sup> metadata frames (tally, captions, custom XML) as server-sent events named <code>metadata</code>, one XML document per event. Use it with <code>EventSource</code>.</li>
+    <li><code>POST /api/hooks/&lt;name&gt;?source=&lt;name&gt;</code> &mdash; publishes a webhook event for the source, e.g. to trigger capture rules from the config file. Returns 202.</li>
+    <li><code>POST /api/commands</code> &mdash; runs one JSON command for automation systems: <code>{"command": "set_quality", "source": "&lt;name&gt;", "jpeg_quality": 50}</code>, <code>start_record</code> / <code>stop_record</code> (raw files in <code>--record-dir</code>, or AVI with <code>"format": "avi"</code>), <code>recall_ptz_preset</code> with <code>preset</code> and optional <code>speed</code>, or <code>switch_program</code> (routes the <code>--program-output</code> NDI<sup>&reg;</sup> source). Send an <code>Idempotency-Key</code> header or <code>idempotency_key</code> field to make retries safe: a repeated key returns the first response.</li>
+    <li><code>POST /ptz/&lt;name&gt;/pan_tilt</code>, <code>/zoom</code>, <code>/preset</code> &mdash; drives a PTZ camera. Bodies: <code>{"pan": 0.2, "tilt": -0.1}</code> (absolute, -1 to 1) or <code>{"pan_speed": 0.5, "tilt_speed": 0}</code> (0 stops); <code>{"zoom": 0.5}</code> (0 in to 1 out) or <code>{"speed": -0.3}</code>; <code>{"recall": 3, "speed": 1}</code> or <code>{"store": 3}</code>. Returns 409 if the source has no PTZ, 501 if the NDI<sup>&reg;</sup> runtime has no PTZ support, and 504 if the camera does not answer within 5 seconds.</li>
+    <li><code>POST /tally/&lt;name&gt;</code> &mdash; signals on-air state to the source's sender, e.g. a camera's tally lamp: <code>{"program": true, "preview": false}</code>. The bridge stays connected while either is on. Returns 409 for replay, composite and clock sources.</li>
+    <li><code>POST /record/&lt;name&gt;/start</code>, <code>/stop</code> &mdash; records the source into <code>--record-dir</code> as MJPEG in AVI (video only), or with <code>{"format": "raw"}</code> as a raw file with audio for <code>--replay</code>. <code>{"fps": 25}</code> writes the AVI at exactly that rate, repeating or dropping frames, instead of <code>--record-fps</code>. With <code>--record-shared</code>, AVI recordings take the JPEGs sent to viewers, at the fps cap, instead of encoding every frame. Files move on to new ones past <code>--record-max-size</code> or <code>--record-max-duration</code>. Stopping returns the <code>files</code> written.</li>
+    <li><code>GET /recordings</code> &mdash; the files in <code>--record-dir</code> with their <code>name</code>, <code>bytes</code>, <code>modified</code> time and <code>source</code>; <code>GET /recordings/&lt;file&gt;</code> downloads one. Both need the <code>record</code> action for the source.</li>
+    <li><code>GET /preroll/&lt;source&gt;</code> &mdash; the last <code>--preroll</code> seconds of a source as MJPEG in AVI. Needs the <code>record</code> action.</li>
+    <li><code>PATCH /admin/receivers/&lt;name&gt;</code> &mdash; changes a source's settings while it runs, keeping its viewers: <code>{"jpeg_quality": 50, "max_fps": 10, "low_bandwidth": true}</code>, each optional. Returns the settings now in effect. <code>low_bandwidth</code> reconnects to the sender and returns 409 for replay, composite and clock sources.</li>
+    <li><code>GET /admin/receivers</code> &mdash; the running receivers, by key, with their source, producer, client count, uptime in seconds and whether they are recording, on air or reconnecting.</li>
+    <li><code>GET /admin/receivers/&lt;name&gt;/clients</code> &mdash; the source's clients as in <code>/api/clients</code>, with the <code>peer</code> address each connects from and its <code>user_agent</code>.</li>
+    <li><code>POST /admin/receivers/&lt;name&gt;/restart</code> &mdash; reconnects the source's receivers to its NDI&reg; sender, keeping its viewers. 409 when nothing receives it or it isn't an NDI&reg; source.</li>
+    <li><code>DELETE /admin/receivers/&lt;name&gt;</code> &mdash; tears down the source's receivers, disconnecting its viewers as if the source was lost. 409 when nothing receives it.</li>
+    <li><code>PUT /admin/srt/&lt;name&gt;</code> &mdash; pushes the source to a remote receiver as H.264 over SRT: <code>{"mode": "caller", "address": "relay.example.com:9000", "latency": 800}</code>, with optional <code>passphrase</code>, <code>stream_id</code> and <code>bitrate</code> (kbps). 501 without the ffmpeg feature. <code>GET /admin/srt</code> lists the outputs with whether each is <code>running</code>, its <code>frames</code> and last <code>error</code>; <code>DELETE /admin/srt/&lt;name&gt;</code> stops one.</li>
+    <li><code>GET /admin/logs</code> &mdash; the server's recent log events (the last <code>--log-buffer</code>, info and up), oldest first, each with <code>seq</code>, <code>time</code>, <code>level</code>, <code>target</code>, <code>source</code> and <code>message</code>. Filter with <code>?level=warn</code> (that level and worse), <code>?source=&lt;name&gt;</code>, <code>?after=&lt;seq&gt;</code> for only newer events and <code>?limit=</code> for only the newest. The Logs button above shows them. Events about a source need the <code>control</code> action for it, the others <code>control</code> for all sources.</li>
+    <li><code>WebSocket /ws?source=&lt;name&gt;</code> &mdash; streams binary JPEG frames for the given source. Each WebSocket message is one complete JPEG image. A source discovery hasn't seen, e.g. on another VLAN or behind NDI<sup>&reg;</sup> Bridge, can be streamed anyway by its sender's address, <code>/ws?url=10.20.0.15:5961&amp;name=REMOTE%20(Cam%201)</code>, or by its full <code>MACHINE (Source)</code> name alone, which the NDI<sup>&reg;</sup> runtime then looks up itself; a bad address closes with 4400. When the server runs with <code>--chaos</code>, the optional <code>delay_ms</code>, <code>jitter_ms</code>, <code>drop</code> and <code>reorder</code> parameters (probabilities 0&ndash;1) inject faults for client testing. The optional <code>quality</code>, <code>fps</code> and <code>width</code> parameters (also on <code>/stream</code>) lower the JPEG quality, frame rate and width for this client only, <code>format=webp</code> or <code>format=png</code> (also on <code>/stream</code> and <code>/snapshot</code>) sends WebP or lossless PNG frames instead of JPEGs, and <code>bandwidth=lowest</code> (also on <code>/stream</code>) receives the sender's NDI<sup>&reg;</sup> low-bandwidth preview stream instead, or <code>bandwidth=highest</code> the full one. The frame rate also adapts to the client's link: the server pings each WebSocket client every second and lowers its rate while pongs come back slowly or frames back up, then restores it once they flow; <code>adaptive=false</code> turns this off. With <code>metadata=true</code>, the sender's metadata XML arrives as text messages between the frames. Offer the subprotocol <code>streambridge.v1</code> (<code>new WebSocket(url, 'streambridge.v1')</code>) for versioned messages: frames stay binary JPEGs, and text messages are JSON with a <code>type</code>, starting with <code>{"type": "hello", "protocol": "streambridge.v1", "source": "&lt;name&gt;", "format": "image/jpeg"}</code>; metadata arrives as <code>{"type": "metadata", "xml": "..."}</code>, and when an NDI<sup>&reg;</sup> sender drops, <code>{"type": "reconnecting", "attempt": 1}</code> (repeated per attempt) until <code>{"type": "reconnected"}</code>. A client offering only protocols the server doesn't speak is refused with 400. On a <code>[compare]</code> source, sending the text message <code>{"split": 0.3}</code> moves the split for every viewer (needs the <code>control</code> action when grants are in use). Sending <code>{"cmd": "grab"}</code> gets the next frame as a still at the source's own quality and size, ignoring the client's <code>quality</code>, <code>fps</code> and <code>width</code>; <code>streambridge.v1</code> clients get <code>{"type": "still", "format": "image/jpeg"}</code> just before it (needs the <code>snapshot</code> action when grants are in use). With <code>annotations=true</code>, <code>streambridge.v1</code> clients get <code>{"type": "frame", "seq": 42}</code> before each frame and <code>{"type": "annotations", "seq": 42, "boxes": [...], "texts": [...]}</code> whenever a service posts some. With <code>framing=meta</code>, any client gets a text message before each frame for measuring latency: <code>{"type": "frame", "seq": 42, "timecode": ..., "timestamp": ..., "received_ms": 1700000000123, "width": 1920, "height": 1080}</code>, with the NDI<sup>&reg;</sup> sender's timecode and send time in 100&nbsp;ns units (left out for other sources), when the bridge received the frame in Unix milliseconds, and the source's resolution.</li>
+    <li><code>POST /api/annotations/&lt;name&gt;</code> &mdash; passes annotations for one frame, e.g. <code>{"seq": 42, "boxes": [{"x": 0.1, "y": 0.2, "width": 0.3, "height": 0.4, "label": "person", "score": 0.9}], "texts": [{"x": 0.02, "y": 0.02, "text": "1 person"}], "ttl_ms": 500}</code> with positions and sizes as fractions of the frame, on to the source's viewers that asked for them; answers <code>{"ok": true, "viewers": 1}</code>, or 400 for invalid annotations. Frame numbers come from the WebSocket's <code>frame</code> messages or the <code>X-Frame-Seq</code> header of <code>/snapshot</code>. Needs the <code>control</code> action when grants are in use.</li>
+  </ul>
+  <p>When the server runs with <code>--grant</code>, every endpoint above needs a token, sent as <code>Authorization: Bearer &lt;token&gt;</code> or as a <code>token</code> query parameter. Unknown tokens get 401 and tokens without access to the source get 403 (WebSocket close codes 4401 and 4403), and streams past <code>--max-clients</code>, <code>--max-clients-per-source</code> or <code>--max-connections-per-ip</code> get 429 (close code 4429); <code>/sources</code>, <code>/stats</code> and <code>/api/pipeline</code> only list sources the token may view. <code>--api-token</code> (or one token per line in <code>--api-tokens-file</code>) is a shorthand for a grant with full access, and with any token configured, every endpoint except this page, <code>/healthz</code> and <code>/ws</code> (which reports denials as close codes) turns away unknown tokens with 401 before looking at the request. This page asks for a token when it needs one, or open it with <code>?token=&lt;token&gt;</code>.</p>
+
+  <h2>Browser Usage Example</h2>
+  <p>Connect to a source and display frames in an <code>&lt;img&gt;</code> tag:</p>
+  <pre><code>const img = document.getElementById('my-img');
+const ws = new WebSocket('ws://localhost:9550/ws?source=MY_SOURCE');
+ws.binaryType = 'arraybuffer';
+ws.onmessage = (e) => {
+  const blob = new Blob([e.data], { type: 'image/jpeg' });
+  const url = URL.createObjectURL(blob);
+  const old = img.src;
+  img.src = url;
+  if (old.startsWith('blob:')) URL.revokeObjectURL(old);
+};</code></pre>
+
+  <div class="ndi-attr">
+    Powered by NDI<sup>&reg;</sup> &mdash; NDI is a registered trademark of the Vizrt Group.
+  </div>
+</div>
+</body>
+</html>
+"#;
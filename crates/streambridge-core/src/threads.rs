@@ -0,0 +1,126 @@
+//! Running politely next to vMix, OBS and the like on one production machine:
+//! a lower priority and a set of CPUs for the bridge's capture and encode threads.
+
+use serde::Deserialize;
+use std::fmt;
+
+/// A list of CPU indices, e.g. `2,3` or `4-7` on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct CpuList(pub Vec<usize>);
+
+impl CpuList {
+    /// Parse comma-separated CPU indices and `FIRST-LAST` ranges.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut cpus = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let index = |s: &str| {
+                let bad = || format!("bad CPU index \"{s}\" in \"{part}\"");
+                s.trim().parse::<usize>().map_err(|_| bad())
+            };
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (index(first)?, index(last)?);
+                    if first > last {
+                        return Err(format!("CPU range \"{part}\" runs backwards"));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(index(part)?),
+            }
+        }
+        Ok(Self(cpus))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpus: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        f.write_str(&cpus.join(","))
+    }
+}
+
+/// How the bridge's threads run. The default leaves them to the OS.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThreadPolicy {
+    /// Niceness from -20 (most favoured) to 19 (least).
+    pub nice: Option<i32>,
+    /// The CPUs the threads may run on.
+    pub cpus: Option<CpuList>,
+}
+
+impl ThreadPolicy {
+    pub fn is_default(&self) -> bool {
+        self.nice.is_none() && self.cpus.is_none()
+    }
+
+    /// Apply the policy to the calling thread.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self) -> Result<(), String> {
+        use std::io::Error;
+
+        if let Some(nice) = self.nice {
+            // Linux keeps niceness per thread, so a thread id sets just this one.
+            // SAFETY: plain syscalls without pointers.
+            let tid = unsafe { libc::gettid() } as libc::id_t;
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+                return Err(format!("can't set niceness {nice}: {}", Error::last_os_error()));
+            }
+        }
+        if let Some(cpus) = &self.cpus {
+            // SAFETY: cpu_set_t is a plain bit mask, valid when zeroed, and
+            // CPU_SET is only given indices below its size.
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in &cpus.0 {
+                if cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(format!("no CPU {cpu}"));
+                }
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_setaffinity(0, size, &set) } != 0 {
+                return Err(format!("can't run on CPUs {cpus}: {}", Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self) -> Result<(), String> {
+        if self.is_default() {
+            Ok(())
+        } else {
+            Err("thread priority and CPU affinity are only supported on Linux".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_parse_indices_and_ranges() {
+        assert_eq!(CpuList::parse("2,3").unwrap(), CpuList(vec![2, 3]));
+        assert_eq!(CpuList::parse("0, 4-6").unwrap(), CpuList(vec![0, 4, 5, 6]));
+        assert_eq!(CpuList::parse("4-6").unwrap().to_string(), "4,5,6");
+        assert!(CpuList::parse("6-4").is_err());
+        assert!(CpuList::parse("two").is_err());
+        assert!(CpuList::parse("").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn policies_apply_to_the_calling_thread() {
+        // Anyone may lower their own priority all the way.
+        let policy = ThreadPolicy { nice: Some(19), cpus: None };
+        std::thread::spawn(move || {
+            policy.apply().expect("apply");
+            // SAFETY: plain syscall without pointers.
+            let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as _) };
+            assert_eq!(nice, 19);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! How times are written into file names and onto clock frames: in a time zone
+//! and with a strftime format, set for the server and per source, so recordings
+//! and captures from a facility spread over time zones are labelled alike.
+
+use crate::automation::sanitize;
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, Zoned};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The format of timestamps in file names unless one is set: `YYYYMMDD-HHMMSS`.
+pub const FILE_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// A time zone and, optionally, a strftime format; UTC and [`FILE_FORMAT`] by
+/// default.
+#[derive(Debug, Clone)]
+pub struct TimeFormat {
+    zone: TimeZone,
+    format: Option<String>,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        Self { zone: TimeZone::UTC, format: None }
+    }
+}
+
+impl TimeFormat {
+    /// `zone` is an IANA name like `Europe/Berlin`, `UTC`, or `local` for the
+    /// system's; `format` is a strftime format like `%Y-%m-%d_%H%M%S%z`.
+    pub fn new(zone: Option<&str>, format: Option<&str>) -> Result<Self, String> {
+        Self::default().with(zone, format)
+    }
+
+    /// This format with `zone` and `format` replaced where given.
+    pub fn with(&self, zone: Option<&str>, format: Option<&str>) -> Result<Self, String> {
+        let zone = match zone {
+            None => self.zone.clone(),
+            Some("local") => TimeZone::system(),
+            Some(name) => {
+                TimeZone::get(name).map_err(|e| format!("unknown time zone \"{name}\": {e}"))?
+            }
+        };
+        let format = match format {
+            Some("") => return Err("empty time format".to_string()),
+            Some(format) => {
+                let epoch = Timestamp::UNIX_EPOCH.to_zoned(zone.clone());
+                jiff::fmt::strtime::format(format, &epoch)
+                    .map_err(|e| format!("invalid time format \"{format}\": {e}"))?;
+                Some(format.to_string())
+            }
+            None => self.format.clone(),
+        };
+        Ok(Self { zone, format })
+    }
+
+    /// `time` in the time zone.
+    pub fn zoned(&self, time: SystemTime) -> Zoned {
+        let timestamp = Timestamp::try_from(time).unwrap_or(Timestamp::UNIX_EPOCH);
+        timestamp.to_zoned(self.zone.clone())
+    }
+
+    /// `time` for a file name: in the format, or [`FILE_FORMAT`], with anything
+    /// a file name can't hold replaced.
+    pub fn file_timestamp(&self, time: SystemTime) -> String {
+        sanitize(&self.format(time, FILE_FORMAT))
+    }
+
+    /// `time` in the format, or `default` if none is set.
+    pub fn format(&self, time: SystemTime, default: &str) -> String {
+        let format = self.format.as_deref().unwrap_or(default);
+        // Formats are checked when set, so this only fails for times far out of range.
+        jiff::fmt::strtime::format(format, &self.zoned(time)).unwrap_or_else(|_| {
+            let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            secs.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_in_the_zone_and_format_set() {
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_217_296);
+        assert_eq!(TimeFormat::default().file_timestamp(leap_day), "20240229-143456");
+
+        let berlin = TimeFormat::new(Some("Europe/Berlin"), None).unwrap();
+        assert_eq!(berlin.file_timestamp(leap_day), "20240229-153456");
+        let custom = berlin.with(None, Some("%Y-%m-%d %H:%M %Z")).unwrap();
+        assert_eq!(custom.file_timestamp(leap_day), "2024-02-29 15_34 CET");
+        assert_eq!(custom.format(leap_day, FILE_FORMAT), "2024-02-29 15:34 CET");
+
+        assert!(TimeFormat::new(Some("Mars/Olympus"), None).is_err());
+        assert!(TimeFormat::new(None, Some("%Y%")).is_err());
+    }
+}
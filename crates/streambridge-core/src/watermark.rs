@@ -0,0 +1,179 @@
+use bytes::Bytes;
+
+/// How far marked pixels move away from their original luma. Small enough to
+/// stay out of the way, large enough to survive re-encoding.
+const STRENGTH: u8 = 20;
+
+/// Burns a viewer's label faintly into every frame of one connection, so a leaked
+/// recording or screenshot can be traced back to the token that watched it.
+/// Each frame is decoded, marked in its luma plane and re-encoded.
+pub struct Watermarker {
+    label: String,
+    decompressor: turbojpeg::Decompressor,
+    compressor: turbojpeg::Compressor,
+    yuv: Vec<u8>,
+}
+
+impl Watermarker {
+    pub fn new(label: &str, quality: i32) -> Result<Self, String> {
+        let decompressor =
+            turbojpeg::Decompressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        let mut compressor =
+            turbojpeg::Compressor::new().map_err(|e| format!("turbojpeg init error: {e}"))?;
+        compressor
+            .set_quality(quality)
+            .map_err(|e| format!("turbojpeg quality error: {e}"))?;
+        Ok(Self {
+            label: label.to_uppercase(),
+            decompressor,
+            compressor,
+            yuv: Vec::new(),
+        })
+    }
+
+    pub fn apply(&mut self, jpeg: &[u8]) -> Result<Bytes, String> {
+        let header = self
+            .decompressor
+            .read_header(jpeg)
+            .map_err(|e| format!("turbojpeg header error: {e}"))?;
+        let (w, h) = (header.width, header.height);
+        let len = turbojpeg::yuv_pixels_len(w, 1, h, header.subsamp)
+            .map_err(|e| format!("turbojpeg size error: {e}"))?;
+        self.yuv.resize(len, 0);
+        let mut image = turbojpeg::YuvImage {
+            pixels: &mut self.yuv[..],
+            width: w,
+            align: 1,
+            height: h,
+            subsamp: header.subsamp,
+        };
+        self.decompressor
+            .decompress_to_yuv(jpeg, image.as_deref_mut())
+            .map_err(|e| format!("turbojpeg decompress error: {e}"))?;
+
+        // With align 1 the luma plane comes first, one byte per pixel.
+        draw_tiled(&mut image.pixels[..w * h], w, h, &self.label);
+
+        self.compressor
+            .compress_yuv_to_vec(image.as_deref())
+            .map(Bytes::from)
+            .map_err(|e| format!("turbojpeg compress error: {e}"))
+    }
+}
+
+/// Repeat `text` across the frame in staggered rows so cropping can't remove it.
+fn draw_tiled(luma: &mut [u8], w: usize, h: usize, text: &str) {
+    let scale = (h / 180).max(1);
+    let text_w = text.chars().count() * 6 * scale;
+    if text_w == 0 {
+        return;
+    }
+    let step_x = text_w + 8 * 6 * scale;
+    let step_y = (h / 4).max(8 * scale);
+    for (row, y) in (step_y / 2..h).step_by(step_y).enumerate() {
+        let offset = if row % 2 == 0 { 0 } else { step_x / 2 };
+        for x in (0..w + step_x).step_by(step_x) {
+            draw_text(luma, w, h, x.saturating_sub(offset), y, scale, text);
+        }
+    }
+}
+
+fn draw_text(luma: &mut [u8], w: usize, h: usize, x0: usize, y0: usize, scale: usize, text: &str) {
+    for (i, c) in text.chars().enumerate() {
+        for (gy, bits) in glyph(c).iter().enumerate() {
+            for gx in 0..5 {
+                if bits & (0x10 >> gx) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = x0 + (i * 6 + gx) * scale + dx;
+                        let y = y0 + gy * scale + dy;
+                        if x < w && y < h {
+                            let p = &mut luma[y * w + x];
+                            *p = if *p < 128 { *p + STRENGTH } else { *p - STRENGTH };
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 5x7 bitmap for `c`, one row per byte, most significant of the low five bits leftmost.
+pub(crate) fn glyph(c: char) -> [u8; 7] {
+    match c {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '@' => [0x0E, 0x11, 0x17, 0x15, 0x17, 0x10, 0x0F],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_luma_faintly() {
+        let (w, h) = (320, 180);
+        let gray = turbojpeg::Image {
+            pixels: vec![128u8; w * h * 3],
+            width: w,
+            pitch: w * 3,
+            height: h,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+        let jpeg = turbojpeg::compress(gray.as_deref(), 95, turbojpeg::Subsamp::Sub2x2).unwrap();
+
+        let marked = Watermarker::new("viewer 7", 95).unwrap().apply(&jpeg).unwrap();
+        let luma = turbojpeg::decompress(&marked, turbojpeg::PixelFormat::GRAY).unwrap().pixels;
+        let max_delta = luma.iter().map(|&p| p.abs_diff(128)).max().unwrap();
+        let touched = luma.iter().filter(|&&p| p.abs_diff(128) > STRENGTH / 2).count();
+        assert!(max_delta <= STRENGTH + 8, "mark too strong: {max_delta}");
+        assert!(touched > 100, "mark not visible: {touched} pixels");
+        assert!(touched < w * h / 4, "mark covers too much: {touched} pixels");
+    }
+}
@@ -0,0 +1,405 @@
+//! WebRTC output: browsers play a source over a peer connection, as H.264 at
+//! a fraction of MJPEG's bandwidth and with little latency. Sessions are
+//! negotiated as WHEP has it: `POST /webrtc/<source>` with the browser's SDP
+//! offer returns the answer, and the session's URL in `Location` to `DELETE`
+//! when done. Each session's frames are encoded by an `ffmpeg` process of its
+//! own, with the source's `--encoder`, so this needs the `ffmpeg` feature.
+//! Answers offer the bridge's own addresses as ICE candidates, which suits the
+//! LAN or a VPN.
+
+use crate::encode::EncoderKind;
+use crate::server::ViewerFeed;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::task::AbortHandle;
+#[cfg(feature = "ffmpeg")]
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+#[cfg(feature = "ffmpeg")]
+use webrtc::peer_connection::RTCPeerConnection;
+#[cfg(feature = "ffmpeg")]
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+/// Whether this build can answer WebRTC offers.
+pub const AVAILABLE: bool = cfg!(feature = "ffmpeg");
+/// How long a peer has to connect after its offer is answered.
+#[cfg(feature = "ffmpeg")]
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Every WebRTC session, by id.
+#[derive(Default)]
+pub struct WhepSessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    source: String,
+    task: AbortHandle,
+}
+
+impl WhepSessions {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// End `source`'s session `id`. Returns whether it had one.
+    pub fn stop(&self, source: &str, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(id).is_none_or(|session| session.source != source) {
+            return false;
+        }
+        sessions.remove(id).inspect(|session| session.task.abort()).is_some()
+    }
+
+    /// Answer `offer` with a session that plays `feed`, a viewer of `source`,
+    /// as H.264 from `encoder`. Returns the session's id and the SDP answer.
+    #[cfg(feature = "ffmpeg")]
+    pub(crate) async fn start(
+        self: &Arc<Self>,
+        source: &str,
+        feed: ViewerFeed,
+        encoder: EncoderKind,
+        offer: String,
+    ) -> Result<(String, String), String> {
+        let mut negotiated = negotiate(offer).await.map_err(|e| format!("WebRTC: {e}"))?;
+        let answer = std::mem::take(&mut negotiated.answer);
+        let id = crate::clients::new_client_id();
+        let mut sessions = self.sessions.lock().unwrap();
+        let task = tokio::spawn(run(
+            Arc::clone(self),
+            id.clone(),
+            source.to_string(),
+            feed,
+            encoder,
+            negotiated,
+        ));
+        let session = Session { source: source.to_string(), task: task.abort_handle() };
+        sessions.insert(id.clone(), session);
+        Ok((id, answer))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    pub(crate) async fn start(
+        self: &Arc<Self>,
+        _source: &str,
+        _feed: ViewerFeed,
+        _encoder: EncoderKind,
+        _offer: String,
+    ) -> Result<(String, String), String> {
+        Err("WebRTC needs the ffmpeg feature".to_string())
+    }
+}
+
+/// A peer connection answered, with the track its video goes out on.
+#[cfg(feature = "ffmpeg")]
+struct Negotiated {
+    peer: Arc<RTCPeerConnection>,
+    track: Arc<TrackLocalStaticSample>,
+    states: tokio::sync::watch::Receiver<RTCPeerConnectionState>,
+    answer: String,
+}
+
+/// A peer connection with an H.264 track that answers `offer`, once it has
+/// gathered its ICE candidates, as WHEP answers carry them all.
+#[cfg(feature = "ffmpeg")]
+async fn negotiate(offer: String) -> Result<Negotiated, webrtc::Error> {
+    use webrtc::api::interceptor_registry::register_default_interceptors;
+    use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
+    use webrtc::api::APIBuilder;
+    use webrtc::interceptor::registry::Registry;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::track::track_local::TrackLocal;
+
+    let offer = RTCSessionDescription::offer(offer)?;
+    let mut media = MediaEngine::default();
+    media.register_default_codecs()?;
+    let registry = register_default_interceptors(Registry::new(), &mut media)?;
+    let api =
+        APIBuilder::new().with_media_engine(media).with_interceptor_registry(registry).build();
+    let peer = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await?);
+    let (state_tx, states) = tokio::sync::watch::channel(RTCPeerConnectionState::New);
+    peer.on_peer_connection_state_change(Box::new(move |state| {
+        let _ = state_tx.send(state);
+        Box::pin(async {})
+    }));
+
+    // Constrained baseline, which every browser decodes.
+    let codec = RTCRtpCodecCapability {
+        mime_type: MIME_TYPE_H264.to_string(),
+        clock_rate: 90_000,
+        sdp_fmtp_line: "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f"
+            .to_string(),
+        ..Default::default()
+    };
+    let (id, stream_id) = ("video".to_string(), "streambridge".to_string());
+    let track = Arc::new(TrackLocalStaticSample::new(codec, id, stream_id));
+    let sender = peer.add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>).await?;
+    // Read the receiver's reports, so the interceptors see them.
+    tokio::spawn(async move {
+        let mut buf = vec![0; 1500];
+        while sender.read(&mut buf).await.is_ok() {}
+    });
+
+    let answered = async {
+        peer.set_remote_description(offer).await?;
+        let answer = peer.create_answer(None).await?;
+        let mut gathered = peer.gathering_complete_promise().await;
+        peer.set_local_description(answer).await?;
+        let _ = gathered.recv().await;
+        peer.local_description().await.ok_or(webrtc::Error::ErrNoRemoteDescription)
+    };
+    match answered.await {
+        Ok(answer) => Ok(Negotiated { peer, track, states, answer: answer.sdp }),
+        Err(e) => {
+            let _ = peer.close().await;
+            Err(e)
+        }
+    }
+}
+
+/// Play `feed` to the peer from when it connects until either goes away or
+/// the session is stopped, then forget the session.
+#[cfg(feature = "ffmpeg")]
+async fn run(
+    sessions: Arc<WhepSessions>,
+    id: String,
+    source: String,
+    feed: ViewerFeed,
+    encoder: EncoderKind,
+    negotiated: Negotiated,
+) {
+    use tracing::{info, warn};
+
+    let Negotiated { peer, track, mut states, .. } = negotiated;
+    let _closing = Closing(peer);
+    let connected = states.wait_for(|state| *state == RTCPeerConnectionState::Connected);
+    let connected = matches!(tokio::time::timeout(CONNECT_TIMEOUT, connected).await, Ok(Ok(_)));
+    let result = if connected {
+        info!("WebRTC: client connected for \"{}\"", source);
+        let ended = states.wait_for(|state| {
+            matches!(state, RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed)
+        });
+        tokio::select! {
+            result = play(feed, encoder, &track) => result,
+            _ = ended => Ok(()),
+        }
+    } else {
+        Err("the peer never connected".to_string())
+    };
+    if let Err(e) = result {
+        warn!("WebRTC: \"{}\": {}", source, e);
+    }
+    sessions.sessions.lock().unwrap().remove(&id);
+}
+
+/// Closes a session's peer connection when the session ends, however it does.
+#[cfg(feature = "ffmpeg")]
+struct Closing(Arc<RTCPeerConnection>);
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for Closing {
+    fn drop(&mut self) {
+        let peer = Arc::clone(&self.0);
+        tokio::spawn(async move {
+            let _ = peer.close().await;
+        });
+    }
+}
+
+/// `ffmpeg`'s arguments to read JPEGs on stdin and write H.264 for browsers,
+/// made by `encoder`, to stdout, with a keyframe every two seconds for the
+/// peer to recover from losses with.
+#[cfg(feature = "ffmpeg")]
+fn ffmpeg_args(encoder: EncoderKind) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-v", "error", "-nostdin", "-fflags", "nobuffer", "-use_wallclock_as_timestamps",
+        "1", "-f", "mjpeg", "-i", "-", "-an",
+    ]
+    .map(String::from)
+    .to_vec();
+    args.extend(encoder.h264_args(true));
+    args.extend(
+        ["-force_key_frames", "expr:gte(t,n_forced*2)", "-flush_packets", "1", "-f", "h264", "-"]
+            .map(String::from),
+    );
+    args
+}
+
+/// Encode `feed`'s JPEGs with `ffmpeg` and send each frame on `track` as it
+/// comes out, until the source or `ffmpeg` is gone.
+#[cfg(feature = "ffmpeg")]
+async fn play(
+    mut feed: ViewerFeed,
+    encoder: EncoderKind,
+    track: &TrackLocalStaticSample,
+) -> Result<(), String> {
+    use std::process::Stdio;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::process::Command;
+    use tokio::sync::mpsc;
+    use webrtc::media::Sample;
+
+    let encoder = crate::encode::h264_encoder(encoder).await;
+    let mut child = Command::new("ffmpeg")
+        .args(ffmpeg_args(encoder))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run ffmpeg: {e}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+
+    // ffmpeg is written to and read from at once, so neither pipe fills up
+    // while the other waits. A frame that comes while the last is still being
+    // written is dropped.
+    let (jpeg_tx, mut jpegs) = mpsc::channel::<Bytes>(1);
+    let writer = async move {
+        while let Some(jpeg) = jpegs.recv().await {
+            if stdin.write_all(&jpeg).await.is_err() {
+                break;
+            }
+        }
+    };
+    let (unit_tx, mut units) = mpsc::channel(4);
+    let reader = async move {
+        let mut split = AccessUnits::default();
+        let mut buf = vec![0; 64 << 10];
+        while let Ok(n @ 1..) = stdout.read(&mut buf).await {
+            split.push(&buf[..n]);
+            while let Some(unit) = split.next_unit() {
+                if unit_tx.send(unit).await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+    tokio::pin!(writer, reader);
+    let mut reading = true;
+    let mut last = Instant::now();
+    loop {
+        tokio::select! {
+            jpeg = feed.next() => {
+                let Some(jpeg) = jpeg else {
+                    return Err("source lost".to_string());
+                };
+                let _ = jpeg_tx.try_send(jpeg);
+            }
+            unit = units.recv() => {
+                let Some(unit) = unit else {
+                    return Err("ffmpeg stopped".to_string());
+                };
+                let (bytes, sending) = (unit.len(), Instant::now());
+                let sample = Sample { data: unit, duration: sending - last, ..Default::default() };
+                last = sending;
+                track.write_sample(&sample).await.map_err(|e| e.to_string())?;
+                feed.sent(bytes, sending.elapsed());
+            }
+            () = &mut writer => return Err("ffmpeg stopped reading".to_string()),
+            () = &mut reader, if reading => reading = false,
+        }
+    }
+}
+
+/// Splits H.264 in Annex B, as `ffmpeg -f h264` writes it, into access units:
+/// each frame's NAL units, with the parameter sets and SEI before it.
+#[derive(Default)]
+pub struct AccessUnits {
+    /// Bytes pushed but not yet split off.
+    pending: BytesMut,
+    /// How far `pending` has been searched for the next start code.
+    searched: usize,
+    /// The access unit being gathered.
+    unit: BytesMut,
+    /// Whether `unit` has a slice of its frame yet.
+    has_slice: bool,
+}
+
+impl AccessUnits {
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+    }
+
+    /// The next whole access unit, with its start codes. A unit is only known
+    /// to be whole once the next one starts.
+    pub fn next_unit(&mut self) -> Option<Bytes> {
+        loop {
+            let nal = self.next_nal()?;
+            let payload = &nal[nal.iter().position(|&b| b != 0)? + 1..];
+            let kind = payload.first().map_or(0, |header| header & 0x1F);
+            let slice = matches!(kind, 1 | 5);
+            // SEI, parameter sets and delimiters come before a frame's slices,
+            // and its first slice starts at macroblock 0, coded as a 1 bit.
+            let first_slice = slice && payload.get(1).is_some_and(|b| b & 0x80 != 0);
+            let starts_unit = matches!(kind, 6..=9) || first_slice;
+            let done = if starts_unit && self.has_slice {
+                self.has_slice = false;
+                Some(self.unit.split().freeze())
+            } else {
+                None
+            };
+            self.has_slice |= slice;
+            self.unit.extend_from_slice(&nal);
+            if done.is_some() {
+                return done;
+            }
+        }
+    }
+
+    /// The next NAL unit, with its start code, once the next start code shows
+    /// where it ends.
+    fn next_nal(&mut self) -> Option<BytesMut> {
+        let start = find(&self.pending, &[0, 0, 1])?;
+        // Anything before the first start code is no NAL unit; keep a leading
+        // zero of a four-byte start code.
+        let skip = start.saturating_sub(1);
+        if skip > 0 {
+            self.pending.advance(skip);
+            self.searched = 0;
+        }
+        let from = self.searched.max(start - skip + 3);
+        let Some(next) = find(&self.pending[from..], &[0, 0, 1]).map(|at| from + at) else {
+            self.searched = self.pending.len().saturating_sub(2);
+            return None;
+        };
+        // A zero before the next start code makes it a four-byte one.
+        let end = if self.pending[next - 1] == 0 { next - 1 } else { next };
+        self.searched = 0;
+        Some(self.pending.split_to(end))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annex_b_splits_into_access_units() {
+        let (sps, pps) = ([0, 0, 0, 1, 0x67, 0x42], [0, 0, 0, 1, 0x68, 0xCE]);
+        let idr = [0, 0, 1, 0x65, 0x88, 0x84];
+        // A frame in two slices, the second starting past macroblock 0.
+        let (p1, p1b) = ([0, 0, 0, 1, 0x41, 0x9A, 0x02], [0, 0, 1, 0x41, 0x40, 0x11]);
+        let p2 = [0, 0, 0, 1, 0x41, 0x9A, 0x03];
+        let stream = [&[0x55][..], &sps, &pps, &idr, &p1, &p1b, &p2, &sps, &pps].concat();
+
+        let mut split = AccessUnits::default();
+        let mut units = Vec::new();
+        // Byte by byte, as reads can end anywhere.
+        for byte in stream {
+            split.push(&[byte]);
+            while let Some(unit) = split.next_unit() {
+                units.push(unit);
+            }
+        }
+        let expected = [[&sps[..], &pps, &idr].concat(), [&p1[..], &p1b].concat(), p2.to_vec()];
+        assert_eq!(units, expected);
+    }
+}
@@ -0,0 +1,2399 @@
+//! End-to-end tests: the real router on a loopback port, fed by replay sources
+//! instead of the NDI® runtime, exercised with real HTTP and WebSocket clients.
+
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use streambridge_core::auth::Grant;
+use streambridge_core::automation::CaptureRule;
+use streambridge_core::clock::ClockConfig;
+use streambridge_core::composite::{CompareConfig, Corner, Layout, PipConfig};
+use streambridge_core::config::{EncodeSettings, SourceOverride, SourceSettings, PUBLIC_MAX_WIDTH};
+use streambridge_core::encode::{encode_frame, EncodeBuffers};
+use streambridge_core::events::{Event, EventBus};
+use streambridge_core::limits::ConnectionLimits;
+use streambridge_core::logs::LogBuffer;
+use streambridge_core::media::MediaConfig;
+use streambridge_core::motion::MotionSettings;
+use streambridge_core::overlay::{OverlayConfig, OverlayTime};
+use streambridge_core::ndi::FourCCVideoType;
+use streambridge_core::pipeline::VideoFrame;
+use streambridge_core::rawfile::{RawReader, RawWriter, Record};
+use streambridge_core::receiver::VirtualSource;
+use streambridge_core::scripting::Script;
+use streambridge_core::Bridge;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A replay file in the temp directory, deleted when the test finishes.
+struct Fixture(PathBuf);
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Write a short UYVY replay file (one second at 25 fps) to a unique temp path.
+fn fixture() -> Fixture {
+    fixture_sized(64, 48)
+}
+
+/// A unique temp path for a replay file.
+fn fixture_path() -> PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    std::env::temp_dir().join(format!(
+        "streambridge-test-{}-{}.sbraw",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+fn fixture_sized(width: usize, height: usize) -> Fixture {
+    let path = fixture_path();
+    let mut writer = RawWriter::create(&path).expect("create fixture");
+    for i in 0..25u64 {
+        let data: Vec<u8> = (0..width * 2 * height).map(|n| (n as u64 + i * 7) as u8).collect();
+        let frame = VideoFrame {
+            data: &data,
+            width,
+            height,
+            stride: width * 2,
+            fourcc: FourCCVideoType::UYVY,
+        };
+        writer.write_video(i * 40_000, &frame).expect("write fixture");
+    }
+    writer.finish().expect("finish fixture");
+    Fixture(path)
+}
+
+/// Like [`fixture`], with a 48 kHz stereo tone alongside the video.
+fn fixture_with_audio() -> Fixture {
+    let path = fixture_path();
+    let mut writer = RawWriter::create(&path).expect("create fixture");
+    let (width, height) = (64, 48);
+    let data = vec![128u8; width * 2 * height];
+    let tone: Vec<f32> = (0..1920).map(|n| (n as f32 * 0.06).sin() * 0.5).collect();
+    for i in 0..25u64 {
+        let frame = VideoFrame {
+            data: &data,
+            width,
+            height,
+            stride: width * 2,
+            fourcc: FourCCVideoType::UYVY,
+        };
+        writer.write_video(i * 40_000, &frame).expect("write fixture");
+        writer.write_audio(i * 40_000, 48_000, &[&tone, &tone]).expect("write fixture");
+    }
+    writer.finish().expect("finish fixture");
+    Fixture(path)
+}
+
+/// Server flags for [`start_server_with`].
+#[derive(Default)]
+struct Options<'a> {
+    chaos: bool,
+    grants: &'a [&'a str],
+    public_readonly: bool,
+    watermark: bool,
+    client_ids: bool,
+    capture_rules: &'a [CaptureRule],
+    /// Lua hooks.
+    script: Option<Script>,
+    record_dir: Option<PathBuf>,
+    /// Composite sources, by name.
+    composites: &'a [(&'a str, Layout)],
+    /// Clock sources, by name.
+    clocks: &'a [(&'a str, ClockConfig)],
+    /// Media file sources, by name.
+    media: &'a [(&'a str, MediaConfig)],
+    receiver_linger: Duration,
+    idle_after: Duration,
+    logs: Option<LogBuffer>,
+    preroll: Option<Duration>,
+    /// The fps cap; 0 for none.
+    max_fps: u32,
+    record_shared: bool,
+    /// Threads encoding each source; 0 or 1 for the capture thread.
+    encode_threads: usize,
+    /// Draw the viewer count into every source's frames.
+    viewer_badge: bool,
+    /// Look for motion in every source.
+    motion: Option<MotionSettings>,
+    /// Overlays burned into sources, by name.
+    overlays: &'a [(&'a str, OverlayConfig)],
+    limits: ConnectionLimits,
+    state_dir: Option<PathBuf>,
+    /// Statically configured NDI® sources: name and URL.
+    static_sources: &'a [(&'a str, &'a str)],
+    /// Aliases and the source names they stand in for.
+    aliases: &'a [(&'a str, &'a str)],
+    /// Shuts the server down, after draining for the time sent, when sent to.
+    shutdown: Option<tokio::sync::oneshot::Receiver<Duration>>,
+    /// Serve RTSP too, on a free port.
+    rtsp: bool,
+}
+
+/// Start a server with the given replay sources and return its address.
+async fn start_server(replays: &[(&str, &Fixture)], chaos: bool) -> SocketAddr {
+    start_server_with(replays, Options { chaos, ..Default::default() }).await
+}
+
+async fn start_server_with(replays: &[(&str, &Fixture)], options: Options<'_>) -> SocketAddr {
+    start_bridge(replays, options).await.0
+}
+
+/// Like [`start_server_with`] with RTSP on, returning its address too.
+async fn start_rtsp_server(
+    replays: &[(&str, &Fixture)],
+    options: Options<'_>,
+) -> (SocketAddr, SocketAddr) {
+    let (addr, _, rtsp) = start_bridge(replays, Options { rtsp: true, ..options }).await;
+    (addr, rtsp.expect("RTSP address"))
+}
+
+/// Like [`start_server_with`], also returning the bridge's event bus and the
+/// RTSP address, if on.
+async fn start_bridge(
+    replays: &[(&str, &Fixture)],
+    options: Options<'_>,
+) -> (SocketAddr, Arc<EventBus>, Option<SocketAddr>) {
+    let mut bridge = Bridge::builder()
+        .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .chaos(options.chaos)
+        .public_readonly(options.public_readonly)
+        .watermark(options.watermark)
+        .client_ids(options.client_ids)
+        .capture_rules(options.capture_rules.to_vec())
+        .receiver_linger(options.receiver_linger)
+        .idle_after(options.idle_after)
+        .record_shared(options.record_shared)
+        .connection_limits(options.limits);
+    for grant in options.grants {
+        bridge = bridge.grant(Grant::parse(grant).expect("grant"));
+    }
+    if let Some(dir) = options.record_dir {
+        bridge = bridge.record_dir(dir);
+    }
+    if let Some(script) = options.script {
+        bridge = bridge.script(script);
+    }
+    if options.rtsp {
+        bridge = bridge.rtsp_port(0);
+    }
+    if let Some(logs) = options.logs {
+        bridge = bridge.logs(logs);
+    }
+    if let Some(dir) = options.state_dir {
+        bridge = bridge.state_dir(dir);
+    }
+    for (name, url) in options.static_sources {
+        bridge = bridge.static_source(*name, *url);
+    }
+    for (alias, name) in options.aliases {
+        bridge = bridge.alias(*alias, *name);
+    }
+    let encode_options =
+        options.encode_threads > 1 || options.viewer_badge || options.motion.is_some();
+    let overlays = !options.overlays.is_empty();
+    if options.preroll.is_some() || options.max_fps > 0 || encode_options || overlays {
+        let settings = EncodeSettings {
+            preroll: options.preroll,
+            encode_threads: options.encode_threads.max(1),
+            viewer_badge: options.viewer_badge,
+            motion: options.motion,
+            ..EncodeSettings::new(75, options.max_fps)
+        };
+        let overrides = options.overlays.iter().map(|(name, overlay)| {
+            let overlay = SourceOverride { overlay: Some(overlay.clone()), ..Default::default() };
+            (name.to_string(), overlay)
+        });
+        bridge = bridge.settings(SourceSettings::new(settings, overrides.collect()));
+    }
+    for (name, file) in replays {
+        bridge = bridge.virtual_source(*name, VirtualSource::Replay(file.0.clone()));
+    }
+    for (name, layout) in options.composites {
+        bridge = bridge.virtual_source(*name, VirtualSource::Composite(layout.clone()));
+    }
+    for (name, clock) in options.clocks {
+        bridge = bridge.virtual_source(*name, VirtualSource::Clock(clock.clone()));
+    }
+    for (name, media) in options.media {
+        bridge = bridge.virtual_source(*name, VirtualSource::Media(media.clone()));
+    }
+    let bridge = bridge.spawn().await.expect("spawn bridge");
+    let (addr, events, rtsp) = (bridge.local_addr(), bridge.events().clone(), bridge.rtsp_addr());
+    let shutdown = options.shutdown;
+    tokio::spawn(async move {
+        match shutdown {
+            Some(shutdown) => {
+                let drain = shutdown.await.unwrap_or_default();
+                bridge.shutdown(drain).await.expect("server error");
+            }
+            None => std::future::pending().await,
+        }
+    });
+    (addr, events, rtsp)
+}
+
+/// Minimal HTTP/1.1 GET; returns (status, body).
+async fn http_get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let (status, _, body) = http_get_bytes(addr, path).await;
+    (status, String::from_utf8(body).expect("utf-8 body"))
+}
+
+/// Status, raw headers and body of a non-streaming response.
+async fn http_get_bytes(addr: SocketAddr, path: &str) -> (u16, String, Vec<u8>) {
+    http_request(addr, "GET", path).await
+}
+
+async fn http_request(addr: SocketAddr, method: &str, path: &str) -> (u16, String, Vec<u8>) {
+    http_send(addr, method, path, "", "").await
+}
+
+/// POST a JSON body with extra header lines (each ending in `\r\n`); returns
+/// (status, headers, body).
+async fn post_json(
+    addr: SocketAddr,
+    path: &str,
+    headers: &str,
+    json: &str,
+) -> (u16, String, String) {
+    let (status, headers, body) = http_send(addr, "POST", path, headers, json).await;
+    (status, headers, String::from_utf8(body).expect("utf-8 body"))
+}
+
+async fn http_send(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &str,
+    body: &str,
+) -> (u16, String, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\n{headers}Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut response))
+        .await
+        .expect("response timed out")
+        .expect("read response");
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").expect("header end");
+    let headers = String::from_utf8_lossy(&response[..split]).into_owned();
+    let status = headers[9..12].parse().expect("status code");
+    (status, headers, response[split + 4..].to_vec())
+}
+
+async fn get_json(addr: SocketAddr, path: &str) -> serde_json::Value {
+    let (status, body) = http_get(addr, path).await;
+    assert_eq!(status, 200, "GET {path}: {body}");
+    serde_json::from_str(&body).expect("JSON body")
+}
+
+type Ws = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>;
+
+async fn connect_ws(addr: SocketAddr, query: &str) -> Ws {
+    let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws?{query}"))
+        .await
+        .expect("WS connect");
+    ws
+}
+
+/// The next data or close message; pings are answered by the client itself.
+async fn next_message(ws: &mut Ws) -> Message {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, ws.next())
+            .await
+            .expect("no message before timeout")
+            .expect("stream ended")
+            .expect("WS error");
+        if !matches!(message, Message::Ping(_) | Message::Pong(_)) {
+            return message;
+        }
+    }
+}
+
+/// Read messages until a close frame arrives and return its code.
+async fn close_code(ws: &mut Ws) -> u16 {
+    loop {
+        if let Message::Close(frame) = next_message(ws).await {
+            return u16::from(frame.expect("close frame").code);
+        }
+    }
+}
+
+async fn next_jpeg(ws: &mut Ws) -> Vec<u8> {
+    match next_message(ws).await {
+        Message::Binary(data) => data.to_vec(),
+        other => panic!("expected a binary frame, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn sources_lists_replay_sources() {
+    let (a, b) = (fixture(), fixture());
+    let addr = start_server(&[("cam-a", &a), ("cam-b", &b)], false).await;
+    let sources = get_json(addr, "/sources").await;
+    let listed: Vec<_> = sources.as_array().unwrap().iter().map(|s| s["name"].as_str()).collect();
+    assert_eq!(listed, [Some("cam-a"), Some("cam-b")]);
+    // Clients written for bare names can still ask for them.
+    let names = get_json(addr, "/sources?names=true").await;
+    assert_eq!(names, serde_json::json!(["cam-a", "cam-b"]));
+}
+
+#[tokio::test]
+async fn discovery_refresh_lists_sources() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, _, body) = post_json(addr, "/api/discovery/refresh", "", "").await;
+    assert_eq!(status, 200, "{body}");
+    let listed: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+    assert_eq!(listed[0]["name"], "cam");
+}
+
+#[tokio::test]
+async fn test_page_is_served() {
+    let addr = start_server(&[], false).await;
+    let (status, body) = http_get(addr, "/").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<html"), "unexpected body: {body:.200}");
+}
+
+#[tokio::test]
+async fn openapi_spec_lists_routes_the_server_has() {
+    let file = fixture_with_audio();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let spec = get_json(addr, "/openapi.json").await;
+    assert_eq!(spec["openapi"], "3.1.0");
+    let schedule = &spec["paths"]["/api/snapshots/schedule/{id}"];
+    assert_eq!(schedule["delete"]["parameters"][0]["schema"]["type"], "integer");
+    let (status, _) = http_get(addr, "/docs").await;
+    assert_eq!(status, 303);
+    let (status, body) = http_get(addr, "/docs/swagger-initializer.js").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("/openapi.json"), "unexpected body: {body:.200}");
+
+    // Only the response head: streams never end. Unrouted paths get an empty
+    // 404, handlers' own 404s say what wasn't found.
+    let paths = spec["paths"].as_object().expect("paths");
+    let routes = paths.iter().flat_map(|(path, methods)| {
+        methods.as_object().expect("methods").keys().map(move |method| (method, path))
+    });
+    for (method, path) in routes {
+        let path = path.replace("{id}", "1").replace("{file}", "cam.wav").replace("{name}", "cam");
+        let path = path.replace("{control}", "zoom").replace("{action}", "stop");
+        let path = path.replace("{source}", "cam").replace("{service}", "device_service");
+        let method = method.to_uppercase();
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\n\
+             Content-Length: 2\r\nConnection: close\r\n\r\n{{}}"
+        );
+        stream.write_all(request.as_bytes()).await.expect("write request");
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            let read = tokio::time::timeout(TIMEOUT, stream.read(&mut byte)).await;
+            let read = read.unwrap_or_else(|_| panic!("{method} {path}: timed out"));
+            assert_eq!(read.expect("read response"), 1);
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head).to_lowercase();
+        assert!(!head.starts_with("http/1.1 405"), "{method} {path}: {head}");
+        let unrouted = head.starts_with("http/1.1 404") && head.contains("content-length: 0\r\n");
+        assert!(!unrouted, "{method} {path}: {head}");
+    }
+
+    let options = Options { public_readonly: true, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let public = get_json(addr, "/openapi.json").await;
+    assert!(public["paths"]["/stats"].is_null());
+    let public_paths = public["paths"].as_object().expect("paths");
+    assert!(public_paths.len() < paths.len());
+    assert_eq!(public["paths"]["/mosaic"]["get"]["parameters"][0]["required"], true);
+    let ws_params = public["paths"]["/ws"]["get"]["parameters"].as_array().expect("parameters");
+    assert!(ws_params.iter().all(|param| param["required"] != true), "{ws_params:?}");
+}
+
+#[tokio::test]
+async fn ws_delivers_jpeg_frames() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let mut ws = connect_ws(addr, "source=cam").await;
+    for _ in 0..5 {
+        let jpeg = next_jpeg(&mut ws).await;
+        assert_eq!(&jpeg[..2], [0xFF, 0xD8], "missing JPEG SOI marker");
+        assert_eq!(&jpeg[jpeg.len() - 2..], [0xFF, 0xD9], "missing JPEG EOI marker");
+    }
+}
+
+#[tokio::test]
+async fn ws_unknown_source_closes_with_4404() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let mut ws = connect_ws(addr, "source=nope").await;
+    assert_eq!(close_code(&mut ws).await, 4404);
+}
+
+#[tokio::test]
+async fn ws_unlisted_sources_need_a_good_address() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let mut ws = connect_ws(addr, "url=10.20.0.15&name=REMOTE").await;
+    assert_eq!(close_code(&mut ws).await, 4400);
+    // No NDI® runtime here to reach a well-formed one with.
+    let mut ws = connect_ws(addr, "url=10.20.0.15:5961&name=REMOTE").await;
+    assert_eq!(close_code(&mut ws).await, 4404);
+
+    let options = Options { public_readonly: true, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let mut ws = connect_ws(addr, "url=10.20.0.15&name=REMOTE").await;
+    assert_eq!(close_code(&mut ws).await, 4404);
+}
+
+#[tokio::test]
+async fn ws_missing_source_param_is_rejected() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let err = tokio_tungstenite::connect_async(format!("ws://{addr}/ws"))
+        .await
+        .expect_err("upgrade without ?source= must fail");
+    match err {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            assert_eq!(response.status(), 400)
+        }
+        other => panic!("expected HTTP error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn ws_negotiates_a_versioned_protocol() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let connect = |protocols: &'static str| {
+        let mut request = format!("ws://{addr}/ws?source=cam").into_client_request().unwrap();
+        let protocols = protocols.parse().unwrap();
+        request.headers_mut().insert("sec-websocket-protocol", protocols);
+        tokio_tungstenite::connect_async(request)
+    };
+
+    let (mut ws, response) = connect("streambridge.v9, streambridge.v1").await.expect("connect");
+    assert_eq!(response.headers()["sec-websocket-protocol"], "streambridge.v1");
+    let hello = match next_message(&mut ws).await {
+        Message::Text(text) => serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+        other => panic!("expected a hello, got {other:?}"),
+    };
+    assert_eq!(
+        hello,
+        serde_json::json!({
+            "type": "hello",
+            "protocol": "streambridge.v1",
+            "source": "cam",
+            "format": "image/jpeg",
+        })
+    );
+    assert_eq!(&next_jpeg(&mut ws).await[..2], [0xFF, 0xD8]);
+
+    match connect("streambridge.v9").await.expect_err("no common protocol") {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            assert_eq!(response.status(), 400)
+        }
+        other => panic!("expected HTTP error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn ws_meta_framing_precedes_frames_with_their_timing() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let now_ms = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let before = now_ms();
+    let mut ws = connect_ws(addr, "source=cam&framing=meta").await;
+    let mut last_seq = 0;
+    for _ in 0..3 {
+        let Message::Text(text) = next_message(&mut ws).await else {
+            panic!("expected the frame's details first");
+        };
+        let tag: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!((tag["type"].as_str(), tag["width"].as_u64()), (Some("frame"), Some(64)));
+        assert_eq!(tag["height"], 48);
+        // Replays carry no NDI® timing.
+        assert!(tag.get("timecode").is_none() && tag.get("timestamp").is_none(), "{tag}");
+        let received = tag["received_ms"].as_u64().expect("received_ms");
+        assert!((before..=now_ms()).contains(&received), "{tag}");
+        let seq = tag["seq"].as_u64().expect("seq");
+        assert!(seq > last_seq);
+        last_seq = seq;
+        assert!(matches!(next_message(&mut ws).await, Message::Binary(_)));
+    }
+}
+
+#[tokio::test]
+async fn ws_grab_sends_a_tagged_full_size_still() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let url = format!("ws://{addr}/ws?source=cam&width=32&fps=1");
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("sec-websocket-protocol", "streambridge.v1".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await.expect("connect");
+    next_message(&mut ws).await;
+    assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 32);
+
+    ws.send(Message::Text(r#"{"cmd": "grab"}"#.into())).await.expect("send grab");
+    // Stream frames may come first, one a second.
+    loop {
+        match next_message(&mut ws).await {
+            Message::Text(text) => {
+                let tag: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(tag, serde_json::json!({ "type": "still", "format": "image/jpeg" }));
+                break;
+            }
+            Message::Binary(frame) => assert_eq!(jpeg_width(&frame), 32),
+            other => panic!("expected a frame or the still's tag, got {other:?}"),
+        }
+    }
+    assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 64);
+}
+
+#[tokio::test]
+async fn ws_annotations_follow_numbered_frames() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let url = format!("ws://{addr}/ws?source=cam&annotations=true");
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("sec-websocket-protocol", "streambridge.v1".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await.expect("connect");
+    next_message(&mut ws).await;
+    let Message::Text(text) = next_message(&mut ws).await else {
+        panic!("expected the frame's number first");
+    };
+    let tag: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(tag["type"], "frame");
+    let seq = tag["seq"].as_u64().expect("seq");
+    next_jpeg(&mut ws).await;
+
+    let body = format!(
+        r#"{{"seq": {seq}, "boxes": [{{"x": 0.1, "y": 0.1, "width": 0.5, "height": 0.5,
+            "label": "person", "score": 0.9}}]}}"#
+    );
+    let (status, _, response) = post_json(addr, "/api/annotations/cam", "", &body).await;
+    assert_eq!((status, response.as_str()), (200, r#"{"ok":true,"viewers":1}"#));
+    let outside = body.replace("\"x\": 0.1", "\"x\": 0.9");
+    let (status, _, _) = post_json(addr, "/api/annotations/cam", "", &outside).await;
+    assert_eq!(status, 400);
+    let (status, _, _) = post_json(addr, "/api/annotations/nope", "", &body).await;
+    assert_eq!(status, 404);
+
+    loop {
+        let Message::Text(text) = next_message(&mut ws).await else {
+            continue;
+        };
+        let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+        if message["type"] == "annotations" {
+            assert_eq!(message["seq"].as_u64(), Some(seq));
+            assert_eq!(message["boxes"][0]["label"], "person");
+            break;
+        }
+        assert_eq!(message["type"], "frame");
+    }
+
+    let (status, headers, _) = http_get_bytes(addr, "/snapshot/cam").await;
+    assert_eq!(status, 200);
+    assert!(headers.to_ascii_lowercase().contains("x-frame-seq: "), "{headers}");
+}
+
+#[tokio::test]
+async fn encode_threads_deliver_frames_in_capture_order() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture();
+    let options = Options { encode_threads: 3, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let url = format!("ws://{addr}/ws?source=cam&annotations=true");
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("sec-websocket-protocol", "streambridge.v1".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await.expect("connect");
+    next_message(&mut ws).await;
+    let mut last = 0;
+    for _ in 0..10 {
+        let Message::Text(text) = next_message(&mut ws).await else {
+            panic!("expected the frame's number first");
+        };
+        let tag: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let seq = tag["seq"].as_u64().expect("seq");
+        assert!(seq > last, "frame {seq} after {last}");
+        last = seq;
+        assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 64);
+    }
+
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let stages = pipeline["cam"]["stages"].as_array().expect("stages");
+    let encode = stages.iter().find(|s| s["stage"] == "encode").expect("encode stage");
+    assert_eq!(encode["threads"], 3);
+}
+
+#[tokio::test]
+async fn scheduled_snapshots_are_kept_until_their_schedule_goes() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let body = r#"{"sources": ["cam"], "every": 0}"#;
+    assert_eq!(post_json(addr, "/api/snapshots/schedule", "", body).await.0, 400);
+
+    let body = r#"{"sources": ["cam"], "keep": 5}"#;
+    let (status, _, created) = post_json(addr, "/api/snapshots/schedule", "", body).await;
+    assert_eq!(status, 201, "{created}");
+    let id = serde_json::from_str::<serde_json::Value>(&created).unwrap()["id"].clone();
+    let snapshots = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let snapshots = get_json(addr, "/api/snapshots/cam").await;
+            if snapshots.as_array().is_some_and(|s| !s.is_empty()) {
+                return snapshots;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("no scheduled snapshot taken");
+    assert_eq!(snapshots[0]["schedule"], id);
+
+    let path = format!("/api/snapshots/cam/{}", snapshots[0]["file"].as_str().unwrap());
+    let (status, _, jpeg) = http_get_bytes(addr, &path).await;
+    assert_eq!((status, jpeg_width(&jpeg)), (200, 64));
+    assert_eq!(http_get_bytes(addr, "/api/snapshots/cam/latest").await.2, jpeg);
+    // A one-off: done once taken.
+    let schedules = get_json(addr, "/api/snapshots/schedule").await;
+    assert_eq!(schedules[0]["next_at"], serde_json::Value::Null);
+    assert_eq!(schedules[0]["snapshots"], 1);
+
+    let schedule = format!("/api/snapshots/schedule/{id}");
+    assert_eq!(http_request(addr, "DELETE", &schedule).await.0, 204);
+    assert_eq!(get_json(addr, "/api/snapshots/cam").await, serde_json::json!([]));
+    assert_eq!(http_get_bytes(addr, "/api/snapshots/cam/latest").await.0, 404);
+}
+
+#[tokio::test]
+async fn ws_closes_with_4410_when_source_is_lost() {
+    let missing = Fixture(std::env::temp_dir().join("streambridge-test-does-not-exist.sbraw"));
+    let addr = start_server(&[("broken", &missing)], false).await;
+    let mut ws = connect_ws(addr, "source=broken").await;
+    assert_eq!(close_code(&mut ws).await, 4410);
+}
+
+#[tokio::test]
+async fn shutdown_closes_websockets_and_stops_listening() {
+    let file = fixture();
+    let (shutdown, signal) = tokio::sync::oneshot::channel();
+    let options = Options { shutdown: Some(signal), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+
+    shutdown.send(Duration::ZERO).unwrap();
+    assert_eq!(close_code(&mut ws).await, 1001);
+    tokio::time::timeout(TIMEOUT, async {
+        while TcpStream::connect(addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("still listening after shutdown");
+}
+
+#[tokio::test]
+async fn draining_refuses_new_clients_and_asks_others_to_reconnect() {
+    let file = fixture();
+    let (shutdown, signal) = tokio::sync::oneshot::channel();
+    let options = Options { shutdown: Some(signal), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    assert_eq!(http_get(addr, "/healthz").await, (200, "ok".to_string()));
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+
+    shutdown.send(Duration::from_secs(30)).unwrap();
+    // Up until the client has answered its close frame, but taking no one new.
+    tokio::time::timeout(TIMEOUT, async {
+        while http_get(addr, "/healthz").await.0 != 503 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("healthz still reports ok");
+    assert_eq!(http_get_bytes(addr, "/snapshot/cam").await.0, 503);
+    assert_eq!(close_code(&mut ws).await, 1012);
+    drop(ws);
+    tokio::time::timeout(TIMEOUT, async {
+        while TcpStream::connect(addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("drain didn't end when the last client left");
+}
+
+#[tokio::test]
+async fn stats_track_shared_receiver_clients() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    assert_eq!(get_json(addr, "/stats").await, serde_json::json!({}));
+
+    let mut first = connect_ws(addr, "source=cam").await;
+    let mut second = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut first).await;
+    next_jpeg(&mut second).await;
+
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats["cam"]["clients"], 2, "stats: {stats}");
+    assert!(stats["cam"]["loudness"].is_null(), "stats: {stats}");
+
+    // Once both clients leave, the receiver is torn down and drops out of /stats.
+    first.close(None).await.expect("close");
+    second.close(None).await.expect("close");
+    drop((first, second));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        if get_json(addr, "/stats").await == serde_json::json!({}) {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "receiver never removed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn bandwidth_only_splits_ndi_receivers() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    // A replay has one stream, so every bandwidth shares its receiver.
+    let mut full = connect_ws(addr, "source=cam&bandwidth=highest").await;
+    let mut preview = connect_ws(addr, "source=cam&bandwidth=lowest").await;
+    next_jpeg(&mut full).await;
+    next_jpeg(&mut preview).await;
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats.as_object().map(|s| s.len()), Some(1), "stats: {stats}");
+    assert_eq!(stats["cam"]["clients"], 2, "stats: {stats}");
+
+    let (status, _) = http_get(addr, "/stream/cam?bandwidth=medium").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn clients_are_listed_with_their_throughput() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    assert_eq!(get_json(addr, "/api/clients").await, serde_json::json!([]));
+
+    let mut ws = connect_ws(addr, "source=cam").await;
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /stream/cam HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    read_until(&mut stream, &mut response, b"\xFF\xD9").await;
+    // Throughput is measured over two-second windows.
+    let measured = tokio::time::Instant::now() + Duration::from_millis(2_500);
+    while tokio::time::Instant::now() < measured {
+        next_jpeg(&mut ws).await;
+    }
+
+    let clients = get_json(addr, "/api/clients").await;
+    let kinds: Vec<&str> = clients
+        .as_array()
+        .expect("a list")
+        .iter()
+        .map(|c| c["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["websocket", "mjpeg"], "{clients}");
+    let websocket = &clients[0];
+    assert_eq!(websocket["source"], "cam");
+    assert!(websocket["bytes"].as_u64() > Some(0), "{clients}");
+    assert!(websocket["kbps"].as_f64() > Some(0.0), "{clients}");
+
+    ws.close(None).await.expect("close");
+    drop((ws, stream));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while get_json(addr, "/api/clients").await != serde_json::json!([]) {
+        assert!(tokio::time::Instant::now() < deadline, "clients never removed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn reconnections_keep_their_client_id() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture();
+    let options = Options { client_ids: true, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let (_, headers, _) = http_get_bytes(addr, "/").await;
+    let cookie = headers
+        .lines()
+        .find_map(|line| line.strip_prefix("set-cookie: "))
+        .and_then(|value| value.split(';').next())
+        .expect("client id cookie")
+        .to_string();
+    let client_id = cookie.strip_prefix("streambridge_client=").expect("cookie name");
+
+    // A page reload: the same viewer connects twice.
+    for _ in 0..2 {
+        let mut request = format!("ws://{addr}/ws?source=cam").into_client_request().unwrap();
+        request.headers_mut().insert("cookie", cookie.parse().unwrap());
+        let (mut ws, response) = tokio_tungstenite::connect_async(request).await.expect("WS");
+        assert!(!response.headers().contains_key("set-cookie"), "a known viewer");
+        next_jpeg(&mut ws).await;
+        ws.close(None).await.expect("close");
+    }
+    let mut ws = connect_ws(addr, &format!("source=cam&client_id={client_id}")).await;
+    next_jpeg(&mut ws).await;
+    let mut other = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut other).await;
+
+    let clients = get_json(addr, "/api/clients").await;
+    let listed = clients.as_array().expect("a list");
+    let ours = listed.iter().find(|c| c["client_id"] == client_id).expect("listed");
+    assert_eq!(ours["visits"], 3, "{clients}");
+    let others: Vec<_> = listed.iter().filter(|c| c["client_id"] != client_id).collect();
+    assert_eq!(others.len(), 1, "{clients}");
+    assert_eq!(others[0]["visits"], 1, "{clients}");
+}
+
+#[tokio::test]
+async fn idle_servers_release_lingering_receivers() {
+    let file = fixture();
+    let options = Options {
+        receiver_linger: Duration::from_secs(60),
+        idle_after: Duration::from_secs(1),
+        ..Default::default()
+    };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    ws.close(None).await.expect("close");
+    drop(ws);
+
+    // Any request wakes the server, so look only once it has had time to idle.
+    tokio::time::sleep(Duration::from_millis(3_000)).await;
+    assert_eq!(get_json(addr, "/stats").await, serde_json::json!({}));
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+}
+
+#[tokio::test]
+async fn receivers_linger_after_their_last_client() {
+    let file = fixture();
+    let options = Options { receiver_linger: Duration::from_secs(1), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    ws.close(None).await.expect("close");
+    drop(ws);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats["cam"]["clients"], 0, "stats: {stats}");
+
+    // A client coming back reattaches to the warm receiver.
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    ws.close(None).await.expect("close");
+    drop(ws);
+
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while get_json(addr, "/stats").await != serde_json::json!({}) {
+        assert!(tokio::time::Instant::now() < deadline, "receiver never stopped");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn chaos_params_are_ignored_without_chaos_mode() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let mut ws = connect_ws(addr, "source=cam&drop=1").await;
+    next_jpeg(&mut ws).await;
+}
+
+#[tokio::test]
+async fn chaos_drop_withholds_all_frames() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], true).await;
+    let mut ws = connect_ws(addr, "source=cam&drop=1").await;
+    let result = tokio::time::timeout(Duration::from_millis(500), ws.next()).await;
+    assert!(result.is_err(), "expected no frames with drop=1, got {result:?}");
+}
+
+#[tokio::test]
+async fn source_detail_reports_receiver_state() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    let idle = get_json(addr, "/api/sources/cam").await;
+    assert_eq!(idle["name"], "cam");
+    assert_eq!(idle["active"], false);
+
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    let active = get_json(addr, "/api/sources/cam").await;
+    assert_eq!(active["active"], true);
+    assert_eq!(active["clients"], 1);
+    assert!(active["ndi"].is_null(), "replay sources have no NDI status: {active}");
+
+    let (status, _) = http_get(addr, "/api/sources/nope").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn mjpeg_stream_delivers_multipart_jpegs() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /stream/cam HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+
+    // Read until three complete parts have arrived.
+    let mut received = Vec::new();
+    let mut buf = [0u8; 8192];
+    while count(&received, b"\r\n--frame\r\n") < 3 {
+        let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .expect("stream stalled")
+            .expect("read");
+        assert!(n > 0, "server closed the stream");
+        received.extend_from_slice(&buf[..n]);
+    }
+
+    let text = String::from_utf8_lossy(&received);
+    assert!(text.starts_with("HTTP/1.1 200"), "{text:.200}");
+    assert!(text.contains("multipart/x-mixed-replace; boundary=frame"), "{text:.400}");
+    let part_start = text.find("--frame\r\n").expect("first part");
+    let part = &received[part_start..];
+    let body = part.windows(4).position(|w| w == b"\r\n\r\n").expect("part header") + 4;
+    assert_eq!(&part[body..body + 2], [0xFF, 0xD8], "part body is not a JPEG");
+
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats["cam"]["clients"], 1, "stats: {stats}");
+}
+
+#[tokio::test]
+async fn clients_past_the_connection_limits_get_429() {
+    let (a, b) = (fixture(), fixture());
+    let limits = ConnectionLimits {
+        max_clients_per_source: Some(1),
+        max_connections_per_ip: Some(2),
+        ..Default::default()
+    };
+    let options = Options { limits, ..Default::default() };
+    let addr = start_server_with(&[("a", &a), ("b", &b)], options).await;
+
+    let mut first = connect_ws(addr, "source=a").await;
+    next_jpeg(&mut first).await;
+    let mut second = connect_ws(addr, "source=a").await;
+    assert_eq!(close_code(&mut second).await, 4429);
+    assert_eq!(http_get(addr, "/stream/a").await.0, 429);
+    // Turned away clients don't count against the address.
+    let mut other = connect_ws(addr, "source=b").await;
+    next_jpeg(&mut other).await;
+    drop((first, other));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut third = connect_ws(addr, "source=a").await;
+    assert_eq!(close_code(&mut third).await, 4429);
+}
+
+#[tokio::test]
+async fn mjpeg_stream_unknown_source_is_404() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, _) = http_get(addr, "/stream/nope").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn snapshot_returns_one_jpeg_and_releases_receiver() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, headers, body) = http_get_bytes(addr, "/snapshot/cam").await;
+    assert_eq!(status, 200, "{headers}");
+    assert!(headers.to_lowercase().contains("content-type: image/jpeg"), "{headers}");
+    assert_eq!(&body[..2], [0xFF, 0xD8], "body is not a JPEG");
+    assert_eq!(&body[body.len() - 2..], [0xFF, 0xD9], "JPEG is truncated");
+
+    // The receiver only existed for the snapshot.
+    let stats = get_json(addr, "/stats").await;
+    assert!(stats.get("cam").is_none(), "stats: {stats}");
+}
+
+#[tokio::test]
+async fn frames_come_as_png_or_webp_on_request() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, headers, png) = http_get_bytes(addr, "/snapshot/cam?format=png").await;
+    assert_eq!(status, 200, "{headers}");
+    assert!(headers.to_lowercase().contains("content-type: image/png"), "{headers}");
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(http_get_bytes(addr, "/snapshot/cam?format=gif").await.0, 400);
+
+    let mut ws = connect_ws(addr, "source=cam&format=webp").await;
+    let webp = next_jpeg(&mut ws).await;
+    assert_eq!((&webp[..4], &webp[8..12]), (&b"RIFF"[..], &b"WEBP"[..]));
+}
+
+#[tokio::test]
+async fn snapshot_unknown_source_is_404() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, _) = http_get(addr, "/snapshot/nope").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn grants_restrict_sources_and_actions() {
+    let (a, b) = (fixture(), fixture());
+    let grants = ["viewer:view:cam-a", "admin:*:*"];
+    let options = Options { grants: &grants, ..Default::default() };
+    let addr = start_server_with(&[("cam-a", &a), ("cam-b", &b)], options).await;
+
+    let (status, _) = http_get(addr, "/snapshot/cam-a").await;
+    assert_eq!(status, 401, "no token");
+    let (status, _) = http_get(addr, "/snapshot/cam-a?token=wrong").await;
+    assert_eq!(status, 401, "unknown token");
+    let (status, _) = http_get(addr, "/snapshot/cam-a?token=viewer").await;
+    assert_eq!(status, 403, "viewer may not snapshot");
+    let (status, _) = http_get(addr, "/api/sources/cam-b?token=viewer").await;
+    assert_eq!(status, 403, "viewer may not see cam-b");
+    let (status, _) = http_get(addr, "/api/sources/cam-a?token=viewer").await;
+    assert_eq!(status, 200);
+    let (status, _, _) = http_get_bytes(addr, "/snapshot/cam-b?token=admin").await;
+    assert_eq!(status, 200);
+
+    let names = get_json(addr, "/sources?names=true&token=viewer").await;
+    assert_eq!(names, serde_json::json!(["cam-a"]));
+    let (status, headers, _) = http_get_bytes(addr, "/sources").await;
+    assert_eq!(status, 401, "no token");
+    assert!(headers.contains("www-authenticate: Bearer"), "{headers}");
+    for path in ["/events", "/stats?token=wrong", "/api/clients"] {
+        assert_eq!(http_get(addr, path).await.0, 401, "{path}");
+    }
+    // The page prompts for a token, and health checks carry none.
+    assert_eq!(http_get(addr, "/").await.0, 200);
+    assert_eq!(http_get_bytes(addr, "/healthz").await.0, 200);
+
+    let mut ws = connect_ws(addr, "source=cam-b&token=viewer").await;
+    assert_eq!(close_code(&mut ws).await, 4403);
+    let mut ws = connect_ws(addr, "source=cam-a").await;
+    assert_eq!(close_code(&mut ws).await, 4401);
+    let mut ws = connect_ws(addr, "source=cam-a&token=viewer").await;
+    next_jpeg(&mut ws).await;
+}
+
+#[tokio::test]
+async fn admin_logs_are_filtered_by_level_source_and_grant() {
+    use tracing_subscriber::prelude::*;
+
+    let logs = LogBuffer::new(100);
+    let subscriber = tracing_subscriber::registry().with(logs.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!("discovery started");
+        tracing::warn!("[cam] no frames for 5s");
+        tracing::error!(code = 3, "[other] receiver failed");
+    });
+    let grants = ["ops:control:*", "cam:control:cam"];
+    let options = Options { grants: &grants, logs: Some(logs), ..Default::default() };
+    let addr = start_server_with(&[], options).await;
+
+    let all = get_json(addr, "/admin/logs?token=ops").await;
+    assert_eq!(all.as_array().expect("array").len(), 3, "{all}");
+    assert_eq!(all[2]["message"], "[other] receiver failed code=3");
+    assert_eq!(all[2]["source"], "other");
+    assert_eq!(all[2]["level"], "error");
+
+    let warnings = get_json(addr, "/admin/logs?token=ops&level=warn").await;
+    assert_eq!(warnings.as_array().expect("array").len(), 2, "{warnings}");
+    let cam = get_json(addr, "/admin/logs?token=ops&source=cam").await;
+    assert_eq!(cam[0]["seq"], 2, "{cam}");
+    let newest = get_json(addr, "/admin/logs?token=ops&after=1&limit=1").await;
+    assert_eq!(newest[0]["seq"], 3, "{newest}");
+    let limited = get_json(addr, "/admin/logs?token=cam").await;
+    assert_eq!(limited.as_array().expect("array").len(), 1, "only cam's: {limited}");
+    assert_eq!(http_get(addr, "/admin/logs?token=ops&level=loud").await.0, 400);
+    assert_eq!(http_get(addr, "/admin/logs").await.0, 401);
+}
+
+#[tokio::test]
+async fn bearer_header_is_accepted() {
+    let file = fixture();
+    let options = Options { grants: &["secret:view:*"], ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!(
+        "GET /api/sources/cam HTTP/1.1\r\nHost: {addr}\r\nAuthorization: Bearer secret\r\nConnection: close\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.expect("read response");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
+
+#[tokio::test]
+async fn public_readonly_hides_admin_endpoints_and_caps_size() {
+    let file = fixture_sized(1280, 16);
+    let options = Options { public_readonly: true, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let (status, _) = http_get(addr, "/stats").await;
+    assert_eq!(status, 404);
+    let (status, _, jpeg) = http_get_bytes(addr, "/snapshot/cam").await;
+    assert_eq!(status, 200);
+    assert_eq!(jpeg_width(&jpeg), PUBLIC_MAX_WIDTH);
+}
+
+#[tokio::test]
+async fn watermarked_snapshot_is_a_valid_jpeg() {
+    let file = fixture();
+    let options = Options { grants: &["viewer:*:*"], watermark: true, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let (status, _, jpeg) = http_get_bytes(addr, "/snapshot/cam?token=viewer").await;
+    assert_eq!(status, 200);
+    assert_eq!(&jpeg[..2], [0xFF, 0xD8]);
+    assert_eq!(jpeg_width(&jpeg), 64);
+    // Marks are drawn into JPEGs only.
+    let (status, _, _) = http_get_bytes(addr, "/snapshot/cam?token=viewer&format=webp").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn webhook_triggers_capture_rules() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-captures-{}", std::process::id()));
+    let upload_server = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let upload_addr = upload_server.local_addr().expect("local addr");
+    let rules = [CaptureRule {
+        on: "webhook".into(),
+        source: None,
+        webhook: Some("goal".into()),
+        path: Some(format!("{}/{{source}}-{{event}}.jpg", dir.display())),
+        upload: Some(format!("http://{upload_addr}/in/{{source}}.jpg")),
+        preroll: None,
+    }];
+    let options = Options { capture_rules: &rules, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let (status, _, _) = http_request(addr, "POST", "/api/hooks/other?source=cam").await;
+    assert_eq!(status, 202);
+    let (status, _, _) = http_request(addr, "POST", "/api/hooks/goal?source=cam").await;
+    assert_eq!(status, 202);
+    let (status, _, _) = http_request(addr, "POST", "/api/hooks/goal?source=nope").await;
+    assert_eq!(status, 404);
+
+    // The upload arrives as a PUT with the JPEG as its body.
+    let (mut upload, _) = tokio::time::timeout(TIMEOUT, upload_server.accept())
+        .await
+        .expect("no upload")
+        .expect("accept");
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    while count(&request, &[0xFF, 0xD9]) == 0 {
+        let n = tokio::time::timeout(TIMEOUT, upload.read(&mut buf))
+            .await
+            .expect("upload stalled")
+            .expect("read");
+        assert!(n > 0, "upload closed early");
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request_line = String::from_utf8_lossy(&request[..request.len().min(40)]);
+    assert!(request_line.starts_with("PUT /in/cam.jpg HTTP/1.1"), "{request_line}");
+    upload.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n").await.expect("respond");
+
+    let saved = dir.join("cam-webhook.jpg");
+    let jpeg = std::fs::read(&saved).expect("capture written");
+    assert_eq!(&jpeg[..2], [0xFF, 0xD8]);
+    assert_eq!(std::fs::read_dir(&dir).expect("capture dir").count(), 1, "only the goal hook captures");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn scripts_run_commands_and_webhooks_on_events() {
+    let file = fixture();
+    let hook_server = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let hook_addr = hook_server.local_addr().expect("local addr");
+    let code = format!(
+        r#"
+        streambridge.on("webhook", function(event)
+            local result = streambridge.command({{
+                command = "set_quality", source = event.source, jpeg_quality = 40
+            }})
+            local missing = streambridge.command({{ command = "stop_record", source = "nope" }})
+            streambridge.webhook("http://{hook_addr}/hooks", {{
+                source = event.source, quality = result.jpeg_quality, error = missing.error
+            }})
+        end)
+        "#
+    );
+    let script = Script::new(&code, "hooks.lua").expect("script");
+    let options = Options { script: Some(script), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let (status, _, _) = http_request(addr, "POST", "/api/hooks/goal?source=cam").await;
+    assert_eq!(status, 202);
+    let (mut hook, _) = tokio::time::timeout(TIMEOUT, hook_server.accept())
+        .await
+        .expect("no webhook")
+        .expect("accept");
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !request.ends_with(b"}") {
+        let n = tokio::time::timeout(TIMEOUT, hook.read(&mut buf))
+            .await
+            .expect("webhook stalled")
+            .expect("read");
+        assert!(n > 0, "webhook closed early");
+        request.extend_from_slice(&buf[..n]);
+    }
+    hook.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.expect("respond");
+    let request = String::from_utf8(request).expect("utf-8 request");
+    assert!(request.starts_with("POST /hooks HTTP/1.1"), "{request}");
+    let (_, body) = request.split_once("\r\n\r\n").expect("body");
+    let body: serde_json::Value = serde_json::from_str(body).expect("JSON body");
+    assert_eq!(body["source"], "cam");
+    assert_eq!(body["quality"], 40);
+    assert_eq!(body["error"], "source not found");
+}
+
+#[tokio::test]
+async fn commands_are_idempotent_per_key() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let set = r#"{"command": "set_quality", "source": "cam", "jpeg_quality": 40}"#;
+
+    let key = "Idempotency-Key: abc\r\n";
+    let (status, headers, body) = post_json(addr, "/api/commands", key, set).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(!headers.to_ascii_lowercase().contains("idempotent-replayed"));
+    let result: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+    assert_eq!(result["command"], "set_quality");
+    assert_eq!(result["jpeg_quality"], 40);
+
+    let (status, headers, replayed) = post_json(addr, "/api/commands", key, set).await;
+    assert_eq!(status, 200);
+    assert!(headers.to_ascii_lowercase().contains("idempotent-replayed: true"), "{headers}");
+    assert_eq!(replayed, body);
+
+    // The key may also come in the body.
+    let keyed = r#"{"command": "set_quality", "source": "cam", "jpeg_quality": 0,
+                   "idempotency_key": "k1"}"#;
+    for _ in 0..2 {
+        let (status, _, body) = post_json(addr, "/api/commands", "", keyed).await;
+        assert_eq!(status, 400, "{body}");
+    }
+
+    let unknown = r#"{"command": "stop_record", "source": "nope"}"#;
+    assert_eq!(post_json(addr, "/api/commands", "", unknown).await.0, 404);
+    let program = r#"{"command": "switch_program", "source": "cam"}"#;
+    assert_eq!(post_json(addr, "/api/commands", "", program).await.0, 409);
+    let ptz = r#"{"command": "recall_ptz_preset", "source": "cam", "preset": 3}"#;
+    let (status, _, body) = post_json(addr, "/api/commands", "", ptz).await;
+    assert_eq!(status, 409, "{body}");
+    assert!(body.contains("no PTZ"), "{body}");
+    assert_eq!(post_json(addr, "/api/commands", "", r#"{"command": "fly"}"#).await.0, 400);
+}
+
+#[tokio::test]
+async fn record_commands_write_a_replayable_file() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-recordings-{}", std::process::id()));
+    let options = Options { record_dir: Some(dir.clone()), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let start = r#"{"command": "start_record", "source": "cam"}"#;
+    let stop = r#"{"command": "stop_record", "source": "cam"}"#;
+
+    let (status, _, body) = post_json(addr, "/api/commands", "", start).await;
+    assert_eq!(status, 200, "{body}");
+    let path = serde_json::from_str::<serde_json::Value>(&body).expect("JSON body")["path"]
+        .as_str()
+        .expect("path")
+        .to_string();
+    assert_eq!(post_json(addr, "/api/commands", "", start).await.0, 409);
+
+    // Nobody is watching; the recording alone keeps the receiver running.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (status, _, body) = post_json(addr, "/api/commands", "", stop).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(post_json(addr, "/api/commands", "", stop).await.0, 409);
+
+    let mut reader = RawReader::open(std::path::Path::new(&path)).expect("recording");
+    let mut frames = 0;
+    while let Some(timed) = reader.next_record().expect("read recording") {
+        if let Record::Video(video) = timed.record {
+            assert_eq!((video.width, video.height), (64, 48));
+            frames += 1;
+        }
+    }
+    assert!(frames >= 5, "only {frames} frames recorded");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recordings_are_written_as_avi_listed_and_downloaded() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-avi-{}", std::process::id()));
+    let grants = ["rec:record:cam", "viewer:view:*"];
+    let options = Options { record_dir: Some(dir.clone()), grants: &grants, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let auth = "Authorization: Bearer rec\r\n";
+
+    let (status, _, body) = post_json(addr, "/record/cam/start", auth, "").await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains(r#""format":"avi""#), "{body}");
+    let viewer = "Authorization: Bearer viewer\r\n";
+    assert_eq!(post_json(addr, "/record/cam/stop", viewer, "").await.0, 403);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (status, _, body) = post_json(addr, "/record/cam/stop", auth, "").await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(post_json(addr, "/record/cam/stop", auth, "").await.0, 409);
+    assert_eq!(post_json(addr, "/record/cam/pause", auth, "").await.0, 404);
+
+    let listed = get_json(addr, "/recordings?token=rec").await;
+    let listed = listed.as_array().expect("array");
+    assert_eq!(listed.len(), 1, "{listed:?}");
+    assert_eq!(listed[0]["source"], "cam");
+    let name = listed[0]["name"].as_str().expect("name");
+    assert!(name.starts_with("cam-") && name.ends_with(".avi"), "{name}");
+    assert_eq!(get_json(addr, "/recordings?token=viewer").await, serde_json::json!([]));
+
+    let path = format!("/recordings/{name}?token=rec");
+    let (status, headers, avi) = http_get_bytes(addr, &path).await;
+    assert_eq!(status, 200);
+    assert!(headers.to_ascii_lowercase().contains("content-type: video/x-msvideo"), "{headers}");
+    assert_eq!(avi.len() as u64, listed[0]["bytes"].as_u64().expect("bytes"));
+    assert_eq!((&avi[..4], &avi[8..12]), (&b"RIFF"[..], &b"AVI "[..]));
+    let frames = u32::from_le_bytes(avi[48..52].try_into().unwrap());
+    assert!(frames >= 5, "only {frames} frames recorded");
+    let path = format!("/recordings/{name}?token=viewer");
+    assert_eq!(http_get_bytes(addr, &path).await.0, 403);
+    assert_eq!(http_get_bytes(addr, "/recordings/..%2Fsecret.avi?token=rec").await.0, 404);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn avi_recordings_can_be_written_at_a_fixed_rate() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-cadence-{}", std::process::id()));
+    let options = Options { record_dir: Some(dir.clone()), ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let raw = r#"{"format": "raw", "fps": 50}"#;
+    assert_eq!(post_json(addr, "/record/cam/start", "", raw).await.0, 400);
+    assert_eq!(post_json(addr, "/record/cam/start", "", r#"{"fps": 0}"#).await.0, 400);
+    let (status, _, body) = post_json(addr, "/record/cam/start", "", r#"{"fps": 50}"#).await;
+    assert_eq!(status, 200, "{body}");
+    let started: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+    assert_eq!(started["fps"], 50);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let (status, _, body) = post_json(addr, "/record/cam/stop", "", "").await;
+    assert_eq!(status, 200, "{body}");
+
+    let avi = std::fs::read(started["path"].as_str().expect("path")).expect("recording");
+    let usec_per_frame = u32::from_le_bytes(avi[32..36].try_into().unwrap());
+    assert_eq!(usec_per_frame, 20_000);
+    // Frames are repeated to fill 50 a second, whatever the source sends.
+    let frames = u32::from_le_bytes(avi[48..52].try_into().unwrap());
+    assert!((25..=40).contains(&frames), "{frames} frames recorded");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn shared_recordings_take_the_frames_viewers_get() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-shared-{}", std::process::id()));
+    let options = Options {
+        record_dir: Some(dir.clone()),
+        max_fps: 5,
+        record_shared: true,
+        ..Default::default()
+    };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let (status, _, body) = post_json(addr, "/record/cam/start", "", "").await;
+    assert_eq!(status, 200, "{body}");
+    let started: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let (status, _, body) = post_json(addr, "/record/cam/stop", "", "").await;
+    assert_eq!(status, 200, "{body}");
+
+    // Nobody watched, yet the recording kept to the 5 fps cap.
+    let avi = std::fs::read(started["path"].as_str().expect("path")).expect("recording");
+    let frames = u32::from_le_bytes(avi[48..52].try_into().unwrap());
+    assert!((2..=7).contains(&frames), "{frames} frames recorded");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn preroll_is_served_recorded_and_captured() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-preroll-{}", std::process::id()));
+    let rules = [CaptureRule {
+        on: "webhook".into(),
+        source: None,
+        webhook: Some("incident".into()),
+        path: None,
+        upload: None,
+        preroll: Some(format!("{}/{{source}}-{{event}}.avi", dir.display())),
+    }];
+    let options = Options {
+        record_dir: Some(dir.clone()),
+        capture_rules: &rules,
+        preroll: Some(Duration::from_secs(2)),
+        // Keeps the source received, and its pre-roll filling, after the snapshot.
+        receiver_linger: Duration::from_secs(5),
+        ..Default::default()
+    };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+    let frames = |avi: &[u8]| u32::from_le_bytes(avi[48..52].try_into().unwrap());
+
+    assert_eq!(http_get_bytes(addr, "/preroll/cam").await.0, 404, "not received yet");
+    assert_eq!(http_get_bytes(addr, "/snapshot/cam").await.0, 200);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (status, headers, avi) = http_get_bytes(addr, "/preroll/cam").await;
+    assert_eq!(status, 200);
+    assert!(headers.contains("cam-preroll-"), "{headers}");
+    assert_eq!((&avi[..4], &avi[8..12]), (&b"RIFF"[..], &b"AVI "[..]));
+    let buffered = frames(&avi);
+    assert!(buffered >= 5, "only {buffered} frames buffered");
+
+    // Recordings start with the pre-roll.
+    assert_eq!(post_json(addr, "/record/cam/start", "", "").await.0, 200);
+    let (status, _, body) = post_json(addr, "/record/cam/stop", "", "").await;
+    assert_eq!(status, 200, "{body}");
+    let listed = get_json(addr, "/recordings").await;
+    let name = listed[0]["name"].as_str().expect("name");
+    let recorded = http_get_bytes(addr, &format!("/recordings/{name}")).await.2;
+    assert!(frames(&recorded) >= buffered, "{} frames recorded", frames(&recorded));
+
+    let (status, _, _) = http_request(addr, "POST", "/api/hooks/incident?source=cam").await;
+    assert_eq!(status, 202);
+    let saved = dir.join("cam-webhook.avi");
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    while !saved.exists() && std::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let captured = std::fs::read(&saved).expect("pre-roll captured");
+    assert_eq!(&captured[..4], b"RIFF");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn audio_streams_as_endless_wav() {
+    let file = fixture_with_audio();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    let (status, body) = http_get(addr, "/audio/cam.ogg").await;
+    assert_eq!(status, 404);
+    assert!(body.contains(".mp3 or .aac"), "{body}");
+    if !cfg!(feature = "ffmpeg") {
+        let (status, body) = http_get(addr, "/audio/cam.mp3").await;
+        assert_eq!(status, 501);
+        assert!(body.contains("ffmpeg feature"), "{body}");
+    }
+
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /audio/cam.wav HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    let mut buf = [0u8; 8192];
+    let body_start = loop {
+        let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .expect("audio stalled")
+            .expect("read");
+        assert!(n > 0, "audio stream closed");
+        response.extend_from_slice(&buf[..n]);
+        if let Some(split) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+            // Header, WAV header and at least one chunk of PCM.
+            if response.len() > split + 4 + 44 + 1000 {
+                break split + 4;
+            }
+        }
+    };
+    let headers = String::from_utf8_lossy(&response[..body_start]).to_ascii_lowercase();
+    assert!(headers.starts_with("http/1.1 200"), "{headers}");
+    assert!(headers.contains("content-type: audio/wav"), "{headers}");
+
+    let body = &response[body_start..];
+    // Chunked transfer encoding: skip the first chunk-size line.
+    let wav = &body[body.windows(2).position(|w| w == b"\r\n").expect("chunk size") + 2..];
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 2, "channels");
+    assert_eq!(u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]), 48_000);
+}
+
+#[tokio::test]
+async fn levels_meter_each_channel_over_http_and_websocket() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let file = fixture_with_audio();
+    let addr = start_server(&[("cam", &file)], false).await;
+    // A sine at half scale: peaks at -6 dBFS, RMS 3 dB below.
+    let levels = get_json(addr, "/levels/cam").await;
+    let channels = levels["channels"].as_array().expect("channels");
+    assert_eq!(channels.len(), 2, "{levels}");
+    for channel in channels {
+        let (rms, peak) = (channel["rms"].as_f64().unwrap(), channel["peak"].as_f64().unwrap());
+        assert!((-9.2..=-8.8).contains(&rms) && (-6.2..=-5.9).contains(&peak), "{levels}");
+    }
+    let (status, _) = http_get(addr, "/levels/nope").await;
+    assert_eq!(status, 404);
+
+    let url = format!("ws://{addr}/ws?source=cam&levels=true");
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("sec-websocket-protocol", "streambridge.v1".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await.expect("connect");
+    loop {
+        let Message::Text(text) = next_message(&mut ws).await else {
+            continue;
+        };
+        let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+        if message["type"] == "levels" {
+            assert_eq!(message["channels"].as_array().map(Vec::len), Some(2), "{message}");
+            break;
+        }
+        assert_eq!(message["type"], "hello");
+    }
+}
+
+#[tokio::test]
+async fn pip_composites_two_sources_at_main_size() {
+    let (main, inset) = (fixture_sized(320, 180), fixture());
+    let pip: PipConfig = toml::from_str("main = \"main\"\ninset = \"guest\"").unwrap();
+    let options = Options { composites: &[("both", Layout::Pip(pip))], ..Default::default() };
+    let addr = start_server_with(&[("main", &main), ("guest", &inset)], options).await;
+
+    let sources = get_json(addr, "/sources").await;
+    assert!(sources.to_string().contains("\"both\""), "sources: {sources}");
+    let (status, _, jpeg) = http_get_bytes(addr, "/snapshot/both").await;
+    assert_eq!(status, 200);
+    assert_eq!(jpeg_width(&jpeg), 320);
+
+    // The inputs' receivers close along with the composite's.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats.as_object().map(|s| s.len()), Some(0), "stats: {stats}");
+}
+
+#[tokio::test]
+async fn mosaic_tiles_sources_into_one_stream() {
+    let (a, b) = (fixture_sized(320, 180), fixture());
+    let addr = start_server(&[("a", &a), ("b", &b)], false).await;
+    assert_eq!(http_get(addr, "/mosaic?sources=a,b,a&layout=1x2").await.0, 400);
+    assert_eq!(http_get(addr, "/mosaic?sources=a,nope").await.0, 404);
+
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /mosaic?sources=a,b&layout=2x2 HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut received = Vec::new();
+    let mut buf = [0u8; 8192];
+    while count(&received, b"\r\n--frame\r\n") < 2 {
+        let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .expect("stream stalled")
+            .expect("read");
+        assert!(n > 0, "server closed the stream");
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert!(received.starts_with(b"HTTP/1.1 200"));
+    let part = received.windows(4).position(|w| w == b"\r\n\r\n").expect("head") + 4;
+    let body = &received[part..];
+    let body = &body[body.windows(4).position(|w| w == b"\r\n\r\n").expect("part") + 4..];
+    assert_eq!(jpeg_width(body), 1920);
+
+    // WebSocket viewers of the same mosaic share its receiver.
+    let mut ws = connect_ws(addr, "mosaic=a,b&layout=2x2&width=960").await;
+    assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 960);
+    let stats = get_json(addr, "/stats").await;
+    let mosaic = &stats["mosaic 2x2 [\"a\", \"b\"]"];
+    assert_eq!(mosaic["clients"], 2, "stats: {stats}");
+    assert_eq!((&stats["a"]["clients"], &stats["b"]["clients"]), (&1.into(), &1.into()));
+
+    drop((stream, ws));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats.as_object().map(|s| s.len()), Some(0), "stats: {stats}");
+}
+
+#[tokio::test]
+async fn clock_source_renders_at_its_configured_size() {
+    let clock: ClockConfig = toml::from_str("width = 320\nheight = 180\nfps = 10").unwrap();
+    let camera = fixture();
+    let compare: CompareConfig = toml::from_str("left = \"clock\"\nright = \"cam\"").unwrap();
+    let options = Options {
+        clocks: &[("clock", clock)],
+        composites: &[("latency", Layout::Compare(compare))],
+        ..Default::default()
+    };
+    let addr = start_server_with(&[("cam", &camera)], options).await;
+
+    let (status, _, jpeg) = http_get_bytes(addr, "/snapshot/clock").await;
+    assert_eq!(status, 200);
+    assert_eq!(jpeg_width(&jpeg), 320);
+
+    // Side by side with a camera, as a latency reference.
+    let mut ws = connect_ws(addr, "source=latency").await;
+    let frame = next_jpeg(&mut ws).await;
+    assert_eq!(jpeg_width(&frame), 320);
+}
+
+#[tokio::test]
+async fn mjpeg_files_loop_as_media_sources() {
+    let mut buffers = EncodeBuffers::new();
+    let uyvy = vec![128; 160 * 90 * 2];
+    let jpeg = encode_frame(&uyvy, 160, 90, 320, FourCCVideoType::UYVY, 75, &mut buffers)
+        .expect("encode");
+    let file = Fixture(fixture_path().with_extension("mjpeg"));
+    std::fs::write(&file.0, [&jpeg[..], &jpeg[..]].concat()).expect("write media file");
+    let mut media = MediaConfig::new(file.0.clone());
+    media.fps = Some(50);
+    let options = Options { media: &[("rehearsal", media)], ..Default::default() };
+    let addr = start_server_with(&[], options).await;
+
+    // Two frames at 50 fps: ten frames in a fifth of a second means it loops.
+    let mut ws = connect_ws(addr, "source=rehearsal").await;
+    for _ in 0..10 {
+        assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 160);
+    }
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let capture = &pipeline["rehearsal"]["stages"][0];
+    assert_eq!((&capture["producer"], &capture["width"]), (&"media".into(), &160.into()));
+}
+
+#[tokio::test]
+async fn viewer_badge_shows_in_the_corner() {
+    let mut buffers = EncodeBuffers::new();
+    let uyvy = vec![128; 160 * 90 * 2];
+    let jpeg = encode_frame(&uyvy, 160, 90, 320, FourCCVideoType::UYVY, 90, &mut buffers)
+        .expect("encode");
+    let file = Fixture(fixture_path().with_extension("mjpeg"));
+    std::fs::write(&file.0, &jpeg[..]).expect("write media file");
+    let media = [("return", MediaConfig::new(file.0.clone()))];
+    let options = Options { media: &media, viewer_badge: true, ..Default::default() };
+    let addr = start_server_with(&[], options).await;
+
+    let mut ws = connect_ws(addr, "source=return").await;
+    let frame = next_jpeg(&mut ws).await;
+    let gray = turbojpeg::decompress(&frame, turbojpeg::PixelFormat::GRAY).expect("decode");
+    let luma = |x: usize, y: usize| gray.pixels[y * gray.pitch + x];
+    assert!(luma(20, 45).abs_diff(128) < 10, "frame drawn on outside the badge");
+    assert!(luma(130, 3) < 60, "no badge box: {}", luma(130, 3));
+    let row_max = |y| (107..158).map(|x| luma(x, y)).max().unwrap();
+    assert!((4..11).any(|y| row_max(y) > 180), "no badge text");
+
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let stages = pipeline["return"]["stages"].as_array().expect("stages");
+    assert!(stages.iter().any(|s| s["stage"] == "badge"), "{stages:?}");
+}
+
+#[tokio::test]
+async fn overlay_burns_the_source_name_into_frames() {
+    let mut buffers = EncodeBuffers::new();
+    let uyvy = vec![128; 320 * 180 * 2];
+    let jpeg = encode_frame(&uyvy, 320, 180, 640, FourCCVideoType::UYVY, 90, &mut buffers)
+        .expect("encode");
+    let file = Fixture(fixture_path().with_extension("mjpeg"));
+    std::fs::write(&file.0, &jpeg[..]).expect("write media file");
+    let media = [("lobby", MediaConfig::new(file.0.clone()))];
+    let overlay = OverlayConfig {
+        name: true,
+        time: Some(OverlayTime::Clock),
+        corner: Corner::BottomLeft,
+        logo: None,
+        logo_corner: Corner::TopLeft,
+    };
+    let overlays = [("lobby", overlay)];
+    let options = Options { media: &media, overlays: &overlays, ..Default::default() };
+    let addr = start_server_with(&[], options).await;
+
+    let mut ws = connect_ws(addr, "source=lobby").await;
+    let frame = next_jpeg(&mut ws).await;
+    let gray = turbojpeg::decompress(&frame, turbojpeg::PixelFormat::GRAY).expect("decode");
+    let luma = |x: usize, y: usize| gray.pixels[y * gray.pitch + x];
+    assert!(luma(160, 20).abs_diff(128) < 10, "frame drawn on outside the overlay");
+    assert!(luma(3, 177) < 60, "no overlay box: {}", luma(3, 177));
+    let row_max = |y| (4..60).map(|x| luma(x, y)).max().unwrap();
+    assert!((160..166).any(|y| row_max(y) > 180), "no source name");
+
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let stages = pipeline["lobby"]["stages"].as_array().expect("stages");
+    let burn_in = stages.iter().find(|s| s["stage"] == "burn_in").expect("no burn_in stage");
+    assert_eq!(burn_in["time"], "clock", "{burn_in}");
+    assert_eq!(burn_in["corner"], "bottom_left", "{burn_in}");
+}
+
+#[tokio::test]
+async fn metadata_streams_as_server_sent_events() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    let (status, _) = http_get(addr, "/metadata/nope").await;
+    assert_eq!(status, 404);
+
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /metadata/cam HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .expect("no response")
+            .expect("read");
+        assert!(n > 0, "metadata stream closed");
+        response.extend_from_slice(&buf[..n]);
+    }
+    let headers = String::from_utf8_lossy(&response).to_ascii_lowercase();
+    assert!(headers.starts_with("http/1.1 200"), "{headers}");
+    assert!(headers.contains("content-type: text/event-stream"), "{headers}");
+
+    // The listener keeps the receiver running; a WebSocket asking for metadata
+    // alongside its frames counts once.
+    let mut ws = connect_ws(addr, "source=cam&metadata=true").await;
+    next_jpeg(&mut ws).await;
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats["cam"]["clients"], 2, "stats: {stats}");
+}
+
+#[tokio::test]
+async fn source_changes_stream_as_server_sent_events() {
+    let options = Options { grants: &["studio:view:STUDIO (1)"], ..Default::default() };
+    let (addr, events, _) = start_bridge(&[], options).await;
+
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /events?token=studio HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    read_until(&mut stream, &mut response, b"\r\n\r\n").await;
+
+    // Only sources the token may view are reported.
+    events.publish(Event::SourceAdded { source: "OFFICE (1)".to_string() });
+    events.publish(Event::SourceAdded { source: "STUDIO (1)".to_string() });
+    events.publish(Event::SourceRemoved { source: "STUDIO (1)".to_string() });
+    read_until(&mut stream, &mut response, b"event: source_removed").await;
+    let body = String::from_utf8_lossy(&response).into_owned();
+    assert!(body.contains("content-type: text/event-stream"), "{body}");
+    assert!(body.contains("event: source_added\ndata: {\"source\":\"STUDIO (1)\"}"), "{body}");
+    assert!(!body.contains("OFFICE"), "{body}");
+}
+
+#[tokio::test]
+async fn motion_streams_as_server_sent_events_and_counts() {
+    // A dark picture with a bright box over a quarter of it for a tenth of
+    // every second, the length of the replay.
+    let path = fixture_path();
+    let mut writer = RawWriter::create(&path).expect("create fixture");
+    let (width, height) = (64, 48);
+    for i in 0..25u64 {
+        let boxed = (5..8).contains(&i);
+        let data: Vec<u8> = (0..width * height)
+            .flat_map(|n| {
+                let (x, y) = (n % width, n / width);
+                let bright = boxed && x < width / 2 && y < height / 2;
+                [128, if bright { 220 } else { 16 }]
+            })
+            .collect();
+        let frame = VideoFrame {
+            data: &data,
+            width,
+            height,
+            stride: width * 2,
+            fourcc: FourCCVideoType::UYVY,
+        };
+        writer.write_video(i * 40_000, &frame).expect("write fixture");
+    }
+    writer.finish().expect("finish fixture");
+    let fixture = Fixture(path);
+    let motion = MotionSettings { threshold: 0.1, hold: Duration::from_millis(300) };
+    let options = Options { motion: Some(motion), ..Default::default() };
+    let addr = start_server_with(&[("cam", &fixture)], options).await;
+
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request = format!("GET /events HTTP/1.1\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    read_until(&mut stream, &mut response, b"\r\n\r\n").await;
+    // Motion is looked for while the source's receiver runs.
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    read_until(&mut stream, &mut response, b"event: motion_stopped").await;
+    let body = String::from_utf8_lossy(&response).into_owned();
+    let started = "event: motion_started\ndata: {\"source\":\"cam\",\"score\":0.25}";
+    assert!(body.contains(started), "{body}");
+    assert!(body.contains("event: motion_stopped\ndata: {\"source\":\"cam\"}"), "{body}");
+
+    let stats = get_json(addr, "/stats").await;
+    assert!(stats["cam"]["motion_events"].as_u64() >= Some(1), "stats: {stats}");
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let stages = pipeline["cam"]["stages"].as_array().expect("stages");
+    assert!(stages.iter().any(|s| s["stage"] == "motion"), "{stages:?}");
+}
+
+#[tokio::test]
+async fn compare_split_moves_over_websocket() {
+    let (left, right) = (fixture_sized(96, 48), fixture());
+    let compare: CompareConfig = toml::from_str("left = \"a\"\nright = \"b\"").unwrap();
+    let options = Options { composites: &[("ab", Layout::Compare(compare))], ..Default::default() };
+    let addr = start_server_with(&[("a", &left), ("b", &right)], options).await;
+
+    let mut ws = connect_ws(addr, "source=ab").await;
+    assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 96);
+    ws.send(Message::Text(r#"{"split": 0.1}"#.into())).await.expect("send split");
+    ws.send(Message::Text("not json".into())).await.expect("send junk");
+    assert_eq!(jpeg_width(&next_jpeg(&mut ws).await), 96);
+}
+
+#[tokio::test]
+async fn diff_mode_streams_grayscale_differences() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    let mut ws = connect_ws(addr, "source=cam&mode=diff").await;
+    let jpeg = next_jpeg(&mut ws).await;
+    assert_eq!(jpeg_width(&jpeg), 64);
+    let sof = jpeg.windows(2).position(|w| w == [0xFF, 0xC0]).expect("SOF0 marker");
+    assert_eq!(jpeg[sof + 9], 1, "expected a single (luma) component");
+
+    let (status, _) = http_get(addr, "/stream/cam?mode=bogus").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn clients_negotiate_their_own_width() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    let mut small = connect_ws(addr, "source=cam&width=32&quality=30&fps=10").await;
+    let mut full = connect_ws(addr, "source=cam").await;
+    assert_eq!(jpeg_width(&next_jpeg(&mut small).await), 32);
+    assert_eq!(jpeg_width(&next_jpeg(&mut full).await), 64);
+    // Zero means no limit.
+    let mut unlimited = connect_ws(addr, "source=cam&width=0").await;
+    assert_eq!(jpeg_width(&next_jpeg(&mut unlimited).await), 64);
+
+    let stats = get_json(addr, "/stats").await;
+    assert_eq!(stats["cam"]["clients"], 3, "stats: {stats}");
+}
+
+#[tokio::test]
+async fn pipeline_endpoint_lists_stages_in_effect() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+    assert_eq!(get_json(addr, "/api/pipeline").await, serde_json::json!({}));
+
+    let mut small = connect_ws(addr, "source=cam&width=32").await;
+    let mut diff = connect_ws(addr, "source=cam&mode=diff").await;
+    next_jpeg(&mut small).await;
+    next_jpeg(&mut diff).await;
+
+    let pipeline = get_json(addr, "/api/pipeline").await;
+    let stages = pipeline["cam"]["stages"].as_array().expect("stages");
+    let names: Vec<_> = stages.iter().map(|s| s["stage"].as_str().unwrap()).collect();
+    assert_eq!(names, ["capture", "scale", "diff", "encode", "outputs"], "{pipeline}");
+    let capture = &stages[0];
+    assert_eq!((&capture["producer"], &capture["width"]), (&"replay".into(), &64.into()));
+    assert!(stages[3]["frames"].as_u64().unwrap() > 0, "{pipeline}");
+    assert!(stages[3]["avg_ms"].is_number(), "{pipeline}");
+    assert_eq!(stages[3]["encoder"], "turbojpeg", "{pipeline}");
+    let outputs = &stages[4];
+    assert_eq!(outputs["diff"], 1, "{pipeline}");
+    assert_eq!(outputs["variants"][0]["width"], 32, "{pipeline}");
+}
+
+#[tokio::test]
+async fn static_sources_are_listed_without_discovery() {
+    let file = fixture();
+    let static_sources = [("REMOTE-PC (Cam 1)", "10.20.0.15:5961")];
+    let options = Options { static_sources: &static_sources, ..Default::default() };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let sources = get_json(addr, "/sources?names=true").await;
+    assert_eq!(sources, serde_json::json!(["cam", "REMOTE-PC (Cam 1)"]));
+    let detail = get_json(addr, "/api/sources/REMOTE-PC%20(Cam%201)").await;
+    assert_eq!(detail["url"], "10.20.0.15:5961");
+    // Configured sources aren't in any NDI group.
+    assert_eq!(get_json(addr, "/sources?groups=public,studio").await, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn sources_can_be_referenced_by_id() {
+    let file = fixture();
+    let static_sources = [("REMOTE-PC (Cam 1)", "10.20.0.15:5961")];
+    let options = Options {
+        static_sources: &static_sources,
+        // Keeps the source received, and its size known, after the snapshot.
+        receiver_linger: Duration::from_secs(5),
+        ..Default::default()
+    };
+    let addr = start_server_with(&[("cam", &file)], options).await;
+
+    let details = get_json(addr, "/sources").await;
+    assert_eq!(details[1]["id"], "remote-pc-cam-1-61fc08");
+    assert_eq!(details[1]["url"], "10.20.0.15:5961");
+    let detail = get_json(addr, "/api/sources/remote-pc-cam-1-61fc08").await;
+    assert_eq!(detail["name"], "REMOTE-PC (Cam 1)");
+
+    let id = details[0]["id"].as_str().expect("id").to_string();
+    let (status, _, _) = http_get_bytes(addr, &format!("/snapshot/{id}")).await;
+    assert_eq!(status, 200);
+    let details = get_json(addr, "/sources").await;
+    assert_eq!((details[0]["width"].as_u64(), details[0]["height"].as_u64()), (Some(64), Some(48)));
+    let quality = format!(r#"{{"command": "set_quality", "source": "{id}", "jpeg_quality": 50}}"#);
+    let (status, _, body) = post_json(addr, "/api/commands", "", &quality).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(http_get_bytes(addr, "/snapshot/cam-000000").await.0, 404);
+}
+
+#[tokio::test]
+async fn aliases_stand_in_for_source_names() {
+    let file = fixture();
+    let aliases = [("program", "STUDIO-PC (vMix - Output 1)")];
+    let options = Options { aliases: &aliases, ..Default::default() };
+    let addr = start_server_with(&[("STUDIO-PC (vMix - Output 1)", &file), ("cam", &file)], options)
+        .await;
+
+    let details = get_json(addr, "/sources").await;
+    assert_eq!(details[0]["alias"], "program", "{details}");
+    assert!(details[1].get("alias").is_none(), "{details}");
+    let detail = get_json(addr, "/api/sources/program").await;
+    assert_eq!((&detail["name"], &detail["alias"]), (&details[0]["name"], &details[0]["alias"]));
+    assert_eq!(http_get_bytes(addr, "/snapshot/program").await.0, 200);
+    let mut ws = connect_ws(addr, "source=program").await;
+    assert_eq!(&next_jpeg(&mut ws).await[..2], [0xFF, 0xD8]);
+}
+
+#[tokio::test]
+async fn favorites_order_the_sources_and_survive_a_restart() {
+    let file = fixture();
+    let dir = std::env::temp_dir().join(format!("streambridge-state-{}", std::process::id()));
+    let replays = [("a", &file), ("b", &file), ("c", &file), ("d", &file)];
+    let grants = ["op:*:*", "viewer:view:*", "cam-op:control:c"];
+    let options = Options { state_dir: Some(dir.clone()), grants: &grants, ..Default::default() };
+    let addr = start_server_with(&replays, options).await;
+    let op = "Authorization: Bearer op\r\n";
+
+    let order = r#"{"order": ["c", "b", "nope"]}"#;
+    let (status, _, body) = http_send(addr, "PATCH", "/api/favorites", op, order).await;
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+    assert_eq!(http_send(addr, "PUT", "/api/favorites/d", op, "").await.0, 200);
+    let viewer = "Authorization: Bearer viewer\r\n";
+    assert_eq!(http_send(addr, "PUT", "/api/favorites/a", viewer, "").await.0, 403);
+    let cam_op = "Authorization: Bearer cam-op\r\n";
+    assert_eq!(http_send(addr, "PATCH", "/api/favorites", cam_op, order).await.0, 403);
+    assert_eq!(http_send(addr, "PUT", "/api/favorites/c", cam_op, "").await.0, 200);
+    assert_eq!(http_send(addr, "DELETE", "/api/favorites/c", cam_op, "").await.0, 200);
+
+    let expected = serde_json::json!(["d", "c", "b", "a"]);
+    assert_eq!(get_json(addr, "/sources?names=true&token=viewer").await, expected);
+    let details = get_json(addr, "/sources?token=viewer").await;
+    assert_eq!(details[0]["starred"], true);
+    let favorites = get_json(addr, "/api/favorites?token=viewer").await;
+    // Sources not seen right now keep their place for when they come back.
+    let expected_favorites = serde_json::json!({ "starred": ["d"], "order": ["c", "b", "nope"] });
+    assert_eq!(favorites, expected_favorites);
+
+    let options = Options { state_dir: Some(dir.clone()), ..Default::default() };
+    let restarted = start_server_with(&replays, options).await;
+    assert_eq!(get_json(restarted, "/sources?names=true").await, expected);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn ptz_endpoints_validate_and_reach_the_source() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file)], false).await;
+
+    let details = get_json(addr, "/sources").await;
+    let expected = serde_json::json!([{
+        "id": "cam-7434ac",
+        "name": "cam",
+        "url": null,
+        "width": null,
+        "height": null,
+        "fps": null,
+        "active_clients": 0,
+        "ptz_supported": null,
+        "starred": false,
+    }]);
+    assert_eq!(details, expected);
+
+    let post = |path: &'static str, body: &'static str| post_json(addr, path, "", body);
+    assert_eq!(post("/ptz/cam/pan_tilt", r#"{"pan": 0.5}"#).await.0, 400);
+    assert_eq!(post("/ptz/cam/zoom", r#"{"zoom": 0.5, "speed": 1}"#).await.0, 400);
+    assert_eq!(post("/ptz/cam/preset", r#"{"recall": 100}"#).await.0, 400);
+    assert_eq!(post("/ptz/cam/focus", "{}").await.0, 404);
+    assert_eq!(post("/ptz/nope/zoom", r#"{"zoom": 0.5}"#).await.0, 404);
+
+    // Replay sources have no camera behind them.
+    let (status, _, body) = post("/ptz/cam/pan_tilt", r#"{"pan_speed": -0.5}"#).await;
+    assert_eq!(status, 409, "{body}");
+    assert!(body.contains("no PTZ"), "{body}");
+    assert_eq!(post("/ptz/cam/preset", r#"{"store": 2}"#).await.0, 409);
+}
+
+#[tokio::test]
+async fn tally_needs_control_and_an_ndi_source() {
+    let file = fixture();
+    let grants = &["viewer:view:*", "desk:view,control:*"];
+    let addr = start_server_with(&[("cam", &file)], Options { grants, ..Default::default() }).await;
+
+    let on_air = r#"{"program": true, "preview": false}"#;
+    let viewer = "Authorization: Bearer viewer\r\n";
+    assert_eq!(post_json(addr, "/tally/cam", viewer, on_air).await.0, 403);
+    let desk = "Authorization: Bearer desk\r\n";
+    let post = |path: &'static str, body: &'static str| post_json(addr, path, desk, body);
+    assert_eq!(post("/tally/cam", r#"{"program": true}"#).await.0, 400);
+    assert_eq!(post("/tally/nope", on_air).await.0, 404);
+    // Replay sources have no sender to signal.
+    let (status, _, body) = post("/tally/cam", on_air).await;
+    assert_eq!(status, 409, "{body}");
+
+    let detail = get_json(addr, "/api/sources/cam?token=desk").await;
+    assert_eq!(detail["tally"], serde_json::Value::Null, "{detail}");
+}
+
+#[tokio::test]
+async fn receivers_are_retuned_without_dropping_viewers() {
+    let file = fixture();
+    let grants = &["viewer:view:*", "desk:view,control:*"];
+    let addr = start_server_with(&[("cam", &file)], Options { grants, ..Default::default() }).await;
+    let mut ws = connect_ws(addr, "source=cam&token=viewer").await;
+    next_jpeg(&mut ws).await;
+
+    let patch = |token: &'static str, path: &'static str, body: &'static str| async move {
+        let headers = format!("Authorization: Bearer {token}\r\n");
+        let (status, _, body) = http_send(addr, "PATCH", path, &headers, body).await;
+        (status, String::from_utf8(body).expect("utf-8 body"))
+    };
+    let path = "/admin/receivers/cam";
+    assert_eq!(patch("viewer", path, r#"{"max_fps": 5}"#).await.0, 403);
+    assert_eq!(patch("desk", path, r#"{"jpeg_quality": 0}"#).await.0, 400);
+    assert_eq!(patch("desk", path, r#"{"fps": 5}"#).await.0, 400);
+    assert_eq!(patch("desk", "/admin/receivers/nope", "{}").await.0, 404);
+    // Replay sources have no proxy stream to switch to.
+    assert_eq!(patch("desk", path, r#"{"low_bandwidth": true}"#).await.0, 409);
+
+    let (status, body) = patch("desk", path, r#"{"max_fps": 5, "jpeg_quality": 40}"#).await;
+    assert_eq!(status, 200, "{body}");
+    let settings: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!((settings["max_fps"].as_u64(), settings["active"].as_bool()), (Some(5), Some(true)));
+    assert_eq!(settings["jpeg_quality"], 40);
+
+    // The viewer stays connected and now gets about 5 of the 25 frames a second.
+    next_jpeg(&mut ws).await;
+    let started = std::time::Instant::now();
+    let mut frames = 0;
+    while started.elapsed() < Duration::from_secs(1) {
+        next_jpeg(&mut ws).await;
+        frames += 1;
+    }
+    assert!((3..=7).contains(&frames), "{frames} frames in a second");
+}
+
+#[tokio::test]
+async fn receivers_are_listed_and_torn_down_on_request() {
+    let file = fixture();
+    let grants = &["viewer:view:*", "desk:view,control:*"];
+    let addr = start_server_with(&[("cam", &file)], Options { grants, ..Default::default() }).await;
+    let mut ws = connect_ws(addr, "source=cam&token=viewer").await;
+    next_jpeg(&mut ws).await;
+
+    let receivers = get_json(addr, "/admin/receivers?token=desk").await;
+    assert_eq!(receivers[0]["key"], "cam", "{receivers}");
+    assert_eq!(receivers[0]["producer"], "replay", "{receivers}");
+    assert_eq!(receivers[0]["clients"], 1, "{receivers}");
+    assert!(receivers[0]["uptime_secs"].as_f64() > Some(0.0), "{receivers}");
+    assert_eq!(get_json(addr, "/admin/receivers?token=viewer").await, serde_json::json!([]));
+
+    let send = |method: &'static str, path: &'static str, token: &'static str| async move {
+        let headers = format!("Authorization: Bearer {token}\r\n");
+        let (status, _, body) = http_send(addr, method, path, &headers, "").await;
+        (status, String::from_utf8(body).expect("utf-8 body"))
+    };
+    let restart = "/admin/receivers/cam/restart";
+    // Replay sources have no sender to reconnect to.
+    assert_eq!(send("POST", restart, "desk").await.0, 409);
+    assert_eq!(send("DELETE", "/admin/receivers/cam", "viewer").await.0, 403);
+    assert_eq!(send("DELETE", "/admin/receivers/nope", "desk").await.0, 404);
+    let (status, body) = send("DELETE", "/admin/receivers/cam", "desk").await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains(r#""receivers":1"#), "{body}");
+    assert_eq!(close_code(&mut ws).await, 4410);
+    assert_eq!(send("DELETE", "/admin/receivers/cam", "desk").await.0, 409);
+    assert_eq!(get_json(addr, "/admin/receivers?token=desk").await, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn srt_outputs_are_added_and_removed_per_source() {
+    let file = fixture();
+    let grants = &["viewer:view:*", "desk:view,control:*"];
+    let addr = start_server_with(&[("cam", &file)], Options { grants, ..Default::default() }).await;
+    let send = |method: &'static str, token: &'static str, body: &'static str| async move {
+        let headers = format!("Authorization: Bearer {token}\r\n");
+        let (status, _, body) = http_send(addr, method, "/admin/srt/cam", &headers, body).await;
+        (status, String::from_utf8(body).expect("utf-8 body"))
+    };
+    let output = r#"{"address": "relay.example.com:9000", "passphrase": "correct horse battery"}"#;
+    assert_eq!(send("PUT", "viewer", output).await.0, 403);
+    let (status, body) = send("PUT", "desk", r#"{"address": "relay.example.com"}"#).await;
+    assert_eq!(status, 400);
+    assert!(body.contains("host and port"), "{body}");
+
+    let (status, body) = send("PUT", "desk", output).await;
+    if !cfg!(feature = "ffmpeg") {
+        assert_eq!(status, 501, "{body}");
+        assert_eq!(get_json(addr, "/admin/srt?token=desk").await, serde_json::json!([]));
+        return;
+    }
+    assert_eq!(status, 201, "{body}");
+    assert!(!body.contains("correct horse"), "{body}");
+    let outputs = get_json(addr, "/admin/srt?token=desk").await;
+    assert_eq!(outputs[0]["source"], "cam", "{outputs}");
+    assert_eq!(outputs[0]["mode"], "caller", "{outputs}");
+    assert_eq!(get_json(addr, "/admin/srt?token=viewer").await, serde_json::json!([]));
+    assert_eq!(send("PUT", "desk", output).await.0, 200);
+    assert_eq!(send("DELETE", "desk", "").await.0, 204);
+    assert_eq!(send("DELETE", "desk", "").await.0, 404);
+}
+
+#[tokio::test]
+async fn webrtc_sessions_answer_offers() {
+    let file = fixture();
+    let grants = &["viewer:view:cam", "desk:control:*"];
+    let addr = start_server_with(&[("cam", &file)], Options { grants, ..Default::default() }).await;
+    let send = |method: &'static str, path: &'static str, token: &'static str| async move {
+        let headers = format!("Authorization: Bearer {token}\r\nContent-Type: application/sdp\r\n");
+        let (status, _, body) = http_send(addr, method, path, &headers, "v=0").await;
+        (status, String::from_utf8(body).expect("utf-8 body"))
+    };
+
+    let (status, body) = send("POST", "/webrtc/cam", "viewer").await;
+    if !cfg!(feature = "ffmpeg") {
+        assert_eq!(status, 501);
+        assert!(body.contains("ffmpeg feature"), "{body}");
+        return;
+    }
+    assert_eq!(status, 400, "{body}");
+    assert!(body.contains("WebRTC"), "{body}");
+    assert_eq!(send("POST", "/webrtc/cam", "desk").await.0, 403);
+    assert_eq!(send("DELETE", "/webrtc/cam/nope", "desk").await.0, 403);
+    assert_eq!(send("DELETE", "/webrtc/cam/nope", "viewer").await.0, 404);
+}
+
+#[tokio::test]
+async fn clients_of_a_source_show_where_they_connect_from() {
+    let file = fixture();
+    let addr = start_server(&[("cam", &file), ("other", &file)], false).await;
+    let mut ws = connect_ws(addr, "source=cam").await;
+    next_jpeg(&mut ws).await;
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    let request =
+        format!("GET /stream/cam HTTP/1.1\r\nHost: {addr}\r\nUser-Agent: VLC/3.0.20\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    read_until(&mut stream, &mut Vec::new(), b"--frame").await;
+    let _other = connect_ws(addr, "source=other").await;
+
+    let clients = get_json(addr, "/admin/receivers/cam/clients").await;
+    let clients = clients.as_array().expect("a list");
+    let kinds: Vec<_> = clients.iter().map(|c| c["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["websocket", "mjpeg"]);
+    assert!(clients.iter().all(|c| c["peer"] == "127.0.0.1"), "{clients:?}");
+    assert_eq!(clients[1]["user_agent"], "VLC/3.0.20");
+    assert_eq!(clients[0]["lags"], 0);
+    assert!(clients[0]["frames"].as_u64() >= Some(1), "{clients:?}");
+}
+
+#[tokio::test]
+async fn sources_play_over_rtsp_as_rtp_jpeg() {
+    let file = fixture();
+    let options = Options { grants: &["viewer:view:*"], ..Default::default() };
+    let (addr, rtsp) = start_rtsp_server(&[("cam", &file)], options).await;
+    let mut stream = TcpStream::connect(rtsp).await.expect("connect");
+    let url = format!("rtsp://{rtsp}/cam");
+    let (status, response) = rtsp_request(&mut stream, "DESCRIBE", &url, 1, "").await;
+    assert_eq!(status, 401);
+    assert!(response.contains("WWW-Authenticate: Basic"), "{response}");
+    // NVRs send the token as a password: "nvr:viewer".
+    let auth = "Authorization: Basic bnZyOnZpZXdlcg==\r\n";
+    let missing = format!("rtsp://{rtsp}/nope");
+    assert_eq!(rtsp_request(&mut stream, "DESCRIBE", &missing, 2, auth).await.0, 404);
+    let (status, sdp) = rtsp_request(&mut stream, "DESCRIBE", &url, 3, auth).await;
+    assert_eq!(status, 200, "{sdp}");
+    assert!(sdp.contains("m=video 0 RTP/AVP 26\r\n"), "{sdp}");
+    // The track's, which comes after the session's.
+    let control = sdp.lines().rev().find_map(|l| l.strip_prefix("a=control:")).unwrap();
+
+    let transport = format!("{auth}Transport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n");
+    let (status, response) = rtsp_request(&mut stream, "SETUP", control, 4, &transport).await;
+    assert_eq!(status, 200, "{response}");
+    assert!(response.contains("interleaved=0-1"), "{response}");
+    let session = response.lines().find_map(|l| l.strip_prefix("Session: ")).expect("session");
+    let session = format!("Session: {}\r\n", session.split(';').next().unwrap());
+    assert_eq!(rtsp_request(&mut stream, "PLAY", &url, 5, &session).await.0, 200);
+
+    // A whole 64x48 frame, in packets up to the marked one.
+    loop {
+        let mut header = [0u8; 4];
+        let read = stream.read_exact(&mut header);
+        tokio::time::timeout(TIMEOUT, read).await.expect("packet before timeout").expect("read");
+        assert_eq!(&header[..2], b"$\0");
+        let mut packet = vec![0u8; u16::from_be_bytes([header[2], header[3]]) as usize];
+        stream.read_exact(&mut packet).await.expect("read packet");
+        assert_eq!(packet[1] & 0x7F, 26, "JPEG payload");
+        assert_eq!(&packet[18..20], &[8, 6], "size in 8-pixel blocks");
+        if packet[1] & 0x80 != 0 {
+            break;
+        }
+    }
+    let clients = get_json(addr, "/api/clients?token=viewer").await;
+    assert_eq!(clients[0]["kind"], "rtsp", "{clients}");
+    assert_eq!(rtsp_request(&mut stream, "TEARDOWN", &url, 6, &session).await.0, 200);
+}
+
+#[tokio::test]
+async fn sources_answer_onvif_requests_as_cameras() {
+    let file = fixture();
+    let options = Options { grants: &["nvr:view:*"], ..Default::default() };
+    let (addr, rtsp) = start_rtsp_server(&[("cam", &file)], options).await;
+    let soap = |security: &str, operation: &str| {
+        format!(
+            "<s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\">\
+             <s:Header>{security}</s:Header><s:Body>{operation}</s:Body></s:Envelope>"
+        )
+    };
+    let headers = "Content-Type: application/soap+xml\r\n";
+    let device = "/onvif/cam/device_service";
+    let media = "/onvif/cam/media_service";
+
+    // Clients ask for the time before they have credentials.
+    let time = soap("", "<tds:GetSystemDateAndTime/>");
+    let (status, _, body) = post_json(addr, device, headers, &time).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains("<tt:UTCDateTime>"), "{body}");
+
+    let stream_uri = soap("", "<trt:GetStreamUri><trt:ProfileToken>main</trt:ProfileToken>\
+                               </trt:GetStreamUri>");
+    let (status, _, body) = post_json(addr, media, headers, &stream_uri).await;
+    assert_eq!(status, 401, "{body}");
+    assert!(body.contains("ter:NotAuthorized"), "{body}");
+
+    let security = "<Security><UsernameToken><Username>admin</Username>\
+                    <Password>nvr</Password></UsernameToken></Security>";
+    let stream_uri = stream_uri.replace("<s:Header>", &format!("<s:Header>{security}"));
+    let (status, _, body) = post_json(addr, media, headers, &stream_uri).await;
+    assert_eq!(status, 200, "{body}");
+    // At the source's discovery id, which RTSP takes like its name.
+    let uri = format!("<tt:Uri>rtsp://127.0.0.1:{}/cam-", rtsp.port());
+    assert!(body.contains(&uri), "{body}");
+    let missing = "/onvif/nope/media_service";
+    let (status, _, body) = post_json(addr, missing, headers, &stream_uri).await;
+    assert_eq!(status, 404, "{body}");
+    let reboot = stream_uri.replace("GetStreamUri", "SystemReboot");
+    let (status, _, body) = post_json(addr, device, headers, &reboot).await;
+    assert_eq!(status, 400, "{body}");
+    assert!(body.contains("ter:ActionNotSupported"), "{body}");
+}
+
+#[tokio::test]
+async fn home_assistant_gets_cameras_ready_to_paste() {
+    let (a, b) = (fixture(), fixture());
+    let options = Options { grants: &["ha:view,snapshot:cam-a"], ..Default::default() };
+    let addr = start_server_with(&[("cam-a", &a), ("cam-b", &b)], options).await;
+    let (status, _) = http_get(addr, "/integrations/homeassistant").await;
+    assert_eq!(status, 401);
+
+    let cameras = get_json(addr, "/integrations/homeassistant?token=ha").await;
+    assert_eq!(cameras.as_array().map(Vec::len), Some(1), "only what the token may view");
+    assert_eq!(cameras[0]["name"], "cam-a");
+    let still = cameras[0]["still_image_url"].as_str().expect("still image URL");
+    let path = still.strip_prefix(&format!("http://{addr}")).expect("this server's URL");
+    assert!(path.ends_with("?token=ha"), "{path}");
+    let (status, headers, body) = http_get_bytes(addr, path).await;
+    assert_eq!(status, 200);
+    assert!(headers.contains("content-type: image/jpeg"), "{headers}");
+    assert_eq!(&body[..2], &[0xFF, 0xD8]);
+
+    let (status, yaml) = http_get(addr, "/integrations/homeassistant?token=ha&format=go2rtc").await;
+    assert_eq!(status, 200);
+    let (id, url) = (&cameras[0]["unique_id"], &cameras[0]["mjpeg_url"]);
+    assert_eq!(yaml, format!("streams:\n  {}: {url}\n", id.as_str().unwrap()));
+}
+
+/// Send an RTSP request with extra header lines (each ending in `\r\n`);
+/// returns the status and the whole response. Reads a byte at a time, so
+/// interleaved packets after it are left alone.
+async fn rtsp_request(
+    stream: &mut TcpStream,
+    method: &str,
+    url: &str,
+    cseq: u32,
+    headers: &str,
+) -> (u16, String) {
+    let request = format!("{method} {url} RTSP/1.0\r\nCSeq: {cseq}\r\n{headers}\r\n");
+    stream.write_all(request.as_bytes()).await.expect("write request");
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        let read = stream.read_exact(&mut byte);
+        tokio::time::timeout(TIMEOUT, read).await.expect("response before timeout").expect("read");
+        // Packets sent before the response ends are skipped whole.
+        if response.is_empty() && byte[0] == b'$' {
+            let mut header = [0u8; 3];
+            stream.read_exact(&mut header).await.expect("read packet");
+            let mut packet = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize];
+            stream.read_exact(&mut packet).await.expect("read packet");
+            continue;
+        }
+        response.push(byte[0]);
+    }
+    let mut response = String::from_utf8(response).expect("utf-8 response");
+    let length = response.lines().find_map(|l| l.strip_prefix("Content-Length: "));
+    let mut body = vec![0u8; length.map_or(0, |len| len.parse().expect("length"))];
+    stream.read_exact(&mut body).await.expect("read body");
+    response.push_str(std::str::from_utf8(&body).expect("utf-8 body"));
+    assert!(response.contains(&format!("CSeq: {cseq}\r\n")), "{response}");
+    let status = response.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("status");
+    (status, response)
+}
+
+/// Read from a streaming response until `needle` has arrived.
+async fn read_until(stream: &mut TcpStream, response: &mut Vec<u8>, needle: &[u8]) {
+    let mut buf = [0u8; 1024];
+    while !response.windows(needle.len()).any(|w| w == needle) {
+        let n = tokio::time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .expect("nothing before timeout")
+            .expect("read");
+        assert!(n > 0, "stream closed");
+        response.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Width from a baseline JPEG's SOF0 header.
+fn jpeg_width(jpeg: &[u8]) -> usize {
+    let sof = jpeg.windows(2).position(|w| w == [0xFF, 0xC0]).expect("SOF0 marker");
+    u16::from_be_bytes([jpeg[sof + 7], jpeg[sof + 8]]) as usize
+}
+
+fn count(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack.windows(needle.len()).filter(|w| *w == needle).count()
+}
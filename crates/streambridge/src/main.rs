@@ -1,16 +1,40 @@
-mod discovery;
-mod encode;
-mod ndi;
-mod receiver;
-mod server;
-mod stats;
-mod test_page;
-
-use clap::{Parser, Subcommand};
-use receiver::ReceiverManager;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tracing::{error, info};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use streambridge_core::auth::Grant;
+use streambridge_core::clock::ClockConfig;
+use streambridge_core::composite::Layout;
+use streambridge_core::config::{Config, EncodeSettings, SourceSettings, CONFIG_ENV};
+use streambridge_core::crash::{self, CrashReporter};
+use streambridge_core::deinterlace::Deinterlace;
+use streambridge_core::discovery;
+use streambridge_core::encode::EncoderKind;
+use streambridge_core::latency::{self, LatencyStats};
+use streambridge_core::limits::ConnectionLimits;
+use streambridge_core::logs::{self, LogBuffer};
+use streambridge_core::media::MediaConfig;
+use streambridge_core::ndi::{
+    self, FindSettings, FrameType, RecvSettings, SendInstance, SendSettings, Source,
+};
+use streambridge_core::quirks::Quirk;
+use streambridge_core::rawfile::RawWriter;
+use streambridge_core::receiver::{self, ColorFormat, VirtualSource};
+use streambridge_core::recording::{Rotation, MAX_RECORD_FPS};
+use streambridge_core::scripting::Script;
+use streambridge_core::service::{self, SystemLog};
+use streambridge_core::threads::{CpuList, ThreadPolicy};
+use streambridge_core::timestamps::TimeFormat;
+use streambridge_core::{publish, setup, Bridge};
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(
@@ -22,29 +46,353 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// TOML config file [env: STREAMBRIDGE_CONFIG]; STREAMBRIDGE_<KEY> variables
+    /// override its keys, and flags given on the command line override both
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
     /// HTTP/WS listen port
     #[arg(long, default_value_t = 9550, global = true)]
     port: u16,
 
+    /// Also serve sources over RTSP on this port, e.g. 8554, as
+    /// rtsp://host:port/<source> for NVRs and players
+    #[arg(long, global = true)]
+    rtsp_port: Option<u16>,
+
+    /// Answer ONVIF WS-Discovery probes with every source, so video management
+    /// systems find and add them as cameras
+    #[arg(long, global = true)]
+    onvif: bool,
+
     /// Max frames per second
     #[arg(long, default_value_t = 25, global = true)]
     max_fps: u32,
 
+    /// Scale wider frames down by halving before encoding
+    #[arg(long, global = true)]
+    max_width: Option<usize>,
+
+    /// Scale taller frames down by halving before encoding
+    #[arg(long, global = true)]
+    max_height: Option<usize>,
+
+    /// Also ask the machines at these IP addresses (comma-separated) for their
+    /// NDI\u{00ae} sources, e.g. senders on other subnets
+    #[arg(long, value_delimiter = ',', global = true)]
+    extra_ips: Vec<IpAddr>,
+
+    /// Streaming clients (WebSocket and MJPEG) allowed at once; more get 429
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_clients: Option<u64>,
+
+    /// Streaming clients allowed at once for one source
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_clients_per_source: Option<u64>,
+
+    /// New streaming connections allowed from one IP address per minute
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_connections_per_ip: Option<u64>,
+
+    /// Time zone of the timestamps in recording and capture file names and on
+    /// clock sources: an IANA name like Europe/Berlin, UTC (the default) or local
+    #[arg(long, global = true)]
+    time_zone: Option<String>,
+
+    /// strftime format of the timestamps in file names and of the date on clock
+    /// sources, e.g. %Y-%m-%d_%H%M%S%z
+    #[arg(long, global = true)]
+    time_format: Option<String>,
+
+    /// Only discover and serve NDI\u{00ae} sources in these groups
+    /// (comma-separated) instead of the default group
+    #[arg(long, value_delimiter = ',', global = true)]
+    groups: Vec<String>,
+
+    /// What NDI\u{00ae} senders and tools like NDI\u{00ae} Studio Monitor show the
+    /// bridge's receivers as; streambridge@<hostname> by default
+    #[arg(long, global = true)]
+    receiver_name: Option<String>,
+
+    /// Ask NDI\u{00ae} senders for their low-bandwidth preview streams; clients can
+    /// still ask for the full stream with bandwidth=highest
+    #[arg(long, global = true)]
+    low_bandwidth: bool,
+
+    /// How interlaced NDI\u{00ae} sources are made progressive: bob (default),
+    /// weave or off
+    #[arg(long, value_parser = Deinterlace::parse, global = true)]
+    deinterlace: Option<Deinterlace>,
+
+    /// The pixel format NDI\u{00ae} receivers ask for: fastest (default, UYVY on
+    /// most machines), best, bgra, which skips converting the chroma before
+    /// encoding, or uyvy
+    #[arg(long, value_parser = ColorFormat::parse, global = true)]
+    color_format: Option<ColorFormat>,
+
+    /// Encoding backend: turbojpeg, or with the ffmpeg feature qsv, vaapi or nvenc
+    #[arg(long, value_parser = EncoderKind::parse, global = true)]
+    encoder: Option<EncoderKind>,
+
+    /// Encode each source's frames on this many threads of its own, for sources
+    /// too large for their capture thread to encode, like 4K60; 1 (default)
+    /// encodes on the capture thread
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    encode_threads: Option<u64>,
+
     /// TurboJPEG quality (1-100)
     #[arg(long, default_value_t = 75, global = true)]
     jpeg_quality: i32,
 
+    /// Lower the JPEG quality, then the frame rate, while encoding can't keep up
+    /// with a source or its clients keep falling behind, and raise them back
+    /// once there is headroom
+    #[arg(long, global = true)]
+    adaptive_quality: bool,
+
     /// Stats log interval in seconds
     #[arg(long, default_value_t = 20, global = true)]
     log_interval: u64,
+
+    /// Keep this many recent log events, info and up, for /admin/logs and
+    /// crash reports
+    #[arg(long, default_value_t = logs::DEFAULT_CAPACITY, global = true)]
+    log_buffer: usize,
+
+    /// On SIGTERM, refuse new clients, send existing ones away with a "server
+    /// restarting" close and wait up to this many seconds for them to leave; 0 to
+    /// stop at once
+    #[arg(long, default_value_t = 0, global = true)]
+    drain_timeout: u64,
+
+    /// Keep receiving a source for this many seconds after its last client
+    /// leaves, so a page reload doesn't reconnect to the sender
+    #[arg(long, default_value_t = 0, global = true)]
+    receiver_linger: u64,
+
+    /// After this many seconds without requests or streams, release every
+    /// receiver no one uses and look for sources less often until the next
+    /// request; 0 to never idle
+    #[arg(long, default_value_t = 0, global = true)]
+    idle_after: u64,
+
+    /// Niceness of the capture, encode and server threads, from -20 to 19;
+    /// e.g. 10 to leave vMix or OBS on the same machine the CPU first (Linux)
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19),
+        global = true
+    )]
+    thread_nice: Option<i32>,
+
+    /// Run the capture, encode and server threads only on these CPUs, e.g.
+    /// 2,3 or 4-7 (Linux)
+    #[arg(long, value_parser = CpuList::parse, global = true)]
+    thread_cpus: Option<CpuList>,
+
+    /// At most this many threads for per-viewer re-encodes, like watermarks
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    encode_workers: Option<u64>,
+
+    /// Where to write a diagnostic bundle (recent log, redacted config, stats)
+    /// on a crash; the system's temporary directory by default
+    #[arg(long, global = true)]
+    crash_dir: Option<PathBuf>,
+
+    /// Keep state such as the source favorites in this directory across
+    /// restarts; without it, it lasts until the server stops
+    #[arg(long, global = true)]
+    state_dir: Option<PathBuf>,
+
+    /// Measure EBU R128 loudness of each source's audio
+    #[arg(long, global = true)]
+    loudness: bool,
+
+    /// Target integrated loudness in LUFS; deviations over 1 LU raise an alert
+    #[arg(long, default_value_t = -23.0, global = true, allow_negative_numbers = true)]
+    loudness_target: f64,
+
+    /// Debug: honour delay_ms/jitter_ms/drop/reorder query parameters on /ws
+    #[arg(long, global = true)]
+    chaos: bool,
+
+    /// Serve a raw capture file as a looping source (NAME=PATH, repeatable)
+    #[arg(long, value_parser = parse_replay, global = true)]
+    replay: Vec<(String, PathBuf)>,
+
+    /// Serve a video file as a looping source (NAME=PATH, repeatable): MJPEG in
+    /// AVI or bare MJPEG, or with the ffmpeg feature anything ffmpeg reads
+    #[arg(long, value_parser = parse_replay, global = true)]
+    media: Vec<(String, PathBuf)>,
+
+    /// Workaround for misbehaving senders whose product string (or source name)
+    /// contains PRODUCT: PRODUCT=force-uyvy,no-fields,timeout=MS (repeatable)
+    #[arg(long, value_parser = Quirk::parse, global = true)]
+    quirk: Vec<Quirk>,
+
+    /// Require an access token: TOKEN:ACTIONS:SOURCES, where ACTIONS is a comma
+    /// list of view,snapshot,ptz,record,control and SOURCES a |-separated list;
+    /// either may be * (repeatable). Without any grant, access is open.
+    #[arg(long, value_parser = Grant::parse, global = true)]
+    grant: Vec<Grant>,
+
+    /// Require this token for everything but the test page and /healthz, with
+    /// full access (repeatable); shorthand for --grant TOKEN:*:*
+    #[arg(long, global = true)]
+    api_token: Vec<String>,
+
+    /// Like --api-token for each line of this file: TOKEN, optionally followed
+    /// by a label; blank lines and # comments are skipped
+    #[arg(long, global = true)]
+    api_tokens_file: Option<PathBuf>,
+
+    /// Safe profile for wide audiences: no control or admin endpoints, no source
+    /// URLs, low-bandwidth NDI streams and at most 10 fps at 640 pixels wide
+    #[arg(long, global = true)]
+    public_readonly: bool,
+
+    /// Burn each viewer's token label faintly into the frames they receive.
+    /// Costs a decode and re-encode per frame per client
+    #[arg(long, global = true)]
+    watermark: bool,
+
+    /// Give each viewer a persistent id in a cookie (or ?client_id=), so
+    /// /api/clients groups their reconnections
+    #[arg(long, global = true)]
+    client_ids: bool,
+
+    /// Publish an NDI\u{00ae} source with this name that the switch_program command
+    /// routes to any other source
+    #[arg(long, global = true)]
+    program_output: Option<String>,
+
+    /// Run this Lua file's event handlers, which can run commands and call
+    /// webhooks
+    #[arg(long, global = true)]
+    script: Option<PathBuf>,
+
+    /// Directory for recordings
+    #[arg(long, default_value = "recordings", global = true)]
+    record_dir: PathBuf,
+
+    /// Move recordings on to a new file once one reaches this many megabytes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    record_max_size: Option<u64>,
+
+    /// Move recordings on to a new file after this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    record_max_duration: Option<u64>,
+
+    /// Write AVI recordings at exactly this many frames per second, repeating
+    /// or dropping frames to keep the rate, instead of at the source's own rate
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=i64::from(MAX_RECORD_FPS)),
+        global = true
+    )]
+    record_fps: Option<u32>,
+
+    /// Record the JPEGs sent to viewers, at the --max-fps cap, into AVI files
+    /// instead of encoding every frame: no encoding beyond what viewers cost
+    #[arg(long, global = true)]
+    record_shared: bool,
+
+    /// Keep the last this many seconds of every source in memory, for
+    /// /preroll and the start of recordings
+    #[arg(long, global = true)]
+    preroll: Option<u64>,
+
+    /// Detach from the terminal and run in the background, logging to syslog
+    /// (and so journald) instead of the console
+    #[cfg(unix)]
+    #[arg(long, global = true)]
+    daemon: bool,
+
+    /// Write the server's process id to this file, and remove it on exit
+    #[cfg(unix)]
+    #[arg(long, global = true)]
+    pid_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Discover and list available NDI\u{00ae} sources on the network
     List,
+    /// Check the NDI\u{00ae} runtime: its version, and which features it supports
+    Doctor,
+    /// Answer a few questions and write a commented config file
+    Init {
+        /// Config file to write
+        #[arg(long, default_value = "streambridge.toml")]
+        output: PathBuf,
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
     /// Start MJPEG server — streams are created on-demand
     Serve,
+    /// Record raw frames from an NDI\u{00ae} source to a file for later replay
+    CaptureRaw {
+        /// NDI\u{00ae} source name
+        #[arg(long)]
+        source: String,
+        /// Output file
+        #[arg(long)]
+        output: PathBuf,
+        /// Capture duration in seconds
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+    },
+    /// Publish an MJPEG stream, a raw capture file or a clock as an NDI\u{00ae} source
+    Publish {
+        /// http://... MJPEG stream (e.g. another bridge's /stream/<source>),
+        /// a raw capture file, or "clock"
+        #[arg(long, value_parser = publish::Input::parse)]
+        input: publish::Input,
+        /// Name the source is published under
+        #[arg(long, default_value = "StreamBridge")]
+        name: String,
+        /// Frame rate of the clock, and the nominal rate announced for MJPEG
+        #[arg(long, default_value_t = 25)]
+        fps: u32,
+        /// Stop after this many seconds; 0 runs until interrupted
+        #[arg(long, default_value_t = 0)]
+        duration: u64,
+    },
+    /// Measure latency through external NDI\u{00ae} gear: publish a timestamped test
+    /// pattern to route through it, and read the pattern back from its output
+    Latency {
+        /// NDI\u{00ae} source the pattern comes back on, e.g. a vision mixer's output
+        #[arg(long)]
+        source: String,
+        /// Name the test pattern is published under
+        #[arg(long, default_value = "StreamBridge Latency")]
+        pattern: String,
+        /// Measurement duration in seconds
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+    },
+    /// Register the server as a Windows service that starts at boot, with the
+    /// rest of the command line as its settings
+    #[cfg(windows)]
+    InstallService,
+    /// Stop and remove the Windows service
+    #[cfg(windows)]
+    UninstallService,
+    /// Run as the Windows service; started by the service manager, not by hand
+    #[cfg(windows)]
+    RunService,
+}
+
+fn parse_replay(s: &str) -> Result<(String, PathBuf), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=PATH, got \"{s}\""))?;
+    if name.is_empty() || path.is_empty() {
+        return Err(format!("expected NAME=PATH, got \"{s}\""));
+    }
+    Ok((name.to_string(), PathBuf::from(path)))
 }
 
 fn print_banner(port: u16) {
@@ -59,21 +407,194 @@ fn print_banner(port: u16) {
 }
 
 fn main() {
-    tracing_subscriber::fmt::init();
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Without a console to read, events go to the system's log as well.
+    #[cfg(unix)]
+    let unattended = cli.daemon;
+    #[cfg(windows)]
+    let unattended = matches!(cli.command, Some(Commands::RunService));
+    #[cfg(not(any(unix, windows)))]
+    let unattended = false;
+    let system_log = unattended.then(SystemLog::new);
+    // The buffer keeps info events even when RUST_LOG hides them from the console.
+    let log = LogBuffer::new(logs::DEFAULT_CAPACITY);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .with(log.clone().with_filter(LevelFilter::INFO))
+        .with(system_log.map(|system_log| system_log.with_filter(LevelFilter::INFO)))
+        .init();
 
-    match cli.command {
-        Some(Commands::List) => cmd_list(),
+    let find = discovery::find_settings(&cli.groups, &cli.extra_ips);
+    match cli.command.take() {
+        Some(Commands::List) => cmd_list(&find),
+        Some(Commands::Doctor) => cmd_doctor(),
+        Some(Commands::CaptureRaw { source, output, duration }) => {
+            cmd_capture_raw(&source, &output, duration, &find)
+        }
+        Some(Commands::Init { output, force }) => cmd_init(&output, force),
+        Some(Commands::Publish { input, name, fps, duration }) => {
+            cmd_publish(&input, &name, fps, duration)
+        }
+        Some(Commands::Latency { source, pattern, duration }) => {
+            cmd_latency(&source, &pattern, duration, &find)
+        }
         Some(Commands::Serve) | None => {
-            cmd_serve(cli.port, cli.max_fps, cli.jpeg_quality, cli.log_interval)
+            let config = apply_config(&mut cli, &matches);
+            cmd_serve(cli, config, log, None)
+        }
+        #[cfg(windows)]
+        Some(Commands::InstallService) => {
+            // Checks the config now rather than at the next boot.
+            apply_config(&mut cli, &matches);
+            cmd_install_service(cli.config.as_deref())
+        }
+        #[cfg(windows)]
+        Some(Commands::UninstallService) => match service::uninstall() {
+            Ok(()) => eprintln!("Removed the {} service.", service::SERVICE_NAME),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        #[cfg(windows)]
+        Some(Commands::RunService) => {
+            let config = apply_config(&mut cli, &matches);
+            let drain = Duration::from_secs(cli.drain_timeout);
+            let serve = move |stop| cmd_serve(cli, config, log, Some(stop));
+            if let Err(e) = service::run(drain, serve) {
+                eprintln!("Error: {e}; use install-service, then start the service");
+                std::process::exit(2);
+            }
+        }
+    }
+}
+
+/// Register the service with this process's arguments, less the subcommand
+/// and with `config` made absolute: services start in the system directory.
+#[cfg(windows)]
+fn cmd_install_service(config: Option<&Path>) {
+    use std::ffi::OsString;
+    let mut arguments = vec![OsString::from("run-service")];
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text == "--config" {
+            args.next();
+        } else if text != "install-service" && !text.starts_with("--config=") {
+            arguments.push(arg);
+        }
+    }
+    if let Some(config) = config {
+        let config = std::path::absolute(config).unwrap_or_else(|_| config.to_path_buf());
+        arguments.extend([OsString::from("--config"), config.into_os_string()]);
+    }
+    match service::install(arguments) {
+        Ok(()) => {
+            let name = service::SERVICE_NAME;
+            eprintln!("Installed the {name} service; it starts at boot, or now with:");
+            eprintln!("  sc start {name}");
         }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Fill in settings from `--config` (or `STREAMBRIDGE_CONFIG`) and `STREAMBRIDGE_*`
+/// variables that weren't given on the command line, and
+/// return the rest of the config (per-source overrides, capture rules).
+fn apply_config(cli: &mut Cli, matches: &ArgMatches) -> Config {
+    if cli.config.is_none() {
+        cli.config = std::env::var_os(CONFIG_ENV).map(PathBuf::from);
+    }
+    let config = Config::load_with_env(cli.config.as_deref(), std::env::vars())
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            std::process::exit(2);
+        });
+    // Global flags may come after the subcommand, so look in both places.
+    let explicit = |id: &str| {
+        let given = |m: &ArgMatches| m.value_source(id) == Some(ValueSource::CommandLine);
+        given(matches) || matches.subcommand().is_some_and(|(_, sub)| given(sub))
+    };
+    if let (Some(port), false) = (config.port, explicit("port")) {
+        cli.port = port;
+    }
+    if let (Some(max_fps), false) = (config.max_fps, explicit("max_fps")) {
+        cli.max_fps = max_fps;
+    }
+    cli.rtsp_port = cli.rtsp_port.or(config.rtsp_port);
+    cli.onvif |= config.onvif.unwrap_or(false);
+    cli.max_width = cli.max_width.or(config.max_width);
+    cli.max_height = cli.max_height.or(config.max_height);
+    cli.low_bandwidth |= config.low_bandwidth.unwrap_or(false);
+    cli.adaptive_quality |= config.adaptive_quality.unwrap_or(false);
+    cli.client_ids |= config.client_ids.unwrap_or(false);
+    if cli.extra_ips.is_empty() {
+        cli.extra_ips = config.extra_ips.clone().unwrap_or_default();
+    }
+    if cli.groups.is_empty() {
+        cli.groups = config.groups.clone().unwrap_or_default();
+    }
+    cli.receiver_name = cli.receiver_name.take().or_else(|| config.receiver_name.clone());
+    cli.deinterlace = cli.deinterlace.or(config.deinterlace);
+    cli.color_format = cli.color_format.or(config.color_format);
+    cli.encoder = cli.encoder.or(config.encoder);
+    cli.encode_threads = cli.encode_threads.or(config.encode_threads.map(|t| t as u64));
+    if let (Some(quality), false) = (config.jpeg_quality, explicit("jpeg_quality")) {
+        cli.jpeg_quality = quality;
+    }
+    if let (Some(interval), false) = (config.log_interval, explicit("log_interval")) {
+        cli.log_interval = interval;
     }
+    if let (Some(events), false) = (config.log_buffer, explicit("log_buffer")) {
+        cli.log_buffer = events;
+    }
+    if let (Some(timeout), false) = (config.drain_timeout, explicit("drain_timeout")) {
+        cli.drain_timeout = timeout;
+    }
+    if let (Some(linger), false) = (config.receiver_linger, explicit("receiver_linger")) {
+        cli.receiver_linger = linger;
+    }
+    if let (Some(after), false) = (config.idle_after, explicit("idle_after")) {
+        cli.idle_after = after;
+    }
+    cli.thread_nice = cli.thread_nice.or(config.thread_nice);
+    cli.thread_cpus = cli.thread_cpus.take().or_else(|| config.thread_cpus.clone());
+    cli.encode_workers = cli.encode_workers.or(config.encode_workers);
+    cli.crash_dir = cli.crash_dir.take().or_else(|| config.crash_dir.clone());
+    cli.state_dir = cli.state_dir.take().or_else(|| config.state_dir.clone());
+    cli.record_max_size = cli.record_max_size.or(config.record_max_size);
+    cli.record_max_duration = cli.record_max_duration.or(config.record_max_duration);
+    cli.record_fps = cli.record_fps.or(config.record_fps);
+    cli.record_shared |= config.record_shared.unwrap_or(false);
+    cli.max_clients = cli.max_clients.or(config.max_clients);
+    cli.max_clients_per_source = cli.max_clients_per_source.or(config.max_clients_per_source);
+    cli.max_connections_per_ip = cli.max_connections_per_ip.or(config.max_connections_per_ip);
+    cli.preroll = cli.preroll.or(config.preroll);
+    cli.time_zone = cli.time_zone.take().or_else(|| config.time_zone.clone());
+    cli.time_format = cli.time_format.take().or_else(|| config.time_format.clone());
+    cli.script = cli.script.take().or_else(|| config.script.clone());
+    // Validated by Config::load_with_env.
+    cli.grant.extend(config.grants.iter().filter_map(|g| g.to_grant().ok()));
+    cli.grant.extend(cli.api_token.iter().map(|token| Grant::full_access(token, None)));
+    if let Some(path) = &cli.api_tokens_file {
+        let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Error: can't read {}: {e}", path.display());
+            std::process::exit(2);
+        });
+        cli.grant.extend(Grant::parse_token_file(&contents));
+    }
+    config
 }
 
-fn cmd_list() {
-    let ndi = match crate::ndi::load() {
+/// Load the NDI runtime, or exit with instructions for installing it.
+fn load_ndi() -> ndi::NdiInstance {
+    let ndi = match ndi::load() {
         Ok(n) => n,
-        Err(crate::ndi::NdiError::DllNotFound(_)) => {
+        Err(ndi::NdiError::DllNotFound(_)) => {
             eprintln!("Error: NDI\u{00ae} runtime not found.\n");
             eprintln!("Download and install it from: https://ndi.video/tools/");
             std::process::exit(1);
@@ -83,9 +604,39 @@ fn cmd_list() {
             std::process::exit(1);
         }
     };
+    ndi
+}
+
+/// Wait up to 10 s for `source_name` to show up on the network, or exit.
+fn find_source(ndi: &ndi::NdiInstance, source_name: &str, find: &FindSettings) -> Source {
+    let finder = ndi.create_find_instance_with(find).expect("failed to create finder");
+    println!("Searching for \"{}\"...", source_name);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(s) = finder
+            .get_current_sources()
+            .into_iter()
+            .find(|s| s.name == source_name)
+        {
+            return s;
+        }
+        if Instant::now() >= deadline {
+            eprintln!("Error: NDI\u{00ae} source \"{}\" not found.", source_name);
+            std::process::exit(1);
+        }
+        finder.wait_for_sources(1000);
+    }
+}
+
+fn cmd_list(find: &FindSettings) {
+    let ndi = load_ndi();
 
-    info!("NDI version: {}", ndi.version());
-    let finder = ndi.create_find_instance().expect("failed to create finder");
+    println!("NDI\u{00ae} runtime {}", ndi.version());
+    let missing = missing_capabilities(&ndi);
+    if !missing.is_empty() {
+        println!("Missing: {} (see `streambridge doctor`)", missing.join(", "));
+    }
+    let finder = ndi.create_find_instance_with(find).expect("failed to create finder");
 
     println!("Searching for NDI\u{00ae} sources...");
     finder.wait_for_sources(5000);
@@ -105,62 +656,603 @@ fn cmd_list() {
     }
 }
 
-fn cmd_serve(port: u16, max_fps: u32, jpeg_quality: i32, log_interval: u64) {
+/// What each of [`ndi::Capabilities::list`] is for.
+const CAPABILITY_USES: [(&str, &str); 5] = [
+    ("send", "publish, latency and the program output"),
+    ("ptz", "POST /ptz/<source>"),
+    ("routing", "the program output"),
+    ("framesync", "nothing yet"),
+    ("recv_v4", "nothing yet"),
+];
+
+/// The optional parts of the SDK the runtime lacks.
+fn missing_capabilities(ndi: &ndi::NdiInstance) -> Vec<&'static str> {
+    let capabilities = ndi.capabilities().list();
+    capabilities.into_iter().filter(|(_, found)| !found).map(|(name, _)| name).collect()
+}
+
+fn cmd_doctor() {
+    let ndi = load_ndi();
+    println!("NDI\u{00ae} runtime {}", ndi.version());
+    for ((name, found), (_, uses)) in ndi.capabilities().list().into_iter().zip(CAPABILITY_USES) {
+        let found = if found { "found" } else { "MISSING" };
+        println!("  {name:<10} {found:<8} used by {uses}");
+    }
+    if !missing_capabilities(&ndi).is_empty() {
+        println!();
+        println!("Features needing what's missing report that this runtime lacks it;");
+        println!("update the runtime from https://ndi.video/tools/ to use them.");
+    }
+}
+
+fn cmd_init(output: &Path, force: bool) {
+    if output.exists() && !force {
+        eprintln!("Error: {} already exists; use --force to replace it.", output.display());
+        std::process::exit(1);
+    }
+    // Setup works without NDI; the sources question is just skipped.
+    let discovered = match ndi::load() {
+        Ok(ndi) => {
+            println!("Searching for NDI\u{00ae} sources...");
+            let finder = ndi.create_find_instance().expect("failed to create finder");
+            finder.wait_for_sources(5000);
+            let names: Vec<String> =
+                finder.get_current_sources().into_iter().map(|s| s.name).collect();
+            println!("Found {} source(s).", names.len());
+            names
+        }
+        Err(e) => {
+            println!("Skipping source discovery: {e}");
+            Vec::new()
+        }
+    };
+    let answers = match setup::ask(&mut io::stdin().lock(), &mut io::stdout(), &discovered) {
+        Ok(answers) => answers,
+        Err(e) => {
+            eprintln!("\nError: {e}");
+            std::process::exit(1);
+        }
+    };
+    let config = setup::render(&answers, &output.display().to_string());
+    if let Err(e) = std::fs::write(output, config) {
+        eprintln!("Error: failed to write {}: {e}", output.display());
+        std::process::exit(1);
+    }
+    println!();
+    println!("Wrote {}.", output.display());
+    if let Some((viewer, operator)) = &answers.tokens {
+        println!("Viewer token:   {viewer}");
+        println!("Operator token: {operator}");
+    }
+    println!("Start the server with: streambridge serve --config {}", output.display());
+}
+
+/// Receiver settings for the one-off commands, named like the server's.
+fn named_receiver() -> RecvSettings {
+    RecvSettings { name: Some(receiver::default_receiver_name()), ..Default::default() }
+}
+
+fn cmd_capture_raw(
+    source_name: &str,
+    output: &std::path::Path,
+    duration: u64,
+    find: &FindSettings,
+) {
+    let ndi = load_ndi();
+
+    let source = find_source(&ndi, source_name, find);
+
+    let recv = ndi
+        .create_receive_instance(&named_receiver())
+        .expect("failed to create receiver");
+    recv.connect(&source);
+
+    let mut writer = match RawWriter::create(output) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Error: cannot create {}: {}", output.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Capturing {} s to {}...", duration, output.display());
+    let mut video_frame = ndi::ffi::NDIlib_video_frame_v2_t::default();
+    let mut audio_frame = ndi::ffi::NDIlib_audio_frame_v3_t::default();
+    let mut first_frame: Option<Instant> = None;
+    let (mut video_count, mut audio_count) = (0u64, 0u64);
+    let end = Instant::now() + Duration::from_secs(duration);
+
+    while Instant::now() < end {
+        let result = match recv.capture(&mut video_frame, Some(&mut audio_frame), None, 1000) {
+            FrameType::Video => {
+                let time_us = first_frame.get_or_insert_with(Instant::now).elapsed().as_micros() as u64;
+                let result = match receiver::ndi_video_frame(&recv, &video_frame) {
+                    Some(frame) => writer.write_video(time_us, &frame).map(|_| video_count += 1),
+                    None => Ok(()),
+                };
+                recv.free_video(&video_frame);
+                result
+            }
+            FrameType::Audio => {
+                let time_us = first_frame.get_or_insert_with(Instant::now).elapsed().as_micros() as u64;
+                let channels = receiver::ndi_audio_channels(&recv, &audio_frame);
+                let result = writer
+                    .write_audio(time_us, audio_frame.sample_rate.max(0) as u32, &channels)
+                    .map(|_| audio_count += 1);
+                recv.free_audio(&audio_frame);
+                result
+            }
+            FrameType::Error => {
+                eprintln!("Error: NDI\u{00ae} connection lost.");
+                break;
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Error: write failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = writer.finish() {
+        eprintln!("Error: write failed: {}", e);
+        std::process::exit(1);
+    }
+    println!(
+        "Captured {} video and {} audio frame(s) to {}",
+        video_count,
+        audio_count,
+        output.display()
+    );
+}
+
+fn cmd_publish(input: &publish::Input, name: &str, fps: u32, duration: u64) {
+    if !(1..=120).contains(&fps) {
+        eprintln!("Error: --fps must be 1 to 120.");
+        std::process::exit(2);
+    }
+    let ndi = load_ndi();
+    // Every input paces itself: clocks and files by their own timing, MJPEG by arrival.
+    let settings = SendSettings::new(name).clock_video(false).clock_audio(false);
+    let sender = ndi.create_send_instance(&settings).expect("failed to create sender");
+    let published = sender.source().map_or_else(|| name.to_string(), |s| s.name);
+    println!("Publishing \"{}\"; press Ctrl+C to stop.", published);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    if duration > 0 {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs(duration));
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
+    let result = match input {
+        publish::Input::Clock => {
+            let config = ClockConfig { fps, ..Default::default() };
+            publish::clock(&sender, &config, &stop, |_, _| {});
+            Ok(())
+        }
+        publish::Input::File(path) => publish::file(&sender, path, &stop),
+        publish::Input::Mjpeg(url) => tokio::runtime::Runtime::new()
+            .expect("failed to start runtime")
+            .block_on(publish::mjpeg(&sender, url, fps, &stop)),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Frame rate of the latency pattern; also the resolution of the measurement.
+const PATTERN_FPS: u32 = 50;
+
+fn cmd_latency(source_name: &str, pattern: &str, duration: u64, find: &FindSettings) {
+    let ndi = load_ndi();
+    let sender = ndi
+        .create_send_instance(&SendSettings::new(pattern))
+        .expect("failed to create sender");
+    let stop = Arc::new(AtomicBool::new(false));
+    let pattern_thread = {
+        let stop = stop.clone();
+        std::thread::spawn(move || send_latency_pattern(&sender, &stop))
+    };
+    println!("Publishing \"{}\"; route it through the gear under test.", pattern);
+
+    let source = find_source(&ndi, source_name, find);
+    let recv = ndi
+        .create_receive_instance(&named_receiver())
+        .expect("failed to create receiver");
+    recv.connect(&source);
+
+    println!("Measuring for {} s...", duration);
+    let mut video_frame = ndi::ffi::NDIlib_video_frame_v2_t::default();
+    let mut stats = LatencyStats::default();
+    let (mut unreadable, mut last_sent) = (0u64, None);
+    let end = Instant::now() + Duration::from_secs(duration);
+    let mut next_report = Instant::now() + Duration::from_secs(5);
+
+    while Instant::now() < end {
+        match recv.capture_video(&mut video_frame, 1000) {
+            FrameType::Video => {
+                let now = unix_millis(SystemTime::now());
+                let sent = receiver::ndi_video_frame(&recv, &video_frame)
+                    .and_then(|frame| latency::read_stamp(&frame));
+                recv.free_video(&video_frame);
+                match sent.and_then(|sent| Some((sent, latency::delay_ms(sent, now)?))) {
+                    // Gear that repeats frames shows an older stamp again; only
+                    // its first appearance measures the delay.
+                    Some((sent, _)) if last_sent == Some(sent) => {}
+                    Some((sent, delay)) => {
+                        stats.record(delay);
+                        last_sent = Some(sent);
+                    }
+                    None => unreadable += 1,
+                }
+            }
+            FrameType::Error => {
+                eprintln!("Error: NDI\u{00ae} connection lost.");
+                break;
+            }
+            _ => {}
+        }
+        if Instant::now() >= next_report {
+            if let Some(summary) = stats.summary() {
+                println!("  {}", summary);
+            }
+            next_report += Duration::from_secs(5);
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    let _ = pattern_thread.join();
+
+    match stats.summary() {
+        Some(summary) => println!(
+            "Latency through \"{}\": {}; {} unreadable frame(s)",
+            source_name, summary, unreadable
+        ),
+        None => {
+            eprintln!(
+                "Error: no readable pattern in {} frame(s) from \"{}\"; is \"{}\" routed to it?",
+                unreadable, source_name, pattern
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Send the clock with its send time stamped across the top until `stop` is set.
+/// Paced here rather than by the SDK, so each stamp is taken right before sending.
+fn send_latency_pattern(sender: &SendInstance, stop: &AtomicBool) {
+    let config = ClockConfig {
+        width: 1280,
+        height: 720,
+        fps: PATTERN_FPS,
+        text: "latency pattern".to_string(),
+    };
+    publish::clock(sender, &config, stop, |data, now| {
+        latency::stamp(data, config.width, config.height, unix_millis(now));
+    });
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Run the server until a shutdown signal or, when given, until `stop` says how
+/// long to drain for.
+fn cmd_serve(cli: Cli, config: Config, log: LogBuffer, stop: Option<oneshot::Receiver<Duration>>) {
+    log.set_capacity(cli.log_buffer);
+    let args = std::env::args();
+    let snapshot = crash::config_snapshot(cli.config.as_deref(), std::env::vars(), args);
+    let crash_dir = cli.crash_dir.clone().unwrap_or_else(crash::default_dir);
+    let reporter = CrashReporter::new(crash_dir, log.clone(), snapshot);
+    reporter.install_panic_hook();
+    let Cli {
+        port,
+        rtsp_port,
+        onvif,
+        max_fps,
+        max_width,
+        max_height,
+        low_bandwidth,
+        deinterlace,
+        color_format,
+        encoder,
+        encode_threads,
+        jpeg_quality,
+        adaptive_quality,
+        log_interval,
+        drain_timeout,
+        receiver_linger,
+        idle_after,
+        thread_nice,
+        thread_cpus,
+        encode_workers,
+        loudness,
+        loudness_target,
+        chaos,
+        replay: replays,
+        media,
+        quirk: quirks,
+        grant: grants,
+        public_readonly,
+        watermark,
+        client_ids,
+        program_output,
+        script,
+        record_dir,
+        record_max_size,
+        record_max_duration,
+        record_fps,
+        record_shared,
+        preroll,
+        state_dir,
+        extra_ips,
+        groups,
+        receiver_name,
+        max_clients,
+        max_clients_per_source,
+        max_connections_per_ip,
+        time_zone,
+        time_format,
+        #[cfg(unix)]
+        daemon,
+        #[cfg(unix)]
+        pid_file,
+        ..
+    } = cli;
+    let loudness_target = loudness.then_some(loudness_target);
+    // Threads inherit their creator's priority and CPUs, so every thread started
+    // from here on runs with the policy. Failing here, e.g. without the right to
+    // raise priority, beats a warning from each thread.
+    let thread_policy = ThreadPolicy { nice: thread_nice, cpus: thread_cpus };
+    if let Err(e) = thread_policy.apply() {
+        eprintln!("Error: {e}");
+        std::process::exit(2);
+    }
+    let default = EncodeSettings {
+        adaptive_quality,
+        max_width,
+        max_height,
+        low_bandwidth,
+        deinterlace: deinterlace.unwrap_or_default(),
+        color_format: color_format.unwrap_or_default(),
+        encoder: encoder.unwrap_or_default(),
+        encode_threads: encode_threads.map_or(1, |threads| threads as usize),
+        preroll: preroll.filter(|&secs| secs > 0).map(Duration::from_secs),
+        ..EncodeSettings::new(jpeg_quality, max_fps)
+    };
+    let times = TimeFormat::new(time_zone.as_deref(), time_format.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            std::process::exit(2);
+        });
+    // The bridge applies the --public-readonly caps on top.
+    let settings = SourceSettings::new(default, config.sources).time_format(times);
     print_banner(port);
+    // Before any thread starts: only the forking thread carries on.
+    #[cfg(unix)]
+    if daemon {
+        if let Err(e) = service::daemonize() {
+            eprintln!("Error: {e}");
+            std::process::exit(2);
+        }
+    }
+    #[cfg(unix)]
+    let _pid_file = pid_file.map(|path| {
+        service::PidFile::create(&path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        })
+    });
 
-    let ndi = match crate::ndi::load() {
-        Ok(n) => n,
-        Err(crate::ndi::NdiError::DllNotFound(_)) => {
+    let mut virtual_sources: Vec<(String, VirtualSource)> = replays
+        .into_iter()
+        .map(|(name, path)| (name, VirtualSource::Replay(path)))
+        .collect();
+    for (name, pip) in config.pip {
+        virtual_sources.push((name, VirtualSource::Composite(Layout::Pip(pip))));
+    }
+    for (name, compare) in config.compare {
+        virtual_sources.push((name, VirtualSource::Composite(Layout::Compare(compare))));
+    }
+    for (name, crop) in config.crop {
+        virtual_sources.push((name, VirtualSource::Crop(crop)));
+    }
+    for (name, clock) in config.clock {
+        virtual_sources.push((name, VirtualSource::Clock(clock)));
+    }
+    for (name, media) in config.media {
+        virtual_sources.push((name, VirtualSource::Media(media)));
+    }
+    for (name, path) in media {
+        virtual_sources.push((name, VirtualSource::Media(MediaConfig::new(path))));
+    }
+
+    let ndi = match ndi::load() {
+        Ok(n) => {
+            info!("NDI version: {}", n.version());
+            let missing = missing_capabilities(&n);
+            if !missing.is_empty() {
+                warn!("NDI runtime lacks {}; see `streambridge doctor`", missing.join(", "));
+            }
+            reporter.set_ndi_version(n.version());
+            Some(Arc::new(n))
+        }
+        Err(ndi::NdiError::DllNotFound(_)) if !virtual_sources.is_empty() => {
+            warn!("NDI runtime not found; serving replay, media, composite and clock sources only");
+            None
+        }
+        Err(ndi::NdiError::DllNotFound(_)) => {
             eprintln!("Error: NDI\u{00ae} runtime not found.\n");
             eprintln!("Download and install it from: https://ndi.video/tools/");
             std::process::exit(1);
         }
         Err(e) => {
             error!("Failed to initialize NDI: {}", e);
+            reporter.report(&format!("failed to initialize NDI: {e}"));
             std::process::exit(1);
         }
     };
 
-    info!("NDI version: {}", ndi.version());
+    let mut bridge = Bridge::builder()
+        .port(port)
+        .settings(settings)
+        .quirks(quirks)
+        .chaos(chaos)
+        .public_readonly(public_readonly)
+        .watermark(watermark)
+        .client_ids(client_ids)
+        .onvif(onvif)
+        .capture_rules(config.capture_rules)
+        .record_dir(record_dir)
+        .record_rotation(Rotation {
+            max_bytes: record_max_size.map(|mb| mb * 1_000_000),
+            max_duration: record_max_duration.map(Duration::from_secs),
+        })
+        .record_fps(record_fps)
+        .record_shared(record_shared)
+        .connection_limits(ConnectionLimits {
+            max_clients: max_clients.map(|n| n as usize),
+            max_clients_per_source: max_clients_per_source.map(|n| n as usize),
+            max_connections_per_ip: max_connections_per_ip.map(|n| n as usize),
+        })
+        .log_interval(log_interval)
+        .logs(log)
+        .receiver_linger(Duration::from_secs(receiver_linger))
+        .idle_after(Duration::from_secs(idle_after))
+        .thread_policy(thread_policy.clone())
+        .extra_ips(extra_ips)
+        .groups(groups);
+    if let Some(name) = receiver_name {
+        bridge = bridge.receiver_name(name);
+    }
+    if let Some(ndi) = ndi {
+        bridge = bridge.ndi(ndi);
+    }
+    if let Some(target) = loudness_target {
+        bridge = bridge.loudness_target(target);
+    }
+    for (name, source) in virtual_sources {
+        bridge = bridge.virtual_source(name, source);
+    }
+    if let Some(dir) = state_dir {
+        bridge = bridge.state_dir(dir);
+    }
+    for (name, url) in config.static_sources {
+        bridge = bridge.static_source(name, url);
+    }
+    for (alias, name) in config.aliases {
+        bridge = bridge.alias(alias, name);
+    }
+    if let Some(name) = program_output {
+        bridge = bridge.program_output(name);
+    }
+    if let Some(path) = script {
+        let script = Script::load(&path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        });
+        bridge = bridge.script(script);
+    }
+    for (name, republish) in config.republish {
+        // Checked when the config was read.
+        if let Ok(input) = republish.input(&name) {
+            bridge = bridge.republish(name, input);
+        }
+    }
+    if let Some(port) = rtsp_port {
+        bridge = bridge.rtsp_port(port);
+    }
+    if public_readonly {
+        info!("public read-only mode: control and admin endpoints disabled, previews capped");
+    }
+    if grants.is_empty() {
+        info!("no --grant or --api-token given: all sources are open to every client");
+        if watermark {
+            warn!("--watermark has no effect without grants: viewers are anonymous");
+        }
+    }
+    for grant in grants {
+        bridge = bridge.grant(grant);
+    }
+    if chaos && !public_readonly {
+        warn!("chaos mode enabled: clients may request delayed, dropped or reordered frames");
+    }
+
+    let rt = runtime(encode_workers);
+    rt.block_on(async {
+        let bridge = bridge.spawn().await.unwrap_or_else(|e| {
+            error!("{}", e);
+            reporter.report(&e);
+            std::process::exit(1);
+        });
+        reporter.watch(bridge.receiver_manager().clone());
+        info!("streambridge server listening on http://{}", bridge.local_addr());
+        if let Some(addr) = bridge.rtsp_addr() {
+            info!("RTSP on rtsp://{}/<source>", addr);
+        }
+        let drain = match stop {
+            Some(stop) => stop.await.unwrap_or_default(),
+            None => shutdown_signal(Duration::from_secs(drain_timeout)).await,
+        };
+        bridge.shutdown(drain).await.expect("server error");
+    });
+    // With the runtime's tasks gone, nothing holds the NDI® runtime any more:
+    // the last handle's drop calls NDIlib_destroy.
+    rt.shutdown_timeout(Duration::from_secs(1));
+    info!("shutdown complete");
+}
 
-    let ndi = Arc::new(ndi);
-    let finder = ndi.create_find_instance().expect("failed to create finder");
-    let sources = discovery::start_discovery(finder);
-    let receiver_manager = ReceiverManager::new(Arc::clone(&ndi), jpeg_quality, max_fps);
+/// The server's runtime, with at most `encode_workers` blocking threads, where
+/// per-viewer re-encodes like watermarks run.
+fn runtime(encode_workers: Option<u64>) -> tokio::runtime::Runtime {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(workers) = encode_workers {
+        builder.max_blocking_threads(workers as usize);
+    }
+    builder.build().expect("failed to create tokio runtime")
+}
 
-    let state = server::AppState {
-        sources: sources.clone(),
-        receiver_manager: receiver_manager.clone(),
+/// Resolves on Ctrl+C or SIGTERM (on Windows, Ctrl+C, Ctrl+Break or closing the
+/// console) to how long to drain clients first: `drain` for SIGTERM, which is
+/// what container runtimes send, and nothing for the rest. A second Ctrl+C exits
+/// at once.
+async fn shutdown_signal(drain: Duration) -> Duration {
+    #[cfg(unix)]
+    let wait = {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => Duration::ZERO,
+            _ = terminate.recv() => drain,
+        }
     };
+    #[cfg(windows)]
+    let wait = {
+        use tokio::signal::windows;
+        let mut close = windows::ctrl_close().expect("failed to listen for console close");
+        let mut ctrl_break = windows::ctrl_break().expect("failed to listen for Ctrl+Break");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = close.recv() => {}
+            _ = ctrl_break.recv() => {}
+        }
+        Duration::ZERO
+    };
+    #[cfg(not(any(unix, windows)))]
+    let wait = {
+        let _ = tokio::signal::ctrl_c().await;
+        Duration::ZERO
+    };
+    #[cfg(not(unix))]
+    let _ = drain;
 
-    let rt = tokio::runtime::Runtime::new().expect("failed to create tokio runtime");
-    rt.block_on(async {
-        // Stats logging task
-        if log_interval > 0 {
-            let manager = receiver_manager.clone();
-            let interval_secs = log_interval as f64;
-            tokio::spawn(async move {
-                let mut tick = tokio::time::interval(Duration::from_secs(log_interval));
-                loop {
-                    tick.tick().await;
-                    for (name, stats) in manager.active_stats() {
-                        let snap = stats.snapshot_and_reset(interval_secs);
-                        if snap.clients > 0 || snap.fps_out > 0.0 {
-                            info!("[{}] {}", name, snap);
-                        }
-                    }
-                }
-            });
-        }
-
-        let router = server::create_router(state);
-        let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
-        info!("streambridge server listening on http://{}", addr);
-        let listener = tokio::net::TcpListener::bind(addr)
-            .await
-            .expect("failed to bind");
-        axum::serve(listener, router)
-            .await
-            .expect("server error");
+    tokio::spawn(async {
+        let _ = tokio::signal::ctrl_c().await;
+        eprintln!("Interrupted again; exiting without cleanup.");
+        std::process::exit(130);
     });
+    wait
 }